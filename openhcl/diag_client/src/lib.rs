@@ -500,6 +500,26 @@ pub async fn inspect(
         Ok(response.result)
     }
 
+    /// Watches an inspection path for changes, sampled every `interval`.
+    ///
+    /// Call [`InspectWatch::next`] to wait for each set of changes.
+    pub fn watch(
+        &self,
+        path: impl Into<String>,
+        depth: Option<usize>,
+        interval: Duration,
+    ) -> InspectWatch<'_> {
+        InspectWatch {
+            client: self,
+            request: inspect_proto::WatchRequest {
+                path: path.into(),
+                depth: depth.unwrap_or(u32::MAX as usize) as u32,
+                interval_ms: interval.as_millis().try_into().unwrap_or(u64::MAX),
+                watch_id: 0,
+            },
+        }
+    }
+
     /// Updates an inspectable value.
     pub async fn update(
         &self,
@@ -793,6 +813,32 @@ fn grpc_status(status: Status) -> anyhow::Error {
     anyhow::anyhow!(status.message)
 }
 
+/// An inspection watch, returned by [`DiagClient::watch`].
+pub struct InspectWatch<'a> {
+    client: &'a DiagClient,
+    request: inspect_proto::WatchRequest,
+}
+
+impl InspectWatch<'_> {
+    /// Waits for the next changes to the watched path.
+    ///
+    /// The first call returns the full inspection results immediately. Later
+    /// calls wait for the watch interval and return what changed since the
+    /// previous call, with counters converted to per-second rates, or `None`
+    /// if nothing changed.
+    pub async fn next(&mut self) -> anyhow::Result<Option<Node>> {
+        let response = self
+            .client
+            .ttrpc
+            .call()
+            .start(inspect_proto::InspectService::Watch, self.request.clone());
+
+        let response = response.await.map_err(grpc_status)?;
+        self.request.watch_id = response.watch_id;
+        Ok(response.changes)
+    }
+}
+
 /// A builder for launching a command in VTL2.
 pub struct ExecBuilder<'a> {
     client: &'a DiagClient,
@@ -37,6 +37,9 @@
 use inspect_proto::InspectService;
 use inspect_proto::UpdateRequest;
 use inspect_proto::UpdateResponse2;
+use inspect_proto::WatchRequest;
+use inspect_proto::WatchResponse2;
+use inspect_proto::WatchTable;
 use mesh::CancelContext;
 use mesh::rpc::FailableRpc;
 use mesh::rpc::RpcSend;
@@ -108,6 +111,7 @@ pub(crate) struct DiagServiceHandler {
     request_send: mesh::Sender<DiagRequest>,
     children: Mutex<HashMap<i32, Task<ExitStatus>>>,
     inspect_sensitivity_level: Option<inspect::SensitivityLevel>,
+    watches: WatchTable,
     inner: Arc<crate::Inner>,
 }
 
@@ -123,6 +127,7 @@ pub fn new(request_send: mesh::Sender<DiagRequest>, inner: Arc<crate::Inner>) ->
             } else {
                 None
             },
+            watches: WatchTable::new(),
             // TODO: use a remotable type for `Inner`, which is just used to get
             // data connection sockets.
             inner,
@@ -160,7 +165,9 @@ enum Event {
                         match req {
                             Event::Diag(req) => this.handle_diag_request(&driver, req, ctx).await,
                             Event::Diag2(req) => this.handle_diag2_request(&driver, req, ctx).await,
-                            Event::Inspect(req) => this.handle_inspect_request(req, ctx).await,
+                            Event::Inspect(req) => {
+                                this.handle_inspect_request(&driver, req, ctx).await
+                            }
                             Event::Profile(req) => this.handle_profile_request(req, ctx).await,
                         }
                     }
@@ -174,7 +181,12 @@ async fn take_connection(&self, id: u64) -> anyhow::Result<PolledSocket<Socket>>
         self.inner.take_connection(id).await
     }
 
-    async fn handle_inspect_request(&self, req: InspectService, mut ctx: CancelContext) {
+    async fn handle_inspect_request(
+        &self,
+        driver: &impl Driver,
+        req: InspectService,
+        mut ctx: CancelContext,
+    ) {
         match req {
             InspectService::Inspect(request, response) => {
                 let inspect_response = self.handle_inspect(&request, ctx).await;
@@ -185,6 +197,11 @@ async fn handle_inspect_request(&self, req: InspectService, mut ctx: CancelConte
                     ctx.until_cancelled(self.handle_update(&request)).await,
                 ));
             }
+            InspectService::Watch(request, response) => {
+                response.send(grpc_result(Ok(self
+                    .handle_watch(driver, &request, ctx)
+                    .await)));
+            }
         }
     }
 
@@ -540,6 +557,28 @@ async fn handle_inspect(
         InspectResponse2 { result }
     }
 
+    async fn handle_watch(
+        &self,
+        driver: &impl Driver,
+        request: &WatchRequest,
+        ctx: CancelContext,
+    ) -> anyhow::Result<WatchResponse2> {
+        tracing::debug!(
+            path = request.path.as_str(),
+            depth = request.depth,
+            interval_ms = request.interval_ms,
+            watch_id = request.watch_id,
+            "watch request"
+        );
+        self.watches
+            .watch(driver, ctx, request, |builder| {
+                builder
+                    .sensitivity(self.inspect_sensitivity_level)
+                    .inspect(inspect::send(&self.request_send, DiagRequest::Inspect))
+            })
+            .await
+    }
+
     async fn handle_update(&self, request: &UpdateRequest) -> anyhow::Result<UpdateResponse2> {
         tracing::debug!(
             path = request.path.as_str(),
@@ -60,7 +60,7 @@ async fn complete(
     ) -> Vec<String> {
         match (subcommand_path, arg_id) {
             (["ohcldiag-dev"], "VM") => list_vms().unwrap_or_default(),
-            (["ohcldiag-dev", "inspect"], "path") => {
+            (["ohcldiag-dev", "inspect" | "watch"], "path") => {
                 let on_error = vec!["failed/to/connect".into()];
 
                 let (parent_path, to_complete) = (ctx.to_complete)
@@ -120,6 +120,29 @@ enum Command {
         /// The new value.
         value: String,
     },
+    /// Watches an inspectable path, printing what changed after each period.
+    ///
+    /// The full state is printed first. After that, only the values that
+    /// changed are printed, with counters shown as per-second rates.
+    Watch {
+        /// Recursively enumerate child nodes.
+        #[clap(short)]
+        recursive: bool,
+        /// Limit the recursive inspection depth.
+        #[clap(short, long, requires("recursive"))]
+        limit: Option<usize>,
+        /// Output in JSON format.
+        #[clap(short, long)]
+        json: bool,
+        /// The sampling period in seconds.
+        #[clap(long, default_value = "1")]
+        period: f64,
+        /// The count of samples to take after the first.
+        #[clap(long)]
+        count: Option<usize>,
+        /// The path to watch.
+        path: Option<String>,
+    },
     /// Starts the VM if it's waiting for the signal to start.
     ///
     /// Underhill must have been started with --wait-for-start or
@@ -618,6 +641,35 @@ pub fn main() -> anyhow::Result<()> {
                     }
                 }
             }
+            Command::Watch {
+                recursive,
+                limit,
+                json,
+                period,
+                mut count,
+                path,
+            } => {
+                let client = new_client(driver.clone(), &vm)?;
+                let mut watch = client.watch(
+                    path.unwrap_or_default(),
+                    if recursive { limit } else { Some(0) },
+                    Duration::from_secs_f64(period),
+                );
+                loop {
+                    if let Some(node) = watch.next().await? {
+                        if json {
+                            println!("{}", node.json());
+                        } else {
+                            println!("{node:#}");
+                        }
+                    }
+                    match count.as_mut() {
+                        Some(count) if *count == 0 => break,
+                        Some(count) => *count -= 1,
+                        None => {}
+                    }
+                }
+            }
             Command::Update { path, value } => {
                 eprintln!(
                     "`update` is deprecated - please use `ohcldiag-dev inspect <path> -u <new value>`"
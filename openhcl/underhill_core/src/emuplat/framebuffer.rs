@@ -3,6 +3,7 @@
 
 use mesh::MeshPayload;
 use std::convert::Infallible;
+use video_core::DirtyRect;
 use video_core::FramebufferControl;
 use video_core::FramebufferFormat;
 use video_core::ResolvedFramebuffer;
@@ -39,6 +40,11 @@ async fn unmap(&mut self) {
     async fn set_format(&mut self, format: FramebufferFormat) {
         self.format_send.send(format);
     }
+
+    async fn dirty(&mut self, _rects: &[DirtyRect]) {
+        // Dirty regions are only a hint, and they are not forwarded to the
+        // VNC server here, which finds updated regions itself.
+    }
 }
 
 impl ResolveResource<FramebufferHandleKind, SharedFramebufferHandle> for FramebufferRemoteControl {
@@ -361,6 +361,7 @@ async fn launch_workers(
                         listener,
                         framebuffer,
                         input_send,
+                        security: Default::default(),
                     },
                 )
                 .await?,
@@ -401,6 +402,8 @@ async fn launch_workers(
                         } else {
                             debug_worker_defs::TargetArch::Aarch64
                         },
+                        memory_map: false,
+                        linux: None,
                     },
                 )
                 .await?,
@@ -2401,6 +2401,7 @@ async fn new_underhill_vm(
                 mem_layout: &mem_layout,
                 cache_topology: None,
                 pcie_host_bridges: &vec![],
+                numa_locality: None,
                 arch: vmm_core::acpi_builder::AcpiArchConfig::X86 {
                     with_ioapic: true, // openhcl always runs with ioapic
                     with_pic: capabilities.with_pic,
@@ -308,11 +308,19 @@ pub fn madvise_hugepage(&self, offset: usize, len: usize) -> Result<(), std::io:
     /// host.
     ///
     /// Only valid when private_ram mode is enabled.
-    #[expect(dead_code)] // Will be used by ballooning / memory hot-remove.
     pub fn decommit(&self, offset: usize, len: usize) -> Result<(), std::io::Error> {
         assert!(self.private_ram, "decommit requires private RAM mode");
         self.inner.mapping.decommit(offset, len)
     }
+
+    /// Recommits a range of private RAM after [`decommit`](Self::decommit),
+    /// making it accessible again. The recommitted pages read as zero.
+    ///
+    /// Only valid when private_ram mode is enabled.
+    pub fn commit(&self, offset: usize, len: usize) -> Result<(), std::io::Error> {
+        assert!(self.private_ram, "commit requires private RAM mode");
+        self.inner.mapping.commit(offset, len)
+    }
 }
 
 /// SAFETY: the underlying VA mapping is guaranteed to be valid for the lifetime
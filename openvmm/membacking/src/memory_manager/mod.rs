@@ -27,6 +27,8 @@
 use std::thread::JoinHandle;
 use thiserror::Error;
 use vm_topology::memory::MemoryLayout;
+use vmcore::ram_discard::DiscardRam;
+use vmcore::ram_discard::RamDiscard;
 
 /// The OpenVMM memory manager.
 #[derive(Debug, Inspect)]
@@ -483,6 +485,29 @@ pub fn shared_memory_backing(&self) -> Option<SharedMemoryBacking> {
         Some(SharedMemoryBacking { guest_ram })
     }
 
+    /// Translates guest physical ranges of RAM to `(offset, length)` ranges
+    /// within the shared memory backing, which holds each RAM range back to
+    /// back in guest physical address order.
+    ///
+    /// Parts of `ranges` that are not RAM are ignored. Adjacent results are
+    /// merged.
+    pub fn ram_backing_offsets(&self, ranges: &[MemoryRange]) -> Vec<(u64, u64)> {
+        ram_backing_offsets(&self.ram_regions, ranges)
+    }
+
+    /// Returns an object for releasing the host memory backing guest RAM.
+    ///
+    /// In private memory mode, the pages are decommitted. Otherwise, holes
+    /// are punched in the shared memory backing, which is only supported on
+    /// Linux.
+    pub fn ram_discard(&self) -> RamDiscard {
+        RamDiscard::new(RamDiscarder {
+            guest_ram: self.guest_ram.clone(),
+            ram_regions: self.ram_regions.clone(),
+            va_mapper: self.va_mapper.clone(),
+        })
+    }
+
     /// Attaches the guest memory to a partition, mapping it to the guest
     /// physical address space.
     ///
@@ -531,6 +556,65 @@ pub async fn attach_partition(
     }
 }
 
+fn ram_backing_offsets(ram_regions: &[RamRegion], ranges: &[MemoryRange]) -> Vec<(u64, u64)> {
+    let mut offsets = Vec::<(u64, u64)>::new();
+    let mut base = 0;
+    for region in ram_regions {
+        for range in ranges {
+            if !region.range.overlaps(range) {
+                continue;
+            }
+            let overlap = region.range.intersection(range);
+            let offset = base + (overlap.start() - region.range.start());
+            match offsets.last_mut() {
+                Some((last, len)) if *last + *len == offset => *len += overlap.len(),
+                _ => offsets.push((offset, overlap.len())),
+            }
+        }
+        base += region.range.len();
+    }
+    offsets
+}
+
+/// Releases the host memory backing guest RAM, for [`RamDiscard`].
+struct RamDiscarder {
+    guest_ram: Option<Mappable>,
+    ram_regions: Arc<Vec<RamRegion>>,
+    va_mapper: Arc<VaMapper>,
+}
+
+impl DiscardRam for RamDiscarder {
+    fn discard(&self, range: MemoryRange) -> std::io::Result<()> {
+        match &self.guest_ram {
+            None => {
+                // Private RAM is mapped at its guest physical address.
+                // Recommit after decommitting, since decommitted pages are
+                // inaccessible on Windows.
+                for region in self.ram_regions.iter() {
+                    if !region.range.overlaps(&range) {
+                        continue;
+                    }
+                    let overlap = region.range.intersection(&range);
+                    let (offset, len) = (overlap.start() as usize, overlap.len() as usize);
+                    self.va_mapper.decommit(offset, len)?;
+                    self.va_mapper.commit(offset, len)?;
+                }
+                Ok(())
+            }
+            #[cfg(target_os = "linux")]
+            Some(guest_ram) => {
+                use std::os::fd::AsFd;
+                for (offset, len) in ram_backing_offsets(&self.ram_regions, &[range]) {
+                    sparse_mmap::discard_shared_memory(guest_ram.as_fd(), offset, len)?;
+                }
+                Ok(())
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => Err(std::io::ErrorKind::Unsupported.into()),
+        }
+    }
+}
+
 /// A client to the [`GuestMemoryManager`] used to control the visibility of
 /// RAM regions.
 pub struct RamVisibilityControl {
@@ -104,6 +104,13 @@ fn into_doorbell_registration(
     /// Returns whether partition reset is supported.
     fn supports_reset(&self) -> bool;
 
+    /// Enables or disables tracking of guest writes to RAM.
+    fn set_dirty_page_tracking(&self, enable: bool) -> anyhow::Result<()>;
+
+    /// Returns the guest physical ranges written since tracking was enabled or
+    /// since the last call.
+    fn take_dirty_pages(&self) -> anyhow::Result<Vec<MemoryRange>>;
+
     /// Returns the reference time source.
     fn reference_time_source(&self) -> Option<ReferenceTimeSource>;
 
@@ -124,6 +131,8 @@ pub trait BasicPartitionStateAccess: 'static + Send + Sync + Inspect {
     fn scrub_vtl(&self, vtl: Vtl) -> anyhow::Result<()>;
     fn accept_initial_pages(&self, pages: Vec<(MemoryRange, PageVisibility)>)
     -> anyhow::Result<()>;
+    fn set_dirty_page_tracking(&self, enable: bool) -> anyhow::Result<()>;
+    fn take_dirty_pages(&self) -> anyhow::Result<Vec<MemoryRange>>;
 }
 
 impl<T: Partition + PartitionAccessState> BasicPartitionStateAccess for T {
@@ -167,6 +176,21 @@ fn accept_initial_pages(
             .accept_initial_pages(&pages)?;
         Ok(())
     }
+
+    fn set_dirty_page_tracking(&self, enable: bool) -> anyhow::Result<()> {
+        self.supports_dirty_page_tracking()
+            .context("dirty page tracking not supported")?
+            .set_dirty_page_tracking(enable)?;
+        Ok(())
+    }
+
+    fn take_dirty_pages(&self) -> anyhow::Result<Vec<MemoryRange>> {
+        let pages = self
+            .supports_dirty_page_tracking()
+            .context("dirty page tracking not supported")?
+            .take_dirty_pages()?;
+        Ok(pages)
+    }
 }
 
 impl<T> HvlitePartition for T
@@ -236,6 +260,14 @@ fn supports_reset(&self) -> bool {
         self.supports_reset().is_some()
     }
 
+    fn set_dirty_page_tracking(&self, enable: bool) -> anyhow::Result<()> {
+        BasicPartitionStateAccess::set_dirty_page_tracking(self, enable)
+    }
+
+    fn take_dirty_pages(&self) -> anyhow::Result<Vec<MemoryRange>> {
+        BasicPartitionStateAccess::take_dirty_pages(self)
+    }
+
     fn reference_time_source(&self) -> Option<ReferenceTimeSource> {
         self.reference_time_source()
     }
@@ -127,6 +127,7 @@
 use vmgs_resources::GuestStateEncryptionPolicy;
 use vmgs_resources::VmgsResource;
 use vmm_core::acpi_builder::AcpiTablesBuilder;
+use vmm_core::acpi_builder::NumaLocality;
 use vmm_core::input_distributor::InputDistributor;
 use vmm_core::partition_unit::Halt;
 use vmm_core::partition_unit::PartitionUnit;
@@ -137,6 +138,7 @@
 use vmm_core::vmbus_unit::offer_channel_unit;
 use vmm_core::vmbus_unit::offer_vmbus_device_handle_unit;
 use vmm_core_defs::HaltReason;
+use vmm_core_defs::debug_rpc::DebugRequest;
 use vmotherboard::BaseChipsetBuilder;
 use vmotherboard::BaseChipsetBuilderOutput;
 use vmotherboard::ChipsetDeviceHandle;
@@ -616,6 +618,13 @@ pub(crate) struct LoadedVm {
     running: bool,
 }
 
+/// Relays debugger requests to the partition unit, except for those that apply
+/// to the whole VM.
+struct DebuggerRelay {
+    recv: mesh::Receiver<DebugRequest>,
+    partition_send: mesh::Sender<DebugRequest>,
+}
+
 /// Most of the VM state for [`LoadedVm`], excluding things that are necessary
 /// for state machine transitions.
 struct LoadedVmInner {
@@ -670,6 +679,8 @@ struct LoadedVmInner {
     // relay halt messages, intercepting reset if configured.
     halt_recv: mesh::Receiver<HaltReason>,
     client_notify_send: mesh::Sender<HaltReason>,
+    halt_vps: Arc<Halt>,
+    debugger: Option<DebuggerRelay>,
     /// allow the guest to reset without notifying the client
     automatic_guest_reset: bool,
     pcie_host_bridges: Vec<PcieHostBridge>,
@@ -681,6 +692,18 @@ struct LoadedVmInner {
     )>,
 }
 
+/// Returns the distances and memory performance between vNUMA nodes to report
+/// in the SLIT and HMAT, if any are configured.
+fn numa_locality(memory: &MemoryConfig) -> Option<NumaLocality> {
+    let locality = NumaLocality {
+        distances: memory.numa_distances.clone(),
+        latencies: memory.numa_latencies.clone(),
+        bandwidths: memory.numa_bandwidths.clone(),
+    };
+    (locality.distances.is_some() || locality.latencies.is_some() || locality.bandwidths.is_some())
+        .then_some(locality)
+}
+
 fn convert_vtl2_config(
     vtl2_cfg: Option<&Vtl2Config>,
     load_mode: &LoadMode,
@@ -902,6 +925,19 @@ pub(crate) async fn new_with_hypervisor<P, H>(
         }
         .context("invalid memory configuration")?;
 
+        if let Some(numa_locality) = numa_locality(&cfg.memory) {
+            let node_count = processor_topology
+                .vps()
+                .map(|vp| vp.vnode)
+                .chain(mem_layout.ram().iter().map(|range| range.vnode))
+                .max()
+                .unwrap_or(0) as usize
+                + 1;
+            numa_locality
+                .validate(node_count)
+                .context("invalid vNUMA locality configuration")?;
+        }
+
         if mem_layout.end_of_layout() > 1 << physical_address_size {
             anyhow::bail!(
                 "memory layout ends at {:#x}, which exceeds the address with of {} bits",
@@ -1123,6 +1159,7 @@ async fn load(
         let halt_vps = Arc::new(halt_vps);
 
         resolver.add_resolver(vmm_core::platform_resolvers::HaltResolver(halt_vps.clone()));
+        resolver.add_resolver(memory_manager.ram_discard());
 
         let generation_id_recv = cfg.generation_id_recv.unwrap_or_else(|| mesh::channel().1);
 
@@ -1230,6 +1267,7 @@ async fn load(
                             mem_layout: &mem_layout,
                             cache_topology: None,
                             pcie_host_bridges: &Vec::new(),
+                            numa_locality: None,
                             arch: vmm_core::acpi_builder::AcpiArchConfig::X86 {
                                 with_ioapic: cfg.chipset.with_generic_ioapic,
                                 with_pic: cfg.chipset_capabilities.with_pic,
@@ -2249,6 +2287,22 @@ fn make_ids(
         // create a new channel to intercept guest resets
         let (halt_send, halt_recv) = mesh::channel();
 
+        // Intercept debugger requests that apply to the whole VM, relaying the
+        // rest to the partition unit.
+        let (debugger, partition_debugger_rpc) = match cfg.debugger_rpc {
+            Some(recv) => {
+                let (partition_send, partition_recv) = mesh::channel();
+                (
+                    Some(DebuggerRelay {
+                        recv,
+                        partition_send,
+                    }),
+                    Some(partition_recv),
+                )
+            }
+            None => (None, None),
+        };
+
         let (partition_unit, vp_runners) = PartitionUnit::new(
             driver_source.simple(),
             state_units
@@ -2258,7 +2312,7 @@ fn make_ids(
             partition.clone().into_vm_partition(),
             PartitionUnitParams {
                 processor_topology: &processor_topology,
-                halt_vps,
+                halt_vps: halt_vps.clone(),
                 halt_request_recv,
                 client_notify_send: halt_send,
                 vtl_guest_memory: [
@@ -2266,7 +2320,7 @@ fn make_ids(
                     None,
                     cfg.hypervisor.with_vtl2.is_some().then_some(&gm),
                 ],
-                debugger_rpc: cfg.debugger_rpc,
+                debugger_rpc: partition_debugger_rpc,
             },
         )
         .context("failed to create partition unit")?;
@@ -2347,6 +2401,8 @@ fn make_ids(
                 vfio_inspect,
                 halt_recv,
                 client_notify_send,
+                halt_vps,
+                debugger,
                 automatic_guest_reset: cfg.automatic_guest_reset,
                 pcie_host_bridges,
                 pcie_root_complexes,
@@ -2367,6 +2423,39 @@ fn make_ids(
 }
 
 impl LoadedVmInner {
+    fn inspect_worker(&self, resp: &mut inspect::Response<'_>) {
+        resp.field("memory", &self.memory_manager)
+            .field("memory_layout", &self.mem_layout)
+            .field("resolver", &self.resolver)
+            .field("vmgs", &self.vmgs_client_inspect_handle);
+        #[cfg(target_os = "linux")]
+        resp.field("vfio", &self.vfio_inspect);
+    }
+
+    fn nmi(&self, vpindex: u32) {
+        if vpindex < self.processor_topology.vp_count() {
+            // Send an NMI MSI to the processor. We could raise LINT1 instead,
+            // which would allow the guest to reconfigure the LINT to do
+            // something other than an NMI. Since this is for diagnostics, that
+            // doesn't seem like what we want.
+            //
+            // AARCH64-TODO: is there an equivalent?
+            #[cfg(guest_arch = "x86_64")]
+            self.partition.request_msi(
+                Vtl::Vtl0,
+                virt::irqcon::MsiRequest::new_x86(
+                    virt::irqcon::DeliveryMode::NMI,
+                    self.processor_topology
+                        .vp_arch(VpIndex::new(vpindex))
+                        .apic_id,
+                    false,
+                    0,
+                    false,
+                ),
+            );
+        }
+    }
+
     async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
         let cache_topology = if cfg!(guest_arch = "aarch64") {
             Some(
@@ -2376,11 +2465,13 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
         } else {
             None
         };
+        let numa_locality = numa_locality(&self.memory_cfg);
         let acpi_builder = AcpiTablesBuilder {
             processor_topology: &self.processor_topology,
             mem_layout: &self.mem_layout,
             cache_topology: cache_topology.as_ref(),
             pcie_host_bridges: &self.pcie_host_bridges,
+            numa_locality: numa_locality.as_ref(),
             #[cfg(guest_arch = "x86_64")]
             arch: vmm_core::acpi_builder::AcpiArchConfig::X86 {
                 with_ioapic: self.chipset_cfg.with_generic_ioapic,
@@ -2515,6 +2606,14 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                 let srat = acpi_builder.build_srat();
                 let mcfg = (!self.pcie_host_bridges.is_empty()).then(|| acpi_builder.build_mcfg());
                 let pptt = cache_topology.is_some().then(|| acpi_builder.build_pptt());
+                let slit = numa_locality
+                    .as_ref()
+                    .is_some_and(|numa| numa.distances.is_some())
+                    .then(|| acpi_builder.build_slit());
+                let hmat = numa_locality
+                    .as_ref()
+                    .is_some_and(|numa| numa.latencies.is_some() || numa.bandwidths.is_some())
+                    .then(|| acpi_builder.build_hmat());
                 let load_settings = super::vm_loaders::uefi::UefiLoadSettings {
                     debugging: enable_debugging,
                     memory_protections: enable_memory_protections,
@@ -2539,6 +2638,8 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                     &srat,
                     mcfg.as_deref(),
                     pptt.as_deref(),
+                    slit.as_deref(),
+                    hmat.as_deref(),
                 )?;
 
                 (regs, Vec::new())
@@ -2557,6 +2658,10 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
             } => {
                 let madt = acpi_builder.build_madt();
                 let srat = acpi_builder.build_srat();
+                let slit = numa_locality
+                    .as_ref()
+                    .is_some_and(|numa| numa.distances.is_some())
+                    .then(|| acpi_builder.build_slit());
                 const ENTROPY_SIZE: usize = 64;
                 let mut entropy = [0u8; ENTROPY_SIZE];
                 getrandom::fill(&mut entropy).unwrap();
@@ -2570,7 +2675,7 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                     acpi_tables: super::vm_loaders::igvm::AcpiTables {
                         madt: &madt,
                         srat: &srat,
-                        slit: None,
+                        slit: slit.as_deref(),
                         pptt: None,
                     },
                     vtl2_base_address,
@@ -2662,6 +2767,7 @@ enum Event {
             WorkerRpc(Result<WorkerRpc<RestartState>, mesh::RecvError>),
             VmRpc(Result<VmRpc, mesh::RecvError>),
             Halt(Result<HaltReason, mesh::RecvError>),
+            Debug(Result<DebugRequest, mesh::RecvError>),
         }
 
         // Start a task to handle state unit inspections by filtering the worker
@@ -2689,7 +2795,15 @@ enum Event {
                 let a = rpc_recv.recv().map(Event::VmRpc);
                 let b = worker_rpc.recv().map(Event::WorkerRpc);
                 let c = self.inner.halt_recv.recv().map(Event::Halt);
-                (a, b, c).race().await
+                let debugger = &mut self.inner.debugger;
+                let d = async move {
+                    match debugger {
+                        Some(debugger) => debugger.recv.recv().await,
+                        None => std::future::pending().await,
+                    }
+                }
+                .map(Event::Debug);
+                (a, b, c, d).race().await
             };
 
             match event {
@@ -2728,14 +2842,9 @@ enum Event {
                             }
                         }
                     }
-                    WorkerRpc::Inspect(deferred) => deferred.respond(|resp| {
-                        resp.field("memory", &self.inner.memory_manager)
-                            .field("memory_layout", &self.inner.mem_layout)
-                            .field("resolver", &self.inner.resolver)
-                            .field("vmgs", &self.inner.vmgs_client_inspect_handle);
-                        #[cfg(target_os = "linux")]
-                        resp.field("vfio", &self.inner.vfio_inspect);
-                    }),
+                    WorkerRpc::Inspect(deferred) => {
+                        deferred.respond(|resp| self.inner.inspect_worker(resp))
+                    }
                 },
                 Event::VmRpc(Err(_)) => break,
                 Event::VmRpc(Ok(message)) => match message {
@@ -2752,31 +2861,7 @@ enum Event {
                         rpc.handle_failable(async |()| self.save().await.map(ProtobufMessage::new))
                             .await
                     }
-                    VmRpc::Nmi(rpc) => rpc.handle_sync(|vpindex| {
-                        if vpindex < self.inner.processor_topology.vp_count() {
-                            // Send an NMI MSI to the processor. We could raise
-                            // LINT1 instead, which would allow the guest to
-                            // reconfigure the LINT to do something other than
-                            // an NMI. Since this is for diagnostics, that
-                            // doesn't seem like what we want.
-                            //
-                            // AARCH64-TODO: is there an equivalent?
-                            #[cfg(guest_arch = "x86_64")]
-                            self.inner.partition.request_msi(
-                                Vtl::Vtl0,
-                                virt::irqcon::MsiRequest::new_x86(
-                                    virt::irqcon::DeliveryMode::NMI,
-                                    self.inner
-                                        .processor_topology
-                                        .vp_arch(VpIndex::new(vpindex))
-                                        .apic_id,
-                                    false,
-                                    0,
-                                    false,
-                                ),
-                            );
-                        }
-                    }),
+                    VmRpc::Nmi(rpc) => rpc.handle_sync(|vpindex| self.inner.nmi(vpindex)),
                     VmRpc::AddVmbusDevice(rpc) => {
                         rpc.handle_failable(async |(vtl, resource)| {
                             let vmbus = match vtl {
@@ -2850,6 +2935,13 @@ enum Event {
                     VmRpc::WriteMemory(rpc) => rpc.handle_failable_sync(|(gpa, bytes)| {
                         self.inner.gm.write_at(gpa, bytes.as_slice())
                     }),
+                    VmRpc::SetDirtyPageTracking(rpc) => rpc.handle_failable_sync(|enable| {
+                        self.inner.partition.set_dirty_page_tracking(enable)
+                    }),
+                    VmRpc::TakeDirtyMemory(rpc) => rpc.handle_failable_sync(|()| {
+                        let pages = self.inner.partition.take_dirty_pages()?;
+                        anyhow::Ok(self.inner.memory_manager.ram_backing_offsets(&pages))
+                    }),
                     VmRpc::UpdateCliParams(rpc) => {
                         rpc.handle_failable_sync(|params| match &mut self.inner.load_mode {
                             LoadMode::Igvm { cmdline, .. } => {
@@ -2961,6 +3053,8 @@ enum Event {
                         .await
                     }
                 },
+                Event::Debug(Err(_)) => self.inner.debugger = None,
+                Event::Debug(Ok(request)) => self.handle_debug_request(request).await,
                 Event::Halt(Err(_)) => break,
                 Event::Halt(Ok(reason)) => {
                     if matches!(reason, HaltReason::Reset) && self.inner.automatic_guest_reset {
@@ -3143,6 +3237,55 @@ async fn serialize(
         }
     }
 
+    async fn handle_debug_request(&mut self, request: DebugRequest) {
+        match request {
+            DebugRequest::Inspect(deferred) => deferred.respond(|resp| {
+                resp.merge(&self.state_units.inspector());
+                self.inner.inspect_worker(resp);
+            }),
+            DebugRequest::Nmi(rpc) => rpc.handle_failable_sync(|vpindex| {
+                anyhow::ensure!(
+                    vpindex < self.inner.processor_topology.vp_count(),
+                    "invalid vp {vpindex}"
+                );
+                self.inner.nmi(vpindex);
+                anyhow::Ok(())
+            }),
+            DebugRequest::Reset(rpc) => {
+                rpc.handle_failable(async |()| {
+                    let resume = self.pause().await;
+                    self.state_units.reset().await?;
+                    self.inner.load_firmware(false).await?;
+                    // Keep the VPs from running until the debugger resumes
+                    // them, as if they had broken in at the reset vector.
+                    self.inner
+                        .halt_vps
+                        .halt(HaltReason::DebugBreak { vp: None });
+                    if resume {
+                        self.resume().await;
+                    }
+                    anyhow::Ok(())
+                })
+                .await
+            }
+            DebugRequest::GetMemoryMap(rpc) => rpc.handle_failable_sync(|()| {
+                anyhow::Ok(
+                    self.inner
+                        .mem_layout
+                        .ram()
+                        .iter()
+                        .map(|ram| (ram.range.start(), ram.range.len()))
+                        .collect(),
+                )
+            }),
+            request => {
+                if let Some(debugger) = &self.inner.debugger {
+                    debugger.partition_send.send(request);
+                }
+            }
+        }
+    }
+
     async fn reset(&mut self, reload_firmware: bool) -> anyhow::Result<()> {
         let resume = self.pause().await;
 
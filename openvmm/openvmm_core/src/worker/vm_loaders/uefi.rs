@@ -58,6 +58,8 @@ pub fn load_uefi(
     srat: &[u8],
     mcfg: Option<&[u8]>,
     pptt: Option<&[u8]>,
+    slit: Option<&[u8]>,
+    hmat: Option<&[u8]>,
 ) -> Result<Vec<Register>, Error> {
     if mem_layout.mmio().len() < 2 {
         return Err(Error::UnsupportedMmio);
@@ -178,6 +180,14 @@ pub fn load_uefi(
         cfg.add_raw(config::BlobStructureType::Pptt, pptt);
     }
 
+    if let Some(slit) = slit {
+        cfg.add_raw(config::BlobStructureType::Slit, slit);
+    }
+
+    if let Some(hmat) = hmat {
+        cfg.add_raw(config::BlobStructureType::Hmat, hmat);
+    }
+
     if !pcie_host_bridges.is_empty() {
         let mut ssdt = acpi::ssdt::Ssdt::new();
         for bridge in pcie_host_bridges {
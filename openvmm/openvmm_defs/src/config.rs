@@ -342,6 +342,18 @@ pub struct MemoryConfig {
     /// across vNUMA nodes according to these sizes instead of assigning all RAM
     /// to node 0. The sum must equal `mem_size`.
     pub numa_mem_sizes: Option<Vec<u64>>,
+    /// Test only: the relative distances between vNUMA nodes, reported to the
+    /// guest in the SLIT. Entry `[i][j]` is the distance from node `i` to node
+    /// `j`, and the distance from a node to itself must be 10.
+    pub numa_distances: Option<Vec<Vec<u8>>>,
+    /// Test only: the latencies of memory accesses between vNUMA nodes in
+    /// nanoseconds, reported to the guest in the HMAT. Entry `[i][j]` is the
+    /// latency from the processors in node `i` to the memory in node `j`.
+    pub numa_latencies: Option<Vec<Vec<u32>>>,
+    /// Test only: the bandwidths of memory accesses between vNUMA nodes in
+    /// MB/s, reported to the guest in the HMAT, indexed like
+    /// `numa_latencies`.
+    pub numa_bandwidths: Option<Vec<Vec<u32>>>,
 }
 
 #[derive(Debug, MeshPayload, Default)]
@@ -40,6 +40,16 @@ pub enum VmRpc {
     AddPcieDevice(FailableRpc<(String, Resource<PciDeviceHandleKind>), ()>),
     /// Hot-remove a PCIe device from a named port at runtime.
     RemovePcieDevice(FailableRpc<String, ()>),
+    /// Enables or disables tracking of guest writes to RAM, for incremental
+    /// snapshots. Enabling tracking resets all pages to clean.
+    SetDirtyPageTracking(FailableRpc<bool, ()>),
+    /// Returns the `(offset, length)` ranges of the shared memory backing
+    /// written by the guest since tracking was enabled or since the last
+    /// call.
+    ///
+    /// Only writes made by the guest's processors are tracked, so this should
+    /// be called with the VM paused and its devices quiesced.
+    TakeDirtyMemory(FailableRpc<(), Vec<(u64, u64)>>),
 }
 
 #[derive(Debug, MeshPayload, thiserror::Error)]
@@ -75,6 +85,8 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             VmRpc::UpdateCliParams(_) => "UpdateCliParams",
             VmRpc::AddPcieDevice(_) => "AddPcieDevice",
             VmRpc::RemovePcieDevice(_) => "RemovePcieDevice",
+            VmRpc::SetDirtyPageTracking(_) => "SetDirtyPageTracking",
+            VmRpc::TakeDirtyMemory(_) => "TakeDirtyMemory",
         };
         f.pad(s)
     }
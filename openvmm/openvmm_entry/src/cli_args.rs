@@ -62,6 +62,26 @@ pub struct Options {
     #[clap(long, value_name = "SIZES", value_parser = parse_memory, value_delimiter = ',', conflicts_with = "memory")]
     pub numa_memory: Option<Vec<u64>>,
 
+    /// relative distances between vNUMA nodes, reported in the ACPI SLIT, as a
+    /// comma-separated square matrix in row-major order (e.g.
+    /// "10,20,20,10"). The distance from a node to itself must be 10. This is
+    /// for test-only usage.
+    #[clap(long, value_name = "MATRIX", value_delimiter = ',')]
+    pub numa_distances: Option<Vec<u8>>,
+
+    /// memory access latencies between vNUMA nodes in nanoseconds, reported
+    /// in the ACPI HMAT, as a comma-separated square matrix in row-major
+    /// order. Row i, column j is the latency from the processors in node i to
+    /// the memory in node j. This is for test-only usage.
+    #[clap(long, value_name = "MATRIX", value_delimiter = ',')]
+    pub numa_latencies: Option<Vec<u32>>,
+
+    /// memory access bandwidths between vNUMA nodes in MB/s, reported in the
+    /// ACPI HMAT, as a comma-separated square matrix laid out like
+    /// --numa-latencies. This is for test-only usage.
+    #[clap(long, value_name = "MATRIX", value_delimiter = ',')]
+    pub numa_bandwidths: Option<Vec<u32>>,
+
     /// use shared memory segment
     #[clap(short = 'M', long)]
     pub shared_memory: bool,
@@ -76,11 +96,23 @@ pub struct Options {
     #[clap(long, value_name = "FILE", conflicts_with = "private_memory")]
     pub memory_backing_file: Option<PathBuf>,
 
-    /// Restore VM from a snapshot directory (implies file-backed memory from
-    /// the snapshot's memory.bin). Cannot be used with --memory-backing-file.
-    #[clap(long, value_name = "DIR", conflicts_with = "memory_backing_file")]
+    /// Restore VM from a snapshot directory. Guest RAM is backed by the
+    /// snapshot's memory.bin, or, with --memory-backing-file, copied into the
+    /// backing file.
+    #[clap(long, value_name = "DIR")]
     pub restore_snapshot: Option<PathBuf>,
 
+    /// Wait for a live migration from another OpenVMM process on this Unix
+    /// socket path, then run the migrated VM instead of booting. Guest RAM
+    /// is received into the memory backing file.
+    #[clap(
+        long,
+        value_name = "SOCKET",
+        requires = "memory_backing_file",
+        conflicts_with = "restore_snapshot"
+    )]
+    pub migrate_listen: Option<PathBuf>,
+
     /// use private anonymous memory for guest RAM
     #[clap(long, conflicts_with_all = ["memory_backing_file", "restore_snapshot"])]
     pub private_memory: bool,
@@ -160,13 +192,15 @@ pub struct Options {
 valid disk kinds:
     `mem:<len>`                    memory backed disk
         <len>: length of ramdisk, e.g.: `1G`
-    `memdiff:<disk>`               memory backed diff disk
+    `memdiff[;commit]:<disk>`      memory backed diff disk
         <disk>: lower disk, e.g.: `file:base.img`
+        `;commit`: open <disk> for write, so the diff can be committed into it
     `file:<path>[;direct][;create=<len>]`   file-backed disk
         <path>: path to file
         `;direct`: bypass the OS page cache
     `sql:<path>[;create=<len>]`    SQLite-backed disk (dev/test)
-    `sqldiff:<path>[;create]:<disk>` SQLite diff layer on a backing disk
+    `sqldiff:<path>[;create][;commit]:<disk>` SQLite diff layer on a backing disk
+        `;commit`: open <disk> for write, so the diff can be committed into it
     `autocache:<key>:<disk>`       auto-cached SQLite layer (use `autocache::<disk>` to omit key; needs OPENVMM_AUTO_CACHE_PATH)
     `blob:<type>:<url>`            HTTP blob (read-only)
         <type>: `flat` or `vhd1`
@@ -196,13 +230,15 @@ pub struct Options {
 valid disk kinds:
     `mem:<len>`                    memory backed disk
         <len>: length of ramdisk, e.g.: `1G`
-    `memdiff:<disk>`               memory backed diff disk
+    `memdiff[;commit]:<disk>`      memory backed diff disk
         <disk>: lower disk, e.g.: `file:base.img`
+        `;commit`: open <disk> for write, so the diff can be committed into it
     `file:<path>[;direct][;create=<len>]`   file-backed disk
         <path>: path to file
         `;direct`: bypass the OS page cache
     `sql:<path>[;create=<len>]`    SQLite-backed disk (dev/test)
-    `sqldiff:<path>[;create]:<disk>` SQLite diff layer on a backing disk
+    `sqldiff:<path>[;create][;commit]:<disk>` SQLite diff layer on a backing disk
+        `;commit`: open <disk> for write, so the diff can be committed into it
     `autocache:<key>:<disk>`       auto-cached SQLite layer (use `autocache::<disk>` to omit key; needs OPENVMM_AUTO_CACHE_PATH)
     `blob:<type>:<url>`            HTTP blob (read-only)
         <type>: `flat` or `vhd1`
@@ -231,8 +267,9 @@ pub struct Options {
 valid disk kinds:
     `mem:<len>`                    memory backed disk
         <len>: length of ramdisk, e.g.: `1G`
-    `memdiff:<disk>`               memory backed diff disk
+    `memdiff[;commit]:<disk>`      memory backed diff disk
         <disk>: lower disk, e.g.: `file:base.img`
+        `;commit`: open <disk> for write, so the diff can be committed into it
     `file:<path>[;direct]`                  file-backed disk
         <path>: path to file
         `;direct`: bypass the OS page cache
@@ -246,6 +283,35 @@ pub struct Options {
     #[clap(long = "virtio-blk")]
     pub virtio_blk: Vec<DiskCli>,
 
+    /// attach a disk via a virtio-scsi controller
+    #[clap(long_help = r#"
+e.g: --virtio-scsi memdiff:file:/path/to/disk.vhd
+
+All disks are attached to target 0 of a single controller, in order starting
+at LUN 0, or to one controller per PCIe port.
+
+syntax: <path> | kind:<arg>[,flag,opt=arg,...]
+
+valid disk kinds:
+    `mem:<len>`                    memory backed disk
+        <len>: length of ramdisk, e.g.: `1G`
+    `memdiff[;commit]:<disk>`      memory backed diff disk
+        <disk>: lower disk, e.g.: `file:base.img`
+        `;commit`: open <disk> for write, so the diff can be committed into it
+    `file:<path>[;direct]`                  file-backed disk
+        <path>: path to file
+        `;direct`: bypass the OS page cache
+
+flags:
+    `ro`                           open disk as read-only
+    `dvd`                          specifies that device is cd/dvd and it is read_only
+
+options:
+    `pcie_port=<name>`             present the controller using pcie under the specified port, incompatible with `dvd`
+"#)]
+    #[clap(long = "virtio-scsi")]
+    pub virtio_scsi: Vec<DiskCli>,
+
     /// Attach a vhost-user device via a Unix socket.
     ///
     /// The first positional argument is the socket path. Options:
@@ -310,7 +376,7 @@ pub struct Options {
     #[clap(long, requires("vtl2"), conflicts_with("gfx"))]
     pub vtl2_gfx: bool,
 
-    /// listen for vnc connections. implied by gfx.
+    /// listen for vnc connections. implied by gfx and virtio-gpu.
     #[clap(long)]
     pub vnc: bool,
 
@@ -318,6 +384,22 @@ pub struct Options {
     #[clap(long, value_name = "PORT", default_value = "5900")]
     pub vnc_port: u16,
 
+    /// VNC listen address
+    #[clap(long, value_name = "ADDRESS", default_value = "127.0.0.1")]
+    pub vnc_address: std::net::IpAddr,
+
+    /// require VNC clients to authenticate with the password in this file
+    #[clap(long, value_name = "PATH")]
+    pub vnc_password_file: Option<PathBuf>,
+
+    /// require VNC clients to use TLS, presenting this PEM certificate chain
+    #[clap(long, value_name = "PATH", requires("vnc_tls_key"))]
+    pub vnc_tls_cert: Option<PathBuf>,
+
+    /// the PEM private key for --vnc-tls-cert
+    #[clap(long, value_name = "PATH", requires("vnc_tls_cert"))]
+    pub vnc_tls_key: Option<PathBuf>,
+
     /// set the APIC ID offset, for testing APIC IDs that don't match VP index
     #[cfg(guest_arch = "x86_64")]
     #[clap(long, default_value_t)]
@@ -462,6 +544,29 @@ pub struct Options {
     #[clap(long, value_name = "PORT", requires("virtio_rng"))]
     pub virtio_rng_pcie_port: Option<String>,
 
+    /// add a virtio memory balloon device, whose target size is set with the
+    /// `balloon` interactive command
+    #[clap(long)]
+    pub virtio_balloon: bool,
+
+    /// add the virtio-balloon device under either the PCI or MMIO bus, or whatever the hypervisor supports (pci | mmio | vpci | auto)
+    #[clap(
+        long,
+        value_name = "BUS",
+        default_value = "auto",
+        requires("virtio_balloon")
+    )]
+    pub virtio_balloon_bus: VirtioBusCli,
+
+    /// attach the virtio-balloon device to the specified PCIe port (overrides --virtio-balloon-bus)
+    #[clap(long, value_name = "PORT", requires("virtio_balloon"))]
+    pub virtio_balloon_pcie_port: Option<String>,
+
+    /// don't offer free page reporting on the virtio-balloon device, with
+    /// which the guest returns its free memory without inflating the balloon
+    #[clap(long, requires("virtio_balloon"))]
+    pub virtio_balloon_no_page_reporting: bool,
+
     /// virtio console device backed by a serial backend (/dev/hvc0 in guest)
     ///
     /// Accepts serial config (console | stderr | listen=\<path\> |
@@ -474,6 +579,35 @@ pub struct Options {
     #[clap(long, value_name = "PORT", requires("virtio_console"))]
     pub virtio_console_pcie_port: Option<String>,
 
+    /// add a virtio-input device that receives the VNC input (keyboard |
+    /// mouse | tablet); can be passed multiple times
+    ///
+    /// Use these for guests without VMBus or PS/2 input drivers. A `tablet`
+    /// tracks the VNC pointer exactly, while a relative `mouse` may drift.
+    #[clap(long, value_name = "KIND")]
+    pub virtio_input: Vec<VirtioInputCli>,
+
+    /// add the virtio-input devices under either the PCI or MMIO bus, or whatever the hypervisor supports (pci | mmio | vpci | auto)
+    #[clap(long, value_name = "BUS", default_value = "auto")]
+    pub virtio_input_bus: VirtioBusCli,
+
+    /// add a virtio-gpu 2D display device, shown over VNC. implies vnc.
+    ///
+    /// Use this for guests without VMBus or VGA display drivers. The device
+    /// draws into the framebuffer otherwise used by the synthetic video and
+    /// VGA devices, so it cannot be used with them.
+    #[clap(long, conflicts_with_all = ["gfx", "vtl2_gfx", "pcat"])]
+    pub virtio_gpu: bool,
+
+    /// add the virtio-gpu device under either the PCI or MMIO bus, or whatever the hypervisor supports (pci | mmio | vpci | auto)
+    #[clap(
+        long,
+        value_name = "BUS",
+        default_value = "auto",
+        requires("virtio_gpu")
+    )]
+    pub virtio_gpu_bus: VirtioBusCli,
+
     /// add a virtio vsock device with the given Unix socket base path
     #[clap(long, value_name = "PATH")]
     pub virtio_vsock_path: Option<String>,
@@ -497,6 +631,17 @@ pub struct Options {
     #[clap(long, value_name = "PATH")]
     pub pidfile: Option<PathBuf>,
 
+    /// serve inspect values as OpenMetrics (Prometheus) metrics over HTTP at
+    /// /metrics on ADDRESS, which is either a TCP socket address (such as
+    /// 127.0.0.1:9100) or a Unix socket path
+    #[clap(long, value_name = "ADDRESS")]
+    pub metrics: Option<String>,
+
+    /// an inspect path to export with --metrics (can be passed multiple
+    /// times). defaults to `vm`.
+    #[clap(long, value_name = "PATH", requires("metrics"))]
+    pub metrics_path: Vec<String>,
+
     /// run as a ttrpc server on the specified Unix socket
     #[clap(long, value_name = "SOCKETPATH")]
     pub ttrpc: Option<PathBuf>,
@@ -597,6 +742,25 @@ pub struct Options {
     #[clap(long, value_name = "PORT")]
     pub gdb: Option<u16>,
 
+    /// report the guest RAM ranges to gdb as a memory map
+    ///
+    /// gdb refuses memory accesses outside the map, including to virtual
+    /// addresses, unless `set mem inaccessible-by-default off` is run.
+    #[clap(long, requires("gdb"))]
+    pub gdb_memory_map: bool,
+
+    /// present the tasks of a guest Linux kernel as gdb threads, using the
+    /// kernel description in the given JSON file
+    ///
+    /// The description has the `task_struct` field offsets, and the kernel
+    /// symbol addresses unless they are read from --gdb-vmlinux.
+    #[clap(long, value_name = "FILE", requires("gdb"))]
+    pub gdb_linux: Option<PathBuf>,
+
+    /// read the kernel symbol addresses for --gdb-linux from a vmlinux file
+    #[clap(long, value_name = "FILE", requires("gdb_linux"))]
+    pub gdb_vmlinux: Option<PathBuf>,
+
     /// enable emulated MANA devices with the given network backend (see --net)
     ///
     /// Prefix with `pcie_port=<port_name>:` to expose the nic over emulated PCIe
@@ -647,12 +811,14 @@ pub struct Options {
 valid disk kinds:
     `mem:<len>`                    memory backed disk
         <len>: length of ramdisk, e.g.: `1G`
-    `memdiff:<disk>`               memory backed diff disk
+    `memdiff[;commit]:<disk>`      memory backed diff disk
         <disk>: lower disk, e.g.: `file:base.img`
+        `;commit`: open <disk> for write, so the diff can be committed into it
     `file:<path>[;create=<len>]`   file-backed disk
         <path>: path to file
     `sql:<path>[;create=<len>]`    SQLite-backed disk (dev/test)
-    `sqldiff:<path>[;create]:<disk>` SQLite diff layer on a backing disk
+    `sqldiff:<path>[;create][;commit]:<disk>` SQLite diff layer on a backing disk
+        `;commit`: open <disk> for write, so the diff can be committed into it
     `blob:<type>:<url>`            HTTP blob (read-only)
         <type>: `flat` or `vhd1`
     `crypt:<cipher>:<key_file>:<disk>` encrypted disk wrapper
@@ -679,12 +845,14 @@ pub struct Options {
 valid disk kinds:
     `mem:<len>`                    memory backed disk
         <len>: length of ramdisk, e.g.: `1G`
-    `memdiff:<disk>`               memory backed diff disk
+    `memdiff[;commit]:<disk>`      memory backed diff disk
         <disk>: lower disk, e.g.: `file:base.img`
+        `;commit`: open <disk> for write, so the diff can be committed into it
     `file:<path>[;create=<len>]`   file-backed disk
         <path>: path to file
     `sql:<path>[;create=<len>]`    SQLite-backed disk (dev/test)
-    `sqldiff:<path>[;create]:<disk>` SQLite diff layer on a backing disk
+    `sqldiff:<path>[;create][;commit]:<disk>` SQLite diff layer on a backing disk
+        `;commit`: open <disk> for write, so the diff can be committed into it
     `blob:<type>:<url>`            HTTP blob (read-only)
         <type>: `flat` or `vhd1`
     `crypt:<cipher>:<key_file>:<disk>` encrypted disk wrapper
@@ -924,6 +1092,13 @@ pub enum VirtioBusCli {
     Vpci,
 }
 
+#[derive(Copy, Clone, clap::ValueEnum)]
+pub enum VirtioInputCli {
+    Keyboard,
+    Mouse,
+    Tablet,
+}
+
 /// Parse an optional `pcie_port=<name>:` prefix from a CLI argument string.
 ///
 /// Returns `(Some(port_name), rest)` if the prefix is present, or
@@ -966,7 +1141,7 @@ pub enum SecureBootTemplateCli {
     UefiCa,
 }
 
-fn parse_memory(s: &str) -> anyhow::Result<u64> {
+pub(crate) fn parse_memory(s: &str) -> anyhow::Result<u64> {
     if s == "VMGS_DEFAULT" {
         Ok(vmgs_format::VMGS_DEFAULT_CAPACITY)
     } else {
@@ -1007,17 +1182,21 @@ fn parse_number(s: &str) -> Result<u64, std::num::ParseIntError> {
 pub enum DiskCliKind {
     // mem:<len>
     Memory(u64),
-    // memdiff:<kind>
-    MemoryDiff(Box<DiskCliKind>),
+    // memdiff[;commit]:<kind>
+    MemoryDiff {
+        disk: Box<DiskCliKind>,
+        commit: bool,
+    },
     // sql:<path>[;create=<len>]
     Sqlite {
         path: PathBuf,
         create_with_len: Option<u64>,
     },
-    // sqldiff:<path>[;create]:<kind>
+    // sqldiff:<path>[;create][;commit]:<kind>
     SqliteDiff {
         path: PathBuf,
         create: bool,
+        commit: bool,
         disk: Box<DiskCliKind>,
     },
     // autocache:[key]:<kind>
@@ -1133,7 +1312,10 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
             }
             Some((kind, arg)) => match kind {
                 "mem" => DiskCliKind::Memory(parse_memory(arg)?),
-                "memdiff" => DiskCliKind::MemoryDiff(Box::new(arg.parse()?)),
+                "memdiff" | "memdiff;commit" => DiskCliKind::MemoryDiff {
+                    disk: Box::new(arg.parse()?),
+                    commit: kind == "memdiff;commit",
+                },
                 "sql" => {
                     let FileOpts {
                         path,
@@ -1152,22 +1334,24 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
                     let (path_and_opts, kind) =
                         arg.split_once(':').context("expected path[;opts]:kind")?;
                     let disk = Box::new(kind.parse()?);
-                    match path_and_opts.split_once(';') {
-                        Some((path, create)) => {
-                            if create != "create" {
-                                anyhow::bail!("invalid syntax after ';', expected 'create'")
-                            }
-                            DiskCliKind::SqliteDiff {
-                                path: path.into(),
-                                create: true,
-                                disk,
-                            }
+                    let mut opts = path_and_opts.split(';');
+                    let path = opts.next().unwrap().into();
+                    let mut create = false;
+                    let mut commit = false;
+                    for opt in opts {
+                        match opt {
+                            "create" => create = true,
+                            "commit" => commit = true,
+                            _ => anyhow::bail!(
+                                "invalid syntax after ';', expected 'create' or 'commit'"
+                            ),
                         }
-                        None => DiskCliKind::SqliteDiff {
-                            path: path_and_opts.into(),
-                            create: false,
-                            disk,
-                        },
+                    }
+                    DiskCliKind::SqliteDiff {
+                        path,
+                        create,
+                        commit,
+                        disk,
                     }
                 }
                 "autocache" => {
@@ -2483,7 +2667,10 @@ fn test_parse_memory_diff_disk() {
         let s = "memdiff:file:base.img";
         let disk = DiskCliKind::from_str(s).unwrap();
         match disk {
-            DiskCliKind::MemoryDiff(inner) => match *inner {
+            DiskCliKind::MemoryDiff {
+                disk,
+                commit: false,
+            } => match *disk {
                 DiskCliKind::File {
                     path,
                     create_with_len,
@@ -2496,6 +2683,12 @@ fn test_parse_memory_diff_disk() {
             },
             _ => panic!("Expected MemoryDiff variant"),
         }
+
+        // Test with commit option
+        assert!(matches!(
+            DiskCliKind::from_str("memdiff;commit:file:base.img").unwrap(),
+            DiskCliKind::MemoryDiff { commit: true, .. }
+        ));
     }
 
     #[test]
@@ -2534,9 +2727,15 @@ fn test_parse_sqlite_diff_disk() {
         let s = "sqldiff:diff.sqlite;create:file:base.img";
         let disk = DiskCliKind::from_str(s).unwrap();
         match disk {
-            DiskCliKind::SqliteDiff { path, create, disk } => {
+            DiskCliKind::SqliteDiff {
+                path,
+                create,
+                commit,
+                disk,
+            } => {
                 assert_eq!(path, PathBuf::from("diff.sqlite"));
                 assert!(create);
+                assert!(!commit);
                 match *disk {
                     DiskCliKind::File {
                         path,
@@ -2556,9 +2755,15 @@ fn test_parse_sqlite_diff_disk() {
         let s = "sqldiff:diff.sqlite:file:base.img";
         let disk = DiskCliKind::from_str(s).unwrap();
         match disk {
-            DiskCliKind::SqliteDiff { path, create, disk } => {
+            DiskCliKind::SqliteDiff {
+                path,
+                create,
+                commit,
+                disk,
+            } => {
                 assert_eq!(path, PathBuf::from("diff.sqlite"));
                 assert!(!create);
+                assert!(!commit);
                 match *disk {
                     DiskCliKind::File {
                         path,
@@ -2573,6 +2778,17 @@ fn test_parse_sqlite_diff_disk() {
             }
             _ => panic!("Expected SqliteDiff variant"),
         }
+
+        // Test with create and commit options
+        assert!(matches!(
+            DiskCliKind::from_str("sqldiff:diff.sqlite;create;commit:file:base.img").unwrap(),
+            DiskCliKind::SqliteDiff {
+                create: true,
+                commit: true,
+                ..
+            }
+        ));
+        assert!(DiskCliKind::from_str("sqldiff:diff.sqlite;bogus:file:base.img").is_err());
     }
 
     #[test]
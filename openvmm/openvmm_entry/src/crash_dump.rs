@@ -22,16 +22,19 @@
 
 /// Spawns a crash dump handling task and returns a resource to instantiate a
 /// guest crash device.
+///
+/// If `notify` is set, the path of each completed dump is sent to it.
 pub fn spawn_dump_handler(
     spawner: impl Spawn,
     dump_path: PathBuf,
     max_file_size: Option<u64>,
+    notify: Option<mesh::Sender<PathBuf>>,
 ) -> (Resource<VmbusDeviceHandleKind>, Task<()>) {
     const DEFAULT_MAX_DUMP_SIZE: u64 = 256 * 1024 * 1024;
 
     let (send, recv) = channel::<FailableRpc<_, _>>();
     let task = spawner.spawn("crash_dumps", async move {
-        handle_dump_requests(&dump_path, recv).await
+        handle_dump_requests(&dump_path, recv, notify.as_ref()).await
     });
     let config = GuestCrashDeviceHandle {
         request_dump: send,
@@ -47,6 +50,7 @@ pub async fn handle_dump_requests(
     mut recv: mesh::Receiver<
         mesh::rpc::Rpc<OneshotReceiver<()>, Result<File, mesh::error::RemoteError>>,
     >,
+    notify: Option<&mesh::Sender<PathBuf>>,
 ) {
     let mut tasks = FuturesUnordered::new();
     while let Some(rpc) = ((&mut recv).map(Some), (&mut tasks).map(|()| None))
@@ -68,13 +72,17 @@ pub async fn handle_dump_requests(
                 .context("failed to clone file")?;
 
             tracing::info!(path = %tempfile.path().display(), "writing VTL2 crash dump");
-            tasks.push(wait_for_dump(done, tempfile));
+            tasks.push(wait_for_dump(done, tempfile, notify));
             anyhow::Ok(file)
         })
     }
 }
 
-async fn wait_for_dump(done: OneshotReceiver<()>, tempfile: tempfile::NamedTempFile) {
+async fn wait_for_dump(
+    done: OneshotReceiver<()>,
+    tempfile: tempfile::NamedTempFile,
+    notify: Option<&mesh::Sender<PathBuf>>,
+) {
     if let Ok(()) = done.await {
         match tempfile.keep() {
             Ok((_, path)) => {
@@ -82,6 +90,9 @@ async fn wait_for_dump(done: OneshotReceiver<()>, tempfile: tempfile::NamedTempF
                     path = %path.display(),
                     "wrote VTL2 crash dump"
                 );
+                if let Some(notify) = notify {
+                    notify.send(path);
+                }
             }
             Err(err) => {
                 tracing::error!(
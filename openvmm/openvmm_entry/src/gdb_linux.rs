@@ -0,0 +1,153 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Loading the guest Linux kernel description used by the debugger to present
+//! the kernel's tasks as threads.
+
+use anyhow::Context;
+use debug_worker_defs::LinuxKernelLayout;
+use object::Object;
+use object::ObjectSymbol;
+use serde::Deserialize;
+use std::path::Path;
+
+/// The JSON kernel description.
+///
+/// The symbol addresses may instead be read from vmlinux, but the field
+/// offsets must be provided since they come from the kernel's debug info.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KernelDescription {
+    init_task: Option<u64>,
+    per_cpu_offset: Option<u64>,
+    current_task: Option<u64>,
+    task_struct: TaskStructOffsets,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TaskStructOffsets {
+    tasks: u64,
+    pid: u64,
+    comm: u64,
+    context: u64,
+}
+
+/// Loads the kernel description at `path`, taking any symbol addresses it
+/// does not have from the symbol table of `vmlinux`.
+pub fn load_kernel_layout(
+    path: &Path,
+    vmlinux: Option<&Path>,
+) -> anyhow::Result<LinuxKernelLayout> {
+    let description: KernelDescription = serde_json::from_slice(&fs_err::read(path)?)
+        .with_context(|| format!("failed to parse kernel description {}", path.display()))?;
+
+    let KernelDescription {
+        mut init_task,
+        mut per_cpu_offset,
+        mut current_task,
+        task_struct,
+    } = description;
+
+    if let Some(vmlinux) = vmlinux {
+        let data = fs_err::read(vmlinux)?;
+        let elf = object::File::parse(&*data)
+            .with_context(|| format!("failed to parse {}", vmlinux.display()))?;
+        for symbol in elf.symbols() {
+            let address = match symbol.name() {
+                Ok("init_task") => &mut init_task,
+                Ok("__per_cpu_offset") => &mut per_cpu_offset,
+                // The current task pointer is in `pcpu_hot` on x86_64 kernels
+                // 6.2 through 6.14, and is `__entry_task` on aarch64.
+                Ok("current_task" | "pcpu_hot" | "__entry_task") => &mut current_task,
+                _ => continue,
+            };
+            address.get_or_insert(symbol.address());
+        }
+    }
+
+    Ok(LinuxKernelLayout {
+        init_task: init_task.context("missing the address of init_task")?,
+        per_cpu_offset,
+        current_task,
+        task_tasks: task_struct.tasks,
+        task_pid: task_struct.pid,
+        task_comm: task_struct.comm,
+        task_context: task_struct.context,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::load_kernel_layout;
+    use object::write::Symbol;
+    use object::write::SymbolSection;
+    use std::io::Write;
+
+    const TASK_STRUCT: &str =
+        r#""task_struct": { "tasks": 8, "pid": 16, "comm": 24, "context": 32 }"#;
+
+    fn description(json: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(json.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn layout_from_description() {
+        let path = description(&format!(
+            r#"{{ "init_task": 4096, "per_cpu_offset": 8192, "current_task": 64, {TASK_STRUCT} }}"#
+        ));
+        let layout = load_kernel_layout(path.path(), None).unwrap();
+        assert_eq!(layout.init_task, 4096);
+        assert_eq!(layout.per_cpu_offset, Some(8192));
+        assert_eq!(layout.current_task, Some(64));
+        assert_eq!(layout.task_tasks, 8);
+        assert_eq!(layout.task_pid, 16);
+        assert_eq!(layout.task_comm, 24);
+        assert_eq!(layout.task_context, 32);
+
+        // init_task is required.
+        let path = description(&format!("{{ {TASK_STRUCT} }}"));
+        load_kernel_layout(path.path(), None).unwrap_err();
+
+        // Unknown fields are rejected, to catch misspellings.
+        let path = description(&format!(r#"{{ "init_tasks": 4096, {TASK_STRUCT} }}"#));
+        load_kernel_layout(path.path(), None).unwrap_err();
+    }
+
+    #[test]
+    fn layout_from_vmlinux() {
+        let mut elf = object::write::Object::new(
+            object::BinaryFormat::Elf,
+            object::Architecture::X86_64,
+            object::Endianness::Little,
+        );
+        for (name, value) in [
+            ("init_task", 0xffffffff82a0c940),
+            ("__per_cpu_offset", 0xffffffff82a1e000),
+            ("pcpu_hot", 0x32000),
+        ] {
+            elf.add_symbol(Symbol {
+                name: name.into(),
+                value,
+                size: 8,
+                kind: object::SymbolKind::Data,
+                scope: object::SymbolScope::Linkage,
+                weak: false,
+                section: SymbolSection::Absolute,
+                flags: object::SymbolFlags::None,
+            });
+        }
+        let mut vmlinux = tempfile::NamedTempFile::new().unwrap();
+        vmlinux.write_all(&elf.write().unwrap()).unwrap();
+
+        // Addresses in the description take precedence over vmlinux.
+        let path = description(&format!(r#"{{ "current_task": 64, {TASK_STRUCT} }}"#));
+        let layout = load_kernel_layout(path.path(), Some(vmlinux.path())).unwrap();
+        assert_eq!(layout.init_task, 0xffffffff82a0c940);
+        assert_eq!(layout.per_cpu_offset, Some(0xffffffff82a1e000));
+        assert_eq!(layout.current_task, Some(64));
+        assert_eq!(layout.task_context, 32);
+    }
+}
@@ -9,8 +9,11 @@
 
 mod cli_args;
 mod crash_dump;
+mod gdb_linux;
 mod kvp;
 mod meshworker;
+mod metrics;
+mod packet_capture;
 mod repl;
 mod serial_io;
 mod storage_builder;
@@ -36,6 +39,7 @@
 use cli_args::SerialConfigCli;
 use cli_args::UefiConsoleModeCli;
 use cli_args::VirtioBusCli;
+use cli_args::VirtioInputCli;
 use cli_args::VmgsCli;
 use crash_dump::spawn_dump_handler;
 use disk_backend_resources::DelayDiskHandle;
@@ -61,9 +65,11 @@
 use memory_range::MemoryRange;
 use mesh::CancelContext;
 use mesh::CellUpdater;
+use mesh::rpc::FailableRpc;
 use mesh::rpc::RpcSend;
 use meshworker::VmmMesh;
 use net_backend_resources::mac_address::MacAddress;
+use net_backend_resources::packet_capture::PacketCaptureRequest;
 use nvme_resources::NvmeControllerRequest;
 use openvmm_defs::config::Config;
 use openvmm_defs::config::DEFAULT_MMIO_GAPS_AARCH64;
@@ -144,6 +150,8 @@
 use vmgs_resources::VmgsResource;
 use vmotherboard::ChipsetDeviceHandle;
 use vnc_worker_defs::VncParameters;
+use vnc_worker_defs::VncSecurity;
+use vnc_worker_defs::VncTls;
 
 pub fn openvmm_main() {
     // Save the current state of the terminal so we can restore it back to
@@ -189,8 +197,11 @@ struct VmResources {
     kvp_ic: Option<mesh::Sender<hyperv_ic_resources::kvp::KvpConnectRpc>>,
     scsi_rpc: Option<mesh::Sender<ScsiControllerRequest>>,
     nvme_vtl2_rpc: Option<mesh::Sender<NvmeControllerRequest>>,
+    balloon_rpc: Option<mesh::Sender<virtio_resources::balloon::BalloonRequest>>,
     ged_rpc: Option<mesh::Sender<get_resources::ged::GuestEmulationRequest>>,
     vtl2_settings: Option<vtl2_settings_proto::Vtl2Settings>,
+    /// Packet capture channels for each NIC, indexed by NIC index.
+    packet_capture: Vec<mesh::Sender<FailableRpc<PacketCaptureRequest, ()>>>,
     #[cfg(windows)]
     switch_ports: Vec<vmswitch::kernel::SwitchPort>,
 }
@@ -519,6 +530,30 @@ async fn vm_config_from_command_line(
             .await?;
     }
 
+    for &cli_args::DiskCli {
+        vtl,
+        ref kind,
+        read_only,
+        is_dvd,
+        ref underhill,
+        ref pcie_port,
+    } in &opt.virtio_scsi
+    {
+        if underhill.is_some() {
+            anyhow::bail!("underhill not supported with virtio-scsi");
+        }
+        storage
+            .add(
+                vtl,
+                None,
+                storage_builder::DiskLocation::VirtioScsi(pcie_port.clone()),
+                kind,
+                is_dvd,
+                read_only,
+            )
+            .await?;
+    }
+
     let mut floppy_disks = Vec::new();
     for disk in &opt.floppy {
         let &cli_args::FloppyDiskCli {
@@ -834,7 +869,7 @@ async fn vm_config_from_command_line(
         None
     };
 
-    let framebuffer = if opt.gfx || opt.vtl2_gfx || opt.vnc || opt.pcat {
+    let framebuffer = if opt.gfx || opt.vtl2_gfx || opt.vnc || opt.pcat || opt.virtio_gpu {
         let vram = alloc_shared_memory(FRAMEBUFFER_SIZE, "vram")?;
         let (fb, fba) =
             framebuffer::framebuffer(vram, FRAMEBUFFER_SIZE, 0).context("creating framebuffer")?;
@@ -902,9 +937,10 @@ async fn vm_config_from_command_line(
         .build()
         .context("failed to build chipset configuration")?;
 
-    if opt.restore_snapshot.is_some() {
-        // Snapshot restore: skip firmware loading entirely. Device state and
-        // memory come from the snapshot directory.
+    if opt.restore_snapshot.is_some() || opt.migrate_listen.is_some() {
+        // Snapshot restore or incoming migration: skip firmware loading
+        // entirely. Device state and memory come from the snapshot directory
+        // or the migration source.
         load_mode = LoadMode::None;
         with_hv = true;
     } else if let Some(path) = &opt.igvm {
@@ -983,7 +1019,7 @@ async fn vm_config_from_command_line(
             let _ = write!(&mut cmdline, " console={}", console_str);
         }
 
-        if opt.gfx {
+        if opt.gfx || opt.virtio_gpu {
             cmdline += " console=tty";
         }
         for extra in &opt.cmdline {
@@ -1291,7 +1327,7 @@ async fn vm_config_from_command_line(
     let vtl2_vsock_listener = vsock_listener(opt.vmbus_vtl2_vsock_path.as_deref())?;
 
     if let Some(path) = &opt.openhcl_dump_path {
-        let (resource, task) = spawn_dump_handler(&spawner, path.clone(), None);
+        let (resource, task) = spawn_dump_handler(&spawner, path.clone(), None, None);
         task.detach();
         vmbus_devices.push((openhcl_vtl, resource));
     }
@@ -1485,6 +1521,56 @@ async fn vm_config_from_command_line(
         }
     }
 
+    if opt.virtio_balloon {
+        let (balloon_send, balloon_recv) = mesh::channel();
+        resources.balloon_rpc = Some(balloon_send);
+        let resource: Resource<VirtioDeviceHandle> =
+            virtio_resources::balloon::VirtioBalloonHandle {
+                free_page_reporting: !opt.virtio_balloon_no_page_reporting,
+                requests: Some(balloon_recv),
+            }
+            .into_resource();
+        if let Some(pcie_port) = &opt.virtio_balloon_pcie_port {
+            pcie_devices.push(PcieDeviceConfig {
+                port_name: pcie_port.clone(),
+                resource: VirtioPciDeviceHandle(resource).into_resource(),
+            });
+        } else {
+            add_virtio_device(opt.virtio_balloon_bus, resource);
+        }
+    }
+
+    for (i, kind) in opt.virtio_input.iter().enumerate() {
+        // Sit above PS/2 (0) and the synthetic devices (1), so that input goes
+        // to the virtio devices once the guest driver is using them.
+        let elevation = 2 + i;
+        let resource: Resource<VirtioDeviceHandle> = match kind {
+            VirtioInputCli::Keyboard => virtio_resources::input::VirtioKeyboardHandle {
+                source: MultiplexedInputHandle { elevation }.into_resource(),
+            }
+            .into_resource(),
+            VirtioInputCli::Mouse => virtio_resources::input::VirtioMouseHandle {
+                source: MultiplexedInputHandle { elevation }.into_resource(),
+            }
+            .into_resource(),
+            VirtioInputCli::Tablet => virtio_resources::input::VirtioTabletHandle {
+                source: MultiplexedInputHandle { elevation }.into_resource(),
+            }
+            .into_resource(),
+        };
+        add_virtio_device(opt.virtio_input_bus, resource);
+    }
+
+    if opt.virtio_gpu {
+        let resource: Resource<VirtioDeviceHandle> = virtio_resources::gpu::VirtioGpuHandle {
+            framebuffer: SharedFramebufferHandle.into_resource(),
+            width: 1024,
+            height: 768,
+        }
+        .into_resource();
+        add_virtio_device(opt.virtio_gpu_bus, resource);
+    }
+
     if let Some(backend) = virtio_console_backend {
         let resource: Resource<VirtioDeviceHandle> =
             virtio_resources::console::VirtioConsoleHandle { backend }.into_resource();
@@ -1598,6 +1684,21 @@ async fn vm_config_from_command_line(
             pci_ecam_gaps,
             pci_mmio_gaps,
             numa_mem_sizes: opt.numa_memory.clone(),
+            numa_distances: opt
+                .numa_distances
+                .as_deref()
+                .map(|d| square_matrix("numa-distances", d))
+                .transpose()?,
+            numa_latencies: opt
+                .numa_latencies
+                .as_deref()
+                .map(|l| square_matrix("numa-latencies", l))
+                .transpose()?,
+            numa_bandwidths: opt
+                .numa_bandwidths
+                .as_deref()
+                .map(|b| square_matrix("numa-bandwidths", b))
+                .transpose()?,
         },
         processor_topology: ProcessorTopologyConfig {
             proc_count: opt.processors,
@@ -1671,6 +1772,18 @@ async fn vm_config_from_command_line(
     Ok((cfg, resources))
 }
 
+/// Splits a square matrix, given on the command line in row-major order, into
+/// its rows.
+fn square_matrix<T: Clone>(name: &str, values: &[T]) -> anyhow::Result<Vec<Vec<T>>> {
+    let n = values.len().isqrt();
+    anyhow::ensure!(
+        n > 0 && n * n == values.len(),
+        "--{name} must be a square matrix, but has {} entries",
+        values.len()
+    );
+    Ok(values.chunks(n).map(|row| row.to_vec()).collect())
+}
+
 /// Gets the terminal to use for externally launched console windows.
 pub(crate) fn openvmm_terminal_app() -> Option<PathBuf> {
     std::env::var_os("OPENVMM_TERM")
@@ -1723,7 +1836,6 @@ fn parse_endpoint(
     index: &mut usize,
     resources: &mut VmResources,
 ) -> anyhow::Result<NicConfig> {
-    let _ = resources;
     let endpoint = match &cli_cfg.endpoint {
         EndpointConfigCli::Consomme { cidr, host_fwd } => {
             let ports = host_fwd
@@ -1796,6 +1908,18 @@ fn parse_endpoint(
         data1: *index as u32,
         ..BASE_INSTANCE_ID
     };
+
+    // Wrap the endpoint so that packet captures can be started at runtime.
+    let (send, recv) = mesh::channel();
+    assert_eq!(resources.packet_capture.len(), *index);
+    resources.packet_capture.push(send);
+    let endpoint = net_backend_resources::packet_capture::PacketCaptureHandle {
+        id: format!("nic{index}"),
+        endpoint,
+        requests: recv,
+    }
+    .into_resource();
+
     *index += 1;
 
     Ok(NicConfig {
@@ -1838,6 +1962,15 @@ enum LayerOrDisk {
     Disk(Resource<DiskHandleKind>),
 }
 
+impl LayerOrDisk {
+    fn into_layer(self) -> DiskLayerDescription {
+        match self {
+            LayerOrDisk::Layer(layer) => layer,
+            LayerOrDisk::Disk(disk) => DiskLayerHandle(disk).into_resource().into(),
+        }
+    }
+}
+
 async fn disk_open(
     disk_cli: &DiskCliKind,
     read_only: bool,
@@ -1851,17 +1984,7 @@ async fn disk_open(
         Ok(disk)
     } else {
         Ok(Resource::new(disk_backend_resources::LayeredDiskHandle {
-            layers: layers
-                .into_iter()
-                .map(|layer| match layer {
-                    LayerOrDisk::Layer(layer) => layer,
-                    LayerOrDisk::Disk(disk) => DiskLayerDescription {
-                        layer: DiskLayerHandle(disk).into_resource(),
-                        read_cache: false,
-                        write_through: false,
-                    },
-                })
-                .collect(),
+            layers: layers.into_iter().map(LayerOrDisk::into_layer).collect(),
         }))
     }
 }
@@ -1921,12 +2044,12 @@ fn disk<T: IntoResource<DiskHandleKind>>(disk: T) -> LayerOrDisk {
                     },
                 }))
             }
-            DiskCliKind::MemoryDiff(inner) => {
+            DiskCliKind::MemoryDiff { disk, commit } => {
                 layers.push(layer(RamDiskLayerHandle {
                     len: None,
                     sector_size: None,
                 }));
-                disk_open_inner(inner, true, layers).await?;
+                disk_open_lower(disk, read_only, *commit, layers).await?;
             }
             DiskCliKind::PersistentReservationsWrapper(inner) => {
                 layers.push(disk(disk_backend_resources::DiskWithReservationsHandle(
@@ -1981,7 +2104,12 @@ fn disk<T: IntoResource<DiskHandleKind>>(disk: T) -> LayerOrDisk {
                     }),
                 }));
             }
-            DiskCliKind::SqliteDiff { path, create, disk } => {
+            DiskCliKind::SqliteDiff {
+                path,
+                create,
+                commit,
+                disk,
+            } => {
                 // FUTURE: this code should be responsible for opening
                 // file-handle(s) itself, and passing them into sqlite via a custom
                 // vfs. For now though - simply check if the file exists or not, and
@@ -2007,7 +2135,7 @@ fn disk<T: IntoResource<DiskHandleKind>>(disk: T) -> LayerOrDisk {
                         },
                     ),
                 }));
-                disk_open_inner(disk, true, layers).await?;
+                disk_open_lower(disk, read_only, *commit, layers).await?;
             }
             DiskCliKind::AutoCacheSqlite {
                 cache_path,
@@ -2017,6 +2145,7 @@ fn disk<T: IntoResource<DiskHandleKind>>(disk: T) -> LayerOrDisk {
                 layers.push(LayerOrDisk::Layer(DiskLayerDescription {
                     read_cache: true,
                     write_through: false,
+                    writable: false,
                     layer: SqliteAutoCacheDiskLayerHandle {
                         cache_path: cache_path.clone(),
                         cache_key: key.clone(),
@@ -2030,6 +2159,28 @@ fn disk<T: IntoResource<DiskHandleKind>>(disk: T) -> LayerOrDisk {
     })
 }
 
+/// Opens the disk below a diff layer.
+///
+/// This is read only unless `commit` is set, in which case it is opened for
+/// write along with the rest of the disk, so that the diff layer can be
+/// committed into it at runtime.
+async fn disk_open_lower(
+    disk_cli: &DiskCliKind,
+    read_only: bool,
+    commit: bool,
+    layers: &mut Vec<LayerOrDisk>,
+) -> anyhow::Result<()> {
+    if !commit {
+        return disk_open_inner(disk_cli, true, layers).await;
+    }
+    let index = layers.len();
+    disk_open_inner(disk_cli, read_only, layers).await?;
+    let mut lower = layers.remove(index).into_layer();
+    lower.writable = true;
+    layers.insert(index, LayerOrDisk::Layer(lower));
+    Ok(())
+}
+
 /// Get the system page size.
 pub(crate) fn system_page_size() -> u32 {
     sparse_mmap::SparseMapping::page_size() as u32
@@ -2044,9 +2195,11 @@ pub(crate) fn system_page_size() -> u32 {
 
 /// Open a snapshot directory and validate it against the current VM config.
 /// Returns the shared memory fd (from memory.bin) and the saved device state.
-fn prepare_snapshot_restore(
+pub(crate) fn prepare_snapshot_restore(
     snapshot_dir: &Path,
-    opt: &Options,
+    memory_backing_file: Option<&Path>,
+    memory: u64,
+    processors: u32,
 ) -> anyhow::Result<(
     openvmm_defs::worker::SharedMemoryFd,
     mesh::payload::message::ProtobufMessage,
@@ -2057,16 +2210,32 @@ fn prepare_snapshot_restore(
     openvmm_helpers::snapshot::validate_manifest(
         &manifest,
         GUEST_ARCH,
-        opt.memory,
-        opt.processors,
+        memory,
+        processors,
         system_page_size(),
     )?;
 
-    // Open memory.bin (existing file, no create, no resize).
-    let memory_file = fs_err::OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(snapshot_dir.join("memory.bin"))?;
+    let memory_file = if let Some(path) = memory_backing_file {
+        // Copy guest RAM into the backing file, leaving the snapshot
+        // untouched.
+        let mut file = fs_err::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        file.set_len(0)?;
+        let mut memory_bin = fs_err::File::open(snapshot_dir.join("memory.bin"))?;
+        std::io::copy(&mut memory_bin, &mut file)
+            .context("failed to copy guest memory from snapshot")?;
+        file
+    } else {
+        // Open memory.bin (existing file, no create, no resize).
+        fs_err::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(snapshot_dir.join("memory.bin"))?
+    };
 
     // Validate file size matches expected memory size.
     let file_size = memory_file.metadata()?.len();
@@ -2089,6 +2258,63 @@ fn prepare_snapshot_restore(
     Ok((shared_memory_fd, state_msg))
 }
 
+/// Wait for a live migration on `listen_path` and receive guest RAM into the
+/// memory backing file at `memory_path`.
+///
+/// Returns the migration connection, on which the result of restoring the VM
+/// must be reported, along with the shared memory fd and the device state.
+pub(crate) async fn receive_migration(
+    listen_path: PathBuf,
+    memory_path: PathBuf,
+    memory_size: u64,
+    processors: u32,
+) -> anyhow::Result<(
+    openvmm_helpers::migration::IncomingMigration,
+    openvmm_defs::worker::SharedMemoryFd,
+    mesh::payload::message::ProtobufMessage,
+)> {
+    let (incoming, memory_file, state_msg) = blocking::unblock(move || {
+        let mut incoming = openvmm_helpers::migration::IncomingMigration::listen(&listen_path)?;
+        openvmm_helpers::migration::validate_hello(
+            incoming.hello(),
+            GUEST_ARCH,
+            memory_size,
+            processors,
+            system_page_size(),
+        )?;
+        tracing::info!(
+            source_version = %incoming.hello().openvmm_version,
+            "receiving migration"
+        );
+
+        let mut memory_file = fs_err::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&memory_path)?;
+        memory_file
+            .set_len(memory_size)
+            .context("failed to set memory backing file size")?;
+
+        let state_bytes = incoming.receive(&mut memory_file)?;
+        let state_msg = match mesh::payload::decode(&state_bytes) {
+            Ok(state_msg) => state_msg,
+            Err(err) => {
+                let err = anyhow::Error::from(err).context("failed to decode migrated state");
+                incoming.complete(Err(format!("{err:#}")))?;
+                return Err(err);
+            }
+        };
+        anyhow::Ok((incoming, memory_file, state_msg))
+    })
+    .await?;
+
+    let shared_memory_fd =
+        openvmm_helpers::shared_memory::file_to_shared_memory_fd(memory_file.into())?;
+    Ok((incoming, shared_memory_fd, state_msg))
+}
+
 fn do_main(pidfile_path: &mut Option<PathBuf>) -> anyhow::Result<()> {
     #[cfg(windows)]
     pal::windows::disable_hard_error_dialog();
@@ -2163,6 +2389,40 @@ fn new_hvsock_service_id(port: u32) -> Guid {
     }
 }
 
+fn vnc_security_from_command_line(opt: &Options) -> anyhow::Result<VncSecurity> {
+    let password = opt
+        .vnc_password_file
+        .as_ref()
+        .map(|path| {
+            let password = fs_err::read_to_string(path)?;
+            anyhow::Ok(password.trim_end_matches(['\r', '\n']).to_owned())
+        })
+        .transpose()
+        .context("failed to read VNC password file")?;
+    let tls = match (&opt.vnc_tls_cert, &opt.vnc_tls_key) {
+        (Some(cert), Some(key)) => Some(VncTls {
+            cert_chain: fs_err::read(cert).context("failed to read VNC TLS certificate")?,
+            private_key: fs_err::read(key).context("failed to read VNC TLS private key")?,
+        }),
+        _ => None,
+    };
+    if let Some(password) = &password {
+        if password.is_empty() {
+            anyhow::bail!("VNC password file is empty");
+        }
+        if tls.is_none() && password.len() > 8 {
+            tracing::warn!("only the first 8 bytes of the VNC password are used without TLS");
+        }
+    }
+    if !opt.vnc_address.is_loopback() && (password.is_none() || tls.is_none()) {
+        tracing::warn!(
+            address = %opt.vnc_address,
+            "VNC server is reachable from the network without both a password and TLS"
+        );
+    }
+    Ok(VncSecurity { password, tls })
+}
+
 async fn run_control(driver: &DefaultDriver, opt: Options) -> anyhow::Result<()> {
     let mut mesh = Some(VmmMesh::new(&driver, opt.single_process)?);
     let result = run_control_inner(driver, &mut mesh, opt).await;
@@ -2183,8 +2443,9 @@ async fn run_control_inner(
     let (mut vm_config, mut resources) = vm_config_from_command_line(driver, mesh, &opt).await?;
 
     let mut vnc_worker = None;
-    if opt.gfx || opt.vnc {
-        let listener = TcpListener::bind(format!("127.0.0.1:{}", opt.vnc_port))
+    if opt.gfx || opt.vnc || opt.virtio_gpu {
+        let security = vnc_security_from_command_line(&opt)?;
+        let listener = TcpListener::bind((opt.vnc_address, opt.vnc_port))
             .with_context(|| format!("binding to VNC port {}", opt.vnc_port))?;
 
         let input_send = vm_config.input.sender();
@@ -2206,6 +2467,7 @@ async fn run_control_inner(
                         listener,
                         framebuffer,
                         input_send,
+                        security,
                     },
                 )
                 .await?,
@@ -2217,6 +2479,12 @@ async fn run_control_inner(
         let listener = TcpListener::bind(format!("127.0.0.1:{}", port))
             .with_context(|| format!("binding to gdb port {}", port))?;
 
+        let linux = opt
+            .gdb_linux
+            .as_deref()
+            .map(|path| gdb_linux::load_kernel_layout(path, opt.gdb_vmlinux.as_deref()))
+            .transpose()?;
+
         let (req_tx, req_rx) = mesh::channel();
         vm_config.debugger_rpc = Some(req_rx);
 
@@ -2238,6 +2506,8 @@ async fn run_control_inner(
                         } else {
                             debug_worker_defs::TargetArch::Aarch64
                         },
+                        memory_map: opt.gdb_memory_map,
+                        linux,
                     },
                 )
                 .await
@@ -2253,8 +2523,26 @@ async fn run_control_inner(
     let vm_worker = {
         let vm_host = mesh.make_host("vm", opt.log_file.clone()).await?;
 
+        let mut incoming_migration = None;
         let (shared_memory, saved_state) = if let Some(snapshot_dir) = &opt.restore_snapshot {
-            let (fd, state_msg) = prepare_snapshot_restore(snapshot_dir, &opt)?;
+            let (fd, state_msg) = prepare_snapshot_restore(
+                snapshot_dir,
+                opt.memory_backing_file.as_deref(),
+                opt.memory,
+                opt.processors,
+            )?;
+            (Some(fd), Some(state_msg))
+        } else if let Some(listen_path) = &opt.migrate_listen {
+            let (incoming, fd, state_msg) = receive_migration(
+                listen_path.clone(),
+                opt.memory_backing_file
+                    .clone()
+                    .context("--migrate-listen requires --memory-backing-file")?,
+                opt.memory,
+                opt.processors,
+            )
+            .await?;
+            incoming_migration = Some(incoming);
             (Some(fd), Some(state_msg))
         } else {
             let shared_memory = opt
@@ -2278,14 +2566,27 @@ async fn run_control_inner(
             rpc: rpc_recv,
             notify: notify_send,
         };
-        vm_host
+        let vm_worker = vm_host
             .launch_worker(VM_WORKER, params)
             .await
-            .context("failed to launch vm worker")?
+            .context("failed to launch vm worker");
+
+        // Tell the migration source whether the VM was restored, so that it
+        // can resume the VM on failure.
+        if let Some(incoming) = incoming_migration {
+            let result = vm_worker
+                .as_ref()
+                .map(|_| ())
+                .map_err(|err| format!("{err:#}"));
+            blocking::unblock(move || incoming.complete(result)).await?;
+        }
+        vm_worker?
     };
 
     if opt.restore_snapshot.is_some() {
         tracing::info!("restoring VM from snapshot");
+    } else if opt.migrate_listen.is_some() {
+        tracing::info!("restored migrated VM");
     }
 
     if !opt.paused {
@@ -2331,6 +2632,23 @@ async fn run_control_inner(
         log_file: opt.log_file.clone(),
     };
 
+    // Serve metrics until the REPL exits.
+    let _metrics_task = opt
+        .metrics
+        .as_deref()
+        .map(|address| {
+            let listener = metrics::bind(address)?;
+            let paths = if opt.metrics_path.is_empty() {
+                vec!["vm".to_owned()]
+            } else {
+                opt.metrics_path.clone()
+            };
+            tracing::info!(address, "serving metrics");
+            let server = metrics::MetricsServer::new(vm_controller_send.clone(), paths);
+            anyhow::Ok(driver.spawn("metrics", server.run(driver.clone(), listener)))
+        })
+        .transpose()?;
+
     // Spawn the VmController as a task.
     let controller_task = driver.spawn(
         "vm-controller",
@@ -2346,9 +2664,11 @@ async fn run_control_inner(
             vm_controller_events: vm_controller_event_recv,
             scsi_rpc: resources.scsi_rpc,
             nvme_vtl2_rpc: resources.nvme_vtl2_rpc,
+            balloon_rpc: resources.balloon_rpc,
             shutdown_ic: resources.shutdown_ic,
             kvp_ic: resources.kvp_ic,
             console_in: resources.console_in,
+            packet_capture: resources.packet_capture,
             has_vtl2,
         },
     )
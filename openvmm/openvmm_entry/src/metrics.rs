@@ -0,0 +1,180 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! An HTTP endpoint that exports inspect values as OpenMetrics (Prometheus)
+//! metrics.
+
+use crate::cleanup_socket;
+use crate::vm_controller::InspectTarget;
+use crate::vm_controller::VmControllerRpc;
+use anyhow::Context;
+use futures::AsyncReadExt;
+use futures::AsyncWriteExt;
+use inspect::InspectionBuilder;
+use inspect_openmetrics::Encoder;
+use mesh::CancelContext;
+use pal_async::DefaultDriver;
+use pal_async::socket::PolledSocket;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The largest HTTP request header accepted.
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// How long to wait for a scrape's inspect requests to complete.
+const INSPECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Binds the metrics listener to `address`, which is either a TCP socket
+/// address or a Unix socket path.
+pub(crate) fn bind(address: &str) -> anyhow::Result<socket2::Socket> {
+    let socket = if let Ok(addr) = address.parse::<SocketAddr>() {
+        std::net::TcpListener::bind(addr)
+            .with_context(|| format!("failed to bind metrics address {addr}"))?
+            .into()
+    } else {
+        cleanup_socket(Path::new(address));
+        unix_socket::UnixListener::bind(address)
+            .with_context(|| format!("failed to bind metrics socket {address}"))?
+            .into()
+    };
+    Ok(socket)
+}
+
+/// Serves OpenMetrics scrapes of the inspect `paths` on `listener`.
+pub(crate) struct MetricsServer {
+    vm_controller: mesh::Sender<VmControllerRpc>,
+    paths: Vec<String>,
+    /// The results of the previous scrape, for computing counter rates.
+    last: Option<(Instant, Vec<inspect::Node>)>,
+}
+
+impl MetricsServer {
+    pub fn new(vm_controller: mesh::Sender<VmControllerRpc>, paths: Vec<String>) -> Self {
+        Self {
+            vm_controller,
+            paths,
+            last: None,
+        }
+    }
+
+    pub async fn run(mut self, driver: DefaultDriver, listener: socket2::Socket) {
+        let mut listener = match PolledSocket::new(&driver, listener) {
+            Ok(listener) => listener,
+            Err(err) => {
+                tracing::error!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to start metrics server"
+                );
+                return;
+            }
+        };
+        loop {
+            let socket = match listener.accept().await {
+                Ok((socket, _)) => socket,
+                Err(err) => {
+                    tracing::warn!(
+                        error = &err as &dyn std::error::Error,
+                        "failed to accept metrics connection"
+                    );
+                    continue;
+                }
+            };
+            // Scrapes are infrequent, so serve connections one at a time.
+            // This also keeps the rate computations consistent.
+            if let Err(err) = self.handle_connection(&driver, socket).await {
+                tracing::debug!(
+                    error = &*err as &dyn std::error::Error,
+                    "metrics request failed"
+                );
+            }
+        }
+    }
+
+    async fn handle_connection(
+        &mut self,
+        driver: &DefaultDriver,
+        socket: socket2::Socket,
+    ) -> anyhow::Result<()> {
+        let mut socket = PolledSocket::new(driver, socket)?;
+        let request = CancelContext::new()
+            .with_timeout(REQUEST_TIMEOUT)
+            .until_cancelled(read_request(&mut socket))
+            .await
+            .context("timed out reading request")??;
+
+        let (method, target) = {
+            let mut parts = request.split(' ');
+            (parts.next().unwrap_or(""), parts.next().unwrap_or(""))
+        };
+        let (status, content_type, body) = match (method, target) {
+            ("GET", "/metrics") => (
+                "200 OK",
+                inspect_openmetrics::CONTENT_TYPE,
+                self.scrape().await,
+            ),
+            ("GET", _) => ("404 Not Found", "text/plain", "not found\n".to_owned()),
+            _ => (
+                "405 Method Not Allowed",
+                "text/plain",
+                "method not allowed\n".to_owned(),
+            ),
+        };
+        let response = format!(
+            "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        socket.write_all(response.as_bytes()).await?;
+        socket.close().await?;
+        Ok(())
+    }
+
+    /// Inspects each path and encodes the results.
+    async fn scrape(&mut self) -> String {
+        let now = Instant::now();
+        let mut nodes = Vec::with_capacity(self.paths.len());
+        for path in &self.paths {
+            let mut inspection = InspectionBuilder::new(path).inspect(inspect::adhoc_mut(|req| {
+                self.vm_controller
+                    .send(VmControllerRpc::Inspect(InspectTarget::Host, req.defer()));
+            }));
+            let _ = CancelContext::new()
+                .with_timeout(INSPECT_TIMEOUT)
+                .until_cancelled(inspection.resolve())
+                .await;
+            nodes.push(inspection.results());
+        }
+
+        let mut encoder = Encoder::new("openvmm");
+        for (i, (path, node)) in self.paths.iter().zip(&nodes).enumerate() {
+            encoder.add(path, node);
+            if let Some((last_time, last_nodes)) = &self.last {
+                encoder.add_rates(path, node, &last_nodes[i], now - *last_time);
+            }
+        }
+        self.last = Some((now, nodes));
+        encoder.finish()
+    }
+}
+
+/// Reads an HTTP request header and returns its request line.
+async fn read_request(socket: &mut PolledSocket<socket2::Socket>) -> anyhow::Result<String> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        if buf.len() >= MAX_REQUEST_LEN {
+            anyhow::bail!("request too large");
+        }
+        let n = socket.read(&mut chunk).await?;
+        if n == 0 {
+            anyhow::bail!("connection closed");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let header = String::from_utf8_lossy(&buf);
+    Ok(header.lines().next().unwrap_or_default().to_owned())
+}
@@ -0,0 +1,131 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Code to start and stop packet captures on the VM's NICs.
+
+use anyhow::Context;
+use mesh::rpc::FailableRpc;
+use mesh::rpc::RpcSend as _;
+use net_backend_resources::packet_capture::PacketCaptureRequest;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How long to wait for a NIC to handle a request. The NIC only handles
+/// requests while it is running, so this keeps the REPL from hanging when the
+/// NIC is not up or the VM is paused.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The packet capture channels of the VM's NICs, indexed by NIC index.
+pub(crate) type PacketCaptureSenders = [mesh::Sender<FailableRpc<PacketCaptureRequest, ()>>];
+
+#[derive(clap::Args)]
+pub(crate) struct PacketCaptureCommand {
+    /// The NICs to capture, by index. Defaults to all NICs.
+    #[clap(long = "nic")]
+    nics: Vec<usize>,
+    /// The maximum number of bytes to capture from each packet.
+    #[clap(short, long, default_value = "65535", value_parser = clap::value_parser!(u32).range(1..))]
+    snaplen: u32,
+    /// Stop capturing.
+    #[clap(long, conflicts_with("output"))]
+    stop: bool,
+    /// The destination file path. The NIC index is appended to the file name.
+    #[clap(required_unless_present("stop"))]
+    output: Option<PathBuf>,
+}
+
+/// Handles the `packet-capture` command.
+pub(crate) async fn handle_packet_capture(
+    senders: &PacketCaptureSenders,
+    command: PacketCaptureCommand,
+) -> anyhow::Result<()> {
+    if command.stop {
+        stop(senders, &command.nics).await
+    } else {
+        let output = command.output.expect("required by clap");
+        for path in start(senders, &command.nics, &output, command.snaplen).await? {
+            println!("capturing to {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+/// Starts capturing the packets of `nics`, or all NICs if empty, to a file
+/// per NIC, named after `output` with the NIC index appended.
+///
+/// Returns the paths of the files.
+pub(crate) async fn start(
+    senders: &PacketCaptureSenders,
+    nics: &[usize],
+    output: &Path,
+    snaplen: u32,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let stem = output
+        .file_stem()
+        .context("output path has no file name")?
+        .to_string_lossy();
+    let extension = output.extension().unwrap_or(OsStr::new("pcapng"));
+    let mut paths = Vec::new();
+    for (index, sender) in selected(senders, nics)? {
+        let path = output
+            .with_file_name(format!("{stem}-{index}"))
+            .with_extension(extension);
+        let file = std::fs::File::create(&path)
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        if let Err(err) = request(sender, PacketCaptureRequest::Start { file, snaplen }).await {
+            let _ = std::fs::remove_file(&path);
+            return Err(err.context(format!("failed to start capture on nic {index}")));
+        }
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Stops capturing the packets of `nics`, or all NICs if empty.
+pub(crate) async fn stop(senders: &PacketCaptureSenders, nics: &[usize]) -> anyhow::Result<()> {
+    for (index, sender) in selected(senders, nics)? {
+        request(sender, PacketCaptureRequest::Stop)
+            .await
+            .with_context(|| format!("failed to stop capture on nic {index}"))?;
+    }
+    Ok(())
+}
+
+async fn request(
+    sender: &mesh::Sender<FailableRpc<PacketCaptureRequest, ()>>,
+    request: PacketCaptureRequest,
+) -> anyhow::Result<()> {
+    mesh::CancelContext::new()
+        .with_timeout(REQUEST_TIMEOUT)
+        .until_cancelled(sender.call_failable(|x| x, request))
+        .await
+        .context("nic did not respond; is it up and is the VM running?")??;
+    Ok(())
+}
+
+fn selected<'a>(
+    senders: &'a PacketCaptureSenders,
+    nics: &'a [usize],
+) -> anyhow::Result<
+    Vec<(
+        usize,
+        &'a mesh::Sender<FailableRpc<PacketCaptureRequest, ()>>,
+    )>,
+> {
+    if senders.is_empty() {
+        anyhow::bail!("no nics configured");
+    }
+    if nics.is_empty() {
+        return Ok(senders.iter().enumerate().collect());
+    }
+    nics.iter()
+        .map(|&index| {
+            let sender = senders
+                .get(index)
+                .with_context(|| format!("no nic with index {index}"))?;
+            Ok((index, sender))
+        })
+        .collect()
+}
@@ -8,12 +8,15 @@
 //! directly. Commands that need exclusive resources (worker handles,
 //! DiagInspector, vtl2_settings) are dispatched via `Sender<VmControllerRpc>`.
 
+use crate::cli_args::parse_memory;
 use crate::kvp;
+use crate::packet_capture;
 use crate::storage_builder;
 use crate::vm_controller::AddVtl0ScsiDiskParams;
 use crate::vm_controller::InspectTarget;
 use crate::vm_controller::RemoveVtl0ScsiDiskByNvmeNsidParams;
 use crate::vm_controller::RemoveVtl0ScsiDiskParams;
+use crate::vm_controller::SaveSnapshotParams;
 use crate::vm_controller::ServiceVtl2Params;
 use crate::vm_controller::VmControllerEvent;
 use crate::vm_controller::VmControllerRpc;
@@ -32,9 +35,11 @@
 use inspect::InspectionBuilder;
 use mesh::CancelContext;
 use mesh::error::RemoteError;
+use mesh::rpc::FailableRpc;
 use mesh::rpc::Rpc;
 use mesh::rpc::RpcError;
 use mesh::rpc::RpcSend;
+use net_backend_resources::packet_capture::PacketCaptureRequest;
 use nvme_resources::NamespaceDefinition;
 use nvme_resources::NvmeControllerRequest;
 use openvmm_defs::config::DeviceVtl;
@@ -44,6 +49,7 @@
 use pal_async::socket::PolledSocket;
 use pal_async::task::Spawn;
 use pal_async::task::Task;
+use pal_async::timer::Instant;
 use pal_async::timer::PolledTimer;
 use scsidisk_resources::SimpleScsiDiskHandle;
 use scsidisk_resources::SimpleScsiDvdHandle;
@@ -110,6 +116,19 @@ enum InteractiveCommand {
     SaveSnapshot {
         /// Directory to write the snapshot to.
         dir: PathBuf,
+        /// Copy guest RAM into the snapshot and keep the VM running, rather
+        /// than linking the snapshot to the memory backing file.
+        #[clap(long)]
+        live: bool,
+    },
+
+    /// Live migrate the VM to an OpenVMM process started with
+    /// `--migrate-listen` (requires --memory-backing-file).
+    ///
+    /// On success, the VM is left paused and cannot be resumed.
+    MigrateSend {
+        /// The destination's listening socket.
+        socket: PathBuf,
     },
 
     /// Do a pulsed save restore (pause, save, reset, restore, resume) to the VM.
@@ -204,6 +223,42 @@ enum InteractiveCommand {
         update: Option<String>,
     },
 
+    /// Watch an inspect path, printing what changed after each period.
+    ///
+    /// The full state is printed first. After that, only the values that
+    /// changed are printed, with counters shown as per-second rates. Only one
+    /// path can be watched at a time.
+    Watch {
+        /// Enumerate state recursively.
+        #[clap(short, long)]
+        recursive: bool,
+        /// The recursive depth limit.
+        #[clap(short, long, requires("recursive"))]
+        limit: Option<usize>,
+        /// Target the paravisor.
+        #[clap(short = 'v', long)]
+        paravisor: bool,
+        /// The sampling period in seconds.
+        #[clap(short, long, default_value = "1")]
+        period: f64,
+        /// Stop watching.
+        #[clap(long, conflicts_with("element"))]
+        stop: bool,
+        /// The element path to watch.
+        element: Option<String>,
+    },
+
+    /// Commit the top layer of a layered disk into the layer below it, and
+    /// then remove it, while the VM runs.
+    ///
+    /// The layer below must have been opened for write, for example with
+    /// `memdiff;commit:<disk>`. Inspect `<element>/commit` to see the
+    /// progress.
+    CommitDisk {
+        /// The inspect path of the layered disk.
+        element: String,
+    },
+
     /// Restart the VNC worker.
     #[clap(visible_alias = "V")]
     RestartVnc,
@@ -251,6 +306,14 @@ enum InteractiveCommand {
         force: bool,
     },
 
+    /// Set the size of the memory balloon, the guest memory to reclaim from
+    /// the guest.
+    Balloon {
+        /// The balloon size, in bytes, with an optional K, M, G, or T suffix.
+        #[clap(value_parser = parse_memory)]
+        size: u64,
+    },
+
     /// Clears the current halt condition, resuming the VPs if the VM is
     /// running.
     #[clap(visible_alias = "ch")]
@@ -308,6 +371,15 @@ enum InteractiveCommand {
 
     /// Use KVP to interact with the guest.
     Kvp(kvp::KvpCommand),
+
+    /// Start or stop capturing the packets sent and received by the NICs, in
+    /// pcapng format.
+    ///
+    /// The NICs are indexed in command line order, counting the `--net` NICs
+    /// first, then the `--nic` NIC, the `--mana` NICs, and the `--virtio-net`
+    /// NICs.
+    #[clap(visible_alias = "pcap")]
+    PacketCapture(packet_capture::PacketCaptureCommand),
 }
 
 /// Subcommands for managing VTL2 settings.
@@ -386,12 +458,27 @@ pub(crate) struct ReplResources {
     pub vm_controller_events: mesh::Receiver<VmControllerEvent>,
     pub scsi_rpc: Option<mesh::Sender<ScsiControllerRequest>>,
     pub nvme_vtl2_rpc: Option<mesh::Sender<NvmeControllerRequest>>,
+    pub balloon_rpc: Option<mesh::Sender<virtio_resources::balloon::BalloonRequest>>,
     pub shutdown_ic: Option<mesh::Sender<hyperv_ic_resources::shutdown::ShutdownRpc>>,
     pub kvp_ic: Option<mesh::Sender<hyperv_ic_resources::kvp::KvpConnectRpc>>,
     pub console_in: Option<Box<dyn AsyncWrite + Send + Unpin>>,
+    pub packet_capture: Vec<mesh::Sender<FailableRpc<PacketCaptureRequest, ()>>>,
     pub has_vtl2: bool,
 }
 
+/// The state of the `watch` command.
+struct InspectWatch {
+    target: InspectTarget,
+    element: String,
+    depth: Option<usize>,
+    period: Duration,
+    watch: inspect::Watch,
+    /// The time of the last sample.
+    last: Instant,
+    /// The time of the next sample.
+    next: Instant,
+}
+
 /// Run the interactive REPL.
 pub(crate) async fn run_repl(
     driver: &DefaultDriver,
@@ -403,9 +490,11 @@ pub(crate) async fn run_repl(
         mut vm_controller_events,
         mut scsi_rpc,
         mut nvme_vtl2_rpc,
+        balloon_rpc,
         shutdown_ic,
         kvp_ic,
         console_in,
+        packet_capture,
         has_vtl2,
     } = resources;
 
@@ -549,8 +638,10 @@ pub(crate) async fn run_repl(
 
     let mut state_change_task = None::<Task<Result<StateChange, RpcError>>>;
     let mut pulse_save_restore_interval: Option<Duration> = None;
+    let mut inspect_watch: Option<InspectWatch> = None;
     let mut pending_shutdown = None;
     let mut snapshot_saved = false;
+    let mut migrated = false;
 
     enum StateChange {
         Pause(bool),
@@ -567,6 +658,7 @@ enum Event {
         ),
         Quit,
         PulseSaveRestore,
+        InspectWatch,
         StateChange(Result<StateChange, RpcError>),
         ShutdownResult(Result<hyperv_ic_resources::shutdown::ShutdownResult, RpcError>),
         Controller(VmControllerEvent),
@@ -591,6 +683,16 @@ enum Event {
                 }
             });
 
+            let watch_tick = pin!(async {
+                match &inspect_watch {
+                    Some(watch) => {
+                        PolledTimer::new(driver).sleep_until(watch.next).await;
+                        Event::InspectWatch
+                    }
+                    None => pending().await,
+                }
+            });
+
             let change = futures::stream::iter(state_change_task.as_mut().map(|x| x.into_stream()))
                 .flatten()
                 .map(Event::StateChange);
@@ -607,6 +709,7 @@ enum Event {
                 &mut console_command_recv,
                 &mut inspect_completion_engine_recv,
                 pulse_save_restore.into_stream(),
+                watch_tick.into_stream(),
                 change,
                 shutdown.into_stream(),
                 controller_events,
@@ -643,6 +746,26 @@ enum Event {
                 vm_rpc.call(VmRpc::PulseSaveRestore, ()).await??;
                 continue;
             }
+            Event::InspectWatch => {
+                let watch = inspect_watch.as_mut().unwrap();
+                let target = watch.target;
+                let mut inspection = InspectionBuilder::new(&watch.element)
+                    .depth(watch.depth)
+                    .inspect(inspect::adhoc_mut(|req| {
+                        vm_controller.send(VmControllerRpc::Inspect(target, req.defer()));
+                    }));
+                let _ = CancelContext::new()
+                    .with_timeout(Duration::from_secs(1))
+                    .until_cancelled(inspection.resolve())
+                    .await;
+                let now = Instant::now();
+                if let Some(changes) = watch.watch.next(inspection.results(), now - watch.last) {
+                    println!("{:#}", changes);
+                }
+                watch.last = now;
+                watch.next = now + watch.period;
+                continue;
+            }
             Event::StateChange(r) => {
                 match r {
                     Ok(sc) => match sc {
@@ -735,7 +858,7 @@ enum Event {
                         // VNC stopped but VM is still running, continue.
                     }
                     VmControllerEvent::GuestHalt(reason) => {
-                        tracing::info!(reason = reason.as_str(), "guest halted");
+                        tracing::info!(?reason, "guest halted");
                     }
                 }
                 continue;
@@ -789,6 +912,10 @@ fn state_change<U: 'static + Send>(
                     eprintln!(
                         "error: cannot resume after snapshot save — resuming would corrupt the snapshot. Use 'shutdown' to exit."
                     );
+                } else if migrated {
+                    eprintln!(
+                        "error: cannot resume after migration — the VM is now running on the destination. Use 'shutdown' to exit."
+                    );
                 } else {
                     state_change(
                         driver,
@@ -808,16 +935,22 @@ fn state_change<U: 'static + Send>(
                     StateChange::Reset,
                 );
             }
-            InteractiveCommand::SaveSnapshot { dir } => {
+            InteractiveCommand::SaveSnapshot { dir, live } => {
                 match vm_controller
                     .call(
                         VmControllerRpc::SaveSnapshot,
-                        dir.to_string_lossy().into_owned(),
+                        SaveSnapshotParams {
+                            dir: dir.to_string_lossy().into_owned(),
+                            live,
+                        },
                     )
                     .await
                     .map_err(anyhow::Error::from)
                     .and_then(|r| Ok(r?))
                 {
+                    Ok(()) if live => {
+                        tracing::info!(dir = %dir.display(), "live snapshot saved");
+                    }
                     Ok(()) => {
                         snapshot_saved = true;
                         tracing::info!(
@@ -832,6 +965,30 @@ fn state_change<U: 'static + Send>(
                     }
                 }
             }
+            InteractiveCommand::MigrateSend { socket } => {
+                match vm_controller
+                    .call(
+                        VmControllerRpc::MigrateSend,
+                        socket.to_string_lossy().into_owned(),
+                    )
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|r| Ok(r?))
+                {
+                    Ok(()) => {
+                        migrated = true;
+                        tracing::info!(
+                            socket = %socket.display(),
+                            "migration complete; VM is paused. \
+                             Resume is blocked since the VM is running on the destination. \
+                             Use 'shutdown' to exit."
+                        );
+                    }
+                    Err(err) => {
+                        eprintln!("error: migrate-send failed: {err:#}");
+                    }
+                }
+            }
             InteractiveCommand::PulseSaveRestore => {
                 state_change(
                     driver,
@@ -871,6 +1028,20 @@ fn state_change<U: 'static + Send>(
                     println!("no shutdown ic configured");
                 }
             }
+            InteractiveCommand::Balloon { size } => {
+                let action = async {
+                    let balloon = balloon_rpc.as_ref().context("no balloon device")?;
+                    let pages = u32::try_from(size / 4096).context("balloon size too large")?;
+                    balloon
+                        .call(virtio_resources::balloon::BalloonRequest::SetTarget, pages)
+                        .await?;
+                    anyhow::Ok(())
+                };
+
+                if let Err(error) = action.await {
+                    eprintln!("error setting balloon size: {}", error);
+                }
+            }
             InteractiveCommand::Nmi => {
                 let _ = vm_rpc.call(VmRpc::Nmi, 0).await;
             }
@@ -1241,6 +1412,65 @@ fn state_change<U: 'static + Send>(
                     println!("{:#}", node);
                 }
             }
+            InteractiveCommand::Watch {
+                recursive,
+                limit,
+                paravisor,
+                period,
+                stop,
+                element,
+            } => {
+                if stop {
+                    inspect_watch = None;
+                    continue;
+                }
+                let period = match Duration::try_from_secs_f64(period) {
+                    Ok(period) if !period.is_zero() && period <= Duration::from_secs(86400) => {
+                        period
+                    }
+                    _ => {
+                        eprintln!("error: invalid period");
+                        continue;
+                    }
+                };
+                // Sample right away to print the full state.
+                let now = Instant::now();
+                inspect_watch = Some(InspectWatch {
+                    target: if paravisor {
+                        InspectTarget::Paravisor
+                    } else {
+                        InspectTarget::Host
+                    },
+                    element: element.unwrap_or_default(),
+                    depth: if recursive { limit } else { Some(0) },
+                    period,
+                    watch: inspect::Watch::new(),
+                    last: now,
+                    next: now,
+                });
+            }
+            InteractiveCommand::CommitDisk { element } => {
+                let obj = inspect::adhoc_mut(|req| {
+                    vm_controller.send(VmControllerRpc::Inspect(InspectTarget::Host, req.defer()));
+                });
+                let element = format!("{}/commit", element.trim_end_matches('/'));
+                let value = async {
+                    let update = inspect::update(&element, "start", obj);
+                    let value = CancelContext::new()
+                        .with_timeout(Duration::from_secs(1))
+                        .until_cancelled(update)
+                        .await??;
+                    anyhow::Ok(value)
+                }
+                .await;
+                match value {
+                    Ok(node) => match &node.kind {
+                        inspect::ValueKind::String(s) => println!("{s}"),
+                        _ => println!("{:#}", node),
+                    },
+                    Err(err) => println!("error: {:#}", err),
+                }
+            }
             InteractiveCommand::RestartVnc => {
                 match vm_controller
                     .call(VmControllerRpc::RestartVnc, ())
@@ -1441,6 +1671,13 @@ fn state_change<U: 'static + Send>(
                     eprintln!("error: {err:#}");
                 }
             }
+            InteractiveCommand::PacketCapture(command) => {
+                if let Err(err) =
+                    packet_capture::handle_packet_capture(&packet_capture, command).await
+                {
+                    eprintln!("error: {err:#}");
+                }
+            }
             InteractiveCommand::Input { .. } | InteractiveCommand::InputMode => unreachable!(),
         }
     }
@@ -1527,7 +1764,7 @@ async fn complete(
         arg_id: &str,
     ) -> Vec<String> {
         match (subcommand_path, arg_id) {
-            (["openvmm", "inspect"], "element") => {
+            (["openvmm", "inspect" | "watch"], "element") => {
                 let on_error = vec!["failed/to/connect".into()];
 
                 let (parent_path, to_complete) = (ctx.to_complete)
@@ -27,9 +27,11 @@
 use storvsp_resources::ScsiPath;
 use virtio_resources::VirtioPciDeviceHandle;
 use virtio_resources::blk::VirtioBlkHandle;
+use virtio_resources::scsi::VirtioScsiHandle;
 use vm_resource::IntoResource;
 use vm_resource::Resource;
 use vm_resource::kind::DiskHandleKind;
+use vm_resource::kind::ScsiDeviceHandleKind;
 use vtl2_settings_proto::Lun;
 use vtl2_settings_proto::StorageController;
 use vtl2_settings_proto::storage_controller;
@@ -45,6 +47,8 @@ pub(super) struct StorageBuilder {
     underhill_scsi_luns: Vec<Lun>,
     underhill_nvme_luns: Vec<Lun>,
     vtl0_virtio_blk_disks: Vec<VirtioBlkDisk>,
+    vtl0_virtio_scsi_devices: Vec<ScsiDeviceAndPath>,
+    pcie_virtio_scsi_controllers: BTreeMap<String, Vec<ScsiDeviceAndPath>>,
     openhcl_vtl: Option<DeviceVtl>,
 }
 
@@ -59,6 +63,7 @@ pub enum DiskLocation {
     Scsi(Option<u8>),
     Nvme(Option<u32>, Option<String>),
     VirtioBlk(Option<String>),
+    VirtioScsi(Option<String>),
 }
 
 impl From<UnderhillDiskSource> for DiskLocation {
@@ -110,6 +115,8 @@ fn from(value: UnderhillDiskSource) -> Self {
 /// an arbitrarily generated fixed value.
 const VIRTIO_BLK_INSTANCE_ID_TEMPLATE: Guid = guid::guid!("00000000-a4e7-4b53-b702-1f42d938647e");
 
+const VIRTIO_SCSI_VTL0_INSTANCE_ID: Guid = guid::guid!("5e3e1a6c-03c5-4c8f-9b0f-6b1f4d2e8a71");
+
 impl StorageBuilder {
     pub fn new(openhcl_vtl: Option<DeviceVtl>) -> Self {
         Self {
@@ -123,6 +130,8 @@ pub fn new(openhcl_vtl: Option<DeviceVtl>) -> Self {
             underhill_scsi_luns: Vec::new(),
             underhill_nvme_luns: Vec::new(),
             vtl0_virtio_blk_disks: Vec::new(),
+            vtl0_virtio_scsi_devices: Vec::new(),
+            pcie_virtio_scsi_controllers: BTreeMap::new(),
             openhcl_vtl,
         }
     }
@@ -208,20 +217,7 @@ async fn add_inner(
                 None
             }
             DiskLocation::Scsi(lun) => {
-                let device = if is_dvd {
-                    SimpleScsiDvdHandle {
-                        media: Some(disk),
-                        requests: None,
-                    }
-                    .into_resource()
-                } else {
-                    SimpleScsiDiskHandle {
-                        disk,
-                        read_only,
-                        parameters: Default::default(),
-                    }
-                    .into_resource()
-                };
+                let device = scsi_device(disk, is_dvd, read_only);
                 let devices = match vtl {
                     DeviceVtl::Vtl0 => &mut self.vtl0_scsi_devices,
                     DeviceVtl::Vtl1 => anyhow::bail!("vtl1 unsupported"),
@@ -277,6 +273,25 @@ async fn add_inner(
                 }
                 None
             }
+            DiskLocation::VirtioScsi(pcie_port) => {
+                if vtl != DeviceVtl::Vtl0 {
+                    anyhow::bail!("virtio-scsi only supported for VTL0");
+                }
+                let devices = if let Some(port) = pcie_port {
+                    self.pcie_virtio_scsi_controllers.entry(port).or_default()
+                } else {
+                    &mut self.vtl0_virtio_scsi_devices
+                };
+                devices.push(ScsiDeviceAndPath {
+                    path: ScsiPath {
+                        path: 0,
+                        target: 0,
+                        lun: devices.len() as u8,
+                    },
+                    device: scsi_device(disk, is_dvd, read_only),
+                });
+                None
+            }
         };
         Ok(location)
     }
@@ -319,6 +334,9 @@ async fn add_underhill(
             DiskLocation::VirtioBlk(_) => {
                 anyhow::bail!("underhill not supported with virtio-blk")
             }
+            DiskLocation::VirtioScsi(_) => {
+                anyhow::bail!("underhill not supported with virtio-scsi")
+            }
         };
 
         let (luns, location) = match target {
@@ -340,6 +358,9 @@ async fn add_underhill(
             DiskLocation::VirtioBlk(_) => {
                 anyhow::bail!("underhill not supported with virtio-blk")
             }
+            DiskLocation::VirtioScsi(_) => {
+                anyhow::bail!("underhill not supported with virtio-scsi")
+            }
         };
 
         luns.push(Lun {
@@ -526,6 +547,39 @@ pub fn build_config(
             });
         }
 
+        // Use a request queue per processor, as Linux does by default.
+        let num_queues = config
+            .processor_topology
+            .proc_count
+            .try_into()
+            .unwrap_or(u16::MAX);
+        let virtio_scsi = |devices| {
+            VirtioPciDeviceHandle(
+                VirtioScsiHandle {
+                    num_queues,
+                    devices,
+                    requests: None,
+                }
+                .into_resource(),
+            )
+            .into_resource()
+        };
+
+        if !self.vtl0_virtio_scsi_devices.is_empty() {
+            config.vpci_devices.push(VpciDeviceConfig {
+                vtl: DeviceVtl::Vtl0,
+                instance_id: VIRTIO_SCSI_VTL0_INSTANCE_ID,
+                resource: virtio_scsi(std::mem::take(&mut self.vtl0_virtio_scsi_devices)),
+            });
+        }
+
+        for (port_name, devices) in std::mem::take(&mut self.pcie_virtio_scsi_controllers) {
+            config.pcie_devices.push(PcieDeviceConfig {
+                port_name,
+                resource: virtio_scsi(devices),
+            });
+        }
+
         Ok(())
     }
 
@@ -558,3 +612,25 @@ pub fn build_underhill(&self, vmbus_redirect: bool) -> Vec<StorageController> {
         storage_controllers
     }
 }
+
+/// Returns the SCSI device resource for a disk or DVD.
+fn scsi_device(
+    disk: Resource<DiskHandleKind>,
+    is_dvd: bool,
+    read_only: bool,
+) -> Resource<ScsiDeviceHandleKind> {
+    if is_dvd {
+        SimpleScsiDvdHandle {
+            media: Some(disk),
+            requests: None,
+        }
+        .into_resource()
+    } else {
+        SimpleScsiDiskHandle {
+            disk,
+            read_only,
+            parameters: Default::default(),
+        }
+        .into_resource()
+    }
+}
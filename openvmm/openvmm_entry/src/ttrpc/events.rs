@@ -0,0 +1,133 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! VM event history for the `StreamVMEvents` RPC.
+
+use mesh_rpc::service::Code;
+use mesh_rpc::service::Status;
+use openvmm_ttrpc_vmservice as vmservice;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::SystemTime;
+use vmm_core_defs::HaltReason;
+use vmservice::guest_halt_event::Reason;
+use vmservice::vm_event::Event;
+
+/// The maximum number of events kept for new streams.
+const MAX_HISTORY: usize = 256;
+
+/// The events of the current VM and the streams receiving them.
+pub(super) struct VmEvents {
+    history: VecDeque<vmservice::VmEvent>,
+    /// The sequence number of the current VM's first event.
+    first_sequence: u64,
+    last_sequence: u64,
+    streams: Vec<mesh::Sender<Result<vmservice::VmEvent, Status>>>,
+}
+
+impl Default for VmEvents {
+    fn default() -> Self {
+        Self {
+            history: VecDeque::new(),
+            first_sequence: 1,
+            last_sequence: 0,
+            streams: Vec::new(),
+        }
+    }
+}
+
+impl VmEvents {
+    /// Ends all streams and clears the history, for a new VM.
+    ///
+    /// Sequence numbers keep increasing, so that a client resuming a stream
+    /// does not mistake the new VM's events for ones it has already seen.
+    pub fn reset(&mut self) {
+        self.end_streams();
+        self.history.clear();
+        self.first_sequence = self.last_sequence + 1;
+    }
+
+    /// Ends all streams, successfully.
+    pub fn end_streams(&mut self) {
+        self.streams.clear();
+    }
+
+    /// Sends the recorded events after `after_sequence` to `send`, and then
+    /// sends it new events until the streams are ended.
+    ///
+    /// Fails the stream with `OutOfRange` if some of the current VM's events
+    /// after `after_sequence` are no longer in the history.
+    pub fn stream(
+        &mut self,
+        after_sequence: u64,
+        send: mesh::Sender<Result<vmservice::VmEvent, Status>>,
+    ) {
+        let oldest = self
+            .history
+            .front()
+            .map_or(self.last_sequence + 1, |e| e.sequence);
+        if after_sequence.saturating_add(1).max(self.first_sequence) < oldest {
+            send.send(Err(super::grpc_error(
+                anyhow::Error::new(Code::OutOfRange).context(format!(
+                    "events after sequence {after_sequence} are no longer recorded, the oldest is {oldest}"
+                )),
+            )));
+            return;
+        }
+        for event in self.history.iter().filter(|e| e.sequence > after_sequence) {
+            send.send(Ok(event.clone()));
+        }
+        self.streams.retain(|send| !send.is_closed());
+        self.streams.push(send);
+    }
+
+    /// Records a new event and sends it to the streams.
+    pub fn push(&mut self, event: Event) {
+        self.last_sequence += 1;
+        let event = vmservice::VmEvent {
+            sequence: self.last_sequence,
+            timestamp: Some(SystemTime::now().into()),
+            event: Some(event),
+        };
+        tracing::debug!(?event, "vm event");
+        if self.history.len() == MAX_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(event.clone());
+        self.streams.retain(|send| {
+            if send.is_closed() {
+                return false;
+            }
+            send.send(Ok(event.clone()));
+            true
+        });
+    }
+}
+
+pub(super) fn guest_halt(reason: &HaltReason) -> Event {
+    let (reason, vp) = match *reason {
+        HaltReason::PowerOff => (Reason::PowerOff, None),
+        HaltReason::Reset => (Reason::Reset, None),
+        HaltReason::Hibernate => (Reason::Hibernate, None),
+        HaltReason::DebugBreak { vp } => (Reason::DebugBreak, vp),
+        HaltReason::TripleFault { vp, .. } => (Reason::TripleFault, Some(vp)),
+        HaltReason::SingleStep { vp } => (Reason::SingleStep, Some(vp)),
+        HaltReason::HwBreakpoint { vp, .. } => (Reason::HwBreakpoint, Some(vp)),
+    };
+    Event::GuestHalt(vmservice::GuestHaltEvent {
+        reason: reason.into(),
+        vp,
+    })
+}
+
+pub(super) fn guest_crash(dump_path: &Path) -> Event {
+    Event::GuestCrash(vmservice::GuestCrashEvent {
+        dump_path: dump_path.display().to_string(),
+    })
+}
+
+pub(super) fn worker_stopped(error: Option<&str>) -> Event {
+    Event::WorkerStopped(vmservice::WorkerStoppedEvent {
+        error: error.unwrap_or_default().to_owned(),
+    })
+}
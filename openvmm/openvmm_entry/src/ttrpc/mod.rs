@@ -5,15 +5,20 @@
 
 #![cfg(any(feature = "ttrpc", feature = "grpc"))]
 
+mod events;
+
+use crate::crash_dump::spawn_dump_handler;
 use crate::meshworker::VmmMesh;
 use crate::serial_io::bind_serial;
 use crate::vm_controller::InspectTarget;
+use crate::vm_controller::SaveSnapshotParams;
 use crate::vm_controller::VmController;
 use crate::vm_controller::VmControllerEvent;
 use crate::vm_controller::VmControllerRpc;
 use anyhow::Context;
 use anyhow::anyhow;
 use anyhow::bail;
+use events::VmEvents;
 use futures::FutureExt;
 use futures::StreamExt;
 use guid::Guid;
@@ -21,15 +26,19 @@
 use inspect_proto::InspectResponse2;
 use inspect_proto::InspectService;
 use inspect_proto::UpdateResponse2;
+use inspect_proto::WatchResponse2;
+use inspect_proto::WatchTable;
 use mesh::CancelReason;
 use mesh::MeshPayload;
 use mesh::error::RemoteError;
+use mesh::rpc::FailableRpc;
 use mesh::rpc::RpcSend;
 use mesh_rpc::service::Code;
 use mesh_rpc::service::Status;
 use mesh_worker::Worker;
 use mesh_worker::WorkerId;
 use mesh_worker::WorkerRpc;
+use net_backend_resources::packet_capture::PacketCaptureRequest;
 use netvsp_resources::NetvspHandle;
 use openvmm_defs::config::Config;
 use openvmm_defs::config::DEFAULT_MMIO_GAPS_X86;
@@ -41,6 +50,7 @@
 use openvmm_defs::config::VirtioBus;
 use openvmm_defs::config::VmbusConfig;
 use openvmm_defs::config::VpciDeviceConfig;
+use openvmm_defs::rpc::PulseSaveRestoreError;
 use openvmm_defs::rpc::VmRpc;
 use openvmm_defs::worker::VM_WORKER;
 use openvmm_defs::worker::VmWorkerParameters;
@@ -51,9 +61,12 @@
 use pal_async::DefaultPool;
 use pal_async::task::Spawn;
 use pal_async::task::Task;
+use parking_lot::Mutex;
 use scsidisk_resources::SimpleScsiDiskHandle;
+use std::collections::HashMap;
 use std::fs::File;
 use std::future::Future;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use storvsp_resources::ScsiControllerHandle;
@@ -136,7 +149,13 @@ fn run(self, recv: mesh::Receiver<WorkerRpc<Self::State>>) -> anyhow::Result<()>
                 controller_task: None,
                 wait_vm_response: None,
                 halted: false,
+                events: VmEvents::default(),
+                crash_dumps: None,
+                packet_capture: Default::default(),
+                resume_blocked: false,
                 rpc_tasks: Vec::new(),
+                pending_migration: None,
+                watches: Arc::new(WatchTable::new()),
                 transport: self.transport,
             };
             service.run(self.listener, recv).await?;
@@ -190,6 +209,15 @@ async fn run(
                 }
             };
 
+            // Likewise for crash dump notifications.
+            let mut crash_dumps = self.crash_dumps.take();
+            let crash_dump_fut = async {
+                match &mut crash_dumps {
+                    Some(recv) => recv.next().await,
+                    None => std::future::pending().await,
+                }
+            };
+
             // Clone the WaitVm cancel context so we can poll it without
             // borrowing self.
             let mut wait_cancel_ctx = self.wait_vm_response.as_mut().map(|(ctx, _)| ctx.clone());
@@ -200,12 +228,23 @@ async fn run(
                 }
             };
 
+            // Likewise for an incoming migration.
+            let mut pending_migration = self.pending_migration.take();
+            let migration_fut = async {
+                match &mut pending_migration {
+                    Some(migration) => (&mut migration.task).await,
+                    None => std::future::pending().await,
+                }
+            };
+
             enum Action {
                 VmService(Box<Option<(mesh::CancelContext, vmservice::Vm)>>),
                 InspectService(Option<(mesh::CancelContext, InspectService)>),
                 WorkerRpc(Result<WorkerRpc<()>, mesh::RecvError>),
                 ControllerEvent(Option<VmControllerEvent>),
+                CrashDump(Option<PathBuf>),
                 WaitVmCancelled(CancelReason),
+                MigrationReceived(anyhow::Result<ReceivedMigration>),
             }
 
             let action = futures::select! { // merge semantics
@@ -213,7 +252,9 @@ enum Action {
                 m = inspect_service_recv.next() => Action::InspectService(m),
                 r = recv.recv().fuse() => Action::WorkerRpc(r),
                 e = ctrl_fut.fuse() => Action::ControllerEvent(e),
+                p = crash_dump_fut.fuse() => Action::CrashDump(p),
                 reason = wait_cancel_fut.fuse() => Action::WaitVmCancelled(reason.unwrap()),
+                r = migration_fut.fuse() => Action::MigrationReceived(r),
             };
 
             // Restore controller events (unless the channel closed).
@@ -222,6 +263,14 @@ enum Action {
             } else {
                 self.vm_controller_events = ctrl_events;
             }
+            // Restore crash dump notifications (unless the channel closed).
+            if !matches!(action, Action::CrashDump(None)) {
+                self.crash_dumps = crash_dumps;
+            }
+            // Restore the incoming migration (unless it completed).
+            if !matches!(action, Action::MigrationReceived(_)) {
+                self.pending_migration = pending_migration.take();
+            }
 
             match action {
                 Action::VmService(message) => match *message {
@@ -260,12 +309,30 @@ enum Action {
                     self.handle_controller_event(event);
                 }
                 Action::ControllerEvent(None) => {} // handled above
+                Action::CrashDump(Some(path)) => {
+                    tracing::info!(path = %path.display(), "guest crash dump");
+                    self.events.push(events::guest_crash(&path));
+                }
+                Action::CrashDump(None) => {}
                 Action::WaitVmCancelled(reason) => {
                     tracing::debug!("WaitVm client cancelled");
                     if let Some((_, response)) = self.wait_vm_response.take() {
                         response.send(Err(grpc_error(anyhow::Error::new(reason))));
                     }
                 }
+                Action::MigrationReceived(r) => {
+                    let PendingMigration {
+                        config, response, ..
+                    } = pending_migration.take().unwrap();
+                    let r = match r {
+                        Ok(received) => {
+                            self.create_vm_from(config, VmSource::Migrated(received))
+                                .await
+                        }
+                        Err(err) => Err(err.context("failed to receive migration")),
+                    };
+                    response.send(map_grpc(r));
+                }
             }
         };
 
@@ -283,6 +350,12 @@ enum Action {
         if let Some((_, response)) = self.wait_vm_response.take() {
             response.send(Err(grpc_error(anyhow!("server shutting down"))));
         }
+        if let Some(migration) = self.pending_migration.take() {
+            migration
+                .response
+                .send(Err(grpc_error(anyhow!("server shutting down"))));
+        }
+        self.events.end_streams();
 
         // Drain any remaining RPCs.
         futures::future::join_all(self.rpc_tasks.drain(..)).await;
@@ -313,9 +386,39 @@ fn start_rpc<F, R>(
     }
 }
 
+/// Where a new VM's state comes from.
+enum VmSource {
+    /// Boot the VM from its boot configuration.
+    Boot,
+    /// Restore the VM received from a live migration.
+    Migrated(ReceivedMigration),
+    /// Restore the VM from this snapshot directory.
+    Snapshot(PathBuf),
+}
+
+/// The memory and device state received from a migration source, with the
+/// connection to report the restore result on.
+type ReceivedMigration = (
+    openvmm_helpers::migration::IncomingMigration,
+    openvmm_defs::worker::SharedMemoryFd,
+    mesh::payload::message::ProtobufMessage,
+);
+
+/// The channel for starting and stopping a NIC's packet capture.
+type PacketCaptureSender = mesh::Sender<FailableRpc<PacketCaptureRequest, ()>>;
+
+/// A `CreateVm` request waiting for an incoming migration.
+struct PendingMigration {
+    task: Task<anyhow::Result<ReceivedMigration>>,
+    config: vmservice::VmConfig,
+    response: mesh::OneshotSender<Result<(), Status>>,
+}
+
 struct Vm {
     worker_rpc: mesh::Sender<VmRpc>,
     scsi_rpc: Option<mesh::Sender<ScsiControllerRequest>>,
+    balloon_rpc: Option<mesh::Sender<virtio_resources::balloon::BalloonRequest>>,
+    mem_size: u64,
 }
 
 struct VmService {
@@ -328,7 +431,23 @@ struct VmService {
     /// Set when the guest has halted, so that a later `WaitVm` completes
     /// immediately instead of blocking forever. Cleared on `CreateVm`.
     halted: bool,
+    /// The VM's events, for `StreamVmEvents`. Cleared on `CreateVm`.
+    events: VmEvents,
+    /// Paths of completed guest crash dumps.
+    crash_dumps: Option<mesh::Receiver<PathBuf>>,
+    /// Packet capture channels for the VM's NICs, by NIC instance ID. Shared
+    /// so that a hot-added NIC is only registered once the add succeeds.
+    packet_capture: Arc<Mutex<HashMap<Guid, PacketCaptureSender>>>,
+    /// Set after a non-live snapshot, whose memory is linked to the memory
+    /// backing file, so that the VM cannot be resumed and corrupt it.
+    /// Cleared on `CreateVm`.
+    resume_blocked: bool,
     rpc_tasks: Vec<Task<()>>,
+    /// The `CreateVm` request receiving a migration, which completes once the
+    /// source has sent the VM.
+    pending_migration: Option<PendingMigration>,
+    /// Active inspect watches.
+    watches: Arc<WatchTable>,
     transport: ResolvedTransport,
 }
 
@@ -355,6 +474,36 @@ fn map_grpc<T>(r: anyhow::Result<T>) -> Result<T, Status> {
     r.map_err(grpc_error)
 }
 
+/// Returns the guest RAM size in bytes requested by `config`.
+fn memory_size(config: &vmservice::VmConfig) -> anyhow::Result<u64> {
+    config
+        .memory_config
+        .as_ref()
+        .context("missing memory configuration")?
+        .memory_mb
+        .checked_mul(0x100000)
+        .context("invalid memory configuration")
+}
+
+/// Returns the memory backing file requested by `config`, if any.
+fn memory_backing_file(config: &vmservice::VmConfig) -> Option<PathBuf> {
+    config
+        .memory_config
+        .as_ref()
+        .map(|c| &c.backing_file_path)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Returns the processor count requested by `config`.
+fn processor_count(config: &vmservice::VmConfig) -> u32 {
+    config
+        .processor_config
+        .as_ref()
+        .map(|c| c.processor_count)
+        .unwrap_or(1)
+}
+
 enum HandleAction {
     None,
     Quit,
@@ -365,7 +514,25 @@ async fn handle(&mut self, ctx: mesh::CancelContext, request: vmservice::Vm) ->
         tracing::debug!(?request, "request");
         match request {
             vmservice::Vm::CreateVm(request, response) => {
-                response.send(map_grpc(self.create_vm(request).await))
+                if request.migrate_listen_path.is_empty() {
+                    response.send(map_grpc(self.create_vm(request).await))
+                } else {
+                    // Waiting for the source can take arbitrarily long, so
+                    // receive the migration without blocking other requests.
+                    match self.receive_migration(request) {
+                        Ok((task, config)) => {
+                            self.pending_migration = Some(PendingMigration {
+                                task,
+                                config,
+                                response,
+                            })
+                        }
+                        Err(err) => response.send(Err(grpc_error(err))),
+                    }
+                }
+            }
+            vmservice::Vm::RestoreSnapshot(request, response) => {
+                response.send(map_grpc(self.restore_snapshot(request).await))
             }
             vmservice::Vm::TeardownVm((), response) => {
                 response.send(map_grpc(self.teardown_vm().await))
@@ -383,6 +550,10 @@ async fn handle(&mut self, ctx: mesh::CancelContext, request: vmservice::Vm) ->
                 if let Some((_, wait_response)) = self.wait_vm_response.take() {
                     wait_response.send(Err(grpc_error(anyhow!("VM quit"))));
                 }
+                if let Some(migration) = self.pending_migration.take() {
+                    migration.response.send(Err(grpc_error(anyhow!("VM quit"))));
+                }
+                self.events.end_streams();
                 response.send(Ok(()));
                 return HandleAction::Quit;
             }
@@ -400,7 +571,7 @@ async fn handle(&mut self, ctx: mesh::CancelContext, request: vmservice::Vm) ->
                         self.start_rpc(response, r);
                     }
                     vmservice::Vm::ResumeVm((), response) => {
-                        let r = Ok(self.resume_vm(&vm));
+                        let r = self.resume_vm(&vm);
                         self.start_rpc(response, r);
                     }
                     vmservice::Vm::WaitVm((), response) => {
@@ -414,10 +585,30 @@ async fn handle(&mut self, ctx: mesh::CancelContext, request: vmservice::Vm) ->
                             self.wait_vm_response = Some((ctx.clone(), response));
                         }
                     }
+                    vmservice::Vm::StreamVmEvents(request, response) => {
+                        self.events.stream(request.after_sequence, response);
+                    }
                     vmservice::Vm::ModifyResource(request, response) => {
                         let r = self.modify_resource(&vm, request);
                         self.start_rpc(response, r);
                     }
+                    vmservice::Vm::MigrateVm(request, response) => {
+                        let r = self.migrate_vm(request);
+                        self.start_rpc(response, r);
+                    }
+                    vmservice::Vm::SaveSnapshot(request, response) => {
+                        // Run the save inline so that a later `ResumeVm` sees
+                        // whether the VM must stay paused.
+                        response.send(map_grpc(self.save_snapshot(request).await))
+                    }
+                    vmservice::Vm::PulseSaveRestore((), response) => {
+                        let r = Ok(self.pulse_save_restore(&vm));
+                        self.start_rpc(response, r);
+                    }
+                    vmservice::Vm::PacketCapture(request, response) => {
+                        let r = self.packet_capture(request);
+                        self.start_rpc(response, r);
+                    }
 
                     r @ vmservice::Vm::CapabilitiesVm(_, _)
                     | r @ vmservice::Vm::PropertiesVm(_, _) => {
@@ -425,6 +616,7 @@ async fn handle(&mut self, ctx: mesh::CancelContext, request: vmservice::Vm) ->
                     }
 
                     vmservice::Vm::CreateVm(_, _)
+                    | vmservice::Vm::RestoreSnapshot(_, _)
                     | vmservice::Vm::TeardownVm(_, _)
                     | vmservice::Vm::Quit(_, _) => unreachable!(),
                 };
@@ -441,6 +633,9 @@ async fn handle_inspect(&mut self, ctx: mesh::CancelContext, request: InspectSer
             InspectService::Update(request, response) => {
                 self.start_rpc(response, Ok(self.update(ctx, request)))
             }
+            InspectService::Watch(request, response) => {
+                self.start_rpc(response, Ok(self.watch(ctx, request)))
+            }
         }
     }
 
@@ -491,39 +686,120 @@ fn update(
         }
     }
 
+    fn watch(
+        &self,
+        ctx: mesh::CancelContext,
+        request: inspect_proto::WatchRequest,
+    ) -> impl Future<Output = anyhow::Result<WatchResponse2>> + use<> {
+        let watches = self.watches.clone();
+        let driver = self.driver.clone();
+        let controller = self.vm_controller.clone();
+        async move {
+            watches
+                .watch(&driver, ctx, &request, |builder| {
+                    builder.inspect(inspect::adhoc(|req| {
+                        if let Some(controller) = &controller {
+                            controller
+                                .send(VmControllerRpc::Inspect(InspectTarget::Host, req.defer()));
+                        }
+                    }))
+                })
+                .await
+        }
+    }
+
     async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Result<()> {
         let req_config = request.config.context("missing configuration")?;
+        self.create_vm_from(req_config, VmSource::Boot).await
+    }
+
+    /// Starts listening for an incoming migration, returning the task that
+    /// receives it. The VM is created from the received state once the task
+    /// completes.
+    fn receive_migration(
+        &mut self,
+        request: vmservice::CreateVmRequest,
+    ) -> anyhow::Result<(Task<anyhow::Result<ReceivedMigration>>, vmservice::VmConfig)> {
+        if self.vm.is_some() || self.pending_migration.is_some() {
+            bail!("VM already created");
+        }
+        let req_config = request.config.context("missing configuration")?;
+        let memory_size = memory_size(&req_config)?;
+        let memory_backing_file =
+            memory_backing_file(&req_config).context("migration requires a memory backing file")?;
+        let task = self.driver.spawn(
+            "migration-receive",
+            crate::receive_migration(
+                PathBuf::from(request.migrate_listen_path),
+                memory_backing_file,
+                memory_size,
+                processor_count(&req_config),
+            ),
+        );
+        Ok((task, req_config))
+    }
 
-        if self.vm.is_some() {
+    async fn restore_snapshot(
+        &mut self,
+        request: vmservice::RestoreSnapshotRequest,
+    ) -> anyhow::Result<()> {
+        let req_config = request.config.context("missing configuration")?;
+        if request.snapshot_dir.is_empty() {
+            return Err(
+                anyhow::Error::new(Code::InvalidArgument).context("missing snapshot directory")
+            );
+        }
+        self.create_vm_from(
+            req_config,
+            VmSource::Snapshot(PathBuf::from(request.snapshot_dir)),
+        )
+        .await
+    }
+
+    async fn create_vm_from(
+        &mut self,
+        req_config: vmservice::VmConfig,
+        source: VmSource,
+    ) -> anyhow::Result<()> {
+        if self.vm.is_some() || self.pending_migration.is_some() {
             bail!("VM already created");
         }
 
         // Reset halt state for the new VM.
         self.halted = false;
-
-        let load_mode = match req_config
-            .boot_config
-            .context("missing boot configuration")?
-        {
-            vmservice::vm_config::BootConfig::DirectBoot(boot) => {
-                let kernel = File::open(boot.kernel_path).context("failed to open kernel")?;
-                let initrd = if boot.initrd_path.is_empty() {
-                    None
-                } else {
-                    Some(File::open(boot.initrd_path).context("failed to open initrd")?)
-                };
-                LoadMode::Linux {
-                    kernel,
-                    initrd,
-                    cmdline: boot.kernel_cmdline,
-                    custom_dsdt: None,
-                    enable_serial: true,
-                    boot_mode: openvmm_defs::config::LinuxDirectBootMode::Acpi,
+        self.resume_blocked = false;
+        self.events.reset();
+        self.crash_dumps = None;
+        self.packet_capture.lock().clear();
+
+        // An incoming migration or a snapshot brings the VM's memory and
+        // device state, so there is nothing to boot.
+        let load_mode = match source {
+            VmSource::Migrated(_) | VmSource::Snapshot(_) => LoadMode::None,
+            VmSource::Boot => match req_config
+                .boot_config
+                .context("missing boot configuration")?
+            {
+                vmservice::vm_config::BootConfig::DirectBoot(boot) => {
+                    let kernel = File::open(boot.kernel_path).context("failed to open kernel")?;
+                    let initrd = if boot.initrd_path.is_empty() {
+                        None
+                    } else {
+                        Some(File::open(boot.initrd_path).context("failed to open initrd")?)
+                    };
+                    LoadMode::Linux {
+                        kernel,
+                        initrd,
+                        cmdline: boot.kernel_cmdline,
+                        custom_dsdt: None,
+                        enable_serial: true,
+                        boot_mode: openvmm_defs::config::LinuxDirectBootMode::Acpi,
+                    }
                 }
-            }
-            vmservice::vm_config::BootConfig::Uefi(_) => {
-                anyhow::bail!("uefi not yet supported")
-            }
+                vmservice::vm_config::BootConfig::Uefi(_) => {
+                    anyhow::bail!("uefi not yet supported")
+                }
+            },
         };
 
         let mut ports = [(); 4].map(|_| None);
@@ -545,18 +821,10 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
         .context("failed to build vm configuration")?;
 
         // Extract memory and processor counts for the VmController.
-        let config_mem_size = req_config
-            .memory_config
-            .as_ref()
-            .context("missing memory configuration")?
-            .memory_mb
-            .checked_mul(0x100000)
-            .context("invalid memory configuration")?;
-        let config_proc_count = req_config
-            .processor_config
-            .as_ref()
-            .map(|c| c.processor_count)
-            .unwrap_or(1);
+        let config_mem_size = memory_size(&req_config)?;
+        let memory_backing_file = memory_backing_file(&req_config);
+        let balloon = req_config.memory_config.as_ref().is_some_and(|c| c.balloon);
+        let config_proc_count = processor_count(&req_config);
 
         let mut config = Config {
             // TODO: devices, other stuff
@@ -576,6 +844,9 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
                 private_memory: false,
                 transparent_hugepages: false,
                 numa_mem_sizes: None,
+                numa_distances: None,
+                numa_latencies: None,
+                numa_bandwidths: None,
             },
             chipset: chipset.chipset,
             processor_topology: ProcessorTopologyConfig {
@@ -614,6 +885,28 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             efi_diagnostics_log_level: Default::default(),
         };
 
+        let mut balloon_rpc = None;
+        if balloon {
+            let (send, recv) = mesh::channel();
+            let resource = virtio_resources::balloon::VirtioBalloonHandle {
+                free_page_reporting: true,
+                requests: Some(recv),
+            }
+            .into_resource();
+            // Use VPCI when possible (currently only on Windows and macOS due
+            // to KVM backend limitations).
+            if cfg!(windows) || cfg!(target_os = "macos") {
+                config.vpci_devices.push(VpciDeviceConfig {
+                    vtl: DeviceVtl::Vtl0,
+                    instance_id: Guid::new_random(),
+                    resource: VirtioPciDeviceHandle(resource).into_resource(),
+                });
+            } else {
+                config.virtio_devices.push((VirtioBus::Pci, resource));
+            }
+            balloon_rpc = Some(send);
+        }
+
         let mut scsi_rpc = None;
         if let Some(devices_config) = req_config.devices_config {
             if !devices_config.scsi_disks.is_empty() {
@@ -638,7 +931,10 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             }
 
             for nic in devices_config.nic_config {
-                config.vmbus_devices.push(parse_nic_config(nic)?);
+                let (device, (instance_id, send)) =
+                    parse_nic_config(nic, &self.packet_capture.lock())?;
+                config.vmbus_devices.push(device);
+                self.packet_capture.lock().insert(instance_id, send);
             }
 
             for virtiofs in devices_config.virtiofs_config {
@@ -664,6 +960,20 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             }
         }
 
+        let mut crash_dumps = None;
+        if !req_config.crash_dump_path.is_empty() {
+            let (send, recv) = mesh::channel();
+            let (resource, task) = spawn_dump_handler(
+                &self.driver,
+                req_config.crash_dump_path.into(),
+                None,
+                Some(send),
+            );
+            task.detach();
+            config.vmbus_devices.push((DeviceVtl::Vtl0, resource));
+            crash_dumps = Some(recv);
+        }
+
         if let Some(hvsocket_config) = req_config.hvsocket_config {
             let listener = UnixListener::bind(&hvsocket_config.path).with_context(|| {
                 format!("failed to bind hvsocket path: {}", &hvsocket_config.path)
@@ -682,19 +992,66 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             .await
             .context("spawning vm process failed")?;
 
+        let mut incoming_migration = None;
+        let (shared_memory, saved_state) = match source {
+            VmSource::Boot => {
+                let shared_memory = memory_backing_file
+                    .as_ref()
+                    .map(|path| {
+                        openvmm_helpers::shared_memory::open_memory_backing_file(
+                            path,
+                            config_mem_size,
+                        )
+                    })
+                    .transpose()?;
+                (shared_memory, None)
+            }
+            VmSource::Migrated((incoming, fd, state_msg)) => {
+                incoming_migration = Some(incoming);
+                (Some(fd), Some(state_msg))
+            }
+            VmSource::Snapshot(snapshot_dir) => {
+                // Restoring may copy all of guest RAM, so keep it off the
+                // service loop.
+                let memory_backing_file = memory_backing_file.clone();
+                let (fd, state_msg) = blocking::unblock(move || {
+                    crate::prepare_snapshot_restore(
+                        &snapshot_dir,
+                        memory_backing_file.as_deref(),
+                        config_mem_size,
+                        config_proc_count,
+                    )
+                    .with_context(|| {
+                        format!("failed to restore snapshot {}", snapshot_dir.display())
+                    })
+                })
+                .await?;
+                (Some(fd), Some(state_msg))
+            }
+        };
+
         let worker = vm_host
             .launch_worker(
                 VM_WORKER,
                 VmWorkerParameters {
                     hypervisor: openvmm_helpers::hypervisor::choose_hypervisor()?,
                     cfg: config,
-                    saved_state: None,
-                    shared_memory: None,
+                    saved_state,
+                    shared_memory,
                     rpc: recv,
                     notify: notify_send,
                 },
             )
-            .await?;
+            .await;
+
+        if let Some(incoming) = incoming_migration {
+            let result = worker
+                .as_ref()
+                .map(|_| ())
+                .map_err(|err| format!("{err:#}"));
+            blocking::unblock(move || incoming.complete(result)).await?;
+        }
+        let worker = worker?;
 
         let memory = config_mem_size;
         let processors = config_proc_count;
@@ -715,7 +1072,7 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             vm_rpc: send.clone(),
             paravisor_diag: None,
             igvm_path: None,
-            memory_backing_file: None,
+            memory_backing_file,
             memory,
             processors,
             log_file: None,
@@ -729,9 +1086,12 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
 
         self.vm_controller = Some(vm_controller_send);
         self.vm_controller_events = Some(event_recv);
+        self.crash_dumps = crash_dumps;
         self.controller_task = Some(controller_task);
         self.vm = Some(Arc::new(Vm {
             scsi_rpc,
+            balloon_rpc,
+            mem_size: config_mem_size,
             worker_rpc: send,
         }));
         Ok(())
@@ -746,26 +1106,92 @@ async fn teardown_vm(&mut self) -> anyhow::Result<()> {
         }
         self.vm.take();
         self.vm_controller_events.take();
+        self.crash_dumps.take();
+        self.packet_capture.lock().clear();
         if let Some((_, response)) = self.wait_vm_response.take() {
             response.send(Err(grpc_error(anyhow!("VM torn down"))));
         }
+        self.events.end_streams();
+        Ok(())
+    }
+
+    fn migrate_vm(
+        &mut self,
+        request: vmservice::MigrateVmRequest,
+    ) -> anyhow::Result<impl Future<Output = anyhow::Result<()>> + use<>> {
+        let controller = self.vm_controller.as_ref().context("vm not created")?;
+        let recv = controller.call(VmControllerRpc::MigrateSend, request.socket_path);
+        Ok(async move {
+            recv.await.context("migration failed")??;
+            Ok(())
+        })
+    }
+
+    async fn save_snapshot(
+        &mut self,
+        request: vmservice::SaveSnapshotRequest,
+    ) -> anyhow::Result<()> {
+        if request.snapshot_dir.is_empty() {
+            return Err(
+                anyhow::Error::new(Code::InvalidArgument).context("missing snapshot directory")
+            );
+        }
+        let controller = self.vm_controller.as_ref().context("vm not created")?;
+        controller
+            .call(
+                VmControllerRpc::SaveSnapshot,
+                SaveSnapshotParams {
+                    dir: request.snapshot_dir,
+                    live: request.live,
+                },
+            )
+            .await
+            .context("failed to save snapshot")?
+            .context("failed to save snapshot")?;
+        if !request.live {
+            self.resume_blocked = true;
+        }
         Ok(())
     }
 
+    fn pulse_save_restore(&mut self, vm: &Vm) -> impl Future<Output = anyhow::Result<()>> + use<> {
+        let recv = vm.worker_rpc.call(VmRpc::PulseSaveRestore, ());
+        async move {
+            match recv.await.context("pulse save/restore failed")? {
+                Ok(()) => Ok(()),
+                Err(PulseSaveRestoreError::ResetNotSupported) => {
+                    Err(anyhow::Error::new(Code::Unimplemented)
+                        .context("pulse save/restore not supported by this VM"))
+                }
+                Err(PulseSaveRestoreError::Other(err)) => {
+                    Err(anyhow::Error::new(err).context("pulse save/restore failed"))
+                }
+            }
+        }
+    }
+
     fn pause_vm(&mut self, vm: &Vm) -> impl Future<Output = anyhow::Result<()>> + use<> {
         let recv = vm.worker_rpc.call(VmRpc::Pause, ());
         async move { recv.await.map(drop).context("pause failed") }
     }
 
-    fn resume_vm(&mut self, vm: &Vm) -> impl Future<Output = anyhow::Result<()>> + use<> {
+    fn resume_vm(
+        &mut self,
+        vm: &Vm,
+    ) -> anyhow::Result<impl Future<Output = anyhow::Result<()>> + use<>> {
+        if self.resume_blocked {
+            return Err(anyhow::Error::new(Code::FailedPrecondition)
+                .context("the VM cannot be resumed after saving a non-live snapshot"));
+        }
         let recv = vm.worker_rpc.call(VmRpc::Resume, ());
-        async move { recv.await.map(drop).context("resume failed") }
+        Ok(async move { recv.await.map(drop).context("resume failed") })
     }
 
     fn handle_controller_event(&mut self, event: VmControllerEvent) {
         match event {
             VmControllerEvent::GuestHalt(reason) => {
-                tracing::info!(%reason, "guest halted (via controller)");
+                tracing::info!(?reason, "guest halted (via controller)");
+                self.events.push(events::guest_halt(&reason));
                 self.halted = true;
                 if let Some((_, response)) = self.wait_vm_response.take() {
                     response.send(Ok(()));
@@ -785,6 +1211,8 @@ fn handle_controller_event(&mut self, event: VmControllerEvent) {
                     };
                     response.send(Err(status));
                 }
+                self.events.push(events::worker_stopped(error.as_deref()));
+                self.events.end_streams();
                 // Clear VM state since the worker is gone. The controller
                 // task will be awaited during final cleanup.
                 self.vm.take();
@@ -798,6 +1226,38 @@ fn handle_controller_event(&mut self, event: VmControllerEvent) {
         }
     }
 
+    fn packet_capture(
+        &mut self,
+        request: vmservice::PacketCaptureRequest,
+    ) -> anyhow::Result<impl Future<Output = anyhow::Result<()>> + use<>> {
+        let nic_id: Guid = request.nic_id.parse().context("invalid nic ID")?;
+        let send = self
+            .packet_capture
+            .lock()
+            .get(&nic_id)
+            .cloned()
+            .with_context(|| format!("unknown nic {nic_id}"))?;
+        let capture = if request.stop {
+            PacketCaptureRequest::Stop
+        } else {
+            if request.file_path.is_empty() {
+                bail!("missing file path");
+            }
+            let file = File::create(&request.file_path)
+                .with_context(|| format!("failed to create {}", request.file_path))?;
+            PacketCaptureRequest::Start {
+                file,
+                snaplen: if request.snaplen == 0 {
+                    65535
+                } else {
+                    request.snaplen
+                },
+            }
+        };
+        let recv = send.call_failable(|x| x, capture);
+        Ok(async move { recv.await.map_err(anyhow::Error::from) })
+    }
+
     fn modify_resource(
         &mut self,
         vm: &Vm,
@@ -840,14 +1300,39 @@ fn modify_resource(
                 if request.r#type != vmservice::ModifyType::Add as i32 {
                     anyhow::bail!("not supported yet");
                 }
-                let config = parse_nic_config(nic)?;
+                let (config, (instance_id, send)) =
+                    parse_nic_config(nic, &self.packet_capture.lock())?;
                 let recv = vm.worker_rpc.call_failable(VmRpc::AddVmbusDevice, config);
-                Ok(async move { recv.await.map_err(anyhow::Error::from) }.boxed())
+                let packet_capture = self.packet_capture.clone();
+                Ok(async move {
+                    recv.await?;
+                    // Only register the capture channel once the NIC exists.
+                    packet_capture.lock().insert(instance_id, send);
+                    anyhow::Ok(())
+                }
+                .boxed())
             }
             Resource::VpmemDisk(_) => anyhow::bail!("vpmem not supported"),
             Resource::WindowsDevice(_) => anyhow::bail!("device assignment not supported"),
-            Resource::Processor(_) | Resource::ProcessorConfig(_) | Resource::Memory(_) => {
-                anyhow::bail!("processor and memory resources not supported")
+            Resource::Memory(memory) => {
+                if request.r#type != vmservice::ModifyType::Update as i32 {
+                    anyhow::bail!("unsupported request type {}", request.r#type);
+                }
+                let balloon_rpc = vm.balloon_rpc.as_ref().context("no balloon device")?;
+                // Reclaim the memory above the requested size with the
+                // balloon.
+                let balloon_size = memory
+                    .memory_mb
+                    .checked_mul(0x100000)
+                    .and_then(|size| vm.mem_size.checked_sub(size))
+                    .context("memory size exceeds the configured memory")?;
+                let pages = u32::try_from(balloon_size / 4096).context("memory size too small")?;
+                let recv =
+                    balloon_rpc.call(virtio_resources::balloon::BalloonRequest::SetTarget, pages);
+                Ok(async move { recv.await.map_err(anyhow::Error::from) }.boxed())
+            }
+            Resource::Processor(_) | Resource::ProcessorConfig(_) => {
+                anyhow::bail!("processor resources not supported")
             }
         }
     }
@@ -864,10 +1349,20 @@ fn modify_resource(
 )]
 fn parse_nic_config(
     nic: vmservice::NicConfig,
-) -> anyhow::Result<(DeviceVtl, Resource<VmbusDeviceHandleKind>)> {
+    packet_capture: &HashMap<Guid, PacketCaptureSender>,
+) -> anyhow::Result<(
+    (DeviceVtl, Resource<VmbusDeviceHandleKind>),
+    (Guid, PacketCaptureSender),
+)> {
     #[cfg(any(windows, target_os = "linux"))]
     use self::vmservice::nic_config::Backend;
 
+    let instance_id: Guid = nic.nic_id.parse().context("invalid instance ID")?;
+    if packet_capture.contains_key(&instance_id) {
+        return Err(anyhow::Error::new(Code::AlreadyExists)
+            .context(format!("nic {instance_id} already exists")));
+    }
+
     let endpoint = match nic.backend.context("missing backend")? {
         #[cfg(windows)]
         Backend::LegacyPortId(port_id) => net_backend_resources::dio::WindowsDirectIoHandle {
@@ -893,8 +1388,16 @@ fn parse_nic_config(
         }
         _ => anyhow::bail!("unsupported backend"),
     };
+    // Wrap the endpoint so that packet captures can be started at runtime.
+    let (send, recv) = mesh::channel();
+    let endpoint = net_backend_resources::packet_capture::PacketCaptureHandle {
+        id: instance_id.to_string(),
+        endpoint,
+        requests: recv,
+    }
+    .into_resource();
     let cfg = NetvspHandle {
-        instance_id: nic.nic_id.parse().context("invalid instance ID")?,
+        instance_id,
         mac_address: nic
             .mac_address
             .parse::<net_backend_resources::mac_address::MacAddress>()
@@ -902,7 +1405,7 @@ fn parse_nic_config(
         endpoint,
         max_queues: None,
     };
-    Ok((DeviceVtl::Vtl0, cfg.into_resource()))
+    Ok(((DeviceVtl::Vtl0, cfg.into_resource()), (instance_id, send)))
 }
 
 async fn make_disk_config(disk: vmservice::ScsiDisk) -> anyhow::Result<ScsiDeviceAndPath> {
@@ -18,6 +18,7 @@
 use mesh_worker::WorkerEvent;
 use mesh_worker::WorkerHandle;
 use openvmm_defs::rpc::VmRpc;
+use openvmm_helpers::migration::MigrationSender;
 use std::path::Path;
 use std::path::PathBuf;
 use std::pin::pin;
@@ -54,7 +55,9 @@ pub enum VmControllerRpc {
         Rpc<RemoveVtl0ScsiDiskByNvmeNsidParams, Result<Option<u32>, mesh::error::RemoteError>>,
     ),
     /// Save a VM snapshot to a directory.
-    SaveSnapshot(Rpc<String, Result<(), mesh::error::RemoteError>>),
+    SaveSnapshot(Rpc<SaveSnapshotParams, Result<(), mesh::error::RemoteError>>),
+    /// Live migrate the VM to the destination listening on a socket path.
+    MigrateSend(Rpc<String, Result<(), mesh::error::RemoteError>>),
     /// Service (update) the VTL2 firmware.
     ServiceVtl2(Rpc<ServiceVtl2Params, Result<u64, mesh::error::RemoteError>>),
     /// Stop the VM and quit.
@@ -83,6 +86,15 @@ pub struct RemoveVtl0ScsiDiskByNvmeNsidParams {
     pub nsid: u32,
 }
 
+#[derive(mesh::MeshPayload)]
+pub struct SaveSnapshotParams {
+    pub dir: String,
+    /// Copy guest RAM into the snapshot and resume the VM afterwards, instead
+    /// of linking the snapshot to the memory backing file and leaving the VM
+    /// paused.
+    pub live: bool,
+}
+
 #[derive(mesh::MeshPayload)]
 pub struct ServiceVtl2Params {
     pub user_mode_only: bool,
@@ -99,7 +111,7 @@ pub enum VmControllerEvent {
     /// The VNC worker stopped or failed.
     VncWorkerStopped { error: Option<String> },
     /// The guest halted.
-    GuestHalt(String),
+    GuestHalt(vmm_core_defs::HaltReason),
 }
 
 /// Owns exclusive VM resources and services RPCs from the REPL.
@@ -120,6 +132,13 @@ pub struct VmController {
     pub(crate) log_file: Option<PathBuf>,
 }
 
+/// The maximum number of passes over dirty memory before the VM is paused,
+/// in case the guest dirties memory faster than it can be sent.
+const MAX_PRECOPY_ROUNDS: usize = 8;
+
+/// Pause the VM once the dirty memory left to send is at most this size.
+const PRECOPY_DIRTY_THRESHOLD: u64 = 32 * 1024 * 1024;
+
 impl VmController {
     /// Run the controller, processing RPCs and worker events until the VM
     /// stops or the caller (REPL or ttrpc server) sends Quit.
@@ -229,7 +248,7 @@ enum Event {
                 },
                 Event::Halt(reason) => {
                     tracing::info!(?reason, "guest halted");
-                    event_send.send(VmControllerEvent::GuestHalt(format!("{reason:?}")));
+                    event_send.send(VmControllerEvent::GuestHalt(reason));
                 }
             }
         }
@@ -302,8 +321,13 @@ async fn handle_rpc(&mut self, rpc: VmControllerRpc, quit: &mut bool) {
                 req.complete(result.map_err(mesh::error::RemoteError::new));
             }
             VmControllerRpc::SaveSnapshot(req) => {
-                let (dir, req) = req.split();
-                let result = self.handle_save_snapshot(Path::new(&dir)).await;
+                let (params, req) = req.split();
+                let result = self.handle_save_snapshot(params).await;
+                req.complete(result.map_err(mesh::error::RemoteError::new));
+            }
+            VmControllerRpc::MigrateSend(req) => {
+                let (socket, req) = req.split();
+                let result = self.handle_migrate_send(socket).await;
                 req.complete(result.map_err(mesh::error::RemoteError::new));
             }
             VmControllerRpc::ServiceVtl2(req) => {
@@ -361,18 +385,42 @@ fn handle_inspect(&mut self, target: InspectTarget, deferred: inspect::Deferred)
         deferred.inspect(obj);
     }
 
-    async fn handle_save_snapshot(&self, dir: &Path) -> anyhow::Result<()> {
+    async fn handle_save_snapshot(&mut self, params: SaveSnapshotParams) -> anyhow::Result<()> {
+        let dir = Path::new(&params.dir);
         let memory_file_path = self
             .memory_backing_file
-            .as_ref()
-            .context("save-snapshot requires --memory-backing-file")?;
+            .clone()
+            .context("saving a snapshot requires a memory backing file")?;
 
         // Pause the VM.
-        self.vm_rpc
+        let was_running = self
+            .vm_rpc
             .call(VmRpc::Pause, ())
             .await
             .context("failed to pause VM")?;
 
+        let result = self
+            .save_snapshot(dir, &memory_file_path, params.live)
+            .await;
+
+        if params.live && was_running {
+            self.vm_rpc
+                .call(VmRpc::Resume, ())
+                .await
+                .context("failed to resume VM")?;
+        }
+
+        // Otherwise the VM stays paused. Do NOT resume, since memory.bin is
+        // linked to the backing file.
+        result
+    }
+
+    async fn save_snapshot(
+        &self,
+        dir: &Path,
+        memory_file_path: &Path,
+        live: bool,
+    ) -> anyhow::Result<()> {
         // Get device state via existing VmRpc::Save.
         let saved_state_msg = self
             .vm_rpc
@@ -401,17 +449,126 @@ async fn handle_save_snapshot(&self, dir: &Path) -> anyhow::Result<()> {
         };
 
         // Write snapshot directory.
-        openvmm_helpers::snapshot::write_snapshot(
-            dir,
-            &manifest,
-            &saved_state_bytes,
-            memory_file_path,
-        )?;
-
-        // VM stays paused. Do NOT resume.
+        if live {
+            openvmm_helpers::snapshot::write_live_snapshot(
+                dir,
+                &manifest,
+                &saved_state_bytes,
+                memory_file_path,
+            )?;
+        } else {
+            openvmm_helpers::snapshot::write_snapshot(
+                dir,
+                &manifest,
+                &saved_state_bytes,
+                memory_file_path,
+            )?;
+        }
         Ok(())
     }
 
+    async fn handle_migrate_send(&mut self, socket: String) -> anyhow::Result<()> {
+        let memory_file_path = self
+            .memory_backing_file
+            .clone()
+            .context("migrate-send requires --memory-backing-file")?;
+
+        let hello = openvmm_helpers::migration::MigrationHello {
+            version: openvmm_helpers::migration::MIGRATION_VERSION,
+            openvmm_version: env!("CARGO_PKG_VERSION").to_string(),
+            memory_size_bytes: self.memory,
+            vp_count: self.processors,
+            page_size: crate::system_page_size(),
+            architecture: crate::GUEST_ARCH.to_string(),
+        };
+        let transfer = blocking::unblock(move || {
+            let sender = MigrationSender::connect(Path::new(&socket), hello)?;
+            let memory_file = fs_err::File::open(&memory_file_path)?;
+            anyhow::Ok((sender, memory_file))
+        })
+        .await?;
+
+        // Start tracking writes before the first pass over memory, so that
+        // nothing written during the pass is missed.
+        self.vm_rpc
+            .call_failable(VmRpc::SetDirtyPageTracking, true)
+            .await
+            .context("live migration requires dirty page tracking")?;
+
+        let result = self.migrate(transfer).await;
+        if result.is_err()
+            && let Err(err) = self
+                .vm_rpc
+                .call_failable(VmRpc::SetDirtyPageTracking, false)
+                .await
+        {
+            tracing::warn!(
+                error = &err as &dyn std::error::Error,
+                "failed to disable dirty page tracking"
+            );
+        }
+        result
+    }
+
+    async fn migrate(
+        &mut self,
+        mut transfer: (MigrationSender, fs_err::File),
+    ) -> anyhow::Result<()> {
+        // Send all of memory, then keep sending what the guest wrote in the
+        // meantime until the remainder is small enough to send with the VM
+        // paused.
+        let mut ranges = vec![(0, self.memory)];
+        for round in 0..MAX_PRECOPY_ROUNDS {
+            let len = ranges.iter().map(|&(_, len)| len).sum::<u64>();
+            tracing::info!(round, len, "sending guest memory");
+            transfer = send_memory(transfer, ranges).await?;
+            ranges = self
+                .vm_rpc
+                .call_failable(VmRpc::TakeDirtyMemory, ())
+                .await
+                .context("failed to get dirty memory")?;
+            if ranges.iter().map(|&(_, len)| len).sum::<u64>() <= PRECOPY_DIRTY_THRESHOLD {
+                break;
+            }
+        }
+
+        let was_running = self
+            .vm_rpc
+            .call(VmRpc::Pause, ())
+            .await
+            .context("failed to pause VM")?;
+
+        let result = async {
+            let saved_state_msg = self
+                .vm_rpc
+                .call_failable(VmRpc::Save, ())
+                .await
+                .context("failed to save state")?;
+            ranges.extend(
+                self.vm_rpc
+                    .call_failable(VmRpc::TakeDirtyMemory, ())
+                    .await
+                    .context("failed to get dirty memory")?,
+            );
+            let len = ranges.iter().map(|&(_, len)| len).sum::<u64>();
+            tracing::info!(len, "sending final guest memory and device state");
+            let (sender, _) = send_memory(transfer, ranges).await?;
+            let saved_state_bytes = mesh::payload::encode(saved_state_msg);
+            blocking::unblock(move || sender.finish(saved_state_bytes)).await
+        }
+        .await;
+
+        // On success the VM now runs on the destination, so leave it paused
+        // here.
+        if result.is_err() && was_running {
+            self.vm_rpc
+                .call(VmRpc::Resume, ())
+                .await
+                .context("failed to resume VM")?;
+        }
+        result
+    }
+
     async fn handle_service_vtl2(&self, params: ServiceVtl2Params) -> anyhow::Result<u64> {
         let start;
         if params.user_mode_only {
@@ -574,3 +731,15 @@ async fn handle_remove_vtl0_scsi_disk_by_nvme_nsid(
         Ok(removed_lun)
     }
 }
+
+/// Sends `ranges` of the memory backing file to the migration destination.
+async fn send_memory(
+    (mut sender, mut memory_file): (MigrationSender, fs_err::File),
+    ranges: Vec<(u64, u64)>,
+) -> anyhow::Result<(MigrationSender, fs_err::File)> {
+    blocking::unblock(move || {
+        sender.send_memory(&mut memory_file, &ranges)?;
+        Ok((sender, memory_file))
+    })
+    .await
+}
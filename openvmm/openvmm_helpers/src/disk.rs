@@ -5,6 +5,7 @@
 
 use anyhow::Context;
 use std::path::Path;
+use std::path::PathBuf;
 use vm_resource::Resource;
 use vm_resource::kind::DiskHandleKind;
 
@@ -22,6 +23,37 @@ fn disk_open_error(path: &Path, verb: &str) -> String {
     msg
 }
 
+/// The maximum number of images in a backing file or parent chain.
+const MAX_CHAIN_DEPTH: usize = 64;
+
+/// The images opened so far in a backing file or parent chain.
+#[derive(Default)]
+struct ImageChain {
+    paths: Vec<PathBuf>,
+}
+
+impl ImageChain {
+    /// Records that `path` is the next image in the chain, failing if it is
+    /// already in the chain or if the chain is too long.
+    fn push(&mut self, path: &Path) -> anyhow::Result<()> {
+        let path = fs_err::canonicalize(path)?;
+        if self.paths.contains(&path) {
+            anyhow::bail!(
+                "the chain of backing files or parents loops back to '{}'",
+                path.display()
+            );
+        }
+        if self.paths.len() >= MAX_CHAIN_DEPTH {
+            anyhow::bail!(
+                "the chain of backing files or parents of '{}' is longer than {MAX_CHAIN_DEPTH} images",
+                self.paths[0].display()
+            );
+        }
+        self.paths.push(path);
+        Ok(())
+    }
+}
+
 /// Options for opening a disk file.
 #[derive(Clone, Copy)]
 pub struct OpenDiskOptions {
@@ -35,7 +67,13 @@ pub struct OpenDiskOptions {
 ///
 /// If the file ends with .vhd and is a fixed VHD1, it will be opened using
 /// the user-mode VHD parser. Otherwise, if the file ends with .vhd or
-/// .vhdx, the file will be opened using the kernel-mode VHD parser.
+/// .vhdx, the file will be opened using the kernel-mode VHD parser on
+/// Windows. On other hosts, dynamic and differencing .vhd files and .vhdx
+/// files are opened using the user-mode parsers, along with their chain of
+/// parents.
+///
+/// If the file ends with .qcow2, it will be opened using the user-mode QCOW2
+/// parser, along with its chain of backing files.
 pub async fn open_disk_type(
     path: &Path,
     options: OpenDiskOptions,
@@ -74,7 +112,10 @@ pub async fn open_disk_type(
                         ))
                     }
                     #[cfg(not(windows))]
-                    anyhow::bail!("non-fixed VHD not supported on Linux");
+                    {
+                        ensure_no_direct("dynamic .vhd")?;
+                        open_dynamic_vhd1_disk(path, read_only, None, &mut ImageChain::default())?
+                    }
                 }
                 Err(err) => return Err(err.into()),
             }
@@ -91,7 +132,14 @@ pub async fn open_disk_type(
                 ))
             }
             #[cfg(not(windows))]
-            anyhow::bail!("VHDX not supported on Linux");
+            {
+                ensure_no_direct(".vhdx")?;
+                open_vhdx_disk(path, read_only, None, &mut ImageChain::default())?
+            }
+        }
+        Some("qcow2") => {
+            ensure_no_direct(".qcow2")?;
+            open_qcow2_disk(path, read_only, &mut ImageChain::default())?
         }
         Some("iso") if !read_only => {
             anyhow::bail!("iso file cannot be opened as read/write")
@@ -136,7 +184,53 @@ pub fn create_disk_type(
             Resource::new(disk_backend_resources::FixedVhd1DiskHandle(file))
         }
         Some("vhdx") => {
-            anyhow::bail!("creating vhdx not supported")
+            if options.direct {
+                anyhow::bail!("direct I/O is not supported for vhdx files");
+            }
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .read(true)
+                .write(true)
+                .open(path)
+                .with_context(|| disk_open_error(path, "failed to create"))?;
+
+            disk_vhdx::VhdxDiskLayer::create(
+                &file,
+                &disk_vhdx::CreateParams {
+                    size,
+                    block_size: None,
+                    logical_sector_size: None,
+                    physical_sector_size: None,
+                    parent: None,
+                },
+            )?;
+            Resource::new(disk_backend_resources::VhdxDiskHandle { file, parent: None })
+        }
+        Some("qcow2") => {
+            if options.direct {
+                anyhow::bail!("direct I/O is not supported for qcow2 files");
+            }
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .read(true)
+                .write(true)
+                .open(path)
+                .with_context(|| disk_open_error(path, "failed to create"))?;
+
+            disk_qcow2::Qcow2DiskLayer::create(
+                &file,
+                &disk_qcow2::CreateParams {
+                    size,
+                    cluster_bits: None,
+                    backing_file: None,
+                },
+            )?;
+            Resource::new(disk_backend_resources::Qcow2DiskHandle {
+                file,
+                backing: None,
+            })
         }
         Some("iso") => {
             anyhow::bail!("creating iso not supported")
@@ -145,6 +239,182 @@ pub fn create_disk_type(
     })
 }
 
+/// Opens a QCOW2 image and, recursively, its backing files.
+///
+/// Backing files are always opened read-only. Relative backing file paths are
+/// resolved relative to the directory containing the image.
+fn open_qcow2_disk(
+    path: &Path,
+    read_only: bool,
+    chain: &mut ImageChain,
+) -> anyhow::Result<Resource<DiskHandleKind>> {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(!read_only)
+        .open(path)
+        .with_context(|| disk_open_error(path, "failed to open"))?;
+    chain.push(path)?;
+
+    let backing = match disk_qcow2::Qcow2DiskLayer::backing_file(&file)
+        .with_context(|| format!("failed to parse qcow2 header of '{}'", path.display()))?
+    {
+        Some(backing) => {
+            let backing_path = path.parent().unwrap_or(Path::new("")).join(&backing.path);
+            let is_qcow2 = match backing.format.as_deref() {
+                Some("qcow2") => true,
+                Some("raw") => false,
+                Some(format) => anyhow::bail!(
+                    "unsupported backing file format '{format}' for '{}'",
+                    path.display()
+                ),
+                None => disk_qcow2::Qcow2DiskLayer::probe(
+                    &std::fs::File::open(&backing_path)
+                        .with_context(|| disk_open_error(&backing_path, "failed to open"))?,
+                )?,
+            };
+            Some(if is_qcow2 {
+                open_qcow2_disk(&backing_path, true, chain)?
+            } else {
+                open_raw_disk(
+                    &backing_path,
+                    OpenDiskOptions {
+                        read_only: true,
+                        direct: false,
+                    },
+                    None,
+                )?
+            })
+        }
+        None => None,
+    };
+
+    Ok(Resource::new(disk_backend_resources::Qcow2DiskHandle {
+        file,
+        backing,
+    }))
+}
+
+/// Opens a VHDX image and, recursively, its parents.
+///
+/// Parents are always opened read-only. A parent is found using its
+/// locator's relative path, resolved relative to the directory containing the
+/// child, or else its absolute path. If `linkage` is set, this is a parent
+/// and its data write GUID must match the child's parent locator.
+#[cfg(not(windows))]
+fn open_vhdx_disk(
+    path: &Path,
+    read_only: bool,
+    linkage: Option<&disk_vhdx::ParentLocator>,
+    chain: &mut ImageChain,
+) -> anyhow::Result<Resource<DiskHandleKind>> {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(!read_only)
+        .open(path)
+        .with_context(|| disk_open_error(path, "failed to open"))?;
+    chain.push(path)?;
+
+    if !read_only {
+        disk_vhdx::VhdxDiskLayer::replay_log(&file)
+            .with_context(|| format!("failed to replay the log of '{}'", path.display()))?;
+    }
+    let info = disk_vhdx::VhdxDiskLayer::info(&file)
+        .with_context(|| format!("failed to parse vhdx metadata of '{}'", path.display()))?;
+
+    if let Some(linkage) = linkage
+        && info.data_write_guid != linkage.parent_linkage
+        && Some(info.data_write_guid) != linkage.parent_linkage2
+    {
+        anyhow::bail!(
+            "'{}' has been modified since its differencing disk was created",
+            path.display()
+        );
+    }
+
+    let parent = match &info.parent {
+        Some(locator) => {
+            let dir = path.parent().unwrap_or(Path::new(""));
+            let parent_path = locator
+                .relative_path
+                .iter()
+                .map(|relative| dir.join(relative.replace('\\', "/")))
+                .chain(locator.absolute_win32_path.iter().map(Into::into))
+                .find(|candidate| candidate.exists())
+                .with_context(|| format!("could not find the parent of '{}'", path.display()))?;
+            Some(open_vhdx_disk(&parent_path, true, Some(locator), chain)?)
+        }
+        None => None,
+    };
+
+    Ok(Resource::new(disk_backend_resources::VhdxDiskHandle {
+        file,
+        parent,
+    }))
+}
+
+/// Opens a dynamic or differencing VHD1 image and, recursively, its parents.
+///
+/// Parents are always opened read-only, and may be fixed images. A parent is
+/// found using its locator's relative path, resolved relative to the
+/// directory containing the child, or else its absolute path. If `linkage` is
+/// set, this is a parent and its unique ID must match the child's parent
+/// locator.
+#[cfg(not(windows))]
+fn open_dynamic_vhd1_disk(
+    path: &Path,
+    read_only: bool,
+    linkage: Option<&disk_vhd1::ParentLocator>,
+    chain: &mut ImageChain,
+) -> anyhow::Result<Resource<DiskHandleKind>> {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(!read_only)
+        .open(path)
+        .with_context(|| disk_open_error(path, "failed to open"))?;
+    chain.push(path)?;
+
+    let info = disk_vhd1::Vhd1Disk::info(&file)
+        .with_context(|| format!("failed to parse vhd footer of '{}'", path.display()))?;
+
+    if let Some(linkage) = linkage
+        && info.unique_id != linkage.unique_id
+    {
+        anyhow::bail!(
+            "'{}' does not match the parent recorded by its differencing disk",
+            path.display()
+        );
+    }
+
+    let parent = match &info.parent {
+        Some(locator) => {
+            let dir = path.parent().unwrap_or(Path::new(""));
+            let parent_path = locator
+                .relative_path
+                .iter()
+                .map(|relative| dir.join(relative.replace('\\', "/")))
+                .chain(locator.absolute_path.iter().map(Into::into))
+                .find(|candidate| candidate.exists())
+                .with_context(|| format!("could not find the parent of '{}'", path.display()))?;
+            Some(open_dynamic_vhd1_disk(
+                &parent_path,
+                true,
+                Some(locator),
+                chain,
+            )?)
+        }
+        None => None,
+    };
+
+    Ok(match info.disk_type {
+        disk_vhd1::Vhd1DiskType::Fixed => {
+            Resource::new(disk_backend_resources::FixedVhd1DiskHandle(file))
+        }
+        disk_vhd1::Vhd1DiskType::Dynamic | disk_vhd1::Vhd1DiskType::Differencing => {
+            Resource::new(disk_backend_resources::DynamicVhd1DiskHandle { file, parent })
+        }
+    })
+}
+
 /// Open or create a raw file or block device, returning the appropriate
 /// disk resource for the current platform.
 fn open_raw_disk(
@@ -193,3 +463,132 @@ fn open_raw_disk(
         Ok(Resource::new(disk_backend_resources::FileDiskHandle(file)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_qcow2(path: &Path, backing: &str) {
+        let file = std::fs::OpenOptions::new()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        disk_qcow2::Qcow2DiskLayer::create(
+            &file,
+            &disk_qcow2::CreateParams {
+                size: 0x100000,
+                cluster_bits: None,
+                backing_file: Some(&disk_qcow2::BackingFile {
+                    path: backing.to_owned(),
+                    format: Some("qcow2".to_owned()),
+                }),
+            },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn qcow2_backing_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        create_qcow2(&dir.path().join("self.qcow2"), "self.qcow2");
+        create_qcow2(&dir.path().join("a.qcow2"), "b.qcow2");
+        create_qcow2(&dir.path().join("b.qcow2"), "a.qcow2");
+
+        for name in ["self.qcow2", "a.qcow2"] {
+            let Err(err) =
+                open_qcow2_disk(&dir.path().join(name), true, &mut ImageChain::default())
+            else {
+                panic!("opened a backing file loop");
+            };
+            assert!(format!("{err:#}").contains("loops back to"), "{err:#}");
+        }
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn vhdx_parent_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("self.vhdx");
+        let file = std::fs::OpenOptions::new()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        disk_vhdx::VhdxDiskLayer::create(
+            &file,
+            &disk_vhdx::CreateParams {
+                size: 0x100000,
+                block_size: None,
+                logical_sector_size: None,
+                physical_sector_size: None,
+                parent: Some(&disk_vhdx::ParentLocator {
+                    parent_linkage: guid::Guid::new_random(),
+                    parent_linkage2: None,
+                    relative_path: Some("self.vhdx".to_owned()),
+                    volume_path: None,
+                    absolute_win32_path: None,
+                }),
+            },
+        )
+        .unwrap();
+
+        let Err(err) = open_vhdx_disk(&path, true, None, &mut ImageChain::default()) else {
+            panic!("opened a parent loop");
+        };
+        assert!(format!("{err:#}").contains("loops back to"), "{err:#}");
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn vhd1_parent_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("self.vhd");
+        let file = std::fs::OpenOptions::new()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        disk_vhd1::DynamicVhd1Layer::create(
+            &file,
+            &disk_vhd1::CreateParams {
+                size: 0x100000,
+                block_size: None,
+                parent: Some(&disk_vhd1::ParentLocator {
+                    unique_id: guid::Guid::new_random(),
+                    unicode_name: "self.vhd".to_owned(),
+                    relative_path: Some("self.vhd".to_owned()),
+                    absolute_path: None,
+                }),
+            },
+        )
+        .unwrap();
+
+        let Err(err) = open_dynamic_vhd1_disk(&path, true, None, &mut ImageChain::default()) else {
+            panic!("opened a parent loop");
+        };
+        assert!(format!("{err:#}").contains("loops back to"), "{err:#}");
+    }
+
+    #[test]
+    fn qcow2_backing_chain_too_long() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..=MAX_CHAIN_DEPTH {
+            create_qcow2(
+                &dir.path().join(format!("{i}.qcow2")),
+                &format!("{}.qcow2", i + 1),
+            );
+        }
+        let Err(err) = open_qcow2_disk(
+            &dir.path().join("0.qcow2"),
+            true,
+            &mut ImageChain::default(),
+        ) else {
+            panic!("opened an overlong backing file chain");
+        };
+        assert!(format!("{err:#}").contains("is longer than"), "{err:#}");
+    }
+}
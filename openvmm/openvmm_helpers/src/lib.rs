@@ -7,6 +7,7 @@
 
 pub mod disk;
 pub mod hypervisor;
+pub mod migration;
 pub mod shared_memory;
 pub mod snapshot;
 pub mod underhill;
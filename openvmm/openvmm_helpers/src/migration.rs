@@ -0,0 +1,424 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Wire protocol for pre-copy live migration between two OpenVMM processes.
+//!
+//! The source connects to the destination's Unix socket and sends a
+//! [`MigrationHello`] describing the VM, followed by guest RAM. While the VM
+//! keeps running, the source sends all of RAM once and then, repeatedly, the
+//! pages written since the previous pass. Once the remaining dirty set is
+//! small, the source pauses the VM and sends the last dirty pages and the
+//! device state. The destination replies with the result of restoring the VM,
+//! so that the source can resume it if the migration failed.
+//!
+//! Each message is a little-endian `u32` length followed by a protobuf-encoded
+//! message.
+
+use anyhow::Context;
+use mesh::payload::Protobuf;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use unix_socket::UnixListener;
+use unix_socket::UnixStream;
+
+/// Current protocol version. Both sides must use the same version.
+pub const MIGRATION_VERSION: u32 = 1;
+
+/// The maximum amount of guest RAM sent in a single message.
+const MEMORY_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The maximum size of a message, with room for framing overhead and device
+/// state.
+const MAX_MESSAGE_SIZE: usize = 256 * 1024 * 1024;
+
+/// The first message on a migration stream, describing the migrating VM.
+#[derive(Clone, Debug, Protobuf)]
+#[mesh(package = "openvmm.migration")]
+pub struct MigrationHello {
+    /// Protocol version.
+    #[mesh(1)]
+    pub version: u32,
+    /// OpenVMM version of the source.
+    #[mesh(2)]
+    pub openvmm_version: String,
+    /// Guest RAM size in bytes.
+    #[mesh(3)]
+    pub memory_size_bytes: u64,
+    /// Number of virtual processors.
+    #[mesh(4)]
+    pub vp_count: u32,
+    /// Page size in bytes.
+    #[mesh(5)]
+    pub page_size: u32,
+    /// Architecture string ("x86_64" or "aarch64").
+    #[mesh(6)]
+    pub architecture: String,
+}
+
+/// Validates that a migration described by `hello` can be received into a VM
+/// with the given configuration.
+pub fn validate_hello(
+    hello: &MigrationHello,
+    expected_arch: &str,
+    expected_memory_size: u64,
+    expected_vp_count: u32,
+    expected_page_size: u32,
+) -> anyhow::Result<()> {
+    if hello.architecture != expected_arch {
+        anyhow::bail!(
+            "source architecture '{}' doesn't match expected '{}'",
+            hello.architecture,
+            expected_arch,
+        );
+    }
+    if hello.memory_size_bytes != expected_memory_size {
+        anyhow::bail!(
+            "source memory size ({} bytes) doesn't match expected ({} bytes)",
+            hello.memory_size_bytes,
+            expected_memory_size,
+        );
+    }
+    if hello.vp_count != expected_vp_count {
+        anyhow::bail!(
+            "source VP count ({}) doesn't match expected ({})",
+            hello.vp_count,
+            expected_vp_count,
+        );
+    }
+    if hello.page_size != expected_page_size {
+        anyhow::bail!(
+            "source page size ({}) doesn't match expected ({})",
+            hello.page_size,
+            expected_page_size,
+        );
+    }
+    Ok(())
+}
+
+#[derive(Protobuf)]
+#[mesh(package = "openvmm.migration")]
+enum Message {
+    #[mesh(1)]
+    Hello(#[mesh(1)] MigrationHello),
+    /// Guest RAM at `offset` in the memory backing file.
+    #[mesh(2)]
+    Memory {
+        #[mesh(1)]
+        offset: u64,
+        #[mesh(2)]
+        data: Vec<u8>,
+    },
+    /// The saved device state. This is the last message from the source.
+    #[mesh(3)]
+    DeviceState(#[mesh(1)] Vec<u8>),
+    /// The destination's result, with an error message on failure.
+    #[mesh(4)]
+    Complete(#[mesh(1)] Option<String>),
+}
+
+fn write_message(stream: &mut impl Write, message: Message) -> std::io::Result<()> {
+    let data = mesh::payload::encode(message);
+    stream.write_all(&(data.len() as u32).to_le_bytes())?;
+    stream.write_all(&data)
+}
+
+fn read_message(stream: &mut impl Read) -> anyhow::Result<Message> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        anyhow::bail!("migration message too large ({len} bytes)");
+    }
+    let mut data = vec![0; len];
+    stream.read_exact(&mut data)?;
+    mesh::payload::decode(&data).context("failed to decode migration message")
+}
+
+/// The sending side of a migration.
+pub struct MigrationSender<S = UnixStream> {
+    stream: S,
+    memory_size: u64,
+    buf: Vec<u8>,
+}
+
+impl MigrationSender {
+    /// Connects to the destination listening at `path` and sends `hello`.
+    pub fn connect(path: &Path, hello: MigrationHello) -> anyhow::Result<Self> {
+        let stream = UnixStream::connect(path)
+            .with_context(|| format!("failed to connect to {}", path.display()))?;
+        Self::new(stream, hello)
+    }
+}
+
+impl<S: Read + Write> MigrationSender<S> {
+    fn new(mut stream: S, hello: MigrationHello) -> anyhow::Result<Self> {
+        let memory_size = hello.memory_size_bytes;
+        write_message(&mut stream, Message::Hello(hello)).context("failed to send hello")?;
+        Ok(Self {
+            stream,
+            memory_size,
+            buf: vec![0; MEMORY_CHUNK_SIZE],
+        })
+    }
+
+    /// Sends the given `(offset, length)` ranges of guest RAM, read from the
+    /// memory backing file `memory`.
+    pub fn send_memory(
+        &mut self,
+        memory: &mut fs_err::File,
+        ranges: &[(u64, u64)],
+    ) -> anyhow::Result<()> {
+        for &(offset, len) in ranges {
+            if offset
+                .checked_add(len)
+                .is_none_or(|end| end > self.memory_size)
+            {
+                anyhow::bail!("memory range {offset:#x}+{len:#x} is outside of guest RAM");
+            }
+            memory.seek(SeekFrom::Start(offset))?;
+            let mut done = 0;
+            while done < len {
+                let n = (len - done).min(MEMORY_CHUNK_SIZE as u64) as usize;
+                memory.read_exact(&mut self.buf[..n])?;
+                write_message(
+                    &mut self.stream,
+                    Message::Memory {
+                        offset: offset + done,
+                        data: self.buf[..n].to_vec(),
+                    },
+                )
+                .context("failed to send memory")?;
+                done += n as u64;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the device state and waits for the destination to restore the
+    /// VM.
+    pub fn finish(mut self, device_state: Vec<u8>) -> anyhow::Result<()> {
+        write_message(&mut self.stream, Message::DeviceState(device_state))
+            .context("failed to send device state")?;
+        match read_message(&mut self.stream).context("failed to read migration result")? {
+            Message::Complete(None) => Ok(()),
+            Message::Complete(Some(err)) => {
+                anyhow::bail!("destination failed to restore the VM: {err}")
+            }
+            _ => anyhow::bail!("unexpected message from the destination"),
+        }
+    }
+}
+
+/// The receiving side of a migration.
+pub struct IncomingMigration<S = UnixStream> {
+    stream: S,
+    hello: MigrationHello,
+}
+
+impl IncomingMigration {
+    /// Listens on `path` for a single incoming migration and reads its hello
+    /// message.
+    ///
+    /// A Unix socket left at `path` by an earlier listener is removed first.
+    pub fn listen(path: &Path) -> anyhow::Result<Self> {
+        remove_stale_socket(path);
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("failed to bind to {}", path.display()))?;
+        tracing::info!(path = %path.display(), "waiting for incoming migration");
+        let (stream, _) = listener.accept().context("failed to accept connection")?;
+        Self::new(stream)
+    }
+}
+
+/// Removes `path` if it is a Unix socket, leaving any other kind of file in
+/// place so that binding to it fails.
+fn remove_stale_socket(path: &Path) {
+    #[cfg(windows)]
+    let is_socket = pal::windows::fs::is_unix_socket(path).unwrap_or(false);
+    #[cfg(not(windows))]
+    let is_socket = path
+        .metadata()
+        .is_ok_and(|meta| std::os::unix::fs::FileTypeExt::is_socket(&meta.file_type()));
+
+    if is_socket {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+impl<S: Read + Write> IncomingMigration<S> {
+    fn new(mut stream: S) -> anyhow::Result<Self> {
+        let Message::Hello(hello) = read_message(&mut stream).context("failed to read hello")?
+        else {
+            anyhow::bail!("migration stream did not start with a hello");
+        };
+        if hello.version != MIGRATION_VERSION {
+            anyhow::bail!(
+                "migration protocol version {} is not supported (expected {})",
+                hello.version,
+                MIGRATION_VERSION,
+            );
+        }
+        Ok(Self { stream, hello })
+    }
+
+    /// Returns the source's description of the VM.
+    pub fn hello(&self) -> &MigrationHello {
+        &self.hello
+    }
+
+    /// Receives guest RAM into the memory backing file `memory`, which must
+    /// already be sized to the guest RAM size, until the source sends the
+    /// device state.
+    ///
+    /// Returns the device state.
+    pub fn receive(&mut self, memory: &mut fs_err::File) -> anyhow::Result<Vec<u8>> {
+        loop {
+            match read_message(&mut self.stream).context("failed to read migration message")? {
+                Message::Memory { offset, data } => {
+                    if offset
+                        .checked_add(data.len() as u64)
+                        .is_none_or(|end| end > self.hello.memory_size_bytes)
+                    {
+                        anyhow::bail!("migrated memory at {offset:#x} is outside of guest RAM");
+                    }
+                    memory.seek(SeekFrom::Start(offset))?;
+                    memory.write_all(&data)?;
+                }
+                Message::DeviceState(state) => break Ok(state),
+                _ => anyhow::bail!("unexpected message from the source"),
+            }
+        }
+    }
+
+    /// Reports the result of restoring the VM to the source.
+    pub fn complete(mut self, result: Result<(), String>) -> anyhow::Result<()> {
+        write_message(&mut self.stream, Message::Complete(result.err()))
+            .context("failed to send migration result")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_hello() -> MigrationHello {
+        MigrationHello {
+            version: MIGRATION_VERSION,
+            openvmm_version: "test-0.1.0".to_string(),
+            memory_size_bytes: 3 * MEMORY_CHUNK_SIZE as u64,
+            vp_count: 2,
+            page_size: 4096,
+            architecture: "x86_64".to_string(),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let size = 3 * MEMORY_CHUNK_SIZE;
+        let source_path = dir.path().join("source");
+        let mut contents = (0..size).map(|i| i as u8).collect::<Vec<_>>();
+        std::fs::write(&source_path, &contents).unwrap();
+
+        let (source_stream, dest_stream) = UnixStream::pair().unwrap();
+        let dest = std::thread::spawn({
+            let dest_path = dir.path().join("dest");
+            move || {
+                let mut incoming = IncomingMigration::new(dest_stream).unwrap();
+                assert_eq!(incoming.hello().vp_count, 2);
+                let mut memory = fs_err::File::create(&dest_path).unwrap();
+                memory.set_len(size as u64).unwrap();
+                let state = incoming.receive(&mut memory).unwrap();
+                incoming.complete(Ok(())).unwrap();
+                (std::fs::read(&dest_path).unwrap(), state)
+            }
+        });
+
+        let mut sender = MigrationSender::new(source_stream, test_hello()).unwrap();
+        let mut source = fs_err::File::open(&source_path).unwrap();
+        sender
+            .send_memory(&mut source, &[(0, size as u64)])
+            .unwrap();
+
+        // Dirty a range spanning a chunk boundary and send it again.
+        let dirty = MEMORY_CHUNK_SIZE - 4096..MEMORY_CHUNK_SIZE + 4096;
+        contents[dirty.clone()].fill(0xcc);
+        std::fs::write(&source_path, &contents).unwrap();
+        sender
+            .send_memory(&mut source, &[(dirty.start as u64, dirty.len() as u64)])
+            .unwrap();
+        sender.finish(b"device-state".to_vec()).unwrap();
+
+        let (memory, state) = dest.join().unwrap();
+        assert!(memory == contents);
+        assert_eq!(state, b"device-state");
+    }
+
+    #[test]
+    fn destination_failure() {
+        let (source_stream, dest_stream) = UnixStream::pair().unwrap();
+        let dest = std::thread::spawn(move || {
+            let mut incoming = IncomingMigration::new(dest_stream).unwrap();
+            let dir = tempfile::tempdir().unwrap();
+            let mut memory = fs_err::File::create(dir.path().join("dest")).unwrap();
+            incoming.receive(&mut memory).unwrap();
+            incoming
+                .complete(Err("restore failed".to_string()))
+                .unwrap();
+        });
+
+        let sender = MigrationSender::new(source_stream, test_hello()).unwrap();
+        let err = sender.finish(Vec::new()).unwrap_err();
+        assert!(
+            err.to_string().contains("restore failed"),
+            "unexpected error: {err}"
+        );
+        dest.join().unwrap();
+    }
+
+    #[test]
+    fn validate_hello_mismatch() {
+        let hello = test_hello();
+        let memory = hello.memory_size_bytes;
+        validate_hello(&hello, "x86_64", memory, 2, 4096).unwrap();
+        assert!(validate_hello(&hello, "aarch64", memory, 2, 4096).is_err());
+        assert!(validate_hello(&hello, "x86_64", memory * 2, 2, 4096).is_err());
+        assert!(validate_hello(&hello, "x86_64", memory, 4, 4096).is_err());
+        assert!(validate_hello(&hello, "x86_64", memory, 2, 65536).is_err());
+    }
+
+    #[test]
+    fn stale_socket_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("socket");
+        drop(UnixListener::bind(&socket_path).unwrap());
+        remove_stale_socket(&socket_path);
+        assert!(!socket_path.exists());
+        UnixListener::bind(&socket_path).unwrap();
+
+        // Other files are left alone.
+        let file_path = dir.path().join("file");
+        std::fs::write(&file_path, b"data").unwrap();
+        remove_stale_socket(&file_path);
+        assert!(file_path.exists());
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let (source_stream, dest_stream) = UnixStream::pair().unwrap();
+        let hello = MigrationHello {
+            version: 999,
+            ..test_hello()
+        };
+        let _sender = MigrationSender::new(source_stream, hello).unwrap();
+        let err = IncomingMigration::new(dest_stream).err().unwrap();
+        assert!(
+            err.to_string().contains("version"),
+            "unexpected error: {err}"
+        );
+    }
+}
@@ -103,6 +103,42 @@ pub fn write_snapshot(
     Ok(())
 }
 
+/// Write a snapshot to the given directory by copying guest RAM out of the
+/// memory backing file.
+///
+/// Unlike [`write_snapshot`], the snapshot does not share storage with the
+/// backing file, so the VM may keep running once this returns.
+pub fn write_live_snapshot(
+    dir: &Path,
+    manifest: &SnapshotManifest,
+    saved_state_bytes: &[u8],
+    memory_file_path: &Path,
+) -> anyhow::Result<()> {
+    fs_err::create_dir_all(dir)?;
+    let memory_bin_path = dir.join("memory.bin");
+    if memory_bin_path.exists()
+        && fs_err::canonicalize(&memory_bin_path)? == fs_err::canonicalize(memory_file_path)?
+    {
+        anyhow::bail!(
+            "the memory backing file cannot be the memory.bin of a live snapshot ({})",
+            dir.display()
+        );
+    }
+    // Let the filesystem clone or copy the file in kernel where it can.
+    fs_err::copy(memory_file_path, &memory_bin_path)?;
+    fs_err::OpenOptions::new()
+        .write(true)
+        .open(&memory_bin_path)?
+        .sync_all()?;
+
+    // Write the manifest after the memory so that a partially written snapshot
+    // is not mistaken for a complete one.
+    fs_err::write(dir.join("state.bin"), saved_state_bytes)?;
+    let manifest_bytes = mesh::payload::encode(manifest.clone());
+    fs_err::write(dir.join("manifest.bin"), &manifest_bytes)?;
+    Ok(())
+}
+
 /// Read a snapshot from the given directory.
 ///
 /// Returns the decoded manifest and the raw saved-state bytes.
@@ -305,6 +341,25 @@ fn validate_manifest_wrong_page_size() {
         );
     }
 
+    #[test]
+    fn live_snapshot_copies_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let snap_dir = dir.path().join("snap");
+        let mem_path = dir.path().join("ram");
+        std::fs::write(&mem_path, vec![0xaa; 1024]).unwrap();
+
+        write_live_snapshot(&snap_dir, &test_manifest(), b"state", &mem_path).unwrap();
+
+        // The live snapshot must not share storage with the backing file.
+        std::fs::write(&mem_path, vec![0xbb; 1024]).unwrap();
+        assert_eq!(
+            std::fs::read(snap_dir.join("memory.bin")).unwrap(),
+            vec![0xaa; 1024]
+        );
+        let (_, state) = read_snapshot(&snap_dir).unwrap();
+        assert_eq!(state, b"state");
+    }
+
     #[test]
     fn validate_manifest_wrong_version() {
         let mut manifest = test_manifest();
@@ -42,6 +42,7 @@
 
     // Network backends
     net_backend::null::NullResolver,
+    net_packet_capture::resolver::PacketCaptureResolver,
     #[cfg(feature = "net_consomme")]
     net_consomme::resolver::ConsommeResolver,
     #[cfg(all(feature = "net_tap", target_os = "linux"))]
@@ -58,7 +59,10 @@
     disk_blockdevice::resolver::StaticBlockDeviceResolver,
     disk_prwrap::DiskWithReservationsResolver,
     disk_delay::resolver::DelayDiskResolver,
+    disk_qcow2::resolver::Qcow2DiskResolver,
     disk_vhd1::Vhd1Resolver,
+    disk_vhd1::DynamicVhd1Resolver,
+    disk_vhdx::resolver::VhdxDiskResolver,
     #[cfg(windows)]
     disk_vhdmp::VhdmpDiskResolver,
     #[cfg(feature = "disk_blob")]
@@ -79,8 +83,11 @@
     scsidisk::resolver::SimpleScsiResolver,
 
     // Virtio devices
+    virtio_balloon::resolver::VirtioBalloonResolver,
     virtio_blk::resolver::VirtioBlkResolver,
     virtio_console::resolver::VirtioConsoleResolver,
+    virtio_gpu::resolver::VirtioGpuResolver,
+    virtio_input::resolver::VirtioInputResolver,
     #[cfg(any(windows, target_os = "linux"))]
     virtiofs::resolver::VirtioFsResolver,
     #[cfg(any(windows, target_os = "linux"))]
@@ -88,6 +95,7 @@
     virtio_net::resolver::VirtioNetResolver,
     virtio_pmem::resolver::VirtioPmemResolver,
     virtio_rng::resolver::VirtioRngResolver,
+    virtio_scsi::resolver::VirtioScsiResolver,
     #[cfg(target_os = "linux")]
     vhost_user_frontend::resolver::VhostUserFrontendResolver,
     virtio_vsock::resolver::VirtioVsockResolver,
@@ -12,5 +12,6 @@
 // automated tools do not remove them.
 use mesh_rpc as _;
 use prost as _;
+use prost_types as _;
 
 include!(concat!(env!("OUT_DIR"), "/vmservice.rs"));
@@ -4,9 +4,9 @@
 //! openvmm_vhost: a vhost-user backend binary that hosts OpenVMM virtio
 //! devices over a Unix domain socket.
 //!
-//! This is primarily a test vehicle at this time, but it could be extended to
-//! support all OpenVMM virtio devices for use in production scenarios in the
-//! future.
+//! This runs OpenVMM's virtio-blk, virtio-net, virtio-fs, virtio-vsock and
+//! virtio-rng devices out-of-process, as vhost-user backends for any
+//! vhost-user capable VMM.
 //!
 //! This binary is Linux-only (vhost-user requires Unix domain sockets with
 //! SCM_RIGHTS fd passing).
@@ -33,20 +33,37 @@ mod linux {
     use clap::Parser;
     use clap::Subcommand;
     use disk_backend_resources::FileDiskHandle;
+    use net_backend_resources::consomme::ConsommeHandle;
+    use net_backend_resources::mac_address::MacAddress;
+    use net_backend_resources::tap::TapHandle;
     use pal_async::DefaultPool;
+    use std::path::Path;
     use std::path::PathBuf;
     use vhost_user_backend::VhostUserDeviceServer;
     use virtio::resolve::VirtioResolveInput;
     use virtio_resources::blk::VirtioBlkHandle;
+    use virtio_resources::fs::VirtioFsBackend;
+    use virtio_resources::fs::VirtioFsHandle;
+    use virtio_resources::net::VirtioNetHandle;
+    use virtio_resources::rng::VirtioRngHandle;
+    use virtio_resources::vsock::VirtioVsockHandle;
+    use vm_resource::IntoResource;
     use vm_resource::Resource;
     use vm_resource::ResourceResolver;
+    use vm_resource::kind::VirtioDeviceHandle;
     use vmcore::vm_task::SingleDriverBackend;
     use vmcore::vm_task::VmTaskDriverSource;
 
     // Register the resolvers needed by this binary.
     vm_resource::register_static_resolvers! {
         virtio_blk::resolver::VirtioBlkResolver,
+        virtio_net::resolver::VirtioNetResolver,
+        virtio_rng::resolver::VirtioRngResolver,
+        virtio_vsock::resolver::VirtioVsockResolver,
+        virtiofs::resolver::VirtioFsResolver,
         disk_file::FileDiskResolver,
+        net_consomme::resolver::ConsommeResolver,
+        net_tap::resolver::TapResolver,
     }
 
     /// openvmm_vhost: vhost-user backend for OpenVMM virtio devices.
@@ -74,6 +91,135 @@ enum DeviceCommand {
             #[arg(long, default_value_t = false)]
             read_only: bool,
         },
+        /// Expose a virtio-net device.
+        ///
+        /// Uses a consomme (user-mode NAT) endpoint unless `--tap` is given.
+        Net {
+            /// Attach to the named TAP interface instead of consomme.
+            #[arg(long, conflicts_with = "cidr")]
+            tap: Option<String>,
+
+            /// The consomme network CIDR (e.g. 10.0.0.0/24).
+            #[arg(long)]
+            cidr: Option<String>,
+
+            /// The MAC address. Defaults to a random address.
+            #[arg(long)]
+            mac: Option<MacAddress>,
+
+            /// Maximum number of queue pairs.
+            #[arg(long)]
+            max_queues: Option<u16>,
+        },
+        /// Expose a virtio-fs device sharing a host directory.
+        Fs {
+            /// The mount tag the guest uses to identify the file system.
+            #[arg(long)]
+            tag: String,
+
+            /// Path to the host directory to share.
+            #[arg(long)]
+            path: String,
+
+            /// Mount options, e.g. "uid=1000;gid=1000".
+            #[arg(long, default_value = "")]
+            options: String,
+        },
+        /// Expose a virtio-vsock device that relays guest connections over
+        /// hybrid vsock Unix sockets.
+        Vsock {
+            /// Base path for the hybrid vsock Unix sockets. Host-initiated
+            /// connections are accepted on this path; guest-initiated
+            /// connections to port N are relayed to `<path>_N`.
+            #[arg(long)]
+            path: String,
+
+            /// The guest CID.
+            #[arg(long, default_value_t = 3)]
+            guest_cid: u64,
+        },
+        /// Expose a virtio-rng device.
+        Rng,
+    }
+
+    /// Builds the device resource for the given command.
+    fn device_resource(device: &DeviceCommand) -> anyhow::Result<Resource<VirtioDeviceHandle>> {
+        let resource = match device {
+            DeviceCommand::Blk { disk, read_only } => {
+                let file = std::fs::OpenOptions::new()
+                    .read(true)
+                    .write(!read_only)
+                    .open(disk)
+                    .with_context(|| format!("failed to open disk: {}", disk.display()))?;
+
+                VirtioBlkHandle {
+                    disk: Resource::new(FileDiskHandle(file)),
+                    read_only: *read_only,
+                }
+                .into_resource()
+            }
+            DeviceCommand::Net {
+                tap,
+                cidr,
+                mac,
+                max_queues,
+            } => {
+                let endpoint = if let Some(name) = tap {
+                    let fd = net_tap::tap::open_tap(name)
+                        .with_context(|| format!("failed to open TAP device '{name}'"))?;
+                    TapHandle { fd }.into_resource()
+                } else {
+                    ConsommeHandle {
+                        cidr: cidr.clone(),
+                        ports: Vec::new(),
+                    }
+                    .into_resource()
+                };
+                let mac_address = mac.unwrap_or_else(|| {
+                    let mut mac_address = [0x00, 0x15, 0x5D, 0, 0, 0];
+                    getrandom::fill(&mut mac_address[3..]).expect("rng failure");
+                    mac_address.into()
+                });
+                VirtioNetHandle {
+                    max_queues: *max_queues,
+                    mac_address,
+                    endpoint,
+                }
+                .into_resource()
+            }
+            DeviceCommand::Fs { tag, path, options } => VirtioFsHandle {
+                tag: tag.clone(),
+                fs: VirtioFsBackend::HostFs {
+                    root_path: path.clone(),
+                    mount_options: options.clone(),
+                },
+            }
+            .into_resource(),
+            DeviceCommand::Vsock { path, guest_cid } => {
+                cleanup_socket(Path::new(path));
+                let listener = unix_socket::UnixListener::bind(path)
+                    .with_context(|| format!("failed to bind to hybrid vsock path: {path}"))?;
+                VirtioVsockHandle {
+                    guest_cid: *guest_cid,
+                    base_path: path.clone(),
+                    listener,
+                }
+                .into_resource()
+            }
+            DeviceCommand::Rng => VirtioRngHandle.into_resource(),
+        };
+        Ok(resource)
+    }
+
+    /// Removes a Unix socket left at `path` by an earlier run. Any other kind
+    /// of file is left in place, so that binding to it fails.
+    fn cleanup_socket(path: &Path) {
+        let is_socket = path
+            .metadata()
+            .is_ok_and(|meta| std::os::unix::fs::FileTypeExt::is_socket(&meta.file_type()));
+        if is_socket {
+            let _ = std::fs::remove_file(path);
+        }
     }
 
     pub fn main() -> anyhow::Result<()> {
@@ -91,32 +237,18 @@ pub fn main() -> anyhow::Result<()> {
             let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
             let resolver = ResourceResolver::new();
 
-            let server = match &cli.device {
-                DeviceCommand::Blk { disk, read_only } => {
-                    let file = std::fs::OpenOptions::new()
-                        .read(true)
-                        .write(!read_only)
-                        .open(disk)
-                        .with_context(|| format!("failed to open disk: {}", disk.display()))?;
-
-                    let virtio_handle = VirtioBlkHandle {
-                        disk: Resource::new(FileDiskHandle(file)),
-                        read_only: *read_only,
-                    };
-
-                    let resolved = resolver
-                        .resolve(
-                            Resource::new(virtio_handle),
-                            VirtioResolveInput {
-                                driver_source: &driver_source,
-                            },
-                        )
-                        .await
-                        .context("failed to resolve virtio-blk device")?;
-
-                    VhostUserDeviceServer::new(resolved.0)
-                }
-            };
+            let resource = device_resource(&cli.device)?;
+            let resolved = resolver
+                .resolve(
+                    resource,
+                    VirtioResolveInput {
+                        driver_source: &driver_source,
+                    },
+                )
+                .await
+                .context("failed to resolve virtio device")?;
+
+            let server = VhostUserDeviceServer::new(resolved.0);
 
             server
                 .run(&driver, &cli.socket)
@@ -15,6 +15,7 @@
 
 pub mod memory;
 pub mod queue_setup;
+pub mod shmem;
 
 /// Re-export protocol types from the shared crate.
 pub use vhost_user_protocol::protocol;
@@ -25,22 +26,28 @@
 use crate::memory::build_guest_memory;
 use crate::protocol::*;
 use crate::queue_setup::QueueSetup;
+use crate::shmem::FrontendShmemRegion;
+use crate::socket::BlockingVhostUserSocket;
 use crate::socket::SocketError;
 use crate::socket::VhostUserSocket;
 use anyhow::Context as _;
 use guestmem::GuestMemory;
+use guestmem::MappedMemoryRegion;
 use pal_async::driver::SpawnDriver;
 use pal_async::socket::PolledSocket;
 use pal_event::Event;
 use std::os::fd::OwnedFd;
 use std::path::Path;
+use std::sync::Arc;
 use unix_socket::UnixListener;
+use unix_socket::UnixStream;
 use virtio::DeviceTraits;
 use virtio::DynVirtioDevice;
 use virtio::queue::QueueState;
 use virtio::spec::VirtioDeviceFeatures;
 use vmcore::interrupt::Interrupt;
 use zerocopy::FromBytes;
+use zerocopy::FromZeros;
 use zerocopy::IntoBytes;
 
 /// vhost-user backend device server.
@@ -188,11 +195,16 @@ async fn dispatch_message(
             }
 
             VhostUserRequestCode::GET_PROTOCOL_FEATURES => {
+                // Shared memory regions are mapped by the frontend on the
+                // backend's behalf, so they need the backend request channel.
+                let has_shmem = traits.shared_memory.size != 0;
                 let pf = VhostUserProtocolFeatures::new()
                     .with_mq(true)
                     .with_reply_ack(true)
                     .with_config(true)
-                    .with_reset_device(true);
+                    .with_reset_device(true)
+                    .with_backend_req(has_shmem)
+                    .with_shmem(has_shmem);
                 let reply_payload = VhostUserU64Msg {
                     value: pf.into_bits(),
                 };
@@ -220,6 +232,34 @@ async fn dispatch_message(
                 maybe_ack(socket, hdr, state).await?;
             }
 
+            VhostUserRequestCode::SET_BACKEND_REQ_FD => {
+                let fd = fds
+                    .into_iter()
+                    .next()
+                    .context("SET_BACKEND_REQ_FD: missing fd")?;
+                let stream = UnixStream::from(fd);
+                // The channel is driven synchronously from the device's
+                // mapping callbacks.
+                stream
+                    .set_nonblocking(false)
+                    .context("SET_BACKEND_REQ_FD: failed to set blocking mode")?;
+                let channel = BlockingVhostUserSocket::new(stream)
+                    .context("SET_BACKEND_REQ_FD: failed to set timeouts")?;
+                state.backend_channel = Some(Arc::new(channel));
+                self.set_shared_memory_region(state, traits)?;
+                maybe_ack(socket, hdr, state).await?;
+            }
+
+            VhostUserRequestCode::GET_SHMEM_CONFIG => {
+                let mut config = VhostUserShmemConfig::new_zeroed();
+                if state.protocol_features.shmem() && traits.shared_memory.size != 0 {
+                    let id = traits.shared_memory.id;
+                    config.nregions = id as u32 + 1;
+                    config.memory_sizes[id as usize] = traits.shared_memory.size;
+                }
+                send_reply(socket, hdr, config.as_bytes(), &[]).await?;
+            }
+
             VhostUserRequestCode::SET_MEM_TABLE => {
                 self.handle_set_mem_table(state, payload, fds).await?;
                 maybe_ack(socket, hdr, state).await?;
@@ -477,6 +517,8 @@ async fn dispatch_message(
                 self.stop_all_queues().await;
                 self.device.reset().await;
                 state.reset(&self.device.traits());
+                // Devices drop their shared memory region on reset.
+                self.set_shared_memory_region(state, traits)?;
                 maybe_ack(socket, hdr, state).await?;
             }
 
@@ -492,6 +534,30 @@ async fn dispatch_message(
         Ok(())
     }
 
+    /// Provide the device with its shared memory region, if it has one and
+    /// the frontend has negotiated the means to map it.
+    fn set_shared_memory_region(
+        &mut self,
+        state: &ConnectionState,
+        traits: &DeviceTraits,
+    ) -> anyhow::Result<()> {
+        if traits.shared_memory.size == 0 || !state.protocol_features.shmem() {
+            return Ok(());
+        }
+        let Some(channel) = &state.backend_channel else {
+            return Ok(());
+        };
+        let region: Arc<dyn MappedMemoryRegion> = Arc::new(FrontendShmemRegion::new(
+            channel.clone(),
+            traits.shared_memory.id,
+            traits.shared_memory.size,
+            state.protocol_features.reply_ack(),
+        ));
+        self.device
+            .set_shared_memory_region(&region)
+            .context("failed to set shared memory region")
+    }
+
     /// Handle SET_MEM_TABLE: build new guest memory, stop/restart queues.
     async fn handle_set_mem_table(
         &mut self,
@@ -623,6 +689,8 @@ struct ConnectionState {
     negotiated_features: VirtioDeviceFeatures,
     protocol_features: VhostUserProtocolFeatures,
     queues: Vec<QueueSetup>,
+    /// The backend-to-frontend request channel from SET_BACKEND_REQ_FD.
+    backend_channel: Option<Arc<BlockingVhostUserSocket>>,
 }
 
 impl ConnectionState {
@@ -635,16 +703,19 @@ fn new(traits: &DeviceTraits) -> Self {
             negotiated_features: VirtioDeviceFeatures::new(),
             protocol_features: VhostUserProtocolFeatures::default(),
             queues,
+            backend_channel: None,
         }
     }
 
-    /// Reset device-level state. Connection-level state (protocol_features)
-    /// is preserved — it was negotiated for the connection, not the device.
+    /// Reset device-level state. Connection-level state (protocol_features,
+    /// backend_channel) is preserved — it belongs to the connection, not the
+    /// device.
     fn reset(&mut self, traits: &DeviceTraits) {
         let Self {
             negotiated_features,
             protocol_features: _, // connection-level, not reset
             queues,
+            backend_channel: _, // connection-level, not reset
         } = self;
         *negotiated_features = VirtioDeviceFeatures::new();
         queues.clear();
@@ -1190,4 +1261,54 @@ async fn reset(&mut self) {
             "expected start→stop→start sequence for queue restart. log: {log:?}"
         );
     }
+
+    #[async_test]
+    async fn test_shmem_config(driver: DefaultDriver) {
+        let (frontend_stream, backend_stream) = socket_pair();
+        let backend_socket =
+            VhostUserSocket::new(PolledSocket::new(&driver, backend_stream).unwrap());
+        let frontend = VhostUserSocket::new(PolledSocket::new(&driver, frontend_stream).unwrap());
+
+        let mut device = MockDevice::new();
+        device.traits.shared_memory = DeviceTraitsSharedMemory {
+            id: 1,
+            size: 0x100_0000,
+        };
+        let server = VhostUserDeviceServer::new(Box::new(device));
+
+        let frontend_task = async {
+            let reply =
+                send_and_recv(&frontend, VhostUserRequestCode::GET_PROTOCOL_FEATURES, &[]).await;
+            let pf = VhostUserProtocolFeatures::from_bits(
+                VhostUserU64Msg::read_from_bytes(&reply).unwrap().value,
+            );
+            assert!(pf.shmem());
+            assert!(pf.backend_req());
+
+            let set_pf = VhostUserU64Msg {
+                value: VhostUserProtocolFeatures::new()
+                    .with_shmem(true)
+                    .into_bits(),
+            };
+            send_msg(
+                &frontend,
+                VhostUserRequestCode::SET_PROTOCOL_FEATURES,
+                set_pf.as_bytes(),
+                &[] as &[OwnedFd],
+            )
+            .await;
+
+            let reply = send_and_recv(&frontend, VhostUserRequestCode::GET_SHMEM_CONFIG, &[]).await;
+            let config = VhostUserShmemConfig::read_from_bytes(&reply).unwrap();
+            assert_eq!(config.nregions, 2);
+            assert_eq!(config.memory_sizes[0], 0);
+            assert_eq!(config.memory_sizes[1], 0x100_0000);
+
+            drop(frontend);
+        };
+
+        let (server_result, ()) =
+            futures::join!(server.serve_connection(backend_socket), frontend_task);
+        server_result.expect("server should exit cleanly");
+    }
 }
@@ -0,0 +1,217 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Shared memory regions for vhost-user devices.
+//!
+//! A device's shared memory region (e.g. the virtio-fs DAX window) lives in
+//! the frontend's address space, not the backend's. The backend asks the
+//! frontend to map and unmap file ranges into the region by sending
+//! `SHMEM_MAP` and `SHMEM_UNMAP` requests on the backend request channel
+//! established by `SET_BACKEND_REQ_FD`.
+
+#![cfg(unix)]
+
+use crate::protocol::VHOST_USER_FLAG_MAP_RW;
+use crate::protocol::VHOST_USER_FLAG_NEED_REPLY;
+use crate::protocol::VHOST_USER_FLAG_VERSION;
+use crate::protocol::VhostUserBackendRequestCode;
+use crate::protocol::VhostUserMMap;
+use crate::protocol::VhostUserMsgHeader;
+use crate::socket::BlockingVhostUserSocket;
+use crate::socket::SocketError;
+use guestmem::MappedMemoryRegion;
+use sparse_mmap::AsMappableRef;
+use std::io;
+use std::os::fd::BorrowedFd;
+use std::sync::Arc;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+/// A [`MappedMemoryRegion`] that forwards mappings to the vhost-user
+/// frontend over the backend request channel.
+pub struct FrontendShmemRegion {
+    channel: Arc<BlockingVhostUserSocket>,
+    shmid: u8,
+    size: u64,
+    /// Whether the frontend negotiated REPLY_ACK, so that requests can wait
+    /// for the mapping to be established before returning.
+    reply_ack: bool,
+}
+
+impl FrontendShmemRegion {
+    /// Create a region for shared memory region `shmid` of `size` bytes.
+    pub fn new(
+        channel: Arc<BlockingVhostUserSocket>,
+        shmid: u8,
+        size: u64,
+        reply_ack: bool,
+    ) -> Self {
+        Self {
+            channel,
+            shmid,
+            size,
+            reply_ack,
+        }
+    }
+
+    fn check_range(&self, offset: usize, len: usize) -> io::Result<()> {
+        let end = (offset as u64).checked_add(len as u64);
+        if end.is_none_or(|end| end > self.size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "range {offset:#x}+{len:#x} exceeds shared memory region size {:#x}",
+                    self.size
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn request(
+        &self,
+        code: VhostUserBackendRequestCode,
+        msg: &VhostUserMMap,
+        fds: &[BorrowedFd<'_>],
+    ) -> io::Result<()> {
+        let mut flags = VHOST_USER_FLAG_VERSION;
+        if self.reply_ack {
+            flags |= VHOST_USER_FLAG_NEED_REPLY;
+        }
+        let hdr = VhostUserMsgHeader {
+            request: code.0,
+            flags,
+            size: size_of::<VhostUserMMap>() as u32,
+        };
+        let status = self
+            .channel
+            .request(&hdr, msg.as_bytes(), fds)
+            .map_err(|err| match err {
+                SocketError::Io(err) => err,
+                err => io::Error::other(err),
+            })?;
+        match status {
+            Some(0) | None => Ok(()),
+            Some(status) => Err(io::Error::other(format!(
+                "frontend failed {code:?} request: {status:#x}"
+            ))),
+        }
+    }
+}
+
+impl MappedMemoryRegion for FrontendShmemRegion {
+    fn map(
+        &self,
+        offset: usize,
+        section: &dyn AsMappableRef,
+        file_offset: u64,
+        len: usize,
+        writable: bool,
+    ) -> io::Result<()> {
+        self.check_range(offset, len)?;
+        let msg = VhostUserMMap {
+            shmid: self.shmid,
+            fd_offset: file_offset,
+            shm_offset: offset as u64,
+            len: len as u64,
+            flags: if writable { VHOST_USER_FLAG_MAP_RW } else { 0 },
+            ..FromZeros::new_zeroed()
+        };
+        self.request(
+            VhostUserBackendRequestCode::SHMEM_MAP,
+            &msg,
+            &[section.as_fd()],
+        )
+    }
+
+    fn unmap(&self, offset: usize, len: usize) -> io::Result<()> {
+        self.check_range(offset, len)?;
+        let msg = VhostUserMMap {
+            shmid: self.shmid,
+            shm_offset: offset as u64,
+            len: len as u64,
+            ..FromZeros::new_zeroed()
+        };
+        self.request(VhostUserBackendRequestCode::SHMEM_UNMAP, &msg, &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::VHOST_USER_FLAG_REPLY;
+    use crate::protocol::VhostUserU64Msg;
+    use std::io::Read;
+    use std::io::Write;
+    use std::time::Duration;
+    use unix_socket::UnixStream;
+    use zerocopy::FromBytes;
+
+    fn read_request(peer: &mut UnixStream) -> (VhostUserMsgHeader, VhostUserMMap) {
+        let mut buf = [0u8; size_of::<VhostUserMsgHeader>() + size_of::<VhostUserMMap>()];
+        peer.read_exact(&mut buf).unwrap();
+        let (hdr, rest) = VhostUserMsgHeader::read_from_prefix(&buf).unwrap();
+        (hdr, VhostUserMMap::read_from_bytes(rest).unwrap())
+    }
+
+    #[test]
+    fn unmap_sends_request_and_waits_for_ack() {
+        let (ours, mut peer) = UnixStream::pair().unwrap();
+        let region = FrontendShmemRegion::new(
+            Arc::new(BlockingVhostUserSocket::new(ours).unwrap()),
+            1,
+            0x10000,
+            true,
+        );
+
+        let frontend = std::thread::spawn(move || {
+            let (hdr, msg) = read_request(&mut peer);
+            assert_eq!(hdr.request, VhostUserBackendRequestCode::SHMEM_UNMAP.0);
+            assert!(hdr.need_reply());
+            assert_eq!(msg.shmid, 1);
+            assert_eq!(msg.shm_offset, 0x1000);
+            assert_eq!(msg.len, 0x2000);
+            let reply = VhostUserMsgHeader {
+                request: hdr.request,
+                flags: VHOST_USER_FLAG_VERSION | VHOST_USER_FLAG_REPLY,
+                size: 8,
+            };
+            peer.write_all(reply.as_bytes()).unwrap();
+            peer.write_all(VhostUserU64Msg { value: 0 }.as_bytes())
+                .unwrap();
+        });
+
+        region.unmap(0x1000, 0x2000).unwrap();
+        frontend.join().unwrap();
+    }
+
+    #[test]
+    fn unresponsive_frontend_times_out() {
+        let (ours, mut peer) = UnixStream::pair().unwrap();
+        let region = FrontendShmemRegion::new(
+            Arc::new(
+                BlockingVhostUserSocket::with_timeout(ours, Duration::from_millis(100)).unwrap(),
+            ),
+            1,
+            0x10000,
+            true,
+        );
+        let err = region.unmap(0x1000, 0x2000).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        // The request was sent; only the reply is missing.
+        read_request(&mut peer);
+    }
+
+    #[test]
+    fn out_of_range_rejected() {
+        let (ours, _peer) = UnixStream::pair().unwrap();
+        let region = FrontendShmemRegion::new(
+            Arc::new(BlockingVhostUserSocket::new(ours).unwrap()),
+            0,
+            0x1000,
+            false,
+        );
+        let err = region.unmap(0x800, 0x1000).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
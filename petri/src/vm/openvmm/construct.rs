@@ -398,6 +398,9 @@ pub async fn new(
                 private_memory: false,
                 transparent_hugepages: false,
                 numa_mem_sizes,
+                numa_distances: None,
+                numa_latencies: None,
+                numa_bandwidths: None,
             }
         };
 
@@ -251,6 +251,7 @@ fn memdiff_remote_disk(url: &str) -> anyhow::Result<Resource<DiskHandleKind>> {
             DiskLayerDescription {
                 read_cache: true,
                 write_through: false,
+                writable: false,
                 layer: SqliteAutoCacheDiskLayerHandle {
                     cache_path: cache_dir,
                     cache_key,
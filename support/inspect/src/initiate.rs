@@ -284,6 +284,61 @@ pub fn since(&self, last: &Node, duration: Duration) -> Self {
         self.compute_since(last, duration.as_secs_f64())
     }
 
+    fn compute_changes(&self, last: &Node, t: f64) -> Option<Node> {
+        match (self, last) {
+            (Node::Value(value), Node::Value(last_value)) => {
+                if value == last_value {
+                    // An unchanged counter has a rate of zero, which is not
+                    // interesting.
+                    None
+                } else {
+                    Some(self.compute_since(last, t))
+                }
+            }
+            (Node::Dir(this), Node::Dir(last)) => {
+                let mut children = Vec::new();
+                let mut this = this.iter().peekable();
+                let mut last = last.iter().peekable();
+                while let (Some(&this_entry), Some(&last_entry)) = (this.peek(), last.peek()) {
+                    match this_entry.name.cmp(&last_entry.name) {
+                        Ordering::Less => {
+                            children.push(this_entry.clone());
+                            this.next();
+                        }
+                        Ordering::Equal => {
+                            if let Some(node) = this_entry.node.compute_changes(&last_entry.node, t)
+                            {
+                                children.push(Entry {
+                                    node,
+                                    ..this_entry.clone()
+                                });
+                            }
+                            this.next();
+                            last.next();
+                        }
+                        Ordering::Greater => {
+                            last.next();
+                        }
+                    }
+                }
+                children.extend(this.cloned());
+                (!children.is_empty()).then_some(Node::Dir(children))
+            }
+            (node, last) => (node != last).then(|| node.clone()),
+        }
+    }
+
+    /// Computes the changes in this node from a previous snapshot of the same
+    /// node, omitting anything that did not change.
+    ///
+    /// This is like [`Node::since`], except that unchanged values (including
+    /// counters that did not advance) and directories with no changed
+    /// descendants are removed from the result. Returns `None` if nothing
+    /// changed.
+    pub fn changes_since(&self, last: &Node, duration: Duration) -> Option<Self> {
+        self.compute_changes(last, duration.as_secs_f64())
+    }
+
     /// Returns an object that implements [`Display`](core::fmt::Display) to output JSON.
     pub fn json(&self) -> impl '_ + fmt::Display {
         JsonDisplay(self)
@@ -511,6 +566,38 @@ fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
     }
 }
 
+/// A subscription to an inspect path, which turns successive inspection
+/// results for the path into a stream of changes.
+///
+/// The caller is responsible for performing the inspections, typically at a
+/// fixed interval, and passing the results to [`Watch::next`].
+#[derive(Debug, Default)]
+pub struct Watch {
+    last: Option<Node>,
+}
+
+impl Watch {
+    /// Creates a new watch with no previous results.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `node`, the latest result of the watched inspection taken
+    /// `elapsed` after the previous one, and returns the changes since the
+    /// previous result, as computed by [`Node::changes_since`].
+    ///
+    /// The first call returns `node` in full. Returns `None` if nothing
+    /// changed.
+    pub fn next(&mut self, node: Node, elapsed: Duration) -> Option<Node> {
+        let changes = match &self.last {
+            Some(last) => node.changes_since(last, elapsed),
+            None => Some(node.clone()),
+        };
+        self.last = Some(node);
+        changes
+    }
+}
+
 impl InternalNode {
     fn poll_resolve(&mut self, cx: &mut Context<'_>) -> Poll<()> {
         loop {
@@ -2198,6 +2198,7 @@ mod tests {
     use crate::Request;
     use crate::SensitivityLevel;
     use crate::ValueKind;
+    use crate::Watch;
     use crate::adhoc;
     use crate::adhoc_mut;
     use crate::inspect;
@@ -2755,6 +2756,66 @@ fn test_since() {
         );
     }
 
+    #[test]
+    fn test_watch() {
+        let mut n = 500_u32;
+        let mut obj = adhoc_mut(|req| {
+            req.respond()
+                .counter("c", n)
+                .field("f", n / 350)
+                .child("d", |req| {
+                    req.respond().field("same", true).counter("idle", 0);
+                });
+            n += 100;
+        });
+        let mut watch = Watch::new();
+
+        let full = watch
+            .next(inspect_sync("", Some(1), &mut obj), Duration::ZERO)
+            .unwrap();
+        expected_node(
+            full,
+            expect!([r#"
+                {
+                    c: 500,
+                    d: {
+                        idle: 0,
+                        same: true,
+                    },
+                    f: 1,
+                }|{"c":500,"d":{"idle":0,"same":true},"f":1}"#]),
+        );
+
+        // The counter advanced but nothing else changed.
+        let changes = watch
+            .next(inspect_sync("", Some(1), &mut obj), Duration::from_secs(2))
+            .unwrap();
+        expected_node(
+            changes,
+            expect!([r#"
+                {
+                    c: 50,
+                }|{"c":50}"#]),
+        );
+
+        // Both the counter and the field changed.
+        let changes = watch
+            .next(inspect_sync("", Some(1), &mut obj), Duration::from_secs(1))
+            .unwrap();
+        expected_node(
+            changes,
+            expect!([r#"
+                {
+                    c: 100,
+                    f: 2,
+                }|{"c":100,"f":2}"#]),
+        );
+
+        let node = inspect_sync("", Some(1), &mut obj);
+        assert!(watch.next(node.clone(), Duration::ZERO).is_some());
+        assert!(watch.next(node, Duration::from_secs(1)).is_none());
+    }
+
     #[test]
     fn test_bytes() {
         inspect_sync_expect(
@@ -0,0 +1,383 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Encodes [`inspect`] results in the OpenMetrics text format, for scraping
+//! by Prometheus and compatible collectors.
+//!
+//! Each numeric or Boolean value in the inspect tree becomes a sample. The
+//! path to the value is mapped to a metric name, except for path components
+//! that look like instance identifiers (such as VP indices), which are
+//! instead mapped to labels named after the preceding component. So
+//! `vm/partition/vps/3/exits` becomes `prefix_vm_partition_vps_exits{vps="3"}`.
+//!
+//! Values flagged as counters (see [`inspect::Value::counter`]) are
+//! exported as OpenMetrics counters, and all other values as gauges. Rates
+//! for counters can additionally be exported from two snapshots of the same
+//! tree via [`Encoder::add_rates`], which uses [`Node::since`].
+
+#![forbid(unsafe_code)]
+
+use inspect::Node;
+use inspect::Value;
+use inspect::ValueKind;
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// The HTTP content type of the OpenMetrics text format.
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Accumulates inspect results and encodes them as OpenMetrics text.
+pub struct Encoder {
+    prefix: String,
+    families: BTreeMap<String, Family>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum MetricType {
+    Counter,
+    Gauge,
+}
+
+struct Family {
+    ty: MetricType,
+    samples: Vec<(Vec<(String, String)>, Sample)>,
+}
+
+#[derive(Copy, Clone)]
+enum Sample {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+}
+
+impl Sample {
+    fn from_value(value: &Value) -> Option<Self> {
+        let sample = match value.kind {
+            ValueKind::Unsigned(n) => Self::Unsigned(n),
+            ValueKind::Signed(n) => Self::Signed(n),
+            ValueKind::Float(n) => Self::Float(n.into()),
+            ValueKind::Double(n) => Self::Float(n),
+            ValueKind::Bool(b) => Self::Unsigned(b.into()),
+            ValueKind::String(_) | ValueKind::Bytes(_) => return None,
+        };
+        Some(sample)
+    }
+}
+
+impl std::fmt::Display for Sample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Sample::Unsigned(n) => write!(f, "{n}"),
+            Sample::Signed(n) => write!(f, "{n}"),
+            Sample::Float(n) if n.is_nan() => f.write_str("NaN"),
+            Sample::Float(n) if n == f64::INFINITY => f.write_str("+Inf"),
+            Sample::Float(n) if n == f64::NEG_INFINITY => f.write_str("-Inf"),
+            Sample::Float(n) => write!(f, "{n:?}"),
+        }
+    }
+}
+
+/// The position in the tree during a walk: the metric name components and
+/// the labels collected so far.
+#[derive(Clone, Default)]
+struct Location {
+    name: Vec<String>,
+    labels: Vec<(String, String)>,
+}
+
+impl Location {
+    fn new(path: &str) -> Self {
+        let mut this = Self::default();
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            this.push(component);
+        }
+        this
+    }
+
+    fn push(&mut self, component: &str) {
+        if is_label_value(component) {
+            let key = self.name.last().map_or("index", |s| s.as_str());
+            let mut key = key.to_owned();
+            // Keep label names unique if the same component repeats.
+            while self.labels.iter().any(|(k, _)| *k == key) {
+                key.push('_');
+            }
+            self.labels.push((key, component.to_owned()));
+        } else {
+            self.name.push(sanitize(component));
+        }
+    }
+}
+
+/// Returns whether a path component identifies an instance rather than
+/// naming a field: it starts with a digit or contains characters that are
+/// not valid in a metric name.
+fn is_label_value(component: &str) -> bool {
+    component.starts_with(|c: char| c.is_ascii_digit())
+        || !component
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn sanitize(component: &str) -> String {
+    component
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+impl Encoder {
+    /// Returns a new encoder that prefixes every metric name with `prefix`.
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: sanitize(prefix),
+            families: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the values in `node`, which is the result of inspecting `path`.
+    pub fn add(&mut self, path: &str, node: &Node) {
+        self.walk(Location::new(path), node, None, &mut |this, loc, value| {
+            let Some(sample) = Sample::from_value(value) else {
+                return;
+            };
+            let ty = if value.flags.count() {
+                MetricType::Counter
+            } else {
+                MetricType::Gauge
+            };
+            this.push(loc, "", ty, sample);
+        });
+    }
+
+    /// Adds the per-second rates of the counters in `node`, the result of
+    /// inspecting `path`, since `last`, a result of the same inspection taken
+    /// `duration` earlier.
+    ///
+    /// Rates are exported as gauges with a `_per_second` suffix. Counters
+    /// missing from `last` are skipped.
+    pub fn add_rates(&mut self, path: &str, node: &Node, last: &Node, duration: Duration) {
+        let rates = node.since(last, duration);
+        self.walk(
+            Location::new(path),
+            &rates,
+            Some(last),
+            &mut |this, loc, value| {
+                if !value.flags.count() {
+                    return;
+                }
+                let Some(sample) = Sample::from_value(value) else {
+                    return;
+                };
+                this.push(loc, "_per_second", MetricType::Gauge, sample);
+            },
+        );
+    }
+
+    /// Walks `node`, calling `f` for each value. If `last` is provided, only
+    /// values that are also present in `last` are visited.
+    fn walk(
+        &mut self,
+        loc: Location,
+        node: &Node,
+        last: Option<&Node>,
+        f: &mut impl FnMut(&mut Self, &Location, &Value),
+    ) {
+        match node {
+            Node::Value(value) => {
+                if last.is_none_or(|last| matches!(last, Node::Value(_))) {
+                    f(self, &loc, value);
+                }
+            }
+            Node::Dir(entries) => {
+                for entry in entries {
+                    let last_child = match last {
+                        Some(Node::Dir(last_entries)) => {
+                            match last_entries.iter().find(|e| e.name == entry.name) {
+                                Some(e) => Some(&e.node),
+                                None => continue,
+                            }
+                        }
+                        Some(_) => continue,
+                        None => None,
+                    };
+                    let mut child = loc.clone();
+                    for component in entry.name.split('/').filter(|c| !c.is_empty()) {
+                        child.push(component);
+                    }
+                    self.walk(child, &entry.node, last_child, f);
+                }
+            }
+            Node::Unevaluated | Node::Failed(_) => {}
+        }
+    }
+
+    fn push(&mut self, loc: &Location, suffix: &str, ty: MetricType, sample: Sample) {
+        let mut name = self.prefix.clone();
+        for component in &loc.name {
+            if !name.is_empty() {
+                name.push('_');
+            }
+            name.push_str(component);
+        }
+        name.push_str(suffix);
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            name.insert(0, '_');
+        }
+        let family = match self.families.entry(name) {
+            btree_map::Entry::Vacant(e) => e.insert(Family {
+                ty,
+                samples: Vec::new(),
+            }),
+            // A name can only have one type. The first one seen wins.
+            btree_map::Entry::Occupied(e) if e.get().ty != ty => return,
+            btree_map::Entry::Occupied(e) => e.into_mut(),
+        };
+        family.samples.push((loc.labels.clone(), sample));
+    }
+
+    /// Returns the encoded metrics, terminated by `# EOF`.
+    pub fn finish(self) -> String {
+        let mut out = String::new();
+        for (name, family) in &self.families {
+            let (ty, suffix) = match family.ty {
+                MetricType::Counter => ("counter", "_total"),
+                MetricType::Gauge => ("gauge", ""),
+            };
+            writeln!(out, "# TYPE {name} {ty}").unwrap();
+            for (labels, sample) in &family.samples {
+                out.push_str(name);
+                out.push_str(suffix);
+                if !labels.is_empty() {
+                    out.push('{');
+                    for (i, (key, value)) in labels.iter().enumerate() {
+                        if i > 0 {
+                            out.push(',');
+                        }
+                        write!(out, "{key}=\"").unwrap();
+                        escape_label_value(&mut out, value);
+                        out.push('"');
+                    }
+                    out.push('}');
+                }
+                writeln!(out, " {sample}").unwrap();
+            }
+        }
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+fn escape_label_value(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use inspect::Entry;
+    use inspect::SensitivityLevel;
+
+    fn dir(entries: impl IntoIterator<Item = (&'static str, Node)>) -> Node {
+        Node::Dir(
+            entries
+                .into_iter()
+                .map(|(name, node)| Entry {
+                    name: name.into(),
+                    node,
+                    sensitivity: SensitivityLevel::Unspecified,
+                })
+                .collect(),
+        )
+    }
+
+    fn counter(n: u64) -> Node {
+        Node::Value(Value::counter(n))
+    }
+
+    fn gauge(kind: impl Into<ValueKind>) -> Node {
+        Node::Value(Value::new(kind))
+    }
+
+    #[test]
+    fn names_labels_and_types() {
+        let node = dir([
+            (
+                "vps",
+                dir([
+                    ("0", dir([("exits", counter(5)), ("running", gauge(true))])),
+                    ("1", dir([("exits", counter(7)), ("running", gauge(false))])),
+                ]),
+            ),
+            ("name", gauge("ignored".to_owned())),
+            ("virtio-net", dir([("temp", gauge(-1.5f64))])),
+        ]);
+
+        let mut encoder = Encoder::new("openvmm");
+        encoder.add("vm/partition", &node);
+        assert_eq!(
+            encoder.finish(),
+            "\
+# TYPE openvmm_vm_partition_virtio_net_temp gauge
+openvmm_vm_partition_virtio_net_temp -1.5
+# TYPE openvmm_vm_partition_vps_exits counter
+openvmm_vm_partition_vps_exits_total{vps=\"0\"} 5
+openvmm_vm_partition_vps_exits_total{vps=\"1\"} 7
+# TYPE openvmm_vm_partition_vps_running gauge
+openvmm_vm_partition_vps_running{vps=\"0\"} 1
+openvmm_vm_partition_vps_running{vps=\"1\"} 0
+# EOF
+"
+        );
+    }
+
+    #[test]
+    fn label_escaping() {
+        let node = dir([("disks", dir([("a\"b\\c", dir([("size", gauge(1u64))]))]))]);
+        let mut encoder = Encoder::new("x");
+        encoder.add("", &node);
+        assert_eq!(
+            encoder.finish(),
+            "# TYPE x_disks_size gauge\nx_disks_size{disks=\"a\\\"b\\\\c\"} 1\n# EOF\n"
+        );
+    }
+
+    #[test]
+    fn rates() {
+        let last = dir([("exits", counter(10)), ("level", gauge(3u64))]);
+        let node = dir([
+            ("exits", counter(30)),
+            ("level", gauge(4u64)),
+            ("new", counter(100)),
+        ]);
+        let mut encoder = Encoder::new("vmm");
+        encoder.add_rates("", &node, &last, Duration::from_secs(2));
+        assert_eq!(
+            encoder.finish(),
+            "# TYPE vmm_exits_per_second gauge\nvmm_exits_per_second 10.0\n# EOF\n"
+        );
+    }
+
+    #[test]
+    fn conflicting_types() {
+        let node = dir([
+            ("a", dir([("0", dir([("n", counter(1))]))])),
+            ("a", dir([("1", dir([("n", gauge(2u64))]))])),
+        ]);
+        let mut encoder = Encoder::new("p");
+        encoder.add("", &node);
+        assert_eq!(
+            encoder.finish(),
+            "# TYPE p_a_n counter\np_a_n_total{a=\"0\"} 1\n# EOF\n"
+        );
+    }
+}
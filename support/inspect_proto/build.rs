@@ -23,7 +23,8 @@ fn main() {
                 // For easy of integration with inspect, use mesh types instead
                 // of prost types for inspect responses.
                 .replace_type("InspectResponse", "InspectResponse2")
-                .replace_type("UpdateResponse", "UpdateResponse2"),
+                .replace_type("UpdateResponse", "UpdateResponse2")
+                .replace_type("WatchResponse", "WatchResponse2"),
         ))
         .compile_protos(
             &["src/inspect_service.proto"],
@@ -15,6 +15,10 @@
 
 include!(concat!(env!("OUT_DIR"), "/inspect.rs"));
 
+mod watch;
+
+pub use watch::WatchTable;
+
 /// Equivalent to [`InspectResponse`], but using [`inspect::Node`].
 /// These have equivalent encodings.
 #[derive(Debug, Clone, mesh::MeshPayload)]
@@ -31,17 +35,34 @@ pub struct UpdateResponse2 {
     pub new_value: inspect::Value,
 }
 
+/// Equivalent to [`WatchResponse`], but using [`inspect::Node`].
+/// These have equivalent encodings.
+#[derive(Debug, Clone, mesh::MeshPayload)]
+pub struct WatchResponse2 {
+    #[mesh(1)]
+    pub watch_id: u64,
+    #[mesh(2)]
+    pub changes: Option<inspect::Node>,
+}
+
 #[cfg(test)]
 mod tests {
     use crate::InspectResponse;
     use crate::InspectResponse2;
+    use crate::WatchRequest;
+    use crate::WatchTable;
+    use futures::poll;
     use inspect::Entry;
     use inspect::Error;
     use inspect::Node;
     use inspect::SensitivityLevel;
     use inspect::Value;
     use inspect::ValueKind;
+    use mesh::CancelContext;
     use mesh::Message;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use std::pin::pin;
 
     #[test]
     fn test() {
@@ -92,4 +113,95 @@ fn test() {
             response2.result
         );
     }
+
+    #[async_test]
+    async fn watch(driver: DefaultDriver) {
+        let table = WatchTable::new();
+        let mut n = 0u64;
+        let mut obj = inspect::adhoc_mut(|req| {
+            req.respond().counter("c", n).field("f", 1);
+            n += 10;
+        });
+
+        let mut request = WatchRequest {
+            path: String::new(),
+            depth: 1,
+            interval_ms: 10,
+            watch_id: 0,
+        };
+        let response = table
+            .watch(&driver, CancelContext::new(), &request, |b| {
+                b.inspect(&mut obj)
+            })
+            .await
+            .unwrap();
+        assert_ne!(response.watch_id, 0);
+        let Some(Node::Dir(entries)) = response.changes else {
+            panic!("expected full results");
+        };
+        assert_eq!(entries.len(), 2);
+
+        // Only the counter changes.
+        request.watch_id = response.watch_id;
+        let response = table
+            .watch(&driver, CancelContext::new(), &request, |b| {
+                b.inspect(&mut obj)
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.watch_id, request.watch_id);
+        let Some(Node::Dir(entries)) = response.changes else {
+            panic!("expected changes");
+        };
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "c");
+
+        request.watch_id += 1;
+        table
+            .watch(&driver, CancelContext::new(), &request, |b| {
+                b.inspect(&mut obj)
+            })
+            .await
+            .unwrap_err();
+    }
+
+    #[async_test]
+    async fn watch_one_request_at_a_time(driver: DefaultDriver) {
+        let table = WatchTable::new();
+        let mut request = WatchRequest {
+            path: String::new(),
+            depth: 1,
+            interval_ms: 60000,
+            watch_id: 0,
+        };
+        let response = table
+            .watch(&driver, CancelContext::new(), &request, |b| {
+                b.inspect(inspect::adhoc(|_| {}))
+            })
+            .await
+            .unwrap();
+        request.watch_id = response.watch_id;
+
+        {
+            // The first request waits for the interval to elapse.
+            let first = pin!(table.watch(&driver, CancelContext::new(), &request, |b| {
+                b.inspect(inspect::adhoc(|_| {}))
+            }));
+            assert!(poll!(first).is_pending());
+
+            // A second concurrent request is rejected.
+            table
+                .watch(&driver, CancelContext::new(), &request, |b| {
+                    b.inspect(inspect::adhoc(|_| {}))
+                })
+                .await
+                .unwrap_err();
+        }
+
+        // Once the first request is dropped, the next one is accepted.
+        let next = pin!(table.watch(&driver, CancelContext::new(), &request, |b| {
+            b.inspect(inspect::adhoc(|_| {}))
+        }));
+        assert!(poll!(next).is_pending());
+    }
 }
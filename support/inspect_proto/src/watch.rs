@@ -0,0 +1,169 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Server-side state for the `Watch` RPC.
+
+use crate::WatchRequest;
+use crate::WatchResponse2;
+use anyhow::Context;
+use inspect::Inspection;
+use inspect::InspectionBuilder;
+use mesh::CancelContext;
+use pal_async::driver::Driver;
+use pal_async::timer::PolledTimer;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The smallest allowed watch interval.
+const MIN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long a watch is kept after its interval elapses without a request for
+/// the next changes.
+const EXPIRY: Duration = Duration::from_secs(60);
+
+/// The maximum number of concurrent watches.
+const MAX_WATCHES: usize = 64;
+
+/// How long to wait for an inspection to complete.
+const INSPECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The set of active watches for an `InspectService` server.
+///
+/// Watches are long polls: each `Watch` request waits until the watch's
+/// interval has elapsed since the previous results, inspects the watched path,
+/// and returns the changes, computed with [`inspect::Watch`]. Watches that are
+/// not polled for a while are dropped. Each watch allows only one outstanding
+/// request at a time.
+#[derive(Debug, Default)]
+pub struct WatchTable {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    watches: HashMap<u64, WatchState>,
+    next_id: u64,
+}
+
+#[derive(Debug)]
+struct WatchState {
+    path: String,
+    depth: u32,
+    interval: Duration,
+    watch: inspect::Watch,
+    /// The time of the previous results.
+    last: Instant,
+    /// Whether a request for the next changes is outstanding.
+    polling: bool,
+}
+
+impl WatchState {
+    fn expired(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last) > self.interval + EXPIRY
+    }
+}
+
+impl WatchTable {
+    /// Returns a new, empty table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles a `Watch` request, calling `start` to start an inspection of
+    /// the watched path.
+    pub async fn watch(
+        &self,
+        driver: &impl Driver,
+        mut ctx: CancelContext,
+        request: &WatchRequest,
+        start: impl FnOnce(InspectionBuilder<'_>) -> Inspection,
+    ) -> anyhow::Result<WatchResponse2> {
+        let (watch_id, path, depth, next_time) = if request.watch_id == 0 {
+            if request.interval_ms == 0 {
+                anyhow::bail!("watch interval must be nonzero");
+            }
+            (0, request.path.clone(), request.depth, None)
+        } else {
+            let mut inner = self.inner.lock();
+            let state = inner
+                .watches
+                .get_mut(&request.watch_id)
+                .context("unknown watch ID, the watch may have expired")?;
+            if state.polling {
+                anyhow::bail!(
+                    "watch {} already has a request outstanding",
+                    request.watch_id
+                );
+            }
+            state.polling = true;
+            (
+                request.watch_id,
+                state.path.clone(),
+                state.depth,
+                Some(state.last + state.interval),
+            )
+        };
+
+        // Allow the next request once this one completes or is dropped.
+        let _guard = (watch_id != 0).then(|| PollGuard {
+            table: self,
+            watch_id,
+        });
+
+        if let Some(next_time) = next_time {
+            let delay = next_time.saturating_duration_since(Instant::now());
+            ctx.until_cancelled(PolledTimer::new(driver).sleep(delay))
+                .await?;
+        }
+
+        let mut inspection = start(InspectionBuilder::new(&path).depth(Some(depth as usize)));
+        // Don't fail on timeout, as the partial results are still useful.
+        let _ = ctx
+            .with_timeout(INSPECT_TIMEOUT)
+            .until_cancelled(inspection.resolve())
+            .await;
+        let node = inspection.results();
+
+        let now = Instant::now();
+        let mut inner = self.inner.lock();
+        inner.watches.retain(|_, state| !state.expired(now));
+        let (watch_id, state) = if watch_id == 0 {
+            if inner.watches.len() >= MAX_WATCHES {
+                anyhow::bail!("too many active watches");
+            }
+            inner.next_id += 1;
+            let watch_id = inner.next_id;
+            let state = inner.watches.entry(watch_id).or_insert(WatchState {
+                path,
+                depth,
+                interval: Duration::from_millis(request.interval_ms).max(MIN_INTERVAL),
+                watch: inspect::Watch::new(),
+                last: now,
+                polling: false,
+            });
+            (watch_id, state)
+        } else {
+            let state = inner.watches.get_mut(&watch_id).context("watch expired")?;
+            (watch_id, state)
+        };
+        let changes = state.watch.next(node, now - state.last);
+        state.last = now;
+        Ok(WatchResponse2 { watch_id, changes })
+    }
+}
+
+/// Clears [`WatchState::polling`] when dropped.
+struct PollGuard<'a> {
+    table: &'a WatchTable,
+    watch_id: u64,
+}
+
+impl Drop for PollGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(state) = self.table.inner.lock().watches.get_mut(&self.watch_id) {
+            state.polling = false;
+        }
+    }
+}
@@ -81,10 +81,30 @@ fn generate(&mut self, service: prost_build::Service, buf: &mut String) {
             .iter()
             .map(|m| self.lookup_type(&m.input_type))
             .collect();
-        let response_types: Vec<_> = service
+        let response_ports: Vec<_> = service
             .methods
             .iter()
-            .map(|m| self.lookup_type(&m.output_type))
+            .map(|m| {
+                assert!(
+                    !m.client_streaming,
+                    "client streaming is not supported: {}.{}",
+                    name, m.proto_name
+                );
+                let ty = self.lookup_type(&m.output_type);
+                // A server-streaming method sends each response on a
+                // multi-message channel, which is closed on success.
+                if m.server_streaming {
+                    quote::quote!(::mesh::Sender<::core::result::Result<#ty, ::mesh_rpc::service::Status>>)
+                } else {
+                    quote::quote!(::mesh::OneshotSender<::core::result::Result<#ty, ::mesh_rpc::service::Status>>)
+                }
+            })
+            .collect();
+        let streaming_names: Vec<_> = service
+            .methods
+            .iter()
+            .filter(|m| m.server_streaming)
+            .map(|m| &m.proto_name)
             .collect();
 
         *buf += &quote::quote! {
@@ -93,7 +113,7 @@ pub enum #ident {
                 #(
                     #method_idents(
                         #request_types,
-                        ::mesh::OneshotSender<::core::result::Result<#response_types, ::mesh_rpc::service::Status>>,
+                        #response_ports,
                     ),
                 )*
             }
@@ -112,6 +132,15 @@ pub fn fail(self, status: ::mesh_rpc::service::Status) {
             impl ::mesh_rpc::service::ServiceRpc for #ident {
                 const NAME: &'static str = #name;
 
+                fn is_server_streaming(method: &str) -> bool {
+                    match method {
+                        #(
+                            #streaming_names => true,
+                        )*
+                        _ => false,
+                    }
+                }
+
                 fn method(&self) -> &'static str {
                     match self {
                         #(
@@ -47,6 +47,11 @@ fn server(path: &str) -> anyhow::Result<()> {
                     }));
                 }
                 items::Example::Method2(_req, _response) => {}
+                items::Example::Method3(req, response) => {
+                    for index in 0..req.count {
+                        response.send(Ok(items::Method3Response { index }));
+                    }
+                }
             }
         }
         drop(recv);
@@ -1,8 +1,10 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-//! TTRPC client.
+//! TTRPC and gRPC client.
 
+use crate::message::FLAG_REMOTE_CLOSED;
+use crate::message::MESSAGE_TYPE_DATA;
 use crate::message::MESSAGE_TYPE_REQUEST;
 use crate::message::MESSAGE_TYPE_RESPONSE;
 use crate::message::ReadResult;
@@ -23,6 +25,7 @@
 use futures::AsyncReadExt;
 use futures::AsyncWrite;
 use futures::FutureExt;
+use futures::Stream;
 use futures::StreamExt;
 use futures_concurrency::future::Race;
 use mesh::Deadline;
@@ -45,7 +48,7 @@
 use std::time::Duration;
 use unix_socket::UnixStream;
 
-/// A TTRPC client connection.
+/// A TTRPC or gRPC client connection.
 pub struct Client {
     send: mesh::Sender<mesh::OwnedMessage>,
     task: Task<()>,
@@ -57,6 +60,7 @@ struct ClientRequest<T> {
     deadline: Option<EncodeAs<Deadline, Timestamp>>,
     wait_ready: bool,
     rpc: T,
+    streaming: bool,
 }
 
 /// Dials a connection to a server.
@@ -114,6 +118,8 @@ async fn dial(&mut self) -> std::io::Result<Self::Stream> {
 /// A builder for [`Client`].
 pub struct ClientBuilder {
     retry_timeout: Duration,
+    #[cfg(feature = "grpc")]
+    grpc: bool,
 }
 
 impl ClientBuilder {
@@ -122,6 +128,8 @@ pub fn new() -> Self {
         Self {
             // Use the gRPC default.
             retry_timeout: Duration::from_secs(20),
+            #[cfg(feature = "grpc")]
+            grpc: false,
         }
     }
 
@@ -131,6 +139,13 @@ pub fn retry_timeout(&mut self, timeout: Duration) -> &mut Self {
         self
     }
 
+    /// Sets whether to use the gRPC (HTTP/2) protocol instead of ttrpc.
+    #[cfg(feature = "grpc")]
+    pub fn grpc(&mut self, grpc: bool) -> &mut Self {
+        self.grpc = grpc;
+        self
+    }
+
     /// Builds a new client from a dialier.
     pub fn build(&self, driver: &(impl Driver + Spawn), dialer: impl Dial) -> Client {
         let (send, recv) = mesh::channel();
@@ -142,6 +157,8 @@ pub fn build(&self, driver: &(impl Driver + Spawn), dialer: impl Dial) -> Client
             rpc_recv: Some(recv),
             last_failure: None,
             failure_timeout: self.retry_timeout,
+            #[cfg(feature = "grpc")]
+            grpc: self.grpc,
         };
         let task = driver.spawn("ttrpc client", worker.run());
         Client {
@@ -190,6 +207,18 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     }
 }
 
+/// A stream of the responses to a server-streaming RPC call.
+///
+/// The stream ends after the last response if the call succeeds. If the call
+/// fails, the last item is the error.
+pub struct CallStream<T>(mesh::Receiver<Result<T, Status>>);
+
+impl<T> std::fmt::Debug for CallStream<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CallStream").field(&self.0).finish()
+    }
+}
+
 impl CallBuilder<'_> {
     /// Sets the timeout for the RPC.
     ///
@@ -235,11 +264,38 @@ pub fn start<F, R, T, U>(&self, rpc: F, input: T) -> Call<U>
                 deadline: self.deadline.map(Into::into),
                 rpc: DecodedRpc::Rpc(rpc(input, send)),
                 wait_ready: self.wait_ready,
+                streaming: false,
             }));
 
         Call(recv)
     }
 
+    /// Starts a server-streaming RPC.
+    ///
+    /// The returned stream yields each response. The deadline, if any,
+    /// applies to the whole call.
+    #[must_use]
+    pub fn start_stream<F, R, T, U>(&self, rpc: F, input: T) -> CallStream<U>
+    where
+        F: FnOnce(T, mesh::Sender<Result<U, Status>>) -> R,
+        R: ServiceRpc,
+        U: 'static + MeshPayload + Send,
+    {
+        let (send, recv) = mesh::channel();
+
+        self.client
+            .send
+            .send(mesh::OwnedMessage::new(ClientRequest {
+                service: R::NAME.to_string(),
+                deadline: self.deadline.map(Into::into),
+                rpc: DecodedRpc::Rpc(rpc(input, send)),
+                wait_ready: self.wait_ready,
+                streaming: true,
+            }));
+
+        CallStream(recv)
+    }
+
     /// Used to send unknown requests for testing.
     #[cfg(test)]
     pub(crate) fn start_raw(&self, service: &str, method: &str, data: Vec<u8>) -> Call<Vec<u8>> {
@@ -256,6 +312,7 @@ pub(crate) fn start_raw(&self, service: &str, method: &str, data: Vec<u8>) -> Ca
                     port: send.into(),
                 },
                 wait_ready: self.wait_ready,
+                streaming: false,
             }));
 
         Call(recv)
@@ -277,6 +334,17 @@ fn poll(
     }
 }
 
+impl<T: 'static + MeshPayload + Send> Stream for CallStream<T> {
+    type Item = Result<T, Status>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.get_mut().0.poll_next_unpin(cx)
+    }
+}
+
 struct ClientWorker<T> {
     dialer: T,
     timer: PolledTimer,
@@ -285,6 +353,8 @@ struct ClientWorker<T> {
     rpc_recv: Option<mesh::Receiver<ClientRequest<GenericRpc>>>,
     last_failure: Option<Instant>,
     failure_timeout: Duration,
+    #[cfg(feature = "grpc")]
+    grpc: bool,
 }
 
 impl<T: Dial> ClientWorker<T> {
@@ -294,7 +364,7 @@ async fn run(mut self) {
                 None => break,
                 Some(Ok(stream)) => {
                     tracing::debug!("connection established");
-                    self.run_connection(stream).await.inspect_err(|err| {
+                    self.run_transport(stream).await.inspect_err(|err| {
                         tracing::debug!(
                             error = err.as_ref() as &dyn std::error::Error,
                             "connection failed"
@@ -409,9 +479,17 @@ enum Event<T> {
         None
     }
 
+    async fn run_transport(&mut self, stream: T::Stream) -> anyhow::Result<()> {
+        #[cfg(feature = "grpc")]
+        if self.grpc {
+            return self.run_connection_grpc(stream).await;
+        }
+        self.run_connection(stream).await
+    }
+
     async fn run_connection(&mut self, stream: T::Stream) -> anyhow::Result<()> {
         let (mut reader, mut writer) = AsyncReadExt::split(stream);
-        let responses = Mutex::new(HashMap::<u32, mesh::OneshotSender<mesh::OwnedMessage>>::new());
+        let responses = Mutex::new(HashMap::<u32, PendingResponse>::new());
         let recv_task = async {
             while let Some(message) = read_message(&mut reader)
                 .await
@@ -420,16 +498,40 @@ async fn run_connection(&mut self, stream: T::Stream) -> anyhow::Result<()> {
                 let stream_id = message.stream_id;
                 tracing::debug!(stream_id, "response");
 
-                let response_send = responses.lock().remove(&stream_id);
-
-                let Some(response_send) = response_send else {
+                let mut responses = responses.lock();
+                let Some(response) = responses.get(&stream_id) else {
                     tracing::error!(stream_id, "response for unknown stream");
                     continue;
                 };
 
-                let result = handle_message(message);
+                if response.streaming && message.message_type == MESSAGE_TYPE_DATA {
+                    let result = message.payload.map_err(|err @ TooLongError { .. }| {
+                        status_from_err(Code::ResourceExhausted, err)
+                    });
+                    let failed = result.is_err();
+                    response.send.send(mesh::OwnedMessage::new(result));
+                    if failed {
+                        responses.remove(&stream_id);
+                    }
+                    continue;
+                }
 
-                response_send.send(mesh::OwnedMessage::new(result));
+                let response = responses.remove(&stream_id).unwrap();
+                let result = handle_message(message);
+                if response.streaming {
+                    // The response message ends the stream, and its status
+                    // indicates whether the call succeeded.
+                    match result {
+                        Err(status) if status.code != Code::Ok as i32 => {
+                            response
+                                .send
+                                .send(mesh::OwnedMessage::new(Err::<Vec<u8>, _>(status)));
+                        }
+                        _ => {}
+                    }
+                } else {
+                    response.send.send(mesh::OwnedMessage::new(result));
+                }
             }
             Ok(())
         };
@@ -447,9 +549,13 @@ async fn run_connection(&mut self, stream: T::Stream) -> anyhow::Result<()> {
                 let Some(request) = request else {
                     break;
                 };
-                responses
-                    .lock()
-                    .insert(next_stream_id, request.rpc.port.into());
+                responses.lock().insert(
+                    next_stream_id,
+                    PendingResponse {
+                        send: request.rpc.port.into(),
+                        streaming: request.streaming,
+                    },
+                );
 
                 let payload = mesh::payload::encode(Request {
                     service: request.service,
@@ -461,16 +567,46 @@ async fn run_connection(&mut self, stream: T::Stream) -> anyhow::Result<()> {
                     metadata: vec![],
                 });
 
-                write_message(&mut writer, next_stream_id, MESSAGE_TYPE_REQUEST, &payload)
-                    .await
-                    .context("failed to write to connection")?;
+                // The client never sends data after a request, so mark it as
+                // closed. This is how server-streaming requests are sent.
+                let flags = if request.streaming {
+                    FLAG_REMOTE_CLOSED
+                } else {
+                    0
+                };
+                write_message(
+                    &mut writer,
+                    next_stream_id,
+                    MESSAGE_TYPE_REQUEST,
+                    flags,
+                    &payload,
+                )
+                .await
+                .context("failed to write to connection")?;
 
                 next_stream_id = next_stream_id.wrapping_add(2);
             }
             Ok(())
         };
 
-        (send_task, recv_task).race().await
+        let r = (send_task, recv_task).race().await;
+        // Fail any calls still waiting for responses, so that streaming calls
+        // are not mistaken for having completed successfully.
+        fail_pending(responses.into_inner().into_values().map(|r| r.send));
+        r
+    }
+}
+
+/// An outstanding call on a connection.
+struct PendingResponse {
+    send: mesh::Sender<mesh::OwnedMessage>,
+    streaming: bool,
+}
+
+fn fail_pending(pending: impl IntoIterator<Item = mesh::Sender<mesh::OwnedMessage>>) {
+    let status = status_from_err(Code::Unavailable, anyhow::anyhow!("connection closed"));
+    for send in pending {
+        send.send(mesh::OwnedMessage::new(Err::<Vec<u8>, _>(status.clone())));
     }
 }
 
@@ -496,6 +632,325 @@ fn handle_message(message: ReadResult) -> Result<Vec<u8>, Status> {
     }
 }
 
+#[cfg(feature = "grpc")]
+mod grpc {
+    use super::ClientRequest;
+    use super::ClientWorker;
+    use super::Dial;
+    use crate::grpc::FrameError;
+    use crate::grpc::MessageReader;
+    use crate::grpc::TokioIo;
+    use crate::grpc::encode_message;
+    use crate::rpc::status_from_err;
+    use crate::service::Code;
+    use crate::service::GenericRpc;
+    use crate::service::Status;
+    use anyhow::Context as _;
+    use futures::FutureExt;
+    use futures::StreamExt;
+    use futures::future::AbortHandle;
+    use futures::future::Abortable;
+    use futures_concurrency::future::Race;
+    use h2::client::SendRequest;
+    use http::HeaderMap;
+    use mesh::Deadline;
+    use pal_async::timer::Instant;
+    use prost::bytes::Bytes;
+    use std::collections::HashMap;
+    use std::future::pending;
+    use std::time::Duration;
+    use unicycle::FuturesUnordered;
+
+    /// How often to check for calls whose callers have dropped their
+    /// receivers, so that the calls' streams can be reset.
+    const CLOSED_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+    impl<T: Dial> ClientWorker<T> {
+        pub(super) async fn run_connection_grpc(
+            &mut self,
+            stream: T::Stream,
+        ) -> anyhow::Result<()> {
+            let (send_request, mut conn) = h2::client::handshake(TokioIo(Box::pin(stream)))
+                .await
+                .context("failed http2 handshake")?;
+
+            let mut calls = FuturesUnordered::new();
+            // The senders for the outstanding calls, so that they can be
+            // failed if the connection is lost, and the handles to cancel them
+            // if their callers go away.
+            let mut pending_calls = HashMap::new();
+            let mut next_call_id = 0u64;
+            let mut next_closed_check = Instant::now() + CLOSED_CHECK_INTERVAL;
+            let r = loop {
+                enum Event<T> {
+                    Request(Option<ClientRequest<GenericRpc>>),
+                    Call(u64),
+                    CheckClosed(()),
+                    Closed(T),
+                }
+
+                let next = async {
+                    if let Some(req) = self.waiting.pop_front() {
+                        Some(req)
+                    } else if let Some(recv) = &mut self.rpc_recv {
+                        recv.next().await
+                    } else {
+                        None
+                    }
+                };
+                let call = async {
+                    if calls.is_empty() {
+                        pending().await
+                    } else {
+                        calls.next().await.unwrap()
+                    }
+                };
+                // h2 only resets a stream when its response body is dropped,
+                // which a call waiting for its next response never does on
+                // its own, so periodically look for callers that went away.
+                let check_closed = async {
+                    if pending_calls.is_empty() {
+                        pending().await
+                    } else {
+                        self.timer.sleep_until(next_closed_check).await
+                    }
+                };
+
+                match (
+                    next.map(Event::Request),
+                    call.map(Event::Call),
+                    check_closed.map(Event::CheckClosed),
+                    (&mut conn).map(Event::Closed),
+                )
+                    .race()
+                    .await
+                {
+                    Event::Request(Some(request)) => {
+                        let id = next_call_id;
+                        next_call_id += 1;
+                        let send: mesh::Sender<mesh::OwnedMessage> = request.rpc.port.into();
+                        let (abort, registration) = AbortHandle::new_pair();
+                        pending_calls.insert(id, (send.clone(), abort));
+                        let call = grpc_call(
+                            send_request.clone(),
+                            request.service,
+                            request.rpc.method,
+                            request.rpc.data,
+                            request.deadline.map(|d| *d),
+                            request.streaming,
+                            send,
+                        );
+                        calls.push(Abortable::new(call, registration).map(move |_| id));
+                    }
+                    Event::Request(None) => break Ok(()),
+                    Event::Call(id) => {
+                        pending_calls.remove(&id);
+                    }
+                    Event::CheckClosed(()) => {
+                        next_closed_check = Instant::now() + CLOSED_CHECK_INTERVAL;
+                        // Aborting a call drops its stream, which resets it.
+                        // The call then completes as usual.
+                        for (send, abort) in pending_calls.values() {
+                            if send.is_closed() {
+                                abort.abort();
+                            }
+                        }
+                    }
+                    Event::Closed(r) => {
+                        break Err(r.map_or_else(
+                            |err| anyhow::Error::new(err).context("http2 connection failed"),
+                            |()| anyhow::anyhow!("connection closed"),
+                        ));
+                    }
+                }
+            };
+
+            // Let any calls that have already finished complete, then fail
+            // the rest, so that streaming calls are not mistaken for having
+            // completed successfully.
+            while let Some(Some(id)) = calls.next().now_or_never() {
+                pending_calls.remove(&id);
+            }
+            drop(calls);
+            super::fail_pending(pending_calls.into_values().map(|(send, _)| send));
+            r
+        }
+    }
+
+    /// Issues a single gRPC call, sending the responses to `send`.
+    async fn grpc_call(
+        send_request: SendRequest<Bytes>,
+        service: String,
+        method: String,
+        data: Vec<u8>,
+        deadline: Option<Deadline>,
+        streaming: bool,
+        send: mesh::Sender<mesh::OwnedMessage>,
+    ) {
+        let result = grpc_call_inner(
+            send_request,
+            &service,
+            &method,
+            &data,
+            deadline,
+            streaming,
+            &send,
+        )
+        .await;
+        if let Err(status) = result {
+            send.send(mesh::OwnedMessage::new(Err::<Vec<u8>, _>(status)));
+        }
+    }
+
+    async fn grpc_call_inner(
+        send_request: SendRequest<Bytes>,
+        service: &str,
+        method: &str,
+        data: &[u8],
+        deadline: Option<Deadline>,
+        streaming: bool,
+        send: &mesh::Sender<mesh::OwnedMessage>,
+    ) -> Result<(), Status> {
+        let mut send_request = send_request
+            .ready()
+            .await
+            .map_err(|err| status_from_err(Code::Unavailable, err))?;
+
+        let mut request = http::Request::builder()
+            .method(http::Method::POST)
+            .uri(format!("http://localhost/{service}/{method}"))
+            .header("content-type", "application/grpc+proto")
+            .header("te", "trailers");
+        if let Some(deadline) = deadline {
+            request = request.header("grpc-timeout", encode_timeout(deadline - Deadline::now()));
+        }
+        let request = request
+            .body(())
+            .map_err(|err| status_from_err(Code::Internal, err))?;
+
+        let (response, mut body) = send_request
+            .send_request(request, false)
+            .map_err(|err| status_from_err(Code::Unavailable, err))?;
+        body.send_data(encode_message(data), true)
+            .map_err(|err| status_from_err(Code::Unavailable, err))?;
+
+        let response = response.await.map_err(|err| {
+            let code = if err.is_reset() {
+                Code::Cancelled
+            } else {
+                Code::Unavailable
+            };
+            status_from_err(code, err)
+        })?;
+
+        let (head, body) = response.into_parts();
+        if head.status != http::StatusCode::OK {
+            return Err(status_from_err(
+                Code::Unknown,
+                anyhow::anyhow!("http status {}", head.status),
+            ));
+        }
+
+        // A response with no messages may put the status in the headers.
+        if let Some(result) = status_from_headers(&head.headers) {
+            return result;
+        }
+
+        let mut reader = MessageReader::new(body);
+        let mut response = None;
+        while let Some(message) = reader.next().await.map_err(|err| {
+            let code = if matches!(err, FrameError::TooLong(_)) {
+                Code::ResourceExhausted
+            } else {
+                Code::Internal
+            };
+            status_from_err(code, err)
+        })? {
+            if streaming {
+                if send.is_closed() {
+                    // The caller is no longer interested. Dropping the stream
+                    // cancels the call.
+                    return Ok(());
+                }
+                send.send(mesh::OwnedMessage::new(Ok::<_, Status>(message)));
+            } else if response.replace(message).is_some() {
+                return Err(status_from_err(
+                    Code::Internal,
+                    anyhow::anyhow!("multiple responses to unary rpc"),
+                ));
+            }
+        }
+
+        let trailers = reader
+            .into_body()
+            .trailers()
+            .await
+            .map_err(|err| status_from_err(Code::Internal, err))?
+            .unwrap_or_default();
+
+        status_from_headers(&trailers).unwrap_or_else(|| {
+            Err(status_from_err(
+                Code::Internal,
+                anyhow::anyhow!("missing grpc-status"),
+            ))
+        })?;
+
+        if !streaming {
+            let response = response.ok_or_else(|| {
+                status_from_err(Code::Internal, anyhow::anyhow!("missing response"))
+            })?;
+            send.send(mesh::OwnedMessage::new(Ok::<_, Status>(response)));
+        }
+        Ok(())
+    }
+
+    /// Encodes a `grpc-timeout` value, which is limited to eight digits.
+    fn encode_timeout(timeout: Duration) -> String {
+        let ms = timeout.as_millis();
+        if ms < 100_000_000 {
+            format!("{ms}m")
+        } else {
+            format!("{}S", timeout.as_secs().min(99_999_999))
+        }
+    }
+
+    /// Parses the call status from the headers or trailers, if present.
+    fn status_from_headers(headers: &HeaderMap) -> Option<Result<(), Status>> {
+        let code = headers.get("grpc-status")?;
+        let Some(code) = code.to_str().ok().and_then(|v| v.parse::<i32>().ok()) else {
+            return Some(Err(status_from_err(
+                Code::Internal,
+                anyhow::anyhow!("invalid grpc-status"),
+            )));
+        };
+        if code == Code::Ok as i32 {
+            return Some(Ok(()));
+        }
+
+        // Prefer the full status, which includes the details.
+        let status = headers
+            .get("grpc-status-details-bin")
+            .and_then(|v| {
+                // Padding is optional.
+                let v = v.to_str().ok()?.trim_end_matches('=');
+                base64::Engine::decode(&base64::engine::general_purpose::STANDARD_NO_PAD, v).ok()
+            })
+            .and_then(|v| <Status as prost::Message>::decode(v.as_slice()).ok())
+            .filter(|status| status.code == code);
+
+        let status = status.unwrap_or_else(|| Status {
+            code,
+            message: headers
+                .get("grpc-message")
+                .and_then(|v| v.to_str().ok())
+                .map(|v| urlencoding::decode(v).map_or_else(|_| v.to_owned(), |v| v.into_owned()))
+                .unwrap_or_default(),
+            details: Vec::new(),
+        });
+        Some(Err(status))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Client;
@@ -0,0 +1,127 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! gRPC message framing, shared by the client and server.
+
+use crate::message::MAX_MESSAGE_SIZE;
+use h2::RecvStream;
+use prost::bytes::Bytes;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use thiserror::Error;
+
+/// The length of the prefix before each gRPC message.
+const PREFIX_LEN: usize = 5;
+
+#[derive(Debug, Error)]
+pub(crate) enum FrameError {
+    #[error("http2 error")]
+    H2(#[from] h2::Error),
+    #[error("invalid message header")]
+    InvalidHeader,
+    #[error("truncated message")]
+    Truncated,
+    #[error("message length {0} exceeds maximum allowed size {MAX_MESSAGE_SIZE}")]
+    TooLong(usize),
+}
+
+/// Encodes `data` as a length-prefixed, uncompressed gRPC message.
+pub(crate) fn encode_message(data: &[u8]) -> Bytes {
+    let mut buf = Vec::with_capacity(PREFIX_LEN + data.len());
+    buf.push(0);
+    buf.extend(&(data.len() as u32).to_be_bytes());
+    buf.extend(data);
+    buf.into()
+}
+
+/// Reads length-prefixed gRPC messages from an HTTP/2 body.
+pub(crate) struct MessageReader {
+    body: RecvStream,
+    buf: Vec<u8>,
+}
+
+impl MessageReader {
+    pub fn new(body: RecvStream) -> Self {
+        Self {
+            body,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Reads the next message, returning `None` at the end of the body.
+    pub async fn next(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        loop {
+            if let Some(hdr) = self.buf.get(..PREFIX_LEN) {
+                if hdr[0] != 0 {
+                    // Compression was not advertised as supported, so the
+                    // peer should not send compressed messages.
+                    return Err(FrameError::InvalidHeader);
+                }
+                let len = u32::from_be_bytes(hdr[1..].try_into().unwrap()) as usize;
+                if len > MAX_MESSAGE_SIZE {
+                    // Fail before buffering the message.
+                    return Err(FrameError::TooLong(len));
+                }
+                if self.buf.len() >= PREFIX_LEN + len {
+                    let message = self.buf[PREFIX_LEN..PREFIX_LEN + len].to_vec();
+                    self.buf.drain(..PREFIX_LEN + len);
+                    return Ok(Some(message));
+                }
+            }
+            let Some(data) = self.body.data().await.transpose()? else {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(FrameError::Truncated);
+            };
+            self.buf.extend(&data);
+            self.body
+                .flow_control()
+                .release_capacity(data.len())
+                .unwrap();
+        }
+    }
+
+    /// Returns the body, for reading trailers.
+    pub fn into_body(self) -> RecvStream {
+        self.body
+    }
+}
+
+/// Adapts a `futures` IO object to the `tokio` IO traits used by `h2`.
+pub(crate) struct TokioIo<T>(pub T);
+
+impl<T: futures::AsyncRead + Unpin> tokio::io::AsyncRead for TokioIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let n = ready!(Pin::new(&mut self.get_mut().0).poll_read(cx, buf.initialize_unfilled()))?;
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: futures::AsyncWrite + Unpin> tokio::io::AsyncWrite for TokioIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.get_mut().0).poll_close(cx)
+    }
+}
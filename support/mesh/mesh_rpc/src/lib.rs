@@ -8,8 +8,11 @@
 //! interop well with mesh channels, allowing gRPC to be easily used with a
 //! mesh-based application.
 //!
-//! Currently, the server supports the gRPC and ttrpc protocols, while the
-//! client only supports the ttrpc protocol.
+//! The server supports the gRPC and ttrpc protocols. The client supports
+//! ttrpc, and gRPC when the `grpc` feature is enabled.
+//!
+//! Unary and server-streaming RPCs are supported. Client-streaming and
+//! bidirectional-streaming RPCs are not.
 //!
 //! # Usage
 //!
@@ -27,6 +30,8 @@
 extern crate self as mesh_rpc;
 
 pub mod client;
+#[cfg(feature = "grpc")]
+mod grpc;
 mod message;
 mod rpc;
 pub mod server;
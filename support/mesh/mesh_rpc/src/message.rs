@@ -32,6 +32,13 @@ struct MessageHeader {
 
 pub const MESSAGE_TYPE_REQUEST: u8 = 1;
 pub const MESSAGE_TYPE_RESPONSE: u8 = 2;
+/// A message carrying one item of a stream. Server-streaming responses are
+/// sent as data messages followed by a response message with the final
+/// status.
+pub const MESSAGE_TYPE_DATA: u8 = 3;
+
+/// The sender will not send any more data on this stream.
+pub const FLAG_REMOTE_CLOSED: u8 = 0x1;
 
 /// The maximum ttrpc message size.
 ///
@@ -50,7 +57,7 @@ struct MessageHeader {
 /// still skeptical that this is possible because existing senders do not
 /// validate this at all. But let's not take a dependency on messages bigger
 /// than this.)
-const MAX_MESSAGE_SIZE: usize = 0xffffff;
+pub(crate) const MAX_MESSAGE_SIZE: usize = 0xffffff;
 
 #[derive(Debug, Error)]
 #[error("message length {0} exceeds maximum allowed size {MAX_MESSAGE_SIZE}")]
@@ -97,13 +104,14 @@ pub async fn write_message(
     writer: &mut (impl AsyncWrite + Unpin),
     stream_id: u32,
     message_type: u8,
+    flags: u8,
     payload: &[u8],
 ) -> anyhow::Result<()> {
     let header = MessageHeader {
         stream_id: stream_id.into(),
         message_type,
         length: (payload.len() as u32).into(),
-        flags: 0,
+        flags,
     };
 
     writer
@@ -3,6 +3,7 @@
 
 //! TTRPC server.
 
+use crate::message::MESSAGE_TYPE_DATA;
 use crate::message::MESSAGE_TYPE_REQUEST;
 use crate::message::MESSAGE_TYPE_RESPONSE;
 use crate::message::ReadResult;
@@ -23,6 +24,7 @@
 use futures::Stream;
 use futures::StreamExt;
 use futures::stream::FusedStream;
+use futures::stream::SelectAll;
 use futures_concurrency::future::TryJoin;
 use futures_concurrency::stream::Merge;
 use mesh::CancelContext;
@@ -42,7 +44,40 @@
 /// A ttrpc server.
 #[derive(Debug, Default)]
 pub struct Server {
-    services: HashMap<&'static str, mesh::Sender<(CancelContext, GenericRpc)>>,
+    services: HashMap<&'static str, Service>,
+}
+
+#[derive(Debug)]
+struct Service {
+    send: mesh::Sender<(CancelContext, GenericRpc)>,
+    is_server_streaming: fn(&str) -> bool,
+}
+
+impl Service {
+    /// Dispatches a request for `method`, returning a receiver for the
+    /// responses.
+    ///
+    /// A unary method responds with exactly one message. A server-streaming
+    /// method responds with any number of messages and then closes the
+    /// channel, or responds with an error.
+    fn dispatch(
+        &self,
+        ctx: CancelContext,
+        method: String,
+        data: Vec<u8>,
+    ) -> (bool, mesh::Receiver<Result<Vec<u8>, Status>>) {
+        let streaming = (self.is_server_streaming)(&method);
+        let (send, recv) = mesh::channel();
+        self.send.send((
+            ctx,
+            GenericRpc {
+                method,
+                data,
+                port: send.into(),
+            },
+        ));
+        (streaming, recv)
+    }
 }
 
 /// A receiver for RPC requests for a given service.
@@ -112,7 +147,13 @@ pub fn new() -> Self {
     /// Adds or updates a channel for receiving service requests.
     pub fn add_service<T: ServiceRpc>(&mut self) -> RpcReceiver<T> {
         let (send, recv) = mesh::channel();
-        self.services.insert(T::NAME, Port::from(send).into());
+        self.services.insert(
+            T::NAME,
+            Service {
+                send: Port::from(send).into(),
+                is_server_streaming: T::is_server_streaming,
+            },
+        );
         RpcReceiver(recv)
     }
 
@@ -166,10 +207,8 @@ async fn serve(
         let recv_task = async {
             let stream_send = stream_send; // move into this task
             while let Some(message) = read_message(&mut reader).await? {
-                let (send, recv) = mesh::oneshot::<Result<Vec<u8>, Status>>();
-                stream_send.send((message.stream_id, recv));
-
-                let handle = handle_message(message).and_then(|request| {
+                let stream_id = message.stream_id;
+                let r = handle_message(message).and_then(|request| {
                     let service = self.services.get(request.service.as_str()).ok_or_else(|| {
                         status_from_err(
                             Code::Unimplemented,
@@ -183,30 +222,26 @@ async fn serve(
                         ctx.with_timeout(std::time::Duration::from_nanos(request.timeout_nano))
                     };
 
-                    Ok(move |port| {
-                        service.send((
-                            ctx,
-                            GenericRpc {
-                                method: request.method,
-                                data: request.payload,
-                                port,
-                            },
-                        ));
-                    })
+                    Ok(service.dispatch(ctx, request.method, request.payload))
                 });
 
-                match handle {
-                    Ok(handle) => handle(send.into()),
-                    Err(err) => send.send(Err(err)),
-                }
+                let (streaming, recv) = match r {
+                    Ok(r) => r,
+                    Err(err) => {
+                        let (send, recv) = mesh::channel();
+                        send.send(Err(err));
+                        (false, recv)
+                    }
+                };
+                stream_send.send(Box::pin(response_stream(stream_id, streaming, recv)));
             }
             Ok(())
         };
         let send_task = async {
-            let mut responses = FuturesUnordered::new();
+            let mut responses = SelectAll::new();
             enum Event<T> {
-                Request((u32, mesh::OneshotReceiver<Result<Vec<u8>, Status>>)),
-                Response(T),
+                Request(T),
+                Response((u32, u8, Vec<u8>)),
             }
             while let Some(event) = (
                 (&mut stream_recv).map(Event::Request),
@@ -217,29 +252,11 @@ enum Event<T> {
                 .await
             {
                 match event {
-                    Event::Request((stream_id, recv)) => {
-                        let recv = recv.map(move |r| {
-                            (
-                                stream_id,
-                                match r {
-                                    Ok(Ok(payload)) => Response::Payload(payload),
-                                    Ok(Err(status)) => Response::Status(status),
-                                    Err(err) => {
-                                        Response::Status(status_from_err(Code::Internal, err))
-                                    }
-                                },
-                            )
-                        });
-                        responses.push(recv);
+                    Event::Request(stream) => {
+                        responses.push(stream);
                     }
-                    Event::Response((stream_id, payload)) => {
-                        write_message(
-                            &mut writer,
-                            stream_id,
-                            MESSAGE_TYPE_RESPONSE,
-                            &mesh::payload::encode(payload),
-                        )
-                        .await?;
+                    Event::Response((stream_id, message_type, payload)) => {
+                        write_message(&mut writer, stream_id, message_type, 0, &payload).await?;
                     }
                 }
             }
@@ -250,6 +267,52 @@ enum Event<T> {
     }
 }
 
+/// Returns a stream of the messages to write in response to a request.
+///
+/// Unary responses are a single response message. Streaming responses are a
+/// data message for each response, followed by a response message with the
+/// final status.
+fn response_stream(
+    stream_id: u32,
+    streaming: bool,
+    recv: mesh::Receiver<Result<Vec<u8>, Status>>,
+) -> impl Stream<Item = (u32, u8, Vec<u8>)> {
+    futures::stream::unfold(Some(recv), move |recv| async move {
+        let mut recv = recv?;
+        let (message_type, payload, done) = match recv.next().await {
+            Some(Ok(payload)) if streaming => (MESSAGE_TYPE_DATA, payload, false),
+            Some(Ok(payload)) => (
+                MESSAGE_TYPE_RESPONSE,
+                mesh::payload::encode(Response::Payload(payload)),
+                true,
+            ),
+            Some(Err(status)) => (
+                MESSAGE_TYPE_RESPONSE,
+                mesh::payload::encode(Response::Status(status)),
+                true,
+            ),
+            None if streaming => (
+                MESSAGE_TYPE_RESPONSE,
+                mesh::payload::encode(Response::Status(Status {
+                    code: Code::Ok.into(),
+                    message: String::new(),
+                    details: Vec::new(),
+                })),
+                true,
+            ),
+            None => (
+                MESSAGE_TYPE_RESPONSE,
+                mesh::payload::encode(Response::Status(status_from_err(
+                    Code::Internal,
+                    anyhow::anyhow!("no response"),
+                ))),
+                true,
+            ),
+        };
+        Some(((stream_id, message_type, payload), (!done).then_some(recv)))
+    })
+}
+
 fn handle_message(message: ReadResult) -> Result<Request, Status> {
     if message.stream_id % 2 != 1 {
         return Err(status_from_err(
@@ -286,15 +349,17 @@ fn handle_message(message: ReadResult) -> Result<Request, Status> {
 #[cfg(feature = "grpc")]
 mod grpc {
     use super::Server;
+    use crate::grpc::FrameError;
+    use crate::grpc::MessageReader;
+    use crate::grpc::TokioIo;
+    use crate::grpc::encode_message;
     use crate::rpc::status_from_err;
     use crate::service::Code;
-    use crate::service::GenericRpc;
     use crate::service::Status;
     use anyhow::Context as _;
-    use futures::AsyncRead as _;
-    use futures::AsyncWrite;
     use futures::FutureExt;
     use futures::StreamExt;
+    use futures_concurrency::future::Race;
     use futures_concurrency::stream::Merge;
     use h2::RecvStream;
     use h2::server::SendResponse;
@@ -308,8 +373,6 @@ mod grpc {
     use prost::bytes::Bytes;
     use std::io::Read;
     use std::io::Write;
-    use std::pin::Pin;
-    use std::task::ready;
     use thiserror::Error;
     use unicycle::FuturesUnordered;
 
@@ -321,8 +384,8 @@ enum RequestError {
         H2(#[from] h2::Error),
         #[error("unreachable")]
         Status(http::StatusCode),
-        #[error("invalid message header")]
-        InvalidHeader,
+        #[error("invalid request message")]
+        Message(#[from] FrameError),
     }
 
     impl From<http::StatusCode> for RequestError {
@@ -367,47 +430,7 @@ async fn serve_grpc(
             &self,
             stream: PolledSocket<impl AsSockRef + Read + Write>,
         ) -> anyhow::Result<()> {
-            struct Wrap<T>(T);
-
-            impl<T: AsSockRef + Read> tokio::io::AsyncRead for Wrap<PolledSocket<T>> {
-                fn poll_read(
-                    self: Pin<&mut Self>,
-                    cx: &mut std::task::Context<'_>,
-                    buf: &mut tokio::io::ReadBuf<'_>,
-                ) -> std::task::Poll<std::io::Result<()>> {
-                    let n = ready!(
-                        Pin::new(&mut self.get_mut().0).poll_read(cx, buf.initialize_unfilled())
-                    )?;
-                    buf.advance(n);
-                    std::task::Poll::Ready(Ok(()))
-                }
-            }
-
-            impl<T: AsSockRef + Write> tokio::io::AsyncWrite for Wrap<PolledSocket<T>> {
-                fn poll_write(
-                    self: Pin<&mut Self>,
-                    cx: &mut std::task::Context<'_>,
-                    buf: &[u8],
-                ) -> std::task::Poll<Result<usize, std::io::Error>> {
-                    Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
-                }
-
-                fn poll_flush(
-                    self: Pin<&mut Self>,
-                    cx: &mut std::task::Context<'_>,
-                ) -> std::task::Poll<Result<(), std::io::Error>> {
-                    Pin::new(&mut self.get_mut().0).poll_flush(cx)
-                }
-
-                fn poll_shutdown(
-                    self: Pin<&mut Self>,
-                    cx: &mut std::task::Context<'_>,
-                ) -> std::task::Poll<Result<(), std::io::Error>> {
-                    Pin::new(&mut self.get_mut().0).poll_close(cx)
-                }
-            }
-
-            let mut conn = h2::server::handshake(Wrap(stream))
+            let mut conn = h2::server::handshake(TokioIo(stream))
                 .await
                 .context("failed http2 handshake")?;
 
@@ -508,18 +531,50 @@ async fn handle_request(
             // No returning HTTP status code errors after this point.
             let mut resp = resp.send_response(response.body(())?, false)?;
 
-            let result = self.invoke_rpc(service, method, body, ctx).await?;
+            let (streaming, mut recv) = self.invoke_rpc(service, method, body, ctx).await?;
+
+            // Send the responses. A unary RPC has exactly one response. A
+            // server-streaming RPC has any number, ending when the service
+            // closes the channel.
+            let result = loop {
+                enum Event<T> {
+                    Response(Option<T>),
+                    Reset(Result<h2::Reason, h2::Error>),
+                }
+                let event = (
+                    recv.next().map(Event::Response),
+                    std::future::poll_fn(|cx| resp.poll_reset(cx)).map(Event::Reset),
+                )
+                    .race()
+                    .await;
+                match event {
+                    Event::Response(Some(Ok(data))) => {
+                        resp.send_data(encode_message(&data), false)?;
+                        if !streaming {
+                            break Ok(());
+                        }
+                    }
+                    Event::Response(Some(Err(status))) => break Err(status),
+                    Event::Response(None) if streaming => break Ok(()),
+                    Event::Response(None) => {
+                        break Err(status_from_err(
+                            Code::Internal,
+                            anyhow::anyhow!("no response"),
+                        ));
+                    }
+                    Event::Reset(_) => {
+                        // The client cancelled the RPC. Dropping the receiver
+                        // lets the service know.
+                        tracing::debug!(service, method, "rpc reset by client");
+                        return Ok(());
+                    }
+                }
+            };
 
             let mut trailers = HeaderMap::new();
             match result {
-                Ok(data) => {
+                Ok(()) => {
                     tracing::debug!(service, method, "rpc success");
-
-                    let mut buf = Vec::with_capacity(5 + data.len());
-                    buf.push(0);
-                    buf.extend(&(data.len() as u32).to_be_bytes());
-                    buf.extend(data);
-                    resp.send_data(buf.into(), false)?;
                     trailers.insert("grpc-status", const { HeaderValue::from_static("0") });
                 }
                 Err(status) => {
@@ -548,63 +603,42 @@ async fn handle_request(
             Ok(())
         }
 
+        /// Reads the request message and dispatches it to the service,
+        /// returning whether the method is server streaming and a receiver for
+        /// the responses.
         async fn invoke_rpc(
             &self,
             service: &str,
             method: &str,
-            mut body: RecvStream,
+            body: RecvStream,
             ctx: CancelContext,
-        ) -> Result<Result<Vec<u8>, Status>, RequestError> {
+        ) -> Result<(bool, mesh::Receiver<Result<Vec<u8>, Status>>), RequestError> {
+            let fail = |status| {
+                let (send, recv) = mesh::channel();
+                send.send(Err(status));
+                Ok((false, recv))
+            };
+
             let Some(service) = self.services.get(service) else {
-                return Ok(Err(Status {
+                return fail(Status {
                     code: Code::Unimplemented.into(),
                     message: format!("unknown service {}", service),
                     details: Vec::new(),
-                }));
+                });
             };
 
-            // For now, only non-stream RPCs are supported, so read the first
-            // message and ignore the rest.
-            //
-            // FUTURE: change the `GenericRpc` type to include channels for
-            // streams.
-
-            let mut buf = Vec::new();
-
-            // Read data frames until the header is complete.
-            while buf.len() < 5 {
-                let data = body.data().await.ok_or(RequestError::InvalidHeader)??;
-                buf.extend(&data);
-                body.flow_control().release_capacity(data.len()).unwrap();
-            }
-            let hdr = buf.get(0..5).ok_or(RequestError::InvalidHeader)?;
-            if hdr[0] != 0 {
-                // Compression was not advertised as supported, so the client
-                // should not send compressed messages.
-                return Err(RequestError::InvalidHeader);
-            }
-            let len = u32::from_be_bytes(hdr[1..5].try_into().unwrap()) as usize;
-
-            buf.drain(..5);
-            while buf.len() < len {
-                let data = body.data().await.ok_or(RequestError::InvalidHeader)??;
-                buf.extend(&data);
-                body.flow_control().release_capacity(data.len()).unwrap();
-            }
-
-            let (send, recv) = mesh::oneshot();
-
-            let rpc = GenericRpc {
-                method: method.to_owned(),
-                data: buf,
-                port: send.into(),
+            // Only server streaming is supported, so read the first message
+            // and ignore the rest.
+            let data = match MessageReader::new(body).next().await {
+                Ok(Some(data)) => data,
+                Ok(None) => Err(FrameError::Truncated)?,
+                Err(err @ FrameError::TooLong(_)) => {
+                    return fail(status_from_err(Code::ResourceExhausted, err));
+                }
+                Err(err) => Err(err)?,
             };
 
-            service.send((ctx, rpc));
-
-            Ok(recv
-                .await
-                .unwrap_or_else(|err| Err(status_from_err(Code::Internal, err))))
+            Ok(service.dispatch(ctx, method.to_owned(), data))
         }
     }
 }
@@ -614,8 +648,11 @@ mod tests {
     use crate::Client;
     use crate::Server;
     use crate::client::ExistingConnection;
+    #[cfg(feature = "grpc")]
+    use crate::client::UnixDialier;
     use crate::service::Code;
     use crate::service::ServiceRpc;
+    use crate::service::Status;
     use futures::StreamExt;
     use futures::executor::block_on;
     use pal_async::DefaultPool;
@@ -690,4 +727,230 @@ fn client_server() {
         client_thread.join().unwrap();
         server_thread.join().unwrap().unwrap();
     }
+
+    async fn serve_example(mut recv: mesh::Receiver<(mesh::CancelContext, items::Example)>) {
+        while let Some((_, req)) = recv.next().await {
+            match req {
+                items::Example::Method1(input, resp) => {
+                    resp.send(Ok(items::Method1Response {
+                        foo: input.foo + "123",
+                        bar: input.bar + "456",
+                    }));
+                }
+                items::Example::Method2(_, resp) => resp.send(Ok(())),
+                items::Example::Method3(input, resp) => {
+                    for index in 0..input.count {
+                        resp.send(Ok(items::Method3Response { index }));
+                    }
+                    if input.fail {
+                        resp.send(Err(Status {
+                            code: Code::Aborted as i32,
+                            message: "failed".to_string(),
+                            details: Vec::new(),
+                        }));
+                    }
+                }
+            }
+        }
+    }
+
+    async fn check_example(client: &Client) {
+        let response = client
+            .call()
+            .start(
+                items::Example::Method1,
+                items::Method1Request {
+                    foo: "abc".to_string(),
+                    bar: "def".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(&response.foo, "abc123");
+        assert_eq!(&response.bar, "def456");
+
+        let responses = client
+            .call()
+            .start_stream(
+                items::Example::Method3,
+                items::Method3Request {
+                    count: 3,
+                    fail: false,
+                },
+            )
+            .map(|r| r.unwrap().index)
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(responses, [0, 1, 2]);
+
+        let responses = client
+            .call()
+            .start_stream(
+                items::Example::Method3,
+                items::Method3Request {
+                    count: 2,
+                    fail: true,
+                },
+            )
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[1].as_ref().unwrap().index, 1);
+        assert_eq!(
+            responses[2].as_ref().unwrap_err().code,
+            Code::Aborted as i32
+        );
+
+        let status = client
+            .call()
+            .start_raw(items::Example::NAME, "unknown", Vec::new())
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code, Code::Unimplemented as i32);
+    }
+
+    #[test]
+    fn client_server_streaming() {
+        let (c, s) = unix_socket::UnixStream::pair().unwrap();
+        let mut server = Server::new();
+        let recv = server.add_service::<items::Example>();
+        let server_thread = std::thread::spawn(move || {
+            block_with_io(async |driver| server.run_single(&driver, s).await)
+        });
+
+        let client_thread = std::thread::spawn(move || {
+            DefaultPool::run_with(async |driver| {
+                let client = Client::new(
+                    &driver,
+                    ExistingConnection::new(PolledSocket::new(&driver, c).unwrap()),
+                );
+                check_example(&client).await;
+                client.shutdown().await;
+            })
+        });
+
+        block_on(serve_example(recv));
+
+        client_thread.join().unwrap();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    #[cfg(feature = "grpc")]
+    #[test]
+    fn grpc_client_server() {
+        let path = std::env::temp_dir().join(format!("mesh_rpc_grpc_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = unix_socket::UnixListener::bind(&path).unwrap();
+        let mut server = Server::new();
+        let recv = server.add_service::<items::Example>();
+        let (stop_send, stop_recv) = mesh::oneshot();
+        let server_thread = std::thread::spawn(move || {
+            block_with_io(async |driver| server.run_grpc(&driver, listener, stop_recv).await)
+        });
+        let serve_thread = std::thread::spawn(|| block_on(serve_example(recv)));
+
+        DefaultPool::run_with(async |driver| {
+            let client = crate::client::ClientBuilder::new()
+                .grpc(true)
+                .build(&driver, UnixDialier::new(driver.clone(), &path));
+            check_example(&client).await;
+            client.shutdown().await;
+        });
+
+        stop_send.send(());
+        server_thread.join().unwrap().unwrap();
+        serve_thread.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(feature = "grpc")]
+    #[test]
+    fn grpc_message_too_long() {
+        let path = std::env::temp_dir().join(format!("mesh_rpc_grpc_long_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = unix_socket::UnixListener::bind(&path).unwrap();
+        let mut server = Server::new();
+        let recv = server.add_service::<items::Example>();
+        let (stop_send, stop_recv) = mesh::oneshot();
+        let server_thread = std::thread::spawn(move || {
+            block_with_io(async |driver| server.run_grpc(&driver, listener, stop_recv).await)
+        });
+        let serve_thread = std::thread::spawn(|| block_on(serve_example(recv)));
+
+        DefaultPool::run_with(async |driver| {
+            let client = crate::client::ClientBuilder::new()
+                .grpc(true)
+                .build(&driver, UnixDialier::new(driver.clone(), &path));
+            let status = client
+                .call()
+                .start(
+                    items::Example::Method1,
+                    items::Method1Request {
+                        foo: "a".repeat(crate::message::MAX_MESSAGE_SIZE + 1),
+                        bar: String::new(),
+                    },
+                )
+                .await
+                .unwrap_err();
+            assert_eq!(status.code, Code::ResourceExhausted as i32);
+
+            // The connection is still usable.
+            check_example(&client).await;
+            client.shutdown().await;
+        });
+
+        stop_send.send(());
+        server_thread.join().unwrap().unwrap();
+        serve_thread.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(feature = "grpc")]
+    #[test]
+    fn grpc_connection_lost() {
+        let (c, s) = unix_socket::UnixStream::pair().unwrap();
+        let server_thread = std::thread::spawn(move || {
+            block_with_io(async |driver| {
+                let stream = PolledSocket::new(&driver, s).unwrap();
+                let mut conn = h2::server::handshake(crate::grpc::TokioIo(stream))
+                    .await
+                    .unwrap();
+                // Accept the call, then drop the connection without responding.
+                let _request = conn.accept().await.unwrap().unwrap();
+            })
+        });
+
+        DefaultPool::run_with(async |driver| {
+            let client = crate::client::ClientBuilder::new().grpc(true).build(
+                &driver,
+                ExistingConnection::new(PolledSocket::new(&driver, c).unwrap()),
+            );
+            let responses = client
+                .call()
+                .start_stream(
+                    items::Example::Method3,
+                    items::Method3Request {
+                        count: 1,
+                        fail: false,
+                    },
+                )
+                .collect::<Vec<_>>()
+                .await;
+
+            // The call must fail rather than appear to complete with no
+            // responses.
+            assert_eq!(responses.len(), 1);
+            assert_eq!(
+                responses[0].as_ref().unwrap_err().code,
+                Code::Unavailable as i32
+            );
+            client.shutdown().await;
+        });
+
+        server_thread.join().unwrap();
+    }
 }
@@ -91,6 +91,18 @@ fn decode(
         port: Port,
         data: &[u8],
     ) -> std::result::Result<Self, (ServiceRpcError, Port)>;
+
+    /// Returns whether `method` is a server-streaming method.
+    ///
+    /// The response port for a server-streaming method is a
+    /// `mesh::Sender<Result<T, Status>>` instead of a
+    /// `mesh::OneshotSender<Result<T, Status>>`. The server sends zero or more
+    /// responses and then closes the channel to complete the call
+    /// successfully, or sends an error to fail it.
+    fn is_server_streaming(method: &str) -> bool {
+        let _ = method;
+        false
+    }
 }
 
 /// An error returned while decoding a method call.
@@ -17,6 +17,8 @@
 pub use sys::MappableRef;
 pub use sys::SparseMapping;
 pub use sys::alloc_shared_memory;
+#[cfg(target_os = "linux")]
+pub use sys::discard_shared_memory;
 pub use sys::new_mappable_from_file;
 
 use std::mem::MaybeUninit;
@@ -315,6 +317,32 @@ fn test_commit_idempotent() {
         assert_eq!(buf, pattern);
     }
 
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_discard_shared_memory() {
+        let page_size = SparseMapping::page_size();
+        let shmem = alloc_shared_memory(4 * page_size, "test").unwrap();
+        let mapping = SparseMapping::new(4 * page_size).unwrap();
+        mapping.map_file(0, 4 * page_size, &shmem, 0, true).unwrap();
+
+        let pattern = vec![0x5Au8; 2 * page_size];
+        mapping.write_at(0, &pattern).unwrap();
+
+        // Discard the second page.
+        discard_shared_memory(shmem.as_fd(), page_size as u64, page_size as u64).unwrap();
+
+        let mut buf = vec![0xFFu8; page_size];
+        mapping.read_at(page_size, &mut buf).unwrap();
+        assert!(
+            buf.iter().all(|&b| b == 0),
+            "discarded page should be zeros"
+        );
+
+        // The first page should still have its data.
+        mapping.read_at(0, &mut buf).unwrap();
+        assert_eq!(buf, pattern[..page_size]);
+    }
+
     #[test]
     #[cfg(target_os = "linux")]
     fn test_madvise_hugepage() {
@@ -496,3 +496,21 @@ pub fn alloc_shared_memory(size: usize, name: &str) -> io::Result<OwnedFd> {
     fd.set_len(size as u64)?;
     Ok(fd.into())
 }
+
+/// Releases the memory backing `len` bytes at `offset` in the shared memory
+/// object or file `mappable`, which then read as zero. The object's size is
+/// unchanged.
+#[cfg(target_os = "linux")]
+pub fn discard_shared_memory(mappable: MappableRef<'_>, offset: u64, len: u64) -> io::Result<()> {
+    // SAFETY: fallocate does not access process memory.
+    unsafe {
+        libc::fallocate(
+            mappable.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t,
+            len as libc::off_t,
+        )
+        .syscall_result()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,153 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Heterogeneous Memory Attribute Table (HMAT), ACPI 6.5 §5.2.28.
+
+use super::Table;
+use crate::packed_nums::*;
+use core::mem::size_of;
+use open_enum::open_enum;
+use static_assertions::const_assert_eq;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+use zerocopy::Unaligned;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes, Unaligned)]
+pub struct Hmat {
+    pub rsvd: u32_ne,
+}
+
+impl Hmat {
+    pub fn new() -> Self {
+        Self { rsvd: 0.into() }
+    }
+}
+
+impl Table for Hmat {
+    const SIGNATURE: [u8; 4] = *b"HMAT";
+}
+
+pub const HMAT_REVISION: u8 = 2;
+
+open_enum! {
+    pub enum HmatType: u16 {
+        MEMORY_PROXIMITY_DOMAIN_ATTRIBUTES = 0,
+        SYSTEM_LOCALITY_LATENCY_BANDWIDTH = 1,
+        MEMORY_SIDE_CACHE = 2,
+    }
+}
+
+/// Describes the memory in a proximity domain and, optionally, the proximity
+/// domain of the initiators (processors) attached to it.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes, Unaligned)]
+pub struct HmatMemoryProximityDomain {
+    pub typ: u16_ne,
+    pub rsvd1: u16_ne,
+    pub length: u32_ne,
+    pub flags: u16_ne,
+    pub rsvd2: u16_ne,
+    pub initiator_proximity_domain: u32_ne,
+    pub memory_proximity_domain: u32_ne,
+    pub rsvd3: u32_ne,
+    pub rsvd4: u64_ne,
+    pub rsvd5: u64_ne,
+}
+
+const_assert_eq!(size_of::<HmatMemoryProximityDomain>(), 40);
+
+/// `initiator_proximity_domain` is valid.
+pub const HMAT_INITIATOR_PROXIMITY_DOMAIN_VALID: u16 = 1 << 0;
+
+impl HmatMemoryProximityDomain {
+    pub fn new(memory_proximity_domain: u32, initiator_proximity_domain: Option<u32>) -> Self {
+        Self {
+            typ: HmatType::MEMORY_PROXIMITY_DOMAIN_ATTRIBUTES.0.into(),
+            rsvd1: 0.into(),
+            length: (size_of::<Self>() as u32).into(),
+            flags: if initiator_proximity_domain.is_some() {
+                HMAT_INITIATOR_PROXIMITY_DOMAIN_VALID
+            } else {
+                0
+            }
+            .into(),
+            rsvd2: 0.into(),
+            initiator_proximity_domain: initiator_proximity_domain.unwrap_or(0).into(),
+            memory_proximity_domain: memory_proximity_domain.into(),
+            rsvd3: 0.into(),
+            rsvd4: 0.into(),
+            rsvd5: 0.into(),
+        }
+    }
+}
+
+open_enum! {
+    #[derive(IntoBytes, Immutable, KnownLayout, FromBytes, Unaligned)]
+    pub enum HmatDataType: u8 {
+        ACCESS_LATENCY = 0,
+        READ_LATENCY = 1,
+        WRITE_LATENCY = 2,
+        ACCESS_BANDWIDTH = 3,
+        READ_BANDWIDTH = 4,
+        WRITE_BANDWIDTH = 5,
+    }
+}
+
+/// The memory hierarchy level described by a
+/// [`HmatSystemLocalityLatencyBandwidth`] structure, in the low four bits of
+/// its flags.
+pub const HMAT_MEMORY_HIERARCHY_MEMORY: u8 = 0;
+
+/// The header of a structure describing the latency or bandwidth between
+/// initiator and target proximity domains. It is followed by the list of
+/// `num_initiator_proximity_domains` initiator domains and the list of
+/// `num_target_proximity_domains` target domains, each as `u32`s, and then by
+/// a matrix of `u16` entries in initiator-major order. Each entry is
+/// multiplied by `entry_base_unit` to get the latency in picoseconds or the
+/// bandwidth in MB/s; zero means that no value is provided.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes, Unaligned)]
+pub struct HmatSystemLocalityLatencyBandwidth {
+    pub typ: u16_ne,
+    pub rsvd1: u16_ne,
+    pub length: u32_ne,
+    pub flags: u8,
+    pub data_type: HmatDataType,
+    pub min_transfer_size: u8,
+    pub rsvd2: u8,
+    pub num_initiator_proximity_domains: u32_ne,
+    pub num_target_proximity_domains: u32_ne,
+    pub rsvd3: u32_ne,
+    pub entry_base_unit: u64_ne,
+}
+
+const_assert_eq!(size_of::<HmatSystemLocalityLatencyBandwidth>(), 32);
+
+impl HmatSystemLocalityLatencyBandwidth {
+    pub fn new(
+        data_type: HmatDataType,
+        num_initiators: u32,
+        num_targets: u32,
+        entry_base_unit: u64,
+    ) -> Self {
+        let length = size_of::<Self>()
+            + (num_initiators as usize + num_targets as usize) * size_of::<u32>()
+            + num_initiators as usize * num_targets as usize * size_of::<u16>();
+        Self {
+            typ: HmatType::SYSTEM_LOCALITY_LATENCY_BANDWIDTH.0.into(),
+            rsvd1: 0.into(),
+            length: (length as u32).into(),
+            flags: HMAT_MEMORY_HIERARCHY_MEMORY,
+            data_type,
+            min_transfer_size: 0,
+            rsvd2: 0,
+            num_initiator_proximity_domains: num_initiators.into(),
+            num_target_proximity_domains: num_targets.into(),
+            rsvd3: 0.into(),
+            entry_base_unit: entry_base_unit.into(),
+        }
+    }
+}
@@ -13,9 +13,11 @@
 pub mod aspt;
 pub mod fadt;
 pub mod gtdt;
+pub mod hmat;
 pub mod madt;
 pub mod mcfg;
 pub mod pptt;
+pub mod slit;
 pub mod srat;
 
 #[expect(non_camel_case_types)]
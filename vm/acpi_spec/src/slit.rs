@@ -0,0 +1,87 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! System Locality Information Table (SLIT), ACPI 6.5 §5.2.17.
+
+use super::Table;
+use crate::packed_nums::*;
+use thiserror::Error;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+use zerocopy::Ref;
+use zerocopy::Unaligned;
+
+/// The fixed SLIT header. It is followed by a `num_localities` by
+/// `num_localities` matrix of one-byte distances, where entry `(i, j)` is the
+/// relative distance from locality (proximity domain) `i` to locality `j`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes, Unaligned)]
+pub struct Slit {
+    pub num_localities: u64_ne,
+}
+
+impl Slit {
+    pub fn new(num_localities: u64) -> Self {
+        Self {
+            num_localities: num_localities.into(),
+        }
+    }
+}
+
+impl Table for Slit {
+    const SIGNATURE: [u8; 4] = *b"SLIT";
+}
+
+pub const SLIT_REVISION: u8 = 1;
+
+/// The distance from a locality to itself. Distances to other localities
+/// must be greater than this.
+pub const SLIT_LOCAL_DISTANCE: u8 = 10;
+
+/// The distance reported for a locality that is unreachable from another.
+pub const SLIT_UNREACHABLE_DISTANCE: u8 = 0xff;
+
+#[derive(Debug, Error)]
+pub enum ParseSlitError {
+    #[error("could not read standard ACPI header")]
+    MissingAcpiHeader,
+    #[error("invalid signature. expected b\"SLIT\", found {0:?}")]
+    InvalidSignature([u8; 4]),
+    #[error("mismatched length, header: {0}, actual: {1}")]
+    MismatchedLength(usize, usize),
+    #[error("could not read fixed SLIT header")]
+    MissingFixedHeader,
+    #[error("distance matrix is {0} bytes, expected {1}x{1}")]
+    BadMatrix(usize, u64),
+}
+
+/// Parses a SLIT, returning its headers and the distance matrix in row-major
+/// order.
+pub fn parse_slit(raw_slit: &[u8]) -> Result<(&crate::Header, &Slit, &[u8]), ParseSlitError> {
+    let raw_slit_len = raw_slit.len();
+    let (acpi_header, buf) = Ref::<_, crate::Header>::from_prefix(raw_slit)
+        .map_err(|_| ParseSlitError::MissingAcpiHeader)?;
+
+    if acpi_header.signature != *b"SLIT" {
+        return Err(ParseSlitError::InvalidSignature(acpi_header.signature));
+    }
+
+    if acpi_header.length.get() as usize != raw_slit_len {
+        return Err(ParseSlitError::MismatchedLength(
+            acpi_header.length.get() as usize,
+            raw_slit_len,
+        ));
+    }
+
+    let (slit, matrix) =
+        Ref::<_, Slit>::from_prefix(buf).map_err(|_| ParseSlitError::MissingFixedHeader)?;
+
+    let n = slit.num_localities.get();
+    if n.checked_mul(n) != Some(matrix.len() as u64) {
+        return Err(ParseSlitError::BadMatrix(matrix.len(), n));
+    }
+
+    Ok((Ref::into_ref(acpi_header), Ref::into_ref(slit), matrix))
+}
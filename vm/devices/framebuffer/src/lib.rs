@@ -37,6 +37,7 @@
 use std::convert::Infallible;
 use std::io;
 use std::sync::Arc;
+use video_core::DirtyRect;
 use video_core::FramebufferControl;
 use video_core::FramebufferFormat;
 use video_core::ResolvedFramebuffer;
@@ -62,6 +63,15 @@ fn default_framebuffer_format() -> FramebufferFormat {
 // TODO: Make framebuffer size variable. See DetermineSynthVideoVramSize() in OS repo
 pub const FRAMEBUFFER_SIZE: usize = 8 * 1024 * 1024; // 8 MB
 
+/// The number of batches of dirty regions that can be in flight to the view.
+/// Beyond this, regions are coalesced until the view catches up, so that a
+/// view that is not being read does not accumulate them without bound.
+const DIRTY_WINDOW: usize = 8;
+
+/// The number of pending dirty regions beyond which they are coalesced into
+/// their bounding box.
+const MAX_PENDING_DIRTY_RECTS: usize = 64;
+
 /// Creates a framebuffer and an object that can be used to read from it.
 /// The framebuffer should be allocated prior to calling this function.
 ///
@@ -85,17 +95,26 @@ pub fn framebuffer(
     );
 
     let (send, recv) = mesh::channel();
+    let (dirty_send, dirty_recv) = mesh::channel();
+    let (dirty_ready_send, dirty_ready_recv) = mesh::channel();
+    for _ in 0..DIRTY_WINDOW {
+        dirty_ready_send.send(());
+    }
 
     let fb = Framebuffer {
         vram: vram.try_clone()?,
         len,
         format_send: send,
+        dirty_send,
+        dirty_ready: dirty_ready_recv,
     };
     let access = FramebufferAccess {
         vram,
         len,
         format_recv: recv,
         offset,
+        dirty_recv,
+        dirty_ready: dirty_ready_send,
     };
     Ok((fb, access))
 }
@@ -106,6 +125,8 @@ pub struct Framebuffer {
     vram: Mappable,
     len: usize,
     format_send: mesh::Sender<FramebufferFormat>,
+    dirty_send: mesh::Sender<Vec<DirtyRect>>,
+    dirty_ready: mesh::Receiver<()>,
 }
 
 impl Framebuffer {
@@ -127,6 +148,8 @@ pub struct FramebufferAccess {
     len: usize,
     format_recv: mesh::Receiver<FramebufferFormat>,
     offset: u64,
+    dirty_recv: mesh::Receiver<Vec<DirtyRect>>,
+    dirty_ready: mesh::Sender<()>,
 }
 
 impl FramebufferAccess {
@@ -141,6 +164,9 @@ pub fn view(self) -> io::Result<View> {
             vram: self.vram,
             len: self.len,
             offset: self.offset,
+            dirty_recv: self.dirty_recv,
+            dirty_ready: self.dirty_ready,
+            reports_dirty: false,
         })
     }
 }
@@ -154,6 +180,9 @@ pub struct View {
     vram: Mappable,
     len: usize,
     offset: u64,
+    dirty_recv: mesh::Receiver<Vec<DirtyRect>>,
+    dirty_ready: mesh::Sender<()>,
+    reports_dirty: bool,
 }
 
 impl View {
@@ -180,6 +209,9 @@ pub fn resolution(&mut self) -> (u16, u16) {
         // message to avoid possible high memory use.
         while let Ok(format) = self.format_recv.try_recv() {
             self.format = Some(format);
+            // The new mode may be drawn by something that does not report
+            // dirty regions, such as the firmware.
+            self.reports_dirty = false;
         }
         if let Some(format) = &self.format {
             (format.width as u16, format.height as u16)
@@ -188,6 +220,22 @@ pub fn resolution(&mut self) -> (u16, u16) {
         }
     }
 
+    /// Appends the regions of the framebuffer that the guest has reported as
+    /// updated since the last call to `rects`.
+    ///
+    /// Returns `false` if the guest has not reported any regions since the
+    /// format last changed, in which case the caller must find the updated
+    /// regions itself. Even when this returns `true`, the guest may have
+    /// updated regions it did not report.
+    pub fn take_dirty(&mut self, rects: &mut Vec<DirtyRect>) -> bool {
+        while let Ok(batch) = self.dirty_recv.try_recv() {
+            rects.extend(batch);
+            self.reports_dirty = true;
+            self.dirty_ready.send(());
+        }
+        self.reports_dirty
+    }
+
     /// Gets the framebuffer access back.
     pub fn access(self) -> FramebufferAccess {
         // Put the current format at the head of the channel.
@@ -201,6 +249,8 @@ pub fn access(self) -> FramebufferAccess {
             len: self.len,
             format_recv: recv,
             offset: self.offset,
+            dirty_recv: self.dirty_recv,
+            dirty_ready: self.dirty_ready,
         }
     }
 }
@@ -231,6 +281,9 @@ struct FramebufferInner {
     format: FramebufferFormat,
     #[inspect(skip)]
     mapper: Box<dyn MemoryMapper>,
+    /// Dirty regions waiting for the view to be ready for them.
+    #[inspect(with = "Vec::len")]
+    dirty: Vec<DirtyRect>,
 }
 
 #[derive(Inspect)]
@@ -300,6 +353,7 @@ pub fn new(
                 format,
                 framebuffer: Some(framebuffer),
                 mapper,
+                dirty: Vec::new(),
             })),
             len,
         })
@@ -462,6 +516,38 @@ pub fn set_format(&mut self, format: FramebufferFormat) {
         }
     }
 
+    /// Reports regions of the framebuffer that the guest has updated.
+    pub fn dirty(&mut self, rects: &[DirtyRect]) {
+        let mut inner = self.inner.lock();
+        let inner = &mut *inner;
+        let Some(framebuffer) = &mut inner.framebuffer else {
+            return;
+        };
+        inner.dirty.extend_from_slice(rects);
+        if inner.dirty.len() > MAX_PENDING_DIRTY_RECTS {
+            let bounds = inner
+                .dirty
+                .iter()
+                .copied()
+                .reduce(|a, b| DirtyRect {
+                    left: a.left.min(b.left),
+                    top: a.top.min(b.top),
+                    right: a.right.max(b.right),
+                    bottom: a.bottom.max(b.bottom),
+                })
+                .unwrap();
+            inner.dirty.clear();
+            inner.dirty.push(bounds);
+        }
+        // Send the regions if the view has room for them. Otherwise, keep
+        // them until the next report.
+        if !inner.dirty.is_empty() && framebuffer.dirty_ready.try_recv().is_ok() {
+            framebuffer
+                .dirty_send
+                .send(std::mem::take(&mut inner.dirty));
+        }
+    }
+
     /// Gets a `GuestMemory` object that can be used to access the framebuffer
     /// memory.
     pub fn memory(&self) -> io::Result<GuestMemory> {
@@ -489,6 +575,21 @@ async fn unmap(&mut self) {
     async fn set_format(&mut self, format: FramebufferFormat) {
         self.set_format(format);
     }
+    async fn dirty(&mut self, rects: &[DirtyRect]) {
+        self.dirty(rects);
+    }
+    fn vram(&self) -> Option<(GuestMemory, usize)> {
+        match self.memory() {
+            Ok(mem) => Some((mem, self.len)),
+            Err(err) => {
+                tracing::error!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to map framebuffer memory"
+                );
+                None
+            }
+        }
+    }
 }
 
 impl ResolveResource<FramebufferHandleKind, SharedFramebufferHandle> for FramebufferLocalControl {
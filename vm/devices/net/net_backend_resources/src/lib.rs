@@ -141,3 +141,43 @@ impl ResourceId<NetEndpointHandleKind> for TapHandle {
         const ID: &'static str = "tap";
     }
 }
+
+/// Packet capture wrapper.
+pub mod packet_capture {
+    use mesh::MeshPayload;
+    use mesh::rpc::FailableRpc;
+    use vm_resource::Resource;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::NetEndpointHandleKind;
+
+    /// A request to start or stop a packet capture.
+    #[derive(Debug, MeshPayload)]
+    pub enum PacketCaptureRequest {
+        /// Start writing packets to `file` in pcapng format, replacing any
+        /// capture in progress.
+        Start {
+            /// The file to write to.
+            file: std::fs::File,
+            /// The maximum number of bytes to capture from each packet.
+            snaplen: u32,
+        },
+        /// Stop the capture in progress, if any.
+        Stop,
+    }
+
+    /// Handle to an endpoint that wraps another endpoint and can capture the
+    /// packets sent and received through it.
+    #[derive(MeshPayload)]
+    pub struct PacketCaptureHandle {
+        /// An identifier for the endpoint, used in traces.
+        pub id: String,
+        /// The wrapped endpoint.
+        pub endpoint: Resource<NetEndpointHandleKind>,
+        /// Channel for starting and stopping captures.
+        pub requests: mesh::Receiver<FailableRpc<PacketCaptureRequest, ()>>,
+    }
+
+    impl ResourceId<NetEndpointHandleKind> for PacketCaptureHandle {
+        const ID: &'static str = "packet_capture";
+    }
+}
@@ -6,6 +6,8 @@
 #![expect(missing_docs)]
 #![forbid(unsafe_code)]
 
+pub mod resolver;
+
 use async_trait::async_trait;
 use futures::FutureExt;
 use futures::StreamExt;
@@ -14,6 +16,7 @@
 use inspect::InspectMut;
 use mesh::error::RemoteError;
 use mesh::rpc::FailableRpc;
+use mesh::rpc::Rpc;
 use mesh::rpc::RpcSend;
 use net_backend::BufferAccess;
 use net_backend::Endpoint;
@@ -28,6 +31,7 @@
 use net_backend::TxOffloadSupport;
 use net_backend::TxSegment;
 use net_backend::next_packet;
+use net_backend_resources::packet_capture::PacketCaptureRequest;
 use pcap_file::DataLink;
 use pcap_file::PcapError;
 use pcap_file::PcapResult;
@@ -132,6 +136,13 @@ fn new_with_stop() -> Self {
             writer: None,
         }
     }
+
+    fn from_request(request: PacketCaptureRequest) -> Self {
+        match request {
+            PacketCaptureRequest::Start { file, snaplen } => Self::new_with_start(snaplen, file),
+            PacketCaptureRequest::Stop => Self::new_with_stop(),
+        }
+    }
 }
 
 enum PacketCaptureEndpointCommand {
@@ -189,6 +200,8 @@ pub struct PacketCaptureEndpoint {
     id: String,
     endpoint: Box<dyn Endpoint>,
     control_rx: Arc<Mutex<mesh::Receiver<PacketCaptureEndpointCommand>>>,
+    /// Requests from outside the process, see [`Self::with_requests`].
+    requests: Option<mesh::Receiver<FailableRpc<PacketCaptureRequest, ()>>>,
     pcap: Arc<Pcap>,
 }
 
@@ -210,12 +223,23 @@ pub fn new(endpoint: Box<dyn Endpoint>, id: String) -> (Self, PacketCaptureEndpo
                 id,
                 endpoint,
                 control_rx: Arc::new(Mutex::new(control_rx)),
+                requests: None,
                 pcap,
             },
             control,
         )
     }
 
+    /// Also handles capture requests from `requests`, which, unlike
+    /// [`PacketCaptureEndpointControl`], can be sent from another process.
+    pub fn with_requests(
+        mut self,
+        requests: mesh::Receiver<FailableRpc<PacketCaptureRequest, ()>>,
+    ) -> Self {
+        self.requests = Some(requests);
+        self
+    }
+
     fn current(&self) -> &dyn Endpoint {
         self.endpoint.as_ref()
     }
@@ -287,7 +311,7 @@ async fn get_data_path_to_guest_vf(&self) -> anyhow::Result<bool> {
 
     async fn wait_for_endpoint_action(&mut self) -> EndpointAction {
         enum Message {
-            PacketCaptureEndpointCommand(PacketCaptureEndpointCommand),
+            PacketCapture(PacketCaptureOptions, Rpc<(), Result<(), RemoteError>>),
             UpdateFromEndpoint(EndpointAction),
         }
         loop {
@@ -295,23 +319,42 @@ enum Message {
             let mut receive_update = receiver.lock().await;
             let update = async {
                 match receive_update.next().await {
-                    Some(m) => Message::PacketCaptureEndpointCommand(m),
+                    Some(PacketCaptureEndpointCommand::PacketCapture(rpc)) => {
+                        let (options, response) = rpc.split();
+                        Message::PacketCapture(options, response)
+                    }
                     None => {
                         std::future::pending::<()>().await;
                         unreachable!()
                     }
                 }
             };
+            let requests = &mut self.requests;
+            let request = async {
+                match requests {
+                    Some(recv) => match recv.next().await {
+                        Some(rpc) => {
+                            let (request, response) = rpc.split();
+                            Message::PacketCapture(
+                                PacketCaptureOptions::from_request(request),
+                                response,
+                            )
+                        }
+                        None => {
+                            *requests = None;
+                            std::future::pending().await
+                        }
+                    },
+                    None => std::future::pending().await,
+                }
+            };
             let ep_update = self
-                .current_mut()
+                .endpoint
                 .wait_for_endpoint_action()
                 .map(Message::UpdateFromEndpoint);
-            let m = (update, ep_update).race().await;
+            let m = (update, request, ep_update).race().await;
             match m {
-                Message::PacketCaptureEndpointCommand(
-                    PacketCaptureEndpointCommand::PacketCapture(rpc),
-                ) => {
-                    let (options, response) = rpc.split();
+                Message::PacketCapture(options, response) => {
                     let result = async {
                         let id = &self.id;
                         let start = match options.operation {
@@ -567,3 +610,49 @@ fn inspect_mut(&mut self, req: inspect::Request<'_>) {
         self.current_mut().inspect_mut(req)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::join;
+    use net_backend::null::NullEndpoint;
+
+    #[pal_async::async_test]
+    async fn capture_requests() {
+        let (endpoint, _control) =
+            PacketCaptureEndpoint::new(Box::new(NullEndpoint::new()), "test".into());
+        let (send, recv) = mesh::channel();
+        let mut endpoint = endpoint.with_requests(recv);
+        let file = tempfile::tempfile().unwrap();
+
+        // Starting a capture restarts the queues to begin capturing.
+        let (action, result) = join(
+            endpoint.wait_for_endpoint_action(),
+            send.call_failable(
+                |x| x,
+                PacketCaptureRequest::Start {
+                    file: file.try_clone().unwrap(),
+                    snaplen: 128,
+                },
+            ),
+        )
+        .await;
+        result.unwrap();
+        assert!(matches!(action, EndpointAction::RestartRequired));
+        assert!(endpoint.pcap.enabled.load(Ordering::Relaxed));
+        assert_eq!(endpoint.pcap.snaplen.load(Ordering::Relaxed), 128);
+        assert!(endpoint.pcap.pcap_writer.lock().is_some());
+        // The section header is written when the capture starts.
+        assert_ne!(file.metadata().unwrap().len(), 0);
+
+        let (action, result) = join(
+            endpoint.wait_for_endpoint_action(),
+            send.call_failable(|x| x, PacketCaptureRequest::Stop),
+        )
+        .await;
+        result.unwrap();
+        assert!(matches!(action, EndpointAction::RestartRequired));
+        assert!(!endpoint.pcap.enabled.load(Ordering::Relaxed));
+        assert!(endpoint.pcap.pcap_writer.lock().is_none());
+    }
+}
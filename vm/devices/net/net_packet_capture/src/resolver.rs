@@ -0,0 +1,39 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource resolver for the packet capture endpoint.
+
+use crate::PacketCaptureEndpoint;
+use async_trait::async_trait;
+use net_backend::resolve::ResolveEndpointParams;
+use net_backend::resolve::ResolvedEndpoint;
+use net_backend_resources::packet_capture::PacketCaptureHandle;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::NetEndpointHandleKind;
+
+/// A resolver for [`PacketCaptureHandle`].
+pub struct PacketCaptureResolver;
+
+declare_static_async_resolver! {
+    PacketCaptureResolver,
+    (NetEndpointHandleKind, PacketCaptureHandle),
+}
+
+#[async_trait]
+impl AsyncResolveResource<NetEndpointHandleKind, PacketCaptureHandle> for PacketCaptureResolver {
+    type Output = ResolvedEndpoint;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: PacketCaptureHandle,
+        input: ResolveEndpointParams,
+    ) -> Result<Self::Output, Self::Error> {
+        let inner = resolver.resolve(resource.endpoint, input).await?;
+        let (endpoint, _control) = PacketCaptureEndpoint::new(inner.0, resource.id);
+        Ok(endpoint.with_requests(resource.requests).into())
+    }
+}
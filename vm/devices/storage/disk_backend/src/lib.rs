@@ -69,6 +69,9 @@
 //! | `DelayDisk` | `disk_delay` | Injected I/O latency wrapper |
 //! | `DiskWithReservations` | `disk_prwrap` | In-memory PR emulation wrapper |
 //! | `LayeredDisk` | `disk_layered` | Layered disk with per-sector presence |
+//! | `Qcow2DiskLayer` | `disk_qcow2` | QCOW2 image, as a `LayeredDisk` layer |
+//! | `VhdxDiskLayer` | `disk_vhdx` | VHDX image, as a `LayeredDisk` layer |
+//! | `DynamicVhd1Layer` | `disk_vhd1` | Dynamic or differencing VHD1, as a `LayeredDisk` layer |
 
 #![forbid(unsafe_code)]
 
@@ -69,6 +69,48 @@ impl ResourceId<DiskHandleKind> for FixedVhd1DiskHandle {
     const ID: &'static str = "fixed_vhd1";
 }
 
+/// Disk handle for a dynamic or differencing VHD1 image.
+#[derive(MeshPayload)]
+pub struct DynamicVhd1DiskHandle {
+    /// The image file.
+    pub file: std::fs::File,
+    /// The parent disk of a differencing image. This is always opened
+    /// read-only.
+    pub parent: Option<Resource<DiskHandleKind>>,
+}
+
+impl ResourceId<DiskHandleKind> for DynamicVhd1DiskHandle {
+    const ID: &'static str = "dynamic_vhd1";
+}
+
+/// Disk handle for a QCOW2 image.
+#[derive(MeshPayload)]
+pub struct Qcow2DiskHandle {
+    /// The image file.
+    pub file: std::fs::File,
+    /// The disk named by the image's backing file, if any. This is always
+    /// opened read-only.
+    pub backing: Option<Resource<DiskHandleKind>>,
+}
+
+impl ResourceId<DiskHandleKind> for Qcow2DiskHandle {
+    const ID: &'static str = "qcow2";
+}
+
+/// Disk handle for a VHDX image.
+#[derive(MeshPayload)]
+pub struct VhdxDiskHandle {
+    /// The image file.
+    pub file: std::fs::File,
+    /// The parent disk of a differencing image. This is always opened
+    /// read-only.
+    pub parent: Option<Resource<DiskHandleKind>>,
+}
+
+impl ResourceId<DiskHandleKind> for VhdxDiskHandle {
+    const ID: &'static str = "vhdx";
+}
+
 /// Disk configuration for a striped disk.
 #[derive(MeshPayload)]
 pub struct StripedDiskHandle {
@@ -157,6 +199,9 @@ pub struct DiskLayerDescription {
     pub read_cache: bool,
     /// If true, writes are written both to this layer and the next one.
     pub write_through: bool,
+    /// If true, the layer is opened for write even though writes to the disk
+    /// do not reach it, so that the layer above it can be committed into it.
+    pub writable: bool,
 }
 
 impl From<Resource<DiskLayerHandleKind>> for DiskLayerDescription {
@@ -165,6 +210,7 @@ fn from(layer: Resource<DiskLayerHandleKind>) -> Self {
             layer,
             read_cache: false,
             write_through: false,
+            writable: false,
         }
     }
 }
@@ -2,9 +2,12 @@
 // Licensed under the MIT License.
 
 #![expect(missing_docs)]
-#![forbid(unsafe_code)]
+// UNSAFETY: Issuing io_uring IOs on Linux.
+#![cfg_attr(not(target_os = "linux"), forbid(unsafe_code))]
 
 mod readwriteat;
+#[cfg(target_os = "linux")]
+mod uring;
 
 use self::readwriteat::ReadWriteAt;
 use blocking::unblock;
@@ -44,10 +47,13 @@ fn resolve(
         rsrc: FileDiskHandle,
         input: ResolveDiskParameters<'_>,
     ) -> Result<Self::Output, Self::Error> {
-        ResolvedDisk::new(
-            FileDisk::open(rsrc.0, input.read_only).map_err(ResolveFileDiskError::Io)?,
-        )
-        .map_err(ResolveFileDiskError::InvalidDisk)
+        #[cfg_attr(not(target_os = "linux"), expect(unused_mut))]
+        let mut disk = FileDisk::open(rsrc.0, input.read_only).map_err(ResolveFileDiskError::Io)?;
+        #[cfg(target_os = "linux")]
+        if !disk.set_io_uring_driver(input.driver_source.current()) {
+            tracing::debug!("io_uring unavailable, using blocking file IO");
+        }
+        ResolvedDisk::new(disk).map_err(ResolveFileDiskError::InvalidDisk)
     }
 }
 
@@ -56,6 +62,8 @@ pub struct FileDisk {
     file: Arc<fs::File>,
     metadata: Metadata,
     sector_shift: u32,
+    #[cfg(target_os = "linux")]
+    io_uring: Option<uring::Uring>,
 }
 
 #[derive(Debug, Inspect)]
@@ -90,9 +98,25 @@ pub fn with_metadata(file: fs::File, metadata: Metadata) -> Self {
             file: Arc::new(file),
             metadata,
             sector_shift,
+            #[cfg(target_os = "linux")]
+            io_uring: None,
         }
     }
 
+    /// Issues IOs as io_uring operations on `driver`, rather than as blocking
+    /// calls on the thread pool.
+    ///
+    /// This also enables FUA and unmap (by punching holes in the file), and
+    /// makes reads skip over holes.
+    ///
+    /// Returns false, leaving the disk on the thread pool, if `driver` does
+    /// not support io_uring.
+    #[cfg(target_os = "linux")]
+    pub fn set_io_uring_driver(&mut self, driver: impl pal_async::driver::Driver) -> bool {
+        self.io_uring = uring::Uring::new(driver, &self.file);
+        self.io_uring.is_some()
+    }
+
     pub fn into_inner(self) -> fs::File {
         Arc::try_unwrap(self.file).expect("no outstanding IOs")
     }
@@ -103,9 +127,13 @@ pub async fn read(&self, buffers: &RequestBuffers<'_>, sector: u64) -> Result<()
         if ((sector << self.sector_shift) + buffers.len() as u64) > self.metadata.disk_size {
             return Err(DiskError::IllegalBlock);
         }
+        let offset = sector << self.sector_shift;
+        #[cfg(target_os = "linux")]
+        if let Some(io_uring) = &self.io_uring {
+            return io_uring.read(&self.file, buffers, offset).await;
+        }
         let mut buffer = vec![0; buffers.len()];
         let file = self.file.clone();
-        let offset = sector << self.sector_shift;
         let buffer = unblock(move || -> Result<_, std::io::Error> {
             file.read_at(&mut buffer, offset)?;
             Ok(buffer)
@@ -120,15 +148,21 @@ pub async fn write(
         &self,
         buffers: &RequestBuffers<'_>,
         sector: u64,
-        _fua: bool,
+        fua: bool,
     ) -> Result<(), DiskError> {
         if ((sector << self.sector_shift) + buffers.len() as u64) > self.metadata.disk_size {
             return Err(DiskError::IllegalBlock);
         }
+        let offset = sector << self.sector_shift;
+        #[cfg(target_os = "linux")]
+        if let Some(io_uring) = &self.io_uring {
+            return io_uring.write(&self.file, buffers, offset, fua).await;
+        }
+        // The thread pool path cannot honor FUA, and reports as much.
+        let _ = fua;
         let mut buffer = vec![0; buffers.len()];
         let file = self.file.clone();
         buffers.reader().read(&mut buffer)?;
-        let offset = sector << self.sector_shift;
         unblock(move || file.write_at(&buffer, offset))
             .await
             .map_err(DiskError::Io)?;
@@ -136,6 +170,10 @@ pub async fn write(
     }
 
     pub async fn flush(&self) -> Result<(), DiskError> {
+        #[cfg(target_os = "linux")]
+        if let Some(io_uring) = &self.io_uring {
+            return io_uring.flush(&self.file).await;
+        }
         let file = self.file.clone();
         unblock(move || file.sync_all())
             .await
@@ -170,6 +208,10 @@ fn physical_sector_size(&self) -> u32 {
     }
 
     fn is_fua_respected(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        if self.io_uring.is_some() {
+            return true;
+        }
         false
     }
 
@@ -196,14 +238,121 @@ async fn sync_cache(&self) -> Result<(), DiskError> {
 
     async fn unmap(
         &self,
-        _sector: u64,
-        _count: u64,
+        sector: u64,
+        count: u64,
         _block_level_only: bool,
     ) -> Result<(), DiskError> {
+        if sector
+            .checked_add(count)
+            .is_none_or(|end| end > self.sector_count())
+        {
+            return Err(DiskError::IllegalBlock);
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(io_uring) = &self.io_uring {
+            return io_uring
+                .unmap(
+                    &self.file,
+                    sector << self.sector_shift,
+                    count << self.sector_shift,
+                )
+                .await;
+        }
         Ok(())
     }
 
     fn unmap_behavior(&self) -> disk_backend::UnmapBehavior {
+        // Punching a hole zeroes the range, but the file system may not
+        // support it, in which case the unmap is dropped.
+        #[cfg(target_os = "linux")]
+        if self.io_uring.as_ref().is_some_and(|u| u.supports_unmap()) {
+            return disk_backend::UnmapBehavior::Unspecified;
+        }
         disk_backend::UnmapBehavior::Ignored
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disk_backend::UnmapBehavior;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use scsi_buffers::OwnedRequestBuffers;
+
+    fn new_disk() -> FileDisk {
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(0x10000).unwrap();
+        FileDisk::open(file, false).unwrap()
+    }
+
+    async fn read_pages(disk: &FileDisk, mem: &GuestMemory) -> Vec<u8> {
+        mem.fill_at(0x1000, 0xff, 0x3000).unwrap();
+        disk.read_vectored(
+            &OwnedRequestBuffers::linear(0x1000, 0x3000, true).buffer(mem),
+            0,
+        )
+        .await
+        .unwrap();
+        let mut buf = vec![0; 0x3000];
+        mem.read_at(0x1000, &mut buf).unwrap();
+        buf
+    }
+
+    /// Writes the second page of a sparse file and checks that it reads back
+    /// surrounded by zeroes.
+    async fn check_io(disk: &FileDisk, mem: &GuestMemory) {
+        mem.fill_at(0, 0xcc, 0x1000).unwrap();
+        disk.write_vectored(
+            &OwnedRequestBuffers::linear(0, 0x1000, false).buffer(mem),
+            8,
+            true,
+        )
+        .await
+        .unwrap();
+        disk.sync_cache().await.unwrap();
+
+        let buf = read_pages(disk, mem).await;
+        assert!(buf[..0x1000].iter().all(|&b| b == 0));
+        assert!(buf[0x1000..0x2000].iter().all(|&b| b == 0xcc));
+        assert!(buf[0x2000..].iter().all(|&b| b == 0));
+    }
+
+    #[async_test]
+    async fn test_thread_pool_io() {
+        let disk = new_disk();
+        check_io(&disk, &GuestMemory::allocate(0x4000)).await;
+        assert!(!disk.is_fua_respected());
+        assert_eq!(disk.unmap_behavior(), UnmapBehavior::Ignored);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[async_test]
+    async fn test_io_uring_io() {
+        let pool = match pal_uring::IoUringPool::new("test", 16) {
+            Ok(pool) => pool,
+            Err(err) => {
+                println!("Test case skipped (no io_uring support: {err})");
+                return;
+            }
+        };
+        let initiator = pool.client().initiator().clone();
+        std::thread::spawn(|| pool.run());
+
+        let mut disk = new_disk();
+        assert!(disk.set_io_uring_driver(initiator));
+        assert!(disk.is_fua_respected());
+
+        let mem = GuestMemory::allocate(0x4000);
+        check_io(&disk, &mem).await;
+
+        disk.unmap(8, 8, false).await.unwrap();
+        if disk.unmap_behavior() != UnmapBehavior::Ignored {
+            assert!(read_pages(&disk, &mem).await.iter().all(|&b| b == 0));
+        }
+        assert!(matches!(
+            disk.unmap(0x80, 1, false).await,
+            Err(DiskError::IllegalBlock)
+        ));
+    }
+}
@@ -0,0 +1,280 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The io_uring IO path for [`FileDisk`](super::FileDisk) on Linux.
+//!
+//! IOs are issued as SQEs on the disk's driver rather than as blocking calls
+//! on the thread pool. The driver's ring collects the SQEs of all outstanding
+//! IOs and submits them to the kernel together, so concurrent requests (and
+//! the segments of a single read of a sparse file) are submitted in batches.
+
+// UNSAFETY: Issuing IOs and calling lseek.
+#![expect(unsafe_code)]
+
+use disk_backend::DiskError;
+use guestmem::MemoryWrite;
+use inspect::Inspect;
+use io_uring::opcode;
+use io_uring::types;
+use pal_async::driver::Driver;
+use scsi_buffers::RequestBuffers;
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::os::unix::prelude::*;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+#[derive(Inspect)]
+pub(crate) struct Uring {
+    #[inspect(skip)]
+    driver: Box<dyn Driver>,
+    /// Whether unmaps punch holes in the file. Cleared if the file system
+    /// turns out not to support it.
+    punch_hole: AtomicBool,
+    /// Whether the file may have holes, in which case reads skip them.
+    sparse: AtomicBool,
+}
+
+impl Debug for Uring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Uring")
+            .field("punch_hole", &self.punch_hole)
+            .field("sparse", &self.sparse)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Uring {
+    /// Returns the io_uring IO path for `file`, or `None` if `driver` does
+    /// not support io_uring.
+    pub fn new(driver: impl Driver, file: &fs::File) -> Option<Self> {
+        if ![
+            opcode::Readv::CODE,
+            opcode::Writev::CODE,
+            opcode::Fsync::CODE,
+        ]
+        .into_iter()
+        .all(|code| driver.io_uring_probe(code))
+        {
+            return None;
+        }
+        // A file with fewer blocks allocated than its length has holes. If
+        // this can't be determined, assume it does.
+        let sparse = match file.metadata() {
+            Ok(metadata) => metadata.blocks() * 512 < metadata.len(),
+            Err(_) => true,
+        };
+        Some(Self {
+            punch_hole: driver.io_uring_probe(opcode::Fallocate::CODE).into(),
+            sparse: sparse.into(),
+            driver: Box::new(driver),
+        })
+    }
+
+    /// Returns whether unmaps may punch holes in the file.
+    pub fn supports_unmap(&self) -> bool {
+        self.punch_hole.load(Ordering::Relaxed)
+    }
+
+    pub async fn read(
+        &self,
+        file: &Arc<fs::File>,
+        buffers: &RequestBuffers<'_>,
+        offset: u64,
+    ) -> Result<(), DiskError> {
+        let range = offset..offset + buffers.len() as u64;
+        let data = if self.sparse.load(Ordering::Relaxed) {
+            // Seeking for holes can block on the file system, so keep it off
+            // the executor.
+            let file = file.clone();
+            let range = range.clone();
+            blocking::unblock(move || data_ranges(&file, range))
+                .await
+                .map_err(DiskError::Io)?
+        } else {
+            vec![range.clone()]
+        };
+
+        // Zero the holes, then read the data ranges all at once.
+        let subrange = |r: &Range<u64>| {
+            buffers.subrange((r.start - offset) as usize, (r.end - r.start) as usize)
+        };
+        let zero = |hole: Range<u64>| -> Result<(), DiskError> {
+            if !hole.is_empty() {
+                subrange(&hole)
+                    .writer()
+                    .zero((hole.end - hole.start) as usize)?;
+            }
+            Ok(())
+        };
+        let mut next = range.start;
+        for r in &data {
+            zero(next..r.start)?;
+            next = r.end;
+        }
+        zero(next..range.end)?;
+
+        futures::future::try_join_all(
+            data.iter()
+                .filter(|r| !r.is_empty())
+                .map(|r| self.read_range(file, subrange(r), r.start)),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn read_range(
+        &self,
+        file: &fs::File,
+        buffers: RequestBuffers<'_>,
+        offset: u64,
+    ) -> Result<(), DiskError> {
+        let mut done = 0;
+        while done < buffers.len() {
+            let remaining = buffers.subrange(done, buffers.len() - done);
+            let locked = remaining.lock(true)?;
+            let io_vecs = locked.io_vecs();
+            // SAFETY: `io_vecs` and the underlying locked pages are locals in
+            // this `async fn`--they are part of the same state machine as the
+            // returned future and will not be freed before it completes or is
+            // dropped (which aborts).
+            let bytes_read = unsafe {
+                self.driver.io_uring_submit(
+                    opcode::Readv::new(
+                        types::Fd(file.as_raw_fd()),
+                        io_vecs.as_ptr().cast(),
+                        io_vecs.len() as u32,
+                    )
+                    .offset(offset + done as u64)
+                    .build(),
+                )
+            }
+            .await
+            .map_err(DiskError::Io)?;
+            // A short read is retried for the rest of the buffer, unless it
+            // reached the end of the file.
+            if bytes_read == 0 {
+                return Err(DiskError::Io(io::ErrorKind::UnexpectedEof.into()));
+            }
+            done += bytes_read as usize;
+        }
+        Ok(())
+    }
+
+    pub async fn write(
+        &self,
+        file: &fs::File,
+        buffers: &RequestBuffers<'_>,
+        offset: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        let mut done = 0;
+        while done < buffers.len() {
+            let remaining = buffers.subrange(done, buffers.len() - done);
+            let locked = remaining.lock(false)?;
+            let io_vecs = locked.io_vecs();
+            // SAFETY: `io_vecs` and the underlying locked pages are locals in
+            // this `async fn`--they are part of the same state machine as the
+            // returned future and will not be freed before it completes or is
+            // dropped (which aborts).
+            let bytes_written = unsafe {
+                self.driver.io_uring_submit(
+                    opcode::Writev::new(
+                        types::Fd(file.as_raw_fd()),
+                        io_vecs.as_ptr().cast(),
+                        io_vecs.len() as u32,
+                    )
+                    .offset(offset + done as u64)
+                    .rw_flags(if fua { libc::RWF_DSYNC } else { 0 })
+                    .build(),
+                )
+            }
+            .await
+            .map_err(DiskError::Io)?;
+            // A short write is retried for the rest of the buffer.
+            if bytes_written == 0 {
+                return Err(DiskError::Io(io::ErrorKind::WriteZero.into()));
+            }
+            done += bytes_written as usize;
+        }
+        Ok(())
+    }
+
+    pub async fn flush(&self, file: &fs::File) -> Result<(), DiskError> {
+        // SAFETY: No data buffers.
+        unsafe {
+            self.driver
+                .io_uring_submit(opcode::Fsync::new(types::Fd(file.as_raw_fd())).build())
+        }
+        .await
+        .map_err(DiskError::Io)?;
+        Ok(())
+    }
+
+    pub async fn unmap(&self, file: &fs::File, offset: u64, len: u64) -> Result<(), DiskError> {
+        if !self.supports_unmap() {
+            return Ok(());
+        }
+        // Mark the file sparse before punching the hole so that reads that
+        // race with the unmap look for it.
+        self.sparse.store(true, Ordering::Relaxed);
+        // SAFETY: No data buffers.
+        let r = unsafe {
+            self.driver.io_uring_submit(
+                opcode::Fallocate::new(types::Fd(file.as_raw_fd()), len)
+                    .offset(offset)
+                    .mode(libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE)
+                    .build(),
+            )
+        }
+        .await;
+        match r {
+            Ok(_) => Ok(()),
+            Err(err) if err.raw_os_error() == Some(libc::EOPNOTSUPP) => {
+                // Unmap content is unspecified, so it's fine to leave the data
+                // in place.
+                tracing::debug!("file system does not support punching holes");
+                self.punch_hole.store(false, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(err) => Err(DiskError::Io(err)),
+        }
+    }
+}
+
+/// Returns the subranges of `range` that contain data in `file`, as reported
+/// by `SEEK_DATA` and `SEEK_HOLE`.
+///
+/// This moves the file position, which is unused since all IOs are issued
+/// with explicit offsets.
+fn data_ranges(file: &fs::File, range: Range<u64>) -> io::Result<Vec<Range<u64>>> {
+    let mut ranges = Vec::new();
+    let mut offset = range.start;
+    while offset < range.end {
+        let start = match lseek(file, offset, libc::SEEK_DATA) {
+            Ok(start) => start,
+            // There is no data past `offset`.
+            Err(err) if err.raw_os_error() == Some(libc::ENXIO) => break,
+            Err(err) => return Err(err),
+        };
+        if start >= range.end {
+            break;
+        }
+        let end = lseek(file, start, libc::SEEK_HOLE)?.min(range.end);
+        ranges.push(start..end);
+        offset = end;
+    }
+    Ok(ranges)
+}
+
+fn lseek(file: &fs::File, offset: u64, whence: i32) -> io::Result<u64> {
+    // SAFETY: calling lseek on a valid file descriptor.
+    let r = unsafe { libc::lseek(file.as_raw_fd(), offset as i64, whence) };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(r as u64)
+}
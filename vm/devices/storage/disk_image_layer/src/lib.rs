@@ -0,0 +1,216 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Shared support for disk layers backed by a single image file, such as QCOW2,
+//! VHDX, and dynamic VHD1 images.
+//!
+//! These formats translate guest offsets to file offsets through metadata that
+//! is read and updated with synchronous, positioned file IO. A format
+//! implements that part as an [`ImageFile`], and uses the functions here to
+//! run it on the blocking thread pool from its [`LayerIo`] implementation.
+//!
+//! [`LayerIo`]: disk_layered::LayerIo
+
+#![forbid(unsafe_code)]
+
+pub mod resolver;
+#[cfg(feature = "test")]
+pub mod test_helpers;
+
+use blocking::unblock;
+use disk_backend::DiskError;
+use disk_layered::SectorMarker;
+use guestmem::MemoryRead;
+use guestmem::MemoryWrite;
+use scsi_buffers::RequestBuffers;
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// The synchronous part of a disk layer backed by an image file.
+///
+/// Offsets and ranges are in guest bytes, and are always within the disk and
+/// aligned to [`sector_size`](Self::sector_size).
+pub trait ImageFile: 'static + Send + Sync {
+    /// Returns the image file.
+    fn file(&self) -> &File;
+
+    /// Returns the virtual size of the disk, in bytes.
+    fn disk_size(&self) -> u64;
+
+    /// Returns the logical sector size of the disk, in bytes.
+    fn sector_size(&self) -> u32;
+
+    /// Returns whether the image was opened read-only.
+    fn is_read_only(&self) -> bool;
+
+    /// Reads the bytes at `offset` into `buf`, returning the ranges that are
+    /// present in this image. The rest of `buf` is left unspecified.
+    fn read(&self, offset: u64, buf: &mut [u8]) -> io::Result<Vec<Range<u64>>>;
+
+    /// Writes `data` at `offset`.
+    fn write(&self, offset: u64, data: &[u8]) -> io::Result<()>;
+
+    /// Unmaps `range`. If `next_is_zero`, unmapped ranges may fall through to
+    /// the next layer; otherwise they must read as zero.
+    fn unmap(&self, range: Range<u64>, next_is_zero: bool) -> io::Result<()>;
+}
+
+/// Checks that the `len` bytes starting at `sector` are within the disk.
+pub fn check_range(image: &impl ImageFile, sector: u64, len: u64) -> Result<(), DiskError> {
+    let end = sector
+        .checked_mul(image.sector_size().into())
+        .and_then(|offset| offset.checked_add(len))
+        .ok_or(DiskError::IllegalBlock)?;
+    if end > image.disk_size() {
+        return Err(DiskError::IllegalBlock);
+    }
+    Ok(())
+}
+
+/// Implements [`LayerIo::read`](disk_layered::LayerIo::read) for `image`.
+pub async fn read<T: ImageFile>(
+    image: &Arc<T>,
+    buffers: &RequestBuffers<'_>,
+    sector: u64,
+    mut marker: SectorMarker<'_>,
+) -> Result<(), DiskError> {
+    check_range(&**image, sector, buffers.len() as u64)?;
+    let sector_size = image.sector_size() as u64;
+    let offset = sector * sector_size;
+    let mut buf = vec![0; buffers.len()];
+    let image = image.clone();
+    let (buf, present) = unblock(move || -> io::Result<_> {
+        let present = image.read(offset, &mut buf)?;
+        Ok((buf, present))
+    })
+    .await
+    .map_err(DiskError::Io)?;
+
+    for range in present {
+        let start = (range.start - offset) as usize;
+        let len = (range.end - range.start) as usize;
+        buffers
+            .subrange(start, len)
+            .writer()
+            .write(&buf[start..][..len])?;
+        marker.set_range(range.start / sector_size..range.end / sector_size);
+    }
+    Ok(())
+}
+
+/// Implements [`LayerIo::write`](disk_layered::LayerIo::write) for `image`.
+pub async fn write<T: ImageFile>(
+    image: &Arc<T>,
+    buffers: &RequestBuffers<'_>,
+    sector: u64,
+    fua: bool,
+) -> Result<(), DiskError> {
+    if image.is_read_only() {
+        return Err(DiskError::ReadOnly);
+    }
+    check_range(&**image, sector, buffers.len() as u64)?;
+    let data = buffers.reader().read_all()?;
+    let offset = sector * image.sector_size() as u64;
+    let image = image.clone();
+    unblock(move || -> io::Result<()> {
+        image.write(offset, &data)?;
+        if fua {
+            image.file().sync_data()?;
+        }
+        Ok(())
+    })
+    .await
+    .map_err(DiskError::Io)
+}
+
+/// Implements [`LayerIo::unmap`](disk_layered::LayerIo::unmap) for `image`.
+pub async fn unmap<T: ImageFile>(
+    image: &Arc<T>,
+    sector: u64,
+    count: u64,
+    next_is_zero: bool,
+) -> Result<(), DiskError> {
+    if image.is_read_only() {
+        return Err(DiskError::ReadOnly);
+    }
+    let sector_size = image.sector_size() as u64;
+    check_range(
+        &**image,
+        sector,
+        count
+            .checked_mul(sector_size)
+            .ok_or(DiskError::IllegalBlock)?,
+    )?;
+    let range = sector * sector_size..(sector + count) * sector_size;
+    let image = image.clone();
+    unblock(move || image.unmap(range, next_is_zero))
+        .await
+        .map_err(DiskError::Io)
+}
+
+/// Implements [`LayerIo::sync_cache`](disk_layered::LayerIo::sync_cache) for
+/// `image`.
+pub async fn sync_cache<T: ImageFile>(image: &Arc<T>) -> Result<(), DiskError> {
+    let image = image.clone();
+    unblock(move || image.file().sync_all())
+        .await
+        .map_err(DiskError::Io)
+}
+
+/// Reads `buf.len()` bytes at `offset`, zero filling anything past the end of
+/// the file.
+pub fn read_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match platform_read_at(file, buf, offset) {
+            Ok(0) => {
+                buf.fill(0);
+                break;
+            }
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Writes all of `buf` at `offset`, extending the file if necessary.
+pub fn write_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match platform_write_at(file, buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn platform_read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(unix)]
+fn platform_write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::write_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn platform_read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn platform_write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_write(file, buf, offset)
+}
@@ -0,0 +1,82 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Helpers for resolving image disks with a parent (or backing) disk.
+
+use disk_backend::Disk;
+use disk_backend::InvalidDisk;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_layered::DiskLayer;
+use disk_layered::InvalidLayeredDisk;
+use disk_layered::LayerConfiguration;
+use disk_layered::LayeredDisk;
+use thiserror::Error;
+use vm_resource::ResolveError;
+use vm_resource::Resource;
+use vm_resource::ResourceResolver;
+use vm_resource::kind::DiskHandleKind;
+
+/// Error type for [`resolve_image_disk`], where `E` is the error type for
+/// opening the image.
+#[derive(Debug, Error)]
+pub enum ResolveImageDiskError<E: 'static + std::error::Error> {
+    /// Failed to resolve the parent disk.
+    #[error("failed to resolve parent disk")]
+    ResolveParent(#[source] ResolveError),
+    /// Failed to open the image.
+    #[error("failed to open image")]
+    Open(#[source] E),
+    /// Failed to create the layered disk.
+    #[error("failed to create layered disk")]
+    CreateDisk(#[source] InvalidLayeredDisk),
+    /// Failed to instantiate the disk.
+    #[error("invalid disk")]
+    InvalidDisk(#[source] InvalidDisk),
+}
+
+/// Resolves a layered disk made of an image and its optional parent.
+///
+/// The parent is resolved read-only and passed to `open`, which opens the
+/// image's layer. The parent is then placed below that layer.
+pub async fn resolve_image_disk<E: 'static + std::error::Error>(
+    resolver: &ResourceResolver,
+    parent: Option<Resource<DiskHandleKind>>,
+    input: ResolveDiskParameters<'_>,
+    open: impl FnOnce(Option<Disk>) -> Result<DiskLayer, E>,
+) -> Result<ResolvedDisk, ResolveImageDiskError<E>> {
+    let parent = if let Some(parent) = parent {
+        let disk = resolver
+            .resolve(
+                parent,
+                ResolveDiskParameters {
+                    read_only: true,
+                    driver_source: input.driver_source,
+                },
+            )
+            .await
+            .map_err(ResolveImageDiskError::ResolveParent)?;
+        Some(disk.0)
+    } else {
+        None
+    };
+
+    let mut layers = vec![LayerConfiguration {
+        layer: open(parent.clone()).map_err(ResolveImageDiskError::Open)?,
+        write_through: false,
+        read_cache: false,
+    }];
+    if let Some(parent) = parent {
+        layers.push(LayerConfiguration {
+            layer: DiskLayer::from_disk(parent),
+            write_through: false,
+            read_cache: false,
+        });
+    }
+
+    let disk = LayeredDisk::new(input.read_only, layers)
+        .await
+        .map_err(ResolveImageDiskError::CreateDisk)?;
+
+    ResolvedDisk::new(disk).map_err(ResolveImageDiskError::InvalidDisk)
+}
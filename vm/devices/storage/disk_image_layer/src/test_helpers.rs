@@ -0,0 +1,61 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Helpers for testing image layers.
+
+use disk_backend::DiskIo;
+use disk_layered::DiskLayer;
+use disk_layered::LayerConfiguration;
+use disk_layered::LayeredDisk;
+use guestmem::GuestMemory;
+use scsi_buffers::OwnedRequestBuffers;
+
+/// Builds a layered disk from `layers`, without caching.
+pub async fn layered(read_only: bool, layers: Vec<DiskLayer>) -> LayeredDisk {
+    LayeredDisk::new(
+        read_only,
+        layers
+            .into_iter()
+            .map(|layer| LayerConfiguration {
+                layer,
+                write_through: false,
+                read_cache: false,
+            })
+            .collect(),
+    )
+    .await
+    .unwrap()
+}
+
+/// Writes `data` to `disk` at `sector`, staging it at the start of `mem`.
+pub async fn write(disk: &impl DiskIo, mem: &GuestMemory, sector: u64, data: &[u8]) {
+    mem.write_at(0, data).unwrap();
+    disk.write_vectored(
+        &OwnedRequestBuffers::linear(0, data.len(), false).buffer(mem),
+        sector,
+        false,
+    )
+    .await
+    .unwrap();
+}
+
+/// Reads `len` bytes from `disk` at `sector`, staging them at the start of
+/// `mem`.
+pub async fn read(disk: &impl DiskIo, mem: &GuestMemory, sector: u64, len: usize) -> Vec<u8> {
+    disk.read_vectored(
+        &OwnedRequestBuffers::linear(0, len, true).buffer(mem),
+        sector,
+    )
+    .await
+    .unwrap();
+    let mut data = vec![0; len];
+    mem.read_at(0, &mut data).unwrap();
+    data
+}
+
+/// Returns `len` bytes of test data that varies with `seed`.
+pub fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+        .collect()
+}
@@ -24,12 +24,18 @@
 //! # Layer types
 //!
 //! Each layer implements [`LayerIo`], which is similar to [`DiskIo`]
-//! but adds per-sector presence tracking via [`SectorMarker`]. Two concrete
+//! but adds per-sector presence tracking via [`SectorMarker`]. Five concrete
 //! layer implementations exist:
 //!
 //! - **`RamDiskLayer`** (`disklayer_ram`) — ephemeral, in-memory.
 //! - **`SqliteDiskLayer`** (`disklayer_sqlite`) — persistent, file-backed
 //!   (dev/test only).
+//! - **`Qcow2DiskLayer`** (`disk_qcow2`) — persistent, a QCOW2 image whose
+//!   backing file is the next layer down.
+//! - **`VhdxDiskLayer`** (`disk_vhdx`) — persistent, a dynamic or
+//!   differencing VHDX image whose parent is the next layer down.
+//! - **`DynamicVhd1Layer`** (`disk_vhd1`) — persistent, a dynamic or
+//!   differencing VHD1 image whose parent is the next layer down.
 //!
 //! A full [`Disk`] can appear at the bottom of the stack
 //! as a fully-present layer via `DiskLayer::from_disk`, which wraps it in
@@ -44,6 +50,13 @@
 //! - The last layer must not be write-through.
 //! - Layers used as read caches must support [`WriteNoOverwrite`].
 //! - If the disk is writable, all layers in the write path must be writable.
+//!
+//! # Committing
+//!
+//! [`LayeredDisk::commit`] merges the top layer into the layer below it and
+//! then removes it from the stack, while the disk remains in use. This can also
+//! be started by updating the `commit` inspect node, whose value shows the
+//! progress of the most recent commit.
 
 #![forbid(unsafe_code)]
 
@@ -61,18 +74,26 @@
 use guestmem::GuestMemory;
 use guestmem::MemoryWrite;
 use inspect::Inspect;
+use pal_async::task::Spawn;
+use parking_lot::Mutex;
+use parking_lot::RwLock;
 use scsi_buffers::OwnedRequestBuffers;
 use scsi_buffers::RequestBuffers;
 use std::convert::Infallible;
 use std::future::Future;
+use std::ops::Deref;
+use std::ops::Range;
 use std::pin::Pin;
+use std::sync::Arc;
 use thiserror::Error;
+use vmcore::vm_task::VmTaskDriver;
+
+/// The amount of data to copy at a time when committing a layer.
+const COMMIT_CHUNK_SIZE: usize = 0x100000;
 
 /// A disk composed of multiple layers.
-#[derive(Inspect)]
 pub struct LayeredDisk {
-    #[inspect(iter_by_index)]
-    layers: Vec<Layer>,
+    state: Arc<LayerState>,
     read_only: bool,
     is_fua_respected: bool,
     sector_shift: u32,
@@ -80,14 +101,81 @@ pub struct LayeredDisk {
     physical_sector_size: u32,
     unmap_behavior: UnmapBehavior,
     optimal_unmap_sectors: u32,
+    commit_driver: Option<VmTaskDriver>,
 }
 
-#[derive(Inspect)]
+/// The layer state shared with commit tasks.
+struct LayerState {
+    /// The current layers. IOs hold a reference to the list for their
+    /// duration, so a commit replaces the list rather than modifying it.
+    layers: RwLock<Arc<LayerList>>,
+    /// Notified when an IO releases its reference to the layer list.
+    released: event_listener::Event,
+    /// Held by writes while a commit is in progress, and by the commit while
+    /// it copies each chunk, so that a copy cannot overwrite newer data.
+    commit_lock: futures::lock::Mutex<()>,
+    commit_status: Mutex<CommitStatus>,
+}
+
+struct LayerList {
+    layers: Vec<Layer>,
+    /// The top layer is being committed, and has been made write-through.
+    committing: bool,
+}
+
+#[derive(Clone, Inspect)]
 struct Layer {
-    backing: Box<dyn DynLayerIo>,
+    backing: Arc<dyn DynLayerIo>,
     visible_sector_count: u64,
     read_cache: bool,
     write_through: bool,
+    read_only: bool,
+    unmap_behavior: UnmapBehavior,
+}
+
+/// The progress of the most recent commit.
+enum CommitStatus {
+    Idle,
+    Running { sector: u64, sector_count: u64 },
+    Done { sectors: u64 },
+    Failed(String),
+}
+
+impl std::fmt::Display for CommitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommitStatus::Idle => f.write_str("idle"),
+            CommitStatus::Running {
+                sector,
+                sector_count,
+            } => write!(f, "running: sector {sector} of {sector_count}"),
+            CommitStatus::Done { sectors } => write!(f, "done: committed {sectors} sectors"),
+            CommitStatus::Failed(err) => write!(f, "failed: {err}"),
+        }
+    }
+}
+
+/// A reference to the current layer list, held for the duration of an IO.
+struct LayersRef<'a> {
+    list: Option<Arc<LayerList>>,
+    released: &'a event_listener::Event,
+}
+
+impl Deref for LayersRef<'_> {
+    type Target = LayerList;
+
+    fn deref(&self) -> &LayerList {
+        self.list.as_ref().unwrap()
+    }
+}
+
+impl Drop for LayersRef<'_> {
+    fn drop(&mut self) {
+        // Release the reference before notifying, so that a waiting commit
+        // sees it gone.
+        self.list = None;
+        self.released.notify(usize::MAX);
+    }
 }
 
 /// A single layer which can be attached to a [`LayeredDisk`].
@@ -156,6 +244,44 @@ pub enum InvalidLayer {
     ReadOnly,
 }
 
+/// An error returned by [`LayeredDisk::commit`].
+#[derive(Debug, Error)]
+pub enum CommitError {
+    /// The disk has a single layer.
+    #[error("there is no layer to commit into")]
+    NoLowerLayer,
+    /// Another commit is running.
+    #[error("a commit is already in progress")]
+    InProgress,
+    /// The top layer already writes through to the layer below it.
+    #[error("the top layer is write-through")]
+    WriteThrough,
+    /// The layer below the top layer was not opened for write.
+    #[error("the lower layer is read only")]
+    ReadOnly,
+    /// The layers have different sizes.
+    #[error("mismatched sector count {lower}, expected {top}")]
+    MismatchedSectorCount {
+        /// The sector count of the top layer.
+        top: u64,
+        /// The sector count of the lower layer.
+        lower: u64,
+    },
+    /// The disk reports that unmapped sectors are zeroed, but the lower
+    /// layer does not zero them.
+    #[error("the lower layer does not zero unmapped sectors")]
+    UnmapBehavior,
+    /// No driver was provided to run commits started through inspect.
+    #[error("commit is not supported on this disk")]
+    NoDriver,
+    /// Copying the sectors failed.
+    #[error("failed to copy sectors")]
+    Io(#[source] DiskError),
+    /// Discarding the copied sectors from the top layer failed.
+    #[error("failed to discard committed sectors")]
+    Discard(#[source] DiskError),
+}
+
 /// An error returned when creating a [`LayeredDisk`].
 #[derive(Debug, Error)]
 pub enum InvalidLayeredDisk {
@@ -326,10 +452,12 @@ pub async fn new(
                 } = config;
                 visible_sector_count = sector_count.min(visible_sector_count);
                 Layer {
-                    backing: layer.backing,
+                    backing: layer.backing.into(),
                     visible_sector_count,
                     read_cache,
                     write_through,
+                    read_only: layer.meta.read_only,
+                    unmap_behavior: layer.meta.unmap_behavior,
                 }
             })
             .collect::<Vec<_>>();
@@ -342,12 +470,334 @@ pub async fn new(
             physical_sector_size,
             unmap_behavior,
             optimal_unmap_sectors,
-            layers,
+            state: Arc::new(LayerState {
+                layers: RwLock::new(Arc::new(LayerList {
+                    layers,
+                    committing: false,
+                })),
+                released: Default::default(),
+                commit_lock: Default::default(),
+                commit_status: Mutex::new(CommitStatus::Idle),
+            }),
+            commit_driver: None,
         })
     }
+
+    /// Sets the driver used to run commits started through inspect.
+    pub fn set_commit_driver(&mut self, driver: VmTaskDriver) {
+        self.commit_driver = Some(driver);
+    }
+
+    fn layers(&self) -> LayersRef<'_> {
+        self.state.layers()
+    }
+
+    /// Returns the number of layers, including the top layer.
+    pub fn layer_count(&self) -> usize {
+        self.layers().layers.len()
+    }
+
+    /// Returns the type name of layer `index`, for diagnostic purposes.
+    pub fn layer_type(&self, index: usize) -> String {
+        self.layers().layers[index].backing.layer_type().to_owned()
+    }
+
+    /// Returns the sector count of layer `index`.
+    pub fn layer_sector_count(&self, index: usize) -> u64 {
+        self.layers().layers[index].backing.sector_count()
+    }
+
+    /// Reads sectors from layer `index` only, without falling through to the
+    /// layers below it.
+    ///
+    /// Returns the ranges of sectors that are present in the layer. The
+    /// contents of `buffers` for the other sectors are unspecified.
+    pub async fn read_layer(
+        &self,
+        index: usize,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<Vec<Range<u64>>, DiskError> {
+        let layers = self.layers();
+        // Sectors beyond the visible sector count of a lower layer are not
+        // present.
+        let end = (index != 0).then_some(layers.layers[index].visible_sector_count);
+        layers.layers[index]
+            .read_present(buffers, sector, self.sector_shift, end)
+            .await
+    }
+
+    /// Writes sectors to layer `index` only, bypassing the layers above it
+    /// and ignoring write-through.
+    ///
+    /// The layer must have been attached as writable.
+    pub async fn write_layer(
+        &self,
+        index: usize,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        self.layers().layers[index]
+            .backing
+            .write(buffers, sector, false, false)
+            .await
+    }
+
+    /// Flushes layer `index` to its backing store.
+    pub async fn sync_layer(&self, index: usize) -> Result<(), DiskError> {
+        self.layers().layers[index].backing.sync_cache().await
+    }
+
+    /// Commits the top layer into the layer below it, and then removes it from
+    /// the disk. Returns the number of sectors that were committed.
+    ///
+    /// The disk can be used while this runs. Until the commit completes,
+    /// writes go to both layers.
+    ///
+    /// The lower layer must have been attached as writable, and must be the
+    /// same size as the top layer. The returned future must be run to
+    /// completion; if it is dropped early, the top layer is left
+    /// write-through.
+    ///
+    /// Once copied, the sectors are discarded from the top layer, so that a
+    /// persistent top layer can be attached above the lower layer again
+    /// without hiding writes made to the lower layer after the commit.
+    pub async fn commit(&self) -> Result<u64, CommitError> {
+        let original = self.state.begin_commit(self.unmap_behavior)?;
+        self.state.finish_commit(original, self.sector_shift).await
+    }
+
+    /// Starts committing the top layer on the commit driver, for inspect.
+    fn start_commit(&self) -> Result<(), CommitError> {
+        let driver = self.commit_driver.as_ref().ok_or(CommitError::NoDriver)?;
+        let original = self.state.begin_commit(self.unmap_behavior)?;
+        let state = self.state.clone();
+        let sector_shift = self.sector_shift;
+        driver
+            .spawn("layered-disk-commit", async move {
+                match state.finish_commit(original, sector_shift).await {
+                    Ok(sectors) => tracing::info!(sectors, "committed top disk layer"),
+                    Err(err) => tracing::error!(
+                        error = &err as &dyn std::error::Error,
+                        "failed to commit top disk layer"
+                    ),
+                }
+            })
+            .detach();
+        Ok(())
+    }
+}
+
+impl Inspect for LayeredDisk {
+    fn inspect(&self, req: inspect::Request<'_>) {
+        req.respond()
+            .field(
+                "layers",
+                inspect::iter_by_index(self.layers().layers.iter()),
+            )
+            .field("read_only", self.read_only)
+            .field("is_fua_respected", self.is_fua_respected)
+            .field("sector_shift", self.sector_shift)
+            .field("disk_id", &self.disk_id)
+            .field("physical_sector_size", self.physical_sector_size)
+            .field("unmap_behavior", &self.unmap_behavior)
+            .field("optimal_unmap_sectors", self.optimal_unmap_sectors)
+            .child("commit", |req| match req.update() {
+                Ok(update) => match self.start_commit() {
+                    Ok(()) => update.succeed(self.state.commit_status.lock().to_string()),
+                    Err(err) => update.fail(err),
+                },
+                Err(req) => req.value(self.state.commit_status.lock().to_string()),
+            });
+    }
+}
+
+impl LayerState {
+    fn layers(&self) -> LayersRef<'_> {
+        LayersRef {
+            list: Some(self.layers.read().clone()),
+            released: &self.released,
+        }
+    }
+
+    /// Validates the layers for a commit and makes the top layer
+    /// write-through. Returns the original layer list.
+    fn begin_commit(&self, unmap_behavior: UnmapBehavior) -> Result<Arc<LayerList>, CommitError> {
+        let mut status = self.commit_status.lock();
+        if matches!(*status, CommitStatus::Running { .. }) {
+            return Err(CommitError::InProgress);
+        }
+        let mut layers = self.layers.write();
+        let original = layers.clone();
+        let [top, lower, ..] = original.layers.as_slice() else {
+            return Err(CommitError::NoLowerLayer);
+        };
+        if top.write_through {
+            return Err(CommitError::WriteThrough);
+        }
+        if lower.read_only {
+            return Err(CommitError::ReadOnly);
+        }
+        let sector_count = top.backing.sector_count();
+        if lower.backing.sector_count() != sector_count {
+            return Err(CommitError::MismatchedSectorCount {
+                top: sector_count,
+                lower: lower.backing.sector_count(),
+            });
+        }
+        // Unmaps now reach the lower layer too, which must produce the same
+        // result as the top layer.
+        if unmap_behavior == UnmapBehavior::Zeroes && lower.unmap_behavior != UnmapBehavior::Zeroes
+        {
+            return Err(CommitError::UnmapBehavior);
+        }
+        let mut committing = original.layers.clone();
+        committing[0].write_through = true;
+        *layers = Arc::new(LayerList {
+            layers: committing,
+            committing: true,
+        });
+        *status = CommitStatus::Running {
+            sector: 0,
+            sector_count,
+        };
+        Ok(original)
+    }
+
+    /// Copies the top layer into the lower layer and then removes it, or
+    /// restores `original` on failure.
+    async fn finish_commit(
+        &self,
+        original: Arc<LayerList>,
+        sector_shift: u32,
+    ) -> Result<u64, CommitError> {
+        // Wait for IOs that might not have written through to the lower layer.
+        loop {
+            let released = self.released.listen();
+            if Arc::strong_count(&original) == 1 {
+                break;
+            }
+            released.await;
+        }
+        let result = self.copy_top_layer(sector_shift).await;
+        let mut status = self.commit_status.lock();
+        *self.layers.write() = match &result {
+            Ok(_) => Arc::new(LayerList {
+                layers: original.layers[1..].to_vec(),
+                committing: false,
+            }),
+            Err(_) => original,
+        };
+        *status = match &result {
+            Ok(sectors) => CommitStatus::Done { sectors: *sectors },
+            Err(err) => CommitStatus::Failed(match std::error::Error::source(err) {
+                Some(source) => format!("{err}: {source}"),
+                None => err.to_string(),
+            }),
+        };
+        result
+    }
+
+    async fn copy_top_layer(&self, sector_shift: u32) -> Result<u64, CommitError> {
+        let layers = self.layers.read().clone();
+        let [top, lower, ..] = layers.layers.as_slice() else {
+            unreachable!()
+        };
+        let sector_count = top.backing.sector_count();
+        let chunk_sectors = (COMMIT_CHUNK_SIZE >> sector_shift) as u64;
+        let mem = GuestMemory::allocate(COMMIT_CHUNK_SIZE);
+        let mut committed = 0;
+        let mut sector = 0;
+        while sector < sector_count {
+            let count = chunk_sectors.min(sector_count - sector);
+            let len = (count as usize) << sector_shift;
+            {
+                let _lock = self.commit_lock.lock().await;
+                let present = top
+                    .read_present(
+                        &OwnedRequestBuffers::linear(0, len, true).buffer(&mem),
+                        sector,
+                        sector_shift,
+                        None,
+                    )
+                    .await
+                    .map_err(CommitError::Io)?;
+                let buffers = OwnedRequestBuffers::linear(0, len, false);
+                for range in present {
+                    let offset = ((range.start - sector) as usize) << sector_shift;
+                    let len = ((range.end - range.start) as usize) << sector_shift;
+                    lower
+                        .backing
+                        .write(
+                            &buffers.buffer(&mem).subrange(offset, len),
+                            range.start,
+                            false,
+                            false,
+                        )
+                        .await
+                        .map_err(CommitError::Io)?;
+                    committed += range.end - range.start;
+                }
+            }
+            sector += count;
+            *self.commit_status.lock() = CommitStatus::Running {
+                sector,
+                sector_count,
+            };
+        }
+        lower.backing.sync_cache().await.map_err(CommitError::Io)?;
+
+        // Discard the top layer's sectors now that the lower layer durably
+        // holds them. Until the top layer is removed, writes still go to both
+        // layers, and reads of discarded sectors fall through to the lower
+        // layer, so this is safe to do while the disk is in use.
+        {
+            let _lock = self.commit_lock.lock().await;
+            top.backing
+                .unmap(0, sector_count, false, true)
+                .await
+                .map_err(CommitError::Discard)?;
+        }
+        top.backing
+            .sync_cache()
+            .await
+            .map_err(CommitError::Discard)?;
+        Ok(committed)
+    }
+}
+
+impl Layer {
+    /// Reads sectors from this layer only, returning the ranges of sectors
+    /// that are present. Sectors at or beyond `end` are not read.
+    async fn read_present(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        sector_shift: u32,
+        end: Option<u64>,
+    ) -> Result<Vec<Range<u64>>, DiskError> {
+        let sector_count = buffers.len() >> sector_shift;
+        let mut bitmap = Bitmap::new(sector, sector_count);
+        if let Some(mut range) = bitmap.unset_iter().next() {
+            let end = end.map_or(range.end_sector(), |end| end.min(range.end_sector()));
+            if end > range.start_sector() {
+                let sectors = end - range.start_sector();
+                self.backing
+                    .read(
+                        &buffers.subrange(0, (sectors as usize) << sector_shift),
+                        sector,
+                        range.view(sectors),
+                    )
+                    .await?;
+            }
+        }
+        Ok(bitmap.set_iter().collect())
+    }
 }
 
 trait DynLayerIo: Send + Sync + Inspect {
+    fn layer_type(&self) -> &str;
+
     fn sector_count(&self) -> u64;
 
     fn read<'a>(
@@ -379,6 +829,10 @@ fn unmap(
 }
 
 impl<T: LayerIo> DynLayerIo for T {
+    fn layer_type(&self) -> &str {
+        LayerIo::layer_type(self)
+    }
+
     fn sector_count(&self) -> u64 {
         self.sector_count()
     }
@@ -646,7 +1100,7 @@ fn disk_type(&self) -> &str {
     }
 
     fn sector_count(&self) -> u64 {
-        self.layers[0].backing.sector_count()
+        self.state.layers.read().layers[0].backing.sector_count()
     }
 
     fn sector_size(&self) -> u32 {
@@ -678,9 +1132,10 @@ async fn read_vectored(
         let sector_count = buffers.len() >> self.sector_shift;
         let mut bitmap = Bitmap::new(sector, sector_count);
         let mut bits_set = 0;
+        let layers = self.layers();
         let mut populate_cache = Vec::new();
         // FUTURE: queue the reads to the layers in parallel.
-        'done: for (i, layer) in self.layers.iter().enumerate() {
+        'done: for (i, layer) in layers.layers.iter().enumerate() {
             if bits_set == sector_count {
                 break;
             }
@@ -787,7 +1242,13 @@ async fn write_vectored(
         sector: u64,
         fua: bool,
     ) -> Result<(), DiskError> {
-        for layer in &self.layers {
+        let layers = self.layers();
+        let _lock = if layers.committing {
+            Some(self.state.commit_lock.lock().await)
+        } else {
+            None
+        };
+        for layer in &layers.layers {
             layer.backing.write(buffers, sector, fua, false).await?;
             if !layer.write_through {
                 break;
@@ -797,7 +1258,8 @@ async fn write_vectored(
     }
 
     async fn sync_cache(&self) -> Result<(), DiskError> {
-        for layer in &self.layers {
+        let layers = self.layers();
+        for layer in &layers.layers {
             layer.backing.sync_cache().await?;
             if !layer.write_through {
                 break;
@@ -807,7 +1269,10 @@ async fn sync_cache(&self) -> Result<(), DiskError> {
     }
 
     fn wait_resize(&self, sector_count: u64) -> impl Future<Output = u64> + Send {
-        self.layers[0].backing.wait_resize(sector_count)
+        // Don't hold a reference to the layer list while waiting, since that
+        // would block commits.
+        let top = self.state.layers.read().layers[0].backing.clone();
+        async move { top.wait_resize(sector_count).await }
     }
 
     async fn unmap(
@@ -820,10 +1285,16 @@ async fn unmap(
             return Ok(());
         }
 
-        for (layer, next_layer) in self
+        let layers = self.layers();
+        let _lock = if layers.committing {
+            Some(self.state.commit_lock.lock().await)
+        } else {
+            None
+        };
+        for (layer, next_layer) in layers
             .layers
             .iter()
-            .zip(self.layers.iter().map(Some).skip(1).chain([None]))
+            .zip(layers.layers.iter().map(Some).skip(1).chain([None]))
         {
             let next_is_zero = if let Some(next_layer) = next_layer {
                 // Sectors beyond the layer's visible sector count are logically
@@ -1167,4 +1638,161 @@ async fn test_read_cache() {
             );
         }
     }
+
+    #[async_test]
+    async fn test_layer_access() {
+        const SIZE: u64 = 64;
+        let top = Arc::new(TestLayer::new(SIZE));
+        let bottom = Arc::new(TestLayer::new(SIZE));
+        bottom
+            .sectors
+            .lock()
+            .extend((0..2).map(|i| (i, Data(vec![1; 512].into()))));
+
+        let disk = LayeredDisk::new(
+            false,
+            vec![
+                LayerConfiguration {
+                    layer: DiskLayer::new(top.clone()),
+                    read_cache: false,
+                    write_through: false,
+                },
+                LayerConfiguration {
+                    layer: DiskLayer::new(bottom.clone()),
+                    read_cache: false,
+                    write_through: false,
+                },
+            ],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(disk.layer_count(), 2);
+        assert_eq!(disk.layer_type(1), "test");
+        assert_eq!(disk.layer_sector_count(1), SIZE);
+
+        let mem = GuestMemory::allocate(0x1000);
+        mem.fill_at(0, 2, 0x1000).unwrap();
+        let write_buffers = OwnedRequestBuffers::linear(0, 2 * 512, false);
+        disk.write_vectored(&write_buffers.buffer(&mem), 3, false)
+            .await
+            .unwrap();
+
+        let read_buffers = OwnedRequestBuffers::linear(0, 0x1000, true);
+        assert_eq!(
+            disk.read_layer(0, &read_buffers.buffer(&mem), 0)
+                .await
+                .unwrap(),
+            [3..5]
+        );
+        assert_eq!(
+            disk.read_layer(1, &read_buffers.buffer(&mem), 0)
+                .await
+                .unwrap(),
+            [0..2]
+        );
+
+        disk.write_layer(1, &write_buffers.buffer(&mem), 3)
+            .await
+            .unwrap();
+        assert_eq!(
+            disk.read_layer(1, &read_buffers.buffer(&mem), 0)
+                .await
+                .unwrap(),
+            [0..2, 3..5]
+        );
+        assert!(top.sectors.lock().contains_key(&3));
+    }
+
+    #[async_test]
+    async fn test_commit() {
+        const SIZE: u64 = 4096;
+        let top = Arc::new(TestLayer::new(SIZE));
+        let bottom = Arc::new(TestLayer::new(SIZE));
+        bottom
+            .sectors
+            .lock()
+            .extend((0..4).map(|i| (i, Data(vec![1; 512].into()))));
+
+        let disk = LayeredDisk::new(
+            false,
+            vec![
+                LayerConfiguration {
+                    layer: DiskLayer::new(top.clone()),
+                    read_cache: false,
+                    write_through: false,
+                },
+                LayerConfiguration {
+                    layer: DiskLayer::new(bottom.clone()),
+                    read_cache: false,
+                    write_through: false,
+                },
+            ],
+        )
+        .await
+        .unwrap();
+
+        // Write across the boundary of the first commit chunk.
+        let mem = GuestMemory::allocate(0x1000);
+        mem.fill_at(0, 2, 0x1000).unwrap();
+        let buffers = OwnedRequestBuffers::linear(0, 0x1000, false);
+        disk.write_vectored(&buffers.buffer(&mem).subrange(0, 2 * 512), 2, false)
+            .await
+            .unwrap();
+        disk.write_vectored(&buffers.buffer(&mem), 2044, false)
+            .await
+            .unwrap();
+
+        assert_eq!(disk.commit().await.unwrap(), 10);
+        assert_eq!(disk.layer_count(), 1);
+        assert!(matches!(
+            disk.commit().await,
+            Err(crate::CommitError::NoLowerLayer)
+        ));
+
+        let sectors = bottom.sectors.lock();
+        assert_eq!(
+            sectors.keys().copied().collect::<Vec<_>>(),
+            [0, 1, 2, 3, 2044, 2045, 2046, 2047, 2048, 2049, 2050, 2051]
+        );
+        assert_eq!(sectors[&1].0[..], [1; 512]);
+        assert_eq!(sectors[&2].0[..], [2; 512]);
+        assert_eq!(sectors[&2051].0[..], [2; 512]);
+        drop(sectors);
+
+        // Writes now go to the former lower layer.
+        disk.write_vectored(&buffers.buffer(&mem).subrange(0, 512), 100, false)
+            .await
+            .unwrap();
+        assert!(bottom.sectors.lock().contains_key(&100));
+        assert!(top.sectors.lock().is_empty());
+
+        // The committed layer no longer hides the lower layer, so it can be
+        // attached above it again.
+        let disk = LayeredDisk::new(
+            false,
+            vec![
+                LayerConfiguration {
+                    layer: DiskLayer::new(top.clone()),
+                    read_cache: false,
+                    write_through: false,
+                },
+                LayerConfiguration {
+                    layer: DiskLayer::new(bottom.clone()),
+                    read_cache: false,
+                    write_through: false,
+                },
+            ],
+        )
+        .await
+        .unwrap();
+        let mut read_mem = GuestMemory::allocate(512);
+        disk.read_vectored(
+            &OwnedRequestBuffers::linear(0, 512, true).buffer(&read_mem),
+            100,
+        )
+        .await
+        .unwrap();
+        assert_eq!(read_mem.inner_buf_mut().unwrap()[..], [2; 512]);
+    }
 }
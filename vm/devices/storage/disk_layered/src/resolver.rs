@@ -64,7 +64,8 @@ async fn resolve(
             .into_iter()
             .enumerate()
             .map(|(i, desc)| {
-                let this_read_only = read_only && !desc.read_cache;
+                let this_read_only =
+                    read_only && !desc.read_cache && !(desc.writable && !input.read_only);
                 if !desc.write_through {
                     read_only = true;
                 }
@@ -90,9 +91,10 @@ async fn resolve(
             .collect::<TryJoinAll<_>>()
             .await?;
 
-        let disk = LayeredDisk::new(input.read_only, layers)
+        let mut disk = LayeredDisk::new(input.read_only, layers)
             .await
             .map_err(ResolveLayeredDiskError::CreateDisk)?;
+        disk.set_commit_driver(input.driver_source.simple());
 
         ResolvedDisk::new(disk).map_err(ResolveLayeredDiskError::InvalidDisk)
     }
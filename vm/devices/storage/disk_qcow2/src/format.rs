@@ -0,0 +1,107 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! QCOW2 on-disk format definitions.
+//!
+//! See the QEMU `docs/interop/qcow2.txt` specification. All fields are big
+//! endian.
+
+use self::packed_nums::*;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+#[expect(non_camel_case_types)]
+mod packed_nums {
+    pub type u32_be = zerocopy::U32<zerocopy::BigEndian>;
+    pub type u64_be = zerocopy::U64<zerocopy::BigEndian>;
+}
+
+/// `QFI\xfb`
+pub const MAGIC: u32 = 0x514649fb;
+
+pub const VERSION_2: u32 = 2;
+pub const VERSION_3: u32 = 3;
+
+pub const MIN_CLUSTER_BITS: u32 = 9;
+pub const MAX_CLUSTER_BITS: u32 = 21;
+pub const DEFAULT_CLUSTER_BITS: u32 = 16;
+
+/// The refcount order implied by version 2 images, and the default for new
+/// images (16-bit refcounts).
+pub const DEFAULT_REFCOUNT_ORDER: u32 = 4;
+
+pub const MAX_BACKING_FILE_NAME: u32 = 1023;
+
+/// The common header shared by version 2 and version 3 images.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct Header {
+    pub magic: u32_be,
+    pub version: u32_be,
+    pub backing_file_offset: u64_be,
+    pub backing_file_size: u32_be,
+    pub cluster_bits: u32_be,
+    pub size: u64_be,
+    pub crypt_method: u32_be,
+    pub l1_size: u32_be,
+    pub l1_table_offset: u64_be,
+    pub refcount_table_offset: u64_be,
+    pub refcount_table_clusters: u32_be,
+    pub nb_snapshots: u32_be,
+    pub snapshots_offset: u64_be,
+}
+
+/// Additional header fields present in version 3 images, immediately
+/// following [`Header`].
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct HeaderV3 {
+    pub incompatible_features: u64_be,
+    pub compatible_features: u64_be,
+    pub autoclear_features: u64_be,
+    pub refcount_order: u32_be,
+    pub header_length: u32_be,
+}
+
+pub const HEADER_V2_LEN: u32 = size_of::<Header>() as u32;
+pub const HEADER_V3_LEN: u32 = (size_of::<Header>() + size_of::<HeaderV3>()) as u32;
+
+pub const INCOMPAT_DIRTY: u64 = 1 << 0;
+pub const INCOMPAT_CORRUPT: u64 = 1 << 1;
+pub const INCOMPAT_EXTERNAL_DATA_FILE: u64 = 1 << 2;
+pub const INCOMPAT_COMPRESSION_TYPE: u64 = 1 << 3;
+pub const INCOMPAT_EXTENDED_L2: u64 = 1 << 4;
+pub const INCOMPAT_KNOWN: u64 = INCOMPAT_DIRTY
+    | INCOMPAT_CORRUPT
+    | INCOMPAT_EXTERNAL_DATA_FILE
+    | INCOMPAT_COMPRESSION_TYPE
+    | INCOMPAT_EXTENDED_L2;
+
+/// Header extension framing. The data is padded to a multiple of 8 bytes.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct HeaderExtension {
+    pub extension_type: u32_be,
+    pub len: u32_be,
+}
+
+pub const HEADER_EXT_END: u32 = 0;
+pub const HEADER_EXT_BACKING_FORMAT: u32 = 0xe2792aca;
+
+/// Set in L1 and L2 entries when the referenced cluster has a refcount of
+/// exactly one, meaning it can be written in place.
+pub const OFLAG_COPIED: u64 = 1 << 63;
+
+pub const L1E_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+
+pub const L2E_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+pub const L2E_COMPRESSED: u64 = 1 << 62;
+/// Version 3 only: the cluster reads as all zeroes.
+pub const L2E_ZERO: u64 = 1 << 0;
+
+pub const REFT_OFFSET_MASK: u64 = 0xffff_ffff_ffff_fe00;
+
+/// The unit in which compressed cluster lengths are expressed.
+pub const COMPRESSED_SECTOR_SIZE: u64 = 512;
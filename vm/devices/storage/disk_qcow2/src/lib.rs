@@ -0,0 +1,1457 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A QCOW2 disk layer implementation, [`Qcow2DiskLayer`].
+//!
+//! QCOW2 images are exposed as a [`disk_layered`] layer: a guest cluster that
+//! is allocated in the image is reported as present, and everything else falls
+//! through to the next layer. An image's backing file is therefore just the
+//! next layer down in a [`LayeredDisk`](disk_layered::LayeredDisk); the
+//! [`resolver`] builds that stack from a
+//! [`Qcow2DiskHandle`](disk_backend_resources::Qcow2DiskHandle).
+//!
+//! Because QCOW2 tracks presence at cluster granularity rather than sector
+//! granularity, a partial write to a cluster that is not yet allocated must
+//! first copy the rest of the cluster from the backing disk. The layer is given
+//! its own handle to the backing disk for this purpose.
+//!
+//! # Supported features
+//!
+//! - Version 2 and version 3 images, with any cluster size.
+//! - Reading normal, zero, and deflate-compressed clusters.
+//! - Writing, with new clusters (and L2 tables and refcount blocks) allocated
+//!   at the end of the file. The refcount table is moved there too when it
+//!   fills up.
+//! - Unmapping whole clusters.
+//!
+//! Encryption, external data files, extended L2 entries, and non-deflate
+//! compression are not supported. Images with internal snapshots, or whose
+//! refcounts are not byte-sized, can only be opened read-only.
+
+#![forbid(unsafe_code)]
+
+mod format;
+pub mod resolver;
+
+use blocking::unblock;
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use disk_backend::UnmapBehavior;
+use disk_image_layer::ImageFile;
+use disk_image_layer::read_at;
+use disk_image_layer::write_at;
+use disk_layered::LayerIo;
+use disk_layered::SectorMarker;
+use guestmem::GuestMemory;
+use guestmem::MemoryRead;
+use inspect::Inspect;
+use parking_lot::Mutex;
+use scsi_buffers::OwnedRequestBuffers;
+use scsi_buffers::RequestBuffers;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+use thiserror::Error;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+const SECTOR_SHIFT: u32 = 9;
+const SECTOR_SIZE: u32 = 1 << SECTOR_SHIFT;
+
+/// The maximum number of L2 tables to keep cached in memory.
+const L2_CACHE_ENTRIES: usize = 256;
+
+/// An error encountered while opening or creating a QCOW2 image.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum OpenError {
+    /// An IO error occurred.
+    #[error("io error")]
+    Io(#[from] io::Error),
+    /// The file is not a QCOW2 image.
+    #[error("not a qcow2 image")]
+    InvalidMagic,
+    /// The image version is not supported.
+    #[error("unsupported qcow2 version: {0}")]
+    UnsupportedVersion(u32),
+    /// The cluster size is out of range.
+    #[error("invalid cluster bits: {0}")]
+    InvalidClusterBits(u32),
+    /// The disk size is invalid.
+    #[error("invalid disk size: {0}")]
+    InvalidDiskSize(u64),
+    /// The L1 table is too small for the disk size, or too large to load.
+    #[error("invalid L1 table size: {0}")]
+    InvalidL1Size(u32),
+    /// The header length is invalid.
+    #[error("invalid header length: {0}")]
+    InvalidHeaderLength(u32),
+    /// The backing file name is invalid.
+    #[error("invalid backing file name")]
+    InvalidBackingFile,
+    /// The image is encrypted.
+    #[error("encrypted images are not supported")]
+    Encrypted,
+    /// The image uses a feature that is not supported.
+    #[error("unsupported incompatible features: {0:#x}")]
+    UnsupportedFeatures(u64),
+    /// The image is marked corrupt.
+    #[error("image is marked corrupt")]
+    Corrupt,
+    /// The image has lazy refcounts that were not cleanly written back.
+    #[error("image was not closed cleanly and must be repaired before writing")]
+    Dirty,
+    /// The image has internal snapshots, which require copy-on-write of
+    /// shared clusters.
+    #[error("images with internal snapshots cannot be opened for write")]
+    InternalSnapshots,
+    /// The refcount width is not supported for writing.
+    #[error("unsupported refcount order for write: {0}")]
+    UnsupportedRefcountOrder(u32),
+}
+
+/// The backing file of a QCOW2 image, as recorded in its header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackingFile {
+    /// The path to the backing file. Relative paths are relative to the
+    /// directory containing the image.
+    pub path: String,
+    /// The format of the backing file (e.g. `raw` or `qcow2`), if recorded.
+    pub format: Option<String>,
+}
+
+/// Parameters for creating a new QCOW2 image with [`Qcow2DiskLayer::create`].
+#[derive(Debug, Clone)]
+pub struct CreateParams<'a> {
+    /// The virtual size of the disk, in bytes. Must be a multiple of 512.
+    pub size: u64,
+    /// The log2 of the cluster size. If `None`, uses 64KiB clusters.
+    pub cluster_bits: Option<u32>,
+    /// The backing file to record in the image.
+    pub backing_file: Option<&'a BackingFile>,
+}
+
+/// A disk layer backed by a QCOW2 image file.
+#[derive(Inspect)]
+pub struct Qcow2DiskLayer {
+    #[inspect(flatten)]
+    inner: Arc<Inner>,
+    #[inspect(skip)]
+    cow_source: Option<Disk>,
+}
+
+#[derive(Inspect)]
+struct Inner {
+    #[inspect(skip)]
+    file: File,
+    #[inspect(flatten)]
+    meta: Meta,
+    #[inspect(skip)]
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Inspect)]
+struct Meta {
+    version: u32,
+    cluster_bits: u32,
+    #[inspect(hex)]
+    disk_size: u64,
+    refcount_order: u32,
+    l1_table_offset: u64,
+    backing_file: Option<String>,
+    read_only: bool,
+}
+
+struct State {
+    l1: Vec<u64>,
+    l2_cache: HashMap<u64, Box<[u64]>>,
+    refcount_table: Vec<u64>,
+    /// The offset of the refcount table, which moves when the table grows.
+    refcount_table_offset: u64,
+    /// The cluster-aligned end of the file, where new clusters are allocated.
+    end_of_file: u64,
+}
+
+/// The mapping of a guest cluster to the image file.
+#[derive(Debug, Copy, Clone)]
+enum Mapping {
+    /// Not present in this image.
+    Unallocated,
+    /// Reads as zero. May have a preallocated host cluster.
+    Zero { host: Option<u64> },
+    /// Stored uncompressed at the given host offset.
+    Data { host: u64 },
+    /// Stored compressed in the given host byte range.
+    Compressed { host: u64, len: u64 },
+}
+
+/// The result of an attempted write.
+enum WriteResult {
+    Done,
+    /// The write needs the contents of these guest clusters from the backing
+    /// disk before it can allocate them.
+    NeedFill(Vec<u64>),
+}
+
+impl Qcow2DiskLayer {
+    /// Opens a QCOW2 image.
+    ///
+    /// `cow_source` is the disk backing this image, used to fill the rest of
+    /// newly allocated clusters on partial writes. It should be the same disk
+    /// that is placed below this layer in the layered disk. If it is `None`,
+    /// newly allocated clusters are zero filled.
+    pub fn open(file: File, read_only: bool, cow_source: Option<Disk>) -> Result<Self, OpenError> {
+        let header = ParsedHeader::read(&file)?;
+        let Header {
+            version,
+            cluster_bits,
+            disk_size,
+            l1_size,
+            l1_table_offset,
+            refcount_table_offset,
+            refcount_table_clusters,
+            nb_snapshots,
+            incompatible_features,
+            autoclear_features,
+            refcount_order,
+        } = header.header;
+
+        if incompatible_features & format::INCOMPAT_CORRUPT != 0 {
+            return Err(OpenError::Corrupt);
+        }
+        let unsupported = incompatible_features & !format::INCOMPAT_DIRTY;
+        if unsupported != 0 {
+            return Err(OpenError::UnsupportedFeatures(unsupported));
+        }
+        if !read_only {
+            if incompatible_features & format::INCOMPAT_DIRTY != 0 {
+                return Err(OpenError::Dirty);
+            }
+            if nb_snapshots != 0 {
+                return Err(OpenError::InternalSnapshots);
+            }
+            if !(3..=6).contains(&refcount_order) {
+                return Err(OpenError::UnsupportedRefcountOrder(refcount_order));
+            }
+        }
+
+        let cluster_size = 1u64 << cluster_bits;
+        let l2_bits = cluster_bits - 3;
+        let required_l1 = disk_size.div_ceil(cluster_size << l2_bits);
+        // Cap the L1 table at 32MiB, as QEMU does.
+        if (l1_size as u64) < required_l1 || l1_size as u64 > (32 << 20) / 8 {
+            return Err(OpenError::InvalidL1Size(l1_size));
+        }
+
+        let mut l1 = vec![0u64; l1_size as usize];
+        read_at(&file, l1.as_mut_bytes(), l1_table_offset)?;
+        for entry in &mut l1 {
+            *entry = u64::from_be(*entry);
+        }
+
+        let refcount_table = if read_only {
+            Vec::new()
+        } else {
+            let mut table = vec![0u64; (refcount_table_clusters as usize) << l2_bits];
+            read_at(&file, table.as_mut_bytes(), refcount_table_offset)?;
+            for entry in &mut table {
+                *entry = u64::from_be(*entry);
+            }
+            table
+        };
+
+        if !read_only && version >= format::VERSION_3 && autoclear_features != 0 {
+            // Any autoclear feature (such as persistent dirty bitmaps) is
+            // invalidated by writes from an implementation that doesn't
+            // understand it, so clear them all.
+            write_at(
+                &file,
+                0u64.as_bytes(),
+                format::HEADER_V2_LEN as u64
+                    + std::mem::offset_of!(format::HeaderV3, autoclear_features) as u64,
+            )?;
+        }
+
+        let end_of_file = file.metadata()?.len().next_multiple_of(cluster_size);
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                file,
+                meta: Meta {
+                    version,
+                    cluster_bits,
+                    disk_size,
+                    refcount_order,
+                    l1_table_offset,
+                    backing_file: header.backing_file.map(|b| b.path),
+                    read_only,
+                },
+                state: Mutex::new(State {
+                    l1,
+                    l2_cache: HashMap::new(),
+                    refcount_table,
+                    refcount_table_offset,
+                    end_of_file,
+                }),
+            }),
+            cow_source,
+        })
+    }
+
+    /// Returns whether `file` starts with the QCOW2 magic number.
+    pub fn probe(file: &File) -> io::Result<bool> {
+        let mut magic = [0; 4];
+        read_at(file, &mut magic, 0)?;
+        Ok(u32::from_be_bytes(magic) == format::MAGIC)
+    }
+
+    /// Returns the backing file recorded in the header of the image in `file`.
+    pub fn backing_file(file: &File) -> Result<Option<BackingFile>, OpenError> {
+        Ok(ParsedHeader::read(file)?.backing_file)
+    }
+
+    /// Formats `file` as an empty version 3 QCOW2 image.
+    ///
+    /// Any existing contents of the file are discarded.
+    pub fn create(file: &File, params: &CreateParams<'_>) -> Result<(), OpenError> {
+        let cluster_bits = params.cluster_bits.unwrap_or(format::DEFAULT_CLUSTER_BITS);
+        if !(format::MIN_CLUSTER_BITS..=format::MAX_CLUSTER_BITS).contains(&cluster_bits) {
+            return Err(OpenError::InvalidClusterBits(cluster_bits));
+        }
+        if params.size % SECTOR_SIZE as u64 != 0 {
+            return Err(OpenError::InvalidDiskSize(params.size));
+        }
+        let cluster_size = 1u64 << cluster_bits;
+        let l2_bits = cluster_bits - 3;
+        let l1_size = params.size.div_ceil(cluster_size << l2_bits);
+        let l1_clusters = (l1_size * 8).div_ceil(cluster_size).max(1);
+
+        // Layout: header, refcount table, refcount block, L1 table.
+        let refcount_table_offset = cluster_size;
+        let refcount_block_offset = cluster_size * 2;
+        let l1_table_offset = cluster_size * 3;
+        let total_clusters = 3 + l1_clusters;
+        let refcount_bytes = 1u64 << (format::DEFAULT_REFCOUNT_ORDER - 3);
+        if total_clusters * refcount_bytes > cluster_size {
+            return Err(OpenError::InvalidDiskSize(params.size));
+        }
+
+        // Build the header cluster: fixed header, extensions, backing file
+        // name.
+        let mut header_cluster = vec![0u8; cluster_size as usize];
+        let mut offset = format::HEADER_V3_LEN as usize;
+        let mut backing_file_offset = 0;
+        let mut backing_file_size = 0;
+        if let Some(backing) = params.backing_file {
+            if backing.path.is_empty()
+                || backing.path.len() > format::MAX_BACKING_FILE_NAME as usize
+            {
+                return Err(OpenError::InvalidBackingFile);
+            }
+            if let Some(backing_format) = &backing.format {
+                let ext = format::HeaderExtension {
+                    extension_type: format::HEADER_EXT_BACKING_FORMAT.into(),
+                    len: (backing_format.len() as u32).into(),
+                };
+                header_cluster[offset..][..size_of_val(&ext)].copy_from_slice(ext.as_bytes());
+                offset += size_of_val(&ext);
+                header_cluster[offset..][..backing_format.len()]
+                    .copy_from_slice(backing_format.as_bytes());
+                offset += backing_format.len().next_multiple_of(8);
+            }
+            // The end extension is all zeroes.
+            offset += size_of::<format::HeaderExtension>();
+            backing_file_offset = offset as u64;
+            backing_file_size = backing.path.len() as u32;
+            if offset + backing.path.len() > header_cluster.len() {
+                return Err(OpenError::InvalidBackingFile);
+            }
+            header_cluster[offset..][..backing.path.len()].copy_from_slice(backing.path.as_bytes());
+        }
+
+        let header = format::Header {
+            magic: format::MAGIC.into(),
+            version: format::VERSION_3.into(),
+            backing_file_offset: backing_file_offset.into(),
+            backing_file_size: backing_file_size.into(),
+            cluster_bits: cluster_bits.into(),
+            size: params.size.into(),
+            crypt_method: 0.into(),
+            l1_size: (l1_size as u32).into(),
+            l1_table_offset: l1_table_offset.into(),
+            refcount_table_offset: refcount_table_offset.into(),
+            refcount_table_clusters: 1.into(),
+            nb_snapshots: 0.into(),
+            snapshots_offset: 0.into(),
+        };
+        let header_v3 = format::HeaderV3 {
+            incompatible_features: 0.into(),
+            compatible_features: 0.into(),
+            autoclear_features: 0.into(),
+            refcount_order: format::DEFAULT_REFCOUNT_ORDER.into(),
+            header_length: format::HEADER_V3_LEN.into(),
+        };
+        header_cluster[..size_of_val(&header)].copy_from_slice(header.as_bytes());
+        header_cluster[size_of_val(&header)..][..size_of_val(&header_v3)]
+            .copy_from_slice(header_v3.as_bytes());
+
+        let mut refcount_block = vec![0u8; cluster_size as usize];
+        for refcount in refcount_block
+            .chunks_exact_mut(refcount_bytes as usize)
+            .take(total_clusters as usize)
+        {
+            refcount.copy_from_slice(&1u16.to_be_bytes());
+        }
+
+        file.set_len(0)?;
+        file.set_len(total_clusters * cluster_size)?;
+        write_at(file, &header_cluster, 0)?;
+        write_at(
+            file,
+            &refcount_block_offset.to_be_bytes(),
+            refcount_table_offset,
+        )?;
+        write_at(file, &refcount_block, refcount_block_offset)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    fn cluster_size(&self) -> u64 {
+        1 << self.inner.meta.cluster_bits
+    }
+
+    /// Reads the full contents of the guest cluster at `cluster_offset` from
+    /// the backing disk.
+    async fn read_backing(&self, disk: &Disk, cluster_offset: u64) -> Result<Vec<u8>, DiskError> {
+        let cluster_size = self.cluster_size();
+        let mut data = vec![0; cluster_size as usize];
+        let sector = cluster_offset >> SECTOR_SHIFT;
+        let count = (cluster_size >> SECTOR_SHIFT).min(disk.sector_count().saturating_sub(sector));
+        if count > 0 {
+            let len = (count << SECTOR_SHIFT) as usize;
+            let mut mem = GuestMemory::allocate(len);
+            disk.read_vectored(
+                &OwnedRequestBuffers::linear(0, len, true).buffer(&mem),
+                sector,
+            )
+            .await?;
+            data[..len].copy_from_slice(&mem.inner_buf_mut().unwrap()[..len]);
+        }
+        Ok(data)
+    }
+}
+
+/// The interesting fields of the image header, normalized across versions.
+#[derive(Debug, Copy, Clone)]
+struct Header {
+    version: u32,
+    cluster_bits: u32,
+    disk_size: u64,
+    l1_size: u32,
+    l1_table_offset: u64,
+    refcount_table_offset: u64,
+    refcount_table_clusters: u32,
+    nb_snapshots: u32,
+    incompatible_features: u64,
+    autoclear_features: u64,
+    refcount_order: u32,
+}
+
+struct ParsedHeader {
+    header: Header,
+    backing_file: Option<BackingFile>,
+}
+
+impl ParsedHeader {
+    fn read(file: &File) -> Result<Self, OpenError> {
+        let mut raw = format::Header::new_zeroed();
+        read_at(file, raw.as_mut_bytes(), 0)?;
+        if raw.magic.get() != format::MAGIC {
+            return Err(OpenError::InvalidMagic);
+        }
+        let version = raw.version.get();
+        let (v3, header_length) = match version {
+            format::VERSION_2 => (None, format::HEADER_V2_LEN),
+            format::VERSION_3 => {
+                let mut v3 = format::HeaderV3::new_zeroed();
+                read_at(file, v3.as_mut_bytes(), format::HEADER_V2_LEN as u64)?;
+                let header_length = v3.header_length.get();
+                if header_length < format::HEADER_V3_LEN || header_length % 8 != 0 {
+                    return Err(OpenError::InvalidHeaderLength(header_length));
+                }
+                (Some(v3), header_length)
+            }
+            version => return Err(OpenError::UnsupportedVersion(version)),
+        };
+
+        let cluster_bits = raw.cluster_bits.get();
+        if !(format::MIN_CLUSTER_BITS..=format::MAX_CLUSTER_BITS).contains(&cluster_bits) {
+            return Err(OpenError::InvalidClusterBits(cluster_bits));
+        }
+        let disk_size = raw.size.get();
+        if disk_size % SECTOR_SIZE as u64 != 0 {
+            return Err(OpenError::InvalidDiskSize(disk_size));
+        }
+        if raw.crypt_method.get() != 0 {
+            return Err(OpenError::Encrypted);
+        }
+
+        let header = Header {
+            version,
+            cluster_bits,
+            disk_size,
+            l1_size: raw.l1_size.get(),
+            l1_table_offset: raw.l1_table_offset.get(),
+            refcount_table_offset: raw.refcount_table_offset.get(),
+            refcount_table_clusters: raw.refcount_table_clusters.get(),
+            nb_snapshots: raw.nb_snapshots.get(),
+            incompatible_features: v3.map_or(0, |v3| v3.incompatible_features.get()),
+            autoclear_features: v3.map_or(0, |v3| v3.autoclear_features.get()),
+            refcount_order: v3.map_or(format::DEFAULT_REFCOUNT_ORDER, |v3| v3.refcount_order.get()),
+        };
+        if header.incompatible_features & !format::INCOMPAT_KNOWN != 0 {
+            return Err(OpenError::UnsupportedFeatures(
+                header.incompatible_features & !format::INCOMPAT_KNOWN,
+            ));
+        }
+
+        let backing_file_offset = raw.backing_file_offset.get();
+        let backing_file = if backing_file_offset != 0 {
+            let len = raw.backing_file_size.get();
+            if len == 0 || len > format::MAX_BACKING_FILE_NAME {
+                return Err(OpenError::InvalidBackingFile);
+            }
+            let mut path = vec![0; len as usize];
+            read_at(file, &mut path, backing_file_offset)?;
+            let path = String::from_utf8(path).map_err(|_| OpenError::InvalidBackingFile)?;
+            let format = Self::read_backing_format(
+                file,
+                header_length as u64,
+                backing_file_offset.min(1 << cluster_bits),
+            )?;
+            Some(BackingFile { path, format })
+        } else {
+            None
+        };
+
+        Ok(Self {
+            header,
+            backing_file,
+        })
+    }
+
+    /// Walks the header extensions looking for the backing file format.
+    fn read_backing_format(
+        file: &File,
+        mut offset: u64,
+        end: u64,
+    ) -> Result<Option<String>, OpenError> {
+        let mut ext = format::HeaderExtension::new_zeroed();
+        while offset + size_of_val(&ext) as u64 <= end {
+            read_at(file, ext.as_mut_bytes(), offset)?;
+            offset += size_of_val(&ext) as u64;
+            let len = ext.len.get() as u64;
+            match ext.extension_type.get() {
+                format::HEADER_EXT_END => break,
+                format::HEADER_EXT_BACKING_FORMAT => {
+                    if len > format::MAX_BACKING_FILE_NAME as u64 {
+                        return Err(OpenError::InvalidBackingFile);
+                    }
+                    let mut name = vec![0; len as usize];
+                    read_at(file, &mut name, offset)?;
+                    return Ok(Some(
+                        String::from_utf8(name).map_err(|_| OpenError::InvalidBackingFile)?,
+                    ));
+                }
+                _ => {}
+            }
+            offset += len.next_multiple_of(8);
+        }
+        Ok(None)
+    }
+}
+
+impl Inner {
+    fn cluster_size(&self) -> u64 {
+        1 << self.meta.cluster_bits
+    }
+
+    fn l2_bits(&self) -> u32 {
+        self.meta.cluster_bits - 3
+    }
+
+    fn l1_index(&self, guest: u64) -> usize {
+        (guest >> (self.meta.cluster_bits + self.l2_bits())) as usize
+    }
+
+    fn l2_index(&self, guest: u64) -> usize {
+        ((guest >> self.meta.cluster_bits) & ((1 << self.l2_bits()) - 1)) as usize
+    }
+
+    /// Returns the L2 table at `offset`, loading it into the cache if
+    /// necessary.
+    fn l2_table<'a>(&self, state: &'a mut State, offset: u64) -> io::Result<&'a mut [u64]> {
+        if !state.l2_cache.contains_key(&offset) {
+            if state.l2_cache.len() >= L2_CACHE_ENTRIES {
+                // FUTURE: use a smarter eviction policy.
+                state.l2_cache.clear();
+            }
+            let mut table = vec![0u64; 1 << self.l2_bits()].into_boxed_slice();
+            read_at(&self.file, table.as_mut_bytes(), offset)?;
+            for entry in &mut table {
+                *entry = u64::from_be(*entry);
+            }
+            state.l2_cache.insert(offset, table);
+        }
+        Ok(state.l2_cache.get_mut(&offset).unwrap())
+    }
+
+    fn lookup(&self, state: &mut State, guest: u64) -> io::Result<Mapping> {
+        let Some(&l1_entry) = state.l1.get(self.l1_index(guest)) else {
+            return Ok(Mapping::Unallocated);
+        };
+        let l2_offset = l1_entry & format::L1E_OFFSET_MASK;
+        if l2_offset == 0 {
+            return Ok(Mapping::Unallocated);
+        }
+        let entry = self.l2_table(state, l2_offset)?[self.l2_index(guest)];
+        Ok(self.decode_l2_entry(entry))
+    }
+
+    fn decode_l2_entry(&self, entry: u64) -> Mapping {
+        if entry & format::L2E_COMPRESSED != 0 {
+            let offset_bits = 62 - (self.meta.cluster_bits - 8);
+            let host = entry & ((1 << offset_bits) - 1);
+            let sectors = (entry >> offset_bits) & ((1 << (self.meta.cluster_bits - 8)) - 1);
+            let len = (sectors + 1) * format::COMPRESSED_SECTOR_SIZE
+                - (host % format::COMPRESSED_SECTOR_SIZE);
+            return Mapping::Compressed { host, len };
+        }
+        let host = entry & format::L2E_OFFSET_MASK;
+        if self.meta.version >= format::VERSION_3 && entry & format::L2E_ZERO != 0 {
+            Mapping::Zero {
+                host: (host != 0).then_some(host),
+            }
+        } else if host == 0 {
+            Mapping::Unallocated
+        } else {
+            Mapping::Data { host }
+        }
+    }
+
+    fn read_compressed(&self, host: u64, len: u64) -> io::Result<Vec<u8>> {
+        let mut compressed = vec![0; len as usize];
+        read_at(&self.file, &mut compressed, host)?;
+        let mut data = vec![0; self.cluster_size() as usize];
+        let mut decompress = flate2::Decompress::new(false);
+        decompress
+            .decompress(&compressed, &mut data, flate2::FlushDecompress::Finish)
+            .map_err(io::Error::other)?;
+        if decompress.total_out() != data.len() as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated compressed cluster",
+            ));
+        }
+        Ok(data)
+    }
+
+    /// Writes `data` at guest byte `offset`.
+    ///
+    /// Clusters that are only partially written and not yet allocated are
+    /// filled from `fill` (keyed by guest cluster offset). If a needed fill is
+    /// missing and `need_fill` is set, nothing is written and the missing
+    /// clusters are returned instead.
+    fn write_with_fill(
+        &self,
+        offset: u64,
+        data: &[u8],
+        fill: &HashMap<u64, Vec<u8>>,
+        need_fill: bool,
+    ) -> io::Result<WriteResult> {
+        let cluster_size = self.cluster_size();
+        let mut in_place = Vec::new();
+        {
+            let mut state = self.state.lock();
+            let mut plan = Vec::new();
+            let mut missing = Vec::new();
+            let mut pos = 0;
+            while pos < data.len() {
+                let guest = offset + pos as u64;
+                let in_cluster = guest & (cluster_size - 1);
+                let len = ((cluster_size - in_cluster) as usize).min(data.len() - pos);
+                let mapping = self.lookup(&mut state, guest)?;
+                match mapping {
+                    Mapping::Data { host } => in_place.push((host + in_cluster, pos..pos + len)),
+                    Mapping::Unallocated
+                        if need_fill
+                            && len as u64 != cluster_size
+                            && !fill.contains_key(&(guest - in_cluster)) =>
+                    {
+                        missing.push(guest - in_cluster);
+                    }
+                    _ => plan.push((guest, mapping, pos..pos + len)),
+                }
+                pos += len;
+            }
+            if !missing.is_empty() {
+                return Ok(WriteResult::NeedFill(missing));
+            }
+
+            for (guest, mapping, range) in plan {
+                let in_cluster = (guest & (cluster_size - 1)) as usize;
+                let cluster = guest - in_cluster as u64;
+                let chunk = &data[range];
+                let mut buf;
+                let contents = if chunk.len() as u64 == cluster_size {
+                    chunk
+                } else {
+                    buf = match mapping {
+                        Mapping::Unallocated => fill
+                            .get(&cluster)
+                            .cloned()
+                            .unwrap_or_else(|| vec![0; cluster_size as usize]),
+                        Mapping::Zero { .. } => vec![0; cluster_size as usize],
+                        Mapping::Compressed { host, len } => self.read_compressed(host, len)?,
+                        Mapping::Data { .. } => unreachable!(),
+                    };
+                    buf[in_cluster..][..chunk.len()].copy_from_slice(chunk);
+                    &buf[..]
+                };
+                let host = match mapping {
+                    Mapping::Zero { host: Some(host) } => host,
+                    // FUTURE: free the host clusters of compressed data. These
+                    // can be shared between multiple compressed guest clusters,
+                    // so for now they are just leaked.
+                    _ => self.allocate_cluster(&mut state)?,
+                };
+                write_at(&self.file, contents, host)?;
+                self.set_l2_entry(&mut state, cluster, host | format::OFLAG_COPIED)?;
+            }
+        }
+
+        for (host, range) in in_place {
+            write_at(&self.file, &data[range], host)?;
+        }
+        Ok(WriteResult::Done)
+    }
+
+    /// Allocates a new cluster at the end of the file.
+    fn allocate_cluster(&self, state: &mut State) -> io::Result<u64> {
+        let host = state.end_of_file;
+        state.end_of_file += self.cluster_size();
+        self.file.set_len(state.end_of_file)?;
+        self.set_refcount(state, host >> self.meta.cluster_bits, 1)?;
+        Ok(host)
+    }
+
+    fn set_l2_entry(&self, state: &mut State, guest: u64, entry: u64) -> io::Result<()> {
+        let l1_index = self.l1_index(guest);
+        let l1_entry = *state
+            .l1
+            .get(l1_index)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "offset beyond L1 table"))?;
+        let mut l2_offset = l1_entry & format::L1E_OFFSET_MASK;
+        if l2_offset == 0 {
+            // The new cluster is zeroed by extending the file.
+            l2_offset = self.allocate_cluster(state)?;
+            let l1_entry = l2_offset | format::OFLAG_COPIED;
+            write_at(
+                &self.file,
+                &l1_entry.to_be_bytes(),
+                self.meta.l1_table_offset + l1_index as u64 * 8,
+            )?;
+            state.l1[l1_index] = l1_entry;
+        }
+        let l2_index = self.l2_index(guest);
+        write_at(
+            &self.file,
+            &entry.to_be_bytes(),
+            l2_offset + l2_index as u64 * 8,
+        )?;
+        self.l2_table(state, l2_offset)?[l2_index] = entry;
+        Ok(())
+    }
+
+    fn set_refcount(&self, state: &mut State, cluster_index: u64, value: u64) -> io::Result<()> {
+        let refcount_bytes = 1u64 << (self.meta.refcount_order - 3);
+        let entries_per_block = self.cluster_size() / refcount_bytes;
+        let table_index = (cluster_index / entries_per_block) as usize;
+        let block_index = cluster_index % entries_per_block;
+        if table_index >= state.refcount_table.len() {
+            self.grow_refcount_table(state, table_index)?;
+        }
+        let table_entry = state.refcount_table[table_index];
+        let mut block = table_entry & format::REFT_OFFSET_MASK;
+        if block == 0 {
+            // The new block is zeroed by extending the file. Its own refcount
+            // is set after it is linked into the table, since it may describe
+            // itself.
+            block = state.end_of_file;
+            state.end_of_file += self.cluster_size();
+            self.file.set_len(state.end_of_file)?;
+            write_at(
+                &self.file,
+                &block.to_be_bytes(),
+                state.refcount_table_offset + table_index as u64 * 8,
+            )?;
+            state.refcount_table[table_index] = block;
+            self.set_refcount(state, block >> self.meta.cluster_bits, 1)?;
+        }
+        let bytes = value.to_be_bytes();
+        write_at(
+            &self.file,
+            &bytes[8 - refcount_bytes as usize..],
+            block + block_index * refcount_bytes,
+        )?;
+        Ok(())
+    }
+
+    /// Moves the refcount table to a larger allocation at the end of the file,
+    /// so that it has an entry for `table_index`.
+    fn grow_refcount_table(&self, state: &mut State, table_index: usize) -> io::Result<()> {
+        let cluster_size = self.cluster_size();
+        let old_offset = state.refcount_table_offset;
+        let old_clusters = state.refcount_table.len() as u64 >> self.l2_bits();
+        // Double the table, so that growing a file by appending clusters
+        // needs only a logarithmic number of moves.
+        let entries = (table_index + 1).max(state.refcount_table.len() * 2);
+        let new_clusters = (entries as u64 * 8).div_ceil(cluster_size);
+        let new_clusters_u32 = u32::try_from(new_clusters)
+            .map_err(|_| io::Error::other("qcow2 refcount table is too large"))?;
+
+        let mut table = state.refcount_table.clone();
+        table.resize((new_clusters as usize) << self.l2_bits(), 0);
+        let mut bytes = table.clone();
+        for entry in &mut bytes {
+            *entry = entry.to_be();
+        }
+        let new_offset = state.end_of_file;
+        state.end_of_file += new_clusters * cluster_size;
+        self.file.set_len(state.end_of_file)?;
+        write_at(&self.file, bytes.as_bytes(), new_offset)?;
+        // The new table must be durable before the header points at it.
+        self.file.sync_data()?;
+
+        // The offset and cluster count are adjacent in the header, so update
+        // them with a single write.
+        let mut header_fields = [0; 12];
+        header_fields[..8].copy_from_slice(&new_offset.to_be_bytes());
+        header_fields[8..].copy_from_slice(&new_clusters_u32.to_be_bytes());
+        write_at(
+            &self.file,
+            &header_fields,
+            std::mem::offset_of!(format::Header, refcount_table_offset) as u64,
+        )?;
+        state.refcount_table = table;
+        state.refcount_table_offset = new_offset;
+
+        // Account for the new table, and free the old one.
+        // FUTURE: punch a hole for the old table and reuse its clusters.
+        for i in 0..new_clusters {
+            self.set_refcount(state, (new_offset >> self.meta.cluster_bits) + i, 1)?;
+        }
+        for i in 0..old_clusters {
+            self.set_refcount(state, (old_offset >> self.meta.cluster_bits) + i, 0)?;
+        }
+        Ok(())
+    }
+}
+
+impl ImageFile for Inner {
+    fn file(&self) -> &File {
+        &self.file
+    }
+
+    fn disk_size(&self) -> u64 {
+        self.meta.disk_size
+    }
+
+    fn sector_size(&self) -> u32 {
+        SECTOR_SIZE
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.meta.read_only
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> io::Result<Vec<Range<u64>>> {
+        let cluster_size = self.cluster_size();
+        let mut present = Vec::<Range<u64>>::new();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let guest = offset + pos as u64;
+            let in_cluster = guest & (cluster_size - 1);
+            let len = ((cluster_size - in_cluster) as usize).min(buf.len() - pos);
+            let chunk = &mut buf[pos..pos + len];
+            let mapping = self.lookup(&mut self.state.lock(), guest)?;
+            let is_present = match mapping {
+                Mapping::Unallocated => false,
+                Mapping::Zero { .. } => {
+                    chunk.fill(0);
+                    true
+                }
+                Mapping::Data { host } => {
+                    read_at(&self.file, chunk, host + in_cluster)?;
+                    true
+                }
+                Mapping::Compressed { host, len } => {
+                    let data = self.read_compressed(host, len)?;
+                    chunk.copy_from_slice(&data[in_cluster as usize..][..chunk.len()]);
+                    true
+                }
+            };
+            if is_present {
+                let range = guest..guest + len as u64;
+                match present.last_mut() {
+                    Some(last) if last.end == range.start => last.end = range.end,
+                    _ => present.push(range),
+                }
+            }
+            pos += len;
+        }
+        Ok(present)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+        // Without a backing disk, newly allocated clusters are zero filled.
+        self.write_with_fill(offset, data, &HashMap::new(), false)?;
+        Ok(())
+    }
+
+    /// Unmaps the whole clusters in the range.
+    fn unmap(&self, range: Range<u64>, next_is_zero: bool) -> io::Result<()> {
+        let cluster_size = self.cluster_size();
+        let mut state = self.state.lock();
+        let new_entry = if next_is_zero {
+            0
+        } else if self.meta.version >= format::VERSION_3 {
+            format::L2E_ZERO
+        } else {
+            // Version 2 images cannot represent zero clusters over a backing
+            // file.
+            return Ok(());
+        };
+        let mut cluster = range.start.next_multiple_of(cluster_size);
+        while cluster + cluster_size <= range.end {
+            let (unchanged, host) = match self.lookup(&mut state, cluster)? {
+                Mapping::Unallocated => (next_is_zero, None),
+                Mapping::Zero { host: None } => (!next_is_zero, None),
+                Mapping::Zero { host: Some(host) } | Mapping::Data { host } => (false, Some(host)),
+                Mapping::Compressed { .. } => (false, None),
+            };
+            if !unchanged {
+                self.set_l2_entry(&mut state, cluster, new_entry)?;
+                if let Some(host) = host {
+                    // FUTURE: punch a hole in the file, and reuse freed
+                    // clusters for new allocations.
+                    self.set_refcount(&mut state, host >> self.meta.cluster_bits, 0)?;
+                }
+            }
+            cluster += cluster_size;
+        }
+        Ok(())
+    }
+}
+
+impl LayerIo for Qcow2DiskLayer {
+    fn layer_type(&self) -> &str {
+        "qcow2"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.inner.meta.disk_size >> SECTOR_SHIFT
+    }
+
+    fn sector_size(&self) -> u32 {
+        SECTOR_SIZE
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        None
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        SECTOR_SIZE
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        true
+    }
+
+    fn is_logically_read_only(&self) -> bool {
+        self.inner.meta.read_only
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        disk_image_layer::sync_cache(&self.inner).await
+    }
+
+    async fn read(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        marker: SectorMarker<'_>,
+    ) -> Result<(), DiskError> {
+        disk_image_layer::read(&self.inner, buffers, sector, marker).await
+    }
+
+    async fn write(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        let Some(cow_source) = &self.cow_source else {
+            return disk_image_layer::write(&self.inner, buffers, sector, fua).await;
+        };
+        if self.inner.meta.read_only {
+            return Err(DiskError::ReadOnly);
+        }
+        disk_image_layer::check_range(&*self.inner, sector, buffers.len() as u64)?;
+        let data = Arc::new(buffers.reader().read_all()?);
+        let offset = sector << SECTOR_SHIFT;
+        let mut fill = HashMap::new();
+        loop {
+            let inner = self.inner.clone();
+            let this_data = data.clone();
+            let (result, returned_fill) = unblock(move || {
+                let result = inner.write_with_fill(offset, &this_data, &fill, true);
+                (result, fill)
+            })
+            .await;
+            fill = returned_fill;
+            match result.map_err(DiskError::Io)? {
+                WriteResult::Done => break,
+                WriteResult::NeedFill(clusters) => {
+                    for cluster in clusters {
+                        let contents = self.read_backing(cow_source, cluster).await?;
+                        fill.insert(cluster, contents);
+                    }
+                }
+            }
+        }
+        if fua {
+            let inner = self.inner.clone();
+            unblock(move || inner.file.sync_data())
+                .await
+                .map_err(DiskError::Io)?;
+        }
+        Ok(())
+    }
+
+    async fn unmap(
+        &self,
+        sector: u64,
+        count: u64,
+        _block_level_only: bool,
+        next_is_zero: bool,
+    ) -> Result<(), DiskError> {
+        disk_image_layer::unmap(&self.inner, sector, count, next_is_zero).await
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        UnmapBehavior::Unspecified
+    }
+
+    fn optimal_unmap_sectors(&self) -> u32 {
+        (self.cluster_size() >> SECTOR_SHIFT) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BackingFile;
+    use super::CreateParams;
+    use super::Qcow2DiskLayer;
+    use super::format;
+    use disk_backend::Disk;
+    use disk_backend::DiskIo;
+    use disk_file::FileDisk;
+    use disk_image_layer::read_at;
+    use disk_image_layer::test_helpers::layered;
+    use disk_image_layer::test_helpers::pattern;
+    use disk_image_layer::test_helpers::read;
+    use disk_image_layer::test_helpers::write;
+    use disk_image_layer::write_at;
+    use disk_layered::DiskLayer;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use std::fs::File;
+    use std::io::Write;
+
+    const SIZE: u64 = 16 << 20;
+    const CLUSTER_SIZE: usize = 1 << format::DEFAULT_CLUSTER_BITS;
+    const CLUSTER_SECTORS: u64 = CLUSTER_SIZE as u64 / 512;
+
+    fn create(size: u64, backing_file: Option<&BackingFile>) -> File {
+        create_with_cluster_bits(size, None, backing_file)
+    }
+
+    fn create_with_cluster_bits(
+        size: u64,
+        cluster_bits: Option<u32>,
+        backing_file: Option<&BackingFile>,
+    ) -> File {
+        let file = tempfile::tempfile().unwrap();
+        Qcow2DiskLayer::create(
+            &file,
+            &CreateParams {
+                size,
+                cluster_bits,
+                backing_file,
+            },
+        )
+        .unwrap();
+        file
+    }
+
+    fn read_u64(file: &File, offset: u64) -> u64 {
+        let mut bytes = [0; 8];
+        read_at(file, &mut bytes, offset).unwrap();
+        u64::from_be_bytes(bytes)
+    }
+
+    fn read_u32(file: &File, offset: u64) -> u32 {
+        let mut bytes = [0; 4];
+        read_at(file, &mut bytes, offset).unwrap();
+        u32::from_be_bytes(bytes)
+    }
+
+    /// Returns a raw disk filled with a pattern, and its contents.
+    fn base_disk() -> (Disk, Vec<u8>) {
+        let mut base = tempfile::tempfile().unwrap();
+        let data = pattern(SIZE as usize, 0x5a);
+        base.write_all(&data).unwrap();
+        (
+            Disk::new(FileDisk::open(base, true).unwrap()).unwrap(),
+            data,
+        )
+    }
+
+    #[async_test]
+    async fn read_write() {
+        let file = create(SIZE, None);
+        let disk = layered(
+            false,
+            vec![DiskLayer::new(
+                Qcow2DiskLayer::open(file.try_clone().unwrap(), false, None).unwrap(),
+            )],
+        )
+        .await;
+        let mem = GuestMemory::allocate(0x20000);
+
+        assert!(read(&disk, &mem, 0, 4096).await.iter().all(|&b| b == 0));
+
+        // A write spanning a cluster boundary.
+        let data = (0..0x1800).map(|i| i as u8).collect::<Vec<_>>();
+        write(&disk, &mem, 124, &data).await;
+        assert_eq!(read(&disk, &mem, 124, data.len()).await, data);
+        assert!(
+            read(&disk, &mem, 0, 124 * 512)
+                .await
+                .iter()
+                .all(|&b| b == 0)
+        );
+        drop(disk);
+
+        // Reopen and make sure the data persisted.
+        let disk = layered(
+            true,
+            vec![DiskLayer::new(
+                Qcow2DiskLayer::open(file, true, None).unwrap(),
+            )],
+        )
+        .await;
+        assert_eq!(read(&disk, &mem, 124, data.len()).await, data);
+    }
+
+    #[async_test]
+    async fn backing_file() {
+        let (base, base_data) = base_disk();
+        let backing = BackingFile {
+            path: "base.raw".into(),
+            format: Some("raw".into()),
+        };
+        let file = create(SIZE, Some(&backing));
+        assert_eq!(
+            Qcow2DiskLayer::backing_file(&file).unwrap().as_ref(),
+            Some(&backing)
+        );
+
+        let disk = layered(
+            false,
+            vec![
+                DiskLayer::new(Qcow2DiskLayer::open(file, false, Some(base.clone())).unwrap()),
+                DiskLayer::from_disk(base.clone()),
+            ],
+        )
+        .await;
+        let mem = GuestMemory::allocate(0x20000);
+
+        // Partially overwrite a cluster; the rest must still come from the
+        // backing disk.
+        write(&disk, &mem, 130, &[0xcc; 1024]).await;
+        let mut expected = base_data[120 * 512..][..0x4000].to_vec();
+        expected[10 * 512..][..1024].fill(0xcc);
+        assert_eq!(read(&disk, &mem, 120, 0x4000).await, expected);
+    }
+
+    #[async_test]
+    async fn compressed_cluster() {
+        let file = create(SIZE, None);
+        let mem = GuestMemory::allocate(CLUSTER_SIZE);
+
+        // Allocate the L2 table covering the first cluster by writing the
+        // second one.
+        let disk = layered(
+            false,
+            vec![DiskLayer::new(
+                Qcow2DiskLayer::open(file.try_clone().unwrap(), false, None).unwrap(),
+            )],
+        )
+        .await;
+        write(&disk, &mem, CLUSTER_SECTORS, &[0x11; 512]).await;
+        drop(disk);
+
+        // Append a compressed copy of the first cluster and point its L2 entry
+        // at it, as QEMU would.
+        let data = pattern(CLUSTER_SIZE, 0x33);
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert!(compressed.len() < CLUSTER_SIZE);
+        let host = file.metadata().unwrap().len();
+        write_at(&file, &compressed, host).unwrap();
+
+        let l1_table_offset = read_u64(&file, 40);
+        let l2_offset = read_u64(&file, l1_table_offset) & format::L1E_OFFSET_MASK;
+        let offset_bits = 62 - (format::DEFAULT_CLUSTER_BITS - 8);
+        let extra_sectors = (host + compressed.len() as u64 - 1) / 512 - host / 512;
+        write_at(
+            &file,
+            &(format::L2E_COMPRESSED | (extra_sectors << offset_bits) | host).to_be_bytes(),
+            l2_offset,
+        )
+        .unwrap();
+
+        let disk = layered(
+            false,
+            vec![DiskLayer::new(
+                Qcow2DiskLayer::open(file.try_clone().unwrap(), false, None).unwrap(),
+            )],
+        )
+        .await;
+        assert_eq!(read(&disk, &mem, 0, CLUSTER_SIZE).await, data);
+
+        // A partial write must keep the rest of the decompressed cluster.
+        write(&disk, &mem, 4, &[0xcc; 1024]).await;
+        let mut expected = data;
+        expected[4 * 512..][..1024].fill(0xcc);
+        assert_eq!(read(&disk, &mem, 0, CLUSTER_SIZE).await, expected);
+        drop(disk);
+
+        let disk = layered(
+            true,
+            vec![DiskLayer::new(
+                Qcow2DiskLayer::open(file, true, None).unwrap(),
+            )],
+        )
+        .await;
+        assert_eq!(read(&disk, &mem, 0, CLUSTER_SIZE).await, expected);
+        assert_eq!(
+            read(&disk, &mem, CLUSTER_SECTORS, 512).await,
+            vec![0x11; 512]
+        );
+    }
+
+    #[async_test]
+    async fn unmap() {
+        let file = create(SIZE, None);
+        let disk = layered(
+            false,
+            vec![DiskLayer::new(
+                Qcow2DiskLayer::open(file.try_clone().unwrap(), false, None).unwrap(),
+            )],
+        )
+        .await;
+        let mem = GuestMemory::allocate(CLUSTER_SIZE * 2);
+        let data = pattern(CLUSTER_SIZE * 2, 0x77);
+        write(&disk, &mem, 0, &data).await;
+
+        // Only whole clusters are unmapped.
+        disk.unmap(0, CLUSTER_SECTORS + 8, false).await.unwrap();
+        let mut expected = data;
+        expected[..CLUSTER_SIZE].fill(0);
+        assert_eq!(read(&disk, &mem, 0, CLUSTER_SIZE * 2).await, expected);
+        drop(disk);
+
+        let disk = layered(
+            true,
+            vec![DiskLayer::new(
+                Qcow2DiskLayer::open(file, true, None).unwrap(),
+            )],
+        )
+        .await;
+        assert_eq!(read(&disk, &mem, 0, CLUSTER_SIZE * 2).await, expected);
+    }
+
+    #[async_test]
+    async fn unmap_over_backing_file() {
+        let (base, base_data) = base_disk();
+        let file = create(
+            SIZE,
+            Some(&BackingFile {
+                path: "base.raw".into(),
+                format: None,
+            }),
+        );
+        let disk = layered(
+            false,
+            vec![
+                DiskLayer::new(Qcow2DiskLayer::open(file, false, Some(base.clone())).unwrap()),
+                DiskLayer::from_disk(base),
+            ],
+        )
+        .await;
+        let mem = GuestMemory::allocate(CLUSTER_SIZE * 2);
+
+        // An unmapped cluster must read as zero, not fall through to the
+        // backing disk. This applies both to allocated clusters and to
+        // clusters that were never written.
+        write(&disk, &mem, 0, &[0xcc; CLUSTER_SIZE]).await;
+        disk.unmap(0, CLUSTER_SECTORS * 2, false).await.unwrap();
+        assert!(
+            read(&disk, &mem, 0, CLUSTER_SIZE * 2)
+                .await
+                .iter()
+                .all(|&b| b == 0)
+        );
+        assert_eq!(
+            read(&disk, &mem, CLUSTER_SECTORS * 2, CLUSTER_SIZE).await,
+            base_data[CLUSTER_SIZE * 2..][..CLUSTER_SIZE]
+        );
+    }
+
+    #[async_test]
+    async fn version_2() {
+        let (base, base_data) = base_disk();
+        let file = create(
+            SIZE,
+            Some(&BackingFile {
+                path: "base.raw".into(),
+                format: None,
+            }),
+        );
+        // A version 2 header is a prefix of a version 3 header. The version 3
+        // fields that follow it start with zeroes, which read as the end of
+        // the header extensions.
+        write_at(&file, &format::VERSION_2.to_be_bytes(), 4).unwrap();
+
+        let disk = layered(
+            false,
+            vec![
+                DiskLayer::new(
+                    Qcow2DiskLayer::open(file.try_clone().unwrap(), false, Some(base.clone()))
+                        .unwrap(),
+                ),
+                DiskLayer::from_disk(base.clone()),
+            ],
+        )
+        .await;
+        let mem = GuestMemory::allocate(CLUSTER_SIZE);
+        write(&disk, &mem, 2, &[0xcc; 512]).await;
+        let mut expected = base_data[..CLUSTER_SIZE].to_vec();
+        expected[2 * 512..][..512].fill(0xcc);
+        assert_eq!(read(&disk, &mem, 0, CLUSTER_SIZE).await, expected);
+
+        // Version 2 images cannot mark a cluster as zero over a backing file,
+        // so unmap leaves it alone.
+        disk.unmap(0, CLUSTER_SECTORS, false).await.unwrap();
+        assert_eq!(read(&disk, &mem, 0, CLUSTER_SIZE).await, expected);
+        drop(disk);
+
+        assert_eq!(read_u32(&file, 4), format::VERSION_2);
+        let disk = layered(
+            true,
+            vec![
+                DiskLayer::new(Qcow2DiskLayer::open(file, true, Some(base.clone())).unwrap()),
+                DiskLayer::from_disk(base),
+            ],
+        )
+        .await;
+        assert_eq!(read(&disk, &mem, 0, CLUSTER_SIZE).await, expected);
+    }
+
+    #[async_test]
+    async fn refcount_table_growth() {
+        // With 512-byte clusters and 16-bit refcounts, the initial
+        // single-cluster refcount table covers 8MiB of the file.
+        const CLUSTER_BITS: u32 = 9;
+        const WRITTEN: usize = 12 << 20;
+        const CHUNK: usize = 1 << 20;
+        let file = create_with_cluster_bits(SIZE, Some(CLUSTER_BITS), None);
+        let open = |read_only| {
+            let file = file.try_clone().unwrap();
+            async move {
+                layered(
+                    read_only,
+                    vec![DiskLayer::new(
+                        Qcow2DiskLayer::open(file, read_only, None).unwrap(),
+                    )],
+                )
+                .await
+            }
+        };
+
+        let disk = open(false).await;
+        let mem = GuestMemory::allocate(CHUNK);
+        let data = pattern(WRITTEN, 0x42);
+        for (i, chunk) in data.chunks(CHUNK).enumerate() {
+            write(&disk, &mem, (i * CHUNK / 512) as u64, chunk).await;
+        }
+        drop(disk);
+
+        assert!(file.metadata().unwrap().len() > 8 << 20);
+        let refcount_table_offset = read_u64(&file, 48);
+        let refcount_table_clusters = read_u32(&file, 56);
+        assert!(refcount_table_clusters > 1);
+
+        // The new table's own clusters must be accounted for.
+        let table_cluster = refcount_table_offset >> CLUSTER_BITS;
+        let entries_per_block = (1 << CLUSTER_BITS) / 2;
+        let block = read_u64(
+            &file,
+            refcount_table_offset + (table_cluster / entries_per_block) * 8,
+        );
+        assert_ne!(block, 0);
+        let mut refcount = [0; 2];
+        read_at(
+            &file,
+            &mut refcount,
+            block + (table_cluster % entries_per_block) * 2,
+        )
+        .unwrap();
+        assert_eq!(u16::from_be_bytes(refcount), 1);
+
+        // The image can be reopened and extended further.
+        let disk = open(false).await;
+        write(&disk, &mem, (WRITTEN / 512) as u64, &[0xcc; 512]).await;
+        drop(disk);
+
+        let disk = open(true).await;
+        for (i, chunk) in data.chunks(CHUNK).enumerate() {
+            assert_eq!(
+                read(&disk, &mem, (i * CHUNK / 512) as u64, CHUNK).await,
+                chunk
+            );
+        }
+        assert_eq!(
+            read(&disk, &mem, (WRITTEN / 512) as u64, 512).await,
+            vec![0xcc; 512]
+        );
+    }
+}
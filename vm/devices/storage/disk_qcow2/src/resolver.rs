@@ -0,0 +1,54 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource resolver for QCOW2 disks.
+
+use crate::OpenError;
+use crate::Qcow2DiskLayer;
+use async_trait::async_trait;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::Qcow2DiskHandle;
+use disk_image_layer::resolver::ResolveImageDiskError;
+use disk_image_layer::resolver::resolve_image_disk;
+use disk_layered::DiskLayer;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::DiskHandleKind;
+
+declare_static_async_resolver! {
+    Qcow2DiskResolver,
+    (DiskHandleKind, Qcow2DiskHandle)
+}
+
+/// Resolver for [`Qcow2DiskHandle`].
+pub struct Qcow2DiskResolver;
+
+/// Error type for [`Qcow2DiskResolver`].
+pub type ResolveQcow2DiskError = ResolveImageDiskError<OpenError>;
+
+#[async_trait]
+impl AsyncResolveResource<DiskHandleKind, Qcow2DiskHandle> for Qcow2DiskResolver {
+    type Output = ResolvedDisk;
+    type Error = ResolveQcow2DiskError;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: Qcow2DiskHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let read_only = input.read_only;
+        resolve_image_disk(resolver, resource.backing, input, |backing| {
+            // The layer fills partially written clusters from the backing
+            // disk, so it gets its own handle to it.
+            Ok(DiskLayer::new(Qcow2DiskLayer::open(
+                resource.file,
+                read_only,
+                backing,
+            )?))
+        })
+        .await
+    }
+}
@@ -0,0 +1,884 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Dynamic and differencing VHD1 images.
+//!
+//! The data of a dynamic image is stored in blocks that are allocated on first
+//! write and located through the block allocation table (BAT). Each block is
+//! preceded by a sector bitmap. A dynamic image ignores the bitmap: every
+//! sector of an allocated block is present, and unallocated blocks read as
+//! zero. A differencing image uses the bitmap to track which sectors it
+//! contains; the rest come from its parent, which is the next layer down in a
+//! [`LayeredDisk`](disk_layered::LayeredDisk).
+//!
+//! New blocks are allocated where the footer is, and the footer is moved to
+//! the new end of the file.
+
+use crate::OpenError;
+use crate::validate_footer;
+use disk_backend::DiskError;
+use disk_backend::UnmapBehavior;
+use disk_image_layer::ImageFile;
+use disk_image_layer::read_at;
+use disk_image_layer::write_at;
+use disk_layered::LayerIo;
+use disk_layered::SectorMarker;
+use guid::Guid;
+use inspect::Inspect;
+use parking_lot::Mutex;
+use scsi_buffers::RequestBuffers;
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+use vhd1_defs::VhdDynamicHeader;
+use vhd1_defs::VhdFooter;
+use vhd1_defs::VhdParentLocatorEntry;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+const SECTOR_SIZE: u64 = 512;
+const MIN_BLOCK_SIZE: u32 = 4096;
+const MAX_BLOCK_SIZE: u32 = 256 << 20;
+/// The largest disk the format supports.
+const MAX_DISK_SIZE: u64 = 2040 << 30;
+/// Parent locator data larger than this is considered corrupt.
+const MAX_LOCATOR_LEN: u32 = 64 << 10;
+
+/// The type of a VHD1 image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Vhd1DiskType {
+    /// A fixed image, opened with [`Vhd1Disk::open_fixed`](crate::Vhd1Disk::open_fixed).
+    Fixed,
+    /// A dynamic image, opened with [`DynamicVhd1Layer::open`].
+    Dynamic,
+    /// A differencing image, opened with [`DynamicVhd1Layer::open`].
+    Differencing,
+}
+
+/// The location of the parent of a differencing image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParentLocator {
+    /// The unique ID in the parent's footer.
+    pub unique_id: Guid,
+    /// The parent's file name.
+    pub unicode_name: String,
+    /// The path to the parent, relative to the directory containing this
+    /// image. This uses Windows path separators.
+    pub relative_path: Option<String>,
+    /// The absolute Windows path to the parent.
+    pub absolute_path: Option<String>,
+}
+
+/// Information about a VHD1 image, from [`Vhd1Disk::info`](crate::Vhd1Disk::info).
+#[derive(Debug, Clone)]
+pub struct Vhd1Info {
+    /// The type of the image.
+    pub disk_type: Vhd1DiskType,
+    /// The virtual size of the disk, in bytes.
+    pub disk_size: u64,
+    /// The unique ID of the image. Children record this in
+    /// [`ParentLocator::unique_id`].
+    pub unique_id: Guid,
+    /// The parent locator, for differencing images.
+    pub parent: Option<ParentLocator>,
+}
+
+/// Parameters for creating a new image with [`DynamicVhd1Layer::create`].
+#[derive(Debug, Clone)]
+pub struct CreateParams<'a> {
+    /// The virtual size of the disk, in bytes. Must be a multiple of 512.
+    pub size: u64,
+    /// The block size. If `None`, uses 2MiB blocks.
+    pub block_size: Option<u32>,
+    /// The parent of a differencing image.
+    pub parent: Option<&'a ParentLocator>,
+}
+
+/// A disk layer backed by a dynamic or differencing VHD1 image.
+#[derive(Inspect)]
+pub struct DynamicVhd1Layer {
+    #[inspect(flatten)]
+    inner: Arc<Inner>,
+}
+
+#[derive(Inspect)]
+struct Inner {
+    #[inspect(skip)]
+    file: File,
+    #[inspect(skip)]
+    footer: VhdFooter,
+    #[inspect(flatten)]
+    meta: Meta,
+    #[inspect(skip)]
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Inspect)]
+struct Meta {
+    #[inspect(hex)]
+    disk_size: u64,
+    #[inspect(hex)]
+    block_size: u32,
+    #[inspect(display)]
+    unique_id: Guid,
+    #[inspect(hex)]
+    bat_offset: u64,
+    /// The size of the sector bitmap preceding each block, padded to a
+    /// sector boundary.
+    #[inspect(hex)]
+    bitmap_size: u64,
+    has_parent: bool,
+    read_only: bool,
+}
+
+struct State {
+    bat: Vec<u32>,
+    /// The offset of the footer at the end of the file, where new blocks are
+    /// allocated.
+    footer_offset: u64,
+}
+
+impl DynamicVhd1Layer {
+    /// Opens a dynamic or differencing VHD1 image.
+    pub fn open(file: File, read_only: bool) -> Result<Self, OpenError> {
+        let (footer, footer_offset) = read_footer(&file)?;
+        let has_parent = match footer.disk_type.get() {
+            VhdFooter::DISK_TYPE_DYNAMIC => false,
+            VhdFooter::DISK_TYPE_DIFFERENCING => true,
+            _ => return Err(OpenError::NotDynamic),
+        };
+        let header = read_header(&file, &footer)?;
+        let disk_size = footer.current_size.get();
+        let block_size = header.block_size.get();
+        validate_geometry(disk_size, block_size)?;
+
+        let block_count = disk_size.div_ceil(block_size as u64);
+        if (header.max_table_entries.get() as u64) < block_count {
+            return Err(OpenError::InvalidBat);
+        }
+        let bat_offset = header.table_offset.get();
+        let mut bat = vec![0; block_count as usize * 4];
+        read_at(&file, &mut bat, bat_offset)?;
+        let bat = bat
+            .chunks_exact(4)
+            .map(|entry| u32::from_be_bytes(entry.try_into().unwrap()))
+            .collect();
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                file,
+                footer,
+                meta: Meta {
+                    disk_size,
+                    block_size,
+                    unique_id: footer.unique_id,
+                    bat_offset,
+                    bitmap_size: bitmap_size(block_size),
+                    has_parent,
+                    read_only,
+                },
+                state: Mutex::new(State { bat, footer_offset }),
+            }),
+        })
+    }
+
+    /// Formats `file` as an empty dynamic or differencing VHD1 image.
+    ///
+    /// Any existing contents of the file are discarded.
+    pub fn create(file: &File, params: &CreateParams<'_>) -> Result<(), OpenError> {
+        let block_size = params
+            .block_size
+            .unwrap_or(VhdDynamicHeader::DEFAULT_BLOCK_SIZE);
+        validate_geometry(params.size, block_size)?;
+
+        // Layout: footer copy, dynamic header, BAT, parent locators, footer.
+        let header_offset = VhdFooter::LEN;
+        let bat_offset = header_offset + VhdDynamicHeader::LEN;
+        let max_table_entries = params.size.div_ceil(block_size as u64);
+        let bat_length = (max_table_entries * 4).next_multiple_of(SECTOR_SIZE);
+        let mut end = bat_offset + bat_length;
+
+        let mut header = VhdDynamicHeader {
+            cookie: VhdDynamicHeader::COOKIE_MAGIC,
+            data_offset: VhdDynamicHeader::DATA_OFFSET.into(),
+            table_offset: bat_offset.into(),
+            header_version: VhdDynamicHeader::HEADER_VERSION.into(),
+            max_table_entries: (max_table_entries as u32).into(),
+            block_size: block_size.into(),
+            ..FromZeros::new_zeroed()
+        };
+
+        let mut locators = Vec::new();
+        let disk_type = if let Some(parent) = params.parent {
+            header.parent_unique_id = parent.unique_id;
+            for (dst, src) in header
+                .parent_unicode_name
+                .chunks_exact_mut(2)
+                .zip(parent.unicode_name.encode_utf16())
+            {
+                dst.copy_from_slice(&src.to_be_bytes());
+            }
+            let paths = [
+                (
+                    VhdParentLocatorEntry::PLATFORM_CODE_W2RU,
+                    &parent.relative_path,
+                ),
+                (
+                    VhdParentLocatorEntry::PLATFORM_CODE_W2KU,
+                    &parent.absolute_path,
+                ),
+            ];
+            for ((platform_code, path), entry) in paths
+                .into_iter()
+                .filter_map(|(code, path)| Some((code, path.as_deref()?)))
+                .zip(&mut header.parent_locators)
+            {
+                let data = path
+                    .encode_utf16()
+                    .flat_map(u16::to_le_bytes)
+                    .collect::<Vec<_>>();
+                let space = (data.len() as u64)
+                    .next_multiple_of(SECTOR_SIZE)
+                    .max(SECTOR_SIZE);
+                *entry = VhdParentLocatorEntry {
+                    platform_code: platform_code.into(),
+                    // Windows records the space in bytes, not sectors.
+                    platform_data_space: (space as u32).into(),
+                    platform_data_length: (data.len() as u32).into(),
+                    platform_data_offset: end.into(),
+                    ..FromZeros::new_zeroed()
+                };
+                locators.push((end, data));
+                end += space;
+            }
+            VhdFooter::DISK_TYPE_DIFFERENCING
+        } else {
+            VhdFooter::DISK_TYPE_DYNAMIC
+        };
+        header.checksum = header.compute_checksum().into();
+        let footer =
+            VhdFooter::new_dynamic(params.size, Guid::new_random(), disk_type, header_offset);
+
+        file.set_len(0)?;
+        write_at(file, footer.as_bytes(), 0)?;
+        write_at(file, header.as_bytes(), header_offset)?;
+        write_at(file, &vec![0xff; bat_length as usize], bat_offset)?;
+        for (offset, data) in locators {
+            write_at(file, &data, offset)?;
+        }
+        write_at(file, footer.as_bytes(), end)?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Returns information about the image in `file`.
+pub(crate) fn read_info(file: &File) -> Result<Vhd1Info, OpenError> {
+    let (footer, _) = read_footer(file)?;
+    let disk_type = match footer.disk_type.get() {
+        VhdFooter::DISK_TYPE_FIXED => Vhd1DiskType::Fixed,
+        VhdFooter::DISK_TYPE_DYNAMIC => Vhd1DiskType::Dynamic,
+        VhdFooter::DISK_TYPE_DIFFERENCING => Vhd1DiskType::Differencing,
+        disk_type => return Err(OpenError::UnsupportedDiskType(disk_type)),
+    };
+    let parent = if disk_type == Vhd1DiskType::Differencing {
+        Some(read_parent_locator(file, &read_header(file, &footer)?)?)
+    } else {
+        None
+    };
+    Ok(Vhd1Info {
+        disk_type,
+        disk_size: footer.current_size.get(),
+        unique_id: footer.unique_id,
+        parent,
+    })
+}
+
+/// Reads and validates the footer, returning it along with the offset at
+/// which it belongs.
+///
+/// If the footer at the end of the file is invalid, the copy at the start of
+/// a dynamic or differencing image is used instead.
+fn read_footer(file: &File) -> Result<(VhdFooter, u64), OpenError> {
+    let len = file.metadata()?.len();
+    if len < VhdFooter::LEN || len % VhdFooter::ALIGNMENT != 0 {
+        return Err(OpenError::InvalidFileSize(len));
+    }
+    let footer_offset = len - VhdFooter::LEN;
+    let mut footer = VhdFooter::new_zeroed();
+    read_at(file, footer.as_mut_bytes(), footer_offset)?;
+    let err = match validate_footer(&footer) {
+        Ok(()) => return Ok((footer, footer_offset)),
+        Err(err) => err,
+    };
+
+    let mut copy = VhdFooter::new_zeroed();
+    read_at(file, copy.as_mut_bytes(), 0)?;
+    if validate_footer(&copy).is_ok()
+        && matches!(
+            copy.disk_type.get(),
+            VhdFooter::DISK_TYPE_DYNAMIC | VhdFooter::DISK_TYPE_DIFFERENCING
+        )
+    {
+        // The footer will be rewritten at the end of the file on the next
+        // allocation.
+        return Ok((copy, len));
+    }
+    Err(err)
+}
+
+fn read_header(file: &File, footer: &VhdFooter) -> Result<VhdDynamicHeader, OpenError> {
+    let mut header = VhdDynamicHeader::new_zeroed();
+    read_at(file, header.as_mut_bytes(), footer.data_offset.get())?;
+    if header.cookie != VhdDynamicHeader::COOKIE_MAGIC {
+        return Err(OpenError::InvalidDynamicHeaderCookie);
+    }
+    if header.checksum.get() != header.compute_checksum() {
+        return Err(OpenError::InvalidDynamicHeaderChecksum);
+    }
+    if header.header_version.get() != VhdDynamicHeader::HEADER_VERSION {
+        return Err(OpenError::UnsupportedVersion(header.header_version.get()));
+    }
+    Ok(header)
+}
+
+fn read_parent_locator(file: &File, header: &VhdDynamicHeader) -> Result<ParentLocator, OpenError> {
+    let mut locator = ParentLocator {
+        unique_id: header.parent_unique_id,
+        unicode_name: utf16_string(&header.parent_unicode_name, u16::from_be_bytes)
+            .ok_or(OpenError::InvalidParentLocator)?,
+        relative_path: None,
+        absolute_path: None,
+    };
+    for entry in &header.parent_locators {
+        let path = match entry.platform_code.get() {
+            VhdParentLocatorEntry::PLATFORM_CODE_W2RU => &mut locator.relative_path,
+            VhdParentLocatorEntry::PLATFORM_CODE_W2KU => &mut locator.absolute_path,
+            _ => continue,
+        };
+        let len = entry.platform_data_length.get();
+        if len > MAX_LOCATOR_LEN {
+            return Err(OpenError::InvalidParentLocator);
+        }
+        let mut data = vec![0; len as usize];
+        read_at(file, &mut data, entry.platform_data_offset.get())?;
+        *path =
+            Some(utf16_string(&data, u16::from_le_bytes).ok_or(OpenError::InvalidParentLocator)?);
+    }
+    Ok(locator)
+}
+
+/// Decodes a UTF-16 string, stopping at the first null.
+fn utf16_string(data: &[u8], from_bytes: fn([u8; 2]) -> u16) -> Option<String> {
+    let chars = data
+        .chunks_exact(2)
+        .map(|c| from_bytes([c[0], c[1]]))
+        .take_while(|&c| c != 0)
+        .collect::<Vec<_>>();
+    String::from_utf16(&chars).ok()
+}
+
+fn validate_geometry(disk_size: u64, block_size: u32) -> Result<(), OpenError> {
+    if !block_size.is_power_of_two() || !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) {
+        return Err(OpenError::InvalidBlockSize(block_size));
+    }
+    if disk_size == 0 || disk_size > MAX_DISK_SIZE || disk_size % SECTOR_SIZE != 0 {
+        return Err(OpenError::InvalidDiskSize(disk_size));
+    }
+    Ok(())
+}
+
+fn bitmap_size(block_size: u32) -> u64 {
+    (block_size as u64 / SECTOR_SIZE / 8).next_multiple_of(SECTOR_SIZE)
+}
+
+/// Returns the range of sector bitmap bits that cover `len` bytes at
+/// `in_block`.
+fn bitmap_bits(in_block: u64, len: usize) -> Range<u64> {
+    in_block / SECTOR_SIZE..(in_block + len as u64) / SECTOR_SIZE
+}
+
+/// Sets `bits` in `bytes`, which holds the bitmap starting at byte
+/// `first_byte`. Bits are numbered from the most significant bit of each
+/// byte.
+fn set_bits(bytes: &mut [u8], first_byte: u64, bits: Range<u64>) {
+    for bit in bits {
+        bytes[(bit / 8 - first_byte) as usize] |= 0x80 >> (bit % 8);
+    }
+}
+
+impl Inner {
+    fn block_size(&self) -> u64 {
+        self.meta.block_size as u64
+    }
+
+    /// Returns the file offset of the sector bitmap at the start of `block`,
+    /// or `None` if the block is not allocated.
+    fn block(&self, state: &State, block: u64) -> io::Result<Option<u64>> {
+        let entry = state.bat[block as usize];
+        if entry == VhdDynamicHeader::BAT_ENTRY_UNALLOCATED {
+            return Ok(None);
+        }
+        let host = entry as u64 * SECTOR_SIZE;
+        if host + self.meta.bitmap_size + self.block_size() > state.footer_offset {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid BAT entry",
+            ));
+        }
+        Ok(Some(host))
+    }
+
+    /// Reads the sector bitmap bits for `len` bytes at `in_block` in the
+    /// block at `host`.
+    fn read_bitmap(&self, host: u64, in_block: u64, len: usize) -> io::Result<Vec<bool>> {
+        let bits = bitmap_bits(in_block, len);
+        let first_byte = bits.start / 8;
+        let mut bytes = vec![0; (bits.end.div_ceil(8) - first_byte) as usize];
+        read_at(&self.file, &mut bytes, host + first_byte)?;
+        Ok(bits
+            .map(|bit| bytes[(bit / 8 - first_byte) as usize] & (0x80 >> (bit % 8)) != 0)
+            .collect())
+    }
+
+    /// Sets the sector bitmap bits for `len` bytes at `in_block` in the block
+    /// at `host`.
+    fn write_bitmap(&self, host: u64, in_block: u64, len: usize) -> io::Result<()> {
+        let bits = bitmap_bits(in_block, len);
+        let first_byte = bits.start / 8;
+        let mut bytes = vec![0; (bits.end.div_ceil(8) - first_byte) as usize];
+        read_at(&self.file, &mut bytes, host + first_byte)?;
+        set_bits(&mut bytes, first_byte, bits);
+        write_at(&self.file, &bytes, host + first_byte)
+    }
+
+    /// Allocates a block in place of the footer, moves the footer past the
+    /// block, and writes the block's sector bitmap. Returns the file offset
+    /// of the block.
+    fn allocate(&self, state: &mut State, bitmap: &[u8]) -> io::Result<u64> {
+        let host = state.footer_offset;
+        if host / SECTOR_SIZE >= VhdDynamicHeader::BAT_ENTRY_UNALLOCATED as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                "VHD file is too large",
+            ));
+        }
+        let footer_offset = host + self.meta.bitmap_size + self.block_size();
+        // Write the new footer first so that the file always ends in a
+        // footer. The new block's data is zeroed by extending the file, and
+        // its bitmap overwrites the old footer.
+        write_at(&self.file, self.footer.as_bytes(), footer_offset)?;
+        write_at(&self.file, bitmap, host)?;
+        state.footer_offset = footer_offset;
+        Ok(host)
+    }
+
+    fn set_bat_entry(&self, state: &mut State, block: u64, entry: u32) -> io::Result<()> {
+        write_at(
+            &self.file,
+            &entry.to_be_bytes(),
+            self.meta.bat_offset + block * 4,
+        )?;
+        state.bat[block as usize] = entry;
+        Ok(())
+    }
+}
+
+impl ImageFile for Inner {
+    fn file(&self) -> &File {
+        &self.file
+    }
+
+    fn disk_size(&self) -> u64 {
+        self.meta.disk_size
+    }
+
+    fn sector_size(&self) -> u32 {
+        SECTOR_SIZE as u32
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.meta.read_only
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> io::Result<Vec<Range<u64>>> {
+        let block_size = self.block_size();
+        let mut present = Vec::<Range<u64>>::new();
+        let mut push = |range: Range<u64>| match present.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => present.push(range),
+        };
+        let mut pos = 0;
+        while pos < buf.len() {
+            let guest = offset + pos as u64;
+            let block_index = guest / block_size;
+            let in_block = guest % block_size;
+            let len = ((block_size - in_block) as usize).min(buf.len() - pos);
+            let chunk = &mut buf[pos..pos + len];
+            let (host, bitmap) = {
+                let state = self.state.lock();
+                let host = self.block(&state, block_index)?;
+                // Read the bitmap under the lock so that it is consistent with
+                // concurrent writes.
+                let bitmap = match host {
+                    Some(host) if self.meta.has_parent => {
+                        Some(self.read_bitmap(host, in_block, len)?)
+                    }
+                    _ => None,
+                };
+                (host, bitmap)
+            };
+            match (host, bitmap) {
+                (None, _) if self.meta.has_parent => {}
+                (None, _) => {
+                    chunk.fill(0);
+                    push(guest..guest + len as u64);
+                }
+                (Some(host), None) => {
+                    read_at(&self.file, chunk, host + self.meta.bitmap_size + in_block)?;
+                    push(guest..guest + len as u64);
+                }
+                (Some(host), Some(bitmap)) => {
+                    read_at(&self.file, chunk, host + self.meta.bitmap_size + in_block)?;
+                    for (i, &is_present) in bitmap.iter().enumerate() {
+                        if is_present {
+                            let start = guest + i as u64 * SECTOR_SIZE;
+                            push(start..start + SECTOR_SIZE);
+                        }
+                    }
+                }
+            }
+            pos += len;
+        }
+        Ok(present)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let block_size = self.block_size();
+        let mut in_place = Vec::new();
+        {
+            let mut state = self.state.lock();
+            let mut pos = 0;
+            while pos < data.len() {
+                let guest = offset + pos as u64;
+                let block_index = guest / block_size;
+                let in_block = guest % block_size;
+                let len = ((block_size - in_block) as usize).min(data.len() - pos);
+                let chunk = &data[pos..pos + len];
+                match self.block(&state, block_index)? {
+                    Some(host) if !self.meta.has_parent => {
+                        in_place.push((host + self.meta.bitmap_size + in_block, pos..pos + len));
+                    }
+                    Some(host) => {
+                        // Write the data before marking it present.
+                        write_at(&self.file, chunk, host + self.meta.bitmap_size + in_block)?;
+                        self.write_bitmap(host, in_block, len)?;
+                    }
+                    None => {
+                        let mut bitmap = vec![0; self.meta.bitmap_size as usize];
+                        if self.meta.has_parent && len as u64 != block_size {
+                            set_bits(&mut bitmap, 0, bitmap_bits(in_block, len));
+                        } else {
+                            bitmap.fill(0xff);
+                        }
+                        let host = self.allocate(&mut state, &bitmap)?;
+                        write_at(&self.file, chunk, host + self.meta.bitmap_size + in_block)?;
+                        self.set_bat_entry(&mut state, block_index, (host / SECTOR_SIZE) as u32)?;
+                    }
+                }
+                pos += len;
+            }
+        }
+
+        for (host, range) in in_place {
+            write_at(&self.file, &data[range], host)?;
+        }
+        Ok(())
+    }
+
+    /// Unmaps the whole blocks in the guest byte range.
+    fn unmap(&self, range: Range<u64>, next_is_zero: bool) -> io::Result<()> {
+        // A differencing image has no way to record that a block reads as
+        // zero, only that it comes from the parent.
+        if self.meta.has_parent && !next_is_zero {
+            return Ok(());
+        }
+        let block_size = self.block_size();
+        let mut state = self.state.lock();
+        let mut block = range.start.div_ceil(block_size);
+        while (block + 1) * block_size <= range.end {
+            if state.bat[block as usize] != VhdDynamicHeader::BAT_ENTRY_UNALLOCATED {
+                // FUTURE: reuse the space of unmapped blocks.
+                self.set_bat_entry(&mut state, block, VhdDynamicHeader::BAT_ENTRY_UNALLOCATED)?;
+            }
+            block += 1;
+        }
+        Ok(())
+    }
+}
+
+impl LayerIo for DynamicVhd1Layer {
+    fn layer_type(&self) -> &str {
+        "vhd1"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.inner.meta.disk_size / SECTOR_SIZE
+    }
+
+    fn sector_size(&self) -> u32 {
+        SECTOR_SIZE as u32
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        Some(self.inner.meta.unique_id.into())
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        SECTOR_SIZE as u32
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        true
+    }
+
+    fn is_logically_read_only(&self) -> bool {
+        self.inner.meta.read_only
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        disk_image_layer::sync_cache(&self.inner).await
+    }
+
+    async fn read(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        marker: SectorMarker<'_>,
+    ) -> Result<(), DiskError> {
+        disk_image_layer::read(&self.inner, buffers, sector, marker).await
+    }
+
+    async fn write(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        disk_image_layer::write(&self.inner, buffers, sector, fua).await
+    }
+
+    async fn unmap(
+        &self,
+        sector: u64,
+        count: u64,
+        _block_level_only: bool,
+        next_is_zero: bool,
+    ) -> Result<(), DiskError> {
+        disk_image_layer::unmap(&self.inner, sector, count, next_is_zero).await
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        UnmapBehavior::Unspecified
+    }
+
+    fn optimal_unmap_sectors(&self) -> u32 {
+        self.inner.meta.block_size / SECTOR_SIZE as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CreateParams;
+    use super::DynamicVhd1Layer;
+    use super::MAX_DISK_SIZE;
+    use super::ParentLocator;
+    use super::Vhd1DiskType;
+    use crate::OpenError;
+    use crate::Vhd1Disk;
+    use disk_backend::Disk;
+    use disk_image_layer::test_helpers::layered;
+    use disk_image_layer::test_helpers::pattern;
+    use disk_image_layer::test_helpers::read;
+    use disk_image_layer::test_helpers::write;
+    use disk_layered::DiskLayer;
+    use disk_layered::LayerIo;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+
+    const SIZE: u64 = 16 << 20;
+    const BLOCK_SIZE: u32 = 1 << 20;
+
+    fn create(parent: Option<&ParentLocator>) -> std::fs::File {
+        let file = tempfile::tempfile().unwrap();
+        DynamicVhd1Layer::create(
+            &file,
+            &CreateParams {
+                size: SIZE,
+                block_size: Some(BLOCK_SIZE),
+                parent,
+            },
+        )
+        .unwrap();
+        file
+    }
+
+    #[test]
+    fn max_disk_size() {
+        let create = |size| {
+            DynamicVhd1Layer::create(
+                &tempfile::tempfile().unwrap(),
+                &CreateParams {
+                    size,
+                    block_size: None,
+                    parent: None,
+                },
+            )
+        };
+        create(MAX_DISK_SIZE).unwrap();
+        assert!(matches!(
+            create(MAX_DISK_SIZE + 512),
+            Err(OpenError::InvalidDiskSize(_))
+        ));
+
+        // Larger than the CHS geometry limit, but still valid.
+        let file = tempfile::tempfile().unwrap();
+        DynamicVhd1Layer::create(
+            &file,
+            &CreateParams {
+                size: 1 << 40,
+                block_size: None,
+                parent: None,
+            },
+        )
+        .unwrap();
+        let layer = DynamicVhd1Layer::open(file, true).unwrap();
+        assert_eq!(layer.sector_count(), (1 << 40) / 512);
+    }
+
+    #[async_test]
+    async fn read_write() {
+        let file = create(None);
+        let info = Vhd1Disk::info(&file).unwrap();
+        assert_eq!(info.disk_type, Vhd1DiskType::Dynamic);
+        assert_eq!(info.disk_size, SIZE);
+        assert!(info.parent.is_none());
+        assert!(matches!(
+            Vhd1Disk::open_fixed(file.try_clone().unwrap(), true),
+            Err(OpenError::NotFixed)
+        ));
+
+        let disk = layered(
+            false,
+            vec![DiskLayer::new(
+                DynamicVhd1Layer::open(file.try_clone().unwrap(), false).unwrap(),
+            )],
+        )
+        .await;
+        let mem = GuestMemory::allocate(0x20000);
+
+        assert!(read(&disk, &mem, 0, 4096).await.iter().all(|&b| b == 0));
+
+        // A write spanning a block boundary allocates two blocks.
+        let len = file.metadata().unwrap().len();
+        let data = pattern(0x3000, 1);
+        let sector = (BLOCK_SIZE as u64 / 512) - 8;
+        write(&disk, &mem, sector, &data).await;
+        assert_eq!(read(&disk, &mem, sector, data.len()).await, data);
+        assert!(
+            read(&disk, &mem, sector - 16, 16 * 512)
+                .await
+                .iter()
+                .all(|&b| b == 0)
+        );
+        assert_eq!(
+            file.metadata().unwrap().len(),
+            len + 2 * (BLOCK_SIZE as u64 + 512)
+        );
+        drop(disk);
+
+        // Reopen and make sure the data and footer persisted.
+        assert_eq!(Vhd1Disk::info(&file).unwrap().unique_id, info.unique_id);
+        let disk = layered(
+            true,
+            vec![DiskLayer::new(DynamicVhd1Layer::open(file, true).unwrap())],
+        )
+        .await;
+        assert_eq!(read(&disk, &mem, sector, data.len()).await, data);
+    }
+
+    #[async_test]
+    async fn differencing() {
+        let parent_file = create(None);
+        let mem = GuestMemory::allocate(0x20000);
+        let parent_data = pattern(0x10000, 2);
+        {
+            let parent = layered(
+                false,
+                vec![DiskLayer::new(
+                    DynamicVhd1Layer::open(parent_file.try_clone().unwrap(), false).unwrap(),
+                )],
+            )
+            .await;
+            write(&parent, &mem, 0, &parent_data).await;
+        }
+
+        let locator = ParentLocator {
+            unique_id: Vhd1Disk::info(&parent_file).unwrap().unique_id,
+            unicode_name: "parent.vhd".into(),
+            relative_path: Some(r".\parent.vhd".into()),
+            absolute_path: Some(r"C:\disks\parent.vhd".into()),
+        };
+        let child_file = create(Some(&locator));
+        let info = Vhd1Disk::info(&child_file).unwrap();
+        assert_eq!(info.disk_type, Vhd1DiskType::Differencing);
+        assert_eq!(info.parent.as_ref(), Some(&locator));
+
+        let parent = Disk::new(
+            layered(
+                true,
+                vec![DiskLayer::new(
+                    DynamicVhd1Layer::open(parent_file, true).unwrap(),
+                )],
+            )
+            .await,
+        )
+        .unwrap();
+        let disk = layered(
+            false,
+            vec![
+                DiskLayer::new(
+                    DynamicVhd1Layer::open(child_file.try_clone().unwrap(), false).unwrap(),
+                ),
+                DiskLayer::from_disk(parent.clone()),
+            ],
+        )
+        .await;
+
+        // Partially overwrite a block; the rest must still come from the
+        // parent.
+        let data = [0xcc; 1024];
+        write(&disk, &mem, 10, &data).await;
+        let mut expected = parent_data.clone();
+        expected[10 * 512..][..data.len()].copy_from_slice(&data);
+        assert_eq!(read(&disk, &mem, 0, expected.len()).await, expected);
+        assert_eq!(read(&parent, &mem, 0, parent_data.len()).await, parent_data);
+
+        // Write to the now-allocated block again.
+        write(&disk, &mem, 100, &data).await;
+        expected[100 * 512..][..data.len()].copy_from_slice(&data);
+        drop(disk);
+
+        // Reopen the child and check that the sector bitmap persisted.
+        let disk = layered(
+            true,
+            vec![
+                DiskLayer::new(DynamicVhd1Layer::open(child_file, true).unwrap()),
+                DiskLayer::from_disk(parent),
+            ],
+        )
+        .await;
+        assert_eq!(read(&disk, &mem, 0, expected.len()).await, expected);
+    }
+}
@@ -1,17 +1,34 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-//! A VHD1 disk implementation. Currently only supports fixed VHD1.
+//! A VHD1 disk implementation.
+//!
+//! Fixed VHD1 images are opened as a [`Vhd1Disk`]. Dynamic and differencing
+//! images are opened as a [`DynamicVhd1Layer`], with the parent of a
+//! differencing image as the next layer down.
 
 #![expect(missing_docs)]
 #![forbid(unsafe_code)]
 
+mod dynamic;
+
+pub use dynamic::CreateParams;
+pub use dynamic::DynamicVhd1Layer;
+pub use dynamic::ParentLocator;
+pub use dynamic::Vhd1DiskType;
+pub use dynamic::Vhd1Info;
+
+use async_trait::async_trait;
 use disk_backend::DiskError;
 use disk_backend::DiskIo;
 use disk_backend::resolve::ResolveDiskParameters;
 use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::DynamicVhd1DiskHandle;
 use disk_backend_resources::FixedVhd1DiskHandle;
 use disk_file::FileDisk;
+use disk_image_layer::resolver::ResolveImageDiskError;
+use disk_image_layer::resolver::resolve_image_disk;
+use disk_layered::DiskLayer;
 use guid::Guid;
 use inspect::Inspect;
 use scsi_buffers::RequestBuffers;
@@ -22,7 +39,10 @@
 use std::io::Write;
 use thiserror::Error;
 use vhd1_defs::VhdFooter;
+use vm_resource::AsyncResolveResource;
 use vm_resource::ResolveResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
 use vm_resource::declare_static_resolver;
 use vm_resource::kind::DiskHandleKind;
 use zerocopy::FromZeros;
@@ -54,6 +74,32 @@ fn resolve(
     }
 }
 
+pub struct DynamicVhd1Resolver;
+declare_static_async_resolver!(DynamicVhd1Resolver, (DiskHandleKind, DynamicVhd1DiskHandle));
+
+pub type ResolveDynamicVhd1DiskError = ResolveImageDiskError<OpenError>;
+
+#[async_trait]
+impl AsyncResolveResource<DiskHandleKind, DynamicVhd1DiskHandle> for DynamicVhd1Resolver {
+    type Output = ResolvedDisk;
+    type Error = ResolveDynamicVhd1DiskError;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        rsrc: DynamicVhd1DiskHandle,
+        params: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let read_only = params.read_only;
+        resolve_image_disk(resolver, rsrc.parent, params, |_| {
+            Ok(DiskLayer::new(DynamicVhd1Layer::open(
+                rsrc.file, read_only,
+            )?))
+        })
+        .await
+    }
+}
+
 /// An open VHD1 disk.
 #[derive(Debug, Inspect)]
 pub struct Vhd1Disk {
@@ -75,18 +121,7 @@ struct Metadata {
 impl Metadata {
     /// Parses the essential metadata out of the footer.
     fn from_footer(footer: VhdFooter, file_size: u64) -> Result<Metadata, OpenError> {
-        if footer.cookie != VhdFooter::COOKIE_MAGIC {
-            return Err(OpenError::InvalidFooterCookie);
-        }
-        if footer.checksum != footer.compute_checksum().to_be_bytes() {
-            return Err(OpenError::InvalidFooterChecksum);
-        }
-        if footer.file_format_version != VhdFooter::FILE_FORMAT_VERSION_MAGIC.to_be_bytes() {
-            return Err(OpenError::UnsupportedVersion(
-                footer.file_format_version.into(),
-            ));
-        }
-        // FUTURE: support parsing non-fixed VHDs.
+        validate_footer(&footer)?;
         if footer.disk_type != VhdFooter::DISK_TYPE_FIXED.to_be_bytes() {
             return Err(OpenError::NotFixed);
         }
@@ -105,6 +140,22 @@ fn from_footer(footer: VhdFooter, file_size: u64) -> Result<Metadata, OpenError>
     }
 }
 
+/// Checks the footer's cookie, checksum, and version.
+fn validate_footer(footer: &VhdFooter) -> Result<(), OpenError> {
+    if footer.cookie != VhdFooter::COOKIE_MAGIC {
+        return Err(OpenError::InvalidFooterCookie);
+    }
+    if footer.checksum != footer.compute_checksum().to_be_bytes() {
+        return Err(OpenError::InvalidFooterChecksum);
+    }
+    if footer.file_format_version != VhdFooter::FILE_FORMAT_VERSION_MAGIC.to_be_bytes() {
+        return Err(OpenError::UnsupportedVersion(
+            footer.file_format_version.into(),
+        ));
+    }
+    Ok(())
+}
+
 /// An error encountered while opening a VHD.
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
@@ -123,6 +174,20 @@ pub enum OpenError {
     UnsupportedVersion(u32),
     #[error("not a fixed VHD")]
     NotFixed,
+    #[error("not a dynamic or differencing VHD")]
+    NotDynamic,
+    #[error("unsupported VHD disk type: {0}")]
+    UnsupportedDiskType(u32),
+    #[error("VHD dynamic header is missing")]
+    InvalidDynamicHeaderCookie,
+    #[error("invalid VHD dynamic header checksum")]
+    InvalidDynamicHeaderChecksum,
+    #[error("invalid VHD block size: {0:#x}")]
+    InvalidBlockSize(u32),
+    #[error("invalid VHD block allocation table")]
+    InvalidBat,
+    #[error("invalid VHD parent locator")]
+    InvalidParentLocator,
 }
 
 impl Vhd1Disk {
@@ -167,6 +232,12 @@ pub fn open_fixed(mut file: File, read_only: bool) -> Result<Self, OpenError> {
         })
     }
 
+    /// Returns information about the VHD1 image in `file`, of any type,
+    /// including the parent locator of a differencing image.
+    pub fn info(file: &File) -> Result<Vhd1Info, OpenError> {
+        dynamic::read_info(file)
+    }
+
     /// Drops the parsing state, returning the file handle.
     pub fn into_inner(self) -> File {
         self.file.into_inner()
@@ -0,0 +1,279 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! VHDX on-disk format definitions.
+//!
+//! See the Microsoft VHDX Format Specification, version 1.0. All fields are
+//! little endian.
+
+use guid::Guid;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+pub const KB: u64 = 1024;
+pub const MB: u64 = 1024 * KB;
+
+/// The alignment of regions, payload blocks, and sector bitmap blocks.
+pub const REGION_ALIGNMENT: u64 = MB;
+
+pub const FILE_IDENTIFIER_OFFSET: u64 = 0;
+pub const HEADER_OFFSETS: [u64; 2] = [64 * KB, 128 * KB];
+pub const REGION_TABLE_OFFSETS: [u64; 2] = [192 * KB, 256 * KB];
+pub const REGION_TABLE_SIZE: usize = (64 * KB) as usize;
+pub const METADATA_TABLE_SIZE: usize = (64 * KB) as usize;
+
+/// The end of the fixed-location structures at the start of the file.
+pub const HEADER_SECTION_SIZE: u64 = MB;
+
+/// `vhdxfile`
+pub const FILE_IDENTIFIER_SIGNATURE: u64 = u64::from_le_bytes(*b"vhdxfile");
+/// `head`
+pub const HEADER_SIGNATURE: u32 = u32::from_le_bytes(*b"head");
+/// `regi`
+pub const REGION_TABLE_SIGNATURE: u32 = u32::from_le_bytes(*b"regi");
+/// `metadata`
+pub const METADATA_TABLE_SIGNATURE: u64 = u64::from_le_bytes(*b"metadata");
+/// `loge`
+pub const LOG_ENTRY_SIGNATURE: u32 = u32::from_le_bytes(*b"loge");
+/// `zero`
+pub const LOG_ZERO_SIGNATURE: u32 = u32::from_le_bytes(*b"zero");
+/// `desc`
+pub const LOG_DATA_DESCRIPTOR_SIGNATURE: u32 = u32::from_le_bytes(*b"desc");
+/// `data`
+pub const LOG_DATA_SECTOR_SIGNATURE: u32 = u32::from_le_bytes(*b"data");
+
+pub const VERSION: u16 = 1;
+pub const LOG_VERSION: u16 = 0;
+
+pub const LOG_SECTOR_SIZE: u64 = 4 * KB;
+
+pub const MAX_REGION_ENTRIES: u32 = 2047;
+pub const MAX_METADATA_ENTRIES: u16 = 2047;
+
+pub const MIN_BLOCK_SIZE: u32 = MB as u32;
+pub const MAX_BLOCK_SIZE: u32 = 256 * MB as u32;
+pub const DEFAULT_BLOCK_SIZE: u32 = 32 * MB as u32;
+pub const MAX_DISK_SIZE: u64 = 64 * 1024 * 1024 * MB;
+
+/// The number of sectors described by a single sector bitmap block.
+pub const SECTORS_PER_BITMAP_BLOCK: u64 = 1 << 23;
+
+pub const REGION_BAT: Guid = guid::guid!("2dc27766-f623-4200-9d64-115e9bfd4a08");
+pub const REGION_METADATA: Guid = guid::guid!("8b7ca206-4790-4b9a-b8fe-575f050f886e");
+
+pub const METADATA_FILE_PARAMETERS: Guid = guid::guid!("caa16737-fa36-4d43-b3b6-33f0aa44e76b");
+pub const METADATA_VIRTUAL_DISK_SIZE: Guid = guid::guid!("2fa54224-cd1b-4876-b211-5dbed83bf4b8");
+pub const METADATA_PAGE_83_DATA: Guid = guid::guid!("beca12ab-b2e6-4523-93ef-c309e000c746");
+pub const METADATA_LOGICAL_SECTOR_SIZE: Guid = guid::guid!("8141bf1d-a96f-4709-ba47-f233a8faab5f");
+pub const METADATA_PHYSICAL_SECTOR_SIZE: Guid = guid::guid!("cda348c7-445d-4471-9cc9-e9885251c556");
+pub const METADATA_PARENT_LOCATOR: Guid = guid::guid!("a8d35f2b-b30b-454d-abf7-d3d84834ab0c");
+
+pub const PARENT_LOCATOR_TYPE_VHDX: Guid = guid::guid!("b04aefb7-d19e-4a81-b789-25b8e9445913");
+
+pub const FILE_PARAMETERS_HAS_PARENT: u32 = 0x2;
+
+pub const METADATA_FLAG_IS_VIRTUAL_DISK: u32 = 0x2;
+pub const METADATA_FLAG_IS_REQUIRED: u32 = 0x4;
+
+pub const REGION_REQUIRED: u32 = 0x1;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct FileIdentifier {
+    pub signature: u64,
+    pub creator: [u16; 256],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct Header {
+    pub signature: u32,
+    pub checksum: u32,
+    pub sequence_number: u64,
+    pub file_write_guid: Guid,
+    pub data_write_guid: Guid,
+    pub log_guid: Guid,
+    pub log_version: u16,
+    pub version: u16,
+    pub log_length: u32,
+    pub log_offset: u64,
+    pub reserved: [u8; 4016],
+}
+
+const _: () = assert!(size_of::<Header>() == 4096);
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct RegionTableHeader {
+    pub signature: u32,
+    pub checksum: u32,
+    pub entry_count: u32,
+    pub reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct RegionTableEntry {
+    pub guid: Guid,
+    pub file_offset: u64,
+    pub length: u32,
+    pub required: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct MetadataTableHeader {
+    pub signature: u64,
+    pub reserved: u16,
+    pub entry_count: u16,
+    pub reserved2: [u32; 5],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct MetadataTableEntry {
+    pub item_id: Guid,
+    /// The offset of the item relative to the start of the metadata region.
+    pub offset: u32,
+    pub length: u32,
+    pub flags: u32,
+    pub reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct FileParameters {
+    pub block_size: u32,
+    pub flags: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct ParentLocatorHeader {
+    pub locator_type: Guid,
+    pub reserved: u16,
+    pub key_value_count: u16,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct ParentLocatorEntry {
+    /// The offset of the UTF-16 key, relative to the start of the parent
+    /// locator item.
+    pub key_offset: u32,
+    /// The offset of the UTF-16 value, relative to the start of the parent
+    /// locator item.
+    pub value_offset: u32,
+    pub key_length: u16,
+    pub value_length: u16,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct LogEntryHeader {
+    pub signature: u32,
+    pub checksum: u32,
+    pub entry_length: u32,
+    pub tail: u32,
+    pub sequence_number: u64,
+    pub descriptor_count: u32,
+    pub reserved: u32,
+    pub log_guid: Guid,
+    pub flushed_file_offset: u64,
+    pub last_file_offset: u64,
+}
+
+/// A zero or data descriptor. For zero descriptors, `leading_bytes` holds the
+/// length to zero and `trailing_bytes` is reserved.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct LogDescriptor {
+    pub signature: u32,
+    pub trailing_bytes: [u8; 4],
+    pub leading_bytes: [u8; 8],
+    pub file_offset: u64,
+    pub sequence_number: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct LogDataSector {
+    pub signature: u32,
+    pub sequence_high: u32,
+    pub data: [u8; 4084],
+    pub sequence_low: u32,
+}
+
+const _: () = assert!(size_of::<LogDataSector>() == LOG_SECTOR_SIZE as usize);
+
+pub const BAT_STATE_MASK: u64 = 0x7;
+pub const BAT_FILE_OFFSET_SHIFT: u32 = 20;
+
+pub const PAYLOAD_BLOCK_NOT_PRESENT: u64 = 0;
+pub const PAYLOAD_BLOCK_UNDEFINED: u64 = 1;
+pub const PAYLOAD_BLOCK_ZERO: u64 = 2;
+pub const PAYLOAD_BLOCK_UNMAPPED: u64 = 3;
+pub const PAYLOAD_BLOCK_FULLY_PRESENT: u64 = 6;
+pub const PAYLOAD_BLOCK_PARTIALLY_PRESENT: u64 = 7;
+
+pub const SB_BLOCK_PRESENT: u64 = 6;
+
+/// Builds a BAT entry from a state and a MB-aligned file offset.
+pub fn bat_entry(state: u64, file_offset: u64) -> u64 {
+    debug_assert_eq!(file_offset % MB, 0);
+    state | file_offset
+}
+
+/// Returns the file offset of a BAT entry.
+pub fn bat_file_offset(entry: u64) -> u64 {
+    (entry >> BAT_FILE_OFFSET_SHIFT) << BAT_FILE_OFFSET_SHIFT
+}
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82f63b78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32c_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &b| {
+        CRC32C_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Computes the CRC-32C (Castagnoli) checksum of a structure whose 4-byte
+/// checksum field is at byte offset 4, treating that field as zero.
+pub fn checksum(data: &[u8]) -> u32 {
+    let crc = crc32c_update(!0, &data[..4]);
+    let crc = crc32c_update(crc, &[0; 4]);
+    !crc32c_update(crc, &data[8..])
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn checksum() {
+        // Check values from RFC 3720.
+        assert_eq!(super::checksum(&[0; 32]), 0x8a9136aa);
+        let mut data = [0xff; 32];
+        assert_eq!(super::crc32c_update(!0, &data), !0x62a8ab43);
+        data[4..8].fill(0);
+        assert_ne!(super::checksum(&data), 0x62a8ab43);
+    }
+}
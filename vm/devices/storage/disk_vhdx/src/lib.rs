@@ -0,0 +1,1601 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A VHDX disk layer implementation, [`VhdxDiskLayer`].
+//!
+//! This is a pure Rust implementation of the VHDX format, for use on hosts
+//! where the Windows kernel-mode VHD parser (`disk_vhdmp`) is not available.
+//!
+//! VHDX images are exposed as a [`disk_layered`] layer. A differencing image
+//! tracks which sectors it contains in its sector bitmaps, so sectors that
+//! are not present fall through to the next layer: the parent disk is just
+//! the next layer down in a [`LayeredDisk`](disk_layered::LayeredDisk). The
+//! [`resolver`] builds that stack from a
+//! [`VhdxDiskHandle`](disk_backend_resources::VhdxDiskHandle). Sectors of a
+//! dynamic (non-differencing) image are always present, reading as zero if
+//! they have not been written.
+//!
+//! # Supported features
+//!
+//! - Dynamic and differencing images, with any block size and 512-byte or
+//!   4096-byte logical sectors.
+//! - Replaying the metadata log of an image that was not closed cleanly.
+//!   This requires opening the image for write.
+//! - Writing, with new payload and sector bitmap blocks allocated at the end
+//!   of the file.
+//! - Unmapping whole blocks.
+//!
+//! Updates to the BAT and sector bitmaps are written through the log, which
+//! is emptied when the layer is dropped. The space of unmapped blocks is not
+//! reused.
+
+#![forbid(unsafe_code)]
+
+mod format;
+mod log;
+pub mod resolver;
+
+use disk_backend::DiskError;
+use disk_backend::UnmapBehavior;
+use disk_image_layer::ImageFile;
+use disk_image_layer::read_at;
+use disk_image_layer::write_at;
+use disk_layered::LayerIo;
+use disk_layered::SectorMarker;
+use guid::Guid;
+use inspect::Inspect;
+use log::Transaction;
+use parking_lot::Mutex;
+use scsi_buffers::RequestBuffers;
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+use thiserror::Error;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+use zerocopy::LE;
+use zerocopy::U64;
+
+/// An error encountered while opening or creating a VHDX image.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum OpenError {
+    /// An IO error occurred.
+    #[error("io error")]
+    Io(#[from] io::Error),
+    /// The file is not a VHDX image.
+    #[error("not a vhdx image")]
+    InvalidFileIdentifier,
+    /// Neither header is valid.
+    #[error("no valid vhdx header")]
+    NoValidHeader,
+    /// The image version is not supported.
+    #[error("unsupported vhdx version: {0}")]
+    UnsupportedVersion(u16),
+    /// Neither region table is valid.
+    #[error("no valid region table")]
+    NoValidRegionTable,
+    /// A region has an invalid location.
+    #[error("invalid region {0}")]
+    InvalidRegion(Guid),
+    /// A required region is not known to this implementation.
+    #[error("unknown required region {0}")]
+    UnknownRequiredRegion(Guid),
+    /// A required region is missing.
+    #[error("missing {0} region")]
+    MissingRegion(&'static str),
+    /// The metadata table is invalid.
+    #[error("invalid metadata table")]
+    InvalidMetadataTable,
+    /// A metadata item is invalid.
+    #[error("invalid metadata item {0}")]
+    InvalidMetadataItem(Guid),
+    /// A required metadata item is not known to this implementation.
+    #[error("unknown required metadata item {0}")]
+    UnknownRequiredMetadata(Guid),
+    /// A required metadata item is missing.
+    #[error("missing {0} metadata")]
+    MissingMetadata(&'static str),
+    /// The block size is invalid.
+    #[error("invalid block size: {0:#x}")]
+    InvalidBlockSize(u32),
+    /// The logical or physical sector size is invalid.
+    #[error("invalid sector size: {0}")]
+    InvalidSectorSize(u32),
+    /// The disk size is invalid.
+    #[error("invalid disk size: {0:#x}")]
+    InvalidDiskSize(u64),
+    /// The BAT region is too small for the disk.
+    #[error("BAT region is too small")]
+    InvalidBat,
+    /// The parent locator is invalid.
+    #[error("invalid parent locator")]
+    InvalidParentLocator,
+    /// The image's log must be replayed, but the image was opened read-only.
+    #[error("image was not closed cleanly and must be opened for write to replay its log")]
+    LogReplayRequired,
+    /// The image's log is invalid.
+    #[error("invalid log: {0}")]
+    InvalidLog(&'static str),
+}
+
+/// The location of the parent of a differencing image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParentLocator {
+    /// The data write GUID of the parent when this image was created.
+    pub parent_linkage: Guid,
+    /// An alternate data write GUID for the parent.
+    pub parent_linkage2: Option<Guid>,
+    /// The path to the parent, relative to the directory containing this
+    /// image. This uses Windows path separators.
+    pub relative_path: Option<String>,
+    /// The volume-relative path to the parent, as a Windows volume GUID path.
+    pub volume_path: Option<String>,
+    /// The absolute Windows path to the parent.
+    pub absolute_win32_path: Option<String>,
+}
+
+/// Information about a VHDX image, from [`VhdxDiskLayer::info`].
+#[derive(Debug, Clone)]
+pub struct VhdxInfo {
+    /// The virtual size of the disk, in bytes.
+    pub disk_size: u64,
+    /// The size of each payload block, in bytes.
+    pub block_size: u32,
+    /// The logical sector size.
+    pub logical_sector_size: u32,
+    /// The physical sector size.
+    pub physical_sector_size: u32,
+    /// The SCSI page 83 identifier of the disk.
+    pub page_83_data: Guid,
+    /// The GUID that changes whenever the disk's contents change. Children
+    /// record this in [`ParentLocator::parent_linkage`].
+    pub data_write_guid: Guid,
+    /// The parent locator, for differencing images.
+    pub parent: Option<ParentLocator>,
+}
+
+/// Parameters for creating a new VHDX image with [`VhdxDiskLayer::create`].
+#[derive(Debug, Clone)]
+pub struct CreateParams<'a> {
+    /// The virtual size of the disk, in bytes. Must be a multiple of the
+    /// logical sector size.
+    pub size: u64,
+    /// The block size. If `None`, uses 32MiB blocks.
+    pub block_size: Option<u32>,
+    /// The logical sector size. If `None`, uses 512 bytes.
+    pub logical_sector_size: Option<u32>,
+    /// The physical sector size. If `None`, uses 4096 bytes.
+    pub physical_sector_size: Option<u32>,
+    /// The parent of a differencing image.
+    pub parent: Option<&'a ParentLocator>,
+}
+
+/// A disk layer backed by a VHDX image file.
+#[derive(Inspect)]
+pub struct VhdxDiskLayer {
+    #[inspect(flatten)]
+    inner: Arc<Inner>,
+}
+
+#[derive(Inspect)]
+struct Inner {
+    #[inspect(skip)]
+    file: File,
+    #[inspect(flatten)]
+    meta: Meta,
+    #[inspect(skip)]
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Inspect)]
+struct Meta {
+    #[inspect(hex)]
+    disk_size: u64,
+    #[inspect(hex)]
+    block_size: u32,
+    logical_sector_size: u32,
+    physical_sector_size: u32,
+    #[inspect(display)]
+    page_83_data: Guid,
+    chunk_ratio: u64,
+    #[inspect(hex)]
+    bat_offset: u64,
+    has_parent: bool,
+    read_only: bool,
+}
+
+struct State {
+    header: ActiveHeader,
+    bat: Vec<u64>,
+    /// The MB-aligned end of the file, where new blocks are allocated.
+    end_of_file: u64,
+    /// The log that metadata updates are written through, started by the
+    /// first modification since open.
+    log: Option<log::LogWriter>,
+}
+
+/// The current header and its slot in the file.
+#[derive(Copy, Clone)]
+struct ActiveHeader {
+    index: usize,
+    header: format::Header,
+}
+
+/// The state of a payload block.
+#[derive(Debug, Copy, Clone)]
+enum Block {
+    /// Not present in this image.
+    NotPresent,
+    /// Reads as zero.
+    Zero,
+    /// Every sector is present at the given file offset.
+    FullyPresent { host: u64 },
+    /// The sectors marked in the sector bitmap at `bitmap` are present at the
+    /// given file offset.
+    PartiallyPresent { host: u64, bitmap: u64 },
+}
+
+impl VhdxDiskLayer {
+    /// Opens a VHDX image.
+    ///
+    /// If the image was not closed cleanly, its log is replayed. This fails
+    /// with [`OpenError::LogReplayRequired`] if `read_only` is set.
+    pub fn open(file: File, read_only: bool) -> Result<Self, OpenError> {
+        if !read_only {
+            Self::replay_log(&file)?;
+        }
+        let header = ActiveHeader::read(&file)?;
+        if !header.header.log_guid.is_zero() {
+            return Err(OpenError::LogReplayRequired);
+        }
+
+        let layout = Layout::read(&file, &header.header)?;
+        let VhdxInfo {
+            disk_size,
+            block_size,
+            logical_sector_size,
+            physical_sector_size,
+            page_83_data,
+            ..
+        } = layout.info;
+
+        let has_parent = layout.info.parent.is_some();
+        let bat_entries = bat_entry_count(disk_size, block_size, logical_sector_size, has_parent);
+        if bat_entries * 8 > layout.bat_length {
+            return Err(OpenError::InvalidBat);
+        }
+        let mut bat = vec![U64::<LE>::ZERO; bat_entries as usize];
+        read_at(&file, bat.as_mut_bytes(), layout.bat_offset)?;
+        let bat = bat.into_iter().map(|entry| entry.get()).collect();
+
+        let end_of_file = file
+            .metadata()?
+            .len()
+            .next_multiple_of(format::REGION_ALIGNMENT);
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                file,
+                meta: Meta {
+                    disk_size,
+                    block_size,
+                    logical_sector_size,
+                    physical_sector_size,
+                    page_83_data,
+                    chunk_ratio: chunk_ratio(block_size, logical_sector_size),
+                    bat_offset: layout.bat_offset,
+                    has_parent,
+                    read_only,
+                },
+                state: Mutex::new(State {
+                    header,
+                    bat,
+                    end_of_file,
+                    log: None,
+                }),
+            }),
+        })
+    }
+
+    /// Returns whether `file` starts with the VHDX file identifier.
+    pub fn probe(file: &File) -> io::Result<bool> {
+        let mut signature = [0; 8];
+        read_at(file, &mut signature, format::FILE_IDENTIFIER_OFFSET)?;
+        Ok(u64::from_le_bytes(signature) == format::FILE_IDENTIFIER_SIGNATURE)
+    }
+
+    /// Replays the log of the image in `file` if it was not closed cleanly.
+    /// Does nothing if the log is empty.
+    pub fn replay_log(file: &File) -> Result<(), OpenError> {
+        let mut header = ActiveHeader::read(file)?;
+        if !header.header.log_guid.is_zero() {
+            log::replay(file, &header.header)?;
+            header.update(file, |h| h.log_guid = Guid::ZERO)?;
+        }
+        Ok(())
+    }
+
+    /// Returns information about the image in `file`, including its parent
+    /// locator.
+    ///
+    /// This fails with [`OpenError::LogReplayRequired`] if the image was not
+    /// closed cleanly.
+    pub fn info(file: &File) -> Result<VhdxInfo, OpenError> {
+        let header = ActiveHeader::read(file)?;
+        if !header.header.log_guid.is_zero() {
+            return Err(OpenError::LogReplayRequired);
+        }
+        Ok(Layout::read(file, &header.header)?.info)
+    }
+
+    /// Formats `file` as an empty dynamic or differencing VHDX image.
+    ///
+    /// Any existing contents of the file are discarded.
+    pub fn create(file: &File, params: &CreateParams<'_>) -> Result<(), OpenError> {
+        let block_size = params.block_size.unwrap_or(format::DEFAULT_BLOCK_SIZE);
+        let logical_sector_size = params.logical_sector_size.unwrap_or(512);
+        let physical_sector_size = params.physical_sector_size.unwrap_or(4096);
+        validate_geometry(
+            params.size,
+            block_size,
+            logical_sector_size,
+            physical_sector_size,
+        )?;
+
+        let has_parent = params.parent.is_some();
+        let bat_entries = bat_entry_count(params.size, block_size, logical_sector_size, has_parent);
+
+        // Layout: header section, log, metadata region, BAT region.
+        let log_offset = format::HEADER_SECTION_SIZE;
+        let log_length = format::MB;
+        let metadata_offset = log_offset + log_length;
+        let metadata_length = format::MB;
+        let bat_offset = metadata_offset + metadata_length;
+        let bat_length = (bat_entries * 8).next_multiple_of(format::REGION_ALIGNMENT);
+
+        let mut identifier = format::FileIdentifier::new_zeroed();
+        identifier.signature = format::FILE_IDENTIFIER_SIGNATURE;
+        for (dst, src) in identifier.creator.iter_mut().zip("OpenVMM".encode_utf16()) {
+            *dst = src;
+        }
+
+        let mut header = format::Header::new_zeroed();
+        header.signature = format::HEADER_SIGNATURE;
+        header.file_write_guid = Guid::new_random();
+        header.data_write_guid = Guid::new_random();
+        header.log_version = format::LOG_VERSION;
+        header.version = format::VERSION;
+        header.log_length = log_length as u32;
+        header.log_offset = log_offset;
+
+        let mut region_table = vec![0; format::REGION_TABLE_SIZE];
+        let region_header = format::RegionTableHeader {
+            signature: format::REGION_TABLE_SIGNATURE,
+            checksum: 0,
+            entry_count: 2,
+            reserved: 0,
+        };
+        let regions = [
+            format::RegionTableEntry {
+                guid: format::REGION_BAT,
+                file_offset: bat_offset,
+                length: bat_length as u32,
+                required: format::REGION_REQUIRED,
+            },
+            format::RegionTableEntry {
+                guid: format::REGION_METADATA,
+                file_offset: metadata_offset,
+                length: metadata_length as u32,
+                required: format::REGION_REQUIRED,
+            },
+        ];
+        let mut offset = 0;
+        for bytes in std::iter::once(region_header.as_bytes())
+            .chain(regions.iter().map(|region| region.as_bytes()))
+        {
+            region_table[offset..][..bytes.len()].copy_from_slice(bytes);
+            offset += bytes.len();
+        }
+        let checksum = format::checksum(&region_table);
+        region_table[4..8].copy_from_slice(&checksum.to_le_bytes());
+
+        let mut items = vec![
+            (
+                format::METADATA_FILE_PARAMETERS,
+                format::METADATA_FLAG_IS_REQUIRED,
+                format::FileParameters {
+                    block_size,
+                    flags: if has_parent {
+                        format::FILE_PARAMETERS_HAS_PARENT
+                    } else {
+                        0
+                    },
+                }
+                .as_bytes()
+                .to_vec(),
+            ),
+            (
+                format::METADATA_VIRTUAL_DISK_SIZE,
+                format::METADATA_FLAG_IS_VIRTUAL_DISK | format::METADATA_FLAG_IS_REQUIRED,
+                params.size.to_le_bytes().to_vec(),
+            ),
+            (
+                format::METADATA_PAGE_83_DATA,
+                format::METADATA_FLAG_IS_VIRTUAL_DISK | format::METADATA_FLAG_IS_REQUIRED,
+                Guid::new_random().as_bytes().to_vec(),
+            ),
+            (
+                format::METADATA_LOGICAL_SECTOR_SIZE,
+                format::METADATA_FLAG_IS_VIRTUAL_DISK | format::METADATA_FLAG_IS_REQUIRED,
+                logical_sector_size.to_le_bytes().to_vec(),
+            ),
+            (
+                format::METADATA_PHYSICAL_SECTOR_SIZE,
+                format::METADATA_FLAG_IS_VIRTUAL_DISK | format::METADATA_FLAG_IS_REQUIRED,
+                physical_sector_size.to_le_bytes().to_vec(),
+            ),
+        ];
+        if let Some(parent) = params.parent {
+            items.push((
+                format::METADATA_PARENT_LOCATOR,
+                format::METADATA_FLAG_IS_REQUIRED,
+                build_parent_locator(parent),
+            ));
+        }
+        let metadata = build_metadata(metadata_length as usize, &items)?;
+
+        file.set_len(0)?;
+        file.set_len(bat_offset + bat_length)?;
+        write_at(file, identifier.as_bytes(), format::FILE_IDENTIFIER_OFFSET)?;
+        for (sequence_number, &offset) in format::HEADER_OFFSETS.iter().enumerate() {
+            header.sequence_number = sequence_number as u64;
+            header.checksum = 0;
+            header.checksum = format::checksum(header.as_bytes());
+            write_at(file, header.as_bytes(), offset)?;
+        }
+        for offset in format::REGION_TABLE_OFFSETS {
+            write_at(file, &region_table, offset)?;
+        }
+        write_at(file, &metadata, metadata_offset)?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+impl ActiveHeader {
+    /// Validates the file identifier and returns the current header.
+    fn read(file: &File) -> Result<Self, OpenError> {
+        let mut identifier = format::FileIdentifier::new_zeroed();
+        read_at(
+            file,
+            identifier.as_mut_bytes(),
+            format::FILE_IDENTIFIER_OFFSET,
+        )?;
+        if identifier.signature != format::FILE_IDENTIFIER_SIGNATURE {
+            return Err(OpenError::InvalidFileIdentifier);
+        }
+
+        let mut current: Option<Self> = None;
+        for (index, &offset) in format::HEADER_OFFSETS.iter().enumerate() {
+            let mut header = format::Header::new_zeroed();
+            read_at(file, header.as_mut_bytes(), offset)?;
+            if header.signature != format::HEADER_SIGNATURE
+                || format::checksum(header.as_bytes()) != header.checksum
+            {
+                continue;
+            }
+            if current
+                .as_ref()
+                .is_none_or(|c| header.sequence_number > c.header.sequence_number)
+            {
+                current = Some(Self { index, header });
+            }
+        }
+        let current = current.ok_or(OpenError::NoValidHeader)?;
+        if current.header.version != format::VERSION {
+            return Err(OpenError::UnsupportedVersion(current.header.version));
+        }
+        Ok(current)
+    }
+
+    /// Updates the header by writing a new version to the inactive slot.
+    fn update(&mut self, file: &File, f: impl FnOnce(&mut format::Header)) -> io::Result<()> {
+        let mut header = self.header;
+        f(&mut header);
+        header.sequence_number += 1;
+        header.checksum = 0;
+        header.checksum = format::checksum(header.as_bytes());
+        let index = 1 - self.index;
+        write_at(file, header.as_bytes(), format::HEADER_OFFSETS[index])?;
+        file.sync_data()?;
+        *self = Self { index, header };
+        Ok(())
+    }
+}
+
+/// The region and metadata layout of an image.
+struct Layout {
+    bat_offset: u64,
+    bat_length: u64,
+    info: VhdxInfo,
+}
+
+impl Layout {
+    fn read(file: &File, header: &format::Header) -> Result<Self, OpenError> {
+        let mut bat = None;
+        let mut metadata = None;
+        let entries = read_region_table(file)?;
+        for entry in entries {
+            if entry.file_offset % format::REGION_ALIGNMENT != 0
+                || (entry.length as u64) % format::REGION_ALIGNMENT != 0
+                || entry.file_offset < format::HEADER_SECTION_SIZE
+            {
+                return Err(OpenError::InvalidRegion(entry.guid));
+            }
+            let region = (entry.file_offset, entry.length as u64);
+            match entry.guid {
+                format::REGION_BAT => bat = Some(region),
+                format::REGION_METADATA => metadata = Some(region),
+                guid if entry.required & format::REGION_REQUIRED != 0 => {
+                    return Err(OpenError::UnknownRequiredRegion(guid));
+                }
+                _ => {}
+            }
+        }
+        let (bat_offset, bat_length) = bat.ok_or(OpenError::MissingRegion("BAT"))?;
+        let (metadata_offset, metadata_length) =
+            metadata.ok_or(OpenError::MissingRegion("metadata"))?;
+
+        let info = read_metadata(file, metadata_offset, metadata_length, header)?;
+        Ok(Self {
+            bat_offset,
+            bat_length,
+            info,
+        })
+    }
+}
+
+/// Reads the first valid region table.
+fn read_region_table(file: &File) -> Result<Vec<format::RegionTableEntry>, OpenError> {
+    let mut table = vec![0; format::REGION_TABLE_SIZE];
+    for offset in format::REGION_TABLE_OFFSETS {
+        read_at(file, &mut table, offset)?;
+        let (header, entries) = format::RegionTableHeader::read_from_prefix(&table).unwrap();
+        if header.signature != format::REGION_TABLE_SIGNATURE
+            || format::checksum(&table) != header.checksum
+            || header.entry_count > format::MAX_REGION_ENTRIES
+        {
+            continue;
+        }
+        return Ok(entries
+            .chunks_exact(size_of::<format::RegionTableEntry>())
+            .take(header.entry_count as usize)
+            .map(|entry| format::RegionTableEntry::read_from_bytes(entry).unwrap())
+            .collect());
+    }
+    Err(OpenError::NoValidRegionTable)
+}
+
+fn read_metadata(
+    file: &File,
+    region_offset: u64,
+    region_length: u64,
+    header: &format::Header,
+) -> Result<VhdxInfo, OpenError> {
+    let mut table = vec![0; format::METADATA_TABLE_SIZE];
+    read_at(file, &mut table, region_offset)?;
+    let (table_header, entries) = format::MetadataTableHeader::read_from_prefix(&table).unwrap();
+    if table_header.signature != format::METADATA_TABLE_SIGNATURE
+        || table_header.entry_count > format::MAX_METADATA_ENTRIES
+    {
+        return Err(OpenError::InvalidMetadataTable);
+    }
+
+    let mut file_parameters = None;
+    let mut disk_size = None;
+    let mut page_83_data = None;
+    let mut logical_sector_size = None;
+    let mut physical_sector_size = None;
+    let mut parent = None;
+    for entry in entries
+        .chunks_exact(size_of::<format::MetadataTableEntry>())
+        .take(table_header.entry_count as usize)
+    {
+        let entry = format::MetadataTableEntry::read_from_bytes(entry).unwrap();
+        let invalid = || OpenError::InvalidMetadataItem(entry.item_id);
+        let end = entry.offset as u64 + entry.length as u64;
+        if end > region_length
+            || (entry.length != 0 && (entry.offset as usize) < format::METADATA_TABLE_SIZE)
+        {
+            return Err(invalid());
+        }
+        let read_item = || -> Result<Vec<u8>, OpenError> {
+            let mut data = vec![0; entry.length as usize];
+            read_at(file, &mut data, region_offset + entry.offset as u64)?;
+            Ok(data)
+        };
+        match entry.item_id {
+            format::METADATA_FILE_PARAMETERS => {
+                file_parameters = Some(
+                    format::FileParameters::read_from_bytes(&read_item()?)
+                        .map_err(|_| invalid())?,
+                );
+            }
+            format::METADATA_VIRTUAL_DISK_SIZE => {
+                disk_size = Some(u64::read_from_bytes(&read_item()?).map_err(|_| invalid())?);
+            }
+            format::METADATA_PAGE_83_DATA => {
+                page_83_data = Some(Guid::read_from_bytes(&read_item()?).map_err(|_| invalid())?);
+            }
+            format::METADATA_LOGICAL_SECTOR_SIZE => {
+                logical_sector_size =
+                    Some(u32::read_from_bytes(&read_item()?).map_err(|_| invalid())?);
+            }
+            format::METADATA_PHYSICAL_SECTOR_SIZE => {
+                physical_sector_size =
+                    Some(u32::read_from_bytes(&read_item()?).map_err(|_| invalid())?);
+            }
+            format::METADATA_PARENT_LOCATOR => {
+                parent = Some(parse_parent_locator(&read_item()?)?);
+            }
+            guid if entry.flags & format::METADATA_FLAG_IS_REQUIRED != 0 => {
+                return Err(OpenError::UnknownRequiredMetadata(guid));
+            }
+            _ => {}
+        }
+    }
+
+    let file_parameters = file_parameters.ok_or(OpenError::MissingMetadata("file parameters"))?;
+    let disk_size = disk_size.ok_or(OpenError::MissingMetadata("virtual disk size"))?;
+    let page_83_data = page_83_data.ok_or(OpenError::MissingMetadata("page 83 data"))?;
+    let logical_sector_size =
+        logical_sector_size.ok_or(OpenError::MissingMetadata("logical sector size"))?;
+    let physical_sector_size =
+        physical_sector_size.ok_or(OpenError::MissingMetadata("physical sector size"))?;
+    let parent = if file_parameters.flags & format::FILE_PARAMETERS_HAS_PARENT != 0 {
+        Some(parent.ok_or(OpenError::MissingMetadata("parent locator"))?)
+    } else {
+        None
+    };
+
+    validate_geometry(
+        disk_size,
+        file_parameters.block_size,
+        logical_sector_size,
+        physical_sector_size,
+    )?;
+
+    Ok(VhdxInfo {
+        disk_size,
+        block_size: file_parameters.block_size,
+        logical_sector_size,
+        physical_sector_size,
+        page_83_data,
+        data_write_guid: header.data_write_guid,
+        parent,
+    })
+}
+
+fn validate_geometry(
+    disk_size: u64,
+    block_size: u32,
+    logical_sector_size: u32,
+    physical_sector_size: u32,
+) -> Result<(), OpenError> {
+    if !block_size.is_power_of_two()
+        || !(format::MIN_BLOCK_SIZE..=format::MAX_BLOCK_SIZE).contains(&block_size)
+    {
+        return Err(OpenError::InvalidBlockSize(block_size));
+    }
+    for sector_size in [logical_sector_size, physical_sector_size] {
+        if sector_size != 512 && sector_size != 4096 {
+            return Err(OpenError::InvalidSectorSize(sector_size));
+        }
+    }
+    if disk_size == 0
+        || disk_size > format::MAX_DISK_SIZE
+        || disk_size % logical_sector_size as u64 != 0
+    {
+        return Err(OpenError::InvalidDiskSize(disk_size));
+    }
+    Ok(())
+}
+
+/// Returns the number of payload blocks described by each sector bitmap
+/// block.
+fn chunk_ratio(block_size: u32, logical_sector_size: u32) -> u64 {
+    format::SECTORS_PER_BITMAP_BLOCK * logical_sector_size as u64 / block_size as u64
+}
+
+fn bat_entry_count(
+    disk_size: u64,
+    block_size: u32,
+    logical_sector_size: u32,
+    has_parent: bool,
+) -> u64 {
+    let chunk_ratio = chunk_ratio(block_size, logical_sector_size);
+    let data_blocks = disk_size.div_ceil(block_size as u64);
+    if has_parent {
+        data_blocks.div_ceil(chunk_ratio) * (chunk_ratio + 1)
+    } else {
+        data_blocks + (data_blocks - 1) / chunk_ratio
+    }
+}
+
+fn parse_parent_locator(data: &[u8]) -> Result<ParentLocator, OpenError> {
+    let (header, _) = format::ParentLocatorHeader::read_from_prefix(data)
+        .map_err(|_| OpenError::InvalidParentLocator)?;
+    if header.locator_type != format::PARENT_LOCATOR_TYPE_VHDX {
+        return Err(OpenError::InvalidParentLocator);
+    }
+
+    let string = |offset: u32, len: u16| {
+        let bytes = data.get(offset as usize..)?.get(..len as usize)?;
+        if len % 2 != 0 {
+            return None;
+        }
+        let chars = bytes
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect::<Vec<_>>();
+        String::from_utf16(&chars).ok()
+    };
+
+    let mut parent_linkage = None;
+    let mut locator = ParentLocator {
+        parent_linkage: Guid::ZERO,
+        parent_linkage2: None,
+        relative_path: None,
+        volume_path: None,
+        absolute_win32_path: None,
+    };
+    let mut offset = size_of_val(&header);
+    for _ in 0..header.key_value_count {
+        let (entry, _) = data
+            .get(offset..)
+            .and_then(|d| format::ParentLocatorEntry::read_from_prefix(d).ok())
+            .ok_or(OpenError::InvalidParentLocator)?;
+        offset += size_of_val(&entry);
+        let key =
+            string(entry.key_offset, entry.key_length).ok_or(OpenError::InvalidParentLocator)?;
+        let value = string(entry.value_offset, entry.value_length)
+            .ok_or(OpenError::InvalidParentLocator)?;
+        let parse_guid = |value: &str| {
+            value
+                .parse::<Guid>()
+                .map_err(|_| OpenError::InvalidParentLocator)
+        };
+        match key.as_str() {
+            "parent_linkage" => parent_linkage = Some(parse_guid(&value)?),
+            "parent_linkage2" => locator.parent_linkage2 = Some(parse_guid(&value)?),
+            "relative_path" => locator.relative_path = Some(value),
+            "volume_path" => locator.volume_path = Some(value),
+            "absolute_win32_path" => locator.absolute_win32_path = Some(value),
+            _ => {}
+        }
+    }
+    locator.parent_linkage = parent_linkage.ok_or(OpenError::InvalidParentLocator)?;
+    Ok(locator)
+}
+
+fn build_parent_locator(parent: &ParentLocator) -> Vec<u8> {
+    let linkage = |guid: &Guid| format!("{{{guid}}}");
+    let pairs = [
+        ("parent_linkage", Some(linkage(&parent.parent_linkage))),
+        (
+            "parent_linkage2",
+            parent.parent_linkage2.as_ref().map(linkage),
+        ),
+        ("relative_path", parent.relative_path.clone()),
+        ("volume_path", parent.volume_path.clone()),
+        ("absolute_win32_path", parent.absolute_win32_path.clone()),
+    ];
+    let pairs = pairs
+        .iter()
+        .filter_map(|(key, value)| Some((*key, value.as_deref()?)))
+        .collect::<Vec<_>>();
+
+    let header = format::ParentLocatorHeader {
+        locator_type: format::PARENT_LOCATOR_TYPE_VHDX,
+        reserved: 0,
+        key_value_count: pairs.len() as u16,
+    };
+    let mut entries = Vec::new();
+    let mut strings = Vec::new();
+    let strings_offset =
+        size_of_val(&header) + pairs.len() * size_of::<format::ParentLocatorEntry>();
+    let mut push_string = |s: &str| {
+        let offset = strings_offset + strings.len();
+        strings.extend(s.encode_utf16().flat_map(u16::to_le_bytes));
+        (
+            offset as u32,
+            (strings.len() + strings_offset - offset) as u16,
+        )
+    };
+    for (key, value) in pairs {
+        let (key_offset, key_length) = push_string(key);
+        let (value_offset, value_length) = push_string(value);
+        entries.push(format::ParentLocatorEntry {
+            key_offset,
+            value_offset,
+            key_length,
+            value_length,
+        });
+    }
+
+    let mut data = header.as_bytes().to_vec();
+    data.extend(entries.iter().flat_map(|e| e.as_bytes().iter().copied()));
+    data.extend(strings);
+    data
+}
+
+/// Builds the metadata region from `(item id, flags, data)` tuples.
+fn build_metadata(len: usize, items: &[(Guid, u32, Vec<u8>)]) -> Result<Vec<u8>, OpenError> {
+    let mut region = vec![0; len];
+    let header = format::MetadataTableHeader {
+        signature: format::METADATA_TABLE_SIGNATURE,
+        reserved: 0,
+        entry_count: items.len() as u16,
+        reserved2: [0; 5],
+    };
+    region[..size_of_val(&header)].copy_from_slice(header.as_bytes());
+    let mut entry_offset = size_of_val(&header);
+    let mut data_offset = format::METADATA_TABLE_SIZE;
+    for (item_id, flags, data) in items {
+        if data_offset + data.len() > len {
+            return Err(OpenError::InvalidMetadataItem(*item_id));
+        }
+        let entry = format::MetadataTableEntry {
+            item_id: *item_id,
+            offset: data_offset as u32,
+            length: data.len() as u32,
+            flags: *flags,
+            reserved: 0,
+        };
+        region[entry_offset..][..size_of_val(&entry)].copy_from_slice(entry.as_bytes());
+        entry_offset += size_of_val(&entry);
+        region[data_offset..][..data.len()].copy_from_slice(data);
+        data_offset += data.len();
+    }
+    Ok(region)
+}
+
+impl Inner {
+    fn block_size(&self) -> u64 {
+        self.meta.block_size as u64
+    }
+
+    fn sectors_per_block(&self) -> u64 {
+        self.block_size() / self.meta.logical_sector_size as u64
+    }
+
+    fn payload_index(&self, block: u64) -> usize {
+        (block + block / self.meta.chunk_ratio) as usize
+    }
+
+    fn bitmap_index(&self, block: u64) -> usize {
+        let chunk = block / self.meta.chunk_ratio;
+        (chunk * (self.meta.chunk_ratio + 1) + self.meta.chunk_ratio) as usize
+    }
+
+    fn block(&self, state: &State, block: u64) -> io::Result<Block> {
+        let entry = state.bat[self.payload_index(block)];
+        let host = format::bat_file_offset(entry);
+        let allocated = || {
+            if host < format::HEADER_SECTION_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid payload block offset",
+                ));
+            }
+            Ok(host)
+        };
+        let block = match entry & format::BAT_STATE_MASK {
+            format::PAYLOAD_BLOCK_NOT_PRESENT | format::PAYLOAD_BLOCK_UNDEFINED => {
+                Block::NotPresent
+            }
+            format::PAYLOAD_BLOCK_ZERO | format::PAYLOAD_BLOCK_UNMAPPED => Block::Zero,
+            format::PAYLOAD_BLOCK_FULLY_PRESENT => Block::FullyPresent { host: allocated()? },
+            format::PAYLOAD_BLOCK_PARTIALLY_PRESENT if self.meta.has_parent => {
+                let bitmap = state.bat[self.bitmap_index(block)];
+                if bitmap & format::BAT_STATE_MASK != format::SB_BLOCK_PRESENT {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "missing sector bitmap block",
+                    ));
+                }
+                Block::PartiallyPresent {
+                    host: allocated()?,
+                    bitmap: format::bat_file_offset(bitmap),
+                }
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid BAT entry state",
+                ));
+            }
+        };
+        Ok(block)
+    }
+
+    /// Returns the range of bits in the sector bitmap block that cover `len`
+    /// bytes at `in_block` in `block`.
+    fn bitmap_bits(&self, block: u64, in_block: u64, len: usize) -> Range<u64> {
+        let first = (block % self.meta.chunk_ratio) * self.sectors_per_block()
+            + in_block / self.meta.logical_sector_size as u64;
+        first..first + len as u64 / self.meta.logical_sector_size as u64
+    }
+
+    /// Reads the sector bitmap bits for `len` bytes at `in_block` in `block`.
+    fn read_bitmap(
+        &self,
+        bitmap: u64,
+        block: u64,
+        in_block: u64,
+        len: usize,
+    ) -> io::Result<Vec<bool>> {
+        let bits = self.bitmap_bits(block, in_block, len);
+        let first_byte = bits.start / 8;
+        let mut bytes = vec![0; (bits.end.div_ceil(8) - first_byte) as usize];
+        read_at(&self.file, &mut bytes, bitmap + first_byte)?;
+        Ok(bits
+            .map(|bit| bytes[(bit / 8 - first_byte) as usize] & (1 << (bit % 8)) != 0)
+            .collect())
+    }
+
+    /// Sets or clears the sector bitmap bits for `len` bytes at `in_block` in
+    /// `block`, as part of `transaction`.
+    fn write_bitmap(
+        &self,
+        transaction: &mut Transaction,
+        bitmap: u64,
+        block: u64,
+        in_block: u64,
+        len: usize,
+        value: bool,
+    ) -> io::Result<()> {
+        let bits = self.bitmap_bits(block, in_block, len);
+        let first_byte = bits.start / 8;
+        let mut bytes = vec![0; (bits.end.div_ceil(8) - first_byte) as usize];
+        transaction.read(&self.file, bitmap + first_byte, &mut bytes)?;
+        let old = bytes.clone();
+        for bit in bits {
+            let byte = &mut bytes[(bit / 8 - first_byte) as usize];
+            if value {
+                *byte |= 1 << (bit % 8);
+            } else {
+                *byte &= !(1 << (bit % 8));
+            }
+        }
+        if bytes == old {
+            return Ok(());
+        }
+        transaction.write(&self.file, bitmap + first_byte, &bytes)
+    }
+
+    /// Prepares for the first modification since open: changes the file and
+    /// data write GUIDs, as required by the format, and starts a new log.
+    fn begin_write(&self, state: &mut State) -> io::Result<()> {
+        if state.log.is_none() {
+            let log_guid = Guid::new_random();
+            state.header.update(&self.file, |header| {
+                header.file_write_guid = Guid::new_random();
+                header.data_write_guid = Guid::new_random();
+                header.log_guid = log_guid;
+            })?;
+            state.log = Some(log::LogWriter::new(&state.header.header, log_guid));
+        }
+        Ok(())
+    }
+
+    /// Writes the metadata updates in `transaction` through the log.
+    fn commit(&self, state: &mut State, transaction: Transaction) -> io::Result<()> {
+        if transaction.is_empty() {
+            return Ok(());
+        }
+        self.begin_write(state)?;
+        let file_size = state.end_of_file;
+        state
+            .log
+            .as_mut()
+            .unwrap()
+            .commit(&self.file, transaction, file_size)
+    }
+
+    /// Allocates `len` bytes at the end of the file.
+    fn allocate(&self, state: &mut State, len: u64) -> io::Result<u64> {
+        let host = state.end_of_file;
+        state.end_of_file += len;
+        self.file.set_len(state.end_of_file)?;
+        Ok(host)
+    }
+
+    /// Returns the offset of the sector bitmap block covering `block`,
+    /// allocating it if necessary.
+    fn bitmap_block(
+        &self,
+        state: &mut State,
+        transaction: &mut Transaction,
+        block: u64,
+    ) -> io::Result<u64> {
+        let index = self.bitmap_index(block);
+        let entry = state.bat[index];
+        if entry & format::BAT_STATE_MASK == format::SB_BLOCK_PRESENT {
+            return Ok(format::bat_file_offset(entry));
+        }
+        let bitmap = self.allocate(state, format::MB)?;
+        self.set_bat_entry(
+            state,
+            transaction,
+            index,
+            format::bat_entry(format::SB_BLOCK_PRESENT, bitmap),
+        )?;
+        Ok(bitmap)
+    }
+
+    fn set_bat_entry(
+        &self,
+        state: &mut State,
+        transaction: &mut Transaction,
+        index: usize,
+        entry: u64,
+    ) -> io::Result<()> {
+        transaction.write(
+            &self.file,
+            self.meta.bat_offset + index as u64 * 8,
+            &entry.to_le_bytes(),
+        )?;
+        state.bat[index] = entry;
+        Ok(())
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // Mark the log as empty, so that the image can be opened read-only
+        // again. If this fails, the log is just replayed on the next open.
+        let state = self.state.get_mut();
+        if state.log.is_some() {
+            let _ = self.file.sync_data().and_then(|()| {
+                state
+                    .header
+                    .update(&self.file, |header| header.log_guid = Guid::ZERO)
+            });
+        }
+    }
+}
+
+impl ImageFile for Inner {
+    fn file(&self) -> &File {
+        &self.file
+    }
+
+    fn disk_size(&self) -> u64 {
+        self.meta.disk_size
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.meta.logical_sector_size
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.meta.read_only
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> io::Result<Vec<Range<u64>>> {
+        let block_size = self.block_size();
+        let sector_size = self.meta.logical_sector_size as u64;
+        let mut present = Vec::<Range<u64>>::new();
+        let mut push = |range: Range<u64>| match present.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => present.push(range),
+        };
+        let mut pos = 0;
+        while pos < buf.len() {
+            let guest = offset + pos as u64;
+            let block_index = guest / block_size;
+            let in_block = guest % block_size;
+            let len = ((block_size - in_block) as usize).min(buf.len() - pos);
+            let chunk = &mut buf[pos..pos + len];
+            let (block, bitmap) = {
+                let state = self.state.lock();
+                let block = self.block(&state, block_index)?;
+                // Read the bitmap under the lock so that it is consistent with
+                // the BAT.
+                let bitmap = match block {
+                    Block::PartiallyPresent { bitmap, .. } => {
+                        self.read_bitmap(bitmap, block_index, in_block, len)?
+                    }
+                    _ => Vec::new(),
+                };
+                (block, bitmap)
+            };
+            match block {
+                Block::NotPresent if self.meta.has_parent => {}
+                Block::NotPresent | Block::Zero => {
+                    chunk.fill(0);
+                    push(guest..guest + len as u64);
+                }
+                Block::FullyPresent { host } => {
+                    read_at(&self.file, chunk, host + in_block)?;
+                    push(guest..guest + len as u64);
+                }
+                Block::PartiallyPresent { host, .. } => {
+                    read_at(&self.file, chunk, host + in_block)?;
+                    for (i, &is_present) in bitmap.iter().enumerate() {
+                        if is_present {
+                            let start = guest + i as u64 * sector_size;
+                            push(start..start + sector_size);
+                        }
+                    }
+                }
+            }
+            pos += len;
+        }
+        Ok(present)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let block_size = self.block_size();
+        let mut in_place = Vec::new();
+        {
+            let mut state = self.state.lock();
+            self.begin_write(&mut state)?;
+            let mut transaction = Transaction::default();
+            let mut pos = 0;
+            while pos < data.len() {
+                let guest = offset + pos as u64;
+                let block_index = guest / block_size;
+                let in_block = guest % block_size;
+                let len = ((block_size - in_block) as usize).min(data.len() - pos);
+                let chunk = &data[pos..pos + len];
+                match self.block(&state, block_index)? {
+                    Block::FullyPresent { host } => {
+                        in_place.push((host + in_block, pos..pos + len))
+                    }
+                    Block::PartiallyPresent { host, bitmap } => {
+                        // Write the data before marking it present.
+                        write_at(&self.file, chunk, host + in_block)?;
+                        self.write_bitmap(
+                            &mut transaction,
+                            bitmap,
+                            block_index,
+                            in_block,
+                            len,
+                            true,
+                        )?;
+                    }
+                    block => {
+                        // The new block is zeroed by extending the file.
+                        let host = self.allocate(&mut state, block_size)?;
+                        write_at(&self.file, chunk, host + in_block)?;
+                        let partial = self.meta.has_parent
+                            && matches!(block, Block::NotPresent)
+                            && len as u64 != block_size;
+                        let new_state = if partial {
+                            let bitmap =
+                                self.bitmap_block(&mut state, &mut transaction, block_index)?;
+                            // Clear any bits left over from a previous
+                            // allocation of this block.
+                            self.write_bitmap(
+                                &mut transaction,
+                                bitmap,
+                                block_index,
+                                0,
+                                block_size as usize,
+                                false,
+                            )?;
+                            self.write_bitmap(
+                                &mut transaction,
+                                bitmap,
+                                block_index,
+                                in_block,
+                                len,
+                                true,
+                            )?;
+                            format::PAYLOAD_BLOCK_PARTIALLY_PRESENT
+                        } else {
+                            format::PAYLOAD_BLOCK_FULLY_PRESENT
+                        };
+                        self.set_bat_entry(
+                            &mut state,
+                            &mut transaction,
+                            self.payload_index(block_index),
+                            format::bat_entry(new_state, host),
+                        )?;
+                    }
+                }
+                pos += len;
+            }
+            // This flushes the data written above before the metadata that
+            // refers to it.
+            self.commit(&mut state, transaction)?;
+        }
+
+        for (host, range) in in_place {
+            write_at(&self.file, &data[range], host)?;
+        }
+        Ok(())
+    }
+
+    /// Unmaps the whole blocks in the guest byte range.
+    fn unmap(&self, range: Range<u64>, next_is_zero: bool) -> io::Result<()> {
+        let block_size = self.block_size();
+        let new_state = if !self.meta.has_parent || next_is_zero {
+            format::PAYLOAD_BLOCK_NOT_PRESENT
+        } else {
+            format::PAYLOAD_BLOCK_ZERO
+        };
+        let mut state = self.state.lock();
+        let mut transaction = Transaction::default();
+        let mut block = range.start.div_ceil(block_size);
+        while (block + 1) * block_size <= range.end {
+            let index = self.payload_index(block);
+            if state.bat[index] != new_state {
+                // FUTURE: keep the block allocated in the unmapped state so
+                // that its space can be reused.
+                self.set_bat_entry(&mut state, &mut transaction, index, new_state)?;
+            }
+            block += 1;
+        }
+        self.commit(&mut state, transaction)
+    }
+}
+
+impl LayerIo for VhdxDiskLayer {
+    fn layer_type(&self) -> &str {
+        "vhdx"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.inner.meta.disk_size / self.inner.meta.logical_sector_size as u64
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.inner.meta.logical_sector_size
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        Some(self.inner.meta.page_83_data.as_bytes().try_into().unwrap())
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        self.inner.meta.physical_sector_size
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        true
+    }
+
+    fn is_logically_read_only(&self) -> bool {
+        self.inner.meta.read_only
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        disk_image_layer::sync_cache(&self.inner).await
+    }
+
+    async fn read(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        marker: SectorMarker<'_>,
+    ) -> Result<(), DiskError> {
+        disk_image_layer::read(&self.inner, buffers, sector, marker).await
+    }
+
+    async fn write(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        disk_image_layer::write(&self.inner, buffers, sector, fua).await
+    }
+
+    async fn unmap(
+        &self,
+        sector: u64,
+        count: u64,
+        _block_level_only: bool,
+        next_is_zero: bool,
+    ) -> Result<(), DiskError> {
+        disk_image_layer::unmap(&self.inner, sector, count, next_is_zero).await
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        UnmapBehavior::Unspecified
+    }
+
+    fn optimal_unmap_sectors(&self) -> u32 {
+        (self.inner.block_size() / self.inner.meta.logical_sector_size as u64) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ActiveHeader;
+    use super::CreateParams;
+    use super::OpenError;
+    use super::ParentLocator;
+    use super::VhdxDiskLayer;
+    use super::format;
+    use disk_backend::Disk;
+    use disk_image_layer::read_at;
+    use disk_image_layer::test_helpers::layered;
+    use disk_image_layer::test_helpers::pattern;
+    use disk_image_layer::test_helpers::read;
+    use disk_image_layer::test_helpers::write;
+    use disk_image_layer::write_at;
+    use disk_layered::DiskLayer;
+    use guestmem::GuestMemory;
+    use guid::Guid;
+    use pal_async::async_test;
+    use zerocopy::IntoBytes;
+
+    const SIZE: u64 = 16 << 20;
+    const BLOCK_SIZE: u32 = 1 << 20;
+
+    fn create(parent: Option<&ParentLocator>) -> std::fs::File {
+        let file = tempfile::tempfile().unwrap();
+        VhdxDiskLayer::create(
+            &file,
+            &CreateParams {
+                size: SIZE,
+                block_size: Some(BLOCK_SIZE),
+                logical_sector_size: None,
+                physical_sector_size: None,
+                parent,
+            },
+        )
+        .unwrap();
+        file
+    }
+
+    #[async_test]
+    async fn read_write() {
+        let file = create(None);
+        let info = VhdxDiskLayer::info(&file).unwrap();
+        assert_eq!(info.disk_size, SIZE);
+        assert_eq!(info.block_size, BLOCK_SIZE);
+        assert!(info.parent.is_none());
+
+        let disk = layered(
+            false,
+            vec![DiskLayer::new(
+                VhdxDiskLayer::open(file.try_clone().unwrap(), false).unwrap(),
+            )],
+        )
+        .await;
+        let mem = GuestMemory::allocate(0x20000);
+
+        assert!(read(&disk, &mem, 0, 4096).await.iter().all(|&b| b == 0));
+
+        // A write spanning a block boundary.
+        let data = pattern(0x3000, 1);
+        let sector = (BLOCK_SIZE as u64 / 512) - 8;
+        write(&disk, &mem, sector, &data).await;
+        assert_eq!(read(&disk, &mem, sector, data.len()).await, data);
+        assert!(
+            read(&disk, &mem, sector - 16, 16 * 512)
+                .await
+                .iter()
+                .all(|&b| b == 0)
+        );
+        drop(disk);
+
+        // The data write GUID changes on the first write.
+        assert_ne!(
+            VhdxDiskLayer::info(&file).unwrap().data_write_guid,
+            info.data_write_guid
+        );
+
+        // Reopen and make sure the data persisted.
+        let disk = layered(
+            true,
+            vec![DiskLayer::new(VhdxDiskLayer::open(file, true).unwrap())],
+        )
+        .await;
+        assert_eq!(read(&disk, &mem, sector, data.len()).await, data);
+    }
+
+    #[async_test]
+    async fn differencing() {
+        let parent_file = create(None);
+        let mem = GuestMemory::allocate(0x20000);
+        let parent_data = pattern(0x10000, 2);
+        {
+            let parent = layered(
+                false,
+                vec![DiskLayer::new(
+                    VhdxDiskLayer::open(parent_file.try_clone().unwrap(), false).unwrap(),
+                )],
+            )
+            .await;
+            write(&parent, &mem, 0, &parent_data).await;
+        }
+
+        let locator = ParentLocator {
+            parent_linkage: VhdxDiskLayer::info(&parent_file).unwrap().data_write_guid,
+            parent_linkage2: None,
+            relative_path: Some(r".\parent.vhdx".into()),
+            volume_path: None,
+            absolute_win32_path: Some(r"C:\disks\parent.vhdx".into()),
+        };
+        let child_file = create(Some(&locator));
+        assert_eq!(
+            VhdxDiskLayer::info(&child_file).unwrap().parent.as_ref(),
+            Some(&locator)
+        );
+
+        let parent = Disk::new(
+            layered(
+                true,
+                vec![DiskLayer::new(
+                    VhdxDiskLayer::open(parent_file, true).unwrap(),
+                )],
+            )
+            .await,
+        )
+        .unwrap();
+        let disk = layered(
+            false,
+            vec![
+                DiskLayer::new(
+                    VhdxDiskLayer::open(child_file.try_clone().unwrap(), false).unwrap(),
+                ),
+                DiskLayer::from_disk(parent.clone()),
+            ],
+        )
+        .await;
+
+        // Partially overwrite a block; the rest must still come from the
+        // parent.
+        let data = [0xcc; 1024];
+        write(&disk, &mem, 10, &data).await;
+        let mut expected = parent_data.clone();
+        expected[10 * 512..][..data.len()].copy_from_slice(&data);
+        assert_eq!(read(&disk, &mem, 0, expected.len()).await, expected);
+        assert_eq!(read(&parent, &mem, 0, parent_data.len()).await, parent_data);
+        drop(disk);
+
+        // Reopen the child and check that the sector bitmap persisted.
+        let disk = layered(
+            true,
+            vec![
+                DiskLayer::new(VhdxDiskLayer::open(child_file, true).unwrap()),
+                DiskLayer::from_disk(parent),
+            ],
+        )
+        .await;
+        assert_eq!(read(&disk, &mem, 0, expected.len()).await, expected);
+    }
+
+    #[async_test]
+    async fn log_replay() {
+        let file = create(None);
+        let file_len = file.metadata().unwrap().len();
+
+        // Place block data at the end of the file, and write a log entry that
+        // updates the first BAT sector to point to it.
+        let block_data = pattern(BLOCK_SIZE as usize, 3);
+        write_at(&file, &block_data, file_len).unwrap();
+
+        let bat_offset = 3 * format::MB;
+        let mut bat_sector = vec![0u8; format::LOG_SECTOR_SIZE as usize];
+        bat_sector[..8].copy_from_slice(
+            &format::bat_entry(format::PAYLOAD_BLOCK_FULLY_PRESENT, file_len).to_le_bytes(),
+        );
+
+        let log_guid = Guid::new_random();
+        let sequence_number = 1;
+        let header = format::LogEntryHeader {
+            signature: format::LOG_ENTRY_SIGNATURE,
+            checksum: 0,
+            entry_length: 2 * format::LOG_SECTOR_SIZE as u32,
+            tail: 0,
+            sequence_number,
+            descriptor_count: 1,
+            reserved: 0,
+            log_guid,
+            flushed_file_offset: file_len,
+            last_file_offset: file_len + BLOCK_SIZE as u64,
+        };
+        let descriptor = format::LogDescriptor {
+            signature: format::LOG_DATA_DESCRIPTOR_SIGNATURE,
+            trailing_bytes: bat_sector[4092..].try_into().unwrap(),
+            leading_bytes: bat_sector[..8].try_into().unwrap(),
+            file_offset: bat_offset,
+            sequence_number,
+        };
+        let data_sector = format::LogDataSector {
+            signature: format::LOG_DATA_SECTOR_SIGNATURE,
+            sequence_high: 0,
+            data: bat_sector[8..4092].try_into().unwrap(),
+            sequence_low: sequence_number as u32,
+        };
+        let mut entry = vec![0u8; 2 * format::LOG_SECTOR_SIZE as usize];
+        entry[..size_of_val(&header)].copy_from_slice(header.as_bytes());
+        entry[size_of_val(&header)..][..size_of_val(&descriptor)]
+            .copy_from_slice(descriptor.as_bytes());
+        entry[format::LOG_SECTOR_SIZE as usize..].copy_from_slice(data_sector.as_bytes());
+        let checksum = format::checksum(&entry);
+        entry[4..8].copy_from_slice(&checksum.to_le_bytes());
+
+        let mut active = ActiveHeader::read(&file).unwrap();
+        write_at(&file, &entry, active.header.log_offset).unwrap();
+        active
+            .update(&file, |header| header.log_guid = log_guid)
+            .unwrap();
+
+        assert!(matches!(
+            VhdxDiskLayer::open(file.try_clone().unwrap(), true),
+            Err(OpenError::LogReplayRequired)
+        ));
+
+        let disk = layered(
+            false,
+            vec![DiskLayer::new(
+                VhdxDiskLayer::open(file.try_clone().unwrap(), false).unwrap(),
+            )],
+        )
+        .await;
+        let mem = GuestMemory::allocate(0x20000);
+        assert_eq!(read(&disk, &mem, 0, 0x10000).await, block_data[..0x10000]);
+        drop(disk);
+
+        // The log has been cleared.
+        assert!(ActiveHeader::read(&file).unwrap().header.log_guid.is_zero());
+    }
+
+    #[async_test]
+    async fn metadata_through_log() {
+        let file = create(None);
+        let disk = layered(
+            false,
+            vec![DiskLayer::new(
+                VhdxDiskLayer::open(file.try_clone().unwrap(), false).unwrap(),
+            )],
+        )
+        .await;
+        let mem = GuestMemory::allocate(0x10000);
+        let data = pattern(0x3000, 4);
+        write(&disk, &mem, 8, &data).await;
+
+        // While the image is open, its metadata updates are in the log.
+        assert!(matches!(
+            VhdxDiskLayer::info(&file),
+            Err(OpenError::LogReplayRequired)
+        ));
+
+        // Simulate losing the in-place BAT update by copying the image and
+        // clearing the BAT of the copy.
+        let crashed = tempfile::tempfile().unwrap();
+        let mut image = vec![0; file.metadata().unwrap().len() as usize];
+        read_at(&file, &mut image, 0).unwrap();
+        write_at(&crashed, &image, 0).unwrap();
+        write_at(
+            &crashed,
+            &[0; format::LOG_SECTOR_SIZE as usize],
+            3 * format::MB,
+        )
+        .unwrap();
+
+        // The log is emptied when the layer is dropped.
+        drop(disk);
+        assert!(ActiveHeader::read(&file).unwrap().header.log_guid.is_zero());
+
+        // Replaying the log of the copy restores the BAT.
+        VhdxDiskLayer::replay_log(&crashed).unwrap();
+        let disk = layered(
+            true,
+            vec![DiskLayer::new(VhdxDiskLayer::open(crashed, true).unwrap())],
+        )
+        .await;
+        assert_eq!(read(&disk, &mem, 8, data.len()).await, data);
+    }
+}
@@ -0,0 +1,435 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The VHDX metadata log.
+//!
+//! The log is a circular buffer of entries, each describing a set of sector
+//! writes and zeroing operations to metadata. After an unclean shutdown the
+//! active sequence of entries must be replayed before any metadata can be
+//! trusted, and metadata must only be updated by writing it to the log
+//! first.
+
+use crate::OpenError;
+use crate::format;
+use disk_image_layer::read_at;
+use disk_image_layer::write_at;
+use guid::Guid;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::btree_map;
+use std::fs::File;
+use std::io;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+struct Log<'a> {
+    file: &'a File,
+    offset: u64,
+    length: u64,
+    guid: Guid,
+}
+
+struct Entry {
+    header: format::LogEntryHeader,
+    /// The full contents of the entry.
+    data: Vec<u8>,
+}
+
+/// Replays the active sequence of the log described by `header` into the
+/// file.
+///
+/// The caller is responsible for clearing the log GUID in the header
+/// afterwards.
+pub(crate) fn replay(file: &File, header: &format::Header) -> Result<(), OpenError> {
+    let log = Log {
+        file,
+        offset: header.log_offset,
+        length: header.log_length.into(),
+        guid: header.log_guid,
+    };
+    if log.length == 0
+        || log.length % format::REGION_ALIGNMENT != 0
+        || log.offset % format::REGION_ALIGNMENT != 0
+        || log.offset < format::HEADER_SECTION_SIZE
+    {
+        return Err(OpenError::InvalidLog("invalid log location"));
+    }
+
+    let mut entries = HashMap::new();
+    for offset in (0..log.length).step_by(format::LOG_SECTOR_SIZE as usize) {
+        if let Some(entry) = log.read_entry(offset)? {
+            entries.insert(offset, entry);
+        }
+    }
+
+    let Some(sequence) = log.find_active_sequence(&entries) else {
+        // The log GUID was written but no entries were. There is nothing to
+        // replay.
+        return Ok(());
+    };
+
+    let head = &entries[sequence.last().unwrap()].header;
+    if file.metadata()?.len() < head.flushed_file_offset {
+        return Err(OpenError::InvalidLog(
+            "file is shorter than the flushed offset",
+        ));
+    }
+
+    for offset in &sequence {
+        replay_entry(file, &entries[offset])?;
+    }
+    if file.metadata()?.len() < head.last_file_offset {
+        file.set_len(head.last_file_offset)?;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+impl Log<'_> {
+    /// Reads `buf.len()` bytes at `offset` within the log, wrapping around
+    /// the end of the log.
+    fn read(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        let first = (buf.len() as u64).min(self.length - offset) as usize;
+        let (a, b) = buf.split_at_mut(first);
+        read_at(self.file, a, self.offset + offset)?;
+        read_at(self.file, b, self.offset)
+    }
+
+    /// Reads and validates the entry at `offset` within the log.
+    fn read_entry(&self, offset: u64) -> std::io::Result<Option<Entry>> {
+        let mut header = format::LogEntryHeader::new_zeroed();
+        self.read(offset, header.as_mut_bytes())?;
+        let entry_length = header.entry_length as u64;
+        if header.signature != format::LOG_ENTRY_SIGNATURE
+            || header.log_guid != self.guid
+            || entry_length == 0
+            || entry_length % format::LOG_SECTOR_SIZE != 0
+            || entry_length > self.length
+            || header.tail as u64 % format::LOG_SECTOR_SIZE != 0
+            || header.tail as u64 >= self.length
+        {
+            return Ok(None);
+        }
+
+        let mut data = vec![0; entry_length as usize];
+        self.read(offset, &mut data)?;
+        if format::checksum(&data) != header.checksum {
+            return Ok(None);
+        }
+
+        let entry = Entry { header, data };
+        if entry.descriptors().is_none() {
+            return Ok(None);
+        }
+        Ok(Some(entry))
+    }
+
+    /// Finds the active sequence: the valid run of consecutive entries, ending
+    /// in the entry with the highest sequence number, whose final entry's tail
+    /// points to the run's first entry.
+    ///
+    /// Returns the log offsets of the entries in the sequence, in order.
+    fn find_active_sequence(&self, entries: &HashMap<u64, Entry>) -> Option<Vec<u64>> {
+        let mut best: Option<(u64, Vec<u64>)> = None;
+        for &start in entries.keys() {
+            let mut sequence = vec![start];
+            let mut current = start;
+            loop {
+                let entry = &entries[&current];
+                let sequence_number = entry.header.sequence_number;
+                if entry.header.tail as u64 == start
+                    && best.as_ref().is_none_or(|(n, _)| sequence_number > *n)
+                {
+                    best = Some((sequence_number, sequence.clone()));
+                }
+                let next = (current + entry.header.entry_length as u64) % self.length;
+                match entries.get(&next) {
+                    Some(next_entry)
+                        if next_entry.header.sequence_number == sequence_number + 1
+                            && sequence.len() < entries.len() =>
+                    {
+                        sequence.push(next);
+                        current = next;
+                    }
+                    _ => break,
+                }
+            }
+        }
+        best.map(|(_, sequence)| sequence)
+    }
+}
+
+/// A parsed log descriptor.
+enum Descriptor {
+    Zero {
+        file_offset: u64,
+        length: u64,
+    },
+    Data {
+        file_offset: u64,
+        /// The index of the data sector within the entry.
+        sector: usize,
+        leading_bytes: [u8; 8],
+        trailing_bytes: [u8; 4],
+    },
+}
+
+impl Entry {
+    /// Parses and validates the entry's descriptors and data sectors.
+    fn descriptors(&self) -> Option<Vec<Descriptor>> {
+        let count = self.header.descriptor_count as usize;
+        let header_len = size_of::<format::LogEntryHeader>();
+        let descriptor_len = size_of::<format::LogDescriptor>();
+        let descriptor_sectors =
+            (header_len + count * descriptor_len).div_ceil(format::LOG_SECTOR_SIZE as usize);
+        let sequence_number = self.header.sequence_number;
+
+        let mut descriptors = Vec::with_capacity(count);
+        let mut data_sectors = 0;
+        for i in 0..count {
+            let offset = header_len + i * descriptor_len;
+            let (raw, _) =
+                format::LogDescriptor::read_from_prefix(self.data.get(offset..)?).ok()?;
+            if raw.sequence_number != sequence_number
+                || raw.file_offset % format::LOG_SECTOR_SIZE != 0
+            {
+                return None;
+            }
+            let descriptor = match raw.signature {
+                format::LOG_ZERO_SIGNATURE => {
+                    let length = u64::from_le_bytes(raw.leading_bytes);
+                    if length % format::LOG_SECTOR_SIZE != 0 {
+                        return None;
+                    }
+                    Descriptor::Zero {
+                        file_offset: raw.file_offset,
+                        length,
+                    }
+                }
+                format::LOG_DATA_DESCRIPTOR_SIGNATURE => {
+                    let sector = descriptor_sectors + data_sectors;
+                    data_sectors += 1;
+                    let offset = sector * format::LOG_SECTOR_SIZE as usize;
+                    let (data, _) =
+                        format::LogDataSector::read_from_prefix(self.data.get(offset..)?).ok()?;
+                    if data.signature != format::LOG_DATA_SECTOR_SIGNATURE
+                        || data.sequence_high != (sequence_number >> 32) as u32
+                        || data.sequence_low != sequence_number as u32
+                    {
+                        return None;
+                    }
+                    Descriptor::Data {
+                        file_offset: raw.file_offset,
+                        sector,
+                        leading_bytes: raw.leading_bytes,
+                        trailing_bytes: raw.trailing_bytes,
+                    }
+                }
+                _ => return None,
+            };
+            descriptors.push(descriptor);
+        }
+        Some(descriptors)
+    }
+}
+
+fn replay_entry(file: &File, entry: &Entry) -> Result<(), OpenError> {
+    // Validated when the entry was read.
+    let descriptors = entry.descriptors().unwrap();
+    for descriptor in descriptors {
+        match descriptor {
+            Descriptor::Zero {
+                file_offset,
+                length,
+            } => {
+                let zeroes = vec![0; length.min(format::MB) as usize];
+                let mut offset = 0;
+                while offset < length {
+                    let len = (length - offset).min(zeroes.len() as u64) as usize;
+                    write_at(file, &zeroes[..len], file_offset + offset)?;
+                    offset += len as u64;
+                }
+            }
+            Descriptor::Data {
+                file_offset,
+                sector,
+                leading_bytes,
+                trailing_bytes,
+            } => {
+                let data = &entry.data[sector * format::LOG_SECTOR_SIZE as usize..]
+                    [..format::LOG_SECTOR_SIZE as usize];
+                // The first 8 and last 4 bytes of the data sector hold the
+                // signature and sequence number; the original bytes are in
+                // the descriptor.
+                let mut contents = data.to_vec();
+                contents[..8].copy_from_slice(&leading_bytes);
+                contents[format::LOG_SECTOR_SIZE as usize - 4..].copy_from_slice(&trailing_bytes);
+                write_at(file, &contents, file_offset)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Metadata updates to be written through the log as a single entry.
+#[derive(Default)]
+pub(crate) struct Transaction {
+    /// The new contents of each updated log sector of the file, by file
+    /// offset.
+    sectors: BTreeMap<u64, Box<[u8]>>,
+}
+
+impl Transaction {
+    /// Returns whether the transaction has no updates.
+    pub fn is_empty(&self) -> bool {
+        self.sectors.is_empty()
+    }
+
+    /// Reads `buf.len()` bytes of the file at `offset`, including the updates
+    /// in the transaction.
+    pub fn read(&self, file: &File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let mut pos = 0;
+        while pos < buf.len() {
+            let current = offset + pos as u64;
+            let sector_offset = current - current % format::LOG_SECTOR_SIZE;
+            let in_sector = (current - sector_offset) as usize;
+            let len = (format::LOG_SECTOR_SIZE as usize - in_sector).min(buf.len() - pos);
+            let chunk = &mut buf[pos..pos + len];
+            match self.sectors.get(&sector_offset) {
+                Some(sector) => chunk.copy_from_slice(&sector[in_sector..][..len]),
+                None => read_at(file, chunk, current)?,
+            }
+            pos += len;
+        }
+        Ok(())
+    }
+
+    /// Adds a write of `data` at file offset `offset` to the transaction.
+    pub fn write(&mut self, file: &File, offset: u64, data: &[u8]) -> io::Result<()> {
+        let mut pos = 0;
+        while pos < data.len() {
+            let current = offset + pos as u64;
+            let sector_offset = current - current % format::LOG_SECTOR_SIZE;
+            let in_sector = (current - sector_offset) as usize;
+            let len = (format::LOG_SECTOR_SIZE as usize - in_sector).min(data.len() - pos);
+            let sector = match self.sectors.entry(sector_offset) {
+                btree_map::Entry::Occupied(entry) => entry.into_mut(),
+                btree_map::Entry::Vacant(entry) => {
+                    let mut sector = vec![0; format::LOG_SECTOR_SIZE as usize];
+                    read_at(file, &mut sector, sector_offset)?;
+                    entry.insert(sector.into())
+                }
+            };
+            sector[in_sector..][..len].copy_from_slice(&data[pos..pos + len]);
+            pos += len;
+        }
+        Ok(())
+    }
+}
+
+/// Writes metadata updates through the log.
+///
+/// Each transaction is written as a single entry that is the whole active
+/// sequence, and is then applied to the file. The entry is flushed before it
+/// is applied, and the application is flushed before the next entry is
+/// written, so replay only ever needs the most recent entry.
+pub(crate) struct LogWriter {
+    offset: u64,
+    length: u64,
+    guid: Guid,
+    /// The offset within the log of the next entry.
+    head: u64,
+    sequence_number: u64,
+}
+
+impl LogWriter {
+    /// Returns a writer for a new log, identified by `guid`, in the log region
+    /// described by `header`.
+    pub fn new(header: &format::Header, guid: Guid) -> Self {
+        Self {
+            offset: header.log_offset,
+            length: header.log_length.into(),
+            guid,
+            head: 0,
+            sequence_number: 1,
+        }
+    }
+
+    /// Writes `transaction` to the log and then to the file, whose current
+    /// size is `file_size`.
+    ///
+    /// Anything previously written to the file is flushed first, so the
+    /// updated metadata never refers to data that might be lost.
+    pub fn commit(
+        &mut self,
+        file: &File,
+        transaction: Transaction,
+        file_size: u64,
+    ) -> io::Result<()> {
+        let sector_size = format::LOG_SECTOR_SIZE as usize;
+        let header_len = size_of::<format::LogEntryHeader>();
+        let descriptor_len = size_of::<format::LogDescriptor>();
+        let count = transaction.sectors.len();
+        let descriptor_sectors = (header_len + count * descriptor_len).div_ceil(sector_size);
+        let entry_length = (descriptor_sectors + count) * sector_size;
+        if entry_length as u64 > self.length {
+            return Err(io::Error::other("metadata update does not fit in the log"));
+        }
+        // Entries are not split across the end of the log.
+        if self.head + entry_length as u64 > self.length {
+            self.head = 0;
+        }
+
+        let mut entry = vec![0; entry_length];
+        let header = format::LogEntryHeader {
+            signature: format::LOG_ENTRY_SIGNATURE,
+            checksum: 0,
+            entry_length: entry_length as u32,
+            tail: self.head as u32,
+            sequence_number: self.sequence_number,
+            descriptor_count: count as u32,
+            reserved: 0,
+            log_guid: self.guid,
+            flushed_file_offset: file_size,
+            last_file_offset: file_size,
+        };
+        entry[..header_len].copy_from_slice(header.as_bytes());
+        for (i, (&file_offset, data)) in transaction.sectors.iter().enumerate() {
+            // The data sector's signature and sequence number replace the
+            // first 8 and last 4 bytes of the data, which are kept in the
+            // descriptor instead.
+            let descriptor = format::LogDescriptor {
+                signature: format::LOG_DATA_DESCRIPTOR_SIGNATURE,
+                trailing_bytes: data[sector_size - 4..].try_into().unwrap(),
+                leading_bytes: data[..8].try_into().unwrap(),
+                file_offset,
+                sequence_number: self.sequence_number,
+            };
+            entry[header_len + i * descriptor_len..][..descriptor_len]
+                .copy_from_slice(descriptor.as_bytes());
+            let data_sector = format::LogDataSector {
+                signature: format::LOG_DATA_SECTOR_SIGNATURE,
+                sequence_high: (self.sequence_number >> 32) as u32,
+                data: data[8..sector_size - 4].try_into().unwrap(),
+                sequence_low: self.sequence_number as u32,
+            };
+            entry[(descriptor_sectors + i) * sector_size..][..sector_size]
+                .copy_from_slice(data_sector.as_bytes());
+        }
+        let checksum = format::checksum(&entry);
+        entry[4..8].copy_from_slice(&checksum.to_le_bytes());
+
+        // This entry's tail discards the previous entry, so the previous
+        // entry's updates must be durable first, along with any data.
+        file.sync_data()?;
+        write_at(file, &entry, self.offset + self.head)?;
+        file.sync_data()?;
+        for (&file_offset, data) in &transaction.sectors {
+            write_at(file, data, file_offset)?;
+        }
+        self.head = (self.head + entry_length as u64) % self.length;
+        self.sequence_number += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource resolver for VHDX disks.
+
+use crate::OpenError;
+use crate::VhdxDiskLayer;
+use async_trait::async_trait;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::VhdxDiskHandle;
+use disk_image_layer::resolver::ResolveImageDiskError;
+use disk_image_layer::resolver::resolve_image_disk;
+use disk_layered::DiskLayer;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::DiskHandleKind;
+
+declare_static_async_resolver! {
+    VhdxDiskResolver,
+    (DiskHandleKind, VhdxDiskHandle)
+}
+
+/// Resolver for [`VhdxDiskHandle`].
+pub struct VhdxDiskResolver;
+
+/// Error type for [`VhdxDiskResolver`].
+pub type ResolveVhdxDiskError = ResolveImageDiskError<OpenError>;
+
+#[async_trait]
+impl AsyncResolveResource<DiskHandleKind, VhdxDiskHandle> for VhdxDiskResolver {
+    type Output = ResolvedDisk;
+    type Error = ResolveVhdxDiskError;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: VhdxDiskHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let read_only = input.read_only;
+        resolve_image_disk(resolver, resource.parent, input, |_| {
+            Ok(DiskLayer::new(VhdxDiskLayer::open(
+                resource.file,
+                read_only,
+            )?))
+        })
+        .await
+    }
+}
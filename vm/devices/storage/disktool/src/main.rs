@@ -0,0 +1,425 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A tool to inspect, convert, and merge disk images and layered disks
+//! without booting a VM.
+//!
+//! Disks are opened as [`disk_layered::LayeredDisk`]s through the same
+//! resolvers that OpenVMM uses, so every image format and layer type that
+//! OpenVMM supports can be used here too.
+
+#![forbid(unsafe_code)]
+
+mod spec;
+
+use anyhow::Context;
+use clap::Parser;
+use clap::Subcommand;
+use disk_backend::DiskIo;
+use disk_layered::LayeredDisk;
+use guestmem::GuestMemory;
+use pal_async::DefaultPool;
+use scsi_buffers::OwnedRequestBuffers;
+use spec::CreateSpec;
+use spec::DiskSpec;
+use spec::open;
+use spec::open_layers;
+use std::ops::Range;
+use std::process::ExitCode;
+use vmcore::vm_task::SingleDriverBackend;
+use vmcore::vm_task::VmTaskDriverSource;
+
+// Register the resolvers for the disks and layers that can be opened.
+vm_resource::register_static_resolvers! {
+    disk_layered::resolver::LayeredDiskResolver,
+    disk_file::FileDiskResolver,
+    disk_qcow2::resolver::Qcow2DiskResolver,
+    disk_vhd1::Vhd1Resolver,
+    disk_vhd1::DynamicVhd1Resolver,
+    disk_vhdx::resolver::VhdxDiskResolver,
+    #[cfg(windows)]
+    disk_vhdmp::VhdmpDiskResolver,
+    #[cfg(feature = "disk_blob")]
+    disk_blob::resolver::BlobDiskResolver,
+    #[cfg(feature = "disklayer_sqlite")]
+    disklayer_sqlite::resolver::SqliteDiskLayerResolver,
+}
+
+/// The amount of data to transfer per IO.
+const CHUNK_SIZE: usize = 0x100000;
+
+/// Inspect, convert, and merge disk images and layered disks.
+///
+/// DISK arguments use the same syntax as OpenVMM's `--disk` option:
+/// `[file:]<path>` for an image file (`.vhd`, `.vhdx`, `.qcow2`, `.vmgs`, or
+/// raw), `sql:<path>` for a sqlite layer, `sqldiff:<path>:<DISK>` for a sqlite
+/// layer on top of another disk, and `blob:<flat|vhd1>:<url>` for a disk
+/// served over HTTP.
+#[derive(Parser)]
+#[command(name = "disktool")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show the geometry of a disk and its layers.
+    Info {
+        /// The disk.
+        disk: DiskSpec,
+    },
+    /// Copy the contents of a disk into a new image.
+    ///
+    /// Zero-filled ranges are not written, so the new image is sparse if its
+    /// format allows it.
+    Convert {
+        /// The disk to copy.
+        src: DiskSpec,
+        /// The image to create: `[file:]<path>`, with the format chosen by
+        /// extension, or `sql:<path>`.
+        dst: CreateSpec,
+    },
+    /// Compare the contents of two disks.
+    ///
+    /// Exits with status 1 if they differ.
+    Compare {
+        /// The first disk.
+        a: DiskSpec,
+        /// The second disk.
+        b: DiskSpec,
+    },
+    /// Merge the top layer of a layered disk down into the layer below it.
+    ///
+    /// The top layer is left unchanged, and can be discarded afterwards.
+    Commit {
+        /// The layered disk, such as `sqldiff:<path>:<DISK>`.
+        disk: DiskSpec,
+    },
+    /// Show the allocated sector ranges of each layer of a disk.
+    Map {
+        /// The disk.
+        disk: DiskSpec,
+        /// Only show this layer, counting from 0 at the top.
+        #[clap(long)]
+        layer: Option<usize>,
+    },
+}
+
+fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn")),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    let cli = Cli::parse();
+    match DefaultPool::run_with(async |driver| {
+        let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver));
+        run(&driver_source, cli.command).await
+    }) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {err:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(driver_source: &VmTaskDriverSource, command: Command) -> anyhow::Result<ExitCode> {
+    match command {
+        Command::Info { disk } => {
+            let (disk, names) = open(driver_source, &disk, &[]).await?;
+            info(&disk, &names);
+        }
+        Command::Convert { src, dst } => {
+            let (src, _) = open(driver_source, &src, &[]).await?;
+            let size = src.sector_count() * src.sector_size() as u64;
+            let (dst, _) = open_layers(driver_source, vec![dst.create(size)?], &[0]).await?;
+            convert(&src, &dst).await?;
+        }
+        Command::Compare { a, b } => {
+            let (a, _) = open(driver_source, &a, &[]).await?;
+            let (b, _) = open(driver_source, &b, &[]).await?;
+            if let Some(difference) = compare(&a, &b).await? {
+                println!("{difference}");
+                return Ok(ExitCode::from(1));
+            }
+            println!("disks are identical");
+        }
+        Command::Commit { disk } => {
+            let (disk, names) = open(driver_source, &disk, &[1]).await?;
+            if disk.layer_count() < 2 {
+                anyhow::bail!("{} has no layer to commit into", names[0]);
+            }
+            let sectors = commit(&disk).await?;
+            println!(
+                "committed {sectors} sectors from {} into {}",
+                names[0], names[1]
+            );
+        }
+        Command::Map { disk, layer } => {
+            let (disk, names) = open(driver_source, &disk, &[]).await?;
+            let layers = match layer {
+                Some(layer) if layer >= disk.layer_count() => {
+                    anyhow::bail!("layer {layer} does not exist");
+                }
+                Some(layer) => layer..layer + 1,
+                None => 0..disk.layer_count(),
+            };
+            for layer in layers {
+                println!(
+                    "layer {layer}: {} ({})",
+                    names[layer],
+                    disk.layer_type(layer)
+                );
+                let mut allocated = 0;
+                for range in allocated_ranges(&disk, layer).await? {
+                    println!("  {:#x}-{:#x}", range.start, range.end - 1);
+                    allocated += range.end - range.start;
+                }
+                println!("  {allocated} of {} sectors allocated", disk.sector_count());
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn info(disk: &LayeredDisk, names: &[String]) {
+    println!(
+        "size: {} bytes",
+        disk.sector_count() * disk.sector_size() as u64
+    );
+    println!("sector count: {}", disk.sector_count());
+    println!("sector size: {}", disk.sector_size());
+    println!("physical sector size: {}", disk.physical_sector_size());
+    if let Some(id) = disk.disk_id() {
+        println!("disk id: {}", format_disk_id(&id));
+    }
+    println!("layers:");
+    for (i, name) in names.iter().enumerate() {
+        println!(
+            "  {i}: {name} ({}, {} sectors)",
+            disk.layer_type(i),
+            disk.layer_sector_count(i)
+        );
+    }
+}
+
+/// Formats a 16-byte disk ID as hex.
+fn format_disk_id(id: &[u8; 16]) -> String {
+    id.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// A bounce buffer for disk IO.
+struct Buffer {
+    mem: GuestMemory,
+}
+
+impl Buffer {
+    fn new() -> Self {
+        Self {
+            mem: GuestMemory::allocate(CHUNK_SIZE),
+        }
+    }
+
+    /// Reads `len` bytes starting at `sector` from the disk.
+    async fn read(&self, disk: &LayeredDisk, sector: u64, len: usize) -> anyhow::Result<Vec<u8>> {
+        disk.read_vectored(
+            &OwnedRequestBuffers::linear(0, len, true).buffer(&self.mem),
+            sector,
+        )
+        .await
+        .with_context(|| format!("failed to read sector {sector:#x}"))?;
+        let mut data = vec![0; len];
+        self.mem.read_at(0, &mut data)?;
+        Ok(data)
+    }
+}
+
+/// Iterates over the chunks of a disk, as a starting sector and byte length.
+fn chunks(disk: &LayeredDisk) -> impl Iterator<Item = (u64, usize)> + use<> {
+    let sector_size = disk.sector_size() as u64;
+    let sector_count = disk.sector_count();
+    let chunk_sectors = CHUNK_SIZE as u64 / sector_size;
+    (0..sector_count)
+        .step_by(chunk_sectors as usize)
+        .map(move |sector| {
+            let sectors = chunk_sectors.min(sector_count - sector);
+            (sector, (sectors * sector_size) as usize)
+        })
+}
+
+async fn convert(src: &LayeredDisk, dst: &LayeredDisk) -> anyhow::Result<()> {
+    if src.sector_size() != dst.sector_size() {
+        anyhow::bail!(
+            "sector size mismatch: {} vs {}",
+            src.sector_size(),
+            dst.sector_size()
+        );
+    }
+    let buffer = Buffer::new();
+    for (sector, len) in chunks(src) {
+        let data = buffer.read(src, sector, len).await?;
+        // The new image reads as zero, so zero chunks can be skipped.
+        if data.iter().all(|&b| b == 0) {
+            continue;
+        }
+        dst.write_vectored(
+            &OwnedRequestBuffers::linear(0, len, false).buffer(&buffer.mem),
+            sector,
+            false,
+        )
+        .await
+        .with_context(|| format!("failed to write sector {sector:#x}"))?;
+    }
+    dst.sync_cache().await.context("failed to flush")?;
+    Ok(())
+}
+
+/// Returns a description of the first difference between the disks, if any.
+async fn compare(a: &LayeredDisk, b: &LayeredDisk) -> anyhow::Result<Option<String>> {
+    if a.sector_size() != b.sector_size() {
+        return Ok(Some(format!(
+            "sector sizes differ: {} vs {}",
+            a.sector_size(),
+            b.sector_size()
+        )));
+    }
+    if a.sector_count() != b.sector_count() {
+        return Ok(Some(format!(
+            "sector counts differ: {} vs {}",
+            a.sector_count(),
+            b.sector_count()
+        )));
+    }
+    let (buffer_a, buffer_b) = (Buffer::new(), Buffer::new());
+    let sector_size = a.sector_size() as usize;
+    for (sector, len) in chunks(a) {
+        let data_a = buffer_a.read(a, sector, len).await?;
+        let data_b = buffer_b.read(b, sector, len).await?;
+        if let Some(offset) = data_a.iter().zip(&data_b).position(|(x, y)| x != y) {
+            return Ok(Some(format!(
+                "disks differ at sector {:#x}",
+                sector + (offset / sector_size) as u64
+            )));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the ranges of sectors present in `layer`, merged across chunks.
+async fn allocated_ranges(disk: &LayeredDisk, layer: usize) -> anyhow::Result<Vec<Range<u64>>> {
+    let buffer = Buffer::new();
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+    for (sector, len) in chunks(disk) {
+        let present = disk
+            .read_layer(
+                layer,
+                &OwnedRequestBuffers::linear(0, len, true).buffer(&buffer.mem),
+                sector,
+            )
+            .await
+            .with_context(|| format!("failed to read layer {layer} at sector {sector:#x}"))?;
+        for range in present {
+            match ranges.last_mut() {
+                Some(last) if last.end == range.start => last.end = range.end,
+                _ => ranges.push(range),
+            }
+        }
+    }
+    Ok(ranges)
+}
+
+/// Copies the sectors present in the top layer into the next layer, returning
+/// the number of sectors copied.
+async fn commit(disk: &LayeredDisk) -> anyhow::Result<u64> {
+    let buffer = Buffer::new();
+    let sector_size = disk.sector_size() as usize;
+    let mut committed = 0;
+    for (sector, len) in chunks(disk) {
+        let buffers = OwnedRequestBuffers::linear(0, len, true);
+        let present = disk
+            .read_layer(0, &buffers.buffer(&buffer.mem), sector)
+            .await
+            .with_context(|| format!("failed to read the top layer at sector {sector:#x}"))?;
+        for range in present {
+            let offset = (range.start - sector) as usize * sector_size;
+            let len = (range.end - range.start) as usize * sector_size;
+            disk.write_layer(
+                1,
+                &OwnedRequestBuffers::linear(0, CHUNK_SIZE, false)
+                    .buffer(&buffer.mem)
+                    .subrange(offset, len),
+                range.start,
+            )
+            .await
+            .with_context(|| {
+                format!(
+                    "failed to write the lower layer at sector {:#x}",
+                    range.start
+                )
+            })?;
+            committed += range.end - range.start;
+        }
+    }
+    disk.sync_layer(1).await.context("failed to flush")?;
+    Ok(committed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+
+    fn write_raw(path: &std::path::Path, sectors: &[(u64, u8)]) {
+        let mut data = vec![0; 0x400000];
+        for &(sector, value) in sectors {
+            data[sector as usize * 512..][..512].fill(value);
+        }
+        fs_err::write(path, data).unwrap();
+    }
+
+    #[async_test]
+    async fn convert_and_compare(driver: DefaultDriver) {
+        let dir = tempfile::tempdir().unwrap();
+        let raw = dir.path().join("disk.img");
+        write_raw(&raw, &[(0, 1), (5000, 2)]);
+        let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver));
+
+        let (src, _) = open(&driver_source, &DiskSpec::File(raw), &[])
+            .await
+            .unwrap();
+        for ext in ["vhd", "vhdx", "img"] {
+            let dst_path = dir.path().join(format!("copy.{ext}"));
+            let size = src.sector_count() * 512;
+            let (dst, _) = open_layers(
+                &driver_source,
+                vec![CreateSpec::File(dst_path.clone()).create(size).unwrap()],
+                &[0],
+            )
+            .await
+            .unwrap();
+            convert(&src, &dst).await.unwrap();
+            drop(dst);
+
+            let (dst, _) = open(&driver_source, &DiskSpec::File(dst_path), &[])
+                .await
+                .unwrap();
+            assert_eq!(compare(&src, &dst).await.unwrap(), None, "{ext}");
+        }
+
+        let other = dir.path().join("other.img");
+        write_raw(&other, &[(0, 1), (5001, 2)]);
+        let (other, _) = open(&driver_source, &DiskSpec::File(other), &[])
+            .await
+            .unwrap();
+        assert_eq!(
+            compare(&src, &other).await.unwrap().as_deref(),
+            Some("disks differ at sector 0x1388")
+        );
+    }
+}
@@ -0,0 +1,355 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Disk specifications, and opening them as layered disks.
+
+use anyhow::Context;
+use disk_backend_resources::layer::DiskLayerHandle;
+use disk_layered::LayerConfiguration;
+use disk_layered::LayeredDisk;
+use disk_layered::resolve::ResolveDiskLayerParameters;
+use openvmm_helpers::disk::OpenDiskOptions;
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use vm_resource::IntoResource;
+use vm_resource::Resource;
+use vm_resource::ResourceResolver;
+use vm_resource::kind::DiskHandleKind;
+use vm_resource::kind::DiskLayerHandleKind;
+use vmcore::vm_task::VmTaskDriverSource;
+
+/// A disk, as a stack of layers. This uses the same syntax as OpenVMM's
+/// `--disk` option, for the kinds that make sense outside a VM.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiskSpec {
+    /// `[file:]<path>`: an image file, with the format chosen by extension.
+    File(PathBuf),
+    /// `sql:<path>`: a sqlite layer.
+    Sqlite(PathBuf),
+    /// `sqldiff:<path>:<disk>`: a sqlite layer on top of another disk.
+    SqliteDiff(PathBuf, Box<DiskSpec>),
+    /// `blob:<flat|vhd1>:<url>`: a read-only disk served over HTTP.
+    Blob(BlobFormat, String),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BlobFormat {
+    Flat,
+    Vhd1,
+}
+
+impl FromStr for DiskSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let spec = match s.split_once(':') {
+            Some(("file", path)) => DiskSpec::File(path.into()),
+            Some(("sql", path)) => DiskSpec::Sqlite(path.into()),
+            Some(("sqldiff", arg)) => {
+                let (path, disk) = arg.split_once(':').context("expected path:disk")?;
+                DiskSpec::SqliteDiff(path.into(), Box::new(disk.parse()?))
+            }
+            Some(("blob", arg)) => {
+                let (format, url) = arg.split_once(':').context("expected kind:url")?;
+                let format = match format {
+                    "flat" => BlobFormat::Flat,
+                    "vhd1" => BlobFormat::Vhd1,
+                    _ => anyhow::bail!("unknown blob kind {format}"),
+                };
+                DiskSpec::Blob(format, url.to_string())
+            }
+            // Treat anything else, including Windows paths with a drive
+            // letter, as a path.
+            _ => DiskSpec::File(s.into()),
+        };
+        Ok(spec)
+    }
+}
+
+impl fmt::Display for DiskSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiskSpec::File(path) => write!(f, "{}", path.display()),
+            DiskSpec::Sqlite(path) => write!(f, "sql:{}", path.display()),
+            DiskSpec::SqliteDiff(path, disk) => write!(f, "sqldiff:{}:{disk}", path.display()),
+            DiskSpec::Blob(BlobFormat::Flat, url) => write!(f, "blob:flat:{url}"),
+            DiskSpec::Blob(BlobFormat::Vhd1, url) => write!(f, "blob:vhd1:{url}"),
+        }
+    }
+}
+
+/// A layer of a [`DiskSpec`], as a resource and a name to show the user.
+pub struct LayerSpec {
+    pub name: String,
+    pub resource: Resource<DiskLayerHandleKind>,
+}
+
+impl DiskSpec {
+    /// Opens the resources for each layer, from top to bottom. The layers
+    /// with indexes in `writable` are opened for write.
+    pub async fn layers(&self, writable: &[usize]) -> anyhow::Result<Vec<LayerSpec>> {
+        let mut layers = Vec::new();
+        let mut spec = self;
+        loop {
+            let read_only = !writable.contains(&layers.len());
+            let resource = match spec {
+                DiskSpec::File(path) => {
+                    DiskLayerHandle(open_image(path, read_only).await?).into_resource()
+                }
+                DiskSpec::Sqlite(path) | DiskSpec::SqliteDiff(path, _) => {
+                    if !path.exists() {
+                        anyhow::bail!(
+                            "cannot open sqlite disk at {} - file not found",
+                            path.display()
+                        );
+                    }
+                    sqlite_layer(path, None)?
+                }
+                DiskSpec::Blob(format, url) => DiskLayerHandle(
+                    disk_backend_resources::BlobDiskHandle {
+                        url: url.clone(),
+                        format: match format {
+                            BlobFormat::Flat => disk_backend_resources::BlobDiskFormat::Flat,
+                            BlobFormat::Vhd1 => disk_backend_resources::BlobDiskFormat::FixedVhd1,
+                        },
+                    }
+                    .into_resource(),
+                )
+                .into_resource(),
+            };
+            let name = match spec {
+                DiskSpec::SqliteDiff(path, _) => format!("sql:{}", path.display()),
+                spec => spec.to_string(),
+            };
+            layers.push(LayerSpec { name, resource });
+            match spec {
+                DiskSpec::SqliteDiff(_, disk) => spec = disk,
+                _ => break,
+            }
+        }
+        Ok(layers)
+    }
+}
+
+/// A destination for `convert`, created with the size of the source.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CreateSpec {
+    /// `[file:]<path>`: an image file, with the format chosen by extension.
+    File(PathBuf),
+    /// `sql:<path>`: a sqlite layer.
+    Sqlite(PathBuf),
+}
+
+impl FromStr for CreateSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s.split_once(':') {
+            Some(("file", path)) => CreateSpec::File(path.into()),
+            Some(("sql", path)) => CreateSpec::Sqlite(path.into()),
+            _ => CreateSpec::File(s.into()),
+        })
+    }
+}
+
+impl CreateSpec {
+    /// Creates the disk, returning the resource for its only layer.
+    pub fn create(&self, size: u64) -> anyhow::Result<LayerSpec> {
+        let path = match self {
+            CreateSpec::File(path) | CreateSpec::Sqlite(path) => path,
+        };
+        if path.exists() {
+            anyhow::bail!("{} already exists", path.display());
+        }
+        let resource = match self {
+            CreateSpec::File(path) => {
+                let disk = if is_raw(path) {
+                    let file = fs_err::OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create_new(true)
+                        .open(path)?;
+                    file.set_len(size)?;
+                    disk_backend_resources::FileDiskHandle(file.into()).into_resource()
+                } else {
+                    openvmm_helpers::disk::create_disk_type(
+                        path,
+                        size,
+                        OpenDiskOptions {
+                            read_only: false,
+                            direct: false,
+                        },
+                    )?
+                };
+                DiskLayerHandle(disk).into_resource()
+            }
+            CreateSpec::Sqlite(path) => sqlite_layer(path, Some(size))?,
+        };
+        Ok(LayerSpec {
+            name: match self {
+                CreateSpec::File(path) => DiskSpec::File(path.clone()).to_string(),
+                CreateSpec::Sqlite(path) => DiskSpec::Sqlite(path.clone()).to_string(),
+            },
+            resource,
+        })
+    }
+}
+
+/// Returns whether `path` is opened as a raw image rather than parsed.
+fn is_raw(path: &Path) -> bool {
+    !matches!(
+        path.extension().and_then(|s| s.to_str()),
+        Some("vhd" | "vhdx" | "qcow2" | "vmgs")
+    )
+}
+
+/// Opens an image file.
+///
+/// Raw images are opened with the portable file backend, rather than as block
+/// devices, so that no io-uring driver is needed.
+async fn open_image(path: &Path, read_only: bool) -> anyhow::Result<Resource<DiskHandleKind>> {
+    if is_raw(path) {
+        let file = fs_err::OpenOptions::new()
+            .read(true)
+            .write(!read_only)
+            .open(path)?;
+        Ok(disk_backend_resources::FileDiskHandle(file.into()).into_resource())
+    } else {
+        openvmm_helpers::disk::open_disk_type(
+            path,
+            OpenDiskOptions {
+                read_only,
+                direct: false,
+            },
+        )
+        .await
+        .with_context(|| format!("failed to open {}", path.display()))
+    }
+}
+
+#[cfg(feature = "disklayer_sqlite")]
+fn sqlite_layer(path: &Path, len: Option<u64>) -> anyhow::Result<Resource<DiskLayerHandleKind>> {
+    Ok(disk_backend_resources::layer::SqliteDiskLayerHandle {
+        dbhd_path: path.display().to_string(),
+        format_dbhd: len.map(
+            |len| disk_backend_resources::layer::SqliteDiskLayerFormatParams {
+                logically_read_only: false,
+                len: Some(len),
+            },
+        ),
+    }
+    .into_resource())
+}
+
+#[cfg(not(feature = "disklayer_sqlite"))]
+fn sqlite_layer(path: &Path, _len: Option<u64>) -> anyhow::Result<Resource<DiskLayerHandleKind>> {
+    anyhow::bail!(
+        "cannot open {}: built without sqlite support",
+        path.display()
+    )
+}
+
+/// Opens `spec` as a layered disk. The layers with indexes in `writable` are
+/// opened for write, and the disk itself is writable only if the top layer
+/// is.
+///
+/// Returns the disk and the names of its layers.
+pub async fn open(
+    driver_source: &VmTaskDriverSource,
+    spec: &DiskSpec,
+    writable: &[usize],
+) -> anyhow::Result<(LayeredDisk, Vec<String>)> {
+    open_layers(driver_source, spec.layers(writable).await?, writable).await
+}
+
+/// Resolves `layers` into a layered disk, as in [`open`].
+pub async fn open_layers(
+    driver_source: &VmTaskDriverSource,
+    layers: Vec<LayerSpec>,
+    writable: &[usize],
+) -> anyhow::Result<(LayeredDisk, Vec<String>)> {
+    let resolver = ResourceResolver::new();
+    let mut names = Vec::new();
+    let mut configs = Vec::new();
+    for (i, layer) in layers.into_iter().enumerate() {
+        let resolved = resolver
+            .resolve(
+                layer.resource,
+                ResolveDiskLayerParameters {
+                    read_only: !writable.contains(&i),
+                    driver_source,
+                },
+            )
+            .await
+            .with_context(|| format!("failed to open {}", layer.name))?;
+        names.push(layer.name);
+        configs.push(LayerConfiguration {
+            layer: resolved.0,
+            write_through: false,
+            read_cache: false,
+        });
+    }
+    let disk = LayeredDisk::new(!writable.contains(&0), configs)
+        .await
+        .context("failed to build the layered disk")?;
+    Ok((disk, names))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_disk_spec() {
+        assert_eq!(
+            "disk.vhdx".parse::<DiskSpec>().unwrap(),
+            DiskSpec::File("disk.vhdx".into())
+        );
+        assert_eq!(
+            "file:disk.img".parse::<DiskSpec>().unwrap(),
+            DiskSpec::File("disk.img".into())
+        );
+        assert_eq!(
+            r"d:\disk.img".parse::<DiskSpec>().unwrap(),
+            DiskSpec::File(r"d:\disk.img".into())
+        );
+        assert_eq!(
+            "sqldiff:diff.dbhd:sqldiff:mid.dbhd:base.vhd"
+                .parse::<DiskSpec>()
+                .unwrap(),
+            DiskSpec::SqliteDiff(
+                "diff.dbhd".into(),
+                Box::new(DiskSpec::SqliteDiff(
+                    "mid.dbhd".into(),
+                    Box::new(DiskSpec::File("base.vhd".into()))
+                ))
+            )
+        );
+        assert_eq!(
+            "blob:vhd1:https://example.com/disk.vhd"
+                .parse::<DiskSpec>()
+                .unwrap(),
+            DiskSpec::Blob(BlobFormat::Vhd1, "https://example.com/disk.vhd".into())
+        );
+        assert!(
+            "blob:qcow2:https://example.com/disk"
+                .parse::<DiskSpec>()
+                .is_err()
+        );
+        assert!("sqldiff:diff.dbhd".parse::<DiskSpec>().is_err());
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for s in [
+            "disk.img",
+            "sql:layer.dbhd",
+            "sqldiff:diff.dbhd:base.vhdx",
+            "blob:flat:https://example.com/disk",
+        ] {
+            assert_eq!(s.parse::<DiskSpec>().unwrap().to_string(), s);
+        }
+    }
+}
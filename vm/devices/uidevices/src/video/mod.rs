@@ -12,6 +12,7 @@
 use std::io::IoSlice;
 use task_control::StopTask;
 use thiserror::Error;
+use video_core::DirtyRect;
 use video_core::FramebufferControl;
 use video_core::FramebufferFormat;
 use vmbus_async::async_dgram::AsyncRecv;
@@ -241,6 +242,8 @@ enum ActiveState {
     },
     #[mesh(6)]
     SendCapability,
+    #[mesh(7)]
+    SendFeatureChange,
 }
 
 struct PacketBuffer {
@@ -310,6 +313,7 @@ fn inspect(&mut self, req: inspect::Request<'_>, task: Option<&mut VideoChannel>
                             "send_supported_resolutions"
                         }
                         ActiveState::SendCapability => "send_capability",
+                        ActiveState::SendFeatureChange => "send_feature_change",
                     },
                 ),
             };
@@ -439,7 +443,7 @@ async fn process(
                         tracelimit::info_ratelimited!(?version, "video negotiation succeeded");
                         self.state = ChannelState::Active {
                             version: *version,
-                            substate: ActiveState::ReadRequest,
+                            substate: ActiveState::SendFeatureChange,
                         };
                     } else {
                         tracelimit::warn_ratelimited!(?version, "video negotiation failed");
@@ -489,8 +493,17 @@ async fn process(
                                     let _ = (is_visible, x, y);
                                 }
                                 Request::PointerShape => {}
-                                Request::Dirt(_rects) => {
-                                    // TODO: Support dirt requests
+                                Request::Dirt(rects) => {
+                                    let rects = rects
+                                        .iter()
+                                        .map(|rect| DirtyRect {
+                                            left: i32::from(rect.left).max(0) as u32,
+                                            top: i32::from(rect.top).max(0) as u32,
+                                            right: i32::from(rect.right).max(0) as u32,
+                                            bottom: i32::from(rect.bottom).max(0) as u32,
+                                        })
+                                        .collect::<Vec<_>>();
+                                    framebuffer.dirty(&rects).await;
                                 }
                                 Request::BiosInfo => {
                                     *substate = ActiveState::SendBiosInfo;
@@ -583,6 +596,23 @@ async fn process(
                             }
                             *substate = ActiveState::ReadRequest;
                         }
+                        ActiveState::SendFeatureChange => {
+                            // Ask the guest to report the regions it updates,
+                            // so that they can be found without scanning the
+                            // whole framebuffer.
+                            Self::send_packet(
+                                &mut channel,
+                                protocol::MESSAGE_FEATURE_CHANGE,
+                                &protocol::FeatureChangeMessage {
+                                    is_dirt_needed: 1,
+                                    is_pointer_position_updates_needed: 1,
+                                    is_pointer_shape_updates_needed: 1,
+                                    is_video_situation_updates_needed: 1,
+                                },
+                            )
+                            .await?;
+                            *substate = ActiveState::ReadRequest;
+                        }
                         ActiveState::SendCapability => {
                             Self::send_packet(
                                 &mut channel,
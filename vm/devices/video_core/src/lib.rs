@@ -5,6 +5,7 @@
 
 #![forbid(unsafe_code)]
 
+use guestmem::GuestMemory;
 use inspect::Inspect;
 use mesh::MeshPayload;
 use mesh::payload::Protobuf;
@@ -51,6 +52,24 @@ pub struct FramebufferFormat {
     pub offset: usize,
 }
 
+/// A region of the framebuffer, in pixels.
+#[derive(Debug, Copy, Clone, Protobuf, PartialEq, Eq)]
+#[mesh(package = "framebuffer")]
+pub struct DirtyRect {
+    /// The left edge.
+    #[mesh(1)]
+    pub left: u32,
+    /// The top edge.
+    #[mesh(2)]
+    pub top: u32,
+    /// The right edge, exclusive.
+    #[mesh(3)]
+    pub right: u32,
+    /// The bottom edge, exclusive.
+    #[mesh(4)]
+    pub bottom: u32,
+}
+
 /// Functions necessary to control the framebuffer from a video device.
 ///
 /// This trait needs to be async so that an implementation of these functions can be async.
@@ -65,4 +84,17 @@ pub trait FramebufferControl: Send {
     async fn unmap(&mut self);
     /// Updates the framebuffer format.
     async fn set_format(&mut self, format: FramebufferFormat);
+    /// Reports regions of the framebuffer that the guest has updated.
+    ///
+    /// This is a hint for consumers of the framebuffer. The guest may update
+    /// the framebuffer without reporting it.
+    async fn dirty(&mut self, rects: &[DirtyRect]);
+    /// Returns the framebuffer memory and its size in bytes, for video
+    /// devices that draw into the framebuffer themselves rather than mapping
+    /// it into the guest.
+    ///
+    /// Returns `None` if the framebuffer cannot be accessed directly.
+    fn vram(&self) -> Option<(GuestMemory, usize)> {
+        None
+    }
 }
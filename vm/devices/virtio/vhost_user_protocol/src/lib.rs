@@ -15,5 +15,6 @@
 pub mod socket;
 
 pub use protocol::*;
+pub use socket::BlockingVhostUserSocket;
 pub use socket::SocketError;
 pub use socket::VhostUserSocket;
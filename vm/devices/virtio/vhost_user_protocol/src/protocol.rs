@@ -27,6 +27,12 @@
 /// Index bits for SET_VRING_KICK/CALL/ERR.
 pub const VHOST_USER_VRING_INDEX_MASK: u64 = 0xFF;
 
+/// Maximum number of shared memory regions reported by GET_SHMEM_CONFIG.
+pub const VHOST_USER_MAX_SHMEM_REGIONS: usize = 256;
+
+/// SHMEM_MAP flag: map the region writable.
+pub const VHOST_USER_FLAG_MAP_RW: u64 = 0x1;
+
 open_enum! {
     /// vhost-user frontend-to-backend request types.
     pub enum VhostUserRequestCode: u32 {
@@ -74,6 +80,23 @@ pub enum VhostUserRequestCode: u32 {
         GET_SHARED_OBJECT = 41,
         SET_DEVICE_STATE_FD = 42,
         CHECK_DEVICE_STATE = 43,
+        GET_SHMEM_CONFIG = 44,
+    }
+}
+
+open_enum! {
+    /// vhost-user backend-to-frontend request types, sent on the channel
+    /// established by SET_BACKEND_REQ_FD.
+    pub enum VhostUserBackendRequestCode: u32 {
+        NONE = 0,
+        IOTLB_MSG = 1,
+        CONFIG_CHANGE_MSG = 2,
+        VRING_HOST_NOTIFIER_MSG = 3,
+        SHARED_OBJECT_ADD = 6,
+        SHARED_OBJECT_REMOVE = 7,
+        SHARED_OBJECT_LOOKUP = 8,
+        SHMEM_MAP = 9,
+        SHMEM_UNMAP = 10,
     }
 }
 
@@ -164,6 +187,29 @@ pub struct VhostUserConfigHeader {
     pub flags: u32,
 }
 
+/// Reply payload for GET_SHMEM_CONFIG.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VhostUserShmemConfig {
+    pub nregions: u32,
+    pub padding: u32,
+    pub memory_sizes: [u64; VHOST_USER_MAX_SHMEM_REGIONS],
+}
+
+/// Payload for the backend SHMEM_MAP and SHMEM_UNMAP requests.
+///
+/// For SHMEM_MAP, the fd to map is passed via SCM_RIGHTS.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VhostUserMMap {
+    pub shmid: u8,
+    pub padding: [u8; 7],
+    pub fd_offset: u64,
+    pub shm_offset: u64,
+    pub len: u64,
+    pub flags: u64,
+}
+
 /// Payload for messages carrying a single u64 value (SET_FEATURES, etc.)
 /// and for SET_VRING_KICK/CALL/ERR (fd via SCM_RIGHTS, index in low bits).
 #[repr(C)]
@@ -196,7 +242,8 @@ pub struct VhostUserProtocolFeatures {
     pub shared_object: bool,
     pub device_state: bool,
     pub get_vring_base_inflight: bool,
-    #[bits(43)]
+    pub shmem: bool,
+    #[bits(42)]
     _reserved: u64,
 }
 
@@ -235,6 +282,16 @@ fn u64_msg_size() {
         assert_eq!(size_of::<VhostUserU64Msg>(), 8);
     }
 
+    #[test]
+    fn shmem_config_size() {
+        assert_eq!(size_of::<VhostUserShmemConfig>(), 8 + 8 * 256);
+    }
+
+    #[test]
+    fn mmap_size() {
+        assert_eq!(size_of::<VhostUserMMap>(), 40);
+    }
+
     #[test]
     fn header_roundtrip() {
         let hdr = VhostUserMsgHeader {
@@ -271,6 +328,12 @@ fn protocol_features() {
         assert!(pf.mq());
         assert!(pf.config());
         assert!(!pf.rarp());
+        assert_eq!(
+            VhostUserProtocolFeatures::new()
+                .with_shmem(true)
+                .into_bits(),
+            1 << 21
+        );
     }
 
     #[test]
@@ -17,6 +17,7 @@
 use std::os::fd::FromRawFd;
 use std::os::fd::OwnedFd;
 use std::os::fd::RawFd;
+use std::time::Duration;
 use thiserror::Error;
 use unix_socket::UnixStream;
 use zerocopy::FromBytes;
@@ -222,6 +223,91 @@ async fn send_with_fds(
     }
 }
 
+/// The default time to wait for the peer of a [`BlockingVhostUserSocket`] to
+/// accept or answer a request.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Synchronous vhost-user socket, for channels that are driven from
+/// non-async contexts (such as the backend request channel, which is used
+/// from `MappedMemoryRegion` callbacks).
+///
+/// The underlying socket must be in blocking mode. Since requests may be
+/// issued from an executor thread, sends and receives time out rather than
+/// block forever on an unresponsive peer.
+pub struct BlockingVhostUserSocket {
+    socket: parking_lot::Mutex<UnixStream>,
+}
+
+impl BlockingVhostUserSocket {
+    /// Wrap a connected, blocking `UnixStream`, with a timeout of
+    /// [`REQUEST_TIMEOUT`].
+    pub fn new(socket: UnixStream) -> io::Result<Self> {
+        Self::with_timeout(socket, REQUEST_TIMEOUT)
+    }
+
+    /// Wrap a connected, blocking `UnixStream`, failing sends and receives
+    /// that take longer than `timeout`.
+    pub fn with_timeout(socket: UnixStream, timeout: Duration) -> io::Result<Self> {
+        socket.set_read_timeout(Some(timeout))?;
+        socket.set_write_timeout(Some(timeout))?;
+        Ok(Self {
+            socket: parking_lot::Mutex::new(socket),
+        })
+    }
+
+    /// Send a message and, if `header` requests a reply, wait for the
+    /// u64 reply value.
+    ///
+    /// The socket lock is held across the send and receive so that replies
+    /// are not interleaved between concurrent callers.
+    pub fn request(
+        &self,
+        header: &VhostUserMsgHeader,
+        payload: &[u8],
+        fds: &[impl AsFd],
+    ) -> Result<Option<u64>, SocketError> {
+        let socket = self.socket.lock();
+        let raw_fds: Vec<RawFd> = fds.iter().map(|f| f.as_fd().as_raw_fd()).collect();
+        let iov = [IoSlice::new(header.as_bytes()), IoSlice::new(payload)];
+        let total: usize = iov.iter().map(|s| s.len()).sum();
+        let mut sent = 0;
+        while sent < total {
+            let remaining_iov = build_remaining_iov(&iov, sent);
+            let send_fds: &[RawFd] = if sent == 0 { &raw_fds } else { &[] };
+            sent += try_send(&socket, &remaining_iov, send_fds).map_err(timed_out)?;
+        }
+
+        if !header.need_reply() {
+            return Ok(None);
+        }
+
+        let mut reply = [0u8; size_of::<VhostUserMsgHeader>() + size_of::<u64>()];
+        let mut read = 0;
+        while read < reply.len() {
+            let n = try_recv(&socket, &mut reply[read..], None).map_err(timed_out)?;
+            if n == 0 {
+                return Err(SocketError::Closed);
+            }
+            read += n;
+        }
+        let (reply_hdr, value) =
+            VhostUserMsgHeader::read_from_prefix(&reply).expect("reply buffer is large enough");
+        if !reply_hdr.is_reply() || reply_hdr.request != header.request {
+            return Err(SocketError::Io(io::ErrorKind::InvalidData.into()));
+        }
+        Ok(Some(u64::from_le_bytes(value.try_into().unwrap())))
+    }
+}
+
+/// Reports a socket timeout, which surfaces as `WouldBlock`, as `TimedOut`.
+fn timed_out(err: io::Error) -> io::Error {
+    if err.kind() == io::ErrorKind::WouldBlock {
+        io::ErrorKind::TimedOut.into()
+    } else {
+        err
+    }
+}
+
 /// Build IoSlice entries for the remaining unsent bytes.
 fn build_remaining_iov<'a>(original: &'a [IoSlice<'a>], skip: usize) -> Vec<IoSlice<'a>> {
     let mut remaining = skip;
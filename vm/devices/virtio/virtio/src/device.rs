@@ -15,6 +15,29 @@
 use std::pin::Pin;
 use std::sync::Arc;
 
+/// Tells the guest that a device's config registers have changed, for
+/// devices whose config changes without a guest write, such as the balloon's
+/// target size.
+///
+/// The transport bumps the config generation and, once the driver is ready,
+/// raises a config change interrupt.
+#[derive(Debug, Clone)]
+pub struct ConfigChangeNotifier(mesh::Sender<()>);
+
+impl ConfigChangeNotifier {
+    /// Returns a new notifier and the receiver the transport polls for its
+    /// notifications.
+    pub fn new() -> (Self, mesh::Receiver<()>) {
+        let (send, recv) = mesh::channel();
+        (Self(send), recv)
+    }
+
+    /// Notifies the guest of a config change.
+    pub fn notify(&self) {
+        self.0.send(());
+    }
+}
+
 /// Per-queue virtio device trait. Ergonomic async fn — not object-safe.
 ///
 /// Devices implement this trait. The blanket impl converts any
@@ -63,6 +86,15 @@ fn set_shared_memory_region(
         Ok(())
     }
 
+    /// Provide a notifier for telling the guest that the device config
+    /// registers have changed.
+    ///
+    /// Called once, before any queue is started, by transports that can
+    /// deliver config change interrupts.
+    ///
+    /// Default: no-op.
+    fn set_config_change_notifier(&mut self, _notifier: ConfigChangeNotifier) {}
+
     /// Start a single queue.
     ///
     /// Called when a queue becomes active — either because the guest set
@@ -146,6 +178,9 @@ fn set_shared_memory_region(
         region: &Arc<dyn MappedMemoryRegion>,
     ) -> anyhow::Result<()>;
 
+    /// Provide a notifier for config register changes.
+    fn set_config_change_notifier(&mut self, notifier: ConfigChangeNotifier);
+
     /// Start a single queue.
     fn start_queue<'a>(
         &'a mut self,
@@ -199,6 +234,10 @@ fn set_shared_memory_region(
         VirtioDevice::set_shared_memory_region(self, region)
     }
 
+    fn set_config_change_notifier(&mut self, notifier: ConfigChangeNotifier) {
+        VirtioDevice::set_config_change_notifier(self, notifier)
+    }
+
     fn start_queue<'a>(
         &'a mut self,
         idx: u16,
@@ -17,6 +17,7 @@
 pub mod transport;
 
 pub use common::*;
+pub use device::ConfigChangeNotifier;
 pub use device::DynVirtioDevice;
 pub use device::VirtioDevice;
 pub use transport::*;
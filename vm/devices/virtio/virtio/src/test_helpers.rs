@@ -8,6 +8,10 @@
 //! and used rings in guest memory — the same operations a guest driver would
 //! perform.
 
+use crate::QueueResources;
+use crate::VirtioDevice;
+use crate::queue::QueueParams;
+use crate::spec::VirtioDeviceFeatures;
 use crate::spec::queue::AVAIL_ELEMENT_SIZE;
 use crate::spec::queue::AVAIL_OFFSET_FLAGS;
 use crate::spec::queue::AVAIL_OFFSET_IDX;
@@ -24,6 +28,7 @@
 use pal_async::wait::PolledWait;
 use pal_event::Event;
 use std::time::Duration;
+use vmcore::interrupt::Interrupt;
 
 /// Write a split virtio descriptor at the given descriptor table base.
 pub fn write_descriptor(
@@ -168,3 +173,111 @@ pub async fn wait_for_used(
         .await
         .expect("timed out waiting for used ring entry")
 }
+
+/// A split virtqueue driven by a test acting as the guest driver.
+pub struct TestQueue {
+    pub desc_addr: u64,
+    pub avail_addr: u64,
+    pub used_addr: u64,
+    pub size: u16,
+    /// Signaled by the test to notify the device of new available entries.
+    pub queue_event: Event,
+    /// Signaled by the device when it writes to the used ring.
+    pub interrupt_event: Event,
+    pub avail_idx: u16,
+    pub used_idx: u16,
+}
+
+impl TestQueue {
+    /// The guest memory used by each queue: a page each for the descriptor
+    /// table, the available ring, and the used ring.
+    pub const LEN: u64 = 0x3000;
+
+    /// Lays out a queue of `size` entries at `base` and initializes its rings.
+    pub fn new(mem: &GuestMemory, base: u64, size: u16) -> Self {
+        let queue = Self {
+            desc_addr: base,
+            avail_addr: base + 0x1000,
+            used_addr: base + 0x2000,
+            size,
+            queue_event: Event::new(),
+            interrupt_event: Event::new(),
+            avail_idx: 0,
+            used_idx: 0,
+        };
+        init_avail_ring(mem, queue.avail_addr);
+        init_used_ring(mem, queue.used_addr);
+        queue
+    }
+
+    /// Returns the resources for starting this queue on a device.
+    pub fn resources(&self, mem: &GuestMemory) -> QueueResources {
+        QueueResources {
+            params: QueueParams {
+                size: self.size,
+                enable: true,
+                desc_addr: self.desc_addr,
+                avail_addr: self.avail_addr,
+                used_addr: self.used_addr,
+            },
+            notify: Interrupt::from_event(self.interrupt_event.clone()),
+            event: self.queue_event.clone(),
+            guest_memory: mem.clone(),
+        }
+    }
+
+    /// Writes a descriptor chain of `(gpa, len, writeable)` buffers starting
+    /// at descriptor `desc_index`, makes it available, and notifies the
+    /// device.
+    pub fn post(&mut self, mem: &GuestMemory, desc_index: u16, buffers: &[(u64, u32, bool)]) {
+        for (i, &(gpa, len, writeable)) in buffers.iter().enumerate() {
+            let index = desc_index + i as u16;
+            let flags = DescriptorFlags::new()
+                .with_write(writeable)
+                .with_next(i + 1 < buffers.len());
+            write_descriptor(mem, self.desc_addr, index, gpa, len, flags, index + 1);
+        }
+        make_available(
+            mem,
+            self.avail_addr,
+            self.size,
+            desc_index,
+            &mut self.avail_idx,
+        );
+        self.queue_event.signal();
+    }
+
+    /// Waits for the next used ring entry, returning `(desc_id, bytes_written)`.
+    pub async fn wait(
+        &mut self,
+        driver: &pal_async::DefaultDriver,
+        mem: &GuestMemory,
+    ) -> (u16, u32) {
+        wait_for_used(
+            driver,
+            &self.interrupt_event,
+            mem,
+            self.used_addr,
+            self.size,
+            &mut self.used_idx,
+        )
+        .await
+    }
+}
+
+/// Lays out queue `idx` of `size` entries at `idx * TestQueue::LEN` and starts
+/// it on `device`.
+pub async fn start_queue(
+    device: &mut impl VirtioDevice,
+    mem: &GuestMemory,
+    idx: u16,
+    size: u16,
+    features: &VirtioDeviceFeatures,
+) -> TestQueue {
+    let queue = TestQueue::new(mem, idx as u64 * TestQueue::LEN, size);
+    device
+        .start_queue(idx, queue.resources(mem), features, None)
+        .await
+        .unwrap();
+    queue
+}
@@ -6,6 +6,7 @@
 #![expect(unsafe_code)]
 #![cfg(test)]
 
+use crate::ConfigChangeNotifier;
 use crate::DeviceTraits;
 use crate::DynVirtioDevice;
 use crate::PciInterruptModel;
@@ -4569,3 +4570,81 @@ async fn stop_during_failed_enable_resets_config_pci(_driver: DefaultDriver) {
         PciTestTransport::new(Box::new(PartialFailTestDevice::new(1, 0)), &_driver, 1);
     verify_stop_during_failed_enable_resets_config(&mut transport).await;
 }
+
+/// A device that hands its config change notifier to the test.
+#[derive(InspectMut)]
+#[inspect(skip)]
+struct ConfigChangeTestDevice {
+    notifier: Arc<Mutex<Option<ConfigChangeNotifier>>>,
+}
+
+impl VirtioDevice for ConfigChangeTestDevice {
+    fn traits(&self) -> DeviceTraits {
+        DeviceTraits {
+            device_id: VirtioDeviceType::CONSOLE,
+            max_queues: 1,
+            ..Default::default()
+        }
+    }
+    async fn read_registers_u32(&mut self, _offset: u16) -> u32 {
+        0
+    }
+    async fn write_registers_u32(&mut self, _offset: u16, _val: u32) {}
+    fn set_config_change_notifier(&mut self, notifier: ConfigChangeNotifier) {
+        *self.notifier.lock() = Some(notifier);
+    }
+    async fn start_queue(
+        &mut self,
+        _idx: u16,
+        _resources: QueueResources,
+        _features: &VirtioDeviceFeatures,
+        _initial_state: Option<QueueState>,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+    async fn stop_queue(&mut self, _idx: u16) -> Option<QueueState> {
+        None
+    }
+}
+
+/// Verify that device config change notifications bump config_generation
+/// once per poll, however many notifications arrived.
+async fn verify_device_config_change(
+    transport: &mut impl TestTransport,
+    notifier: &Mutex<Option<ConfigChangeNotifier>>,
+) {
+    let notifier = notifier.lock().clone().expect("notifier was not provided");
+
+    transport.write_driver_ok();
+    yield_and_poll(transport).await;
+    let generation = transport.read_config_generation();
+
+    notifier.notify();
+    notifier.notify();
+    yield_and_poll(transport).await;
+    assert_eq!(transport.read_config_generation(), generation + 1);
+
+    // Nothing changes without another notification.
+    yield_and_poll(transport).await;
+    assert_eq!(transport.read_config_generation(), generation + 1);
+}
+
+#[async_test]
+async fn device_config_change_mmio(driver: DefaultDriver) {
+    let notifier = Arc::new(Mutex::new(None));
+    let device = ConfigChangeTestDevice {
+        notifier: notifier.clone(),
+    };
+    let mut transport = MmioTestTransport::new(Box::new(device), &driver, 1);
+    verify_device_config_change(&mut transport, &notifier).await;
+}
+
+#[async_test]
+async fn device_config_change_pci(driver: DefaultDriver) {
+    let notifier = Arc::new(Mutex::new(None));
+    let device = ConfigChangeTestDevice {
+        notifier: notifier.clone(),
+    };
+    let mut transport = PciTestTransport::new(Box::new(device), &driver, 1);
+    verify_device_config_change(&mut transport, &notifier).await;
+}
@@ -9,6 +9,7 @@
 use super::task::TransportState;
 use super::task::TransportStateResult;
 use super::task::run_device_task;
+use crate::ConfigChangeNotifier;
 use crate::DynVirtioDevice;
 use crate::QueueResources;
 use crate::VirtioDoorbells;
@@ -83,6 +84,9 @@ pub(crate) struct VirtioTransportCore {
     pub device_status: VirtioDeviceStatus,
     #[inspect(skip)]
     pub poll_waker: Option<std::task::Waker>,
+    /// Receives the device's [`ConfigChangeNotifier`] notifications.
+    #[inspect(skip)]
+    pub config_change_recv: mesh::Receiver<()>,
     pub config_generation: u32,
     #[inspect(skip)]
     pub doorbells: VirtioDoorbells,
@@ -98,7 +102,7 @@ pub(crate) struct VirtioTransportCore {
 impl VirtioTransportCore {
     /// Create a new transport core, spawning the device task.
     pub fn new(
-        device: Box<dyn DynVirtioDevice>,
+        mut device: Box<dyn DynVirtioDevice>,
         driver: &impl Spawn,
         guest_memory: GuestMemory,
         doorbell_registration: Option<Arc<dyn DoorbellRegistration>>,
@@ -124,6 +128,9 @@ pub fn new(
         let device_feature = traits.device_features.with_version_1(true);
         let supports_save_restore = device.supports_save_restore();
 
+        let (config_change_notifier, config_change_recv) = ConfigChangeNotifier::new();
+        device.set_config_change_notifier(config_change_notifier);
+
         let (sender, receiver) = mesh::channel();
         let _device_task = driver.spawn("virtio-device-task", async move {
             run_device_task(device, receiver).await;
@@ -141,6 +148,7 @@ pub fn new(
             queues,
             device_status: VirtioDeviceStatus::new(),
             poll_waker: None,
+            config_change_recv,
             config_generation: 0,
             doorbells: VirtioDoorbells::new(doorbell_registration),
             supports_save_restore,
@@ -188,6 +196,7 @@ pub fn reset_status(&mut self, ops: &mut dyn TransportOps) {
             // Async state machine — not owned by reset_status.
             state: _,
             poll_waker: _,
+            config_change_recv: _,
 
             // Deferred IO — drop pending writes and stalled accesses.
             pending_status_deferred,
@@ -349,6 +358,14 @@ pub fn poll_device(&mut self, ops: &mut dyn TransportOps, cx: &mut std::task::Co
             }
             self.apply_transport_result(ops, result);
         }
+
+        let mut config_changed = false;
+        while let Poll::Ready(Ok(())) = self.config_change_recv.poll_recv(cx) {
+            config_changed = true;
+        }
+        if config_changed {
+            self.update_config_generation(ops);
+        }
     }
 
     /// `ChangeDeviceState::start()` implementation.
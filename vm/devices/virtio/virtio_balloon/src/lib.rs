@@ -0,0 +1,683 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio memory balloon device implementation.
+//!
+//! Implements the virtio-balloon device (device ID 5) as specified in the
+//! VIRTIO 1.2 specification, §5.5 "Traditional Memory Balloon Device". The
+//! host sets a target number of pages, and the guest gives pages to the
+//! balloon on the inflate queue until the balloon reaches the target, or
+//! takes them back on the deflate queue. The host memory backing inflated
+//! pages is discarded.
+//!
+//! The device also implements the statistics queue, on which the guest
+//! periodically reports its memory usage, and free page reporting, with which
+//! the guest reports free memory to be discarded without inflating the
+//! balloon.
+
+#![expect(missing_docs)]
+#![forbid(unsafe_code)]
+
+pub mod resolver;
+
+use anyhow::Context as _;
+use futures::StreamExt;
+use guestmem::GuestMemory;
+use inspect::Inspect;
+use inspect::InspectMut;
+use inspect_counters::Counter;
+use memory_range::MemoryRange;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use pal_async::timer::PolledTimer;
+use pal_async::wait::PolledWait;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use task_control::AsyncRun;
+use task_control::Cancelled;
+use task_control::InspectTaskMut;
+use task_control::StopTask;
+use task_control::TaskControl;
+use virtio::ConfigChangeNotifier;
+use virtio::DeviceTraits;
+use virtio::DeviceTraitsSharedMemory;
+use virtio::QueueResources;
+use virtio::VirtioDevice;
+use virtio::VirtioQueue;
+use virtio::VirtioQueueCallbackWork;
+use virtio::queue::QueueState;
+use virtio::spec::VirtioDeviceFeatures;
+use virtio::spec::balloon::*;
+use virtio_resources::balloon::BalloonRequest;
+use vmcore::ram_discard::RamDiscard;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+use zerocopy::FromBytes;
+
+/// The number of queues: inflate, deflate, stats, and free page reporting.
+const MAX_QUEUES: u16 = 4;
+
+/// How long to wait between requests for memory statistics.
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The most page frame numbers to process per inflate or deflate buffer, to
+/// prevent a malicious guest from causing unbounded host memory allocation.
+/// Linux sends at most 256 per buffer.
+const MAX_PFNS: usize = 4096;
+
+/// The most statistics to read per stats buffer.
+const MAX_STATS: usize = 64;
+
+const PAGE_SIZE: u64 = 1 << VIRTIO_BALLOON_PFN_SHIFT;
+
+// Config register offsets.
+const CONFIG_NUM_PAGES: u16 = 0;
+const CONFIG_ACTUAL: u16 = 4;
+
+#[derive(InspectMut)]
+pub struct VirtioBalloonDevice {
+    driver: VmTaskDriver,
+    free_page_reporting: bool,
+    #[inspect(flatten)]
+    state: Arc<Mutex<BalloonState>>,
+    #[inspect(skip)]
+    workers: Vec<TaskControl<BalloonWorker, BalloonQueue>>,
+    #[inspect(skip)]
+    _requests: Option<Task<()>>,
+}
+
+/// State shared between the device, its queue workers, and the request task.
+#[derive(Inspect, Default)]
+struct BalloonState {
+    /// The number of pages the guest should give to the balloon.
+    num_pages: u32,
+    /// The number of pages the guest has given to the balloon.
+    actual: u32,
+    /// The most recent memory statistics from the guest.
+    #[inspect(with = "inspect_stats")]
+    stats: Vec<(u16, u64)>,
+    inflated_pages: Counter,
+    deflated_pages: Counter,
+    reported_bytes: Counter,
+    discard_errors: Counter,
+    #[inspect(skip)]
+    config_change: Option<ConfigChangeNotifier>,
+}
+
+fn stat_name(tag: u16) -> Option<&'static str> {
+    let name = match tag {
+        VIRTIO_BALLOON_S_SWAP_IN => "swap_in",
+        VIRTIO_BALLOON_S_SWAP_OUT => "swap_out",
+        VIRTIO_BALLOON_S_MAJFLT => "major_faults",
+        VIRTIO_BALLOON_S_MINFLT => "minor_faults",
+        VIRTIO_BALLOON_S_MEMFREE => "free_memory",
+        VIRTIO_BALLOON_S_MEMTOT => "total_memory",
+        VIRTIO_BALLOON_S_AVAIL => "available_memory",
+        VIRTIO_BALLOON_S_CACHES => "disk_caches",
+        VIRTIO_BALLOON_S_HTLB_PGALLOC => "hugetlb_allocations",
+        VIRTIO_BALLOON_S_HTLB_PGFAIL => "hugetlb_failures",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn inspect_stats(stats: &[(u16, u64)]) -> impl Inspect + '_ {
+    inspect::adhoc(|req| {
+        let mut resp = req.respond();
+        for &(tag, val) in stats {
+            match stat_name(tag) {
+                Some(name) => resp.field(name, val),
+                None => resp.field(&tag.to_string(), val),
+            };
+        }
+    })
+}
+
+impl VirtioBalloonDevice {
+    /// Creates a new balloon device, discarding the guest RAM given to the
+    /// balloon with `discard`.
+    ///
+    /// If `free_page_reporting` is set, the device offers free page
+    /// reporting. The balloon's target size is set with `requests`.
+    pub fn new(
+        driver_source: &VmTaskDriverSource,
+        discard: RamDiscard,
+        free_page_reporting: bool,
+        requests: Option<mesh::Receiver<BalloonRequest>>,
+    ) -> Self {
+        let driver = driver_source.simple();
+        let state = Arc::new(Mutex::new(BalloonState::default()));
+        let requests = requests.map(|recv| {
+            driver.spawn(
+                "virtio-balloon-requests",
+                handle_requests(state.clone(), recv),
+            )
+        });
+        let workers = (0..MAX_QUEUES)
+            .map(|_| {
+                TaskControl::new(BalloonWorker {
+                    discard: discard.clone(),
+                    state: state.clone(),
+                    driver: driver.clone(),
+                })
+            })
+            .collect();
+        Self {
+            driver,
+            free_page_reporting,
+            state,
+            workers,
+            _requests: requests,
+        }
+    }
+}
+
+async fn handle_requests(
+    state: Arc<Mutex<BalloonState>>,
+    mut recv: mesh::Receiver<BalloonRequest>,
+) {
+    while let Some(req) = recv.next().await {
+        match req {
+            BalloonRequest::SetTarget(rpc) => rpc.handle_sync(|num_pages| {
+                let mut state = state.lock();
+                tracing::info!(
+                    num_pages,
+                    old_num_pages = state.num_pages,
+                    "balloon target changed"
+                );
+                state.num_pages = num_pages;
+                if let Some(notifier) = &state.config_change {
+                    notifier.notify();
+                }
+            }),
+        }
+    }
+}
+
+/// The function of a queue, which depends on its index and the negotiated
+/// features, since the queues for features that were not negotiated are
+/// omitted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Inspect)]
+enum QueueKind {
+    Inflate,
+    Deflate,
+    Stats,
+    Reporting,
+}
+
+impl QueueKind {
+    fn new(idx: u16, features: &VirtioDeviceFeatures) -> Option<Self> {
+        let features = features.device_specific_low();
+        [
+            Some(Self::Inflate),
+            Some(Self::Deflate),
+            (features & VIRTIO_BALLOON_F_STATS_VQ != 0).then_some(Self::Stats),
+            (features & VIRTIO_BALLOON_F_PAGE_REPORTING != 0).then_some(Self::Reporting),
+        ]
+        .into_iter()
+        .flatten()
+        .nth(idx.into())
+    }
+}
+
+impl VirtioDevice for VirtioBalloonDevice {
+    fn traits(&self) -> DeviceTraits {
+        let mut features = VIRTIO_BALLOON_F_STATS_VQ | VIRTIO_BALLOON_F_DEFLATE_ON_OOM;
+        if self.free_page_reporting {
+            features |= VIRTIO_BALLOON_F_PAGE_REPORTING;
+        }
+        DeviceTraits {
+            device_id: virtio::spec::VirtioDeviceType::BALLOON,
+            device_features: VirtioDeviceFeatures::new()
+                .with_device_specific_low(features)
+                .with_ring_event_idx(true)
+                .with_ring_indirect_desc(true)
+                .with_ring_packed(true),
+            max_queues: if self.free_page_reporting {
+                MAX_QUEUES
+            } else {
+                MAX_QUEUES - 1
+            },
+            device_register_length: size_of::<VirtioBalloonConfig>() as u32,
+            shared_memory: DeviceTraitsSharedMemory::default(),
+        }
+    }
+
+    async fn read_registers_u32(&mut self, offset: u16) -> u32 {
+        let state = self.state.lock();
+        match offset {
+            CONFIG_NUM_PAGES => state.num_pages,
+            CONFIG_ACTUAL => state.actual,
+            _ => 0,
+        }
+    }
+
+    async fn write_registers_u32(&mut self, offset: u16, val: u32) {
+        match offset {
+            CONFIG_ACTUAL => self.state.lock().actual = val,
+            _ => {
+                tracelimit::warn_ratelimited!(offset, val, "unexpected balloon config write");
+            }
+        }
+    }
+
+    fn set_config_change_notifier(&mut self, notifier: ConfigChangeNotifier) {
+        self.state.lock().config_change = Some(notifier);
+    }
+
+    async fn start_queue(
+        &mut self,
+        idx: u16,
+        resources: QueueResources,
+        features: &VirtioDeviceFeatures,
+        initial_state: Option<QueueState>,
+    ) -> anyhow::Result<()> {
+        let kind = QueueKind::new(idx, features)
+            .with_context(|| format!("balloon queue {idx} is not in use"))?;
+
+        let queue_event = PolledWait::new(&self.driver, resources.event)
+            .context("failed to create polled wait")?;
+        let queue = VirtioQueue::new(
+            *features,
+            resources.params,
+            resources.guest_memory.clone(),
+            resources.notify,
+            queue_event,
+            initial_state,
+        )
+        .context("failed to create virtio queue")?;
+
+        let worker = &mut self.workers[idx as usize];
+        worker.insert(
+            self.driver.clone(),
+            format!("virtio-balloon-{kind:?}").to_lowercase(),
+            BalloonQueue {
+                kind,
+                queue,
+                mem: resources.guest_memory,
+                stats_work: None,
+            },
+        );
+        worker.start();
+        Ok(())
+    }
+
+    async fn stop_queue(&mut self, idx: u16) -> Option<QueueState> {
+        let worker = &mut self.workers[idx as usize];
+        if !worker.has_state() {
+            return None;
+        }
+        worker.stop().await;
+        let mut queue = worker.remove();
+        // Return the held stats buffer so that the guest sends another one
+        // when the queue is restored.
+        if let Some(work) = queue.stats_work.take() {
+            queue.queue.complete(work, 0);
+        }
+        Some(queue.queue.queue_state())
+    }
+
+    async fn reset(&mut self) {
+        let mut state = self.state.lock();
+        state.actual = 0;
+        state.stats.clear();
+    }
+
+    fn supports_save_restore(&self) -> bool {
+        true
+    }
+}
+
+struct BalloonWorker {
+    discard: RamDiscard,
+    state: Arc<Mutex<BalloonState>>,
+    driver: VmTaskDriver,
+}
+
+#[derive(InspectMut)]
+struct BalloonQueue {
+    kind: QueueKind,
+    queue: VirtioQueue,
+    mem: GuestMemory,
+    /// The stats buffer, which is held until more statistics are wanted.
+    #[inspect(with = "Option::is_some")]
+    stats_work: Option<VirtioQueueCallbackWork>,
+}
+
+impl InspectTaskMut<BalloonQueue> for BalloonWorker {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>, state: Option<&mut BalloonQueue>) {
+        req.respond().merge(state);
+    }
+}
+
+impl AsyncRun<BalloonQueue> for BalloonWorker {
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        state: &mut BalloonQueue,
+    ) -> Result<(), Cancelled> {
+        loop {
+            // The guest sends new statistics when the device completes the
+            // stats buffer, so complete it periodically.
+            if state.stats_work.is_some() {
+                let mut timer = PolledTimer::new(&self.driver);
+                stop.until_stopped(timer.sleep(STATS_INTERVAL)).await?;
+                let work = state.stats_work.take().unwrap();
+                state.queue.complete(work, 0);
+            }
+
+            let work = stop.until_stopped(state.queue.next()).await?;
+            let Some(work) = work else { break };
+            let work = match work {
+                Ok(work) => work,
+                Err(err) => {
+                    tracelimit::error_ratelimited!(
+                        err = &err as &dyn std::error::Error,
+                        "queue error"
+                    );
+                    break;
+                }
+            };
+            match state.kind {
+                QueueKind::Inflate => {
+                    self.inflate(&state.mem, &work);
+                    state.queue.complete(work, 0);
+                }
+                QueueKind::Deflate => {
+                    let pfns = read_pfns(&state.mem, &work);
+                    self.state.lock().deflated_pages.add(pfns.len() as u64);
+                    state.queue.complete(work, 0);
+                }
+                QueueKind::Stats => {
+                    self.update_stats(&state.mem, &work);
+                    state.stats_work = Some(work);
+                }
+                QueueKind::Reporting => {
+                    self.report(&work);
+                    state.queue.complete(work, 0);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BalloonWorker {
+    /// Discards the pages the guest gave to the balloon.
+    fn inflate(&self, mem: &GuestMemory, work: &VirtioQueueCallbackWork) {
+        let mut pfns = read_pfns(mem, work);
+        self.state.lock().inflated_pages.add(pfns.len() as u64);
+        pfns.sort_unstable();
+        pfns.dedup();
+        // Discard each run of contiguous pages at once.
+        for run in pfns.chunk_by(|&a, &b| b == a + 1) {
+            let start = u64::from(run[0]) * PAGE_SIZE;
+            let end = (u64::from(run[run.len() - 1]) + 1) * PAGE_SIZE;
+            self.discard(MemoryRange::new(start..end));
+        }
+    }
+
+    /// Discards the free memory the guest reported.
+    fn report(&self, work: &VirtioQueueCallbackWork) {
+        for payload in &work.payload {
+            let start = payload.address.next_multiple_of(PAGE_SIZE);
+            let end = payload.address.saturating_add(payload.length.into()) & !(PAGE_SIZE - 1);
+            if start < end {
+                self.state.lock().reported_bytes.add(end - start);
+                self.discard(MemoryRange::new(start..end));
+            }
+        }
+    }
+
+    fn discard(&self, range: MemoryRange) {
+        if let Err(err) = self.discard.discard(range) {
+            self.state.lock().discard_errors.increment();
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                %range,
+                "failed to discard balloon memory"
+            );
+        }
+    }
+
+    fn update_stats(&self, mem: &GuestMemory, work: &VirtioQueueCallbackWork) {
+        let len = (work.get_payload_length(false) as usize)
+            .min(MAX_STATS * size_of::<VirtioBalloonStat>());
+        let mut buf = vec![0; len];
+        if let Err(err) = work.read(mem, &mut buf) {
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "failed to read balloon stats"
+            );
+            return;
+        }
+        let stats = buf
+            .chunks_exact(size_of::<VirtioBalloonStat>())
+            .map(|b| {
+                let stat = VirtioBalloonStat::read_from_bytes(b).unwrap();
+                (stat.tag.get(), stat.val.get())
+            })
+            .collect();
+        self.state.lock().stats = stats;
+    }
+}
+
+/// Reads the page frame numbers from an inflate or deflate buffer.
+fn read_pfns(mem: &GuestMemory, work: &VirtioQueueCallbackWork) -> Vec<u32> {
+    let len = (work.get_payload_length(false) as usize).min(MAX_PFNS * 4) & !3;
+    let mut buf = vec![0; len];
+    if let Err(err) = work.read(mem, &mut buf) {
+        tracelimit::warn_ratelimited!(
+            error = &err as &dyn std::error::Error,
+            "failed to read balloon page frame numbers"
+        );
+        return Vec::new();
+    }
+    buf.chunks_exact(4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::rpc::RpcSend;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use test_with_tracing::test;
+    use virtio::test_helpers::TestQueue;
+    use virtio::test_helpers::start_queue;
+    use vmcore::ram_discard::DiscardRam;
+    use vmcore::vm_task::SingleDriverBackend;
+    use zerocopy::IntoBytes;
+
+    const QUEUE_SIZE: u16 = 16;
+    const DATA_BASE: u64 = 0x10000;
+    const TOTAL_MEM_SIZE: usize = 0x40000;
+
+    /// Records the discarded ranges.
+    #[derive(Default, Clone)]
+    struct TestDiscard(Arc<Mutex<Vec<MemoryRange>>>);
+
+    impl DiscardRam for TestDiscard {
+        fn discard(&self, range: MemoryRange) -> std::io::Result<()> {
+            self.0.lock().push(range);
+            Ok(())
+        }
+    }
+
+    struct TestHarness {
+        device: VirtioBalloonDevice,
+        discarded: TestDiscard,
+        mem: GuestMemory,
+        driver: DefaultDriver,
+        queues: Vec<TestQueue>,
+    }
+
+    impl TestHarness {
+        fn new(driver: &DefaultDriver, requests: Option<mesh::Receiver<BalloonRequest>>) -> Self {
+            let mem = GuestMemory::allocate(TOTAL_MEM_SIZE);
+            let discarded = TestDiscard::default();
+            let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+            let device = VirtioBalloonDevice::new(
+                &driver_source,
+                RamDiscard::new(discarded.clone()),
+                true,
+                requests,
+            );
+            Self {
+                device,
+                discarded,
+                mem,
+                driver: driver.clone(),
+                queues: Vec::new(),
+            }
+        }
+
+        /// Enables the queues for `features`.
+        async fn enable(&mut self, features: u32) {
+            let features = VirtioDeviceFeatures::new().with_device_specific_low(features);
+            let count = 2 + features.device_specific_low().count_ones();
+            for idx in 0..count as u16 {
+                let queue =
+                    start_queue(&mut self.device, &self.mem, idx, QUEUE_SIZE, &features).await;
+                self.queues.push(queue);
+            }
+        }
+
+        /// Submits a single buffer on queue `idx`.
+        fn submit(&mut self, idx: usize, gpa: u64, len: u32, writeable: bool) {
+            self.queues[idx].post(&self.mem, 0, &[(gpa, len, writeable)]);
+        }
+
+        /// Submits a single buffer on queue `idx` and waits for completion.
+        async fn submit_and_wait(&mut self, idx: usize, gpa: u64, len: u32, writeable: bool) {
+            self.submit(idx, gpa, len, writeable);
+            self.queues[idx].wait(&self.driver, &self.mem).await;
+        }
+
+        fn discarded(&self) -> Vec<MemoryRange> {
+            std::mem::take(&mut *self.discarded.0.lock())
+        }
+    }
+
+    #[test]
+    fn queue_kinds() {
+        let features = |f| VirtioDeviceFeatures::new().with_device_specific_low(f);
+        let all = features(VIRTIO_BALLOON_F_STATS_VQ | VIRTIO_BALLOON_F_PAGE_REPORTING);
+        assert_eq!(QueueKind::new(2, &all), Some(QueueKind::Stats));
+        assert_eq!(QueueKind::new(3, &all), Some(QueueKind::Reporting));
+        let reporting = features(VIRTIO_BALLOON_F_PAGE_REPORTING);
+        assert_eq!(QueueKind::new(1, &reporting), Some(QueueKind::Deflate));
+        assert_eq!(QueueKind::new(2, &reporting), Some(QueueKind::Reporting));
+        assert_eq!(QueueKind::new(3, &reporting), None);
+    }
+
+    #[async_test]
+    async fn inflate_discards_pages(driver: DefaultDriver) {
+        let mut harness = TestHarness::new(&driver, None);
+        harness.enable(0).await;
+
+        let pfns: [u32; 5] = [0x13, 0x11, 0x12, 0x20, 0x12];
+        harness.mem.write_at(DATA_BASE, pfns.as_bytes()).unwrap();
+        harness
+            .submit_and_wait(0, DATA_BASE, size_of_val(&pfns) as u32, false)
+            .await;
+
+        assert_eq!(
+            harness.discarded(),
+            [
+                MemoryRange::new(0x11000..0x14000),
+                MemoryRange::new(0x20000..0x21000)
+            ]
+        );
+        assert_eq!(harness.device.state.lock().inflated_pages.get(), 5);
+
+        // Deflating only takes the pages back.
+        harness
+            .submit_and_wait(1, DATA_BASE, size_of_val(&pfns) as u32, false)
+            .await;
+        assert!(harness.discarded().is_empty());
+    }
+
+    #[async_test]
+    async fn free_page_reporting(driver: DefaultDriver) {
+        let mut harness = TestHarness::new(&driver, None);
+        harness.enable(VIRTIO_BALLOON_F_PAGE_REPORTING).await;
+
+        harness.submit_and_wait(2, 0x200000, 0x200000, true).await;
+        // Partial pages are not discarded.
+        harness.submit_and_wait(2, 0x10800, 0x2000, true).await;
+
+        assert_eq!(
+            harness.discarded(),
+            [
+                MemoryRange::new(0x200000..0x400000),
+                MemoryRange::new(0x11000..0x12000)
+            ]
+        );
+    }
+
+    #[async_test]
+    async fn stats(driver: DefaultDriver) {
+        let mut harness = TestHarness::new(&driver, None);
+        harness.enable(VIRTIO_BALLOON_F_STATS_VQ).await;
+
+        let stats = [
+            VirtioBalloonStat {
+                tag: VIRTIO_BALLOON_S_MEMFREE.into(),
+                val: 0x1000u64.into(),
+            },
+            VirtioBalloonStat {
+                tag: VIRTIO_BALLOON_S_MEMTOT.into(),
+                val: 0x4000u64.into(),
+            },
+        ];
+        harness.mem.write_at(DATA_BASE, stats.as_bytes()).unwrap();
+        // The device holds the buffer until it wants new statistics, so wait
+        // for the statistics instead.
+        harness.submit(2, DATA_BASE, size_of_val(&stats) as u32, false);
+        let mut timer = PolledTimer::new(&driver);
+        for _ in 0..100 {
+            if !harness.device.state.lock().stats.is_empty() {
+                break;
+            }
+            timer.sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            harness.device.state.lock().stats,
+            [
+                (VIRTIO_BALLOON_S_MEMFREE, 0x1000),
+                (VIRTIO_BALLOON_S_MEMTOT, 0x4000)
+            ]
+        );
+    }
+
+    #[async_test]
+    async fn set_target(driver: DefaultDriver) {
+        let (send, recv) = mesh::channel();
+        let mut harness = TestHarness::new(&driver, Some(recv));
+        let (notifier, mut notifications) = ConfigChangeNotifier::new();
+        harness.device.set_config_change_notifier(notifier);
+
+        send.call(BalloonRequest::SetTarget, 0x100).await.unwrap();
+        notifications.next().await.unwrap();
+        assert_eq!(
+            harness.device.read_registers_u32(CONFIG_NUM_PAGES).await,
+            0x100
+        );
+
+        harness
+            .device
+            .write_registers_u32(CONFIG_ACTUAL, 0x80)
+            .await;
+        assert_eq!(harness.device.read_registers_u32(CONFIG_ACTUAL).await, 0x80);
+        harness.device.reset().await;
+        assert_eq!(harness.device.read_registers_u32(CONFIG_ACTUAL).await, 0);
+        assert_eq!(
+            harness.device.read_registers_u32(CONFIG_NUM_PAGES).await,
+            0x100
+        );
+    }
+}
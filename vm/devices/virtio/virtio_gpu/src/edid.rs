@@ -0,0 +1,101 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Generation of the EDID reported for the scanout.
+
+/// The size of an EDID base block.
+pub const EDID_LEN: usize = 128;
+
+const MONITOR_NAME: &[u8] = b"OpenVMM";
+
+/// The refresh rate of the preferred mode, in Hz.
+const REFRESH_RATE: u64 = 60;
+
+// Blanking, in pixels and lines, loosely following CVT reduced blanking.
+const H_FRONT_PORCH: u32 = 48;
+const H_SYNC: u32 = 32;
+const H_BLANK: u32 = 160;
+const V_FRONT_PORCH: u32 = 3;
+const V_SYNC: u32 = 5;
+const V_BLANK: u32 = 23;
+
+/// The largest width or height that can be described by a detailed timing
+/// descriptor.
+pub const MAX_DIMENSION: u32 = 0xfff;
+
+/// Returns an EDID 1.4 base block describing a monitor whose preferred mode
+/// is `width` by `height`, at 96 DPI.
+pub fn generate(width: u32, height: u32) -> [u8; EDID_LEN] {
+    assert!(width <= MAX_DIMENSION && height <= MAX_DIMENSION);
+
+    let mut edid = [0; EDID_LEN];
+    edid[..8].copy_from_slice(&[0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00]);
+    // The manufacturer ID, as three 5-bit letters.
+    let [a, b, c] = b"MSF".map(|l| u16::from(l - b'A' + 1));
+    edid[8..10].copy_from_slice(&(a << 10 | b << 5 | c).to_be_bytes());
+    // Product code 1, no serial number, made in 2024.
+    edid[10..12].copy_from_slice(&1u16.to_le_bytes());
+    edid[17] = (2024 - 1990) as u8;
+    // EDID 1.4.
+    edid[18] = 1;
+    edid[19] = 4;
+    // Digital input, undefined color depth and interface.
+    edid[20] = 0x80;
+    let width_mm = width * 254 / 960;
+    let height_mm = height * 254 / 960;
+    edid[21] = (width_mm / 10).clamp(1, 255) as u8;
+    edid[22] = (height_mm / 10).clamp(1, 255) as u8;
+    // Gamma 2.2.
+    edid[23] = 120;
+    // RGB 4:4:4, sRGB is the default color space, and the preferred timing
+    // mode is the native mode.
+    edid[24] = 0x06;
+    // sRGB chromaticity coordinates.
+    edid[25..35].copy_from_slice(&[0xee, 0x91, 0xa3, 0x54, 0x4c, 0x99, 0x26, 0x0f, 0x50, 0x54]);
+    // No established or standard timings.
+    edid[38..54].fill(0x01);
+
+    // The descriptors: the preferred mode, then the monitor name.
+    detailed_timing(&mut edid[54..72], width, height, width_mm, height_mm);
+    let name = &mut edid[72..90];
+    name[3] = 0xfc;
+    name[5..].fill(b' ');
+    name[5..5 + MONITOR_NAME.len()].copy_from_slice(MONITOR_NAME);
+    name[5 + MONITOR_NAME.len()] = b'\n';
+    // Dummy descriptors.
+    edid[93] = 0x10;
+    edid[111] = 0x10;
+
+    let sum = edid[..EDID_LEN - 1]
+        .iter()
+        .fold(0u8, |sum, &b| sum.wrapping_add(b));
+    edid[EDID_LEN - 1] = sum.wrapping_neg();
+    edid
+}
+
+fn detailed_timing(dtd: &mut [u8], width: u32, height: u32, width_mm: u32, height_mm: u32) {
+    let total = u64::from(width + H_BLANK) * u64::from(height + V_BLANK);
+    // The pixel clock, in units of 10 kHz.
+    let clock = (total * REFRESH_RATE).div_ceil(10_000).min(u16::MAX.into()) as u16;
+    dtd[0..2].copy_from_slice(&clock.to_le_bytes());
+    dtd[2] = width as u8;
+    dtd[3] = H_BLANK as u8;
+    dtd[4] = ((width >> 8) << 4 | H_BLANK >> 8) as u8;
+    dtd[5] = height as u8;
+    dtd[6] = V_BLANK as u8;
+    dtd[7] = ((height >> 8) << 4 | V_BLANK >> 8) as u8;
+    dtd[8] = H_FRONT_PORCH as u8;
+    dtd[9] = H_SYNC as u8;
+    dtd[10] = (V_FRONT_PORCH << 4 | V_SYNC) as u8;
+    dtd[11] =
+        ((H_FRONT_PORCH >> 8) << 6 | (H_SYNC >> 8) << 4 | (V_FRONT_PORCH >> 4) << 2 | V_SYNC >> 4)
+            as u8;
+    let width_mm = width_mm.min(0xfff);
+    let height_mm = height_mm.min(0xfff);
+    dtd[12] = width_mm as u8;
+    dtd[13] = height_mm as u8;
+    dtd[14] = ((width_mm >> 8) << 4 | height_mm >> 8) as u8;
+    // Digital separate sync, positive horizontal and negative vertical
+    // polarity.
+    dtd[17] = 0x1a;
+}
@@ -0,0 +1,1624 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio GPU device implementation.
+//!
+//! Implements the 2D subset of the virtio-gpu device (device ID 16) as
+//! specified in the VIRTIO 1.2 specification, §5.7 "GPU Device", with a
+//! single scanout. The guest draws into resources, whose pixels are kept in
+//! host memory, and the device copies the flushed regions of the scanout's
+//! resource, with the cursor drawn over them, into a `video_core`
+//! framebuffer, from which the VNC server reads them.
+//!
+//! 3D acceleration and blob resources are not supported.
+
+#![expect(missing_docs)]
+#![forbid(unsafe_code)]
+
+mod edid;
+pub mod resolver;
+
+use anyhow::Context as _;
+use futures::StreamExt;
+use guestmem::GuestMemory;
+use guestmem::GuestMemoryError;
+use inspect::Inspect;
+use inspect::InspectMut;
+use pal_async::wait::PolledWait;
+use std::collections::HashMap;
+use std::future::poll_fn;
+use std::task::Poll;
+use task_control::AsyncRun;
+use task_control::Cancelled;
+use task_control::InspectTaskMut;
+use task_control::StopTask;
+use task_control::TaskControl;
+use thiserror::Error;
+use video_core::DirtyRect;
+use video_core::FramebufferControl;
+use video_core::FramebufferFormat;
+use virtio::DeviceTraits;
+use virtio::DeviceTraitsSharedMemory;
+use virtio::QueueResources;
+use virtio::VirtioDevice;
+use virtio::VirtioQueue;
+use virtio::VirtioQueueCallbackWork;
+use virtio::queue::QueueState;
+use virtio::spec::VirtioDeviceFeatures;
+use virtio::spec::gpu::*;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+/// The config space offset of the `events_clear` field.
+const CONFIG_EVENTS_CLEAR: u16 = 4;
+
+const BYTES_PER_PIXEL: usize = 4;
+
+/// The maximum host memory used for the pixels of all resources.
+const MAX_RESOURCE_MEMORY: usize = 256 * 1024 * 1024;
+
+/// The maximum number of guest memory regions backing a resource.
+const MAX_BACKING_ENTRIES: u32 = 16384;
+
+/// The maximum width and height of a cursor image.
+const MAX_CURSOR_SIZE: u32 = 64;
+
+#[derive(Debug, Error)]
+enum CommandError {
+    #[error("request is too short")]
+    RequestTooShort,
+    #[error("unsupported command {0:#x}")]
+    Unsupported(u32),
+    #[error("invalid scanout id {0}")]
+    InvalidScanoutId(u32),
+    #[error("invalid resource id {0}")]
+    InvalidResourceId(u32),
+    #[error("unsupported format {0}")]
+    UnsupportedFormat(u32),
+    #[error("invalid resource size {0}x{1}")]
+    InvalidSize(u32, u32),
+    #[error("out of resource memory")]
+    OutOfMemory,
+    #[error("invalid rectangle {0:?}")]
+    InvalidRect(Rect),
+    #[error("scanout {0:?} does not fit in the framebuffer")]
+    ScanoutTooLarge(Rect),
+    #[error("invalid number of backing entries {0}")]
+    InvalidBackingEntries(u32),
+    #[error("resource {0} already has backing")]
+    BackingAttached(u32),
+    #[error("resource {0} has no backing")]
+    NoBacking(u32),
+    #[error("resource backing is too small")]
+    BackingTooSmall,
+    #[error("guest memory access failed")]
+    Memory(#[source] GuestMemoryError),
+}
+
+impl CommandError {
+    /// The response type reported to the guest.
+    fn response_type(&self) -> u32 {
+        match self {
+            CommandError::InvalidScanoutId(_) => VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID,
+            CommandError::InvalidResourceId(_) => VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID,
+            CommandError::UnsupportedFormat(_)
+            | CommandError::InvalidSize(..)
+            | CommandError::InvalidRect(_)
+            | CommandError::ScanoutTooLarge(_)
+            | CommandError::InvalidBackingEntries(_)
+            | CommandError::BackingTooSmall => VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER,
+            CommandError::OutOfMemory => VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY,
+            CommandError::RequestTooShort
+            | CommandError::Unsupported(_)
+            | CommandError::BackingAttached(_)
+            | CommandError::NoBacking(_)
+            | CommandError::Memory(_) => VIRTIO_GPU_RESP_ERR_UNSPEC,
+        }
+    }
+}
+
+/// A rectangle, in pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Inspect)]
+struct Rect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl From<VirtioGpuRect> for Rect {
+    fn from(r: VirtioGpuRect) -> Self {
+        Self {
+            x: r.x.get(),
+            y: r.y.get(),
+            width: r.width.get(),
+            height: r.height.get(),
+        }
+    }
+}
+
+impl Rect {
+    fn right(&self) -> u64 {
+        u64::from(self.x) + u64::from(self.width)
+    }
+
+    fn bottom(&self) -> u64 {
+        u64::from(self.y) + u64::from(self.height)
+    }
+
+    fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        (u64::from(x) < right && u64::from(y) < bottom).then(|| Rect {
+            x,
+            y,
+            width: (right - u64::from(x)) as u32,
+            height: (bottom - u64::from(y)) as u32,
+        })
+    }
+}
+
+/// The byte offsets of the components of a pixel in a supported format.
+#[derive(Debug, Copy, Clone)]
+struct PixelLayout {
+    r: usize,
+    g: usize,
+    b: usize,
+    a: Option<usize>,
+}
+
+impl PixelLayout {
+    fn new(format: u32) -> Option<Self> {
+        let (r, g, b, a) = match format {
+            VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM => (2, 1, 0, Some(3)),
+            VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM => (2, 1, 0, None),
+            VIRTIO_GPU_FORMAT_A8R8G8B8_UNORM => (1, 2, 3, Some(0)),
+            VIRTIO_GPU_FORMAT_X8R8G8B8_UNORM => (1, 2, 3, None),
+            VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM => (0, 1, 2, Some(3)),
+            VIRTIO_GPU_FORMAT_X8B8G8R8_UNORM => (3, 2, 1, None),
+            VIRTIO_GPU_FORMAT_A8B8G8R8_UNORM => (3, 2, 1, Some(0)),
+            VIRTIO_GPU_FORMAT_R8G8B8X8_UNORM => (0, 1, 2, None),
+            _ => return None,
+        };
+        Some(Self { r, g, b, a })
+    }
+
+    /// Returns the pixel's components in BGRA order. Pixels of formats
+    /// without alpha are opaque.
+    fn bgra(&self, pixel: &[u8]) -> [u8; 4] {
+        [
+            pixel[self.b],
+            pixel[self.g],
+            pixel[self.r],
+            self.a.map_or(0xff, |a| pixel[a]),
+        ]
+    }
+}
+
+/// A 2D resource.
+#[derive(Inspect)]
+struct Resource2d {
+    format: u32,
+    #[inspect(skip)]
+    layout: PixelLayout,
+    width: u32,
+    height: u32,
+    /// The pixels, in the resource's format, without padding between rows.
+    #[inspect(skip)]
+    data: Vec<u8>,
+    /// The guest memory the pixels are transferred from.
+    #[inspect(with = "Vec::len")]
+    backing: Vec<VirtioGpuMemEntry>,
+}
+
+impl Resource2d {
+    fn stride(&self) -> usize {
+        self.width as usize * BYTES_PER_PIXEL
+    }
+
+    fn contains(&self, rect: &Rect) -> bool {
+        rect.right() <= self.width.into() && rect.bottom() <= self.height.into()
+    }
+
+    /// Returns the pixel at `x`, `y` in BGRA order.
+    fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let offset = y as usize * self.stride() + x as usize * BYTES_PER_PIXEL;
+        self.layout
+            .bgra(&self.data[offset..offset + BYTES_PER_PIXEL])
+    }
+}
+
+/// The resource shown by the scanout.
+#[derive(Inspect)]
+struct Scanout {
+    resource_id: u32,
+    /// The region of the resource that is shown.
+    rect: Rect,
+}
+
+#[derive(Inspect, Default)]
+struct Cursor {
+    resource_id: u32,
+    /// The position of the cursor image's top left corner on the scanout.
+    x: i32,
+    y: i32,
+    hot_x: u32,
+    hot_y: u32,
+    width: u32,
+    height: u32,
+    /// The cursor image in BGRA order, copied from the resource when the
+    /// cursor is updated. Empty if the cursor is hidden.
+    #[inspect(skip)]
+    image: Vec<[u8; 4]>,
+}
+
+impl Cursor {
+    /// Returns the cursor pixel at `x`, `y` on the scanout, if any.
+    fn pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        let x = i64::from(x) - i64::from(self.x);
+        let y = i64::from(y) - i64::from(self.y);
+        if x < 0 || y < 0 || x >= self.width.into() || y >= self.height.into() {
+            return None;
+        }
+        Some(self.image[y as usize * self.width as usize + x as usize])
+    }
+}
+
+/// Blends `src`, in BGRA order, over `dst`.
+fn blend(dst: [u8; 4], src: [u8; 4]) -> [u8; 4] {
+    let alpha = u16::from(src[3]);
+    let mix =
+        |s: u8, d: u8| ((u16::from(s) * alpha + u16::from(d) * (255 - alpha) + 127) / 255) as u8;
+    [
+        mix(src[0], dst[0]),
+        mix(src[1], dst[1]),
+        mix(src[2], dst[2]),
+        0,
+    ]
+}
+
+#[derive(InspectMut)]
+pub struct VirtioGpuDevice {
+    driver: VmTaskDriver,
+    #[inspect(mut)]
+    worker: TaskControl<GpuWorker, GpuQueues>,
+}
+
+impl VirtioGpuDevice {
+    /// Creates a device whose scanout is presented through `framebuffer`,
+    /// with a preferred mode of `width` by `height` pixels.
+    pub fn new(
+        driver_source: &VmTaskDriverSource,
+        framebuffer: Box<dyn FramebufferControl>,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Self> {
+        let (vram, vram_len) = framebuffer
+            .vram()
+            .context("framebuffer memory is not accessible")?;
+        if width == 0 || height == 0 || width > edid::MAX_DIMENSION || height > edid::MAX_DIMENSION
+        {
+            anyhow::bail!("invalid display size {width}x{height}");
+        }
+        if width as usize * height as usize * BYTES_PER_PIXEL > vram_len {
+            anyhow::bail!(
+                "display size {width}x{height} does not fit in the {vram_len} byte framebuffer"
+            );
+        }
+        Ok(Self {
+            driver: driver_source.simple(),
+            worker: TaskControl::new(GpuWorker {
+                framebuffer,
+                vram,
+                vram_len,
+                width,
+                height,
+                format: None,
+                resources: HashMap::new(),
+                resource_memory: 0,
+                scanout: None,
+                cursor: Cursor::default(),
+            }),
+        })
+    }
+}
+
+impl VirtioDevice for VirtioGpuDevice {
+    fn traits(&self) -> DeviceTraits {
+        DeviceTraits {
+            device_id: virtio::spec::VirtioDeviceType::GPU,
+            device_features: VirtioDeviceFeatures::new()
+                .with_device_specific_low(VIRTIO_GPU_F_EDID)
+                .with_ring_event_idx(true)
+                .with_ring_indirect_desc(true)
+                .with_ring_packed(true),
+            max_queues: 2,
+            device_register_length: size_of::<VirtioGpuConfig>() as u32,
+            shared_memory: DeviceTraitsSharedMemory::default(),
+        }
+    }
+
+    async fn read_registers_u32(&mut self, offset: u16) -> u32 {
+        // The device never reports display change events.
+        let config = VirtioGpuConfig {
+            events_read: 0,
+            events_clear: 0,
+            num_scanouts: 1,
+            num_capsets: 0,
+        };
+        let offset = offset as usize;
+        config
+            .as_bytes()
+            .get(offset..offset + 4)
+            .map_or(0, |v| u32::from_le_bytes(v.try_into().unwrap()))
+    }
+
+    async fn write_registers_u32(&mut self, offset: u16, val: u32) {
+        match offset {
+            CONFIG_EVENTS_CLEAR => {}
+            _ => {
+                tracelimit::warn_ratelimited!(offset, val, "unexpected gpu config write");
+            }
+        }
+    }
+
+    async fn start_queue(
+        &mut self,
+        idx: u16,
+        resources: QueueResources,
+        features: &VirtioDeviceFeatures,
+        initial_state: Option<QueueState>,
+    ) -> anyhow::Result<()> {
+        let queue_event = PolledWait::new(&self.driver, resources.event)
+            .context("failed to create polled wait")?;
+        let queue = VirtioQueue::new(
+            *features,
+            resources.params,
+            resources.guest_memory.clone(),
+            resources.notify,
+            queue_event,
+            initial_state,
+        )
+        .context("failed to create virtio queue")?;
+
+        // Both queues are processed by one task, so that commands on them are
+        // ordered without locking. Stop it to add the queue.
+        if self.worker.has_state() {
+            self.worker.stop().await;
+        } else {
+            self.worker.insert(
+                self.driver.clone(),
+                "virtio-gpu",
+                GpuQueues {
+                    mem: resources.guest_memory,
+                    control: None,
+                    cursor: None,
+                },
+            );
+        }
+        let queues = self.worker.state_mut().unwrap();
+        match idx {
+            VIRTIO_GPU_CONTROL_QUEUE => queues.control = Some(queue),
+            VIRTIO_GPU_CURSOR_QUEUE => queues.cursor = Some(queue),
+            _ => unreachable!(),
+        }
+        self.worker.start();
+        Ok(())
+    }
+
+    async fn stop_queue(&mut self, idx: u16) -> Option<QueueState> {
+        if !self.worker.has_state() {
+            return None;
+        }
+        self.worker.stop().await;
+        let queues = self.worker.state_mut().unwrap();
+        let queue = match idx {
+            VIRTIO_GPU_CONTROL_QUEUE => queues.control.take(),
+            VIRTIO_GPU_CURSOR_QUEUE => queues.cursor.take(),
+            _ => unreachable!(),
+        };
+        if queues.control.is_none() && queues.cursor.is_none() {
+            self.worker.remove();
+        } else {
+            self.worker.start();
+        }
+        queue.map(|queue| queue.queue_state())
+    }
+
+    async fn reset(&mut self) {
+        self.worker.task_mut().reset().await;
+    }
+}
+
+#[derive(InspectMut)]
+struct GpuQueues {
+    mem: GuestMemory,
+    control: Option<VirtioQueue>,
+    cursor: Option<VirtioQueue>,
+}
+
+/// The device state, owned by the task that processes both queues.
+#[derive(InspectMut)]
+struct GpuWorker {
+    #[inspect(skip)]
+    framebuffer: Box<dyn FramebufferControl>,
+    #[inspect(skip)]
+    vram: GuestMemory,
+    vram_len: usize,
+    /// The preferred mode.
+    width: u32,
+    height: u32,
+    /// The format last set on the framebuffer.
+    format: Option<FramebufferFormat>,
+    #[inspect(iter_by_key)]
+    resources: HashMap<u32, Resource2d>,
+    resource_memory: usize,
+    scanout: Option<Scanout>,
+    cursor: Cursor,
+}
+
+impl InspectTaskMut<GpuQueues> for GpuWorker {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>, state: Option<&mut GpuQueues>) {
+        req.respond().merge(self).merge(state);
+    }
+}
+
+impl AsyncRun<GpuQueues> for GpuWorker {
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        state: &mut GpuQueues,
+    ) -> Result<(), Cancelled> {
+        loop {
+            let (idx, work) = stop
+                .until_stopped(poll_fn(|cx| {
+                    for (idx, queue) in [
+                        (VIRTIO_GPU_CURSOR_QUEUE, &mut state.cursor),
+                        (VIRTIO_GPU_CONTROL_QUEUE, &mut state.control),
+                    ] {
+                        if let Some(queue) = queue
+                            && let Poll::Ready(item) = queue.poll_next_unpin(cx)
+                        {
+                            let item = item.expect("virtio queue stream never ends");
+                            return Poll::Ready((idx, item));
+                        }
+                    }
+                    Poll::Pending
+                }))
+                .await?;
+
+            let work = match work {
+                Ok(work) => work,
+                Err(err) => {
+                    tracelimit::error_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        "queue error"
+                    );
+                    break;
+                }
+            };
+            let (queue, len) = match idx {
+                VIRTIO_GPU_CONTROL_QUEUE => {
+                    let len = self.control(&state.mem, &work).await;
+                    (&mut state.control, len)
+                }
+                VIRTIO_GPU_CURSOR_QUEUE => {
+                    self.cursor(&state.mem, &work).await;
+                    (&mut state.cursor, 0)
+                }
+                _ => unreachable!(),
+            };
+            queue.as_mut().unwrap().complete(work, len);
+        }
+        Ok(())
+    }
+}
+
+/// A successful response to a control queue command.
+enum Response {
+    NoData,
+    DisplayInfo(Box<VirtioGpuRespDisplayInfo>),
+    Edid(Box<VirtioGpuRespEdid>),
+}
+
+/// Reads a request of type `T` from the readable part of `work`.
+fn read_request<T: FromBytes + IntoBytes>(
+    mem: &GuestMemory,
+    work: &VirtioQueueCallbackWork,
+) -> Result<T, CommandError> {
+    let mut request = T::new_zeroed();
+    let len = work
+        .read(mem, request.as_mut_bytes())
+        .map_err(CommandError::Memory)?;
+    if len < size_of::<T>() {
+        return Err(CommandError::RequestTooShort);
+    }
+    Ok(request)
+}
+
+/// Reads `buf` from `offset` bytes into the guest memory regions in
+/// `backing`.
+fn read_backing(
+    mem: &GuestMemory,
+    backing: &[VirtioGpuMemEntry],
+    mut offset: u64,
+    mut buf: &mut [u8],
+) -> Result<(), CommandError> {
+    for entry in backing {
+        if buf.is_empty() {
+            break;
+        }
+        let len = u64::from(entry.length.get());
+        if offset >= len {
+            offset -= len;
+            continue;
+        }
+        let size = ((len - offset) as usize).min(buf.len());
+        let (current, next) = buf.split_at_mut(size);
+        // Use saturating add so that an overflowing guest-provided address
+        // lands out of range rather than wrapping to a low GPA.
+        mem.read_at(entry.addr.get().saturating_add(offset), current)
+            .map_err(CommandError::Memory)?;
+        buf = next;
+        offset = 0;
+    }
+    if !buf.is_empty() {
+        return Err(CommandError::BackingTooSmall);
+    }
+    Ok(())
+}
+
+impl GpuWorker {
+    async fn reset(&mut self) {
+        self.resources.clear();
+        self.resource_memory = 0;
+        self.cursor = Cursor::default();
+        if self.scanout.take().is_some() {
+            self.blank().await;
+        }
+    }
+
+    /// Processes a control queue command, returning the length of the
+    /// response written.
+    async fn control(&mut self, mem: &GuestMemory, work: &VirtioQueueCallbackWork) -> u32 {
+        let (request, result) = match read_request::<VirtioGpuCtrlHdr>(mem, work) {
+            Ok(request) => (Some(request), self.command(mem, work, &request).await),
+            Err(err) => (None, Err(err)),
+        };
+
+        // All commands complete synchronously, so a requested fence is
+        // signaled by the response itself.
+        let mut hdr = VirtioGpuCtrlHdr::new_zeroed();
+        if let Some(request) = request
+            && request.flags.get() & VIRTIO_GPU_FLAG_FENCE != 0
+        {
+            hdr.flags = VIRTIO_GPU_FLAG_FENCE.into();
+            hdr.fence_id = request.fence_id;
+            hdr.ctx_id = request.ctx_id;
+        }
+
+        let body = match &result {
+            Ok(Response::NoData) => {
+                hdr.ty = VIRTIO_GPU_RESP_OK_NODATA.into();
+                &[][..]
+            }
+            Ok(Response::DisplayInfo(info)) => {
+                hdr.ty = VIRTIO_GPU_RESP_OK_DISPLAY_INFO.into();
+                &info.as_bytes()[size_of::<VirtioGpuCtrlHdr>()..]
+            }
+            Ok(Response::Edid(edid)) => {
+                hdr.ty = VIRTIO_GPU_RESP_OK_EDID.into();
+                &edid.as_bytes()[size_of::<VirtioGpuCtrlHdr>()..]
+            }
+            Err(err) => {
+                tracelimit::warn_ratelimited!(
+                    error = err as &dyn std::error::Error,
+                    "gpu command failed"
+                );
+                hdr.ty = err.response_type().into();
+                &[][..]
+            }
+        };
+
+        let response = [hdr.as_bytes(), body].concat();
+        match work.write(mem, &response) {
+            Ok(()) => response.len() as u32,
+            Err(err) => {
+                tracelimit::warn_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to write gpu response"
+                );
+                0
+            }
+        }
+    }
+
+    async fn command(
+        &mut self,
+        mem: &GuestMemory,
+        work: &VirtioQueueCallbackWork,
+        hdr: &VirtioGpuCtrlHdr,
+    ) -> Result<Response, CommandError> {
+        match hdr.ty.get() {
+            VIRTIO_GPU_CMD_GET_DISPLAY_INFO => Ok(Response::DisplayInfo(self.display_info())),
+            VIRTIO_GPU_CMD_GET_EDID => {
+                let request: VirtioGpuGetEdid = read_request(mem, work)?;
+                self.edid(request.scanout.get()).map(Response::Edid)
+            }
+            VIRTIO_GPU_CMD_RESOURCE_CREATE_2D => {
+                self.resource_create_2d(read_request(mem, work)?)?;
+                Ok(Response::NoData)
+            }
+            VIRTIO_GPU_CMD_RESOURCE_UNREF => {
+                self.resource_unref(read_request(mem, work)?).await?;
+                Ok(Response::NoData)
+            }
+            VIRTIO_GPU_CMD_SET_SCANOUT => {
+                self.set_scanout(read_request(mem, work)?).await?;
+                Ok(Response::NoData)
+            }
+            VIRTIO_GPU_CMD_RESOURCE_FLUSH => {
+                self.resource_flush(read_request(mem, work)?).await?;
+                Ok(Response::NoData)
+            }
+            VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D => {
+                self.transfer_to_host_2d(mem, read_request(mem, work)?)?;
+                Ok(Response::NoData)
+            }
+            VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING => {
+                self.resource_attach_backing(mem, work, read_request(mem, work)?)?;
+                Ok(Response::NoData)
+            }
+            VIRTIO_GPU_CMD_RESOURCE_DETACH_BACKING => {
+                let request: VirtioGpuResourceDetachBacking = read_request(mem, work)?;
+                self.resource_mut(request.resource_id.get())?
+                    .backing
+                    .clear();
+                Ok(Response::NoData)
+            }
+            ty => Err(CommandError::Unsupported(ty)),
+        }
+    }
+
+    fn resource(&self, id: u32) -> Result<&Resource2d, CommandError> {
+        self.resources
+            .get(&id)
+            .ok_or(CommandError::InvalidResourceId(id))
+    }
+
+    fn resource_mut(&mut self, id: u32) -> Result<&mut Resource2d, CommandError> {
+        self.resources
+            .get_mut(&id)
+            .ok_or(CommandError::InvalidResourceId(id))
+    }
+
+    fn display_info(&self) -> Box<VirtioGpuRespDisplayInfo> {
+        let mut info = Box::new(VirtioGpuRespDisplayInfo::new_zeroed());
+        info.pmodes[0] = VirtioGpuDisplayOne {
+            r: VirtioGpuRect {
+                x: 0u32.into(),
+                y: 0u32.into(),
+                width: self.width.into(),
+                height: self.height.into(),
+            },
+            enabled: 1u32.into(),
+            flags: 0u32.into(),
+        };
+        info
+    }
+
+    fn edid(&self, scanout_id: u32) -> Result<Box<VirtioGpuRespEdid>, CommandError> {
+        if scanout_id != 0 {
+            return Err(CommandError::InvalidScanoutId(scanout_id));
+        }
+        let mut resp = Box::new(VirtioGpuRespEdid::new_zeroed());
+        let edid = edid::generate(self.width, self.height);
+        resp.size = (edid.len() as u32).into();
+        resp.edid[..edid.len()].copy_from_slice(&edid);
+        Ok(resp)
+    }
+
+    fn resource_create_2d(
+        &mut self,
+        request: VirtioGpuResourceCreate2d,
+    ) -> Result<(), CommandError> {
+        let id = request.resource_id.get();
+        if id == 0 || self.resources.contains_key(&id) {
+            return Err(CommandError::InvalidResourceId(id));
+        }
+        let format = request.format.get();
+        let layout = PixelLayout::new(format).ok_or(CommandError::UnsupportedFormat(format))?;
+        let (width, height) = (request.width.get(), request.height.get());
+        if width == 0 || height == 0 {
+            return Err(CommandError::InvalidSize(width, height));
+        }
+        let len = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|n| n.checked_mul(BYTES_PER_PIXEL))
+            .filter(|&len| len <= MAX_RESOURCE_MEMORY - self.resource_memory)
+            .ok_or(CommandError::OutOfMemory)?;
+        self.resource_memory += len;
+        self.resources.insert(
+            id,
+            Resource2d {
+                format,
+                layout,
+                width,
+                height,
+                data: vec![0; len],
+                backing: Vec::new(),
+            },
+        );
+        Ok(())
+    }
+
+    async fn resource_unref(
+        &mut self,
+        request: VirtioGpuResourceUnref,
+    ) -> Result<(), CommandError> {
+        let id = request.resource_id.get();
+        let resource = self
+            .resources
+            .remove(&id)
+            .ok_or(CommandError::InvalidResourceId(id))?;
+        self.resource_memory -= resource.data.len();
+        if self
+            .scanout
+            .as_ref()
+            .is_some_and(|scanout| scanout.resource_id == id)
+        {
+            self.scanout = None;
+            self.blank().await;
+        }
+        Ok(())
+    }
+
+    fn resource_attach_backing(
+        &mut self,
+        mem: &GuestMemory,
+        work: &VirtioQueueCallbackWork,
+        request: VirtioGpuResourceAttachBacking,
+    ) -> Result<(), CommandError> {
+        let id = request.resource_id.get();
+        let resource = self.resource_mut(id)?;
+        if !resource.backing.is_empty() {
+            return Err(CommandError::BackingAttached(id));
+        }
+        let count = request.nr_entries.get();
+        if count == 0 || count > MAX_BACKING_ENTRIES {
+            return Err(CommandError::InvalidBackingEntries(count));
+        }
+        let mut entries = vec![VirtioGpuMemEntry::new_zeroed(); count as usize];
+        let len = work
+            .read_at_offset(size_of_val(&request) as u64, mem, entries.as_mut_bytes())
+            .map_err(CommandError::Memory)?;
+        if len < size_of_val(entries.as_slice()) {
+            return Err(CommandError::RequestTooShort);
+        }
+        resource.backing = entries;
+        Ok(())
+    }
+
+    fn transfer_to_host_2d(
+        &mut self,
+        mem: &GuestMemory,
+        request: VirtioGpuTransferToHost2d,
+    ) -> Result<(), CommandError> {
+        let id = request.resource_id.get();
+        let resource = self.resource_mut(id)?;
+        let rect = Rect::from(request.r);
+        if !resource.contains(&rect) {
+            return Err(CommandError::InvalidRect(rect));
+        }
+        if resource.backing.is_empty() {
+            return Err(CommandError::NoBacking(id));
+        }
+
+        // The rectangle's rows are `stride` bytes apart in the backing, as in
+        // the resource, starting at `offset`.
+        let stride = resource.stride();
+        let offset = request.offset.get();
+        let start = rect.y as usize * stride + rect.x as usize * BYTES_PER_PIXEL;
+        if rect.width == resource.width {
+            // The rows are contiguous.
+            let end = start + rect.height as usize * stride;
+            read_backing(
+                mem,
+                &resource.backing,
+                offset,
+                &mut resource.data[start..end],
+            )?;
+        } else {
+            let row_len = rect.width as usize * BYTES_PER_PIXEL;
+            for row in 0..rect.height as usize {
+                let src = offset
+                    .checked_add((row * stride) as u64)
+                    .ok_or(CommandError::BackingTooSmall)?;
+                let dst = start + row * stride;
+                read_backing(
+                    mem,
+                    &resource.backing,
+                    src,
+                    &mut resource.data[dst..dst + row_len],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_scanout(&mut self, request: VirtioGpuSetScanout) -> Result<(), CommandError> {
+        let scanout_id = request.scanout_id.get();
+        if scanout_id != 0 {
+            return Err(CommandError::InvalidScanoutId(scanout_id));
+        }
+        let id = request.resource_id.get();
+        if id == 0 {
+            // The guest has disabled the scanout.
+            if self.scanout.take().is_some() {
+                self.blank().await;
+            }
+            return Ok(());
+        }
+        let resource = self.resource(id)?;
+        let rect = Rect::from(request.r);
+        if rect.width == 0 || rect.height == 0 || !resource.contains(&rect) {
+            return Err(CommandError::InvalidRect(rect));
+        }
+        let format = FramebufferFormat {
+            width: rect.width as usize,
+            height: rect.height as usize,
+            bytes_per_line: rect.width as usize * BYTES_PER_PIXEL,
+            offset: 0,
+        };
+        if format.bytes_per_line * format.height > self.vram_len {
+            return Err(CommandError::ScanoutTooLarge(rect));
+        }
+        if self.format != Some(format) {
+            self.framebuffer.set_format(format).await;
+            self.format = Some(format);
+        }
+        self.scanout = Some(Scanout {
+            resource_id: id,
+            rect,
+        });
+        self.draw(Rect {
+            x: 0,
+            y: 0,
+            width: rect.width,
+            height: rect.height,
+        })
+        .await;
+        Ok(())
+    }
+
+    async fn resource_flush(
+        &mut self,
+        request: VirtioGpuResourceFlush,
+    ) -> Result<(), CommandError> {
+        let id = request.resource_id.get();
+        let resource = self.resource(id)?;
+        let rect = Rect::from(request.r);
+        if !resource.contains(&rect) {
+            return Err(CommandError::InvalidRect(rect));
+        }
+        if let Some(scanout) = &self.scanout
+            && scanout.resource_id == id
+            && let Some(rect) = rect.intersect(&scanout.rect)
+        {
+            let rect = Rect {
+                x: rect.x - scanout.rect.x,
+                y: rect.y - scanout.rect.y,
+                ..rect
+            };
+            self.draw(rect).await;
+        }
+        Ok(())
+    }
+
+    /// Processes a cursor queue command. These have no response.
+    async fn cursor(&mut self, mem: &GuestMemory, work: &VirtioQueueCallbackWork) {
+        let request: VirtioGpuUpdateCursor = match read_request(mem, work) {
+            Ok(request) => request,
+            Err(err) => {
+                tracelimit::warn_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    "invalid cursor command"
+                );
+                return;
+            }
+        };
+        let scanout_id = request.pos.scanout_id.get();
+        if scanout_id != 0 {
+            tracelimit::warn_ratelimited!(scanout_id, "invalid cursor scanout");
+            return;
+        }
+
+        let old_rect = self.cursor_rect();
+        match request.hdr.ty.get() {
+            VIRTIO_GPU_CMD_UPDATE_CURSOR => {
+                let id = request.resource_id.get();
+                if id == 0 {
+                    self.cursor.image.clear();
+                    self.cursor.width = 0;
+                    self.cursor.height = 0;
+                } else {
+                    match self.resources.get(&id) {
+                        Some(resource)
+                            if resource.width <= MAX_CURSOR_SIZE
+                                && resource.height <= MAX_CURSOR_SIZE =>
+                        {
+                            self.cursor.image = resource
+                                .data
+                                .chunks_exact(BYTES_PER_PIXEL)
+                                .map(|pixel| resource.layout.bgra(pixel))
+                                .collect();
+                            self.cursor.width = resource.width;
+                            self.cursor.height = resource.height;
+                        }
+                        _ => {
+                            tracelimit::warn_ratelimited!(
+                                resource_id = id,
+                                "invalid cursor resource"
+                            );
+                            return;
+                        }
+                    }
+                }
+                self.cursor.resource_id = id;
+                self.cursor.hot_x = request.hot_x.get();
+                self.cursor.hot_y = request.hot_y.get();
+            }
+            VIRTIO_GPU_CMD_MOVE_CURSOR => {}
+            ty => {
+                tracelimit::warn_ratelimited!(ty, "unsupported cursor command");
+                return;
+            }
+        }
+        // The driver sends negative positions as two's complement.
+        self.cursor.x = request.pos.x.get() as i32;
+        self.cursor.y = request.pos.y.get() as i32;
+
+        // Redraw where the cursor was and where it is now.
+        let new_rect = self.cursor_rect();
+        for rect in [old_rect, new_rect].into_iter().flatten() {
+            self.draw(rect).await;
+        }
+    }
+
+    /// Returns the part of the scanout covered by the cursor.
+    fn cursor_rect(&self) -> Option<Rect> {
+        let scanout = self.scanout.as_ref()?;
+        let cursor = &self.cursor;
+        let left = i64::from(cursor.x).max(0);
+        let top = i64::from(cursor.y).max(0);
+        let right = (i64::from(cursor.x) + i64::from(cursor.width)).min(scanout.rect.width.into());
+        let bottom =
+            (i64::from(cursor.y) + i64::from(cursor.height)).min(scanout.rect.height.into());
+        (left < right && top < bottom).then(|| Rect {
+            x: left as u32,
+            y: top as u32,
+            width: (right - left) as u32,
+            height: (bottom - top) as u32,
+        })
+    }
+
+    /// Copies `rect`, in scanout coordinates, from the scanout's resource to
+    /// the framebuffer, with the cursor drawn over it, and reports it dirty.
+    async fn draw(&mut self, rect: Rect) {
+        if let Err(err) = self.render(rect) {
+            tracelimit::error_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "failed to write framebuffer"
+            );
+            return;
+        }
+        self.framebuffer
+            .dirty(&[DirtyRect {
+                left: rect.x,
+                top: rect.y,
+                right: rect.x + rect.width,
+                bottom: rect.y + rect.height,
+            }])
+            .await;
+    }
+
+    fn render(&self, rect: Rect) -> Result<(), GuestMemoryError> {
+        let Some(scanout) = &self.scanout else {
+            return Ok(());
+        };
+        let resource = &self.resources[&scanout.resource_id];
+        let mut row = vec![0; rect.width as usize * BYTES_PER_PIXEL];
+        for y in rect.y..rect.y + rect.height {
+            for (x, pixel) in
+                (rect.x..rect.x + rect.width).zip(row.chunks_exact_mut(BYTES_PER_PIXEL))
+            {
+                let mut bgra = resource.pixel(scanout.rect.x + x, scanout.rect.y + y);
+                bgra[3] = 0;
+                if let Some(cursor) = self.cursor.pixel(x, y) {
+                    bgra = blend(bgra, cursor);
+                }
+                pixel.copy_from_slice(&bgra);
+            }
+            let offset =
+                (y as usize * scanout.rect.width as usize + rect.x as usize) * BYTES_PER_PIXEL;
+            self.vram.write_at(offset as u64, &row)?;
+        }
+        Ok(())
+    }
+
+    /// Clears the framebuffer after the scanout has been disabled.
+    async fn blank(&mut self) {
+        let Some(format) = self.format else {
+            return;
+        };
+        if let Err(err) = self
+            .vram
+            .fill_at(0, 0, format.bytes_per_line * format.height)
+        {
+            tracelimit::error_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "failed to clear framebuffer"
+            );
+            return;
+        }
+        self.framebuffer
+            .dirty(&[DirtyRect {
+                left: 0,
+                top: 0,
+                right: format.width as u32,
+                bottom: format.height as u32,
+            }])
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use test_with_tracing::test;
+    use virtio::test_helpers::TestQueue;
+    use virtio::test_helpers::start_queue;
+    use vmcore::vm_task::SingleDriverBackend;
+    use zerocopy::Immutable;
+
+    const QUEUE_SIZE: u16 = 16;
+    const REQUEST_ADDR: u64 = 0x8000;
+    const RESPONSE_ADDR: u64 = 0x9000;
+    const BACKING_ADDR: u64 = 0x10000;
+    const TOTAL_MEM_SIZE: usize = 0x20000;
+    const VRAM_SIZE: usize = 0x10000;
+
+    #[derive(Default)]
+    struct FramebufferState {
+        format: Option<FramebufferFormat>,
+        dirty: Vec<DirtyRect>,
+    }
+
+    struct TestFramebuffer {
+        vram: GuestMemory,
+        state: Arc<Mutex<FramebufferState>>,
+    }
+
+    #[async_trait::async_trait]
+    impl FramebufferControl for TestFramebuffer {
+        async fn map(&mut self, _gpa: u64) {}
+        async fn unmap(&mut self) {}
+        async fn set_format(&mut self, format: FramebufferFormat) {
+            self.state.lock().format = Some(format);
+        }
+        async fn dirty(&mut self, rects: &[DirtyRect]) {
+            self.state.lock().dirty.extend_from_slice(rects);
+        }
+        fn vram(&self) -> Option<(GuestMemory, usize)> {
+            Some((self.vram.clone(), VRAM_SIZE))
+        }
+    }
+
+    fn hdr(ty: u32) -> VirtioGpuCtrlHdr {
+        VirtioGpuCtrlHdr {
+            ty: ty.into(),
+            ..FromZeros::new_zeroed()
+        }
+    }
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> VirtioGpuRect {
+        VirtioGpuRect {
+            x: x.into(),
+            y: y.into(),
+            width: width.into(),
+            height: height.into(),
+        }
+    }
+
+    fn create_2d(id: u32, format: u32, width: u32, height: u32) -> VirtioGpuResourceCreate2d {
+        VirtioGpuResourceCreate2d {
+            hdr: hdr(VIRTIO_GPU_CMD_RESOURCE_CREATE_2D),
+            resource_id: id.into(),
+            format: format.into(),
+            width: width.into(),
+            height: height.into(),
+        }
+    }
+
+    /// Returns an attach backing request for the guest memory regions
+    /// `entries`.
+    fn attach_backing(id: u32, entries: &[(u64, u32)]) -> Vec<u8> {
+        let mut request = VirtioGpuResourceAttachBacking {
+            hdr: hdr(VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING),
+            resource_id: id.into(),
+            nr_entries: (entries.len() as u32).into(),
+        }
+        .as_bytes()
+        .to_vec();
+        for &(addr, length) in entries {
+            request.extend_from_slice(
+                VirtioGpuMemEntry {
+                    addr: addr.into(),
+                    length: length.into(),
+                    padding: 0u32.into(),
+                }
+                .as_bytes(),
+            );
+        }
+        request
+    }
+
+    fn transfer(id: u32, r: VirtioGpuRect, offset: u64) -> VirtioGpuTransferToHost2d {
+        VirtioGpuTransferToHost2d {
+            hdr: hdr(VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D),
+            r,
+            offset: offset.into(),
+            resource_id: id.into(),
+            padding: 0u32.into(),
+        }
+    }
+
+    fn set_scanout(scanout_id: u32, id: u32, r: VirtioGpuRect) -> VirtioGpuSetScanout {
+        VirtioGpuSetScanout {
+            hdr: hdr(VIRTIO_GPU_CMD_SET_SCANOUT),
+            r,
+            scanout_id: scanout_id.into(),
+            resource_id: id.into(),
+        }
+    }
+
+    fn flush(id: u32, r: VirtioGpuRect) -> VirtioGpuResourceFlush {
+        VirtioGpuResourceFlush {
+            hdr: hdr(VIRTIO_GPU_CMD_RESOURCE_FLUSH),
+            r,
+            resource_id: id.into(),
+            padding: 0u32.into(),
+        }
+    }
+
+    fn cursor_request(ty: u32, id: u32, x: u32, y: u32) -> VirtioGpuUpdateCursor {
+        VirtioGpuUpdateCursor {
+            hdr: hdr(ty),
+            pos: VirtioGpuCursorPos {
+                scanout_id: 0u32.into(),
+                x: x.into(),
+                y: y.into(),
+                padding: 0u32.into(),
+            },
+            resource_id: id.into(),
+            ..FromZeros::new_zeroed()
+        }
+    }
+
+    struct TestHarness {
+        device: VirtioGpuDevice,
+        mem: GuestMemory,
+        vram: GuestMemory,
+        framebuffer: Arc<Mutex<FramebufferState>>,
+        driver: DefaultDriver,
+        queues: Vec<TestQueue>,
+    }
+
+    impl TestHarness {
+        async fn new(driver: &DefaultDriver) -> Self {
+            let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+            let vram = GuestMemory::allocate(VRAM_SIZE);
+            let framebuffer = Arc::new(Mutex::new(FramebufferState::default()));
+            let device = VirtioGpuDevice::new(
+                &driver_source,
+                Box::new(TestFramebuffer {
+                    vram: vram.clone(),
+                    state: framebuffer.clone(),
+                }),
+                64,
+                48,
+            )
+            .unwrap();
+            let mut harness = Self {
+                device,
+                mem: GuestMemory::allocate(TOTAL_MEM_SIZE),
+                vram,
+                framebuffer,
+                driver: driver.clone(),
+                queues: Vec::new(),
+            };
+            harness.enable().await;
+            harness
+        }
+
+        async fn enable(&mut self) {
+            let features = VirtioDeviceFeatures::new();
+            for idx in 0..2 {
+                let queue =
+                    start_queue(&mut self.device, &self.mem, idx, QUEUE_SIZE, &features).await;
+                self.queues.push(queue);
+            }
+        }
+
+        /// Sends `request` on the control queue and returns the response.
+        async fn command(&mut self, request: &(impl IntoBytes + Immutable + ?Sized)) -> Vec<u8> {
+            let request = request.as_bytes();
+            self.mem.write_at(REQUEST_ADDR, request).unwrap();
+            let queue = &mut self.queues[VIRTIO_GPU_CONTROL_QUEUE as usize];
+            queue.post(
+                &self.mem,
+                0,
+                &[
+                    (REQUEST_ADDR, request.len() as u32, false),
+                    (RESPONSE_ADDR, 0x1000, true),
+                ],
+            );
+            let (_, len) = queue.wait(&self.driver, &self.mem).await;
+            let mut response = vec![0; len as usize];
+            self.mem.read_at(RESPONSE_ADDR, &mut response).unwrap();
+            response
+        }
+
+        /// Sends `request` on the control queue and returns the response type.
+        async fn command_type(&mut self, request: &(impl IntoBytes + Immutable + ?Sized)) -> u32 {
+            let response = self.command(request).await;
+            VirtioGpuCtrlHdr::read_from_prefix(&response)
+                .unwrap()
+                .0
+                .ty
+                .get()
+        }
+
+        /// Sends `request` on the cursor queue and waits for it to complete.
+        async fn cursor(&mut self, request: VirtioGpuUpdateCursor) {
+            self.mem.write_plain(REQUEST_ADDR, &request).unwrap();
+            let queue = &mut self.queues[VIRTIO_GPU_CURSOR_QUEUE as usize];
+            queue.post(
+                &self.mem,
+                0,
+                &[(REQUEST_ADDR, size_of_val(&request) as u32, false)],
+            );
+            let (_, len) = queue.wait(&self.driver, &self.mem).await;
+            assert_eq!(len, 0);
+        }
+
+        /// Returns the framebuffer pixel at `x`, `y`, for a scanout `width`
+        /// pixels wide.
+        fn pixel(&self, width: u32, x: u32, y: u32) -> [u8; 4] {
+            self.vram
+                .read_plain(u64::from(y * width + x) * BYTES_PER_PIXEL as u64)
+                .unwrap()
+        }
+    }
+
+    #[test]
+    fn edid() {
+        let edid = edid::generate(1280, 800);
+        assert_eq!(edid[..8], [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00]);
+        assert_eq!(edid.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)), 0);
+        // The preferred mode's active pixels.
+        let dtd = &edid[54..72];
+        assert_eq!(u16::from(dtd[4] >> 4) << 8 | u16::from(dtd[2]), 1280);
+        assert_eq!(u16::from(dtd[7] >> 4) << 8 | u16::from(dtd[5]), 800);
+    }
+
+    #[async_test]
+    async fn display_info(driver: DefaultDriver) {
+        let mut harness = TestHarness::new(&driver).await;
+
+        let response = harness.command(&hdr(VIRTIO_GPU_CMD_GET_DISPLAY_INFO)).await;
+        let info = VirtioGpuRespDisplayInfo::read_from_bytes(&response).unwrap();
+        assert_eq!(info.hdr.ty.get(), VIRTIO_GPU_RESP_OK_DISPLAY_INFO);
+        assert_eq!(info.pmodes[0].enabled.get(), 1);
+        assert_eq!(info.pmodes[0].r.width.get(), 64);
+        assert_eq!(info.pmodes[0].r.height.get(), 48);
+        assert_eq!(info.pmodes[1].enabled.get(), 0);
+
+        let response = harness
+            .command(&VirtioGpuGetEdid {
+                hdr: hdr(VIRTIO_GPU_CMD_GET_EDID),
+                scanout: 0u32.into(),
+                padding: 0u32.into(),
+            })
+            .await;
+        let resp = VirtioGpuRespEdid::read_from_bytes(&response).unwrap();
+        assert_eq!(resp.hdr.ty.get(), VIRTIO_GPU_RESP_OK_EDID);
+        assert_eq!(resp.size.get() as usize, edid::EDID_LEN);
+        assert_eq!(resp.edid[..edid::EDID_LEN], edid::generate(64, 48));
+    }
+
+    #[async_test]
+    async fn scanout(driver: DefaultDriver) {
+        let mut harness = TestHarness::new(&driver).await;
+
+        // A 4x2 RGBA resource, backed by two discontiguous regions.
+        let backing = [(BACKING_ADDR, 12), (BACKING_ADDR + 0x1000, 20)];
+        let write_pixel = |mem: &GuestMemory, x: u32, y: u32, pixel: [u8; 4]| {
+            let offset = (y * 4 + x) * 4;
+            let addr = if offset < 12 {
+                BACKING_ADDR + u64::from(offset)
+            } else {
+                BACKING_ADDR + 0x1000 + u64::from(offset - 12)
+            };
+            mem.write_plain(addr, &pixel).unwrap();
+        };
+        for y in 0..2 {
+            for x in 0..4 {
+                write_pixel(&harness.mem, x, y, [x as u8 * 16, y as u8 * 16, 0x80, 0xff]);
+            }
+        }
+
+        assert_eq!(
+            harness
+                .command_type(&create_2d(1, VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM, 4, 2))
+                .await,
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        assert_eq!(
+            harness
+                .command_type(attach_backing(1, &backing).as_slice())
+                .await,
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        assert_eq!(
+            harness
+                .command_type(&transfer(1, rect(0, 0, 4, 2), 0))
+                .await,
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        assert_eq!(
+            harness
+                .command_type(&set_scanout(0, 1, rect(0, 0, 4, 2)))
+                .await,
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        {
+            let mut framebuffer = harness.framebuffer.lock();
+            assert_eq!(
+                framebuffer.format,
+                Some(FramebufferFormat {
+                    width: 4,
+                    height: 2,
+                    bytes_per_line: 16,
+                    offset: 0,
+                })
+            );
+            assert_eq!(
+                framebuffer.dirty.drain(..).collect::<Vec<_>>(),
+                [DirtyRect {
+                    left: 0,
+                    top: 0,
+                    right: 4,
+                    bottom: 2,
+                }]
+            );
+        }
+        // The framebuffer is BGRX.
+        for y in 0..2 {
+            for x in 0..4 {
+                assert_eq!(
+                    harness.pixel(4, x, y),
+                    [0x80, y as u8 * 16, x as u8 * 16, 0]
+                );
+            }
+        }
+
+        // Transfer and flush a single pixel, with a fence.
+        write_pixel(&harness.mem, 2, 1, [1, 2, 3, 0xff]);
+        assert_eq!(
+            harness
+                .command_type(&transfer(1, rect(2, 1, 1, 1), 16 + 8))
+                .await,
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        // The pixel is not shown until it is flushed.
+        assert_eq!(harness.pixel(4, 2, 1), [0x80, 16, 32, 0]);
+        let mut request = flush(1, rect(2, 1, 1, 1));
+        request.hdr.flags = VIRTIO_GPU_FLAG_FENCE.into();
+        request.hdr.fence_id = 7u64.into();
+        let response = harness.command(&request).await;
+        let response = VirtioGpuCtrlHdr::read_from_bytes(&response).unwrap();
+        assert_eq!(response.ty.get(), VIRTIO_GPU_RESP_OK_NODATA);
+        assert_eq!(response.flags.get(), VIRTIO_GPU_FLAG_FENCE);
+        assert_eq!(response.fence_id.get(), 7);
+        assert_eq!(harness.pixel(4, 2, 1), [3, 2, 1, 0]);
+        assert_eq!(
+            harness.framebuffer.lock().dirty,
+            [DirtyRect {
+                left: 2,
+                top: 1,
+                right: 3,
+                bottom: 2,
+            }]
+        );
+
+        // Unreferencing the resource disables the scanout.
+        assert_eq!(
+            harness
+                .command_type(&VirtioGpuResourceUnref {
+                    hdr: hdr(VIRTIO_GPU_CMD_RESOURCE_UNREF),
+                    resource_id: 1u32.into(),
+                    padding: 0u32.into(),
+                })
+                .await,
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        assert_eq!(harness.pixel(4, 2, 1), [0; 4]);
+    }
+
+    #[async_test]
+    async fn errors(driver: DefaultDriver) {
+        let mut harness = TestHarness::new(&driver).await;
+        let format = VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM;
+
+        for (request, expected) in [
+            (
+                create_2d(0, format, 4, 4),
+                VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID,
+            ),
+            (
+                create_2d(1, 99, 4, 4),
+                VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER,
+            ),
+            (
+                create_2d(1, format, 0, 4),
+                VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER,
+            ),
+            (
+                create_2d(1, format, 0x10000, 0x10000),
+                VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY,
+            ),
+            (create_2d(1, format, 4, 4), VIRTIO_GPU_RESP_OK_NODATA),
+            (
+                create_2d(1, format, 4, 4),
+                VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID,
+            ),
+        ] {
+            assert_eq!(harness.command_type(&request).await, expected);
+        }
+
+        // Transfers need backing, within the resource.
+        assert_eq!(
+            harness
+                .command_type(&transfer(1, rect(0, 0, 4, 4), 0))
+                .await,
+            VIRTIO_GPU_RESP_ERR_UNSPEC
+        );
+        assert_eq!(
+            harness
+                .command_type(attach_backing(1, &[(BACKING_ADDR, 32)]).as_slice())
+                .await,
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        assert_eq!(
+            harness
+                .command_type(&transfer(1, rect(0, 0, 4, 5), 0))
+                .await,
+            VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER
+        );
+        // The backing only covers two rows.
+        assert_eq!(
+            harness
+                .command_type(&transfer(1, rect(0, 0, 4, 4), 0))
+                .await,
+            VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER
+        );
+        assert_eq!(
+            harness
+                .command_type(&transfer(1, rect(0, 0, 4, 2), 0))
+                .await,
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+
+        assert_eq!(
+            harness
+                .command_type(&set_scanout(1, 1, rect(0, 0, 4, 4)))
+                .await,
+            VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID
+        );
+        assert_eq!(
+            harness
+                .command_type(&set_scanout(0, 2, rect(0, 0, 4, 4)))
+                .await,
+            VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID
+        );
+        assert_eq!(
+            harness.command_type(&flush(2, rect(0, 0, 4, 4))).await,
+            VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID
+        );
+        assert_eq!(
+            harness
+                .command_type(&hdr(VIRTIO_GPU_CMD_GET_CAPSET_INFO))
+                .await,
+            VIRTIO_GPU_RESP_ERR_UNSPEC
+        );
+        assert_eq!(
+            harness.command_type(&[0u8; 8][..]).await,
+            VIRTIO_GPU_RESP_ERR_UNSPEC
+        );
+    }
+
+    #[async_test]
+    async fn cursor(driver: DefaultDriver) {
+        let mut harness = TestHarness::new(&driver).await;
+
+        // A black 4x4 scanout.
+        assert_eq!(
+            harness
+                .command_type(&create_2d(1, VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM, 4, 4))
+                .await,
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        assert_eq!(
+            harness
+                .command_type(&set_scanout(0, 1, rect(0, 0, 4, 4)))
+                .await,
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+
+        // A 2x2 cursor with an opaque blue top left pixel and a half
+        // transparent white one next to it.
+        let image: [[u8; 4]; 4] = [[0xff, 0, 0, 0xff], [0xff, 0xff, 0xff, 0x80], [0; 4], [0; 4]];
+        harness.mem.write_plain(BACKING_ADDR, &image).unwrap();
+        for request in [
+            create_2d(2, VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM, 2, 2).as_bytes(),
+            attach_backing(2, &[(BACKING_ADDR, 16)]).as_slice(),
+            transfer(2, rect(0, 0, 2, 2), 0).as_bytes(),
+        ] {
+            assert_eq!(
+                harness.command_type(request).await,
+                VIRTIO_GPU_RESP_OK_NODATA
+            );
+        }
+
+        harness
+            .cursor(cursor_request(VIRTIO_GPU_CMD_UPDATE_CURSOR, 2, 1, 1))
+            .await;
+        assert_eq!(harness.pixel(4, 1, 1), [0xff, 0, 0, 0]);
+        assert_eq!(harness.pixel(4, 2, 1), [0x80, 0x80, 0x80, 0]);
+        assert_eq!(harness.pixel(4, 1, 2), [0; 4]);
+
+        // The cursor may be partially off the scanout.
+        harness
+            .cursor(cursor_request(
+                VIRTIO_GPU_CMD_MOVE_CURSOR,
+                0,
+                3,
+                -1i32 as u32,
+            ))
+            .await;
+        assert_eq!(harness.pixel(4, 1, 1), [0; 4]);
+        assert_eq!(harness.pixel(4, 2, 1), [0; 4]);
+        assert_eq!(harness.pixel(4, 3, 0), [0; 4]);
+
+        harness
+            .cursor(cursor_request(VIRTIO_GPU_CMD_MOVE_CURSOR, 0, 3, 3))
+            .await;
+        assert_eq!(harness.pixel(4, 3, 3), [0xff, 0, 0, 0]);
+
+        // Hide the cursor.
+        harness
+            .cursor(cursor_request(VIRTIO_GPU_CMD_UPDATE_CURSOR, 0, 3, 3))
+            .await;
+        assert_eq!(harness.pixel(4, 3, 3), [0; 4]);
+    }
+}
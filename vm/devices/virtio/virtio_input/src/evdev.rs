@@ -0,0 +1,97 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Linux evdev event types and codes, as carried by virtio-input, and the
+//! translation from the PS/2 scan codes provided by input sources.
+
+// Event types.
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const EV_ABS: u16 = 0x03;
+pub const EV_REP: u16 = 0x14;
+
+pub const SYN_REPORT: u16 = 0;
+
+// Relative axes.
+pub const REL_X: u16 = 0x00;
+pub const REL_Y: u16 = 0x01;
+pub const REL_WHEEL: u16 = 0x08;
+
+// Absolute axes.
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+
+// Mouse buttons.
+pub const BTN_LEFT: u16 = 0x110;
+pub const BTN_RIGHT: u16 = 0x111;
+pub const BTN_MIDDLE: u16 = 0x112;
+
+// Autorepeat parameters.
+pub const REP_DELAY: u16 = 0x00;
+pub const REP_PERIOD: u16 = 0x01;
+
+pub const BUS_VIRTUAL: u16 = 0x06;
+
+/// Keys reached by scan codes with an 0xe0 prefix (in the high byte of the
+/// code), or by non-prefixed scan codes that don't map to the key code of the
+/// same value.
+const SCANCODE_TO_KEY: &[(u16, u16)] = &[
+    (0x54, 99),    // KEY_SYSRQ (Alt+Print Screen)
+    (0x70, 93),    // KEY_KATAKANAHIRAGANA
+    (0x73, 89),    // KEY_RO
+    (0x79, 92),    // KEY_HENKAN
+    (0x7b, 94),    // KEY_MUHENKAN
+    (0x7d, 124),   // KEY_YEN
+    (0xe010, 165), // KEY_PREVIOUSSONG
+    (0xe019, 163), // KEY_NEXTSONG
+    (0xe01c, 96),  // KEY_KPENTER
+    (0xe01d, 97),  // KEY_RIGHTCTRL
+    (0xe020, 113), // KEY_MUTE
+    (0xe022, 164), // KEY_PLAYPAUSE
+    (0xe024, 166), // KEY_STOPCD
+    (0xe02e, 114), // KEY_VOLUMEDOWN
+    (0xe030, 115), // KEY_VOLUMEUP
+    (0xe035, 98),  // KEY_KPSLASH
+    (0xe037, 99),  // KEY_SYSRQ
+    (0xe038, 100), // KEY_RIGHTALT
+    (0xe046, 119), // KEY_PAUSE (Ctrl+Break)
+    (0xe047, 102), // KEY_HOME
+    (0xe048, 103), // KEY_UP
+    (0xe049, 104), // KEY_PAGEUP
+    (0xe04b, 105), // KEY_LEFT
+    (0xe04d, 106), // KEY_RIGHT
+    (0xe04f, 107), // KEY_END
+    (0xe050, 108), // KEY_DOWN
+    (0xe051, 109), // KEY_PAGEDOWN
+    (0xe052, 110), // KEY_INSERT
+    (0xe053, 111), // KEY_DELETE
+    (0xe05b, 125), // KEY_LEFTMETA
+    (0xe05c, 126), // KEY_RIGHTMETA
+    (0xe05d, 127), // KEY_COMPOSE
+    (0xe05e, 116), // KEY_POWER
+    (0xe05f, 142), // KEY_SLEEP
+    (0xe063, 143), // KEY_WAKEUP
+    (0xe11d, 119), // KEY_PAUSE
+];
+
+/// Returns the key code for a scan code set 1 make code, with any 0xe0 or
+/// 0xe1 prefix in the high byte.
+pub fn scancode_to_key(code: u16) -> Option<u16> {
+    let code = code & !0x80;
+    match code {
+        // Linux key codes were defined to match the unprefixed scan codes of
+        // the standard keys.
+        0x01..=0x53 | 0x56..=0x58 => Some(code),
+        _ => SCANCODE_TO_KEY
+            .iter()
+            .find_map(|&(scancode, key)| (scancode == code).then_some(key)),
+    }
+}
+
+/// Returns all the key codes that [`scancode_to_key`] can produce.
+pub fn keys() -> impl Iterator<Item = u16> {
+    (0x01..=0x53)
+        .chain(0x56..=0x58)
+        .chain(SCANCODE_TO_KEY.iter().map(|&(_, key)| key))
+}
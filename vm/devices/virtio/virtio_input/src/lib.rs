@@ -0,0 +1,867 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio input device implementation.
+//!
+//! Implements the virtio-input device (device ID 18) as specified in the
+//! VIRTIO 1.2 specification, §5.8 "Input Device", as a keyboard, a relative
+//! mouse, or an absolute pointing device (tablet). Input comes from an
+//! `input_core` source, as for the synthetic VMBus input devices, and is sent
+//! to the guest as Linux evdev events, one per event queue buffer.
+//!
+//! Input sources report absolute pointer positions. The relative mouse
+//! converts them to motion, which the guest may accelerate, so the guest's
+//! pointer can drift from the client's; the tablet does not have this
+//! problem and should be preferred when the guest supports it.
+
+#![expect(missing_docs)]
+#![forbid(unsafe_code)]
+
+mod evdev;
+pub mod resolver;
+
+use anyhow::Context as _;
+use evdev::*;
+use futures::StreamExt;
+use guestmem::GuestMemory;
+use input_core::InputData;
+use input_core::InputSource;
+use input_core::KeyboardData;
+use input_core::MouseData;
+use inspect::Inspect;
+use inspect::InspectMut;
+use pal_async::wait::PolledWait;
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::task::Context;
+use std::task::Poll;
+use task_control::AsyncRun;
+use task_control::Cancelled;
+use task_control::InspectTaskMut;
+use task_control::StopTask;
+use task_control::TaskControl;
+use virtio::DeviceTraits;
+use virtio::DeviceTraitsSharedMemory;
+use virtio::QueueResources;
+use virtio::VirtioDevice;
+use virtio::VirtioQueue;
+use virtio::VirtioQueueCallbackWork;
+use virtio::queue::QueueState;
+use virtio::spec::VirtioDeviceFeatures;
+use virtio::spec::input::*;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+/// The config space offset of the `select`, `subsel` and `size` fields.
+const CONFIG_SELECT: u16 = 0;
+
+const CONFIG_LEN: usize = size_of::<VirtioInputConfigHeader>() + VIRTIO_INPUT_CONFIG_DATA_SIZE;
+
+/// The maximum number of events to buffer while waiting for the guest to
+/// provide event buffers. Beyond this, input is left in the source.
+const MAX_PENDING_EVENTS: usize = 256;
+
+/// The number of units of the input source's absolute range (0..=0x7fff) per
+/// unit of relative motion, so that a move across the whole range moves a
+/// relative pointer 1024 units.
+const REL_SCALE: u16 = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Inspect)]
+enum InputKind {
+    Keyboard,
+    Mouse,
+    Tablet,
+}
+
+impl InputKind {
+    fn name(&self) -> &'static str {
+        match self {
+            InputKind::Keyboard => "Virtio Keyboard",
+            InputKind::Mouse => "Virtio Mouse",
+            InputKind::Tablet => "Virtio Tablet",
+        }
+    }
+
+    fn product_id(&self) -> u16 {
+        match self {
+            InputKind::Keyboard => 1,
+            InputKind::Mouse => 2,
+            InputKind::Tablet => 3,
+        }
+    }
+
+    /// Returns the codes the device can send for event type `ty`.
+    fn event_codes(&self, ty: u16) -> Vec<u16> {
+        const BUTTONS: [u16; 3] = [BTN_LEFT, BTN_RIGHT, BTN_MIDDLE];
+        match (self, ty) {
+            (InputKind::Keyboard, EV_KEY) => evdev::keys().collect(),
+            // Advertise autorepeat so that the guest repeats held keys.
+            (InputKind::Keyboard, EV_REP) => vec![REP_DELAY, REP_PERIOD],
+            (InputKind::Mouse | InputKind::Tablet, EV_KEY) => BUTTONS.to_vec(),
+            (InputKind::Mouse, EV_REL) => vec![REL_X, REL_Y, REL_WHEEL],
+            (InputKind::Tablet, EV_REL) => vec![REL_WHEEL],
+            (InputKind::Tablet, EV_ABS) => vec![ABS_X, ABS_Y],
+            _ => Vec::new(),
+        }
+    }
+}
+
+enum Source {
+    Keyboard(Box<dyn InputSource<KeyboardData>>),
+    Mouse(Box<dyn InputSource<MouseData>>),
+}
+
+impl Source {
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<InputData>> {
+        match self {
+            Source::Keyboard(source) => source
+                .poll_next_unpin(cx)
+                .map(|data| data.map(InputData::Keyboard)),
+            Source::Mouse(source) => source
+                .poll_next_unpin(cx)
+                .map(|data| data.map(InputData::Mouse)),
+        }
+    }
+
+    async fn set_active(&mut self, active: bool) {
+        match self {
+            Source::Keyboard(source) => source.set_active(active).await,
+            Source::Mouse(source) => source.set_active(active).await,
+        }
+    }
+}
+
+#[derive(InspectMut)]
+pub struct VirtioInputDevice {
+    driver: VmTaskDriver,
+    kind: InputKind,
+    #[inspect(hex)]
+    select: u8,
+    #[inspect(hex)]
+    subsel: u8,
+    #[inspect(mut)]
+    event_worker: TaskControl<EventWorker, InputQueue>,
+    #[inspect(mut)]
+    status_worker: TaskControl<StatusWorker, InputQueue>,
+}
+
+impl VirtioInputDevice {
+    /// Creates a keyboard.
+    pub fn keyboard(
+        driver_source: &VmTaskDriverSource,
+        source: Box<dyn InputSource<KeyboardData>>,
+    ) -> Self {
+        Self::new(driver_source, InputKind::Keyboard, Source::Keyboard(source))
+    }
+
+    /// Creates a relative mouse.
+    pub fn mouse(
+        driver_source: &VmTaskDriverSource,
+        source: Box<dyn InputSource<MouseData>>,
+    ) -> Self {
+        Self::new(driver_source, InputKind::Mouse, Source::Mouse(source))
+    }
+
+    /// Creates an absolute pointing device.
+    pub fn tablet(
+        driver_source: &VmTaskDriverSource,
+        source: Box<dyn InputSource<MouseData>>,
+    ) -> Self {
+        Self::new(driver_source, InputKind::Tablet, Source::Mouse(source))
+    }
+
+    fn new(driver_source: &VmTaskDriverSource, kind: InputKind, source: Source) -> Self {
+        Self {
+            driver: driver_source.simple(),
+            kind,
+            select: VIRTIO_INPUT_CFG_UNSET,
+            subsel: 0,
+            event_worker: TaskControl::new(EventWorker {
+                source,
+                source_closed: false,
+                translator: Translator::new(kind),
+                pending: VecDeque::new(),
+            }),
+            status_worker: TaskControl::new(StatusWorker),
+        }
+    }
+
+    /// Returns the data for the selected config item.
+    fn config_data(&self) -> Vec<u8> {
+        let mut data = match self.select {
+            VIRTIO_INPUT_CFG_ID_NAME if self.subsel == 0 => self.kind.name().as_bytes().to_vec(),
+            VIRTIO_INPUT_CFG_ID_DEVIDS if self.subsel == 0 => VirtioInputDevIds {
+                bustype: BUS_VIRTUAL.into(),
+                vendor: 0u16.into(),
+                product: self.kind.product_id().into(),
+                version: 1u16.into(),
+            }
+            .as_bytes()
+            .to_vec(),
+            VIRTIO_INPUT_CFG_EV_BITS => bitmap(self.kind.event_codes(self.subsel.into())),
+            VIRTIO_INPUT_CFG_ABS_INFO
+                if self.kind == InputKind::Tablet
+                    && matches!(u16::from(self.subsel), ABS_X | ABS_Y) =>
+            {
+                VirtioInputAbsInfo {
+                    min: 0u32.into(),
+                    max: 0x7fffu32.into(),
+                    fuzz: 0u32.into(),
+                    flat: 0u32.into(),
+                    res: 0u32.into(),
+                }
+                .as_bytes()
+                .to_vec()
+            }
+            _ => Vec::new(),
+        };
+        data.truncate(VIRTIO_INPUT_CONFIG_DATA_SIZE);
+        data
+    }
+}
+
+/// Returns a config space bitmap with the bits for `codes` set, without
+/// trailing zero bytes.
+fn bitmap(codes: impl IntoIterator<Item = u16>) -> Vec<u8> {
+    let mut bitmap = Vec::new();
+    for code in codes {
+        let byte = usize::from(code / 8);
+        if bitmap.len() <= byte {
+            bitmap.resize(byte + 1, 0);
+        }
+        bitmap[byte] |= 1 << (code % 8);
+    }
+    bitmap
+}
+
+async fn stop_worker<T: AsyncRun<InputQueue>>(
+    worker: &mut TaskControl<T, InputQueue>,
+) -> Option<QueueState> {
+    if !worker.has_state() {
+        return None;
+    }
+    worker.stop().await;
+    Some(worker.remove().queue.queue_state())
+}
+
+impl VirtioDevice for VirtioInputDevice {
+    fn traits(&self) -> DeviceTraits {
+        DeviceTraits {
+            device_id: virtio::spec::VirtioDeviceType::INPUT,
+            device_features: VirtioDeviceFeatures::new()
+                .with_ring_event_idx(true)
+                .with_ring_indirect_desc(true)
+                .with_ring_packed(true),
+            max_queues: 2,
+            device_register_length: CONFIG_LEN as u32,
+            shared_memory: DeviceTraitsSharedMemory::default(),
+        }
+    }
+
+    async fn read_registers_u32(&mut self, offset: u16) -> u32 {
+        let data = self.config_data();
+        let header = VirtioInputConfigHeader {
+            select: self.select,
+            subsel: self.subsel,
+            size: data.len() as u8,
+            reserved: [0; 5],
+        };
+        let mut config = [0; CONFIG_LEN];
+        let (config_header, config_data) = config.split_at_mut(size_of_val(&header));
+        config_header.copy_from_slice(header.as_bytes());
+        config_data[..data.len()].copy_from_slice(&data);
+        let offset = offset as usize;
+        config
+            .get(offset..offset + 4)
+            .map_or(0, |v| u32::from_le_bytes(v.try_into().unwrap()))
+    }
+
+    async fn write_registers_u32(&mut self, offset: u16, val: u32) {
+        match offset {
+            CONFIG_SELECT => {
+                let [select, subsel, ..] = val.to_le_bytes();
+                self.select = select;
+                self.subsel = subsel;
+            }
+            _ => {
+                tracelimit::warn_ratelimited!(offset, val, "unexpected input config write");
+            }
+        }
+    }
+
+    async fn start_queue(
+        &mut self,
+        idx: u16,
+        resources: QueueResources,
+        features: &VirtioDeviceFeatures,
+        initial_state: Option<QueueState>,
+    ) -> anyhow::Result<()> {
+        let queue_event = PolledWait::new(&self.driver, resources.event)
+            .context("failed to create polled wait")?;
+        let queue = VirtioQueue::new(
+            *features,
+            resources.params,
+            resources.guest_memory.clone(),
+            resources.notify,
+            queue_event,
+            initial_state,
+        )
+        .context("failed to create virtio queue")?;
+
+        let state = InputQueue {
+            queue,
+            mem: resources.guest_memory,
+        };
+        match idx {
+            VIRTIO_INPUT_EVENT_QUEUE => {
+                // Ask for input now that the guest is listening.
+                self.event_worker.task_mut().source.set_active(true).await;
+                self.event_worker
+                    .insert(self.driver.clone(), "virtio-input-event", state);
+                self.event_worker.start();
+            }
+            VIRTIO_INPUT_STATUS_QUEUE => {
+                self.status_worker
+                    .insert(self.driver.clone(), "virtio-input-status", state);
+                self.status_worker.start();
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    async fn stop_queue(&mut self, idx: u16) -> Option<QueueState> {
+        match idx {
+            VIRTIO_INPUT_EVENT_QUEUE => stop_worker(&mut self.event_worker).await,
+            VIRTIO_INPUT_STATUS_QUEUE => stop_worker(&mut self.status_worker).await,
+            _ => unreachable!(),
+        }
+    }
+
+    async fn reset(&mut self) {
+        self.select = VIRTIO_INPUT_CFG_UNSET;
+        self.subsel = 0;
+        let worker = self.event_worker.task_mut();
+        worker.pending.clear();
+        worker.translator = Translator::new(self.kind);
+        worker.source.set_active(false).await;
+    }
+
+    fn supports_save_restore(&self) -> bool {
+        true
+    }
+}
+
+#[derive(InspectMut)]
+struct InputQueue {
+    queue: VirtioQueue,
+    mem: GuestMemory,
+}
+
+#[derive(InspectMut)]
+struct EventWorker {
+    #[inspect(skip)]
+    source: Source,
+    source_closed: bool,
+    translator: Translator,
+    /// Events waiting for guest buffers.
+    #[inspect(with = "VecDeque::len")]
+    pending: VecDeque<VirtioInputEvent>,
+}
+
+impl InspectTaskMut<InputQueue> for EventWorker {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>, state: Option<&mut InputQueue>) {
+        req.respond().merge(self).merge(state);
+    }
+}
+
+impl AsyncRun<InputQueue> for EventWorker {
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        state: &mut InputQueue,
+    ) -> Result<(), Cancelled> {
+        enum Event {
+            Input(Option<InputData>),
+            Buffer(Result<VirtioQueueCallbackWork, std::io::Error>),
+        }
+
+        loop {
+            let event = stop
+                .until_stopped(poll_fn(|cx| {
+                    // Only take buffers when there are events to put in them.
+                    if !self.pending.is_empty()
+                        && let Poll::Ready(item) = state.queue.poll_next_unpin(cx)
+                    {
+                        let item = item.expect("virtio queue stream never ends");
+                        return Poll::Ready(Event::Buffer(item));
+                    }
+                    if !self.source_closed
+                        && self.pending.len() < MAX_PENDING_EVENTS
+                        && let Poll::Ready(input) = self.source.poll_next(cx)
+                    {
+                        return Poll::Ready(Event::Input(input));
+                    }
+                    Poll::Pending
+                }))
+                .await?;
+
+            match event {
+                Event::Input(Some(input)) => self.translator.translate(input, &mut self.pending),
+                Event::Input(None) => self.source_closed = true,
+                Event::Buffer(Ok(work)) => {
+                    let event = self.pending.pop_front().unwrap();
+                    let len = match work.write(&state.mem, event.as_bytes()) {
+                        Ok(()) => size_of_val(&event) as u32,
+                        Err(err) => {
+                            tracelimit::warn_ratelimited!(
+                                error = &err as &dyn std::error::Error,
+                                "failed to write input event"
+                            );
+                            0
+                        }
+                    };
+                    state.queue.complete(work, len);
+                }
+                Event::Buffer(Err(err)) => {
+                    tracelimit::error_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        "queue error"
+                    );
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Translates input source data to evdev events.
+#[derive(Inspect)]
+struct Translator {
+    kind: InputKind,
+    /// The last reported pointer state.
+    #[inspect(hex)]
+    buttons: u8,
+    x: u16,
+    y: u16,
+}
+
+impl Translator {
+    fn new(kind: InputKind) -> Self {
+        Self {
+            kind,
+            buttons: 0,
+            x: 0,
+            y: 0,
+        }
+    }
+
+    /// Appends the events for `input` to `events`, followed by a
+    /// `SYN_REPORT`.
+    fn translate(&mut self, input: InputData, events: &mut VecDeque<VirtioInputEvent>) {
+        let start = events.len();
+        match input {
+            InputData::Keyboard(data) => {
+                if let Some(key) = scancode_to_key(data.code) {
+                    push_event(events, EV_KEY, key, data.make.into());
+                } else {
+                    tracelimit::warn_ratelimited!(code = data.code, "unsupported scan code");
+                }
+            }
+            InputData::Mouse(data) => self.pointer(data, events),
+        }
+        if events.len() != start {
+            push_event(events, EV_SYN, SYN_REPORT, 0);
+        }
+    }
+
+    fn pointer(&mut self, data: MouseData, events: &mut VecDeque<VirtioInputEvent>) {
+        // The buttons in the mask, as in the RFB protocol.
+        const BUTTONS: [(u8, u16); 3] = [(0x1, BTN_LEFT), (0x2, BTN_MIDDLE), (0x4, BTN_RIGHT)];
+        const WHEEL_UP: u8 = 0x8;
+        const WHEEL_DOWN: u8 = 0x10;
+
+        if self.kind == InputKind::Tablet {
+            if data.x != self.x {
+                push_event(events, EV_ABS, ABS_X, data.x.into());
+            }
+            if data.y != self.y {
+                push_event(events, EV_ABS, ABS_Y, data.y.into());
+            }
+        } else {
+            // Scale the positions rather than the difference, so that
+            // rounding errors don't accumulate.
+            let dx = i32::from(data.x / REL_SCALE) - i32::from(self.x / REL_SCALE);
+            let dy = i32::from(data.y / REL_SCALE) - i32::from(self.y / REL_SCALE);
+            if dx != 0 {
+                push_event(events, EV_REL, REL_X, dx as u32);
+            }
+            if dy != 0 {
+                push_event(events, EV_REL, REL_Y, dy as u32);
+            }
+        }
+
+        let changed = data.button_mask ^ self.buttons;
+        for (mask, button) in BUTTONS {
+            if changed & mask != 0 {
+                push_event(
+                    events,
+                    EV_KEY,
+                    button,
+                    (data.button_mask & mask != 0).into(),
+                );
+            }
+        }
+
+        // The wheel buttons are pressed and released for each step.
+        let pressed = changed & data.button_mask;
+        if pressed & WHEEL_UP != 0 {
+            push_event(events, EV_REL, REL_WHEEL, 1);
+        }
+        if pressed & WHEEL_DOWN != 0 {
+            push_event(events, EV_REL, REL_WHEEL, -1i32 as u32);
+        }
+
+        self.buttons = data.button_mask;
+        self.x = data.x;
+        self.y = data.y;
+    }
+}
+
+fn push_event(events: &mut VecDeque<VirtioInputEvent>, ty: u16, code: u16, value: u32) {
+    events.push_back(VirtioInputEvent {
+        ty: ty.into(),
+        code: code.into(),
+        value: value.into(),
+    });
+}
+
+/// Completes status queue buffers, which the guest uses to set the keyboard
+/// LEDs. There are no LEDs to set.
+#[derive(InspectMut)]
+struct StatusWorker;
+
+impl InspectTaskMut<InputQueue> for StatusWorker {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>, state: Option<&mut InputQueue>) {
+        req.respond().merge(state);
+    }
+}
+
+impl AsyncRun<InputQueue> for StatusWorker {
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        state: &mut InputQueue,
+    ) -> Result<(), Cancelled> {
+        loop {
+            let work = stop.until_stopped(state.queue.next()).await?;
+            let Some(work) = work else { break };
+            match work {
+                Ok(work) => {
+                    let mut event = VirtioInputEvent::new_zeroed();
+                    if work.read(&state.mem, event.as_mut_bytes()).is_ok() {
+                        tracing::trace!(
+                            ty = event.ty.get(),
+                            code = event.code.get(),
+                            value = event.value.get(),
+                            "input status event"
+                        );
+                    }
+                    state.queue.complete(work, 0);
+                }
+                Err(err) => {
+                    tracelimit::error_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        "queue error"
+                    );
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use input_core::mesh_input::input_pair;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use test_with_tracing::test;
+    use virtio::test_helpers::TestQueue;
+    use virtio::test_helpers::start_queue;
+    use vmcore::vm_task::SingleDriverBackend;
+    use zerocopy::FromBytes;
+
+    const QUEUE_SIZE: u16 = 16;
+    const DATA_BASE: u64 = 0x10000;
+    const TOTAL_MEM_SIZE: usize = 0x20000;
+
+    fn event(ty: u16, code: u16, value: u32) -> VirtioInputEvent {
+        VirtioInputEvent {
+            ty: ty.into(),
+            code: code.into(),
+            value: value.into(),
+        }
+    }
+
+    fn syn() -> VirtioInputEvent {
+        event(EV_SYN, SYN_REPORT, 0)
+    }
+
+    struct TestHarness {
+        device: VirtioInputDevice,
+        mem: GuestMemory,
+        driver: DefaultDriver,
+        queues: Vec<TestQueue>,
+    }
+
+    impl TestHarness {
+        fn new(
+            driver: &DefaultDriver,
+            f: impl FnOnce(&VmTaskDriverSource) -> VirtioInputDevice,
+        ) -> Self {
+            let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+            Self {
+                device: f(&driver_source),
+                mem: GuestMemory::allocate(TOTAL_MEM_SIZE),
+                driver: driver.clone(),
+                queues: Vec::new(),
+            }
+        }
+
+        async fn enable(&mut self) {
+            let features = VirtioDeviceFeatures::new();
+            for idx in 0..2 {
+                let queue =
+                    start_queue(&mut self.device, &self.mem, idx, QUEUE_SIZE, &features).await;
+                self.queues.push(queue);
+            }
+        }
+
+        /// Posts `count` event buffers.
+        fn post_buffers(&mut self, count: u16) {
+            let queue = &mut self.queues[VIRTIO_INPUT_EVENT_QUEUE as usize];
+            for _ in 0..count {
+                let index = queue.avail_idx % QUEUE_SIZE;
+                let gpa = DATA_BASE + index as u64 * size_of::<VirtioInputEvent>() as u64;
+                queue.post(
+                    &self.mem,
+                    index,
+                    &[(gpa, size_of::<VirtioInputEvent>() as u32, true)],
+                );
+            }
+        }
+
+        /// Waits for `count` events.
+        async fn read_events(&mut self, count: usize) -> Vec<VirtioInputEvent> {
+            let queue = &mut self.queues[VIRTIO_INPUT_EVENT_QUEUE as usize];
+            let mut events = Vec::new();
+            for _ in 0..count {
+                let (id, len) = queue.wait(&self.driver, &self.mem).await;
+                assert_eq!(len as usize, size_of::<VirtioInputEvent>());
+                events.push(
+                    self.mem
+                        .read_plain(DATA_BASE + id as u64 * size_of::<VirtioInputEvent>() as u64)
+                        .unwrap(),
+                );
+            }
+            events
+        }
+    }
+
+    async fn read_config(device: &mut VirtioInputDevice, select: u8, subsel: u8) -> Vec<u8> {
+        device
+            .write_registers_u32(CONFIG_SELECT, u32::from_le_bytes([select, subsel, 0, 0]))
+            .await;
+        let [_, _, size, _] = device.read_registers_u32(CONFIG_SELECT).await.to_le_bytes();
+        let mut data = Vec::new();
+        for offset in (0..size as u16).step_by(4) {
+            let offset = size_of::<VirtioInputConfigHeader>() as u16 + offset;
+            data.extend(device.read_registers_u32(offset).await.to_le_bytes());
+        }
+        data.truncate(size.into());
+        data
+    }
+
+    #[test]
+    fn scancodes() {
+        // KEY_A, make and break.
+        assert_eq!(scancode_to_key(0x1e), Some(30));
+        assert_eq!(scancode_to_key(0x9e), Some(30));
+        // KEY_F12.
+        assert_eq!(scancode_to_key(0x58), Some(88));
+        // KEY_UP.
+        assert_eq!(scancode_to_key(0xe048), Some(103));
+        assert_eq!(scancode_to_key(0x00), None);
+        assert_eq!(scancode_to_key(0xe001), None);
+    }
+
+    #[test]
+    fn relative_motion() {
+        let mut translator = Translator::new(InputKind::Mouse);
+        let mut events = VecDeque::new();
+        let mut translate = |button_mask, x, y| {
+            translator.translate(
+                InputData::Mouse(MouseData { button_mask, x, y }),
+                &mut events,
+            );
+            events.drain(..).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            translate(0, 2 * REL_SCALE, REL_SCALE - 1),
+            [event(EV_REL, REL_X, 2), syn()]
+        );
+        // Motion within a unit doesn't move the pointer.
+        assert!(translate(0, 2 * REL_SCALE, 0).is_empty());
+        assert_eq!(
+            translate(0x2, 0, REL_SCALE),
+            [
+                event(EV_REL, REL_X, -2i32 as u32),
+                event(EV_REL, REL_Y, 1),
+                event(EV_KEY, BTN_MIDDLE, 1),
+                syn()
+            ]
+        );
+        assert_eq!(
+            translate(0x10, 0, REL_SCALE),
+            [
+                event(EV_KEY, BTN_MIDDLE, 0),
+                event(EV_REL, REL_WHEEL, -1i32 as u32),
+                syn()
+            ]
+        );
+        // Holding the wheel button doesn't scroll again.
+        assert!(translate(0x10, 0, REL_SCALE).is_empty());
+    }
+
+    #[async_test]
+    async fn config(driver: DefaultDriver) {
+        let (source, _sink) = input_pair();
+        let mut harness = TestHarness::new(&driver, |driver_source| {
+            VirtioInputDevice::tablet(driver_source, Box::new(source))
+        });
+        let device = &mut harness.device;
+
+        assert_eq!(
+            read_config(device, VIRTIO_INPUT_CFG_ID_NAME, 0).await,
+            b"Virtio Tablet"
+        );
+        let ids = read_config(device, VIRTIO_INPUT_CFG_ID_DEVIDS, 0).await;
+        let ids = VirtioInputDevIds::read_from_bytes(&ids).unwrap();
+        assert_eq!(ids.bustype.get(), BUS_VIRTUAL);
+
+        let keys = read_config(device, VIRTIO_INPUT_CFG_EV_BITS, EV_KEY as u8).await;
+        assert_eq!(keys.len(), usize::from(BTN_MIDDLE / 8) + 1);
+        assert_eq!(keys[usize::from(BTN_LEFT / 8)], 0b111);
+        assert_eq!(
+            read_config(device, VIRTIO_INPUT_CFG_EV_BITS, EV_REL as u8).await,
+            [0, 1]
+        );
+        assert_eq!(
+            read_config(device, VIRTIO_INPUT_CFG_EV_BITS, EV_ABS as u8).await,
+            [0b11]
+        );
+        assert!(
+            read_config(device, VIRTIO_INPUT_CFG_EV_BITS, EV_REP as u8)
+                .await
+                .is_empty()
+        );
+
+        let abs = read_config(device, VIRTIO_INPUT_CFG_ABS_INFO, ABS_Y as u8).await;
+        let abs = VirtioInputAbsInfo::read_from_bytes(&abs).unwrap();
+        assert_eq!(abs.max.get(), 0x7fff);
+        assert!(
+            read_config(device, VIRTIO_INPUT_CFG_ABS_INFO, 0x10)
+                .await
+                .is_empty()
+        );
+        assert!(
+            read_config(device, VIRTIO_INPUT_CFG_ID_SERIAL, 0)
+                .await
+                .is_empty()
+        );
+    }
+
+    #[async_test]
+    async fn keyboard(driver: DefaultDriver) {
+        let (source, mut sink) = input_pair();
+        let mut harness = TestHarness::new(&driver, |driver_source| {
+            VirtioInputDevice::keyboard(driver_source, Box::new(source))
+        });
+        assert!(!sink.is_active());
+        harness.enable().await;
+        assert!(sink.is_active());
+
+        harness.post_buffers(4);
+        sink.send(KeyboardData {
+            code: 0x1e,
+            make: true,
+        });
+        // Unknown scan codes are dropped.
+        sink.send(KeyboardData {
+            code: 0xe001,
+            make: true,
+        });
+        sink.send(KeyboardData {
+            code: 0xe048,
+            make: false,
+        });
+        assert_eq!(
+            harness.read_events(4).await,
+            [event(EV_KEY, 30, 1), syn(), event(EV_KEY, 103, 0), syn()]
+        );
+
+        // Events wait for buffers.
+        sink.send(KeyboardData {
+            code: 0x1e,
+            make: false,
+        });
+        harness.post_buffers(2);
+        assert_eq!(harness.read_events(2).await, [event(EV_KEY, 30, 0), syn()]);
+
+        for idx in 0..2 {
+            harness.device.stop_queue(idx).await.unwrap();
+        }
+        harness.device.reset().await;
+        assert!(!sink.is_active());
+    }
+
+    #[async_test]
+    async fn tablet(driver: DefaultDriver) {
+        let (source, mut sink) = input_pair();
+        let mut harness = TestHarness::new(&driver, |driver_source| {
+            VirtioInputDevice::tablet(driver_source, Box::new(source))
+        });
+        harness.enable().await;
+
+        harness.post_buffers(7);
+        sink.send(MouseData {
+            button_mask: 0x1,
+            x: 100,
+            y: 200,
+        });
+        sink.send(MouseData {
+            button_mask: 0x8,
+            x: 100,
+            y: 200,
+        });
+        assert_eq!(
+            harness.read_events(7).await,
+            [
+                event(EV_ABS, ABS_X, 100),
+                event(EV_ABS, ABS_Y, 200),
+                event(EV_KEY, BTN_LEFT, 1),
+                syn(),
+                event(EV_KEY, BTN_LEFT, 0),
+                event(EV_REL, REL_WHEEL, 1),
+                syn(),
+            ]
+        );
+    }
+}
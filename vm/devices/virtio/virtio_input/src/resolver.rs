@@ -0,0 +1,84 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Defines the resource resolver for virtio-input devices.
+
+use crate::VirtioInputDevice;
+use anyhow::Context as _;
+use async_trait::async_trait;
+use virtio::resolve::ResolvedVirtioDevice;
+use virtio::resolve::VirtioResolveInput;
+use virtio_resources::input::VirtioKeyboardHandle;
+use virtio_resources::input::VirtioMouseHandle;
+use virtio_resources::input::VirtioTabletHandle;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::VirtioDeviceHandle;
+
+/// Resolver for virtio-input devices.
+pub struct VirtioInputResolver;
+
+declare_static_async_resolver! {
+    VirtioInputResolver,
+    (VirtioDeviceHandle, VirtioKeyboardHandle),
+    (VirtioDeviceHandle, VirtioMouseHandle),
+    (VirtioDeviceHandle, VirtioTabletHandle),
+}
+
+#[async_trait]
+impl AsyncResolveResource<VirtioDeviceHandle, VirtioKeyboardHandle> for VirtioInputResolver {
+    type Output = ResolvedVirtioDevice;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: VirtioKeyboardHandle,
+        input: VirtioResolveInput<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let source = resolver
+            .resolve(resource.source, "virtio-keyboard")
+            .await
+            .context("failed to resolve input source")?;
+        Ok(VirtioInputDevice::keyboard(input.driver_source, source.0).into())
+    }
+}
+
+#[async_trait]
+impl AsyncResolveResource<VirtioDeviceHandle, VirtioMouseHandle> for VirtioInputResolver {
+    type Output = ResolvedVirtioDevice;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: VirtioMouseHandle,
+        input: VirtioResolveInput<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let source = resolver
+            .resolve(resource.source, "virtio-mouse")
+            .await
+            .context("failed to resolve input source")?;
+        Ok(VirtioInputDevice::mouse(input.driver_source, source.0).into())
+    }
+}
+
+#[async_trait]
+impl AsyncResolveResource<VirtioDeviceHandle, VirtioTabletHandle> for VirtioInputResolver {
+    type Output = ResolvedVirtioDevice;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: VirtioTabletHandle,
+        input: VirtioResolveInput<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let source = resolver
+            .resolve(resource.source, "virtio-tablet")
+            .await
+            .context("failed to resolve input source")?;
+        Ok(VirtioInputDevice::tablet(input.driver_source, source.0).into())
+    }
+}
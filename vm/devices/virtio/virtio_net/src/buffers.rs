@@ -3,13 +3,15 @@
 
 use crate::VirtioNetHeader;
 use crate::VirtioNetHeaderFlags;
-use crate::header_size;
+use crate::rss::HASH_INPUT_LEN;
+use crate::rss::HashConfig;
 use guestmem::GuestMemory;
 use inspect::Inspect;
 use net_backend::BufferAccess;
 use net_backend::RxBufferSegment;
 use net_backend::RxId;
 use net_backend::RxMetadata;
+use std::mem::offset_of;
 use virtio::VirtioQueueCallbackWork;
 use zerocopy::FromZeros;
 use zerocopy::IntoBytes;
@@ -25,6 +27,7 @@ struct RxPacket {
 #[inspect(extra = "Self::inspect_extra")]
 pub struct VirtioWorkPool {
     mem: GuestMemory,
+    header_len: usize,
     #[inspect(skip)]
     rx_packets: Vec<Option<RxPacket>>,
 }
@@ -37,10 +40,12 @@ fn inspect_extra(&self, resp: &mut inspect::Response<'_>) {
         );
     }
 
-    /// Create a new instance.
-    pub fn new(mem: GuestMemory, queue_size: u16) -> Self {
+    /// Create a new instance. `header_len` is the size of the virtio-net
+    /// header that precedes each packet.
+    pub fn new(mem: GuestMemory, queue_size: u16, header_len: usize) -> Self {
         Self {
             mem,
+            header_len,
             rx_packets: (0..queue_size).map(|_| None).collect(),
         }
     }
@@ -84,7 +89,7 @@ pub fn queue_work(
             return Err(work);
         }
         let payload_length = work.get_payload_length(true) as u32;
-        let Some(cap) = payload_length.checked_sub(header_size() as u32) else {
+        let Some(cap) = payload_length.checked_sub(self.header_len as u32) else {
             tracelimit::warn_ratelimited!(
                 len = payload_length,
                 "dropping RX buffer: payload length smaller than virtio-net header size"
@@ -108,10 +113,65 @@ pub fn take_rx_work(&mut self, rx_id: RxId) -> (VirtioQueueCallbackWork, u32) {
             tracelimit::warn_ratelimited!("dropping RX buffer: header not written");
             0
         } else {
-            packet.len + header_size() as u32
+            packet.len + self.header_len as u32
         };
         (packet.work, payload_len)
     }
+
+    /// Computes the hash of a received packet and writes it to the packet's
+    /// virtio-net header. Must be called after the endpoint has written the
+    /// header and data.
+    pub fn report_hash(&mut self, rx_id: RxId, hash_config: &HashConfig) {
+        let Some(packet) = &self.rx_packets[rx_id.0 as usize] else {
+            return;
+        };
+        if packet.len == 0 {
+            return;
+        }
+
+        // Read back the start of the packet from the writeable buffers.
+        let mut data = [0; HASH_INPUT_LEN];
+        let data = &mut data[..HASH_INPUT_LEN.min(packet.len as usize)];
+        let mut offset = self.header_len as u64;
+        let mut read = 0;
+        for p in packet.work.payload.iter().filter(|p| p.writeable) {
+            if offset >= p.length as u64 {
+                offset -= p.length as u64;
+                continue;
+            }
+            let len = ((p.length as u64 - offset) as usize).min(data.len() - read);
+            if let Err(err) = self
+                .mem
+                .read_at(p.address + offset, &mut data[read..read + len])
+            {
+                tracelimit::warn_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    "failure reading rx packet for hashing"
+                );
+                return;
+            }
+            read += len;
+            offset = 0;
+            if read == data.len() {
+                break;
+            }
+        }
+
+        let (hash_value, hash_report) = hash_config.hash_packet(&data[..read]);
+        let mut hash = [0; 8];
+        hash[..4].copy_from_slice(&hash_value.to_le_bytes());
+        hash[4..6].copy_from_slice(&hash_report.0.to_le_bytes());
+        if let Err(err) = packet.work.write_at_offset(
+            offset_of!(VirtioNetHeader, hash_value) as u64,
+            &self.mem,
+            &hash,
+        ) {
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "failure writing rx hash"
+            );
+        }
+    }
 }
 
 impl BufferAccess for VirtioWorkPool {
@@ -125,7 +185,7 @@ fn write_data(&mut self, id: RxId, data: &[u8]) {
             .expect("invalid buffer index");
         if let Err(err) = packet
             .work
-            .write_at_offset(header_size() as u64, &self.mem, data)
+            .write_at_offset(self.header_len as u64, &self.mem, data)
         {
             tracelimit::warn_ratelimited!(
                 len = data.len(),
@@ -180,7 +240,7 @@ fn write_header(&mut self, id: RxId, metadata: &RxMetadata) {
             .expect("invalid buffer index");
         if let Err(err) = packet
             .work
-            .write(&self.mem, &virtio_net_header.as_bytes()[..header_size()])
+            .write(&self.mem, &virtio_net_header.as_bytes()[..self.header_len])
         {
             tracelimit::warn_ratelimited!(
                 error = &err as &dyn std::error::Error,
@@ -0,0 +1,158 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Parsing of virtio-net control queue commands.
+
+use crate::rss::HashConfig;
+use crate::rss::RSS_MAX_KEY_SIZE;
+use crate::rss::RssState;
+use crate::rss::SUPPORTED_HASH_TYPES;
+use thiserror::Error;
+
+// These correspond to VIRTIO_NET_CTRL_ classes and commands.
+const VIRTIO_NET_CTRL_MQ: u8 = 4;
+const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET: u8 = 0;
+const VIRTIO_NET_CTRL_MQ_RSS_CONFIG: u8 = 1;
+const VIRTIO_NET_CTRL_MQ_HASH_CONFIG: u8 = 2;
+
+/// The acknowledgement values written back to the guest.
+pub const VIRTIO_NET_OK: u8 = 0;
+pub const VIRTIO_NET_ERR: u8 = 1;
+
+/// The largest command the device will read from the guest. The largest
+/// supported command is `RSS_CONFIG` with a full indirection table and key.
+pub const MAX_COMMAND_SIZE: usize = 1024;
+
+/// A parsed control queue command.
+#[derive(Debug)]
+pub enum Command {
+    SetQueuePairs(u16),
+    RssConfig(RssState),
+    HashConfig(HashConfig),
+}
+
+/// The negotiated state that determines which commands are valid.
+pub struct CommandLimits {
+    pub mq: bool,
+    pub rss: bool,
+    pub hash_report: bool,
+    pub max_queue_pairs: u16,
+    pub max_indirection_table_length: u16,
+}
+
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error("unsupported command class {0} command {1}")]
+    Unsupported(u8, u8),
+    #[error("command truncated")]
+    Truncated,
+    #[error("invalid queue pair count {0}")]
+    InvalidQueuePairs(u16),
+    #[error("invalid indirection table length {0}")]
+    InvalidTableLength(u32),
+    #[error("queue pair index {0} out of range")]
+    InvalidQueue(u16),
+    #[error("key length {0} exceeds the maximum")]
+    KeyTooLong(u8),
+    #[error("unsupported hash types {0:#x}")]
+    UnsupportedHashTypes(u32),
+}
+
+/// Reads little-endian fields from a command buffer.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], CommandError> {
+        if self.0.len() < n {
+            return Err(CommandError::Truncated);
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, CommandError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, CommandError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, CommandError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+}
+
+/// Parses the readable portion of a control queue request.
+pub fn parse_command(data: &[u8], limits: &CommandLimits) -> Result<Command, CommandError> {
+    let mut reader = Reader(data);
+    let class = reader.u8()?;
+    let command = reader.u8()?;
+    let command = match (class, command) {
+        (VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET) if limits.mq => {
+            let pairs = reader.u16()?;
+            if pairs == 0 || pairs > limits.max_queue_pairs {
+                return Err(CommandError::InvalidQueuePairs(pairs));
+            }
+            Command::SetQueuePairs(pairs)
+        }
+        (VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_RSS_CONFIG) if limits.rss => {
+            let hash_types = read_hash_types(&mut reader)?;
+            let table_len = reader.u16()? as u32 + 1;
+            if !table_len.is_power_of_two()
+                || table_len > limits.max_indirection_table_length as u32
+            {
+                return Err(CommandError::InvalidTableLength(table_len));
+            }
+            let unclassified_queue = read_queue_index(&mut reader, limits)?;
+            let indirection_table = (0..table_len)
+                .map(|_| read_queue_index(&mut reader, limits))
+                .collect::<Result<Vec<_>, _>>()?;
+            let max_tx_vq = reader.u16()?;
+            if max_tx_vq == 0 || max_tx_vq > limits.max_queue_pairs {
+                return Err(CommandError::InvalidQueuePairs(max_tx_vq));
+            }
+            let key = read_key(&mut reader)?;
+            Command::RssConfig(RssState {
+                hash: HashConfig { hash_types, key },
+                indirection_table,
+                unclassified_queue,
+                max_tx_vq,
+            })
+        }
+        (VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_HASH_CONFIG) if limits.hash_report => {
+            let hash_types = read_hash_types(&mut reader)?;
+            // Skip the reserved fields.
+            reader.bytes(8)?;
+            let key = read_key(&mut reader)?;
+            Command::HashConfig(HashConfig { hash_types, key })
+        }
+        (class, command) => return Err(CommandError::Unsupported(class, command)),
+    };
+    Ok(command)
+}
+
+fn read_hash_types(reader: &mut Reader<'_>) -> Result<u32, CommandError> {
+    let hash_types = reader.u32()?;
+    if hash_types & !u32::from(SUPPORTED_HASH_TYPES) != 0 {
+        return Err(CommandError::UnsupportedHashTypes(hash_types));
+    }
+    Ok(hash_types)
+}
+
+fn read_queue_index(reader: &mut Reader<'_>, limits: &CommandLimits) -> Result<u16, CommandError> {
+    let index = reader.u16()?;
+    if index >= limits.max_queue_pairs {
+        return Err(CommandError::InvalidQueue(index));
+    }
+    Ok(index)
+}
+
+fn read_key(reader: &mut Reader<'_>) -> Result<Vec<u8>, CommandError> {
+    let key_len = reader.u8()?;
+    if key_len > RSS_MAX_KEY_SIZE {
+        return Err(CommandError::KeyTooLong(key_len));
+    }
+    Ok(reader.bytes(key_len.into())?.to_vec())
+}
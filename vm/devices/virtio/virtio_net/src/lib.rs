@@ -4,20 +4,31 @@
 //! Virtio network device implementation.
 //!
 //! This crate implements a virtio-net device that connects a guest's virtual
-//! NIC to a pluggable [`net_backend::Endpoint`]. It currently operates with a
-//! single queue pair (one RX, one TX) and supports synchronous and asynchronous
-//! TX completion modes depending on the backend.
+//! NIC to a pluggable [`net_backend::Endpoint`]. When the endpoint supports
+//! multiple queues, the device offers `VIRTIO_NET_F_MQ` and spreads the guest's
+//! queue pairs across endpoint queues, steering receives with the guest's RSS
+//! indirection table when `VIRTIO_NET_F_RSS` is negotiated. It supports
+//! synchronous and asynchronous TX completion modes depending on the backend.
 
 #![expect(missing_docs)]
 #![forbid(unsafe_code)]
 
 mod buffers;
+mod control;
 pub mod resolver;
+mod rss;
 
 #[cfg(test)]
 mod tests;
 
 use crate::buffers::VirtioWorkPool;
+use crate::control::Command;
+use crate::control::CommandLimits;
+use crate::rss::HashConfig;
+use crate::rss::RSS_MAX_INDIRECTION_TABLE_LENGTH;
+use crate::rss::RSS_MAX_KEY_SIZE;
+use crate::rss::RssState;
+use crate::rss::SUPPORTED_HASH_TYPES;
 use anyhow::Context as _;
 use bitfield_struct::bitfield;
 use guestmem::GuestMemory;
@@ -28,6 +39,7 @@
 use net_backend::Endpoint;
 use net_backend::EndpointAction;
 use net_backend::QueueConfig;
+use net_backend::RssConfig;
 use net_backend::RxId;
 use net_backend::TxFlags;
 use net_backend::TxId;
@@ -138,8 +150,9 @@ struct NetStatus {
 
 const DEFAULT_MTU: u16 = 1514;
 
-#[expect(dead_code)]
-const VIRTIO_NET_MAX_QUEUES: u16 = 0x8000;
+/// The maximum number of queue pairs offered to the guest, regardless of how
+/// many queues the endpoint supports.
+const MAX_QUEUE_PAIRS: u16 = 64;
 
 #[repr(C)]
 struct NetConfig {
@@ -212,14 +225,20 @@ struct VirtioNetHeader {
     pub padding_reserved: u16, // Only if VIRTIO_NET_F_HASH_REPORT negotiated
 }
 
-const fn header_size() -> usize {
-    // TODO: Verify hash flags are not set, since header size would be larger in that case.
-    offset_of!(VirtioNetHeader, hash_value)
+/// Returns the size of the virtio-net header used for both RX and TX, which
+/// includes the hash fields only if `VIRTIO_NET_F_HASH_REPORT` was negotiated.
+const fn header_size(hash_report: bool) -> usize {
+    if hash_report {
+        size_of::<VirtioNetHeader>()
+    } else {
+        offset_of!(VirtioNetHeader, hash_value)
+    }
 }
 
 struct Adapter {
     driver: VmTaskDriver,
     max_queue_pairs: u16,
+    indirection_table_size: u16,
     tx_fast_completions: bool,
     mac_address: MacAddress,
     tx_offload_support: TxOffloadSupport,
@@ -232,6 +251,11 @@ pub struct Device {
     driver_source: VmTaskDriverSource,
     /// Per-pair state tracking.
     pairs: Vec<QueuePairState>,
+    /// The index of the control queue, if `VIRTIO_NET_F_CTRL_VQ` was
+    /// negotiated.
+    ctrl_queue_index: Option<u16>,
+    /// The control queue, held until the coordinator is created.
+    pending_ctrl_queue: Option<ControlQueue>,
 }
 
 /// Tracks the state of a queue pair through the start_queue lifecycle.
@@ -264,14 +288,24 @@ fn traits(&self) -> DeviceTraits {
         // Linux kernels.
         let host_uso = offloads.uso && offloads.udp;
 
+        // VIRTIO_NET_F_MQ/RSS: the endpoint has more than one queue, and for
+        // RSS, accepts an indirection table.
+        let mq = self.registers.max_virtqueue_pairs > 1;
+        let rss = self.registers.rss_max_indirection_table_length != 0;
+
         let features_bank0 = NetworkFeaturesBank0::new()
             .with_mac(true)
             .with_csum(csum)
             .with_guest_csum(true)
             .with_host_tso4(host_tso)
-            .with_host_tso6(host_tso);
+            .with_host_tso6(host_tso)
+            .with_ctrl_vq(true)
+            .with_mq(mq);
 
-        let features_bank1 = NetworkFeaturesBank1::new().with_host_uso(host_uso);
+        let features_bank1 = NetworkFeaturesBank1::new()
+            .with_host_uso(host_uso)
+            .with_hash_report(true)
+            .with_rss(rss);
 
         DeviceTraits {
             device_id: virtio::spec::VirtioDeviceType::NET,
@@ -281,7 +315,8 @@ fn traits(&self) -> DeviceTraits {
                 .with_ring_event_idx(true)
                 .with_ring_indirect_desc(true)
                 .with_ring_packed(true),
-            max_queues: 2 * self.registers.max_virtqueue_pairs,
+            // Each queue pair, plus the control queue.
+            max_queues: 2 * self.registers.max_virtqueue_pairs + 1,
             device_register_length: size_of::<NetConfig>() as u32,
             shared_memory: DeviceTraitsSharedMemory { id: 0, size: 0 },
         }
@@ -331,8 +366,30 @@ async fn start_queue(
 
         let negotiated_features = NetworkFeaturesBank0::from(features.bank(0));
         let negotiated_features_bank1 = NetworkFeaturesBank1::from(features.bank(1));
+
+        if negotiated_features.ctrl_vq() {
+            // The control queue follows the last queue pair.
+            let ctrl_queue_index = if negotiated_features.mq() {
+                2 * self.adapter.max_queue_pairs
+            } else {
+                2
+            };
+            self.ctrl_queue_index = Some(ctrl_queue_index);
+            if idx == ctrl_queue_index {
+                self.set_control_queue(Some(ControlQueue {
+                    queue,
+                    mem: guest_memory,
+                }))
+                .await;
+                return Ok(());
+            }
+        }
+
         let pair_idx = (idx / 2) as usize;
         let is_rx = idx.is_multiple_of(2);
+        if pair_idx >= self.pairs.len() {
+            anyhow::bail!("invalid queue index {idx}");
+        }
 
         match &self.pairs[pair_idx] {
             QueuePairState::Empty => {
@@ -355,11 +412,6 @@ async fn start_queue(
                 }
 
                 // Second queue — extract the first, form the pair.
-                let first_pair = !self
-                    .pairs
-                    .iter()
-                    .any(|p| matches!(p, QueuePairState::Active));
-
                 let prev = std::mem::replace(&mut self.pairs[pair_idx], QueuePairState::Active);
                 let QueuePairState::HalfOpen {
                     queue: pending_queue,
@@ -376,8 +428,13 @@ async fn start_queue(
                     (queue, queue_size, pending_queue, pending_queue_size)
                 };
 
-                if first_pair {
-                    self.insert_coordinator(self.pairs.len() as u16);
+                if self.coordinator.has_state() {
+                    // Add the worker to the running coordinator, which will
+                    // restart the endpoint queues to include it.
+                    self.coordinator.stop().await;
+                    self.coordinator.state_mut().unwrap().restart = true;
+                } else {
+                    self.insert_coordinator(negotiated_features, negotiated_features_bank1);
                 }
 
                 let virtio_state = VirtioState {
@@ -394,9 +451,7 @@ async fn start_queue(
                     negotiated_features_bank1,
                 );
 
-                if first_pair {
-                    self.coordinator.start();
-                }
+                self.coordinator.start();
             }
             QueuePairState::Active => {
                 anyhow::bail!("queue pair {pair_idx} already active");
@@ -408,7 +463,9 @@ async fn start_queue(
     async fn stop_queue(&mut self, idx: u16) -> Option<QueueState> {
         let pair_idx = (idx / 2) as usize;
 
-        if pair_idx < self.pairs.len() {
+        if self.ctrl_queue_index == Some(idx) {
+            self.set_control_queue(None).await;
+        } else if pair_idx < self.pairs.len() {
             if let QueuePairState::HalfOpen { is_rx, .. } = self.pairs[pair_idx] {
                 let stopping_rx = idx.is_multiple_of(2);
                 if is_rx != stopping_rx {
@@ -419,15 +476,22 @@ async fn stop_queue(&mut self, idx: u16) -> Option<QueueState> {
                 // Drop the pending half-open queue.
                 self.pairs[pair_idx] = QueuePairState::Empty;
             } else if matches!(self.pairs[pair_idx], QueuePairState::Active) {
-                // Stop the coordinator (which stops all workers).
+                // Stop the coordinator and all workers. The endpoint queues
+                // are shared by all pairs, so every active pair is torn down.
                 self.coordinator.stop().await;
                 if let Some(coordinator) = self.coordinator.state_mut() {
                     for worker in &mut coordinator.workers {
                         worker.stop().await;
                     }
                 }
-                let _ = self.coordinator.remove();
-                self.pairs[pair_idx] = QueuePairState::Empty;
+                if self.coordinator.has_state() {
+                    let _ = self.coordinator.remove();
+                }
+                for pair in &mut self.pairs {
+                    if matches!(pair, QueuePairState::Active) {
+                        *pair = QueuePairState::Empty;
+                    }
+                }
             }
         }
 
@@ -437,6 +501,8 @@ async fn stop_queue(&mut self, idx: u16) -> Option<QueueState> {
 
     async fn reset(&mut self) {
         self.pairs.fill_with(|| QueuePairState::Empty);
+        self.ctrl_queue_index = None;
+        self.pending_ctrl_queue = None;
     }
 
     fn supports_save_restore(&self) -> bool {
@@ -505,10 +571,10 @@ struct ActiveState {
 }
 
 impl ActiveState {
-    fn new(mem: GuestMemory, rx_queue_size: u16, tx_queue_size: u16) -> Self {
+    fn new(mem: GuestMemory, rx_queue_size: u16, tx_queue_size: u16, header_len: usize) -> Self {
         Self {
             pending_tx_packets: (0..tx_queue_size).map(|_| None).collect(),
-            pending_rx_packets: VirtioWorkPool::new(mem, rx_queue_size),
+            pending_rx_packets: VirtioWorkPool::new(mem, rx_queue_size, header_len),
             data: ProcessingData::new(rx_queue_size, tx_queue_size),
             stats: Default::default(),
         }
@@ -537,16 +603,25 @@ pub fn build(
         endpoint: Box<dyn Endpoint>,
         mac_address: MacAddress,
     ) -> Device {
-        // TODO: Implement VIRTIO_NET_F_MQ and VIRTIO_NET_F_RSS logic based on mulitqueue support.
-        // let multiqueue = endpoint.multiqueue_support();
-        // let max_queue_pairs = self.max_queue_pairs.clamp(1, multiqueue.max_queues.min(VIRTIO_NET_MAX_QUEUES));
-        let max_queue_pairs = 1;
+        let multiqueue = endpoint.multiqueue_support();
+        let max_queue_pairs = self
+            .max_queue_pairs
+            .min(multiqueue.max_queues)
+            .clamp(1, MAX_QUEUE_PAIRS);
+        // Only offer RSS if there are multiple queues to steer to and the
+        // endpoint accepts an indirection table.
+        let indirection_table_size = if max_queue_pairs > 1 {
+            multiqueue.indirection_table_size
+        } else {
+            0
+        };
 
         let driver = driver_source.simple();
         let tx_offload_support = endpoint.tx_offload_support();
         let adapter = Arc::new(Adapter {
             driver,
             max_queue_pairs,
+            indirection_table_size,
             tx_fast_completions: endpoint.tx_fast_completions(),
             mac_address,
             tx_offload_support,
@@ -564,9 +639,13 @@ pub fn build(
             mtu: DEFAULT_MTU,
             speed: 0xffffffff,
             duplex: 0xff,
-            rss_max_key_size: 0,
-            rss_max_indirection_table_length: 0,
-            supported_hash_types: 0,
+            rss_max_key_size: RSS_MAX_KEY_SIZE,
+            rss_max_indirection_table_length: if indirection_table_size != 0 {
+                RSS_MAX_INDIRECTION_TABLE_LENGTH
+            } else {
+                0
+            },
+            supported_hash_types: SUPPORTED_HASH_TYPES.into(),
         };
 
         Device {
@@ -577,6 +656,8 @@ pub fn build(
             pairs: (0..max_queue_pairs)
                 .map(|_| QueuePairState::Empty)
                 .collect(),
+            ctrl_queue_index: None,
+            pending_ctrl_queue: None,
         }
     }
 }
@@ -596,7 +677,11 @@ fn inspect_mut(&mut self, req: inspect::Request<'_>) {
 }
 
 impl Device {
-    fn insert_coordinator(&mut self, num_queues: u16) {
+    fn insert_coordinator(
+        &mut self,
+        negotiated_features: NetworkFeaturesBank0,
+        negotiated_features_bank1: NetworkFeaturesBank1,
+    ) {
         self.coordinator.insert(
             &self.adapter.driver,
             "virtio-net-coordinator".to_string(),
@@ -604,12 +689,28 @@ fn insert_coordinator(&mut self, num_queues: u16) {
                 workers: (0..self.adapter.max_queue_pairs)
                     .map(|_| TaskControl::new(NetQueue { state: None }))
                     .collect(),
-                num_queues,
+                num_queues: 0,
                 restart: true,
+                ctrl_queue: self.pending_ctrl_queue.take(),
+                steering: SteeringState::default(),
+                negotiated_features,
+                negotiated_features_bank1,
             },
         );
     }
 
+    /// Sets or clears the control queue, handing it to the coordinator if
+    /// there is one.
+    async fn set_control_queue(&mut self, ctrl_queue: Option<ControlQueue>) {
+        if self.coordinator.has_state() {
+            self.coordinator.stop().await;
+            self.coordinator.state_mut().unwrap().ctrl_queue = ctrl_queue;
+            self.coordinator.start();
+        } else {
+            self.pending_ctrl_queue = ctrl_queue;
+        }
+    }
+
     /// Allocates and inserts a worker.
     ///
     /// The coordinator must be stopped.
@@ -631,14 +732,18 @@ fn insert_worker(
         builder.run_on_target(!self.adapter.tx_fast_completions);
         let driver = builder.build("virtio-net");
 
+        let header_len = header_size(negotiated_features_bank1.hash_report());
         let active_state = ActiveState::new(
             guest_memory.clone(),
             virtio_state.rx_queue_size,
             virtio_state.tx_queue_size,
+            header_len,
         );
         let worker = Worker {
             virtio_state,
             active_state,
+            header_len,
+            hash_config: None,
             negotiated_features,
             negotiated_features_bank1,
         };
@@ -651,8 +756,38 @@ fn insert_worker(
 
 struct Coordinator {
     workers: Vec<TaskControl<NetQueue, Worker>>,
+    /// The number of queue pairs currently backed by endpoint queues.
     num_queues: u16,
     restart: bool,
+    ctrl_queue: Option<ControlQueue>,
+    steering: SteeringState,
+    negotiated_features: NetworkFeaturesBank0,
+    negotiated_features_bank1: NetworkFeaturesBank1,
+}
+
+struct ControlQueue {
+    queue: VirtioQueue,
+    mem: GuestMemory,
+}
+
+/// The queue steering configuration set via the control queue.
+#[derive(Inspect)]
+struct SteeringState {
+    /// The queue pair count set by `VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET`.
+    queue_pairs: u16,
+    rss: Option<RssState>,
+    hash: Option<HashConfig>,
+}
+
+impl Default for SteeringState {
+    fn default() -> Self {
+        // Until the guest configures multiqueue, only the first pair is used.
+        Self {
+            queue_pairs: 1,
+            rss: None,
+            hash: None,
+        }
+    }
 }
 
 struct CoordinatorState {
@@ -676,6 +811,7 @@ fn inspect_mut(&mut self, req: inspect::Request<'_>, coordinator: Option<&mut Co
             .field_mut("endpoint", self.endpoint.as_mut());
 
         if let Some(coordinator) = coordinator {
+            resp.field("steering", &coordinator.steering);
             resp.fields_mut(
                 "queues",
                 coordinator.workers[..coordinator.num_queues as usize]
@@ -703,6 +839,7 @@ async fn process(
         state: &mut CoordinatorState,
     ) -> Result<(), task_control::Cancelled> {
         loop {
+            self.process_control_queue();
             if self.restart {
                 stop.until_stopped(self.stop_workers()).await?;
                 // The queue restart operation is not restartable, so do not
@@ -716,18 +853,99 @@ async fn process(
                 self.restart = false;
             }
             self.start_workers();
-            match stop
-                .until_stopped(state.endpoint.wait_for_endpoint_action())
-                .await?
-            {
-                EndpointAction::RestartRequired => self.restart = true,
-                EndpointAction::LinkStatusNotify(_) => {
+            let mut endpoint_action = std::pin::pin!(state.endpoint.wait_for_endpoint_action());
+            let ctrl_queue = &mut self.ctrl_queue;
+            let action = stop
+                .until_stopped(std::future::poll_fn(|cx| {
+                    if let Poll::Ready(action) = endpoint_action.as_mut().poll(cx) {
+                        return Poll::Ready(Some(action));
+                    }
+                    if let Some(ctrl_queue) = ctrl_queue
+                        && let Poll::Ready(()) = ctrl_queue.queue.poll_kick(cx)
+                    {
+                        return Poll::Ready(None);
+                    }
+                    Poll::Pending
+                }))
+                .await?;
+            match action {
+                Some(EndpointAction::RestartRequired) => self.restart = true,
+                Some(EndpointAction::LinkStatusNotify(_)) => {
                     tracing::error!("unexpected link status notification")
                 }
+                // Control queue commands are processed at the top of the loop.
+                None => {}
             }
         }
     }
 
+    /// Processes any pending control queue commands, flagging a queue restart
+    /// if the steering configuration changed.
+    fn process_control_queue(&mut self) {
+        let Some(ctrl_queue) = &mut self.ctrl_queue else {
+            return;
+        };
+        let limits = CommandLimits {
+            mq: self.negotiated_features.mq(),
+            rss: self.negotiated_features_bank1.rss(),
+            hash_report: self.negotiated_features_bank1.hash_report(),
+            max_queue_pairs: self.workers.len() as u16,
+            max_indirection_table_length: RSS_MAX_INDIRECTION_TABLE_LENGTH,
+        };
+        loop {
+            let work = match ctrl_queue.queue.try_next() {
+                Ok(Some(work)) => work,
+                Ok(None) => break,
+                Err(err) => {
+                    tracelimit::error_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        "control queue failure"
+                    );
+                    break;
+                }
+            };
+            let mut data = vec![
+                0;
+                work.get_payload_length(false)
+                    .min(control::MAX_COMMAND_SIZE as u64) as usize
+            ];
+            let ack = match work
+                .read(&ctrl_queue.mem, &mut data)
+                .map_err(anyhow::Error::from)
+                .and_then(|n| Ok(control::parse_command(&data[..n], &limits)?))
+            {
+                Ok(command) => {
+                    tracing::debug!(?command, "virtio-net control command");
+                    match command {
+                        Command::SetQueuePairs(pairs) => self.steering.queue_pairs = pairs,
+                        Command::RssConfig(rss) => self.steering.rss = Some(rss),
+                        Command::HashConfig(hash) => self.steering.hash = Some(hash),
+                    }
+                    self.restart = true;
+                    control::VIRTIO_NET_OK
+                }
+                Err(err) => {
+                    tracelimit::warn_ratelimited!(
+                        error = err.as_ref() as &dyn std::error::Error,
+                        "failed virtio-net control command"
+                    );
+                    control::VIRTIO_NET_ERR
+                }
+            };
+            let bytes_written = match work.write(&ctrl_queue.mem, &[ack]) {
+                Ok(()) => 1,
+                Err(err) => {
+                    tracelimit::warn_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        "failed to write control command ack"
+                    );
+                    0
+                }
+            };
+            ctrl_queue.queue.complete(work, bytes_written);
+        }
+    }
+
     async fn stop_workers(&mut self) {
         for worker in &mut self.workers {
             worker.stop().await;
@@ -739,21 +957,76 @@ async fn restart_queues(&mut self, c_state: &mut CoordinatorState) -> Result<(),
         for worker in &mut self.workers {
             worker.task_mut().state = None;
         }
+        self.num_queues = 0;
 
-        let queue_config = (0..self.workers.len())
+        // Back as many pairs as the guest asked for, limited to the pairs it
+        // has actually started.
+        let started = self.workers.iter().take_while(|w| w.has_state()).count() as u16;
+        if started == 0 {
+            return Ok(());
+        }
+        let requested = self
+            .steering
+            .rss
+            .as_ref()
+            .map_or(self.steering.queue_pairs, |rss| rss.queue_pairs());
+        let num_queues = requested.clamp(1, started);
+
+        let queue_config = (0..num_queues)
             .map(|_| QueueConfig {
                 driver: Box::new(c_state.adapter.driver.clone()),
             })
             .collect::<Vec<_>>();
 
+        // Replicate the guest's indirection table onto the endpoint's. With no
+        // hash types enabled, everything goes to the unclassified queue.
+        let indirection_table = self.steering.rss.as_ref().map(|rss| {
+            (0..c_state.adapter.indirection_table_size as usize)
+                .map(|i| {
+                    let queue = if rss.hash.hash_types != 0 {
+                        rss.indirection_table[i % rss.indirection_table.len()]
+                    } else {
+                        rss.unclassified_queue
+                    };
+                    queue % num_queues
+                })
+                .collect::<Vec<_>>()
+        });
+        let rss_config = self
+            .steering
+            .rss
+            .as_ref()
+            .zip(indirection_table.as_deref())
+            .map(|(rss, indirection_table)| RssConfig {
+                key: &rss.hash.key,
+                indirection_table,
+                flags: 0,
+            });
+
         let mut queues = Vec::new();
         c_state
             .endpoint
-            .get_queues(queue_config, None, &mut queues)
+            .get_queues(queue_config, rss_config.as_ref(), &mut queues)
             .await
             .map_err(WorkerError::Endpoint)?;
 
-        assert_eq!(queues.len(), self.workers.len());
+        assert_eq!(queues.len(), num_queues as usize);
+
+        let hash_config = self
+            .negotiated_features_bank1
+            .hash_report()
+            .then(|| {
+                self.steering
+                    .rss
+                    .as_ref()
+                    .map(|rss| &rss.hash)
+                    .or(self.steering.hash.as_ref())
+            })
+            .flatten()
+            .filter(|hash| hash.hash_types != 0);
+        for worker in self.workers.iter_mut().filter_map(|w| w.state_mut()) {
+            worker.hash_config = hash_config.cloned();
+        }
 
         for (worker, mut queue) in self.workers.iter_mut().zip(queues) {
             let state = &mut worker.state_mut().unwrap().active_state;
@@ -763,6 +1036,7 @@ async fn restart_queues(&mut self, c_state: &mut CoordinatorState) -> Result<(),
             queue.rx_avail(&mut state.pending_rx_packets, &state.data.rx_ready[..n]);
             worker.task_mut().state = Some(EndpointQueueState { queue });
         }
+        self.num_queues = num_queues;
 
         Ok(())
     }
@@ -831,6 +1105,10 @@ fn from(value: task_control::Cancelled) -> Self {
 struct Worker {
     virtio_state: VirtioState,
     active_state: ActiveState,
+    header_len: usize,
+    /// The hash configuration for `VIRTIO_NET_F_HASH_REPORT`, if the guest
+    /// enabled hash reporting.
+    hash_config: Option<HashConfig>,
     #[inspect(skip)]
     negotiated_features: NetworkFeaturesBank0,
     #[inspect(skip)]
@@ -965,7 +1243,7 @@ fn try_queue_tx_packet(
 
         let total_readable = work.get_payload_length(false) as usize;
         let packet_len: u32 = total_readable
-            .checked_sub(header_size())
+            .checked_sub(self.header_len)
             .and_then(|len| u32::try_from(len).ok())
             .ok_or(TxPacketError::Empty)?;
 
@@ -976,22 +1254,22 @@ fn try_queue_tx_packet(
         let bytes_read = work
             .read(
                 self.active_state.pending_rx_packets.mem(),
-                &mut peek_buf[..header_size() + ETH_PEEK],
+                &mut peek_buf[..self.header_len + ETH_PEEK],
             )
             .map_err(TxPacketError::ReadHeader)?;
 
         let header = VirtioNetHeader::read_from_prefix(&peek_buf)
             .map(|(h, _)| h)
             .ok();
-        let packet_prefix = if bytes_read > header_size() {
-            &peek_buf[header_size()..bytes_read]
+        let packet_prefix = if bytes_read > self.header_len {
+            &peek_buf[self.header_len..bytes_read]
         } else {
             &[]
         };
 
         let segments = &mut self.active_state.data.tx_segments;
         let seg_start = segments.len();
-        let mut header_bytes_remaining = header_size() as u32;
+        let mut header_bytes_remaining = self.header_len as u32;
         for p in &work.payload {
             if p.writeable {
                 continue;
@@ -1275,6 +1553,9 @@ fn process_endpoint_rx(
 
         for ready_id in state.data.rx_ready[..n].iter() {
             state.stats.rx_packets.increment();
+            if let Some(hash_config) = &self.hash_config {
+                state.pending_rx_packets.report_hash(*ready_id, hash_config);
+            }
             let (work, bytes) = state.pending_rx_packets.take_rx_work(*ready_id);
             self.virtio_state.rx_queue.complete(work, bytes);
         }
@@ -0,0 +1,293 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Receive side scaling (RSS) state and packet hashing for
+//! `VIRTIO_NET_F_RSS` and `VIRTIO_NET_F_HASH_REPORT`.
+
+use bitfield_struct::bitfield;
+use inspect::Inspect;
+
+/// The maximum RSS key size, in bytes.
+pub const RSS_MAX_KEY_SIZE: u8 = 40;
+
+/// The maximum indirection table length accepted from the guest. The guest's
+/// table is replicated onto the endpoint's table, whatever its size.
+pub const RSS_MAX_INDIRECTION_TABLE_LENGTH: u16 = 128;
+
+// These correspond to VIRTIO_NET_RSS_HASH_TYPE_ flags.
+#[bitfield(u32)]
+pub struct HashTypes {
+    pub ipv4: bool,
+    pub tcpv4: bool,
+    pub udpv4: bool,
+    pub ipv6: bool,
+    pub tcpv6: bool,
+    pub udpv6: bool,
+    pub ip_ex: bool,
+    pub tcp_ex: bool,
+    pub udp_ex: bool,
+    #[bits(23)]
+    _reserved: u32,
+}
+
+/// The hash types the device can compute. IPv6 extension headers are not
+/// parsed, so the `_EX` types are not supported.
+pub const SUPPORTED_HASH_TYPES: HashTypes = HashTypes::new()
+    .with_ipv4(true)
+    .with_tcpv4(true)
+    .with_udpv4(true)
+    .with_ipv6(true)
+    .with_tcpv6(true)
+    .with_udpv6(true);
+
+// These correspond to VIRTIO_NET_HASH_REPORT_ values.
+open_enum::open_enum! {
+    pub enum HashReport: u16 {
+        NONE = 0,
+        IPV4 = 1,
+        TCPV4 = 2,
+        UDPV4 = 3,
+        IPV6 = 4,
+        TCPV6 = 5,
+        UDPV6 = 6,
+    }
+}
+
+/// The hash configuration set by the guest, used to compute the hash
+/// reported for each received packet.
+#[derive(Debug, Clone, Inspect)]
+pub struct HashConfig {
+    #[inspect(hex)]
+    pub hash_types: u32,
+    #[inspect(bytes)]
+    pub key: Vec<u8>,
+}
+
+/// The RSS configuration set by the guest.
+#[derive(Debug, Clone, Inspect)]
+pub struct RssState {
+    pub hash: HashConfig,
+    /// Queue pair indexes, indexed by the low bits of the packet hash.
+    #[inspect(iter_by_index)]
+    pub indirection_table: Vec<u16>,
+    pub unclassified_queue: u16,
+    pub max_tx_vq: u16,
+}
+
+impl RssState {
+    /// The number of queue pairs the configuration steers packets to.
+    pub fn queue_pairs(&self) -> u16 {
+        let max_rx = self
+            .indirection_table
+            .iter()
+            .copied()
+            .max()
+            .unwrap_or(0)
+            .max(self.unclassified_queue);
+        self.max_tx_vq.max(max_rx + 1)
+    }
+}
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+/// The number of bytes of a packet needed to compute its hash: an Ethernet
+/// header with a VLAN tag, an IPv4 header with options, and the L4 ports.
+pub const HASH_INPUT_LEN: usize = 18 + 60 + 4;
+
+impl HashConfig {
+    /// Computes the hash of the Ethernet frame starting with `packet`,
+    /// returning the hash value and the hash type to report to the guest.
+    pub fn hash_packet(&self, packet: &[u8]) -> (u32, HashReport) {
+        let types = HashTypes::from(self.hash_types);
+        let Some(&[t0, t1]) = packet.get(12..14) else {
+            return (0, HashReport::NONE);
+        };
+        let (l3, ethertype) = match u16::from_be_bytes([t0, t1]) {
+            ETHERTYPE_VLAN => match packet.get(16..18) {
+                Some(&[t0, t1]) => (&packet[18..], u16::from_be_bytes([t0, t1])),
+                _ => return (0, HashReport::NONE),
+            },
+            ethertype => (&packet[14..], ethertype),
+        };
+
+        let mut input = [0; 36];
+        match ethertype {
+            ETHERTYPE_IPV4 if l3.len() >= 20 => {
+                let ihl = (l3[0] & 0xf) as usize * 4;
+                let fragmented = u16::from_be_bytes([l3[6], l3[7]]) & 0x3fff != 0;
+                input[..8].copy_from_slice(&l3[12..20]);
+                if let Some(ports) = l3.get(ihl..ihl + 4)
+                    && ihl >= 20
+                    && !fragmented
+                {
+                    input[8..12].copy_from_slice(ports);
+                    match l3[9] {
+                        IPPROTO_TCP if types.tcpv4() => {
+                            return (self.toeplitz(&input[..12]), HashReport::TCPV4);
+                        }
+                        IPPROTO_UDP if types.udpv4() => {
+                            return (self.toeplitz(&input[..12]), HashReport::UDPV4);
+                        }
+                        _ => {}
+                    }
+                }
+                if types.ipv4() {
+                    return (self.toeplitz(&input[..8]), HashReport::IPV4);
+                }
+            }
+            ETHERTYPE_IPV6 if l3.len() >= 40 => {
+                input[..32].copy_from_slice(&l3[8..40]);
+                if let Some(ports) = l3.get(40..44) {
+                    input[32..36].copy_from_slice(ports);
+                    match l3[6] {
+                        IPPROTO_TCP if types.tcpv6() => {
+                            return (self.toeplitz(&input), HashReport::TCPV6);
+                        }
+                        IPPROTO_UDP if types.udpv6() => {
+                            return (self.toeplitz(&input), HashReport::UDPV6);
+                        }
+                        _ => {}
+                    }
+                }
+                if types.ipv6() {
+                    return (self.toeplitz(&input[..32]), HashReport::IPV6);
+                }
+            }
+            _ => {}
+        }
+        (0, HashReport::NONE)
+    }
+
+    /// Computes the Toeplitz hash of `input` with the configured key.
+    fn toeplitz(&self, input: &[u8]) -> u32 {
+        let key_bit = |n: usize| {
+            self.key
+                .get(n / 8)
+                .map_or(0, |&b| ((b >> (7 - n % 8)) & 1) as u32)
+        };
+        // The 32 key bits that line up with the current input bit.
+        let mut window = (0..32).fold(0, |w, n| (w << 1) | key_bit(n));
+        let mut hash = 0;
+        for (i, &byte) in input.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    hash ^= window;
+                }
+                window = (window << 1) | key_bit(32 + i * 8 + bit);
+            }
+        }
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The verification key and vectors from the Microsoft RSS documentation.
+    const KEY: [u8; 40] = [
+        0x6d, 0x5a, 0x56, 0xda, 0x25, 0x5b, 0x0e, 0xc2, 0x41, 0x67, 0x25, 0x3d, 0x43, 0xa3, 0x8f,
+        0xb0, 0xd0, 0xca, 0x2b, 0xcb, 0xae, 0x7b, 0x30, 0xb4, 0x77, 0xcb, 0x2d, 0xa3, 0x80, 0x30,
+        0xf2, 0x0c, 0x6a, 0x42, 0xb7, 0x3b, 0xbe, 0xac, 0x01, 0xfa,
+    ];
+
+    fn config(hash_types: HashTypes) -> HashConfig {
+        HashConfig {
+            hash_types: hash_types.into(),
+            key: KEY.to_vec(),
+        }
+    }
+
+    fn ipv4_packet(protocol: u8, src: [u8; 4], dst: [u8; 4], ports: (u16, u16)) -> Vec<u8> {
+        let mut packet = vec![0; 14];
+        packet[12..14].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        let mut ip = [0; 20];
+        ip[0] = 0x45;
+        ip[9] = protocol;
+        ip[12..16].copy_from_slice(&src);
+        ip[16..20].copy_from_slice(&dst);
+        packet.extend_from_slice(&ip);
+        packet.extend_from_slice(&ports.0.to_be_bytes());
+        packet.extend_from_slice(&ports.1.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn ipv4_vectors() {
+        let packet = ipv4_packet(
+            IPPROTO_TCP,
+            [66, 9, 149, 187],
+            [161, 142, 100, 80],
+            (2794, 1766),
+        );
+        assert_eq!(
+            config(SUPPORTED_HASH_TYPES).hash_packet(&packet),
+            (0x51ccc178, HashReport::TCPV4)
+        );
+        assert_eq!(
+            config(HashTypes::new().with_ipv4(true)).hash_packet(&packet),
+            (0x323e8fc2, HashReport::IPV4)
+        );
+        assert_eq!(
+            config(HashTypes::new()).hash_packet(&packet),
+            (0, HashReport::NONE)
+        );
+    }
+
+    #[test]
+    fn ipv4_fragment_uses_ip_hash() {
+        let mut packet = ipv4_packet(
+            IPPROTO_UDP,
+            [66, 9, 149, 187],
+            [161, 142, 100, 80],
+            (2794, 1766),
+        );
+        // Set the more-fragments flag.
+        packet[14 + 6] = 0x20;
+        assert_eq!(
+            config(SUPPORTED_HASH_TYPES).hash_packet(&packet),
+            (0x323e8fc2, HashReport::IPV4)
+        );
+    }
+
+    #[test]
+    fn ipv6_vector() {
+        let src = [
+            0x3f, 0xfe, 0x25, 0x01, 0x02, 0x00, 0x1f, 0xff, 0, 0, 0, 0, 0, 0, 0, 0x07,
+        ];
+        let dst = [
+            0x3f, 0xfe, 0x25, 0x01, 0x02, 0x00, 0x00, 0x03, 0, 0, 0, 0, 0, 0, 0, 0x01,
+        ];
+        let mut packet = vec![0; 14];
+        packet[12..14].copy_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+        let mut ip = [0; 40];
+        ip[6] = IPPROTO_TCP;
+        ip[8..24].copy_from_slice(&src);
+        ip[24..40].copy_from_slice(&dst);
+        packet.extend_from_slice(&ip);
+        packet.extend_from_slice(&2794u16.to_be_bytes());
+        packet.extend_from_slice(&1766u16.to_be_bytes());
+
+        assert_eq!(
+            config(SUPPORTED_HASH_TYPES).hash_packet(&packet),
+            (0x40207d3d, HashReport::TCPV6)
+        );
+        assert_eq!(
+            config(HashTypes::new().with_ipv6(true)).hash_packet(&packet),
+            (0x2cc18cd5, HashReport::IPV6)
+        );
+    }
+
+    #[test]
+    fn non_ip_not_hashed() {
+        let packet = [0u8; 64];
+        assert_eq!(
+            config(SUPPORTED_HASH_TYPES).hash_packet(&packet),
+            (0, HashReport::NONE)
+        );
+    }
+}
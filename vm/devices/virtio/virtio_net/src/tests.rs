@@ -35,10 +35,12 @@
 use virtio::queue::QueueParams;
 use virtio::spec::VirtioDeviceFeatures;
 use virtio::spec::queue::DescriptorFlags;
+use virtio::test_helpers::TestQueue;
 use virtio::test_helpers::init_avail_ring;
 use virtio::test_helpers::init_used_ring;
 use virtio::test_helpers::make_available;
 use virtio::test_helpers::read_used;
+use virtio::test_helpers::start_queue;
 use virtio::test_helpers::wait_for_used;
 use virtio::test_helpers::write_descriptor;
 use vmcore::interrupt::Interrupt;
@@ -54,6 +56,9 @@
 use crate::VirtioNetHeaderGso;
 use crate::VirtioNetHeaderGsoProtocol;
 use crate::Worker;
+use crate::control::CommandError;
+use crate::control::CommandLimits;
+use crate::control::parse_command;
 use crate::header_size;
 
 // --- Constants ---
@@ -75,7 +80,7 @@
 const TOTAL_MEM_SIZE: usize = 0x30000;
 
 // Virtio-net header size, derived from the actual layout.
-const NET_HEADER_SIZE: u32 = header_size() as u32;
+const NET_HEADER_SIZE: u32 = header_size(false) as u32;
 
 // --- Simplified segment info for assertions ---
 
@@ -1541,7 +1546,7 @@ async fn tx_offload_end_to_end_tcp_csum(driver: DefaultDriver) {
 /// Read the virtio-net header from guest memory at the given GPA.
 fn read_virtio_header(mem: &GuestMemory, gpa: u64) -> VirtioNetHeader {
     let mut buf = [0u8; size_of::<VirtioNetHeader>()];
-    mem.read_at(gpa, &mut buf[..header_size()]).unwrap();
+    mem.read_at(gpa, &mut buf[..header_size(false)]).unwrap();
     let (h, _) = VirtioNetHeader::read_from_prefix(&buf).unwrap();
     h
 }
@@ -1791,3 +1796,412 @@ async fn wait_for_endpoint_action(&mut self) -> EndpointAction {
         pending().await
     }
 }
+
+// --- Multiqueue and RSS Tests ---
+
+/// The Microsoft RSS verification key.
+const RSS_KEY: [u8; 40] = [
+    0x6d, 0x5a, 0x56, 0xda, 0x25, 0x5b, 0x0e, 0xc2, 0x41, 0x67, 0x25, 0x3d, 0x43, 0xa3, 0x8f, 0xb0,
+    0xd0, 0xca, 0x2b, 0xcb, 0xae, 0x7b, 0x30, 0xb4, 0x77, 0xcb, 0x2d, 0xa3, 0x80, 0x30, 0xf2, 0x0c,
+    0x6a, 0x42, 0xb7, 0x3b, 0xbe, 0xac, 0x01, 0xfa,
+];
+
+const MQ_MEM_SIZE: usize = 0x40000;
+// Queue rings are laid out below this, 0x3000 bytes per queue.
+const MQ_DATA_BASE: u64 = 0x30000;
+const MQ_CTRL_CMD_GPA: u64 = MQ_DATA_BASE;
+const MQ_CTRL_ACK_GPA: u64 = MQ_DATA_BASE + 0x1000;
+const MQ_RX_BUFFER_GPA: u64 = MQ_DATA_BASE + 0x2000;
+
+/// A record of a call to `get_queues` on [`MockMultiQueueEndpoint`].
+struct GetQueuesCall {
+    handles: Vec<MockQueueHandle>,
+    /// The RSS key and indirection table, if any.
+    rss: Option<(Vec<u8>, Vec<u16>)>,
+}
+
+struct MockMultiQueueEndpoint {
+    max_queues: u16,
+    indirection_table_size: u16,
+    calls: mesh::Sender<GetQueuesCall>,
+}
+
+impl InspectMut for MockMultiQueueEndpoint {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        req.ignore();
+    }
+}
+
+#[async_trait]
+impl Endpoint for MockMultiQueueEndpoint {
+    fn endpoint_type(&self) -> &'static str {
+        "mock-multiqueue"
+    }
+
+    async fn get_queues(
+        &mut self,
+        config: Vec<QueueConfig>,
+        rss: Option<&RssConfig<'_>>,
+        queues: &mut Vec<Box<dyn net_backend::Queue>>,
+    ) -> anyhow::Result<()> {
+        let mut handles = Vec::new();
+        for _ in &config {
+            let (queue, handle) = new_mock_queue();
+            queues.push(Box::new(queue));
+            handles.push(handle);
+        }
+        self.calls.send(GetQueuesCall {
+            handles,
+            rss: rss.map(|rss| (rss.key.to_vec(), rss.indirection_table.to_vec())),
+        });
+        Ok(())
+    }
+
+    async fn stop(&mut self) {}
+
+    fn multiqueue_support(&self) -> MultiQueueSupport {
+        MultiQueueSupport {
+            max_queues: self.max_queues,
+            indirection_table_size: self.indirection_table_size,
+        }
+    }
+
+    fn tx_fast_completions(&self) -> bool {
+        true
+    }
+
+    async fn wait_for_endpoint_action(&mut self) -> EndpointAction {
+        pending().await
+    }
+}
+
+/// Features for a guest that negotiates multiqueue, RSS, and hash reporting.
+fn mq_features() -> VirtioDeviceFeatures {
+    VirtioDeviceFeatures::new()
+        .with_bank(
+            0,
+            NetworkFeaturesBank0::new()
+                .with_ctrl_vq(true)
+                .with_mq(true)
+                .into_bits(),
+        )
+        .with_bank(
+            1,
+            NetworkFeaturesBank1::new()
+                .with_rss(true)
+                .with_hash_report(true)
+                .into_bits(),
+        )
+}
+
+struct MqHarness {
+    device: Device,
+    mem: GuestMemory,
+    driver: DefaultDriver,
+    calls: mesh::Receiver<GetQueuesCall>,
+    /// The started queues, indexed by queue.
+    queues: Vec<Option<TestQueue>>,
+}
+
+impl MqHarness {
+    fn new(driver: &DefaultDriver, max_queues: u16, indirection_table_size: u16) -> Self {
+        let (calls_tx, calls) = mesh::channel();
+        let endpoint = MockMultiQueueEndpoint {
+            max_queues,
+            indirection_table_size,
+            calls: calls_tx,
+        };
+        let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+        let mac = MacAddress::new([0x00, 0x15, 0x5d, 0xaa, 0xbb, 0xcc]);
+        let device = Device::builder().build(&driver_source, Box::new(endpoint), mac);
+        let num_queues = device.traits().max_queues as usize;
+        Self {
+            device,
+            mem: GuestMemory::allocate(MQ_MEM_SIZE),
+            driver: driver.clone(),
+            calls,
+            queues: (0..num_queues).map(|_| None).collect(),
+        }
+    }
+
+    async fn start_queue(&mut self, idx: u16) {
+        let queue = start_queue(&mut self.device, &self.mem, idx, QUEUE_SIZE, &mq_features()).await;
+        self.queues[idx as usize] = Some(queue);
+    }
+
+    /// Posts a single writeable buffer on a queue.
+    fn post_buffer(&mut self, idx: u16, gpa: u64, len: u32) {
+        self.queues[idx as usize]
+            .as_mut()
+            .unwrap()
+            .post(&self.mem, 0, &[(gpa, len, true)]);
+    }
+
+    async fn wait_for_used(&mut self, idx: u16) -> (u16, u32) {
+        self.queues[idx as usize]
+            .as_mut()
+            .unwrap()
+            .wait(&self.driver, &self.mem)
+            .await
+    }
+
+    /// Sends a command on the control queue and returns the device's ack.
+    async fn send_control(&mut self, ctrl_idx: u16, command: &[u8]) -> u8 {
+        self.mem.write_at(MQ_CTRL_CMD_GPA, command).unwrap();
+        self.mem.write_at(MQ_CTRL_ACK_GPA, &[0xff]).unwrap();
+        self.queues[ctrl_idx as usize].as_mut().unwrap().post(
+            &self.mem,
+            0,
+            &[
+                (MQ_CTRL_CMD_GPA, command.len() as u32, false),
+                (MQ_CTRL_ACK_GPA, 1, true),
+            ],
+        );
+        let (used_id, used_len) = self.wait_for_used(ctrl_idx).await;
+        assert_eq!(used_id, 0);
+        assert_eq!(used_len, 1);
+        let mut ack = [0];
+        self.mem.read_at(MQ_CTRL_ACK_GPA, &mut ack).unwrap();
+        ack[0]
+    }
+
+    /// Waits for a `get_queues` call matching `f`, skipping any others.
+    async fn wait_for_call(&mut self, f: impl Fn(&GetQueuesCall) -> bool) -> GetQueuesCall {
+        loop {
+            let call = mesh::CancelContext::new()
+                .with_timeout(Duration::from_secs(5))
+                .until_cancelled(self.calls.next())
+                .await
+                .expect("timed out waiting for get_queues")
+                .expect("channel closed");
+            if f(&call) {
+                break call;
+            }
+        }
+    }
+}
+
+fn vq_pairs_set_command(pairs: u16) -> Vec<u8> {
+    let mut command = vec![4, 0];
+    command.extend_from_slice(&pairs.to_le_bytes());
+    command
+}
+
+fn rss_config_command(
+    hash_types: u32,
+    table: &[u16],
+    unclassified_queue: u16,
+    max_tx_vq: u16,
+    key: &[u8],
+) -> Vec<u8> {
+    let mut command = vec![4, 1];
+    command.extend_from_slice(&hash_types.to_le_bytes());
+    command.extend_from_slice(&(table.len() as u16 - 1).to_le_bytes());
+    command.extend_from_slice(&unclassified_queue.to_le_bytes());
+    for entry in table {
+        command.extend_from_slice(&entry.to_le_bytes());
+    }
+    command.extend_from_slice(&max_tx_vq.to_le_bytes());
+    command.push(key.len() as u8);
+    command.extend_from_slice(key);
+    command
+}
+
+/// Verify that MQ, RSS, and HASH_REPORT are offered when the endpoint has
+/// multiple queues and an indirection table.
+#[async_test]
+async fn feature_negotiation_multiqueue(driver: DefaultDriver) {
+    let mut harness = MqHarness::new(&driver, 4, 64);
+    let traits = harness.device.traits();
+
+    let bank0 = NetworkFeaturesBank0::from(traits.device_features.bank(0));
+    assert!(bank0.ctrl_vq());
+    assert!(bank0.mq());
+    let bank1 = NetworkFeaturesBank1::from(traits.device_features.bank(1));
+    assert!(bank1.rss());
+    assert!(bank1.hash_report());
+    // Four queue pairs plus the control queue.
+    assert_eq!(traits.max_queues, 9);
+
+    let max_pairs = harness.device.read_registers_u32(8).await & 0xffff;
+    assert_eq!(max_pairs, 4);
+    let rss_limits = harness.device.read_registers_u32(16).await;
+    assert_eq!((rss_limits >> 8) & 0xff, 40, "rss_max_key_size");
+    assert_eq!(rss_limits >> 16, 128, "rss_max_indirection_table_length");
+    let hash_types = harness.device.read_registers_u32(20).await;
+    assert_eq!(hash_types, 0x3f);
+}
+
+/// Verify that MQ and RSS are not offered for a single-queue endpoint, but
+/// hash reporting still is.
+#[async_test]
+async fn feature_negotiation_single_queue(driver: DefaultDriver) {
+    let mut harness = MqHarness::new(&driver, 1, 64);
+    let traits = harness.device.traits();
+
+    let bank0 = NetworkFeaturesBank0::from(traits.device_features.bank(0));
+    assert!(bank0.ctrl_vq());
+    assert!(!bank0.mq());
+    let bank1 = NetworkFeaturesBank1::from(traits.device_features.bank(1));
+    assert!(!bank1.rss());
+    assert!(bank1.hash_report());
+    assert_eq!(traits.max_queues, 3);
+
+    let rss_limits = harness.device.read_registers_u32(16).await;
+    assert_eq!(rss_limits >> 16, 0, "rss_max_indirection_table_length");
+}
+
+/// Verify that VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET changes the number of endpoint
+/// queues, limited to the pairs the guest has started.
+#[async_test]
+async fn multiqueue_vq_pairs_set(driver: DefaultDriver) {
+    let mut harness = MqHarness::new(&driver, 4, 0);
+    for idx in 0..4 {
+        harness.start_queue(idx).await;
+    }
+    // The control queue follows all four possible pairs.
+    let ctrl_idx = 8;
+    harness.start_queue(ctrl_idx).await;
+
+    // Only the first pair is used until the guest configures multiqueue.
+    let call = harness.wait_for_call(|_| true).await;
+    assert_eq!(call.handles.len(), 1);
+    assert!(call.rss.is_none());
+
+    let ack = harness
+        .send_control(ctrl_idx, &vq_pairs_set_command(2))
+        .await;
+    assert_eq!(ack, 0);
+    harness.wait_for_call(|call| call.handles.len() == 2).await;
+
+    // Three pairs are valid, but only two have been started.
+    let ack = harness
+        .send_control(ctrl_idx, &vq_pairs_set_command(3))
+        .await;
+    assert_eq!(ack, 0);
+    let call = harness.wait_for_call(|_| true).await;
+    assert_eq!(call.handles.len(), 2);
+
+    // More pairs than the device offers is an error.
+    let ack = harness
+        .send_control(ctrl_idx, &vq_pairs_set_command(5))
+        .await;
+    assert_eq!(ack, 1);
+}
+
+/// Verify that VIRTIO_NET_CTRL_MQ_RSS_CONFIG programs the endpoint's
+/// indirection table and enables hash reporting on received packets.
+#[async_test]
+async fn rss_config_steers_and_reports_hash(driver: DefaultDriver) {
+    let mut harness = MqHarness::new(&driver, 2, 64);
+    for idx in 0..4 {
+        harness.start_queue(idx).await;
+    }
+    let ctrl_idx = 4;
+    harness.start_queue(ctrl_idx).await;
+
+    let command = rss_config_command(0x3f, &[0, 1, 0, 1], 0, 2, &RSS_KEY);
+    let ack = harness.send_control(ctrl_idx, &command).await;
+    assert_eq!(ack, 0);
+
+    let mut call = harness.wait_for_call(|call| call.rss.is_some()).await;
+    assert_eq!(call.handles.len(), 2);
+    let (key, table) = call.rss.unwrap();
+    assert_eq!(key, RSS_KEY);
+    // The guest's table is replicated onto the endpoint's.
+    assert_eq!(table.len(), 64);
+    assert!(table.iter().enumerate().all(|(i, &q)| q as usize == i % 2));
+
+    // Receive a TCPv4 packet and check the reported hash.
+    let header_len = header_size(true);
+    harness.post_buffer(0, MQ_RX_BUFFER_GPA, 1500);
+    let handle = &mut call.handles[0];
+    handle.wait_for_rx_pending().await;
+
+    let mut packet = vec![0u8; 14];
+    packet[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+    let mut ip = [0u8; 20];
+    ip[0] = 0x45;
+    ip[9] = 6;
+    ip[12..16].copy_from_slice(&[66, 9, 149, 187]);
+    ip[16..20].copy_from_slice(&[161, 142, 100, 80]);
+    packet.extend_from_slice(&ip);
+    packet.extend_from_slice(&2794u16.to_be_bytes());
+    packet.extend_from_slice(&1766u16.to_be_bytes());
+    packet.resize(64, 0);
+    handle.inject_rx_packet(&packet);
+
+    let (used_id, used_len) = harness.wait_for_used(0).await;
+    assert_eq!(used_id, 0);
+    assert_eq!(used_len as usize, header_len + packet.len());
+
+    let mut buf = [0u8; size_of::<VirtioNetHeader>()];
+    harness.mem.read_at(MQ_RX_BUFFER_GPA, &mut buf).unwrap();
+    let (hdr, _) = VirtioNetHeader::read_from_prefix(&buf).unwrap();
+    assert_eq!(hdr.hash_value, 0x51ccc178);
+    assert_eq!(hdr.hash_report, 2, "VIRTIO_NET_HASH_REPORT_TCPv4");
+    // The packet data follows the larger header.
+    let mut data = vec![0u8; packet.len()];
+    harness
+        .mem
+        .read_at(MQ_RX_BUFFER_GPA + header_len as u64, &mut data)
+        .unwrap();
+    assert_eq!(data, packet);
+}
+
+/// Verify validation of control queue commands.
+#[test]
+fn control_command_validation() {
+    let limits = CommandLimits {
+        mq: true,
+        rss: true,
+        hash_report: true,
+        max_queue_pairs: 2,
+        max_indirection_table_length: 128,
+    };
+
+    assert!(parse_command(&vq_pairs_set_command(2), &limits).is_ok());
+    assert!(matches!(
+        parse_command(&vq_pairs_set_command(0), &limits),
+        Err(CommandError::InvalidQueuePairs(0))
+    ));
+    assert!(matches!(
+        parse_command(&[4, 0, 1], &limits),
+        Err(CommandError::Truncated)
+    ));
+
+    // The indirection table length must be a power of two.
+    let command = rss_config_command(0x3f, &[0, 1, 0], 0, 2, &RSS_KEY);
+    assert!(matches!(
+        parse_command(&command, &limits),
+        Err(CommandError::InvalidTableLength(3))
+    ));
+    // Entries must refer to valid queue pairs.
+    let command = rss_config_command(0x3f, &[0, 2], 0, 2, &RSS_KEY);
+    assert!(matches!(
+        parse_command(&command, &limits),
+        Err(CommandError::InvalidQueue(2))
+    ));
+    // IPv6 extension header hash types are not supported.
+    let command = rss_config_command(0x1ff, &[0, 1], 0, 2, &RSS_KEY);
+    assert!(matches!(
+        parse_command(&command, &limits),
+        Err(CommandError::UnsupportedHashTypes(0x1ff))
+    ));
+    let command = rss_config_command(0x3f, &[0, 1], 0, 2, &[0; 41]);
+    assert!(matches!(
+        parse_command(&command, &limits),
+        Err(CommandError::KeyTooLong(41))
+    ));
+
+    // Commands require their feature to be negotiated.
+    let no_rss = CommandLimits {
+        rss: false,
+        ..limits
+    };
+    let command = rss_config_command(0x3f, &[0, 1], 0, 2, &RSS_KEY);
+    assert!(matches!(
+        parse_command(&command, &no_rss),
+        Err(CommandError::Unsupported(4, 1))
+    ));
+}
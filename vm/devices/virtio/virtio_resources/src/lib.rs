@@ -95,6 +95,34 @@ impl ResourceId<VirtioDeviceHandle> for VirtioRngHandle {
     }
 }
 
+pub mod balloon {
+    use mesh::MeshPayload;
+    use mesh::rpc::Rpc;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::VirtioDeviceHandle;
+
+    #[derive(MeshPayload)]
+    pub struct VirtioBalloonHandle {
+        /// Whether to offer free page reporting, with which the guest reports
+        /// its free memory to be discarded without inflating the balloon.
+        pub free_page_reporting: bool,
+        /// Runtime requests, such as to change the balloon's target size.
+        pub requests: Option<mesh::Receiver<BalloonRequest>>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioBalloonHandle {
+        const ID: &'static str = "virtio-balloon";
+    }
+
+    /// A runtime request to the balloon device.
+    #[derive(MeshPayload)]
+    pub enum BalloonRequest {
+        /// Sets the number of 4KiB pages that the guest should give to the
+        /// balloon.
+        SetTarget(Rpc<u32, ()>),
+    }
+}
+
 pub mod blk {
     use mesh::MeshPayload;
     use vm_resource::Resource;
@@ -113,6 +141,30 @@ impl ResourceId<VirtioDeviceHandle> for VirtioBlkHandle {
     }
 }
 
+pub mod scsi {
+    use mesh::MeshPayload;
+    use storvsp_resources::ScsiControllerRequest;
+    use storvsp_resources::ScsiDeviceAndPath;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::VirtioDeviceHandle;
+
+    /// A virtio-scsi controller. The devices use the same resources as
+    /// storvsp's, and must be at path 0.
+    #[derive(MeshPayload)]
+    pub struct VirtioScsiHandle {
+        /// The number of request queues.
+        pub num_queues: u16,
+        /// The initial set of SCSI devices.
+        pub devices: Vec<ScsiDeviceAndPath>,
+        /// Runtime request channel, for adding and removing devices.
+        pub requests: Option<mesh::Receiver<ScsiControllerRequest>>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioScsiHandle {
+        const ID: &'static str = "virtio-scsi";
+    }
+}
+
 pub mod net {
     use mesh::MeshPayload;
     use net_backend_resources::mac_address::MacAddress;
@@ -150,6 +202,72 @@ impl ResourceId<VirtioDeviceHandle> for VirtioConsoleHandle {
     }
 }
 
+pub mod input {
+    use mesh::MeshPayload;
+    use vm_resource::Resource;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::KeyboardInputHandleKind;
+    use vm_resource::kind::MouseInputHandleKind;
+    use vm_resource::kind::VirtioDeviceHandle;
+
+    /// A virtio-input keyboard.
+    #[derive(MeshPayload)]
+    pub struct VirtioKeyboardHandle {
+        /// The source of keyboard input.
+        pub source: Resource<KeyboardInputHandleKind>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioKeyboardHandle {
+        const ID: &'static str = "virtio-keyboard";
+    }
+
+    /// A virtio-input relative mouse. The absolute positions from the input
+    /// source are converted to relative motion.
+    #[derive(MeshPayload)]
+    pub struct VirtioMouseHandle {
+        /// The source of mouse moves and clicks.
+        pub source: Resource<MouseInputHandleKind>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioMouseHandle {
+        const ID: &'static str = "virtio-mouse";
+    }
+
+    /// A virtio-input absolute pointing device.
+    #[derive(MeshPayload)]
+    pub struct VirtioTabletHandle {
+        /// The source of mouse moves and clicks.
+        pub source: Resource<MouseInputHandleKind>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioTabletHandle {
+        const ID: &'static str = "virtio-tablet";
+    }
+}
+
+pub mod gpu {
+    use mesh::MeshPayload;
+    use vm_resource::Resource;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::FramebufferHandleKind;
+    use vm_resource::kind::VirtioDeviceHandle;
+
+    /// A 2D virtio-gpu device with a single scanout.
+    #[derive(MeshPayload)]
+    pub struct VirtioGpuHandle {
+        /// The framebuffer that the scanout is presented through.
+        pub framebuffer: Resource<FramebufferHandleKind>,
+        /// The preferred display width in pixels.
+        pub width: u32,
+        /// The preferred display height in pixels.
+        pub height: u32,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioGpuHandle {
+        const ID: &'static str = "virtio-gpu";
+    }
+}
+
 #[cfg(unix)]
 pub mod vhost_user {
     use mesh::MeshPayload;
@@ -0,0 +1,1390 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio SCSI host device implementation.
+//!
+//! Implements the virtio-scsi device (device ID 8) as specified in the VIRTIO
+//! 1.2 specification, §5.6 "SCSI Host Device". The device exposes a single
+//! channel of up to 256 targets with up to 256 LUNs each, and forwards
+//! commands to the same [`AsyncScsiDisk`] implementations that back
+//! `storvsp` (`SimpleScsiDisk` and `SimpleScsiDvd`).
+//!
+//! LUNs can be added and removed at runtime with [`VirtioScsiController`],
+//! which reports the change to the guest on the event queue. Task management
+//! functions that reset a LUN or a target wait for the affected commands to
+//! complete, since the backends cannot cancel them.
+
+#![expect(missing_docs)]
+#![forbid(unsafe_code)]
+
+pub mod resolver;
+
+use anyhow::Context as _;
+use futures::StreamExt;
+use guestmem::GuestMemory;
+use guestmem::GuestMemoryError;
+use guestmem::MemoryWrite;
+use guestmem::ranges::PagedRange;
+use inspect::Inspect;
+use inspect::InspectMut;
+use inspect_counters::Counter;
+use pal_async::wait::PolledWait;
+use parking_lot::Mutex;
+use parking_lot::RwLock;
+use scsi_buffers::RequestBuffers;
+use scsi_core::AsyncScsiDisk;
+use scsi_core::Request;
+use scsi_core::ScsiResult;
+use scsi_defs::AdditionalSenseCode;
+use scsi_defs::ScsiOp;
+use scsi_defs::ScsiStatus;
+use scsi_defs::SenseData;
+use scsi_defs::SenseKey;
+use scsi_defs::srb::SrbStatus;
+use scsidisk::illegal_request_sense;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::collections::hash_map::Entry;
+use std::future::Future;
+use std::future::poll_fn;
+use std::mem::offset_of;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::task::Context;
+use std::task::Poll;
+use storvsp_resources::ScsiPath;
+use task_control::AsyncRun;
+use task_control::Cancelled;
+use task_control::InspectTaskMut;
+use task_control::StopTask;
+use task_control::TaskControl;
+use thiserror::Error;
+use unicycle::FuturesUnordered;
+use virtio::DeviceTraits;
+use virtio::DeviceTraitsSharedMemory;
+use virtio::QueueResources;
+use virtio::VirtioDevice;
+use virtio::VirtioQueue;
+use virtio::VirtioQueueCallbackWork;
+use virtio::queue::QueueState;
+use virtio::regions::DataRegion;
+use virtio::regions::data_regions;
+use virtio::regions::try_build_gpn_list;
+use virtio::spec::VirtioDeviceFeatures;
+use virtio::spec::scsi::*;
+use virtio::spec::u32_le;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+/// The maximum number of data segments per command. Each command uses at
+/// least one descriptor for the request header and one for the response.
+const DEFAULT_SEG_MAX: u32 = virtio::DEFAULT_QUEUE_SIZE as u32 - 2;
+
+/// The maximum transfer size, in 512-byte sectors. This also bounds the
+/// bounce buffer allocated for descriptor chains that are not page aligned.
+const MAX_SECTORS: u32 = 0xffff;
+
+/// The maximum number of concurrent commands per request queue.
+const MAX_IO_DEPTH: usize = 64;
+
+/// The maximum number of request queues.
+const MAX_REQUEST_QUEUES: u16 = 64;
+
+const MAX_TARGET: u8 = u8::MAX;
+const MAX_LUN: u8 = u8::MAX;
+
+/// The largest CDB and sense sizes the driver can configure, to bound the
+/// per-command allocations.
+const MAX_CDB_SIZE: u32 = 256;
+const MAX_SENSE_SIZE: u32 = 256;
+
+/// The maximum number of events to queue while the guest has not provided
+/// event buffers. Further events are dropped and reported as missed.
+const MAX_PENDING_EVENTS: usize = 64;
+
+// Config register offsets of the driver-writable fields.
+const CONFIG_SENSE_SIZE: u16 = offset_of!(VirtioScsiConfig, sense_size) as u16;
+const CONFIG_CDB_SIZE: u16 = offset_of!(VirtioScsiConfig, cdb_size) as u16;
+
+/// The virtio-scsi device.
+#[derive(InspectMut)]
+pub struct VirtioScsiDevice {
+    #[inspect(skip)]
+    driver: VmTaskDriver,
+    #[inspect(flatten)]
+    state: Arc<ControllerState>,
+    config: VirtioScsiConfig,
+    #[inspect(skip)]
+    workers: Vec<TaskControl<ScsiWorker, ScsiQueue>>,
+}
+
+/// A handle for adding and removing LUNs on a [`VirtioScsiDevice`].
+#[derive(Clone)]
+pub struct VirtioScsiController {
+    state: Arc<ControllerState>,
+}
+
+/// An error returned by [`VirtioScsiController`].
+#[derive(Debug, Error)]
+pub enum ControllerError {
+    #[error("SCSI path {0} is already in use")]
+    PathInUse(ScsiPath),
+    #[error("SCSI path {0} is not in use")]
+    PathNotInUse(ScsiPath),
+    #[error("SCSI path {0} is not supported, virtio-scsi only has path 0")]
+    UnsupportedPath(ScsiPath),
+}
+
+/// State shared between the device, its queue workers, and the controller
+/// handles.
+struct ControllerState {
+    luns: RwLock<HashMap<ScsiPath, Arc<Lun>>>,
+    events: Mutex<PendingEvents>,
+    events_ready: event_listener::Event,
+}
+
+#[derive(Default)]
+struct PendingEvents {
+    events: VecDeque<VirtioScsiEvent>,
+    /// Events were dropped because too many were pending.
+    missed: bool,
+}
+
+#[derive(Inspect)]
+struct Lun {
+    #[inspect(flatten)]
+    disk: Arc<dyn AsyncScsiDisk>,
+    #[inspect(with = "|x| x.load(Ordering::Relaxed)")]
+    in_flight: AtomicUsize,
+    #[inspect(skip)]
+    idle: event_listener::Event,
+}
+
+impl Inspect for ControllerState {
+    fn inspect(&self, req: inspect::Request<'_>) {
+        let mut resp = req.respond();
+        for (path, lun) in self.luns.read().iter() {
+            resp.field(&format!("disks/{path}"), lun);
+        }
+        resp.field("pending_events", self.events.lock().events.len());
+    }
+}
+
+impl VirtioScsiDevice {
+    /// Creates a new virtio-scsi device with `num_queues` request queues and
+    /// no LUNs. LUNs are attached with [`Self::controller`].
+    pub fn new(driver_source: &VmTaskDriverSource, num_queues: u16) -> Self {
+        let num_queues = num_queues.clamp(1, MAX_REQUEST_QUEUES);
+        let state = Arc::new(ControllerState {
+            luns: Default::default(),
+            events: Default::default(),
+            events_ready: event_listener::Event::new(),
+        });
+        let workers = (0..VIRTIO_SCSI_REQUEST_QUEUE_BASE + num_queues)
+            .map(|_| {
+                TaskControl::new(ScsiWorker {
+                    state: state.clone(),
+                    stats: WorkerStats::default(),
+                    ios: FuturesUnordered::new(),
+                })
+            })
+            .collect();
+        Self {
+            driver: driver_source.simple(),
+            state,
+            config: initial_config(num_queues),
+            workers,
+        }
+    }
+
+    /// Returns a handle for adding and removing LUNs.
+    pub fn controller(&self) -> VirtioScsiController {
+        VirtioScsiController {
+            state: self.state.clone(),
+        }
+    }
+}
+
+/// Returns the config space as of device reset.
+fn initial_config(num_queues: u16) -> VirtioScsiConfig {
+    VirtioScsiConfig {
+        num_queues: num_queues.into(),
+        seg_max: DEFAULT_SEG_MAX,
+        max_sectors: MAX_SECTORS,
+        cmd_per_lun: MAX_IO_DEPTH as u32,
+        event_info_size: size_of::<VirtioScsiEvent>() as u32,
+        sense_size: VIRTIO_SCSI_SENSE_DEFAULT_SIZE,
+        cdb_size: VIRTIO_SCSI_CDB_DEFAULT_SIZE,
+        max_channel: 0,
+        max_target: MAX_TARGET.into(),
+        max_lun: MAX_LUN.into(),
+    }
+}
+
+impl VirtioScsiController {
+    /// Attaches `disk` at `path`, and notifies the guest.
+    pub fn attach(
+        &self,
+        path: ScsiPath,
+        disk: Arc<dyn AsyncScsiDisk>,
+    ) -> Result<(), ControllerError> {
+        if path.path != 0 {
+            return Err(ControllerError::UnsupportedPath(path));
+        }
+        match self.state.luns.write().entry(path) {
+            Entry::Occupied(_) => return Err(ControllerError::PathInUse(path)),
+            Entry::Vacant(entry) => entry.insert(Arc::new(Lun {
+                disk,
+                in_flight: AtomicUsize::new(0),
+                idle: event_listener::Event::new(),
+            })),
+        };
+        self.state.push_event(path, VIRTIO_SCSI_EVT_RESET_RESCAN);
+        Ok(())
+    }
+
+    /// Removes the disk at `path`, and notifies the guest.
+    ///
+    /// Commands that are already in flight to the disk still complete.
+    pub fn remove(&self, path: ScsiPath) -> Result<(), ControllerError> {
+        if self.state.luns.write().remove(&path).is_none() {
+            return Err(ControllerError::PathNotInUse(path));
+        }
+        self.state.push_event(path, VIRTIO_SCSI_EVT_RESET_REMOVED);
+        Ok(())
+    }
+}
+
+impl ControllerState {
+    /// Queues a transport reset event for `path`.
+    fn push_event(&self, path: ScsiPath, reason: u32) {
+        let mut pending = self.events.lock();
+        if pending.events.len() < MAX_PENDING_EVENTS {
+            pending.events.push_back(VirtioScsiEvent {
+                event: VIRTIO_SCSI_T_TRANSPORT_RESET.into(),
+                lun: lun_address(path),
+                reason: reason.into(),
+            });
+        } else {
+            pending.missed = true;
+        }
+        drop(pending);
+        self.events_ready.notify(usize::MAX);
+    }
+
+    /// Takes the next pending event, flagging it if earlier events were
+    /// dropped.
+    fn take_event(&self) -> Option<VirtioScsiEvent> {
+        let mut pending = self.events.lock();
+        let mut event = pending.events.pop_front()?;
+        if std::mem::take(&mut pending.missed) {
+            event.event = (event.event.get() | VIRTIO_SCSI_T_EVENTS_MISSED).into();
+        }
+        Some(event)
+    }
+
+    fn clear_events(&self) {
+        let mut pending = self.events.lock();
+        pending.events.clear();
+        pending.missed = false;
+    }
+
+    /// Waits until there is a pending event.
+    async fn wait_event(&self) {
+        loop {
+            let listener = self.events_ready.listen();
+            if !self.events.lock().events.is_empty() {
+                break;
+            }
+            listener.await;
+        }
+    }
+
+    fn target_exists(luns: &HashMap<ScsiPath, Arc<Lun>>, target: u8) -> bool {
+        luns.keys().any(|path| path.target == target)
+    }
+
+    /// Handles a task management function, returning the response code.
+    async fn task_management(&self, req: &VirtioScsiCtrlTmfReq) -> u8 {
+        let Some((target, lun)) = parse_lun(&req.lun) else {
+            return VIRTIO_SCSI_S_BAD_TARGET;
+        };
+        let subtype = req.subtype.get();
+        let reset: Vec<Arc<Lun>> = {
+            let luns = self.luns.read();
+            if !Self::target_exists(&luns, target) {
+                return VIRTIO_SCSI_S_BAD_TARGET;
+            }
+            match subtype {
+                VIRTIO_SCSI_T_TMF_LOGICAL_UNIT_RESET
+                | VIRTIO_SCSI_T_TMF_ABORT_TASK_SET
+                | VIRTIO_SCSI_T_TMF_CLEAR_TASK_SET => {
+                    let Some(lun) = u8::try_from(lun).ok().and_then(|lun| {
+                        luns.get(&ScsiPath {
+                            path: 0,
+                            target,
+                            lun,
+                        })
+                    }) else {
+                        return VIRTIO_SCSI_S_INCORRECT_LUN;
+                    };
+                    vec![lun.clone()]
+                }
+                VIRTIO_SCSI_T_TMF_I_T_NEXUS_RESET => luns
+                    .iter()
+                    .filter(|(path, _)| path.target == target)
+                    .map(|(_, lun)| lun.clone())
+                    .collect(),
+                // Individual commands cannot be aborted or queried, so the
+                // guest must escalate to a reset.
+                _ => return VIRTIO_SCSI_S_FUNCTION_REJECTED,
+            }
+        };
+        tracing::debug!(
+            target_id = target,
+            lun,
+            subtype,
+            "virtio-scsi task management"
+        );
+        for lun in reset {
+            lun.wait_idle().await;
+        }
+        VIRTIO_SCSI_S_FUNCTION_COMPLETE
+    }
+
+    /// Handles REPORT LUNS for `target`.
+    fn report_luns(&self, target: u8, buffers: &RequestBuffers<'_>) -> ScsiResult {
+        const HEADER_SIZE: usize = size_of::<scsi_defs::LunList>();
+        let mut luns: Vec<u8> = self
+            .luns
+            .read()
+            .keys()
+            .filter(|path| path.target == target)
+            .map(|path| path.lun)
+            .collect();
+        luns.sort_unstable();
+        let mut data: Vec<u64> = vec![0; luns.len() + 1];
+        let header = scsi_defs::LunList {
+            length: (luns.len() as u32 * 8).into(),
+            reserved: [0; 4],
+        };
+        data.as_mut_bytes()[..HEADER_SIZE].copy_from_slice(header.as_bytes());
+        for (i, lun) in luns.iter().enumerate() {
+            data[i + 1].as_mut_bytes()[..2].copy_from_slice(&(*lun as u16).to_be_bytes());
+        }
+        let tx = buffers.len().min(data.as_bytes().len());
+        write_result(buffers, &data.as_bytes()[..tx])
+    }
+}
+
+impl Lun {
+    /// Waits until there are no commands in flight.
+    async fn wait_idle(&self) {
+        loop {
+            let listener = self.idle.listen();
+            if self.in_flight.load(Ordering::Acquire) == 0 {
+                break;
+            }
+            listener.await;
+        }
+    }
+}
+
+/// Tracks a command in flight to a LUN, for task management.
+struct InFlight(Arc<Lun>);
+
+impl InFlight {
+    fn new(lun: Arc<Lun>) -> Self {
+        lun.in_flight.fetch_add(1, Ordering::AcqRel);
+        Self(lun)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.idle.notify(usize::MAX);
+        }
+    }
+}
+
+/// Parses a virtio-scsi LUN address (spec §5.6.6.1) into a target and LUN.
+///
+/// The first byte is 1, the second is the target, and the next two are the
+/// LUN in SAM flat or peripheral device addressing format.
+fn parse_lun(lun: &[u8; 8]) -> Option<(u8, u16)> {
+    (lun[0] == 1).then(|| (lun[1], u16::from_be_bytes([lun[2] & 0x3f, lun[3]])))
+}
+
+/// Returns the virtio-scsi LUN address for `path`, in flat addressing
+/// format.
+fn lun_address(path: ScsiPath) -> [u8; 8] {
+    [1, path.target, 0x40, path.lun, 0, 0, 0, 0]
+}
+
+/// The function of a queue.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Inspect)]
+enum QueueKind {
+    Control,
+    Event,
+    Request,
+}
+
+/// The CDB and sense sizes configured by the driver.
+#[derive(Debug, Copy, Clone, Inspect)]
+struct Sizes {
+    cdb: u32,
+    sense: u32,
+}
+
+/// Persistent worker state. Survives across queue stop and start.
+///
+/// The in-flight commands live here so that they can be drained after the
+/// task is stopped.
+#[derive(InspectMut)]
+struct ScsiWorker {
+    #[inspect(skip)]
+    state: Arc<ControllerState>,
+    stats: WorkerStats,
+    #[inspect(with = "FuturesUnordered::len")]
+    ios: FuturesUnordered<Pin<Box<dyn Future<Output = Completion> + Send>>>,
+}
+
+#[derive(InspectMut)]
+struct ScsiQueue {
+    kind: QueueKind,
+    queue: VirtioQueue,
+    #[inspect(skip)]
+    mem: GuestMemory,
+    sizes: Sizes,
+    hotplug: bool,
+}
+
+#[derive(Inspect, Default)]
+struct WorkerStats {
+    commands: Counter,
+    control_requests: Counter,
+    events: Counter,
+    failures: Counter,
+    bounce_ops: Counter,
+}
+
+/// A completed command or control request.
+struct Completion {
+    work: VirtioQueueCallbackWork,
+    bytes_written: u32,
+    failed: bool,
+    bounced: bool,
+}
+
+impl InspectTaskMut<ScsiQueue> for ScsiWorker {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>, state: Option<&mut ScsiQueue>) {
+        req.respond().merge(self).merge(state);
+    }
+}
+
+impl AsyncRun<ScsiQueue> for ScsiWorker {
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        state: &mut ScsiQueue,
+    ) -> Result<(), Cancelled> {
+        match state.kind {
+            QueueKind::Event => stop.until_stopped(self.run_events(state)).await,
+            QueueKind::Control | QueueKind::Request => {
+                stop.until_stopped(self.run_requests(state)).await
+            }
+        }
+    }
+}
+
+impl ScsiWorker {
+    fn finish(&mut self, queue: &mut VirtioQueue, completion: Completion) {
+        queue.complete(completion.work, completion.bytes_written);
+        if completion.failed {
+            self.stats.failures.increment();
+        }
+        if completion.bounced {
+            self.stats.bounce_ops.increment();
+        }
+    }
+
+    /// Polls all in-flight requests to completion, after the worker task has
+    /// been stopped.
+    fn poll_drain(&mut self, queue: &mut VirtioQueue, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            match self.ios.poll_next_unpin(cx) {
+                Poll::Ready(Some(completion)) => self.finish(queue, completion),
+                Poll::Ready(None) => return Poll::Ready(()),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    /// Processes the control queue or a request queue.
+    async fn run_requests(&mut self, state: &mut ScsiQueue) {
+        loop {
+            enum Event {
+                NewWork(Result<VirtioQueueCallbackWork, std::io::Error>),
+                Completed(Completion),
+            }
+
+            let event = poll_fn(|cx| {
+                if let Poll::Ready(Some(completion)) = self.ios.poll_next_unpin(cx) {
+                    return Poll::Ready(Event::Completed(completion));
+                }
+                if self.ios.len() < MAX_IO_DEPTH {
+                    if let Poll::Ready(item) = state.queue.poll_next_unpin(cx) {
+                        let item = item.expect("virtio queue stream never ends");
+                        return Poll::Ready(Event::NewWork(item));
+                    }
+                }
+                Poll::Pending
+            })
+            .await;
+
+            match event {
+                Event::NewWork(Ok(work)) => {
+                    let controller = self.state.clone();
+                    let mem = state.mem.clone();
+                    if state.kind == QueueKind::Control {
+                        self.stats.control_requests.increment();
+                        self.ios.push(Box::pin(async move {
+                            process_control(&controller, &mem, work).await
+                        }));
+                    } else {
+                        self.stats.commands.increment();
+                        let sizes = state.sizes;
+                        self.ios.push(Box::pin(async move {
+                            process_command(&controller, &mem, sizes, work).await
+                        }));
+                    }
+                }
+                Event::NewWork(Err(err)) => {
+                    tracelimit::error_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        "error reading from virtio queue"
+                    );
+                }
+                Event::Completed(completion) => {
+                    self.finish(&mut state.queue, completion);
+                }
+            }
+        }
+    }
+
+    /// Reports pending events on the event queue.
+    ///
+    /// Waits for an event before taking a buffer, so that stopping the queue
+    /// never strands a buffer.
+    async fn run_events(&mut self, state: &mut ScsiQueue) {
+        loop {
+            self.state.wait_event().await;
+            if !state.hotplug {
+                // The driver does not expect hotplug events.
+                self.state.clear_events();
+                continue;
+            }
+            let work = match state.queue.next().await {
+                Some(Ok(work)) => work,
+                Some(Err(err)) => {
+                    tracelimit::error_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        "error reading from virtio queue"
+                    );
+                    break;
+                }
+                None => break,
+            };
+            // The events may have been cleared by a reset while waiting for
+            // the buffer.
+            let event = self
+                .state
+                .take_event()
+                .unwrap_or_else(VirtioScsiEvent::new_zeroed);
+            let bytes_written = match work.write(&state.mem, event.as_bytes()) {
+                Ok(()) => {
+                    self.stats.events.increment();
+                    size_of_val(&event) as u32
+                }
+                Err(err) => {
+                    tracelimit::warn_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        "failed to write virtio-scsi event"
+                    );
+                    0
+                }
+            };
+            state.queue.complete(work, bytes_written);
+        }
+    }
+}
+
+impl VirtioDevice for VirtioScsiDevice {
+    fn traits(&self) -> DeviceTraits {
+        DeviceTraits {
+            device_id: virtio::spec::VirtioDeviceType::SCSI,
+            device_features: VirtioDeviceFeatures::new()
+                .with_device_specific_low(VIRTIO_SCSI_F_HOTPLUG)
+                .with_ring_event_idx(true)
+                .with_ring_indirect_desc(true)
+                .with_ring_packed(true),
+            max_queues: self.workers.len() as u16,
+            device_register_length: size_of::<VirtioScsiConfig>() as u32,
+            shared_memory: DeviceTraitsSharedMemory::default(),
+        }
+    }
+
+    async fn read_registers_u32(&mut self, offset: u16) -> u32 {
+        let offset = offset as usize;
+        self.config
+            .as_bytes()
+            .get(offset..offset + 4)
+            .map_or(0, |b| u32::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    async fn write_registers_u32(&mut self, offset: u16, val: u32) {
+        match offset {
+            CONFIG_SENSE_SIZE => self.config.sense_size = val.min(MAX_SENSE_SIZE),
+            CONFIG_CDB_SIZE => self.config.cdb_size = val.min(MAX_CDB_SIZE),
+            _ => {
+                tracelimit::warn_ratelimited!(offset, val, "unexpected virtio-scsi config write");
+            }
+        }
+    }
+
+    async fn start_queue(
+        &mut self,
+        idx: u16,
+        resources: QueueResources,
+        features: &VirtioDeviceFeatures,
+        initial_state: Option<QueueState>,
+    ) -> anyhow::Result<()> {
+        let (kind, name) = match idx {
+            VIRTIO_SCSI_CONTROL_QUEUE => (QueueKind::Control, "virtio-scsi-control".to_owned()),
+            VIRTIO_SCSI_EVENT_QUEUE => (QueueKind::Event, "virtio-scsi-event".to_owned()),
+            _ => (
+                QueueKind::Request,
+                format!(
+                    "virtio-scsi-request-{}",
+                    idx - VIRTIO_SCSI_REQUEST_QUEUE_BASE
+                ),
+            ),
+        };
+        let worker = self
+            .workers
+            .get_mut(idx as usize)
+            .with_context(|| format!("virtio-scsi queue {idx} is out of range"))?;
+
+        let queue_event = PolledWait::new(&self.driver, resources.event)
+            .context("failed to create queue event")?;
+        let queue = VirtioQueue::new(
+            *features,
+            resources.params,
+            resources.guest_memory.clone(),
+            resources.notify,
+            queue_event,
+            initial_state,
+        )
+        .context("failed to create virtio queue")?;
+
+        worker.insert(
+            self.driver.clone(),
+            name,
+            ScsiQueue {
+                kind,
+                queue,
+                mem: resources.guest_memory,
+                sizes: Sizes {
+                    cdb: self.config.cdb_size,
+                    sense: self.config.sense_size,
+                },
+                hotplug: features.device_specific_low() & VIRTIO_SCSI_F_HOTPLUG != 0,
+            },
+        );
+        worker.start();
+        Ok(())
+    }
+
+    async fn stop_queue(&mut self, idx: u16) -> Option<QueueState> {
+        let worker = self.workers.get_mut(idx as usize)?;
+        if !worker.has_state() {
+            return None;
+        }
+        worker.stop().await;
+        // Complete the in-flight requests before removing the queue.
+        let (inner, queue) = worker.get_mut();
+        let queue = &mut queue.expect("state exists after stop").queue;
+        poll_fn(|cx| inner.poll_drain(queue, cx)).await;
+        Some(worker.remove().queue.queue_state())
+    }
+
+    async fn reset(&mut self) {
+        self.config.sense_size = VIRTIO_SCSI_SENSE_DEFAULT_SIZE;
+        self.config.cdb_size = VIRTIO_SCSI_CDB_DEFAULT_SIZE;
+        // The driver rescans after a reset, so the pending events are stale.
+        self.state.clear_events();
+    }
+
+    fn supports_save_restore(&self) -> bool {
+        true
+    }
+}
+
+/// Reads a fixed-size control request from the readable descriptors.
+fn read_request<T: FromBytes + IntoBytes>(
+    mem: &GuestMemory,
+    work: &VirtioQueueCallbackWork,
+) -> Option<T> {
+    let mut req = T::new_zeroed();
+    match work.read(mem, req.as_mut_bytes()) {
+        Ok(len) if len == size_of::<T>() => Some(req),
+        _ => None,
+    }
+}
+
+/// Processes a control queue request (spec §5.6.6.2).
+async fn process_control(
+    state: &ControllerState,
+    mem: &GuestMemory,
+    work: VirtioQueueCallbackWork,
+) -> Completion {
+    let ty = read_request::<u32_le>(mem, &work).map(|ty| ty.get());
+    let resp = match ty {
+        Some(VIRTIO_SCSI_T_TMF) => {
+            let response = match read_request::<VirtioScsiCtrlTmfReq>(mem, &work) {
+                Some(req) => state.task_management(&req).await,
+                None => VIRTIO_SCSI_S_FAILURE,
+            };
+            vec![response]
+        }
+        Some(VIRTIO_SCSI_T_AN_QUERY | VIRTIO_SCSI_T_AN_SUBSCRIBE) => {
+            // No asynchronous notifications are supported.
+            VirtioScsiCtrlAnResp {
+                event_actual: 0.into(),
+                response: VIRTIO_SCSI_S_OK,
+            }
+            .as_bytes()
+            .to_vec()
+        }
+        ty => {
+            tracelimit::warn_ratelimited!(?ty, "unknown virtio-scsi control request");
+            vec![VIRTIO_SCSI_S_FAILURE]
+        }
+    };
+    let bytes_written = match work.write(mem, &resp) {
+        Ok(()) => resp.len() as u32,
+        Err(err) => {
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "failed to write virtio-scsi control response"
+            );
+            0
+        }
+    };
+    Completion {
+        work,
+        bytes_written,
+        failed: false,
+        bounced: false,
+    }
+}
+
+/// The outcome of a command, before it is written to the response.
+struct CommandResult {
+    response: u8,
+    status: ScsiStatus,
+    sense: Option<SenseData>,
+    residual: u32,
+    /// The number of bytes written to the data-in buffers.
+    tx_in: u32,
+    bounced: bool,
+}
+
+impl CommandResult {
+    /// A command that failed before reaching the target.
+    fn response(response: u8) -> Self {
+        Self {
+            response,
+            status: ScsiStatus::GOOD,
+            sense: None,
+            residual: 0,
+            tx_in: 0,
+            bounced: false,
+        }
+    }
+}
+
+/// Processes a request queue command (spec §5.6.6.1).
+async fn process_command(
+    state: &ControllerState,
+    mem: &GuestMemory,
+    sizes: Sizes,
+    work: VirtioQueueCallbackWork,
+) -> Completion {
+    let result = execute_command(state, mem, sizes, &work).await;
+    let resp = VirtioScsiCmdResp {
+        sense_len: 0.into(),
+        residual: result.residual.into(),
+        status_qualifier: 0.into(),
+        status: result.status.0,
+        response: result.response,
+    };
+    let mut buf = resp.as_bytes().to_vec();
+    if let Some(sense) = &result.sense {
+        let sense = &sense.as_bytes()[..(sizes.sense as usize).min(size_of::<SenseData>())];
+        buf.extend_from_slice(sense);
+        buf[..4].copy_from_slice(&(sense.len() as u32).to_le_bytes());
+    }
+    let bytes_written = match work.write(mem, &buf) {
+        Ok(()) if result.tx_in != 0 => {
+            (size_of::<VirtioScsiCmdResp>() as u32 + sizes.sense) + result.tx_in
+        }
+        Ok(()) => buf.len() as u32,
+        Err(err) => {
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "failed to write virtio-scsi response"
+            );
+            0
+        }
+    };
+    Completion {
+        work,
+        bytes_written,
+        failed: result.response != VIRTIO_SCSI_S_OK || result.status != ScsiStatus::GOOD,
+        bounced: result.bounced,
+    }
+}
+
+async fn execute_command(
+    state: &ControllerState,
+    mem: &GuestMemory,
+    sizes: Sizes,
+    work: &VirtioQueueCallbackWork,
+) -> CommandResult {
+    let req_len = size_of::<VirtioScsiCmdReq>() + sizes.cdb as usize;
+    let resp_len = size_of::<VirtioScsiCmdResp>() + sizes.sense as usize;
+    let readable = work.get_payload_length(false);
+    let writable = work.get_payload_length(true);
+    if readable < req_len as u64 || writable < resp_len as u64 {
+        return CommandResult::response(VIRTIO_SCSI_S_FAILURE);
+    }
+
+    let mut buf = vec![0; req_len];
+    if let Err(err) = work.read(mem, &mut buf) {
+        tracelimit::warn_ratelimited!(
+            error = &err as &dyn std::error::Error,
+            "failed to read virtio-scsi request"
+        );
+        return CommandResult::response(VIRTIO_SCSI_S_FAILURE);
+    }
+    let (req, cdb) = VirtioScsiCmdReq::read_from_prefix(&buf).unwrap();
+    let Some((target, lun)) = parse_lun(&req.lun) else {
+        return CommandResult::response(VIRTIO_SCSI_S_BAD_TARGET);
+    };
+
+    let data_out_len = readable - req_len as u64;
+    let data_in_len = writable - resp_len as u64;
+    if data_out_len != 0 && data_in_len != 0 {
+        // Bidirectional commands require VIRTIO_SCSI_F_INOUT, which is not
+        // offered.
+        return CommandResult::response(VIRTIO_SCSI_S_FAILURE);
+    }
+    let is_read = data_in_len != 0;
+    let data_len = data_out_len.max(data_in_len);
+    if data_len > MAX_SECTORS as u64 * 512 {
+        return CommandResult::response(VIRTIO_SCSI_S_OVERRUN);
+    }
+
+    let mut request = Request {
+        cdb: [0; 16],
+        srb_flags: 0,
+    };
+    let cdb_len = cdb.len().min(request.cdb.len());
+    request.cdb[..cdb_len].copy_from_slice(&cdb[..cdb_len]);
+
+    let lun_path = u8::try_from(lun).ok().map(|lun| ScsiPath {
+        path: 0,
+        target,
+        lun,
+    });
+    let disk = {
+        let luns = state.luns.read();
+        if !ControllerState::target_exists(&luns, target) {
+            return CommandResult::response(VIRTIO_SCSI_S_BAD_TARGET);
+        }
+        lun_path.and_then(|path| luns.get(&path).cloned())
+    };
+
+    let skip = if is_read { resp_len } else { req_len } as u64;
+    let regions: Vec<_> = data_regions(&work.payload, is_read, skip, data_len).collect();
+    let (mut bounce, gpns, offset, len) = match try_build_gpn_list(&regions) {
+        Some((gpns, offset, len)) => (None, gpns, offset, len),
+        None => {
+            let len = data_len as usize;
+            let mut bounce = GuestMemory::allocate(len);
+            if !is_read
+                && copy_regions(bounce.inner_buf_mut().unwrap(), mem, &regions, true).is_err()
+            {
+                return CommandResult::response(VIRTIO_SCSI_S_FAILURE);
+            }
+            let gpns = (0..len.div_ceil(guestmem::PAGE_SIZE) as u64).collect();
+            (Some(bounce), gpns, 0, len)
+        }
+    };
+    let Some(range) = PagedRange::new(offset, len, &gpns) else {
+        return CommandResult::response(VIRTIO_SCSI_S_FAILURE);
+    };
+    let buffers = RequestBuffers::new(bounce.as_ref().unwrap_or(mem), range, is_read);
+
+    let result = if request.scsiop() == ScsiOp::REPORT_LUNS {
+        state.report_luns(target, &buffers)
+    } else if let Some(lun) = disk {
+        let _in_flight = InFlight::new(lun.clone());
+        lun.disk.execute_scsi(&buffers, &request).await
+    } else if request.scsiop() == ScsiOp::INQUIRY {
+        absent_lun_inquiry(lun, &request, &buffers)
+    } else {
+        ScsiResult {
+            scsi_status: ScsiStatus::CHECK_CONDITION,
+            srb_status: SrbStatus::INVALID_LUN,
+            tx: 0,
+            sense_data: Some(SenseData::new(
+                SenseKey::ILLEGAL_REQUEST,
+                AdditionalSenseCode::INVALID_LUN,
+                0,
+            )),
+        }
+    };
+
+    let tx = result.tx.min(len);
+    let bounced = bounce.is_some();
+    if is_read
+        && let Some(bounce) = &mut bounce
+        && copy_regions(bounce.inner_buf_mut().unwrap(), mem, &regions, false).is_err()
+    {
+        return CommandResult::response(VIRTIO_SCSI_S_FAILURE);
+    }
+
+    CommandResult {
+        response: VIRTIO_SCSI_S_OK,
+        status: result.scsi_status,
+        sense: result.sense_data,
+        residual: (data_len - tx as u64) as u32,
+        tx_in: if is_read { tx as u32 } else { 0 },
+        bounced,
+    }
+}
+
+/// Handles INQUIRY for a LUN that is not present on a present target, so
+/// that the guest can enumerate the target's LUNs.
+fn absent_lun_inquiry(lun: u16, request: &Request, buffers: &RequestBuffers<'_>) -> ScsiResult {
+    const LOGICAL_UNIT_NOT_PRESENT_DEVICE: u8 = 0x7F;
+
+    let cdb = scsi_defs::CdbInquiry::read_from_prefix(&request.cdb)
+        .unwrap()
+        .0;
+    let allocation_length = cdb.allocation_length.get() as usize;
+    if cdb.flags.vpd()
+        || cdb.page_code != 0
+        || allocation_length < size_of::<scsi_defs::InquiryDataHeader>()
+    {
+        // VPD pages cannot be reported for a LUN that is not present.
+        return check_condition(
+            SrbStatus::INVALID_REQUEST,
+            illegal_request_sense(AdditionalSenseCode::INVALID_CDB),
+        );
+    }
+
+    let mut data = scsidisk::INQUIRY_DATA_TEMPLATE;
+    data.header.device_type = LOGICAL_UNIT_NOT_PRESENT_DEVICE;
+    if lun != 0 {
+        // These are only reported for LUN 0.
+        data.vendor_id = [0; 8];
+        data.product_id = [0; 16];
+        data.product_revision_level = [0; 4];
+    }
+    let tx = allocation_length.min(size_of_val(&data)).min(buffers.len());
+    write_result(buffers, &data.as_bytes()[..tx])
+}
+
+fn check_condition(srb_status: SrbStatus, sense: SenseData) -> ScsiResult {
+    ScsiResult {
+        scsi_status: ScsiStatus::CHECK_CONDITION,
+        srb_status,
+        tx: 0,
+        sense_data: Some(sense),
+    }
+}
+
+/// Writes `data` to the data-in buffers.
+fn write_result(buffers: &RequestBuffers<'_>, data: &[u8]) -> ScsiResult {
+    match buffers.writer().write(data) {
+        Ok(()) => ScsiResult {
+            scsi_status: ScsiStatus::GOOD,
+            srb_status: SrbStatus::SUCCESS,
+            tx: data.len(),
+            sense_data: None,
+        },
+        Err(_) => check_condition(
+            SrbStatus::INVALID_REQUEST,
+            illegal_request_sense(AdditionalSenseCode::INVALID_CDB),
+        ),
+    }
+}
+
+/// Copies data between scattered guest regions and a contiguous bounce
+/// buffer, in the direction given by `to_bounce`.
+fn copy_regions(
+    bounce: &mut [u8],
+    mem: &GuestMemory,
+    regions: &[DataRegion],
+    to_bounce: bool,
+) -> Result<(), GuestMemoryError> {
+    let mut offset = 0;
+    for region in regions {
+        let buf = &mut bounce[offset..offset + region.len as usize];
+        if to_bounce {
+            mem.read_at(region.addr, buf)?;
+        } else {
+            mem.write_at(region.addr, buf)?;
+        }
+        offset += buf.len();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use pal_async::timer::PolledTimer;
+    use scsidisk::SimpleScsiDisk;
+    use std::time::Duration;
+    use test_with_tracing::test;
+    use virtio::test_helpers::TestQueue;
+    use virtio::test_helpers::read_used;
+    use virtio::test_helpers::start_queue;
+    use vmcore::vm_task::SingleDriverBackend;
+
+    const QUEUE_SIZE: u16 = 16;
+    const REQ_GPA: u64 = 0x10000;
+    const RESP_GPA: u64 = 0x11000;
+    const DATA_GPA: u64 = 0x12000;
+    const TOTAL_MEM_SIZE: usize = 0x40000;
+
+    const REQ_LEN: u32 = (size_of::<VirtioScsiCmdReq>() as u32) + VIRTIO_SCSI_CDB_DEFAULT_SIZE;
+    const RESP_LEN: u32 = (size_of::<VirtioScsiCmdResp>() as u32) + VIRTIO_SCSI_SENSE_DEFAULT_SIZE;
+
+    struct TestHarness {
+        device: VirtioScsiDevice,
+        mem: GuestMemory,
+        driver: DefaultDriver,
+        queues: Vec<TestQueue>,
+    }
+
+    impl TestHarness {
+        async fn new(driver: &DefaultDriver) -> Self {
+            let mem = GuestMemory::allocate(TOTAL_MEM_SIZE);
+            let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+            let mut device = VirtioScsiDevice::new(&driver_source, 1);
+            let features =
+                VirtioDeviceFeatures::new().with_device_specific_low(VIRTIO_SCSI_F_HOTPLUG);
+            let mut queues = Vec::new();
+            for idx in 0..3 {
+                queues.push(start_queue(&mut device, &mem, idx, QUEUE_SIZE, &features).await);
+            }
+            Self {
+                device,
+                mem,
+                driver: driver.clone(),
+                queues,
+            }
+        }
+
+        fn attach(&self, target: u8, lun: u8) {
+            let disk = SimpleScsiDisk::new(
+                disklayer_ram::ram_disk(0x100000, false).unwrap(),
+                Default::default(),
+            );
+            self.device
+                .controller()
+                .attach(
+                    ScsiPath {
+                        path: 0,
+                        target,
+                        lun,
+                    },
+                    Arc::new(disk),
+                )
+                .unwrap();
+        }
+
+        /// Posts a descriptor chain of `(gpa, len, writeable)` buffers on
+        /// queue `idx`.
+        fn post(&mut self, idx: usize, buffers: &[(u64, u32, bool)]) {
+            self.queues[idx].post(&self.mem, 0, buffers);
+        }
+
+        /// Waits for a completion on queue `idx`, returning the used length.
+        async fn wait(&mut self, idx: usize) -> u32 {
+            self.queues[idx].wait(&self.driver, &self.mem).await.1
+        }
+
+        /// Issues a command and returns the response header and sense data.
+        async fn command(
+            &mut self,
+            lun: [u8; 8],
+            cdb: &[u8],
+            data_out: Option<&[u8]>,
+            data_in_len: u32,
+        ) -> (VirtioScsiCmdResp, Vec<u8>) {
+            let mut req = [0; REQ_LEN as usize];
+            let header = VirtioScsiCmdReq {
+                lun,
+                id: 1.into(),
+                task_attr: 0,
+                prio: 0,
+                crn: 0,
+            };
+            req[..size_of_val(&header)].copy_from_slice(header.as_bytes());
+            req[size_of_val(&header)..][..cdb.len()].copy_from_slice(cdb);
+            self.mem.write_at(REQ_GPA, &req).unwrap();
+
+            let mut buffers = vec![(REQ_GPA, REQ_LEN, false)];
+            if let Some(data) = data_out {
+                self.mem.write_at(DATA_GPA, data).unwrap();
+                buffers.push((DATA_GPA, data.len() as u32, false));
+            }
+            buffers.push((RESP_GPA, RESP_LEN, true));
+            if data_in_len != 0 {
+                buffers.push((DATA_GPA, data_in_len, true));
+            }
+            self.post(2, &buffers);
+            self.wait(2).await;
+
+            let mut resp = [0; RESP_LEN as usize];
+            self.mem.read_at(RESP_GPA, &mut resp).unwrap();
+            let (header, sense) = VirtioScsiCmdResp::read_from_prefix(&resp).unwrap();
+            let sense = sense[..header.sense_len.get() as usize].to_vec();
+            (header, sense)
+        }
+
+        /// Issues a task management function and returns the response.
+        fn post_tmf(&mut self, subtype: u32, lun: [u8; 8]) {
+            let req = VirtioScsiCtrlTmfReq {
+                ty: VIRTIO_SCSI_T_TMF.into(),
+                subtype: subtype.into(),
+                lun,
+                id: 0.into(),
+            };
+            self.mem.write_at(REQ_GPA, req.as_bytes()).unwrap();
+            self.post(
+                0,
+                &[
+                    (REQ_GPA, size_of_val(&req) as u32, false),
+                    (RESP_GPA, 1, true),
+                ],
+            );
+        }
+
+        async fn tmf(&mut self, subtype: u32, lun: [u8; 8]) -> u8 {
+            self.post_tmf(subtype, lun);
+            self.wait(0).await;
+            let mut response = [0];
+            self.mem.read_at(RESP_GPA, &mut response).unwrap();
+            response[0]
+        }
+    }
+
+    fn lun(target: u8, lun: u8) -> [u8; 8] {
+        lun_address(ScsiPath {
+            path: 0,
+            target,
+            lun,
+        })
+    }
+
+    fn cdb10(op: ScsiOp, lba: u32, blocks: u16) -> [u8; 10] {
+        let mut cdb = [0; 10];
+        cdb[0] = op.0;
+        cdb[2..6].copy_from_slice(&lba.to_be_bytes());
+        cdb[7..9].copy_from_slice(&blocks.to_be_bytes());
+        cdb
+    }
+
+    #[test]
+    fn lun_addressing() {
+        let addr = lun(3, 7);
+        assert_eq!(parse_lun(&addr), Some((3, 7)));
+        assert_eq!(parse_lun(&[1, 2, 0x01, 0x02, 0, 0, 0, 0]), Some((2, 0x102)));
+        assert_eq!(parse_lun(&[0xc1, 0x01, 0, 0, 0, 0, 0, 0]), None);
+    }
+
+    #[async_test]
+    async fn read_write(driver: DefaultDriver) {
+        let mut harness = TestHarness::new(&driver).await;
+        harness.attach(0, 0);
+
+        let data: Vec<u8> = (0..512).map(|i| i as u8).collect();
+        let (resp, _) = harness
+            .command(lun(0, 0), &cdb10(ScsiOp::WRITE, 4, 1), Some(&data), 0)
+            .await;
+        assert_eq!(resp.response, VIRTIO_SCSI_S_OK);
+        assert_eq!(resp.status, ScsiStatus::GOOD.0);
+        assert_eq!(resp.residual.get(), 0);
+
+        harness.mem.write_at(DATA_GPA, &[0; 512]).unwrap();
+        let (resp, _) = harness
+            .command(lun(0, 0), &cdb10(ScsiOp::READ, 4, 1), None, 512)
+            .await;
+        assert_eq!(resp.response, VIRTIO_SCSI_S_OK);
+        assert_eq!(resp.status, ScsiStatus::GOOD.0);
+        let mut read = vec![0; 512];
+        harness.mem.read_at(DATA_GPA, &mut read).unwrap();
+        assert_eq!(read, data);
+    }
+
+    #[async_test]
+    async fn bad_target(driver: DefaultDriver) {
+        let mut harness = TestHarness::new(&driver).await;
+        harness.attach(0, 0);
+
+        let (resp, _) = harness
+            .command(lun(1, 0), &[ScsiOp::TEST_UNIT_READY.0], None, 0)
+            .await;
+        assert_eq!(resp.response, VIRTIO_SCSI_S_BAD_TARGET);
+    }
+
+    #[async_test]
+    async fn report_luns(driver: DefaultDriver) {
+        let mut harness = TestHarness::new(&driver).await;
+        harness.attach(0, 3);
+        harness.attach(0, 0);
+        harness.attach(1, 1);
+
+        let mut cdb = [0; 12];
+        cdb[0] = ScsiOp::REPORT_LUNS.0;
+        cdb[6..10].copy_from_slice(&64u32.to_be_bytes());
+        let (resp, _) = harness.command(lun(0, 0), &cdb, None, 64).await;
+        assert_eq!(resp.response, VIRTIO_SCSI_S_OK);
+        assert_eq!(resp.residual.get(), 64 - 24);
+        let mut data = [0; 24];
+        harness.mem.read_at(DATA_GPA, &mut data).unwrap();
+        assert_eq!(data[..4], 16u32.to_be_bytes());
+        assert_eq!(data[8..10], [0, 0]);
+        assert_eq!(data[16..18], [0, 3]);
+    }
+
+    #[async_test]
+    async fn absent_lun(driver: DefaultDriver) {
+        let mut harness = TestHarness::new(&driver).await;
+        harness.attach(0, 0);
+
+        let cdb = [ScsiOp::INQUIRY.0, 0, 0, 0, 96, 0];
+        let (resp, _) = harness.command(lun(0, 1), &cdb, None, 96).await;
+        assert_eq!(resp.response, VIRTIO_SCSI_S_OK);
+        assert_eq!(resp.status, ScsiStatus::GOOD.0);
+        let mut device_type = [0];
+        harness.mem.read_at(DATA_GPA, &mut device_type).unwrap();
+        assert_eq!(device_type[0], 0x7f);
+
+        let (resp, sense) = harness
+            .command(lun(0, 1), &[ScsiOp::TEST_UNIT_READY.0], None, 0)
+            .await;
+        assert_eq!(resp.response, VIRTIO_SCSI_S_OK);
+        assert_eq!(resp.status, ScsiStatus::CHECK_CONDITION.0);
+        let sense = SenseData::read_from_prefix(&sense).unwrap().0;
+        assert_eq!(sense.header.sense_key, SenseKey::ILLEGAL_REQUEST);
+        assert_eq!(
+            sense.additional_sense_code,
+            AdditionalSenseCode::INVALID_LUN
+        );
+    }
+
+    #[async_test]
+    async fn hotplug_events(driver: DefaultDriver) {
+        let mut harness = TestHarness::new(&driver).await;
+        let event_len = size_of::<VirtioScsiEvent>() as u32;
+
+        harness.post(1, &[(DATA_GPA, event_len, true)]);
+        harness.attach(2, 1);
+        assert_eq!(harness.wait(1).await, event_len);
+        let event = harness.mem.read_plain::<VirtioScsiEvent>(DATA_GPA).unwrap();
+        assert_eq!(event.event.get(), VIRTIO_SCSI_T_TRANSPORT_RESET);
+        assert_eq!(event.reason.get(), VIRTIO_SCSI_EVT_RESET_RESCAN);
+        assert_eq!(event.lun, lun(2, 1));
+
+        // An event is held until there is a buffer for it.
+        harness
+            .device
+            .controller()
+            .remove(ScsiPath {
+                path: 0,
+                target: 2,
+                lun: 1,
+            })
+            .unwrap();
+        harness.post(1, &[(DATA_GPA, event_len, true)]);
+        assert_eq!(harness.wait(1).await, event_len);
+        let event = harness.mem.read_plain::<VirtioScsiEvent>(DATA_GPA).unwrap();
+        assert_eq!(event.reason.get(), VIRTIO_SCSI_EVT_RESET_REMOVED);
+    }
+
+    #[async_test]
+    async fn task_management(driver: DefaultDriver) {
+        let mut harness = TestHarness::new(&driver).await;
+        harness.attach(0, 0);
+
+        assert_eq!(
+            harness.tmf(VIRTIO_SCSI_T_TMF_ABORT_TASK, lun(0, 0)).await,
+            VIRTIO_SCSI_S_FUNCTION_REJECTED
+        );
+        assert_eq!(
+            harness
+                .tmf(VIRTIO_SCSI_T_TMF_LOGICAL_UNIT_RESET, lun(0, 1))
+                .await,
+            VIRTIO_SCSI_S_INCORRECT_LUN
+        );
+        assert_eq!(
+            harness
+                .tmf(VIRTIO_SCSI_T_TMF_LOGICAL_UNIT_RESET, lun(1, 0))
+                .await,
+            VIRTIO_SCSI_S_BAD_TARGET
+        );
+
+        // A reset waits for the commands in flight to the LUN.
+        let path = ScsiPath {
+            path: 0,
+            target: 0,
+            lun: 0,
+        };
+        let in_flight = InFlight::new(harness.device.state.luns.read()[&path].clone());
+        harness.post_tmf(VIRTIO_SCSI_T_TMF_LOGICAL_UNIT_RESET, lun(0, 0));
+        PolledTimer::new(&driver)
+            .sleep(Duration::from_millis(50))
+            .await;
+        let queue = &mut harness.queues[0];
+        assert!(
+            read_used(
+                &harness.mem,
+                queue.used_addr,
+                QUEUE_SIZE,
+                &mut queue.used_idx
+            )
+            .is_none()
+        );
+        drop(in_flight);
+        harness.wait(0).await;
+        let mut response = [0];
+        harness.mem.read_at(RESP_GPA, &mut response).unwrap();
+        assert_eq!(response[0], VIRTIO_SCSI_S_FUNCTION_COMPLETE);
+    }
+}
@@ -0,0 +1,63 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio memory balloon device specification constants and types.
+//!
+//! Based on OASIS VIRTIO v1.2, Section 5.5.
+//! <https://docs.oasis-open.org/virtio/virtio/v1.2/cs01/virtio-v1.2-cs01.html>
+
+use crate::u16_le;
+use crate::u64_le;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+// Feature bits (spec §5.5.3). These are device-specific bits in bank 0 (bits 0..23).
+/// Host has to be told before pages from the balloon are used.
+pub const VIRTIO_BALLOON_F_MUST_TELL_HOST: u32 = 1 << 0;
+/// A virtqueue for reporting guest memory statistics is present.
+pub const VIRTIO_BALLOON_F_STATS_VQ: u32 = 1 << 1;
+/// Deflate the balloon on guest out of memory condition.
+pub const VIRTIO_BALLOON_F_DEFLATE_ON_OOM: u32 = 1 << 2;
+/// A virtqueue for reporting free pages is present.
+pub const VIRTIO_BALLOON_F_PAGE_REPORTING: u32 = 1 << 5;
+
+/// The page frame numbers in the inflate and deflate queues are in units of
+/// this many bytes, whatever the guest's page size.
+pub const VIRTIO_BALLOON_PFN_SHIFT: u32 = 12;
+
+/// Virtio balloon device config space layout (spec §5.5.4).
+///
+/// Only the fields used without free page hinting or page poisoning are
+/// included.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioBalloonConfig {
+    /// The number of pages the device wants in the balloon.
+    pub num_pages: u32,
+    /// The number of pages in the balloon, written by the driver.
+    pub actual: u32,
+}
+
+// Memory statistic tags (spec §5.5.6.3).
+pub const VIRTIO_BALLOON_S_SWAP_IN: u16 = 0;
+pub const VIRTIO_BALLOON_S_SWAP_OUT: u16 = 1;
+pub const VIRTIO_BALLOON_S_MAJFLT: u16 = 2;
+pub const VIRTIO_BALLOON_S_MINFLT: u16 = 3;
+pub const VIRTIO_BALLOON_S_MEMFREE: u16 = 4;
+pub const VIRTIO_BALLOON_S_MEMTOT: u16 = 5;
+pub const VIRTIO_BALLOON_S_AVAIL: u16 = 6;
+pub const VIRTIO_BALLOON_S_CACHES: u16 = 7;
+pub const VIRTIO_BALLOON_S_HTLB_PGALLOC: u16 = 8;
+pub const VIRTIO_BALLOON_S_HTLB_PGFAIL: u16 = 9;
+
+/// A memory statistic in the stats queue (spec §5.5.6.3).
+///
+/// The fields are unaligned, so the structure is 10 bytes, as in the spec.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioBalloonStat {
+    pub tag: u16_le,
+    pub val: u64_le,
+}
@@ -0,0 +1,251 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio GPU device specification constants and types.
+//!
+//! Based on OASIS VIRTIO v1.2, Section 5.7.
+//! <https://docs.oasis-open.org/virtio/virtio/v1.2/cs01/virtio-v1.2-cs01.html>
+//!
+//! Only the 2D subset of the device is described here.
+
+use crate::u32_le;
+use crate::u64_le;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+// Feature bits (spec §5.7.3).
+/// 3D mode is supported.
+pub const VIRTIO_GPU_F_VIRGL: u32 = 1 << 0;
+/// EDID is supported.
+pub const VIRTIO_GPU_F_EDID: u32 = 1 << 1;
+
+/// The index of the control queue.
+pub const VIRTIO_GPU_CONTROL_QUEUE: u16 = 0;
+/// The index of the cursor queue.
+pub const VIRTIO_GPU_CURSOR_QUEUE: u16 = 1;
+
+/// The maximum number of scanouts.
+pub const VIRTIO_GPU_MAX_SCANOUTS: usize = 16;
+
+/// Virtio GPU device config space layout (spec §5.7.4).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuConfig {
+    /// Pending events, read-only to the driver.
+    pub events_read: u32,
+    /// Events to clear, written by the driver.
+    pub events_clear: u32,
+    /// The number of scanouts.
+    pub num_scanouts: u32,
+    /// The number of 3D capability sets.
+    pub num_capsets: u32,
+}
+
+/// The display configuration has changed.
+pub const VIRTIO_GPU_EVENT_DISPLAY: u32 = 1 << 0;
+
+// 2D commands (spec §5.7.6.7).
+pub const VIRTIO_GPU_CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+pub const VIRTIO_GPU_CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+pub const VIRTIO_GPU_CMD_RESOURCE_UNREF: u32 = 0x0102;
+pub const VIRTIO_GPU_CMD_SET_SCANOUT: u32 = 0x0103;
+pub const VIRTIO_GPU_CMD_RESOURCE_FLUSH: u32 = 0x0104;
+pub const VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+pub const VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+pub const VIRTIO_GPU_CMD_RESOURCE_DETACH_BACKING: u32 = 0x0107;
+pub const VIRTIO_GPU_CMD_GET_CAPSET_INFO: u32 = 0x0108;
+pub const VIRTIO_GPU_CMD_GET_CAPSET: u32 = 0x0109;
+pub const VIRTIO_GPU_CMD_GET_EDID: u32 = 0x010a;
+pub const VIRTIO_GPU_CMD_RESOURCE_ASSIGN_UUID: u32 = 0x010b;
+pub const VIRTIO_GPU_CMD_RESOURCE_CREATE_BLOB: u32 = 0x010c;
+pub const VIRTIO_GPU_CMD_SET_SCANOUT_BLOB: u32 = 0x010d;
+
+// Cursor commands.
+pub const VIRTIO_GPU_CMD_UPDATE_CURSOR: u32 = 0x0300;
+pub const VIRTIO_GPU_CMD_MOVE_CURSOR: u32 = 0x0301;
+
+// Success responses.
+pub const VIRTIO_GPU_RESP_OK_NODATA: u32 = 0x1100;
+pub const VIRTIO_GPU_RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+pub const VIRTIO_GPU_RESP_OK_CAPSET_INFO: u32 = 0x1102;
+pub const VIRTIO_GPU_RESP_OK_CAPSET: u32 = 0x1103;
+pub const VIRTIO_GPU_RESP_OK_EDID: u32 = 0x1104;
+
+// Error responses.
+pub const VIRTIO_GPU_RESP_ERR_UNSPEC: u32 = 0x1200;
+pub const VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY: u32 = 0x1201;
+pub const VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID: u32 = 0x1202;
+pub const VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID: u32 = 0x1203;
+pub const VIRTIO_GPU_RESP_ERR_INVALID_CONTEXT_ID: u32 = 0x1204;
+pub const VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER: u32 = 0x1205;
+
+/// The driver requests a fence; the device echoes the flag and fence ID in
+/// the response once the command has completed.
+pub const VIRTIO_GPU_FLAG_FENCE: u32 = 1 << 0;
+
+/// The header of every request and response (spec §5.7.6.7).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuCtrlHdr {
+    pub ty: u32_le,
+    pub flags: u32_le,
+    pub fence_id: u64_le,
+    pub ctx_id: u32_le,
+    pub ring_idx: u8,
+    pub padding: [u8; 3],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuRect {
+    pub x: u32_le,
+    pub y: u32_le,
+    pub width: u32_le,
+    pub height: u32_le,
+}
+
+/// A scanout mode in [`VirtioGpuRespDisplayInfo`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuDisplayOne {
+    pub r: VirtioGpuRect,
+    pub enabled: u32_le,
+    pub flags: u32_le,
+}
+
+/// The response to [`VIRTIO_GPU_CMD_GET_DISPLAY_INFO`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuRespDisplayInfo {
+    pub hdr: VirtioGpuCtrlHdr,
+    pub pmodes: [VirtioGpuDisplayOne; VIRTIO_GPU_MAX_SCANOUTS],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuGetEdid {
+    pub hdr: VirtioGpuCtrlHdr,
+    pub scanout: u32_le,
+    pub padding: u32_le,
+}
+
+/// The response to [`VIRTIO_GPU_CMD_GET_EDID`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuRespEdid {
+    pub hdr: VirtioGpuCtrlHdr,
+    pub size: u32_le,
+    pub padding: u32_le,
+    pub edid: [u8; 1024],
+}
+
+// Pixel formats. The names give the order of the components in memory.
+pub const VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM: u32 = 1;
+pub const VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM: u32 = 2;
+pub const VIRTIO_GPU_FORMAT_A8R8G8B8_UNORM: u32 = 3;
+pub const VIRTIO_GPU_FORMAT_X8R8G8B8_UNORM: u32 = 4;
+pub const VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM: u32 = 67;
+pub const VIRTIO_GPU_FORMAT_X8B8G8R8_UNORM: u32 = 68;
+pub const VIRTIO_GPU_FORMAT_A8B8G8R8_UNORM: u32 = 121;
+pub const VIRTIO_GPU_FORMAT_R8G8B8X8_UNORM: u32 = 134;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuResourceCreate2d {
+    pub hdr: VirtioGpuCtrlHdr,
+    pub resource_id: u32_le,
+    pub format: u32_le,
+    pub width: u32_le,
+    pub height: u32_le,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuResourceUnref {
+    pub hdr: VirtioGpuCtrlHdr,
+    pub resource_id: u32_le,
+    pub padding: u32_le,
+}
+
+/// Sets the resource shown by a scanout. A resource ID of zero disables the
+/// scanout.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuSetScanout {
+    pub hdr: VirtioGpuCtrlHdr,
+    pub r: VirtioGpuRect,
+    pub scanout_id: u32_le,
+    pub resource_id: u32_le,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuResourceFlush {
+    pub hdr: VirtioGpuCtrlHdr,
+    pub r: VirtioGpuRect,
+    pub resource_id: u32_le,
+    pub padding: u32_le,
+}
+
+/// Copies a rectangle from a resource's guest backing to the resource.
+/// `offset` is the offset of the rectangle's first byte in the backing.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuTransferToHost2d {
+    pub hdr: VirtioGpuCtrlHdr,
+    pub r: VirtioGpuRect,
+    pub offset: u64_le,
+    pub resource_id: u32_le,
+    pub padding: u32_le,
+}
+
+/// Attaches guest memory to a resource. Followed by `nr_entries` instances
+/// of [`VirtioGpuMemEntry`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuResourceAttachBacking {
+    pub hdr: VirtioGpuCtrlHdr,
+    pub resource_id: u32_le,
+    pub nr_entries: u32_le,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuMemEntry {
+    pub addr: u64_le,
+    pub length: u32_le,
+    pub padding: u32_le,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuResourceDetachBacking {
+    pub hdr: VirtioGpuCtrlHdr,
+    pub resource_id: u32_le,
+    pub padding: u32_le,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuCursorPos {
+    pub scanout_id: u32_le,
+    pub x: u32_le,
+    pub y: u32_le,
+    pub padding: u32_le,
+}
+
+/// A request on the cursor queue, for both [`VIRTIO_GPU_CMD_UPDATE_CURSOR`]
+/// and [`VIRTIO_GPU_CMD_MOVE_CURSOR`]. The resource and hot spot are only
+/// used by the former.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioGpuUpdateCursor {
+    pub hdr: VirtioGpuCtrlHdr,
+    pub pos: VirtioGpuCursorPos,
+    pub resource_id: u32_le,
+    pub hot_x: u32_le,
+    pub hot_y: u32_le,
+    pub padding: u32_le,
+}
@@ -0,0 +1,79 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio input device specification constants and types.
+//!
+//! Based on OASIS VIRTIO v1.2, Section 5.8.
+//! <https://docs.oasis-open.org/virtio/virtio/v1.2/cs01/virtio-v1.2-cs01.html>
+//!
+//! The event types and codes carried by the device are those of the Linux
+//! evdev interface.
+
+use crate::u16_le;
+use crate::u32_le;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+/// The index of the event queue, on which the device sends input events.
+pub const VIRTIO_INPUT_EVENT_QUEUE: u16 = 0;
+/// The index of the status queue, on which the driver sends status updates
+/// such as LED changes.
+pub const VIRTIO_INPUT_STATUS_QUEUE: u16 = 1;
+
+// Config space selectors (spec §5.8.4).
+pub const VIRTIO_INPUT_CFG_UNSET: u8 = 0x00;
+pub const VIRTIO_INPUT_CFG_ID_NAME: u8 = 0x01;
+pub const VIRTIO_INPUT_CFG_ID_SERIAL: u8 = 0x02;
+pub const VIRTIO_INPUT_CFG_ID_DEVIDS: u8 = 0x03;
+pub const VIRTIO_INPUT_CFG_PROP_BITS: u8 = 0x10;
+pub const VIRTIO_INPUT_CFG_EV_BITS: u8 = 0x11;
+pub const VIRTIO_INPUT_CFG_ABS_INFO: u8 = 0x12;
+
+/// The header of the config space (spec §5.8.4), followed by
+/// [`VIRTIO_INPUT_CONFIG_DATA_SIZE`] bytes of data for the selected item.
+///
+/// The driver writes `select` and `subsel`, and the device reports the size
+/// of the selected data in `size`, or zero if there is none.
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioInputConfigHeader {
+    pub select: u8,
+    pub subsel: u8,
+    pub size: u8,
+    pub reserved: [u8; 5],
+}
+
+/// The size of the data union in the config space.
+pub const VIRTIO_INPUT_CONFIG_DATA_SIZE: usize = 128;
+
+/// The config space data for [`VIRTIO_INPUT_CFG_ABS_INFO`].
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioInputAbsInfo {
+    pub min: u32_le,
+    pub max: u32_le,
+    pub fuzz: u32_le,
+    pub flat: u32_le,
+    pub res: u32_le,
+}
+
+/// The config space data for [`VIRTIO_INPUT_CFG_ID_DEVIDS`].
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioInputDevIds {
+    pub bustype: u16_le,
+    pub vendor: u16_le,
+    pub product: u16_le,
+    pub version: u16_le,
+}
+
+/// An entry on the event or status queue (spec §5.8.6).
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioInputEvent {
+    pub ty: u16_le,
+    pub code: u16_le,
+    pub value: u32_le,
+}
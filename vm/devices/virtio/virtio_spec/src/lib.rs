@@ -9,8 +9,12 @@
 
 #![expect(missing_docs)]
 
+pub mod balloon;
 pub mod blk;
 pub mod fs;
+pub mod gpu;
+pub mod input;
+pub mod scsi;
 
 use bitfield_struct::bitfield;
 use inspect::Inspect;
@@ -115,7 +119,11 @@ pub enum VirtioDeviceType: u16 {
         BLK = 2,
         CONSOLE = 3,
         RNG = 4,
+        BALLOON = 5,
+        SCSI = 8,
         P9 = 9,
+        GPU = 16,
+        INPUT = 18,
         VSOCK = 19,
         FS = 26,
         PMEM = 27,
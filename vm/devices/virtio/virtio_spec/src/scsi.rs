@@ -0,0 +1,176 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio SCSI host device specification constants and types.
+//!
+//! Based on OASIS VIRTIO v1.2, Section 5.6.
+//! <https://docs.oasis-open.org/virtio/virtio/v1.2/cs01/virtio-v1.2-cs01.html>
+
+use crate::u16_le;
+use crate::u32_le;
+use crate::u64_le;
+use inspect::Inspect;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+// Feature bits (spec §5.6.3). These are device-specific bits in bank 0 (bits 0..23).
+/// A single request can include both device-readable and device-writable
+/// data buffers.
+pub const VIRTIO_SCSI_F_INOUT: u32 = 1 << 0;
+/// The host reports hotplug and hot-unplug events on the event queue.
+pub const VIRTIO_SCSI_F_HOTPLUG: u32 = 1 << 1;
+/// The host reports changes to LUN parameters on the event queue.
+pub const VIRTIO_SCSI_F_CHANGE: u32 = 1 << 2;
+/// The extended fields for T10 protection information are present.
+pub const VIRTIO_SCSI_F_T10_PI: u32 = 1 << 3;
+
+/// The index of the control queue.
+pub const VIRTIO_SCSI_CONTROL_QUEUE: u16 = 0;
+/// The index of the event queue.
+pub const VIRTIO_SCSI_EVENT_QUEUE: u16 = 1;
+/// The index of the first request queue.
+pub const VIRTIO_SCSI_REQUEST_QUEUE_BASE: u16 = 2;
+
+/// The default CDB size in the config space.
+pub const VIRTIO_SCSI_CDB_DEFAULT_SIZE: u32 = 32;
+/// The default sense data size in the config space.
+pub const VIRTIO_SCSI_SENSE_DEFAULT_SIZE: u32 = 96;
+
+/// Virtio SCSI config space layout (spec §5.6.4).
+///
+/// `sense_size` and `cdb_size` are writable by the driver.
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, Immutable, KnownLayout, FromBytes, Inspect)]
+pub struct VirtioScsiConfig {
+    /// The number of request queues.
+    pub num_queues: u32,
+    /// The maximum number of data segments in a command.
+    pub seg_max: u32,
+    /// The maximum transfer size, in 512-byte sectors.
+    pub max_sectors: u32,
+    /// The maximum number of linked commands per LUN.
+    pub cmd_per_lun: u32,
+    /// The size of an event queue buffer.
+    pub event_info_size: u32,
+    /// The size of the sense data in a response.
+    pub sense_size: u32,
+    /// The size of the CDB in a request.
+    pub cdb_size: u32,
+    pub max_channel: u16,
+    pub max_target: u16,
+    pub max_lun: u32,
+}
+
+// Response codes (spec §5.6.6.1).
+pub const VIRTIO_SCSI_S_OK: u8 = 0;
+pub const VIRTIO_SCSI_S_OVERRUN: u8 = 1;
+pub const VIRTIO_SCSI_S_ABORTED: u8 = 2;
+pub const VIRTIO_SCSI_S_BAD_TARGET: u8 = 3;
+pub const VIRTIO_SCSI_S_RESET: u8 = 4;
+pub const VIRTIO_SCSI_S_BUSY: u8 = 5;
+pub const VIRTIO_SCSI_S_TRANSPORT_FAILURE: u8 = 6;
+pub const VIRTIO_SCSI_S_TARGET_FAILURE: u8 = 7;
+pub const VIRTIO_SCSI_S_NEXUS_FAILURE: u8 = 8;
+pub const VIRTIO_SCSI_S_FAILURE: u8 = 9;
+pub const VIRTIO_SCSI_S_FUNCTION_SUCCEEDED: u8 = 10;
+pub const VIRTIO_SCSI_S_FUNCTION_REJECTED: u8 = 11;
+pub const VIRTIO_SCSI_S_INCORRECT_LUN: u8 = 12;
+/// The task management function completed (the same value as `S_OK`).
+pub const VIRTIO_SCSI_S_FUNCTION_COMPLETE: u8 = 0;
+
+/// The device-readable header of a request queue command, followed by the
+/// CDB of `cdb_size` bytes (spec §5.6.6.1).
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioScsiCmdReq {
+    pub lun: [u8; 8],
+    pub id: u64_le,
+    pub task_attr: u8,
+    pub prio: u8,
+    pub crn: u8,
+}
+
+/// The device-writable header of a request queue response, followed by the
+/// sense data of `sense_size` bytes (spec §5.6.6.1).
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioScsiCmdResp {
+    pub sense_len: u32_le,
+    pub residual: u32_le,
+    pub status_qualifier: u16_le,
+    pub status: u8,
+    pub response: u8,
+}
+
+// Control queue request types (spec §5.6.6.2).
+pub const VIRTIO_SCSI_T_TMF: u32 = 0;
+pub const VIRTIO_SCSI_T_AN_QUERY: u32 = 1;
+pub const VIRTIO_SCSI_T_AN_SUBSCRIBE: u32 = 2;
+
+// Task management function subtypes (spec §5.6.6.2.1).
+pub const VIRTIO_SCSI_T_TMF_ABORT_TASK: u32 = 0;
+pub const VIRTIO_SCSI_T_TMF_ABORT_TASK_SET: u32 = 1;
+pub const VIRTIO_SCSI_T_TMF_CLEAR_ACA: u32 = 2;
+pub const VIRTIO_SCSI_T_TMF_CLEAR_TASK_SET: u32 = 3;
+pub const VIRTIO_SCSI_T_TMF_I_T_NEXUS_RESET: u32 = 4;
+pub const VIRTIO_SCSI_T_TMF_LOGICAL_UNIT_RESET: u32 = 5;
+pub const VIRTIO_SCSI_T_TMF_QUERY_TASK: u32 = 6;
+pub const VIRTIO_SCSI_T_TMF_QUERY_TASK_SET: u32 = 7;
+
+/// The device-readable part of a task management request (spec §5.6.6.2.1).
+///
+/// The device-writable part is a single response byte.
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioScsiCtrlTmfReq {
+    pub ty: u32_le,
+    pub subtype: u32_le,
+    pub lun: [u8; 8],
+    pub id: u64_le,
+}
+
+/// The device-readable part of an asynchronous notification query or
+/// subscription (spec §5.6.6.2.2).
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioScsiCtrlAnReq {
+    pub ty: u32_le,
+    pub lun: [u8; 8],
+    pub event_requested: u32_le,
+}
+
+/// The device-writable part of an asynchronous notification query or
+/// subscription (spec §5.6.6.2.2).
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioScsiCtrlAnResp {
+    pub event_actual: u32_le,
+    pub response: u8,
+}
+
+// Event types (spec §5.6.6.3).
+pub const VIRTIO_SCSI_T_NO_EVENT: u32 = 0;
+pub const VIRTIO_SCSI_T_TRANSPORT_RESET: u32 = 1;
+pub const VIRTIO_SCSI_T_ASYNC_NOTIFY: u32 = 2;
+pub const VIRTIO_SCSI_T_PARAM_CHANGE: u32 = 3;
+/// Set in the event type if the device dropped events because there were no
+/// event buffers.
+pub const VIRTIO_SCSI_T_EVENTS_MISSED: u32 = 0x8000_0000;
+
+// Transport reset event reasons (spec §5.6.6.3.1).
+pub const VIRTIO_SCSI_EVT_RESET_HARD: u32 = 0;
+/// A LUN was added.
+pub const VIRTIO_SCSI_EVT_RESET_RESCAN: u32 = 1;
+/// A LUN was removed.
+pub const VIRTIO_SCSI_EVT_RESET_REMOVED: u32 = 2;
+
+/// An event queue entry (spec §5.6.6.3).
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VirtioScsiEvent {
+    pub event: u32_le,
+    pub lun: [u8; 8],
+    pub reason: u32_le,
+}
@@ -2,8 +2,6 @@
 // Licensed under the MIT License.
 
 //! VHD1 file format definitions.
-//!
-//! Currently incomplete (missing defs for non-fixed disks).
 
 #![expect(missing_docs)]
 #![forbid(unsafe_code)]
@@ -54,6 +52,8 @@ impl VhdFooter {
     pub const FIXED_DATA_OFFSET: u64 = !0;
     pub const CREATOR_VERSION_MAGIC: u32 = 0x000a0000;
     pub const DISK_TYPE_FIXED: u32 = 2;
+    pub const DISK_TYPE_DYNAMIC: u32 = 3;
+    pub const DISK_TYPE_DIFFERENCING: u32 = 4;
 
     pub fn new_fixed(size: u64, guid: Guid) -> Self {
         let mut footer = Self {
@@ -73,6 +73,65 @@ pub fn new_fixed(size: u64, guid: Guid) -> Self {
         footer
     }
 
+    /// Creates a footer for a dynamic or differencing disk whose dynamic
+    /// header is at `data_offset`.
+    pub fn new_dynamic(size: u64, guid: Guid, disk_type: u32, data_offset: u64) -> Self {
+        let mut footer = Self {
+            data_offset: data_offset.into(),
+            disk_type: disk_type.into(),
+            ..Self::new_fixed(size, guid)
+        };
+        footer.checksum = footer.compute_checksum().into();
+        footer
+    }
+
+    pub fn compute_checksum(&self) -> u32 {
+        !(self.as_bytes().iter().map(|b| *b as u32).sum::<u32>()
+            - self
+                .checksum
+                .as_bytes()
+                .iter()
+                .map(|b| *b as u32)
+                .sum::<u32>())
+    }
+}
+
+/// The header of a dynamic or differencing disk, located at the footer's
+/// `data_offset`.
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VhdDynamicHeader {
+    pub cookie: u64_be,
+    pub data_offset: u64_be,
+    /// The file offset of the block allocation table.
+    pub table_offset: u64_be,
+    pub header_version: u32_be,
+    pub max_table_entries: u32_be,
+    pub block_size: u32_be,
+    pub checksum: u32_be,
+    /// The unique ID from the parent's footer.
+    pub parent_unique_id: Guid,
+    pub parent_time_stamp: u32_be,
+    pub reserved: u32_be,
+    /// The parent's file name, in big-endian UTF-16.
+    pub parent_unicode_name: [u8; 512],
+    pub parent_locators: [VhdParentLocatorEntry; 8],
+    pub reserved2: [u8; 256],
+}
+
+const _: () = assert!(size_of::<VhdDynamicHeader>() == VhdDynamicHeader::LEN as usize);
+
+impl VhdDynamicHeader {
+    pub const LEN: u64 = 1024;
+
+    pub const COOKIE_MAGIC: u64_be = u64_be::from_bytes(*b"cxsparse");
+    pub const DATA_OFFSET: u64 = !0;
+    pub const HEADER_VERSION: u32 = 0x00010000;
+    pub const DEFAULT_BLOCK_SIZE: u32 = 0x200000;
+
+    /// The BAT entry value of an unallocated block.
+    pub const BAT_ENTRY_UNALLOCATED: u32 = !0;
+
     pub fn compute_checksum(&self) -> u32 {
         !(self.as_bytes().iter().map(|b| *b as u32).sum::<u32>()
             - self
@@ -83,3 +142,24 @@ pub fn compute_checksum(&self) -> u32 {
                 .sum::<u32>())
     }
 }
+
+/// A parent locator entry in the dynamic header of a differencing disk.
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VhdParentLocatorEntry {
+    pub platform_code: u32_be,
+    /// The space reserved for the locator data.
+    pub platform_data_space: u32_be,
+    /// The length of the locator data, in bytes.
+    pub platform_data_length: u32_be,
+    pub reserved: u32_be,
+    pub platform_data_offset: u64_be,
+}
+
+impl VhdParentLocatorEntry {
+    pub const PLATFORM_CODE_NONE: u32 = 0;
+    /// A relative Windows path, in little-endian UTF-16.
+    pub const PLATFORM_CODE_W2RU: u32 = u32::from_be_bytes(*b"W2ru");
+    /// An absolute Windows path, in little-endian UTF-16.
+    pub const PLATFORM_CODE_W2KU: u32 = u32::from_be_bytes(*b"W2ku");
+}
@@ -18,6 +18,7 @@
 pub mod monitor;
 pub mod non_volatile_store;
 pub mod notify;
+pub mod ram_discard;
 pub mod reference_time;
 pub mod save_restore;
 pub mod slim_event;
@@ -0,0 +1,68 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Types for releasing the host memory backing guest RAM.
+
+#![forbid(unsafe_code)]
+
+use memory_range::MemoryRange;
+use std::convert::Infallible;
+use std::sync::Arc;
+use vm_resource::CanResolveTo;
+use vm_resource::PlatformResource;
+use vm_resource::ResolveResource;
+use vm_resource::ResourceKind;
+
+/// Trait for releasing the host memory backing guest RAM.
+pub trait DiscardRam: Send + Sync {
+    /// Releases the host memory backing the RAM in `range`. Parts of `range`
+    /// that are not RAM are ignored.
+    ///
+    /// The discarded RAM reads as zero the next time it is accessed, at which
+    /// point the host allocates memory for it again.
+    fn discard(&self, range: MemoryRange) -> std::io::Result<()>;
+}
+
+/// A resource kind for releasing the host memory backing guest RAM, for
+/// devices such as memory balloons that the guest gives unused RAM to.
+///
+/// Only the platform resource makes sense for this resource kind, since the
+/// VM's memory backing is shared by all devices.
+pub enum RamDiscardKind {}
+
+impl ResourceKind for RamDiscardKind {
+    const NAME: &'static str = "ram_discard";
+}
+
+impl CanResolveTo<RamDiscard> for RamDiscardKind {
+    type Input<'a> = ();
+}
+
+/// An object for releasing the host memory backing guest RAM.
+#[derive(Clone)]
+pub struct RamDiscard(Arc<dyn DiscardRam>);
+
+impl RamDiscard {
+    /// Creates a new RAM discard object.
+    pub fn new<T: DiscardRam + 'static>(discard: T) -> Self {
+        Self(Arc::new(discard))
+    }
+
+    /// Releases the host memory backing the RAM in `range`.
+    pub fn discard(&self, range: MemoryRange) -> std::io::Result<()> {
+        self.0.discard(range)
+    }
+}
+
+impl ResolveResource<RamDiscardKind, PlatformResource> for RamDiscard {
+    type Output = RamDiscard;
+    type Error = Infallible;
+
+    fn resolve(
+        &self,
+        PlatformResource: PlatformResource,
+        (): (),
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(self.clone())
+    }
+}
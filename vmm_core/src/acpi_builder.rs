@@ -13,6 +13,7 @@
 use chipset::psp;
 use inspect::Inspect;
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use vm_topology::memory::MemoryLayout;
 use vm_topology::pcie::PcieHostBridge;
 use vm_topology::processor::ArchTopology;
@@ -47,10 +48,79 @@ pub struct AcpiTablesBuilder<'a, T: AcpiTopology> {
     ///
     /// If and only if this has root complexes, then an MCFG will be generated.
     pub pcie_host_bridges: &'a Vec<PcieHostBridge>,
+    /// The distances and memory performance between vNUMA nodes.
+    ///
+    /// If set, the SLIT and HMAT are generated for the matrices it contains.
+    pub numa_locality: Option<&'a NumaLocality>,
     /// Architecture-specific ACPI configuration.
     pub arch: AcpiArchConfig,
 }
 
+/// The distances and memory performance between vNUMA nodes, reported to the
+/// guest in the SLIT and HMAT.
+///
+/// Each matrix has one row and one column per vNUMA node. Row `i`, column `j`
+/// describes accesses from the processors in node `i` to the memory in node
+/// `j`.
+#[derive(Debug, Clone, Default)]
+pub struct NumaLocality {
+    /// The relative distances between nodes, reported in the SLIT. The
+    /// distance from a node to itself must be 10, and to any other node must
+    /// be greater than 10.
+    pub distances: Option<Vec<Vec<u8>>>,
+    /// The memory access latencies in nanoseconds, reported in the HMAT.
+    pub latencies: Option<Vec<Vec<u32>>>,
+    /// The memory access bandwidths in MB/s, reported in the HMAT.
+    pub bandwidths: Option<Vec<Vec<u32>>>,
+}
+
+/// An error returned by [`NumaLocality::validate`].
+#[derive(Debug, thiserror::Error)]
+pub enum InvalidNumaLocality {
+    #[error("the {0} matrix must be {1}x{1}, with a row and a column for each vNUMA node")]
+    Dimensions(&'static str, usize),
+    #[error("the distance from vNUMA node {0} to itself must be 10")]
+    LocalDistance(usize),
+    #[error("the distance from vNUMA node {0} to node {1} must be greater than 10")]
+    RemoteDistance(usize, usize),
+}
+
+impl NumaLocality {
+    /// Checks that the matrices are consistent with a VM with `node_count`
+    /// vNUMA nodes.
+    pub fn validate(&self, node_count: usize) -> Result<(), InvalidNumaLocality> {
+        fn check_dimensions<T>(
+            name: &'static str,
+            matrix: &Option<Vec<Vec<T>>>,
+            node_count: usize,
+        ) -> Result<(), InvalidNumaLocality> {
+            if let Some(matrix) = matrix
+                && (matrix.len() != node_count || matrix.iter().any(|row| row.len() != node_count))
+            {
+                return Err(InvalidNumaLocality::Dimensions(name, node_count));
+            }
+            Ok(())
+        }
+
+        check_dimensions("distance", &self.distances, node_count)?;
+        check_dimensions("latency", &self.latencies, node_count)?;
+        check_dimensions("bandwidth", &self.bandwidths, node_count)?;
+        if let Some(distances) = &self.distances {
+            for (i, row) in distances.iter().enumerate() {
+                for (j, &distance) in row.iter().enumerate() {
+                    if i == j && distance != acpi_spec::slit::SLIT_LOCAL_DISTANCE {
+                        return Err(InvalidNumaLocality::LocalDistance(i));
+                    }
+                    if i != j && distance <= acpi_spec::slit::SLIT_LOCAL_DISTANCE {
+                        return Err(InvalidNumaLocality::RemoteDistance(i, j));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 /// Architecture-specific ACPI configuration carried by [`AcpiTablesBuilder`].
 pub enum AcpiArchConfig {
     /// x86-specific settings (IOAPIC, PIC, PIT, PSP, PM base, SCI IRQ).
@@ -228,6 +298,88 @@ fn with_srat<F, R>(&self, f: F) -> R
         ))
     }
 
+    fn with_slit<F, R>(&self, distances: &[Vec<u8>], f: F) -> R
+    where
+        F: FnOnce(&acpi::builder::Table<'_>) -> R,
+    {
+        let matrix = distances.concat();
+        (f)(&acpi::builder::Table::new_dyn(
+            acpi_spec::slit::SLIT_REVISION,
+            None,
+            &acpi_spec::slit::Slit::new(distances.len() as u64),
+            &[matrix.as_slice()],
+        ))
+    }
+
+    fn with_hmat<F, R>(&self, numa: &NumaLocality, f: F) -> R
+    where
+        F: FnOnce(&acpi::builder::Table<'_>) -> R,
+    {
+        use acpi_spec::hmat;
+
+        // The initiators are the nodes with processors, and the targets are
+        // the nodes with memory.
+        let initiators: BTreeSet<u32> = self.processor_topology.vps().map(|vp| vp.vnode).collect();
+        let targets: BTreeSet<u32> = self.mem_layout.ram().iter().map(|r| r.vnode).collect();
+
+        let mut hmat_extra: Vec<u8> = Vec::new();
+        for &target in &targets {
+            hmat_extra.extend_from_slice(
+                hmat::HmatMemoryProximityDomain::new(
+                    target,
+                    initiators.contains(&target).then_some(target),
+                )
+                .as_bytes(),
+            );
+        }
+
+        let mut add_matrix = |data_type, matrix: &[Vec<u32>], unit_scale: u64| {
+            // Entries are 16 bits, and 0xFFFF means the initiator cannot reach
+            // the target, so choose the smallest base unit that lets the
+            // largest value fit in 0xFFFE. Values are rounded up so that they
+            // do not become zero, which means that no value is provided.
+            let max = initiators
+                .iter()
+                .flat_map(|&i| targets.iter().map(move |&t| matrix[i as usize][t as usize]))
+                .max()
+                .unwrap_or(0);
+            let base_unit = u64::from(max).div_ceil(0xfffe).max(1);
+            hmat_extra.extend_from_slice(
+                hmat::HmatSystemLocalityLatencyBandwidth::new(
+                    data_type,
+                    initiators.len() as u32,
+                    targets.len() as u32,
+                    base_unit * unit_scale,
+                )
+                .as_bytes(),
+            );
+            for &domain in initiators.iter().chain(&targets) {
+                hmat_extra.extend_from_slice(domain.as_bytes());
+            }
+            for &i in &initiators {
+                for &t in &targets {
+                    let entry = u64::from(matrix[i as usize][t as usize]).div_ceil(base_unit);
+                    hmat_extra.extend_from_slice((entry as u16).as_bytes());
+                }
+            }
+        };
+
+        if let Some(latencies) = &numa.latencies {
+            // Latencies are reported in picoseconds.
+            add_matrix(hmat::HmatDataType::ACCESS_LATENCY, latencies, 1000);
+        }
+        if let Some(bandwidths) = &numa.bandwidths {
+            add_matrix(hmat::HmatDataType::ACCESS_BANDWIDTH, bandwidths, 1);
+        }
+
+        (f)(&acpi::builder::Table::new_dyn(
+            hmat::HMAT_REVISION,
+            None,
+            &hmat::Hmat::new(),
+            &[hmat_extra.as_slice()],
+        ))
+    }
+
     fn with_madt<F, R>(&self, f: F) -> R
     where
         F: FnOnce(&acpi::builder::Table<'_>) -> R,
@@ -655,6 +807,14 @@ fn build_acpi_tables_inner(&self, gpa: u64, dsdt: &[u8]) -> BuiltAcpiTables {
 
         self.with_madt(|t| b.append(t));
         self.with_srat(|t| b.append(t));
+        if let Some(numa) = self.numa_locality {
+            if let Some(distances) = &numa.distances {
+                self.with_slit(distances, |t| b.append(t));
+            }
+            if numa.latencies.is_some() || numa.bandwidths.is_some() {
+                self.with_hmat(numa, |t| b.append(t));
+            }
+        }
         if !self.pcie_host_bridges.is_empty() {
             self.with_mcfg(|t| b.append(t));
 
@@ -698,6 +858,29 @@ pub fn build_srat(&self) -> Vec<u8> {
         self.with_srat(|t| t.to_vec(&OEM_INFO))
     }
 
+    /// Helper method to construct an SLIT without constructing the rest of
+    /// the ACPI tables.
+    ///
+    /// # Panics
+    /// Panics if `self.numa_locality` does not have a distance matrix.
+    pub fn build_slit(&self) -> Vec<u8> {
+        let distances = self
+            .numa_locality
+            .and_then(|numa| numa.distances.as_deref())
+            .expect("distance matrix is required");
+        self.with_slit(distances, |t| t.to_vec(&OEM_INFO))
+    }
+
+    /// Helper method to construct an HMAT without constructing the rest of
+    /// the ACPI tables.
+    ///
+    /// # Panics
+    /// Panics if `self.numa_locality` is not set.
+    pub fn build_hmat(&self) -> Vec<u8> {
+        let numa = self.numa_locality.expect("numa locality is required");
+        self.with_hmat(numa, |t| t.to_vec(&OEM_INFO))
+    }
+
     /// Helper method to construct a MCFG without constructing the rest of the
     /// ACPI tables.
     pub fn build_mcfg(&self) -> Vec<u8> {
@@ -742,6 +925,7 @@ mod test {
     use super::*;
     use acpi_spec::madt::MadtParser;
     use acpi_spec::mcfg::parse_mcfg;
+    use acpi_spec::slit::parse_slit;
     use memory_range::MemoryRange;
     use virt::VpIndex;
     use virt::VpInfo;
@@ -762,6 +946,10 @@ fn new_mem() -> MemoryLayout {
         MemoryLayout::new(TB, &MMIO, &[], &[], None).unwrap()
     }
 
+    fn new_numa_mem() -> MemoryLayout {
+        MemoryLayout::new_with_numa(&[2 * GB, 2 * GB], &MMIO, &[], &[], None).unwrap()
+    }
+
     fn new_builder<'a>(
         mem_layout: &'a MemoryLayout,
         processor_topology: &'a ProcessorTopology<X86Topology>,
@@ -772,6 +960,7 @@ fn new_builder<'a>(
             mem_layout,
             cache_topology: None,
             pcie_host_bridges,
+            numa_locality: None,
             arch: AcpiArchConfig::X86 {
                 with_ioapic: true,
                 with_pic: false,
@@ -873,4 +1062,127 @@ fn test_basic_pcie_topology() {
         })
         .unwrap();
     }
+
+    #[test]
+    fn test_slit() {
+        let mem = new_numa_mem();
+        let topology = TopologyBuilder::new_x86()
+            .vps_per_socket(2)
+            .build(4)
+            .unwrap();
+        let pcie = vec![];
+        let numa = NumaLocality {
+            distances: Some(vec![vec![10, 21], vec![21, 10]]),
+            ..Default::default()
+        };
+        numa.validate(2).unwrap();
+        let builder = AcpiTablesBuilder {
+            numa_locality: Some(&numa),
+            ..new_builder(&mem, &topology, &pcie)
+        };
+        let slit = builder.build_slit();
+
+        let (_, header, matrix) = parse_slit(&slit).unwrap();
+        assert_eq!(header.num_localities.get(), 2);
+        assert_eq!(matrix, [10, 21, 21, 10]);
+    }
+
+    #[test]
+    fn test_hmat() {
+        use acpi_spec::hmat;
+        use zerocopy::FromBytes;
+
+        let mem = new_numa_mem();
+        let topology = TopologyBuilder::new_x86()
+            .vps_per_socket(2)
+            .build(4)
+            .unwrap();
+        let pcie = vec![];
+        let numa = NumaLocality {
+            latencies: Some(vec![vec![100, 200], vec![200, 100]]),
+            bandwidths: Some(vec![vec![100_000, 50_000], vec![50_000, 100_000]]),
+            ..Default::default()
+        };
+        numa.validate(2).unwrap();
+        let builder = AcpiTablesBuilder {
+            numa_locality: Some(&numa),
+            ..new_builder(&mem, &topology, &pcie)
+        };
+        let table = builder.build_hmat();
+
+        let (header, rest) = acpi_spec::Header::read_from_prefix(&table).unwrap();
+        assert_eq!(&header.signature, b"HMAT");
+        assert_eq!(header.length.get() as usize, table.len());
+        let (_, mut rest) = hmat::Hmat::read_from_prefix(rest).unwrap();
+        for node in 0..2 {
+            let (domain, r) = hmat::HmatMemoryProximityDomain::read_from_prefix(rest).unwrap();
+            assert_eq!(domain.memory_proximity_domain.get(), node);
+            assert_eq!(domain.initiator_proximity_domain.get(), node);
+            assert_eq!(
+                domain.flags.get(),
+                hmat::HMAT_INITIATOR_PROXIMITY_DOMAIN_VALID
+            );
+            rest = r;
+        }
+        // Latencies are in picoseconds, and the bandwidths are too large for
+        // a base unit of 1 MB/s.
+        for (data_type, base_unit, entries) in [
+            (
+                hmat::HmatDataType::ACCESS_LATENCY,
+                1000,
+                [100, 200, 200, 100],
+            ),
+            (
+                hmat::HmatDataType::ACCESS_BANDWIDTH,
+                2,
+                [50_000, 25_000, 25_000, 50_000],
+            ),
+        ] {
+            let (info, r) =
+                hmat::HmatSystemLocalityLatencyBandwidth::read_from_prefix(rest).unwrap();
+            assert_eq!(info.data_type, data_type);
+            assert_eq!(info.entry_base_unit.get(), base_unit);
+            assert_eq!(
+                info.length.get() as usize,
+                size_of_val(&info) + 4 * 4 + 4 * 2
+            );
+            let (domains, r) = <[u32; 4]>::read_from_prefix(r).unwrap();
+            assert_eq!(domains, [0, 1, 0, 1]);
+            let (actual, r) = <[u16; 4]>::read_from_prefix(r).unwrap();
+            assert_eq!(actual, entries);
+            rest = r;
+        }
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_numa_locality() {
+        let numa = NumaLocality {
+            distances: Some(vec![vec![10, 20], vec![20, 10]]),
+            latencies: Some(vec![vec![100, 200]]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            numa.validate(2),
+            Err(InvalidNumaLocality::Dimensions("latency", 2))
+        ));
+
+        let numa = NumaLocality {
+            distances: Some(vec![vec![10, 20], vec![20, 11]]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            numa.validate(2),
+            Err(InvalidNumaLocality::LocalDistance(1))
+        ));
+
+        let numa = NumaLocality {
+            distances: Some(vec![vec![10, 10], vec![20, 10]]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            numa.validate(2),
+            Err(InvalidNumaLocality::RemoteDistance(0, 1))
+        ));
+    }
 }
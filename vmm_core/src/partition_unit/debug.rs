@@ -157,6 +157,14 @@ pub async fn handle_gdb(&mut self, req: DebugRequest) {
                 })
                 .await
             }
+            // The VMM handles requests for the whole VM before they reach the
+            // partition unit, if it supports them.
+            DebugRequest::Inspect(deferred) => deferred.inspect(self),
+            DebugRequest::Nmi(rpc) => rpc.fail(anyhow::anyhow!("nmi is not supported")),
+            DebugRequest::Reset(rpc) => rpc.fail(anyhow::anyhow!("reset is not supported")),
+            DebugRequest::GetMemoryMap(rpc) => {
+                rpc.fail(anyhow::anyhow!("memory map is not supported"))
+            }
         }
     }
 }
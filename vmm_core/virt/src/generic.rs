@@ -280,6 +280,13 @@ fn supports_vtl_scrub(&self) -> Option<&dyn ScrubVtl<Error = <Self as Hv1>::Erro
         None
     }
 
+    /// Returns a trait object to track guest writes to memory, if supported.
+    fn supports_dirty_page_tracking(
+        &self,
+    ) -> Option<&dyn DirtyPageTracking<Error = <Self as Hv1>::Error>> {
+        None
+    }
+
     /// Returns an interface for registering MMIO doorbells for this partition.
     ///
     /// Not all partitions support this.
@@ -397,6 +404,26 @@ pub trait ScrubVtl {
     fn scrub(&self, vtl: Vtl) -> Result<(), Self::Error>;
 }
 
+/// Extension trait for tracking which pages of guest RAM the guest has
+/// written.
+///
+/// This only observes writes made by the guest's processors. Writes to guest
+/// memory made by the host, such as by emulated devices, are not tracked.
+pub trait DirtyPageTracking {
+    type Error: std::error::Error;
+
+    /// Enables or disables dirty page tracking for all mapped RAM.
+    ///
+    /// Enabling tracking resets the dirty state of all pages to clean.
+    fn set_dirty_page_tracking(&self, enable: bool) -> Result<(), Self::Error>;
+
+    /// Returns the guest physical ranges written since tracking was enabled or
+    /// since the last call, and resets them to clean.
+    ///
+    /// Returns an empty list if tracking is not enabled.
+    fn take_dirty_pages(&self) -> Result<Vec<MemoryRange>, Self::Error>;
+}
+
 /// Provides access to partition state for save, restore, and reset.
 ///
 /// This is not part of [`Partition`] because some scenarios do not require such
@@ -818,6 +818,12 @@ fn supports_reset(
         None
     }
 
+    fn supports_dirty_page_tracking(
+        &self,
+    ) -> Option<&dyn virt::DirtyPageTracking<Error = <Self as virt::Hv1>::Error>> {
+        Some(self)
+    }
+
     fn caps(&self) -> &PartitionCapabilities {
         &self.inner.caps
     }
@@ -525,6 +525,12 @@ fn supports_reset(&self) -> Option<&dyn ResetPartition<Error = Self::Error>> {
         Some(self)
     }
 
+    fn supports_dirty_page_tracking(
+        &self,
+    ) -> Option<&dyn virt::DirtyPageTracking<Error = Self::Error>> {
+        Some(self)
+    }
+
     fn doorbell_registration(
         self: &Arc<Self>,
         _minimum_vtl: Vtl,
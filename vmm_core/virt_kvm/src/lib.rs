@@ -69,6 +69,7 @@ pub enum KvmError {
 struct KvmMemoryRange {
     host_addr: *mut u8,
     range: MemoryRange,
+    readonly: bool,
 }
 
 unsafe impl Sync for KvmMemoryRange {}
@@ -78,6 +79,8 @@ unsafe impl Send for KvmMemoryRange {}
 struct KvmMemoryRangeState {
     #[inspect(flatten, iter_by_index)]
     ranges: Vec<Option<KvmMemoryRange>>,
+    /// Whether dirty page logging is enabled for writable slots.
+    log_dirty: bool,
 }
 
 #[derive(Inspect)]
@@ -198,16 +201,122 @@ unsafe fn map_region(
             state.ranges.push(None);
         }
         let slot_to_use = slot_to_use.unwrap();
+        let log_dirty = state.log_dirty && !readonly;
         unsafe {
-            self.kvm
-                .set_user_memory_region(slot_to_use as u32, data, size, addr, readonly)?
+            self.kvm.set_user_memory_region(
+                slot_to_use as u32,
+                data,
+                size,
+                addr,
+                readonly,
+                log_dirty,
+            )?
         };
         state.ranges[slot_to_use] = Some(KvmMemoryRange {
             host_addr: data,
             range: MemoryRange::new(addr..addr + size as u64),
+            readonly,
         });
         Ok(())
     }
+
+    fn set_dirty_page_tracking(&self, enable: bool) -> Result<(), KvmError> {
+        let mut state = self.memory.lock();
+        if state.log_dirty == enable {
+            if enable {
+                // Discard anything logged so far so that tracking restarts
+                // from a clean state.
+                self.collect_dirty_pages(&state)?;
+            }
+            return Ok(());
+        }
+        for (slot, range) in state.ranges.iter().enumerate() {
+            let Some(range) = range else { continue };
+            if range.readonly {
+                continue;
+            }
+            // SAFETY: the slot is re-registered with the same mapping, which
+            // the caller of `map_region` guaranteed remains valid.
+            unsafe {
+                self.kvm.set_user_memory_region(
+                    slot as u32,
+                    range.host_addr,
+                    range.range.len() as usize,
+                    range.range.start(),
+                    false,
+                    enable,
+                )?;
+            }
+        }
+        state.log_dirty = enable;
+        Ok(())
+    }
+
+    fn take_dirty_pages(&self) -> Result<Vec<MemoryRange>, KvmError> {
+        let state = self.memory.lock();
+        if !state.log_dirty {
+            return Ok(Vec::new());
+        }
+        self.collect_dirty_pages(&state)
+    }
+
+    /// Reads and clears the dirty logs of all writable slots, returning the
+    /// dirty pages as merged guest physical ranges.
+    fn collect_dirty_pages(
+        &self,
+        state: &KvmMemoryRangeState,
+    ) -> Result<Vec<MemoryRange>, KvmError> {
+        let page_size = host_page_size();
+        let mut dirty = Vec::<MemoryRange>::new();
+        let mut bitmap = Vec::new();
+        for (slot, range) in state.ranges.iter().enumerate() {
+            let Some(range) = range else { continue };
+            if range.readonly {
+                continue;
+            }
+            let range = range.range;
+            let pages = range.len().div_ceil(page_size);
+            bitmap.clear();
+            bitmap.resize(pages.div_ceil(64) as usize, 0u64);
+            // SAFETY: the bitmap has a bit for every page of the slot.
+            unsafe { self.kvm.get_dirty_log(slot as u32, &mut bitmap)? };
+            for (i, &word) in bitmap.iter().enumerate() {
+                let mut word = word;
+                while word != 0 {
+                    let page = i as u64 * 64 + word.trailing_zeros() as u64;
+                    word &= word - 1;
+                    let start = range.start() + page * page_size;
+                    let end = (start + page_size).min(range.end());
+                    match dirty.last_mut() {
+                        Some(last) if last.end() == start => {
+                            *last = MemoryRange::new(last.start()..end);
+                        }
+                        _ => dirty.push(MemoryRange::new(start..end)),
+                    }
+                }
+            }
+        }
+        // Slots are not kept in address order.
+        dirty.sort_by_key(|range| range.start());
+        Ok(dirty)
+    }
+}
+
+fn host_page_size() -> u64 {
+    // SAFETY: sysconf has no safety requirements.
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
+}
+
+impl virt::DirtyPageTracking for KvmPartition {
+    type Error = KvmError;
+
+    fn set_dirty_page_tracking(&self, enable: bool) -> Result<(), KvmError> {
+        self.inner.set_dirty_page_tracking(enable)
+    }
+
+    fn take_dirty_pages(&self) -> Result<Vec<MemoryRange>, KvmError> {
+        self.inner.take_dirty_pages()
+    }
 }
 
 impl virt::PartitionMemoryMapper for KvmPartition {
@@ -246,6 +355,7 @@ fn unmap_range(&self, addr: u64, size: u64) -> anyhow::Result<()> {
                         0,
                         0,
                         false,
+                        false,
                     )?;
                 }
                 *entry = None;
@@ -36,6 +36,14 @@ pub enum DebugRequest {
     ReadMemory(FailableRpc<(GuestAddress, usize), Vec<u8>>),
     /// Write to the specified GPA from the guest.
     WriteMemory(FailableRpc<(GuestAddress, Vec<u8>), ()>),
+    /// Inspect the VM.
+    Inspect(inspect::Deferred),
+    /// Inject an NMI into the specified vp.
+    Nmi(FailableRpc<u32, ()>),
+    /// Reset the VM, leaving it halted for the debugger.
+    Reset(FailableRpc<(), ()>),
+    /// Get the `(gpa, length)` ranges of guest RAM.
+    GetMemoryMap(FailableRpc<(), Vec<(u64, u64)>>),
 }
 
 /// Register state for a VP.
@@ -4,6 +4,7 @@
 //! Integration tests for OpenVMM's TTRPC interface.
 
 use anyhow::Context;
+use futures::StreamExt;
 use guid::Guid;
 use openvmm_ttrpc_vmservice as vmservice;
 use pal_async::DefaultPool;
@@ -115,6 +116,7 @@ fn test_ttrpc_interface(
                             ..Default::default()
                         }),
                         log_id: String::new(),
+                        migrate_listen_path: String::new(),
                     },
                 )
                 .await
@@ -155,6 +157,25 @@ fn test_ttrpc_interface(
                     waiter.await.unwrap();
 
                     if i == 0 {
+                        // The halt should have been recorded as an event.
+                        let event = client
+                            .call()
+                            .start_stream(
+                                vmservice::Vm::StreamVmEvents,
+                                vmservice::StreamVmEventsRequest { after_sequence: 0 },
+                            )
+                            .next()
+                            .await
+                            .unwrap()
+                            .unwrap();
+
+                        assert_eq!(event.sequence, 1);
+                        assert!(event.timestamp.is_some());
+                        let Some(vmservice::vm_event::Event::GuestHalt(halt)) = &event.event else {
+                            panic!("unexpected event: {event:?}");
+                        };
+                        assert_eq!(halt.reason(), vmservice::guest_halt_event::Reason::PowerOff);
+
                         client
                             .call()
                             .start(vmservice::Vm::TeardownVm, ())
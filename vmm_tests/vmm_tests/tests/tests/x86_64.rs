@@ -541,6 +541,9 @@ async fn snapshot_save_to_disk(
         vp_count: 2,
         page_size: 4096,
         architecture: "x86_64".to_string(),
+        id: guid::Guid::new_random(),
+        parent: None,
+        memory_ranges: Vec::new(),
     };
     openvmm_helpers::snapshot::write_snapshot(&snap_dir, &manifest, &saved_state_bytes, &mem_path)?;
 
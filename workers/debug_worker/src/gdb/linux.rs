@@ -0,0 +1,361 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Linux kernel awareness, which presents the guest kernel's tasks as threads
+//! alongside the VPs.
+//!
+//! The tasks are found by walking the kernel's task list from `init_task`.
+//! That list only links thread-group leaders, since other threads are only
+//! linked from their leader's `signal->thread_head`, so only the main thread
+//! of each process is listed. When the kernel switches away from a task, it
+//! saves the task's callee-saved registers, so the registers of a task that is
+//! not running are reconstructed from those. Tasks that are running are represented by the
+//! thread of the VP they are running on.
+
+use super::VmProxy;
+use anyhow::Context;
+use debug_worker_defs::LinuxKernelLayout;
+use futures::executor::block_on;
+use gdbstub::common::Tid;
+use mesh::rpc::RpcSend;
+use std::num::NonZeroUsize;
+use vmm_core_defs::debug_rpc::DebugRequest;
+use vmm_core_defs::debug_rpc::DebuggerVpState;
+
+/// The most tasks to walk, in case the task list is corrupt.
+const MAX_TASKS: usize = 0x10000;
+
+/// The size of `task_struct.comm`.
+const TASK_COMM_LEN: usize = 16;
+
+/// The size of the x86_64 frame pushed by `__switch_to_asm`.
+const SWITCH_FRAME_SIZE: usize = 7 * 8;
+
+/// The size of the aarch64 `struct cpu_context`.
+const CPU_CONTEXT_SIZE: usize = 13 * 8;
+
+/// The Linux kernel state, as of the last stop.
+pub struct LinuxAwareness {
+    layout: LinuxKernelLayout,
+    /// The tasks that are not running.
+    tasks: Vec<LinuxTask>,
+    /// The task running on each VP, if known.
+    running: Vec<Option<LinuxTask>>,
+    /// Whether the tasks have been read since the VM last ran.
+    valid: bool,
+}
+
+#[derive(Debug, Clone)]
+struct LinuxTask {
+    address: u64,
+    pid: u32,
+    comm: String,
+}
+
+impl LinuxAwareness {
+    pub fn new(layout: LinuxKernelLayout) -> Self {
+        Self {
+            layout,
+            tasks: Vec::new(),
+            running: Vec::new(),
+            valid: false,
+        }
+    }
+
+    pub fn into_layout(self) -> LinuxKernelLayout {
+        self.layout
+    }
+
+    /// Forgets the tasks, which change whenever the VM runs.
+    pub fn invalidate(&mut self) {
+        self.valid = false;
+    }
+}
+
+impl VmProxy {
+    /// Returns whether `tid` is the thread of a task rather than of a VP.
+    pub fn is_task_tid(&self, tid: Tid) -> bool {
+        tid.get() > self.vps.len()
+    }
+
+    /// Returns the VP whose address space to use for memory accesses by the
+    /// thread `tid`.
+    ///
+    /// Tasks use VP 0's address space, which maps the kernel, but which may
+    /// not map the task's user address space.
+    pub fn tid_to_memory_vp(&self, tid: Tid) -> anyhow::Result<u32> {
+        if self.is_task_tid(tid) {
+            self.find_task(tid)?;
+            Ok(0)
+        } else {
+            self.tid_to_vp(tid)
+        }
+    }
+
+    fn task_to_tid(&self, pid: u32) -> Tid {
+        NonZeroUsize::new(self.vps.len() + 1 + pid as usize).unwrap()
+    }
+
+    fn find_task(&self, tid: Tid) -> anyhow::Result<&LinuxTask> {
+        self.linux
+            .as_ref()
+            .and_then(|linux| {
+                linux
+                    .tasks
+                    .iter()
+                    .find(|task| self.task_to_tid(task.pid) == tid)
+            })
+            .with_context(|| format!("Tid {} doesn't correspond to a task", tid))
+    }
+
+    /// Returns the threads of the tasks that are not running.
+    pub fn task_tids(&self) -> Vec<Tid> {
+        self.linux.as_ref().map_or(Vec::new(), |linux| {
+            linux
+                .tasks
+                .iter()
+                .map(|task| self.task_to_tid(task.pid))
+                .collect()
+        })
+    }
+
+    /// Returns a description of the thread `tid`, including the task it is
+    /// running, if known.
+    pub fn thread_description(&self, tid: Tid) -> String {
+        if self.is_task_tid(tid) {
+            return self.find_task(tid).map_or(String::new(), |task| {
+                format!("{} [{}]", task.comm, task.pid)
+            });
+        }
+        let vp = tid.get() - 1;
+        let running = self
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.running.get(vp)?.as_ref());
+        match running {
+            Some(task) => format!("VP {vp}: {} [{}]", task.comm, task.pid),
+            None => format!("VP {vp}"),
+        }
+    }
+
+    /// Reads the guest's task list, if it has not been read since the VM last
+    /// ran.
+    ///
+    /// Only thread-group leaders are found, since only `init_task.tasks` is
+    /// walked.
+    pub fn refresh_linux_tasks(&mut self) -> anyhow::Result<()> {
+        let layout = match &self.linux {
+            Some(linux) if !linux.valid => linux.layout.clone(),
+            _ => return Ok(()),
+        };
+
+        let mut running = vec![None; self.vps.len()];
+        if let (Some(per_cpu_offset), Some(current_task)) =
+            (layout.per_cpu_offset, layout.current_task)
+        {
+            for (vp, task) in running.iter_mut().enumerate() {
+                let offset = self.read_kernel_u64(per_cpu_offset + vp as u64 * 8)?;
+                let address = self
+                    .read_kernel_u64(offset.wrapping_add(current_task))
+                    .context("failed to read the current task")?;
+                *task = Some(self.read_task(&layout, address)?);
+            }
+        }
+
+        let head = layout.init_task + layout.task_tasks;
+        let mut tasks = Vec::new();
+        let mut next = self.read_kernel_u64(head)?;
+        while next != head {
+            anyhow::ensure!(tasks.len() < MAX_TASKS, "too many tasks");
+            let task = self.read_task(&layout, next.wrapping_sub(layout.task_tasks))?;
+            if !running
+                .iter()
+                .flatten()
+                .any(|running| running.address == task.address)
+            {
+                tasks.push(task);
+            }
+            next = self
+                .read_kernel_u64(next)
+                .context("failed to read the task list")?;
+        }
+
+        let linux = self.linux.as_mut().unwrap();
+        linux.tasks = tasks;
+        linux.running = running;
+        linux.valid = true;
+        Ok(())
+    }
+
+    fn read_task(&mut self, layout: &LinuxKernelLayout, address: u64) -> anyhow::Result<LinuxTask> {
+        let mut pid = [0; 4];
+        self.read_kernel(address + layout.task_pid, &mut pid)
+            .context("failed to read the task pid")?;
+        let mut comm = [0; TASK_COMM_LEN];
+        self.read_kernel(address + layout.task_comm, &mut comm)
+            .context("failed to read the task name")?;
+        let len = comm.iter().position(|&c| c == 0).unwrap_or(comm.len());
+        Ok(LinuxTask {
+            address,
+            pid: u32::from_le_bytes(pid),
+            comm: String::from_utf8_lossy(&comm[..len]).into_owned(),
+        })
+    }
+
+    /// Returns the register state of the task thread `tid`, as of when the
+    /// task was switched out.
+    pub fn task_state(&mut self, tid: Tid) -> anyhow::Result<DebuggerVpState> {
+        let address = self.find_task(tid)?.address;
+        let context = address + self.linux.as_ref().unwrap().layout.task_context;
+
+        // Start from VP 0's state for the registers the task does not save,
+        // such as the control registers.
+        let mut state = block_on(self.req_chan.call_failable(DebugRequest::GetVpState, 0))
+            .context("failed to get vp state")?;
+
+        match &mut state {
+            DebuggerVpState::X86_64(state) => {
+                let sp = self.read_kernel_u64(context)?;
+                let mut frame = [0; SWITCH_FRAME_SIZE];
+                self.read_kernel(sp, &mut frame)
+                    .context("failed to read the task's saved registers")?;
+                (state.gp, state.rip) = decode_switch_frame(sp, &frame);
+                // Only the reserved bit, since the saved flags are unknown.
+                state.rflags = 2;
+            }
+            DebuggerVpState::Aarch64(state) => {
+                let mut regs = [0; CPU_CONTEXT_SIZE];
+                self.read_kernel(context, &mut regs)
+                    .context("failed to read the task's saved registers")?;
+                (state.x, state.sp_el1, state.pc) = decode_cpu_context(&regs);
+                // EL1h, with interrupts masked.
+                state.cpsr = 0x3c5;
+            }
+        }
+        Ok(state)
+    }
+
+    fn read_kernel_u64(&mut self, gva: u64) -> anyhow::Result<u64> {
+        let mut data = [0; 8];
+        self.read_kernel(gva, &mut data)?;
+        Ok(u64::from_le_bytes(data))
+    }
+
+    /// Reads kernel memory at `gva`, through the first VP that has it mapped.
+    ///
+    /// The kernel is mapped on every VP running in the kernel, but it may not
+    /// be mapped on VPs running user mode code.
+    fn read_kernel(&mut self, gva: u64, data: &mut [u8]) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        for vp in 0..self.vps.len() as u32 {
+            result = self.read_guest_virtual_memory(vp, gva, data);
+            if result.is_ok() {
+                break;
+            }
+        }
+        result.with_context(|| format!("failed to read kernel memory at {gva:#x}"))
+    }
+}
+
+/// Decodes the x86_64 frame pushed by `__switch_to_asm` at `sp`, which
+/// `thread.sp` points to: r15, r14, r13, r12, rbx, rbp, and the return
+/// address.
+///
+/// Returns the general purpose registers and rip.
+fn decode_switch_frame(sp: u64, frame: &[u8; SWITCH_FRAME_SIZE]) -> ([u64; 16], u64) {
+    let [r15, r14, r13, r12, rbx, rbp, rip] =
+        std::array::from_fn(|i| u64::from_le_bytes(frame[i * 8..][..8].try_into().unwrap()));
+    let mut gp = [0; 16];
+    gp[3] = rbx;
+    gp[4] = sp + frame.len() as u64;
+    gp[5] = rbp;
+    gp[12] = r12;
+    gp[13] = r13;
+    gp[14] = r14;
+    gp[15] = r15;
+    (gp, rip)
+}
+
+/// Decodes the aarch64 `thread.cpu_context`, which holds x19-x28, fp, sp, and
+/// pc.
+///
+/// Returns x0-x30, with lr set to pc, sp, and pc.
+fn decode_cpu_context(regs: &[u8; CPU_CONTEXT_SIZE]) -> ([u64; 31], u64, u64) {
+    let regs: [u64; 13] =
+        std::array::from_fn(|i| u64::from_le_bytes(regs[i * 8..][..8].try_into().unwrap()));
+    let mut x = [0; 31];
+    x[19..=29].copy_from_slice(&regs[..11]);
+    x[30] = regs[12];
+    (x, regs[11], regs[12])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CPU_CONTEXT_SIZE;
+    use super::SWITCH_FRAME_SIZE;
+    use super::decode_cpu_context;
+    use super::decode_switch_frame;
+
+    /// Returns the little-endian bytes of `values`.
+    fn bytes<const N: usize>(values: &[u64]) -> [u8; N] {
+        let mut data = [0; N];
+        for (chunk, value) in data.chunks_exact_mut(8).zip(values) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn switch_frame() {
+        let frame: [u8; SWITCH_FRAME_SIZE] = bytes(&[15, 14, 13, 12, 3, 5, 0xffffffff81000000]);
+        let (gp, rip) = decode_switch_frame(0xffffc90000010000, &frame);
+        assert_eq!(
+            gp,
+            [
+                0,
+                0,
+                0,
+                3,
+                0xffffc90000010038,
+                5,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                12,
+                13,
+                14,
+                15
+            ]
+        );
+        assert_eq!(rip, 0xffffffff81000000);
+    }
+
+    #[test]
+    fn cpu_context() {
+        let regs: [u8; CPU_CONTEXT_SIZE] = bytes(&[
+            19,
+            20,
+            21,
+            22,
+            23,
+            24,
+            25,
+            26,
+            27,
+            28,
+            29,
+            0xffff800082000000,
+            0xffff800080010000,
+        ]);
+        let (x, sp, pc) = decode_cpu_context(&regs);
+        let mut expected = [0; 31];
+        expected[19..=29].copy_from_slice(&[19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29]);
+        expected[30] = 0xffff800080010000;
+        assert_eq!(x, expected);
+        assert_eq!(sp, 0xffff800082000000);
+        assert_eq!(pc, 0xffff800080010000);
+    }
+}
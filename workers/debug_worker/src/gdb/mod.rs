@@ -2,18 +2,26 @@
 // Licensed under the MIT License.
 
 use anyhow::Context;
+use debug_worker_defs::LinuxKernelLayout;
 use futures::executor::block_on;
 use gdbstub::common::Tid;
+use inspect::InspectionBuilder;
+use mesh::CancelContext;
 use mesh::rpc::RpcSend;
 use std::num::NonZeroUsize;
+use std::time::Duration;
 use vmm_core_defs::debug_rpc::DebugRequest;
 use vmm_core_defs::debug_rpc::DebugStopReason;
 use vmm_core_defs::debug_rpc::GuestAddress;
 use vmm_core_defs::debug_rpc::HardwareBreakpoint;
 
 pub mod arch;
+mod linux;
 pub mod targets;
 
+/// How long to wait for the VM to respond to an inspect request.
+const INSPECT_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[derive(Debug, Default, Clone)]
 pub struct Vp {
     pub single_step: bool,
@@ -26,20 +34,46 @@ pub struct VmProxy {
 
     pub vps: Box<[Vp]>,
     pub breakpoints: [Option<HardwareBreakpoint>; 4],
+
+    /// Whether to report the guest RAM layout to the debugger.
+    pub report_memory_map: bool,
+    memory_map_xml: Option<String>,
+
+    linux: Option<linux::LinuxAwareness>,
 }
 
 impl VmProxy {
-    pub fn new(req_chan: mesh::Sender<DebugRequest>, vp_count: u32) -> Self {
+    pub fn new(
+        req_chan: mesh::Sender<DebugRequest>,
+        vp_count: u32,
+        report_memory_map: bool,
+        linux: Option<LinuxKernelLayout>,
+    ) -> Self {
         Self {
             req_chan,
             vps: vec![Vp::default(); vp_count as usize].into(),
             stop_chan: None,
             breakpoints: [None; 4],
+            report_memory_map,
+            memory_map_xml: None,
+            linux: linux.map(linux::LinuxAwareness::new),
         }
     }
 
-    pub fn into_params(self) -> (mesh::Sender<DebugRequest>, u32) {
-        (self.req_chan, self.vps.len() as u32)
+    pub fn into_params(
+        self,
+    ) -> (
+        mesh::Sender<DebugRequest>,
+        u32,
+        bool,
+        Option<LinuxKernelLayout>,
+    ) {
+        (
+            self.req_chan,
+            self.vps.len() as u32,
+            self.report_memory_map,
+            self.linux.map(linux::LinuxAwareness::into_layout),
+        )
     }
 
     pub fn send_req(&mut self, req: DebugRequest) {
@@ -63,7 +97,36 @@ pub fn vp_to_tid(&self, vp: u32) -> Tid {
         NonZeroUsize::new(vp as usize + 1).unwrap()
     }
 
-    #[expect(dead_code)] // TODO: add monitor command to inspect physical memory?
+    /// Inspects the VM at `path`, to `depth` levels, or without limit if
+    /// `None`.
+    fn inspect(&mut self, path: &str, depth: Option<usize>) -> inspect::Node {
+        let mut inspection = InspectionBuilder::new(path)
+            .depth(depth)
+            .inspect(&inspect::send(&self.req_chan, DebugRequest::Inspect));
+        let _ = block_on(
+            CancelContext::new()
+                .with_timeout(INSPECT_TIMEOUT)
+                .until_cancelled(inspection.resolve()),
+        );
+        inspection.results()
+    }
+
+    fn nmi(&mut self, vp_index: u32) -> anyhow::Result<()> {
+        block_on(self.req_chan.call_failable(DebugRequest::Nmi, vp_index))
+            .context("failed to inject nmi")
+    }
+
+    fn reset(&mut self) -> anyhow::Result<()> {
+        block_on(self.req_chan.call_failable(DebugRequest::Reset, ()))
+            .context("failed to reset the vm")
+    }
+
+    /// Returns the `(gpa, length)` ranges of guest RAM.
+    fn memory_map(&mut self) -> anyhow::Result<Vec<(u64, u64)>> {
+        block_on(self.req_chan.call_failable(DebugRequest::GetMemoryMap, ()))
+            .context("failed to get the memory map")
+    }
+
     fn read_guest_physical_memory(&mut self, gpa: u64, data: &mut [u8]) -> anyhow::Result<()> {
         let buf = block_on(self.req_chan.call_failable(
             DebugRequest::ReadMemory,
@@ -3,6 +3,7 @@
 
 use super::TargetArch;
 use super::VmTarget;
+use super::copy_to_buf;
 use crate::gdb::targets::ToTargetResult;
 use futures::executor::block_on;
 use gdbstub::common::Signal;
@@ -14,26 +15,28 @@
 use gdbstub::target::ext::base::multithread::MultiThreadSingleStep;
 use gdbstub::target::ext::base::multithread::MultiThreadSingleStepOps;
 use gdbstub::target::ext::base::single_register_access::SingleRegisterAccess;
+use gdbstub::target::ext::thread_extra_info::ThreadExtraInfo;
+use gdbstub::target::ext::thread_extra_info::ThreadExtraInfoOps;
 use mesh::rpc::RpcSend;
 use vmm_core_defs::debug_rpc::DebugRequest;
 use vmm_core_defs::debug_rpc::DebugState;
+use vmm_core_defs::debug_rpc::DebuggerVpState;
 
 impl<T: TargetArch> MultiThreadBase for VmTarget<'_, T> {
     fn read_registers(&mut self, regs: &mut T::Registers, tid: Tid) -> TargetResult<(), Self> {
-        let vp_index = self.0.tid_to_vp(tid).fatal()?;
-
-        let state = block_on(
-            self.0
-                .req_chan
-                .call_failable(DebugRequest::GetVpState, vp_index),
-        )
-        .nonfatal()?;
+        let state = self.thread_state(tid)?;
 
         T::registers(&state, regs)?;
         Ok(())
     }
 
     fn write_registers(&mut self, regs: &T::Registers, tid: Tid) -> TargetResult<(), Self> {
+        if self.0.is_task_tid(tid) {
+            return Err(anyhow::anyhow!(
+                "cannot write the registers of a task that is not running"
+            ))
+            .nonfatal();
+        }
         let vp_index = self.0.tid_to_vp(tid).fatal()?;
 
         let mut state = block_on(
@@ -62,7 +65,11 @@ fn read_addrs(
         tid: Tid,
     ) -> TargetResult<(), Self> {
         self.0
-            .read_guest_virtual_memory(self.0.tid_to_vp(tid).fatal()?, start_addr.into(), data)
+            .read_guest_virtual_memory(
+                self.0.tid_to_memory_vp(tid).fatal()?,
+                start_addr.into(),
+                data,
+            )
             .nonfatal()?;
         Ok(())
     }
@@ -74,7 +81,11 @@ fn write_addrs(
         tid: Tid,
     ) -> TargetResult<(), Self> {
         self.0
-            .write_guest_virtual_memory(self.0.tid_to_vp(tid).fatal()?, start_addr.into(), data)
+            .write_guest_virtual_memory(
+                self.0.tid_to_memory_vp(tid).fatal()?,
+                start_addr.into(),
+                data,
+            )
             .nonfatal()?;
         Ok(())
     }
@@ -86,6 +97,17 @@ fn list_active_threads(
         for i in 0..self.0.vps.len() as u32 {
             thread_is_active(self.0.vp_to_tid(i));
         }
+        // Still list the VPs if the task list cannot be read, since the guest
+        // may not have booted far enough to have one.
+        if let Err(err) = self.0.refresh_linux_tasks() {
+            tracelimit::warn_ratelimited!(
+                error = err.as_ref() as &dyn std::error::Error,
+                "failed to read the linux task list"
+            );
+        }
+        for tid in self.0.task_tids() {
+            thread_is_active(tid);
+        }
         Ok(())
     }
 
@@ -102,6 +124,34 @@ fn support_single_register_access(
     > {
         Some(self)
     }
+
+    #[inline(always)]
+    fn support_thread_extra_info(&mut self) -> Option<ThreadExtraInfoOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl<T: TargetArch> VmTarget<'_, T> {
+    /// Returns the register state of the thread `tid`.
+    fn thread_state(&mut self, tid: Tid) -> TargetResult<DebuggerVpState, Self> {
+        if self.0.is_task_tid(tid) {
+            return self.0.task_state(tid).nonfatal();
+        }
+        let vp_index = self.0.tid_to_vp(tid).fatal()?;
+        block_on(
+            self.0
+                .req_chan
+                .call_failable(DebugRequest::GetVpState, vp_index),
+        )
+        .nonfatal()
+    }
+}
+
+impl<T: TargetArch> ThreadExtraInfo for VmTarget<'_, T> {
+    fn thread_extra_info(&self, tid: Tid, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let description = self.0.thread_description(tid);
+        Ok(copy_to_buf(description.as_bytes(), buf))
+    }
 }
 
 impl<T: TargetArch> SingleRegisterAccess<Tid> for VmTarget<'_, T> {
@@ -111,19 +161,18 @@ fn read_register(
         reg_id: T::RegId,
         buf: &mut [u8],
     ) -> TargetResult<usize, Self> {
-        let vp_index = self.0.tid_to_vp(tid).fatal()?;
-
-        let state = block_on(
-            self.0
-                .req_chan
-                .call_failable(DebugRequest::GetVpState, vp_index),
-        )
-        .nonfatal()?;
+        let state = self.thread_state(tid)?;
 
         Ok(T::register(&state, reg_id, buf)?)
     }
 
     fn write_register(&mut self, tid: Tid, reg_id: T::RegId, val: &[u8]) -> TargetResult<(), Self> {
+        if self.0.is_task_tid(tid) {
+            return Err(anyhow::anyhow!(
+                "cannot write the registers of a task that is not running"
+            ))
+            .nonfatal();
+        }
         let vp_index = self.0.tid_to_vp(tid).fatal()?;
 
         let mut state = block_on(
@@ -148,6 +197,9 @@ fn write_register(&mut self, tid: Tid, reg_id: T::RegId, val: &[u8]) -> TargetRe
 
 impl<T: TargetArch> MultiThreadResume for VmTarget<'_, T> {
     fn resume(&mut self) -> Result<(), Self::Error> {
+        if let Some(linux) = &mut self.0.linux {
+            linux.invalidate();
+        }
         for (vp_index, vp) in self.0.vps.iter().enumerate() {
             let state = DebugState {
                 single_step: vp.single_step,
@@ -180,6 +232,10 @@ fn set_resume_action_continue(
         tid: Tid,
         _signal: Option<Signal>,
     ) -> Result<(), Self::Error> {
+        // Tasks that are not running continue when the VPs do.
+        if self.0.is_task_tid(tid) {
+            return Ok(());
+        }
         let vp_index = self.0.tid_to_vp(tid)?;
         self.0.vps[vp_index as usize].single_step = false;
         Ok(())
@@ -197,6 +253,10 @@ fn set_resume_action_step(
         tid: Tid,
         _signal: Option<Signal>,
     ) -> Result<(), Self::Error> {
+        if self.0.is_task_tid(tid) {
+            tracelimit::warn_ratelimited!("cannot single step a task that is not running");
+            return Ok(());
+        }
         let vp_index = self.0.tid_to_vp(tid)?;
         self.0.vps[vp_index as usize].single_step = true;
         Ok(())
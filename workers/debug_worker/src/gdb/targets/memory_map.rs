@@ -0,0 +1,49 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use super::TargetArch;
+use super::VmTarget;
+use super::copy_range_to_buf;
+use gdbstub::target;
+use gdbstub::target::TargetResult;
+use std::fmt::Write;
+
+impl<T: TargetArch> target::ext::memory_map::MemoryMap for VmTarget<'_, T> {
+    fn memory_map_xml(
+        &self,
+        offset: u64,
+        length: usize,
+        buf: &mut [u8],
+    ) -> TargetResult<usize, Self> {
+        let xml = self.0.memory_map_xml.as_deref().unwrap_or_default();
+        Ok(copy_range_to_buf(xml.as_bytes(), offset, length, buf))
+    }
+}
+
+impl<T: TargetArch> VmTarget<'_, T> {
+    /// Fetches the guest RAM layout to report to the debugger.
+    pub fn load_memory_map(&mut self) -> anyhow::Result<()> {
+        let ranges = self.0.memory_map()?;
+        self.0.memory_map_xml = Some(memory_map_xml(&ranges));
+        Ok(())
+    }
+}
+
+/// Returns the memory map XML describing the guest RAM `ranges`, each a
+/// `(gpa, length)` pair.
+///
+/// Like the target description, this has no newlines, for ExdiGdbSrv.
+fn memory_map_xml(ranges: &[(u64, u64)]) -> String {
+    let mut xml = String::from(
+        r#"<?xml version="1.0"?><!DOCTYPE memory-map PUBLIC "+//IDN gnu.org//DTD GDB Memory Map V1.0//EN" "http://sourceware.org/gdb/gdb-memory-map.dtd"><memory-map>"#,
+    );
+    for &(start, length) in ranges {
+        write!(
+            xml,
+            r#"<memory type="ram" start="{start:#x}" length="{length:#x}"/>"#
+        )
+        .unwrap();
+    }
+    xml.push_str("</memory-map>");
+    xml
+}
@@ -0,0 +1,124 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! GDB `monitor` commands, for VM operations that GDB has no command for.
+
+use super::TargetArch;
+use super::VmTarget;
+use anyhow::Context;
+use gdbstub::target::ext::monitor_cmd::ConsoleOutput;
+use gdbstub::target::ext::monitor_cmd::MonitorCmd;
+use std::fmt::Write;
+
+const HELP: &str = "\
+monitor commands:
+  help                 show this message
+  inspect [-r] [PATH]  inspect the VM state at PATH, recursively with -r
+  memmap               list the guest RAM ranges
+  nmi [VP]             inject an NMI into VP (GDB thread ID - 1), default 0
+  phys ADDR [LEN]      dump LEN bytes (default 64) of guest physical memory
+  reset                reset the VM, leaving it stopped at the reset vector;
+                       run `maintenance flush register-cache` afterwards
+";
+
+/// The largest physical memory dump, in bytes.
+const MAX_PHYS_LEN: u64 = 0x1000;
+
+impl<T: TargetArch> MonitorCmd for VmTarget<'_, T> {
+    fn handle_monitor_cmd(
+        &mut self,
+        cmd: &[u8],
+        mut out: ConsoleOutput<'_>,
+    ) -> Result<(), Self::Error> {
+        let cmd = String::from_utf8_lossy(cmd);
+        // Report command errors to the user rather than ending the session.
+        let text = self
+            .run_monitor_cmd(&cmd)
+            .unwrap_or_else(|err| format!("error: {err:#}\n"));
+        gdbstub::output!(out, "{}", text);
+        Ok(())
+    }
+}
+
+impl<T: TargetArch> VmTarget<'_, T> {
+    fn run_monitor_cmd(&mut self, cmd: &str) -> anyhow::Result<String> {
+        let mut args = cmd.split_whitespace();
+        let mut text = String::new();
+        match args.next() {
+            None | Some("help") => text.push_str(HELP),
+            Some("inspect") => {
+                let mut recursive = false;
+                let mut path = "";
+                for arg in args {
+                    match arg {
+                        "-r" => recursive = true,
+                        _ if path.is_empty() => path = arg,
+                        _ => anyhow::bail!("unexpected argument: {arg}"),
+                    }
+                }
+                let node = self.0.inspect(path, (!recursive).then_some(0));
+                writeln!(text, "{node:#}")?;
+            }
+            Some("memmap") => {
+                for (start, len) in self.0.memory_map()? {
+                    writeln!(text, "{start:#018x}-{:#018x} ram", start + len - 1)?;
+                }
+            }
+            Some("nmi") => {
+                let vp = args.next().map_or(Ok(0), parse_number)?;
+                let vp = u32::try_from(vp)
+                    .ok()
+                    .filter(|&vp| (vp as usize) < self.0.vps.len())
+                    .with_context(|| format!("invalid vp {vp}"))?;
+                self.0.nmi(vp)?;
+                writeln!(text, "injected nmi into vp {vp}")?;
+            }
+            Some("phys") => {
+                let addr = parse_number(args.next().context("missing address")?)?;
+                let len = args.next().map_or(Ok(64), parse_number)?;
+                anyhow::ensure!(
+                    len <= MAX_PHYS_LEN,
+                    "length must be at most {MAX_PHYS_LEN:#x}"
+                );
+                let mut data = vec![0; len as usize];
+                self.0.read_guest_physical_memory(addr, &mut data)?;
+                hex_dump(&mut text, addr, &data);
+            }
+            Some("reset") => {
+                self.0.reset()?;
+                writeln!(text, "vm reset")?;
+            }
+            Some(cmd) => anyhow::bail!("unknown command: {cmd}, try `monitor help`"),
+        }
+        Ok(text)
+    }
+}
+
+/// Parses a hexadecimal number with a `0x` prefix, or a decimal number.
+fn parse_number(s: &str) -> anyhow::Result<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .with_context(|| format!("invalid number: {s}"))
+}
+
+/// Writes `data`, which was read from `addr`, sixteen bytes per line.
+fn hex_dump(text: &mut String, addr: u64, data: &[u8]) {
+    for (i, line) in data.chunks(16).enumerate() {
+        let _ = write!(text, "{:016x}:", addr.wrapping_add(i as u64 * 16));
+        for b in line {
+            let _ = write!(text, " {b:02x}");
+        }
+        text.push_str(&"   ".repeat(16 - line.len()));
+        text.push_str("  ");
+        text.extend(line.iter().map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        }));
+        text.push('\n');
+    }
+}
@@ -84,7 +84,12 @@ fn new(params: Self::Parameters) -> anyhow::Result<Self> {
         Ok(Self {
             listener: params.listener,
             state: State::Listening {
-                vm_proxy: VmProxy::new(params.req_chan, params.vp_count),
+                vm_proxy: VmProxy::new(
+                    params.req_chan,
+                    params.vp_count,
+                    params.memory_map,
+                    params.linux,
+                ),
             },
             initial_arch: match params.target_arch {
                 debug_worker_defs::TargetArch::X86_64 => Architecture::X86_64,
@@ -135,11 +140,14 @@ fn run(self, mut rpc_recv: mesh::Receiver<WorkerRpc<Self::Parameters>>) -> anyho
                             };
 
                             let state = {
-                                let (req_chan, vp_count) = vm_proxy.into_params();
+                                let (req_chan, vp_count, memory_map, linux) =
+                                    vm_proxy.into_params();
                                 DebuggerParameters {
                                     listener: server.listener.into_inner(),
                                     req_chan,
                                     vp_count,
+                                    memory_map,
+                                    linux,
                                     target_arch: match server.architecture {
                                         Architecture::X86_64 => {
                                             debug_worker_defs::TargetArch::X86_64
@@ -295,6 +303,15 @@ async fn run_state_machine<T: TargetArch>(
 
     tracing::info!(?reason, "got initial breakpoint");
 
+    if vm_target.report_memory_map
+        && let Err(err) = vm_target.load_memory_map()
+    {
+        tracing::warn!(
+            error = err.as_ref() as &dyn std::error::Error,
+            "failed to get the memory map"
+        );
+    }
+
     let mut gdb =
         gdbstub::stub::GdbStub::new(SocketConnection(socket)).run_state_machine(&mut vm_target)?;
 
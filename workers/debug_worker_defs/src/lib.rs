@@ -18,6 +18,40 @@ pub struct DebuggerParameters<T> {
     pub req_chan: mesh::Sender<DebugRequest>,
     pub vp_count: u32,
     pub target_arch: TargetArch,
+    /// Report the guest RAM layout to the debugger.
+    ///
+    /// GDB refuses to access addresses outside the reported memory, including
+    /// virtual addresses, unless `mem inaccessible-by-default` is turned off.
+    pub memory_map: bool,
+    /// Present the tasks of a guest Linux kernel as threads.
+    pub linux: Option<LinuxKernelLayout>,
+}
+
+/// The guest Linux kernel addresses and `struct task_struct` offsets needed to
+/// walk the kernel's task list.
+///
+/// Addresses are kernel virtual addresses, so the kernel must not be
+/// relocated by KASLR.
+#[derive(Debug, Clone, Protobuf)]
+pub struct LinuxKernelLayout {
+    /// The address of `init_task`, the head of the task list.
+    pub init_task: u64,
+    /// The address of the `__per_cpu_offset` array.
+    pub per_cpu_offset: Option<u64>,
+    /// The per-CPU address of the current task pointer.
+    ///
+    /// Along with `per_cpu_offset`, this is used to find the task running on
+    /// each VP, assuming that VP indexes match Linux CPU numbers.
+    pub current_task: Option<u64>,
+    /// The offset of `tasks`, the task list entry.
+    pub task_tasks: u64,
+    /// The offset of `pid`.
+    pub task_pid: u64,
+    /// The offset of `comm`, the task name.
+    pub task_comm: u64,
+    /// The offset of the context saved when the task is switched out:
+    /// `thread.sp` on x86_64, or `thread.cpu_context` on aarch64.
+    pub task_context: u64,
 }
 
 #[derive(Debug, Copy, Clone, Protobuf)]
@@ -8,6 +8,7 @@
 use anyhow::Context;
 use anyhow::anyhow;
 use futures::FutureExt;
+use futures::future::Either;
 use input_core::InputData;
 use input_core::KeyboardData;
 use input_core::MouseData;
@@ -23,14 +24,44 @@
 use std::future::Future;
 use std::net::TcpListener;
 use std::pin::Pin;
+use std::pin::pin;
 use std::time::Duration;
 use tracing_helpers::AnyhowValueExt;
 use vnc_worker_defs::VncParameters;
+use vnc_worker_defs::VncSecurity;
+
+/// How long a new client has to complete the security handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
 
 /// A worker for running a VNC server.
 pub struct VncWorker<T: Listener> {
     listener: T,
     state: State<T>,
+    security: Security,
+}
+
+/// The security requirements, as configured and as used by the server.
+struct Security {
+    params: VncSecurity,
+    config: vnc::SecurityConfig,
+}
+
+impl Security {
+    fn new(params: VncSecurity) -> anyhow::Result<Self> {
+        let tls = params
+            .tls
+            .as_ref()
+            .map(|tls| vnc::TlsConfig::new(&tls.cert_chain, &tls.private_key))
+            .transpose()
+            .context("invalid VNC TLS configuration")?;
+        Ok(Self {
+            config: vnc::SecurityConfig {
+                password: params.password.clone(),
+                tls,
+            },
+            params,
+        })
+    }
 }
 
 /// The current server state.
@@ -88,6 +119,7 @@ impl<T: 'static + Listener + MeshField + Send> VncWorker<T> {
     fn new_inner(params: VncParameters<T>) -> anyhow::Result<Self> {
         Ok(Self {
             listener: params.listener,
+            security: Security::new(params.security)?,
             state: State::Listening {
                 view: ViewWrapper(
                     params
@@ -116,6 +148,7 @@ fn run_inner(
             let mut server = Server {
                 listener,
                 state: self.state,
+                security: self.security,
             };
 
             let rpc = loop {
@@ -145,6 +178,7 @@ fn run_inner(
                     listener: server.listener.into_inner(),
                     framebuffer: view.0.access(),
                     input_send: input.send,
+                    security: server.security.params,
                 };
                 rpc.complete(Ok(state));
             }
@@ -156,20 +190,34 @@ fn run_inner(
 struct Server<T: Listener> {
     listener: PolledSocket<T>,
     state: State<T>,
+    security: Security,
 }
 
 impl<T: Listener> Server<T> {
-    /// Creates a VNC connection task for the given socket and resources.
-    fn start_connection(
+    /// Authenticates a new client, giving up after [`HANDSHAKE_TIMEOUT`].
+    async fn authenticate(
         driver: &LocalDriver,
         socket: PolledSocket<socket2::Socket>,
+        security: &vnc::SecurityConfig,
+    ) -> anyhow::Result<vnc::Client> {
+        let mut timer = PolledTimer::new(driver);
+        futures::select! { // race semantics
+            r = vnc::Client::handshake(socket, security).fuse() => r.context("VNC handshake failed"),
+            _ = timer.sleep(HANDSHAKE_TIMEOUT).fuse() => Err(anyhow!("VNC handshake timed out")),
+        }
+    }
+
+    /// Creates a VNC connection task for the given client and resources.
+    fn start_connection(
+        driver: &LocalDriver,
+        client: vnc::Client,
         view: ViewWrapper,
         input: VncInput,
     ) -> (
         mesh::OneshotSender<()>,
         Pin<Box<dyn Future<Output = (ViewWrapper, VncInput)>>>,
     ) {
-        let mut vncserver = vnc::Server::new("OpenVMM VM".into(), socket, view, input);
+        let mut vncserver = vnc::Server::with_client("OpenVMM VM".into(), client, view, input);
         let mut timer = PolledTimer::new(driver);
         let (abort_send, abort_recv) = mesh::oneshot();
         let connection = Box::pin(async move {
@@ -209,6 +257,19 @@ async fn process(&mut self, driver: &LocalDriver) -> anyhow::Result<()> {
 
                     tracing::info!(address = ?remote_addr, "VNC client connected");
 
+                    let client =
+                        match Self::authenticate(driver, socket, &self.security.config).await {
+                            Ok(client) => client,
+                            Err(err) => {
+                                tracing::warn!(
+                                    address = ?remote_addr,
+                                    error = err.as_error(),
+                                    "rejected VNC client"
+                                );
+                                continue;
+                            }
+                        };
+
                     let (view, input) = if let State::Listening { view, input } =
                         std::mem::replace(&mut self.state, State::Invalid)
                     {
@@ -217,7 +278,7 @@ async fn process(&mut self, driver: &LocalDriver) -> anyhow::Result<()> {
                         unreachable!()
                     };
 
-                    let (abort, task) = Self::start_connection(driver, socket, view, input);
+                    let (abort, task) = Self::start_connection(driver, client, view, input);
                     self.state = State::Connected {
                         remote_addr,
                         task,
@@ -225,7 +286,7 @@ async fn process(&mut self, driver: &LocalDriver) -> anyhow::Result<()> {
                     };
                 }
                 State::Connected { .. } => {
-                    let (mut task, abort, _old_addr) = if let State::Connected {
+                    let (mut task, abort, old_addr) = if let State::Connected {
                         task,
                         abort,
                         remote_addr,
@@ -244,16 +305,59 @@ async fn process(&mut self, driver: &LocalDriver) -> anyhow::Result<()> {
                         }
                         accept = self.listener.accept().fuse() => {
                             let (new_socket, remote_addr) = accept?;
-                            tracing::info!(address = ?remote_addr, "New VNC client, disconnecting previous");
-                            abort.send(());
-                            let (view, input) = task.await;
+                            tracing::info!(address = ?remote_addr, "New VNC client connected");
                             let socket = PolledSocket::new(driver, new_socket.into())?;
-                            let (abort, task) = Self::start_connection(driver, socket, view, input);
-                            self.state = State::Connected {
-                                remote_addr,
-                                task,
-                                abort,
-                            };
+
+                            // Keep serving the current client until the new
+                            // one authenticates, so that an unauthenticated
+                            // client cannot disconnect it.
+                            let authenticate =
+                                pin!(Self::authenticate(driver, socket, &self.security.config));
+                            let (client, finished) =
+                                match futures::future::select(authenticate, task.as_mut()).await {
+                                    Either::Left((client, _)) => (client, None),
+                                    Either::Right((finished, authenticate)) => {
+                                        (authenticate.await, Some(finished))
+                                    }
+                                };
+
+                            match client {
+                                Ok(client) => {
+                                    let (view, input) = match finished {
+                                        Some(finished) => finished,
+                                        None => {
+                                            tracing::info!(
+                                                address = ?remote_addr,
+                                                "New VNC client authenticated, disconnecting previous"
+                                            );
+                                            abort.send(());
+                                            task.await
+                                        }
+                                    };
+                                    let (abort, task) =
+                                        Self::start_connection(driver, client, view, input);
+                                    self.state = State::Connected {
+                                        remote_addr,
+                                        task,
+                                        abort,
+                                    };
+                                }
+                                Err(err) => {
+                                    tracing::warn!(
+                                        address = ?remote_addr,
+                                        error = err.as_error(),
+                                        "rejected VNC client"
+                                    );
+                                    self.state = match finished {
+                                        Some((view, input)) => State::Listening { view, input },
+                                        None => State::Connected {
+                                            remote_addr: old_addr,
+                                            task,
+                                            abort,
+                                        },
+                                    };
+                                }
+                            }
                         }
                     }
                 }
@@ -275,7 +379,9 @@ fn inspect(&self, req: inspect::Request<'_>) {
             }
             State::Invalid => unreachable!(),
         };
-        resp.field("state", state);
+        resp.field("state", state)
+            .field("password", self.security.params.password.is_some())
+            .field("tls", self.security.params.tls.is_some());
     }
 }
 
@@ -308,4 +414,19 @@ fn read_line(&mut self, line: u16, data: &mut [u8]) {
     fn resolution(&mut self) -> (u16, u16) {
         self.0.resolution()
     }
+
+    fn dirty(&mut self, rects: &mut Vec<vnc::Rect>) -> bool {
+        let mut dirty = Vec::new();
+        if !self.0.take_dirty(&mut dirty) {
+            return false;
+        }
+        let clamp = |v: u32| v.try_into().unwrap_or(u16::MAX);
+        rects.extend(dirty.into_iter().map(|rect| vnc::Rect {
+            x: clamp(rect.left),
+            y: clamp(rect.top),
+            width: clamp(rect.right.saturating_sub(rect.left)),
+            height: clamp(rect.bottom.saturating_sub(rect.top)),
+        }));
+        true
+    }
 }
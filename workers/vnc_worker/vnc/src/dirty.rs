@@ -0,0 +1,224 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Tile-based detection of framebuffer updates.
+//!
+//! The framebuffer is divided into tiles, and a tile is sent to the client
+//! when its contents differ from what was last sent. If the framebuffer
+//! reports the regions the guest updated, only the tiles in those regions are
+//! compared, with an occasional comparison of every tile to catch updates
+//! that were not reported.
+
+use crate::Framebuffer;
+use crate::Rect;
+use crate::TILE_SIZE;
+use zerocopy::IntoBytes;
+
+/// The number of updates between comparisons of the whole framebuffer, when
+/// the framebuffer reports updated regions.
+const FULL_SCAN_INTERVAL: u32 = 10;
+
+pub(crate) struct DirtyTracker {
+    width: u16,
+    height: u16,
+    /// The framebuffer contents as last sent to the client.
+    sent: Vec<u32>,
+    line: Vec<u32>,
+    hints: Vec<Rect>,
+    /// Per tile, whether to compare it in this update.
+    check: Vec<bool>,
+    /// Per tile, whether it changed in this update.
+    dirty: Vec<bool>,
+    updates_until_full_scan: u32,
+}
+
+impl DirtyTracker {
+    pub fn new() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            sent: Vec::new(),
+            line: Vec::new(),
+            hints: Vec::new(),
+            check: Vec::new(),
+            dirty: Vec::new(),
+            updates_until_full_scan: 0,
+        }
+    }
+
+    /// Returns the framebuffer contents as of the last update, `width` pixels
+    /// per line.
+    pub fn pixels(&self) -> &[u32] {
+        &self.sent
+    }
+
+    /// Reads the tiles of `fb` that may have changed since the last update,
+    /// appending the ones that did to `rects`. If `full`, all tiles are
+    /// appended.
+    pub fn update(
+        &mut self,
+        fb: &mut impl Framebuffer,
+        width: u16,
+        height: u16,
+        full: bool,
+        rects: &mut Vec<Rect>,
+    ) {
+        let full = full || width != self.width || height != self.height;
+        if full {
+            self.width = width;
+            self.height = height;
+            self.sent.clear();
+            self.sent.resize(width as usize * height as usize, 0);
+        }
+
+        let tiles_x = width.div_ceil(TILE_SIZE) as usize;
+        let tiles_y = height.div_ceil(TILE_SIZE) as usize;
+        let tile_count = tiles_x * tiles_y;
+
+        // Always collect the reported regions, even if they are not needed,
+        // so that they do not apply to a later update.
+        self.hints.clear();
+        let hinted = fb.dirty(&mut self.hints);
+        self.check.clear();
+        if full || !hinted || self.updates_until_full_scan == 0 {
+            self.check.resize(tile_count, true);
+            self.updates_until_full_scan = FULL_SCAN_INTERVAL;
+        } else {
+            self.check.resize(tile_count, false);
+            self.updates_until_full_scan -= 1;
+            for hint in &self.hints {
+                let x_end = (hint.x as u32 + hint.width as u32).min(width.into());
+                let y_end = (hint.y as u32 + hint.height as u32).min(height.into());
+                if hint.x as u32 >= x_end || hint.y as u32 >= y_end {
+                    continue;
+                }
+                let tile_size = TILE_SIZE as u32;
+                for ty in hint.y as u32 / tile_size..y_end.div_ceil(tile_size) {
+                    for tx in hint.x as u32 / tile_size..x_end.div_ceil(tile_size) {
+                        self.check[ty as usize * tiles_x + tx as usize] = true;
+                    }
+                }
+            }
+        }
+
+        self.dirty.clear();
+        self.dirty.resize(tile_count, full);
+        self.line.resize(width as usize, 0);
+        let width = width as usize;
+        let tile_size = TILE_SIZE as usize;
+        for ty in 0..tiles_y {
+            let check = &self.check[ty * tiles_x..(ty + 1) * tiles_x];
+            if !check.contains(&true) {
+                continue;
+            }
+            let dirty = &mut self.dirty[ty * tiles_x..(ty + 1) * tiles_x];
+            for y in ty * tile_size..((ty + 1) * tile_size).min(height as usize) {
+                fb.read_line(y as u16, self.line.as_mut_bytes());
+                let sent = &mut self.sent[y * width..(y + 1) * width];
+                for (tx, (&check, dirty)) in check.iter().zip(dirty.iter_mut()).enumerate() {
+                    let x = tx * tile_size..((tx + 1) * tile_size).min(width);
+                    if check && sent[x.clone()] != self.line[x.clone()] {
+                        sent[x.clone()].copy_from_slice(&self.line[x]);
+                        *dirty = true;
+                    }
+                }
+            }
+        }
+
+        for (i, _) in self.dirty.iter().enumerate().filter(|&(_, &dirty)| dirty) {
+            let x = (i % tiles_x) as u16 * TILE_SIZE;
+            let y = (i / tiles_x) as u16 * TILE_SIZE;
+            rects.push(Rect {
+                x,
+                y,
+                width: TILE_SIZE.min(self.width - x),
+                height: TILE_SIZE.min(self.height - y),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DirtyTracker;
+    use super::FULL_SCAN_INTERVAL;
+    use crate::Framebuffer;
+    use crate::Rect;
+    use zerocopy::IntoBytes;
+
+    struct TestFramebuffer {
+        width: u16,
+        pixels: Vec<u32>,
+        hints: Option<Vec<Rect>>,
+    }
+
+    impl Framebuffer for TestFramebuffer {
+        fn resolution(&mut self) -> (u16, u16) {
+            (self.width, (self.pixels.len() / self.width as usize) as u16)
+        }
+
+        fn read_line(&mut self, line: u16, data: &mut [u8]) {
+            let start = line as usize * self.width as usize;
+            data.copy_from_slice(self.pixels[start..start + self.width as usize].as_bytes());
+        }
+
+        fn dirty(&mut self, rects: &mut Vec<Rect>) -> bool {
+            match &mut self.hints {
+                Some(hints) => {
+                    rects.append(hints);
+                    true
+                }
+                None => false,
+            }
+        }
+    }
+
+    fn update(tracker: &mut DirtyTracker, fb: &mut TestFramebuffer, full: bool) -> Vec<Rect> {
+        let (width, height) = fb.resolution();
+        let mut rects = Vec::new();
+        tracker.update(fb, width, height, full, &mut rects);
+        rects
+    }
+
+    #[test]
+    fn test_dirty_tiles() {
+        let mut fb = TestFramebuffer {
+            width: 100,
+            pixels: vec![0; 100 * 70],
+            hints: None,
+        };
+        let mut tracker = DirtyTracker::new();
+        let tile = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        assert_eq!(
+            update(&mut tracker, &mut fb, false),
+            [
+                tile(0, 0, 64, 64),
+                tile(64, 0, 36, 64),
+                tile(0, 64, 64, 6),
+                tile(64, 64, 36, 6)
+            ]
+        );
+        assert!(update(&mut tracker, &mut fb, false).is_empty());
+
+        fb.pixels[69 * 100 + 99] = 1;
+        assert_eq!(update(&mut tracker, &mut fb, false), [tile(64, 64, 36, 6)]);
+        assert_eq!(tracker.pixels(), fb.pixels);
+
+        // With hints, only the hinted tiles are compared...
+        fb.hints = Some(vec![tile(10, 10, 1, 1)]);
+        fb.pixels[10 * 100 + 10] = 1;
+        fb.pixels[10 * 100 + 90] = 1;
+        assert_eq!(update(&mut tracker, &mut fb, false), [tile(0, 0, 64, 64)]);
+        for _ in 1..FULL_SCAN_INTERVAL {
+            assert!(update(&mut tracker, &mut fb, false).is_empty());
+        }
+        // ...until the next full scan.
+        assert_eq!(update(&mut tracker, &mut fb, false), [tile(64, 0, 36, 64)]);
+        assert_eq!(tracker.pixels(), fb.pixels);
+    }
+}
@@ -0,0 +1,351 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Rectangle encodings for framebuffer updates.
+
+use crate::Error;
+use crate::Rect;
+use crate::convert_pixels;
+use crate::rfb;
+use crate::tight;
+use crate::zrle;
+use flate2::Compress;
+use flate2::Compression;
+use flate2::FlushCompress;
+use std::collections::HashMap;
+use zerocopy::IntoBytes;
+
+/// An encoding for the pixel data of framebuffer updates.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Raw,
+    Zlib,
+    Zrle,
+    Tight,
+}
+
+impl Encoding {
+    /// Returns the first encoding in a client's `SetEncodings` list that the
+    /// server supports, or raw, which all clients support.
+    pub fn choose(encodings: &[u32]) -> Self {
+        encodings
+            .iter()
+            .find_map(|&encoding| match encoding {
+                rfb::ENCODING_TYPE_ZLIB => Some(Self::Zlib),
+                rfb::ENCODING_TYPE_ZRLE => Some(Self::Zrle),
+                rfb::ENCODING_TYPE_TIGHT => Some(Self::Tight),
+                _ => None,
+            })
+            .unwrap_or(Self::Raw)
+    }
+
+    fn encoding_type(&self) -> u32 {
+        match self {
+            Self::Raw => rfb::ENCODING_TYPE_RAW,
+            Self::Zlib => rfb::ENCODING_TYPE_ZLIB,
+            Self::Zrle => rfb::ENCODING_TYPE_ZRLE,
+            Self::Tight => rfb::ENCODING_TYPE_TIGHT,
+        }
+    }
+}
+
+/// Returns the JPEG quality level, 0 to 9, from a client's `SetEncodings`
+/// list, or `None` if the client does not want JPEG.
+pub(crate) fn jpeg_quality_level(encodings: &[u32]) -> Option<u8> {
+    encodings.iter().find_map(|&encoding| {
+        (rfb::ENCODING_TYPE_JPEG_QUALITY_LEVEL_0..=rfb::ENCODING_TYPE_JPEG_QUALITY_LEVEL_9)
+            .contains(&encoding)
+            .then(|| (encoding - rfb::ENCODING_TYPE_JPEG_QUALITY_LEVEL_0) as u8)
+    })
+}
+
+/// The per-connection encoder state.
+///
+/// The zlib-based encodings each use continuous zlib streams for the whole
+/// connection, so the client's decompressor state must stay in sync with
+/// these.
+pub(crate) struct Encoder {
+    zlib: Compress,
+    zrle: Compress,
+    tight: tight::TightEncoder,
+    palette: Palette,
+    pixels: Vec<u32>,
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self {
+            zlib: Compress::new(Compression::fast(), true),
+            zrle: Compress::new(Compression::fast(), true),
+            tight: tight::TightEncoder::new(),
+            palette: Palette::default(),
+            pixels: Vec::new(),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Appends the rectangle `rect` of `fb`, which has `stride` pixels per
+    /// line, to `out`, including the rectangle header.
+    pub fn encode(
+        &mut self,
+        encoding: Encoding,
+        jpeg_quality_level: Option<u8>,
+        fmt: &rfb::PixelFormat,
+        fb: &[u32],
+        stride: usize,
+        rect: Rect,
+        out: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        let width = rect.width as usize;
+        let height = rect.height as usize;
+        self.pixels.clear();
+        for y in rect.y as usize..rect.y as usize + height {
+            let start = y * stride + rect.x as usize;
+            self.pixels.extend_from_slice(&fb[start..start + width]);
+        }
+
+        out.extend_from_slice(
+            rfb::Rectangle {
+                x: rect.x.into(),
+                y: rect.y.into(),
+                width: rect.width.into(),
+                height: rect.height.into(),
+                encoding_type: encoding.encoding_type().into(),
+            }
+            .as_bytes(),
+        );
+
+        match encoding {
+            Encoding::Raw => convert_pixels(&self.pixels, fmt, out),
+            Encoding::Zlib => {
+                self.buf.clear();
+                convert_pixels(&self.pixels, fmt, &mut self.buf);
+                write_zlib(&mut self.zlib, &self.buf, out)?;
+            }
+            Encoding::Zrle => {
+                self.buf.clear();
+                zrle::encode(
+                    &self.pixels,
+                    width,
+                    height,
+                    fmt,
+                    &mut self.palette,
+                    &mut self.buf,
+                );
+                write_zlib(&mut self.zrle, &self.buf, out)?;
+            }
+            Encoding::Tight => self.tight.encode(
+                &self.pixels,
+                width,
+                height,
+                fmt,
+                jpeg_quality_level,
+                &mut self.palette,
+                out,
+            )?,
+        }
+        Ok(())
+    }
+}
+
+/// Appends `data`, compressed, to `out`, preceded by its compressed length as
+/// a big-endian `u32`.
+fn write_zlib(stream: &mut Compress, data: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+    let start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    deflate(stream, data, out)?;
+    let len = (out.len() - start - 4) as u32;
+    out[start..start + 4].copy_from_slice(&len.to_be_bytes());
+    Ok(())
+}
+
+/// Appends `data`, compressed, to `out`.
+///
+/// The stream is flushed to a byte boundary so that the client can decompress
+/// all of `data`, without resetting the stream.
+pub(crate) fn deflate(
+    stream: &mut Compress,
+    mut data: &[u8],
+    out: &mut Vec<u8>,
+) -> Result<(), Error> {
+    loop {
+        out.reserve(data.len() / 2 + 64);
+        let before = stream.total_in();
+        stream
+            .compress_vec(data, out, FlushCompress::Sync)
+            .map_err(|_| Error::ZlibCompression)?;
+        data = &data[(stream.total_in() - before) as usize..];
+        // The flush is complete once the stream stops filling the output.
+        if data.is_empty() && out.len() < out.capacity() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The distinct colors of a rectangle.
+#[derive(Default)]
+pub(crate) struct Palette {
+    colors: Vec<u32>,
+    indexes: HashMap<u32, u8>,
+}
+
+impl Palette {
+    /// Collects the distinct colors of `pixels`, returning `false` if there
+    /// are more than `max`, which must be at most 256.
+    pub fn build(&mut self, pixels: &[u32], max: usize) -> bool {
+        assert!(max <= 256);
+        self.colors.clear();
+        self.indexes.clear();
+        let mut last = None;
+        for &pixel in pixels {
+            if last == Some(pixel) {
+                continue;
+            }
+            last = Some(pixel);
+            if !self.indexes.contains_key(&pixel) {
+                if self.colors.len() == max {
+                    return false;
+                }
+                self.indexes.insert(pixel, self.colors.len() as u8);
+                self.colors.push(pixel);
+            }
+        }
+        true
+    }
+
+    pub fn colors(&self) -> &[u32] {
+        &self.colors
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    /// Returns the index of `pixel`, which must be in the palette.
+    pub fn index(&self, pixel: u32) -> u8 {
+        self.indexes[&pixel]
+    }
+}
+
+/// Appends `indexes` to `out` packed into `bits` bits each, most significant
+/// bits first, with each row of `width` indexes padded to a byte boundary.
+pub(crate) fn write_packed(
+    indexes: impl IntoIterator<Item = u8>,
+    width: usize,
+    bits: usize,
+    out: &mut Vec<u8>,
+) {
+    let mut byte = 0;
+    let mut used = 0;
+    for (i, index) in indexes.into_iter().enumerate() {
+        byte |= index << (8 - bits - used);
+        used += bits;
+        if used == 8 || (i + 1) % width == 0 {
+            out.push(byte);
+            byte = 0;
+            used = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::Encoding;
+    use super::Palette;
+    use super::deflate;
+    use super::jpeg_quality_level;
+    use super::write_packed;
+    use crate::rfb;
+    use flate2::Compress;
+    use flate2::Compression;
+    use flate2::Decompress;
+    use flate2::FlushDecompress;
+
+    /// Returns the client-side decompression of data from [`deflate`].
+    pub fn inflate(stream: &mut Decompress, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len() * 1024 + 64);
+        stream
+            .decompress_vec(data, &mut out, FlushDecompress::Sync)
+            .unwrap();
+        assert_eq!(stream.total_in(), data.len() as u64);
+        out
+    }
+
+    /// Returns the 32-bit little-endian pixel format with 8 bits per
+    /// component.
+    pub fn rgb888() -> rfb::PixelFormat {
+        rfb::PixelFormat {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian_flag: 0,
+            true_color_flag: 1,
+            red_max: 255.into(),
+            green_max: 255.into(),
+            blue_max: 255.into(),
+            red_shift: 16,
+            green_shift: 8,
+            blue_shift: 0,
+            padding: [0; 3],
+        }
+    }
+
+    #[test]
+    fn test_choose() {
+        assert_eq!(Encoding::choose(&[]), Encoding::Raw);
+        assert_eq!(
+            Encoding::choose(&[
+                rfb::ENCODING_TYPE_CURSOR,
+                rfb::ENCODING_TYPE_HEXTILE,
+                rfb::ENCODING_TYPE_TIGHT,
+                rfb::ENCODING_TYPE_ZRLE,
+                rfb::ENCODING_TYPE_ZLIB,
+            ]),
+            Encoding::Tight
+        );
+        assert_eq!(
+            Encoding::choose(&[rfb::ENCODING_TYPE_ZRLE, rfb::ENCODING_TYPE_TIGHT]),
+            Encoding::Zrle
+        );
+        assert_eq!(jpeg_quality_level(&[rfb::ENCODING_TYPE_TIGHT]), None);
+        assert_eq!(
+            jpeg_quality_level(&[
+                rfb::ENCODING_TYPE_TIGHT,
+                rfb::ENCODING_TYPE_JPEG_QUALITY_LEVEL_0 + 6
+            ]),
+            Some(6)
+        );
+    }
+
+    #[test]
+    fn test_deflate() {
+        let mut compress = Compress::new(Compression::fast(), true);
+        let mut decompress = Decompress::new(true);
+        for len in [0, 1, 1000, 100000] {
+            let data = (0..len).map(|i| (i * 7 % 251) as u8).collect::<Vec<_>>();
+            let mut out = Vec::new();
+            deflate(&mut compress, &data, &mut out).unwrap();
+            assert_eq!(inflate(&mut decompress, &out), data);
+        }
+    }
+
+    #[test]
+    fn test_palette() {
+        let mut palette = Palette::default();
+        assert!(palette.build(&[5, 5, 7, 5, 9], 3));
+        assert_eq!(palette.colors(), [5, 7, 9]);
+        assert_eq!(palette.index(9), 2);
+        assert!(!palette.build(&[5, 5, 7, 5, 9], 2));
+    }
+
+    #[test]
+    fn test_write_packed() {
+        let mut out = Vec::new();
+        write_packed([1, 0, 1, 1, 0, 1, 0, 1, 1, 1], 5, 1, &mut out);
+        assert_eq!(out, [0b10110000, 0b10111000]);
+        out.clear();
+        write_packed([3, 2, 1, 0, 1], 5, 2, &mut out);
+        assert_eq!(out, [0b11100100, 0b01000000]);
+    }
+}
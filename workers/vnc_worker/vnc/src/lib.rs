@@ -6,17 +6,32 @@
 #![expect(missing_docs)]
 #![forbid(unsafe_code)]
 
+mod des;
+mod dirty;
+mod encoding;
 mod rfb;
 mod scancode;
-use flate2::Compression;
-use flate2::FlushCompress;
+mod security;
+mod tight;
+mod tls;
+mod zrle;
+
+pub use security::SecurityConfig;
+pub use tls::TlsConfig;
+pub use tls::TlsConfigError;
+
+use futures::AsyncRead;
 use futures::AsyncReadExt;
+use futures::AsyncWrite;
 use futures::AsyncWriteExt;
 use futures::FutureExt;
 use futures::StreamExt;
 use futures::channel::mpsc;
 use futures::future::OptionFuture;
 use pal_async::socket::PolledSocket;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
 use thiserror::Error;
 use zerocopy::FromZeros;
 use zerocopy::IntoBytes;
@@ -35,10 +50,20 @@ pub enum Error {
     UnsupportedPixelFormat(u8),
     #[error("unsupported security type: {0}")]
     UnsupportedSecurityType(u8),
+    #[error("unsupported VeNCrypt version {0}.{1}")]
+    UnsupportedVeNCryptVersion(u8, u8),
+    #[error("unsupported VeNCrypt subtype: {0}")]
+    UnsupportedVeNCryptSubtype(u32),
+    #[error("client authentication failed")]
+    AuthenticationFailed,
+    #[error("TLS handshake failed")]
+    Tls(#[source] std::io::Error),
     #[error("resolution changed but client does not support DesktopSize")]
     ResizeUnsupported,
     #[error("zlib compression failed")]
     ZlibCompression,
+    #[error("JPEG compression failed")]
+    JpegCompression(#[source] image::ImageError),
     #[error("socket error")]
     Io(#[from] std::io::Error),
 }
@@ -47,13 +72,107 @@ pub enum Error {
 pub trait Framebuffer: Send + Sync {
     fn resolution(&mut self) -> (u16, u16);
     fn read_line(&mut self, line: u16, data: &mut [u8]);
+
+    /// Appends the regions that have been updated since the last call to
+    /// `rects`, if known.
+    ///
+    /// Returns `false` if updated regions are not tracked, in which case the
+    /// server finds them by comparing the framebuffer contents. Even if this
+    /// returns `true`, the server occasionally compares the whole framebuffer
+    /// in case some updates were not reported.
+    fn dirty(&mut self, rects: &mut Vec<Rect>) -> bool {
+        let _ = rects;
+        false
+    }
+}
+
+/// A rectangular region of the framebuffer, in pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
 }
 
 pub const HID_MOUSE_MAX_ABS_VALUE: u32 = 0x7FFFu32;
 
+/// A client connection, possibly upgraded to TLS.
+enum Connection {
+    Plain(PolledSocket<socket2::Socket>),
+    #[cfg(all(feature = "tls", target_os = "linux"))]
+    Tls(Box<tls::TlsStream<PolledSocket<socket2::Socket>>>),
+    /// The connection is being upgraded, or the upgrade failed.
+    Invalid,
+}
+
+impl Connection {
+    fn get(self: Pin<&mut Self>) -> Pin<&mut dyn AsyncReadWrite> {
+        match self.get_mut() {
+            Connection::Plain(socket) => Pin::new(socket),
+            #[cfg(all(feature = "tls", target_os = "linux"))]
+            Connection::Tls(stream) => Pin::new(&mut **stream),
+            Connection::Invalid => unreachable!(),
+        }
+    }
+}
+
+/// A client connection that has completed the security handshake.
+pub struct Client(Connection);
+
+impl Client {
+    /// Negotiates the protocol version and security type with a newly
+    /// connected client, authenticating it as required by `security`.
+    ///
+    /// This allows a client to be authenticated before committing any server
+    /// resources to it. Pass the result to [`Server::with_client`].
+    pub async fn handshake(
+        socket: PolledSocket<socket2::Socket>,
+        security: &SecurityConfig,
+    ) -> Result<Self, Error> {
+        let mut socket = Connection::Plain(socket);
+        security::handshake(&mut socket, security).await?;
+        Ok(Self(socket))
+    }
+}
+
+trait AsyncReadWrite: AsyncRead + AsyncWrite {}
+impl<T: AsyncRead + AsyncWrite> AsyncReadWrite for T {}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.get().poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.get().poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.get().poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.get().poll_close(cx)
+    }
+}
+
 /// A VNC server handling a single connection.
 pub struct Server<F, I> {
-    socket: PolledSocket<socket2::Socket>,
+    socket: Connection,
+    /// The security requirements, or `None` if the handshake has already
+    /// completed.
+    security: Option<SecurityConfig>,
     fb: F,
     input: I,
     update_recv: mpsc::Receiver<()>,
@@ -66,9 +185,9 @@ pub struct Server<F, I> {
     clipboard: String,
 
     supports_desktop_resize: bool,
-    supports_zlib: bool,
     supports_cursor: bool,
-    prev_fb: Vec<u32>,
+    encoding: encoding::Encoding,
+    jpeg_quality_level: Option<u8>,
 }
 
 #[derive(Debug, Clone)]
@@ -126,11 +245,29 @@ pub fn new(
         socket: PolledSocket<socket2::Socket>,
         fb: F,
         input: I,
+        security: SecurityConfig,
+    ) -> Server<F, I> {
+        Self::new_inner(name, Connection::Plain(socket), Some(security), fb, input)
+    }
+
+    /// Creates a server for a client that has already completed the security
+    /// handshake.
+    pub fn with_client(name: String, client: Client, fb: F, input: I) -> Server<F, I> {
+        Self::new_inner(name, client.0, None, fb, input)
+    }
+
+    fn new_inner(
+        name: String,
+        socket: Connection,
+        security: Option<SecurityConfig>,
+        fb: F,
+        input: I,
     ) -> Server<F, I> {
         #[expect(clippy::disallowed_methods)] // TODO
         let (update_send, update_recv) = mpsc::channel(1);
         Self {
             socket,
+            security,
             fb,
             input,
             update_recv,
@@ -141,9 +278,9 @@ pub fn new(
             alt_left_pressed: false,
             clipboard: String::new(),
             supports_desktop_resize: false,
-            supports_zlib: false,
             supports_cursor: false,
-            prev_fb: Vec::new(),
+            encoding: encoding::Encoding::Raw,
+            jpeg_quality_level: None,
         }
     }
 
@@ -165,67 +302,11 @@ pub async fn run(&mut self) -> Result<(), Error> {
     }
 
     async fn run_internal(&mut self) -> Result<(), Error> {
-        let socket = &mut self.socket;
-        socket
-            .write_all(rfb::ProtocolVersion(rfb::PROTOCOL_VERSION_38).as_bytes())
-            .await?;
-
-        let mut version = rfb::ProtocolVersion::new_zeroed();
-        socket.read_exact(version.as_mut_bytes()).await?;
-
-        match version.0 {
-            rfb::PROTOCOL_VERSION_33 => {
-                // RFB 3.3: server dictates security type as a u32.
-                socket
-                    .write_all(
-                        rfb::Security33 {
-                            padding: [0; 3],
-                            security_type: rfb::SECURITY_TYPE_NONE,
-                        }
-                        .as_bytes(),
-                    )
-                    .await?;
-            }
-            rfb::PROTOCOL_VERSION_37 | rfb::PROTOCOL_VERSION_38 => {
-                // RFB 3.7/3.8: server sends a list of supported security types.
-                socket
-                    .write_all(rfb::Security37 { type_count: 1 }.as_bytes())
-                    .await?;
-                socket.write_all(&[rfb::SECURITY_TYPE_NONE]).await?;
-
-                // Client responds with chosen security type.
-                let mut chosen_type = 0u8;
-                socket.read_exact(chosen_type.as_mut_bytes()).await?;
-
-                if chosen_type != rfb::SECURITY_TYPE_NONE {
-                    if version.0 == rfb::PROTOCOL_VERSION_38 {
-                        socket
-                            .write_all(
-                                rfb::SecurityResult {
-                                    status: rfb::SECURITY_RESULT_STATUS_FAILED.into(),
-                                }
-                                .as_bytes(),
-                            )
-                            .await?;
-                    }
-                    return Err(Error::UnsupportedSecurityType(chosen_type));
-                }
-
-                if version.0 == rfb::PROTOCOL_VERSION_38 {
-                    // RFB 3.8: server sends SecurityResult after negotiation.
-                    socket
-                        .write_all(
-                            rfb::SecurityResult {
-                                status: rfb::SECURITY_RESULT_STATUS_OK.into(),
-                            }
-                            .as_bytes(),
-                        )
-                        .await?;
-                }
-            }
-            _ => return Err(Error::UnsupportedVersion(version)),
+        if let Some(security) = self.security.take() {
+            security::handshake(&mut self.socket, &security).await?;
         }
 
+        let socket = &mut self.socket;
         let mut init = rfb::ClientInit::new_zeroed();
         socket.read_exact(init.as_mut_bytes()).await?;
 
@@ -261,11 +342,10 @@ async fn run_internal(&mut self) -> Result<(), Error> {
         let mut ready_for_update = false;
         let mut force_full_update = true;
         let mut send_cursor = false;
-        let mut cur_fb: Vec<u32> = Vec::new();
-        let mut tile_buf: Vec<u8> = Vec::new();
-        let mut dirty_rects: Vec<(u16, u16, u16, u16)> = Vec::new();
-        let mut zlib_buf: Vec<u8> = Vec::new();
-        let mut zlib_stream = flate2::Compress::new(Compression::fast(), true);
+        let mut tracker = dirty::DirtyTracker::new();
+        let mut encoder = encoding::Encoder::new();
+        let mut dirty_rects: Vec<Rect> = Vec::new();
+        let mut update_buf: Vec<u8> = Vec::new();
         let mut scancode_state = scancode::State::new();
         loop {
             let mut socket_ready = false;
@@ -320,47 +400,15 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                         .await?;
                 }
 
-                // Read current framebuffer.
-                let fb_size = width as usize * height as usize;
-                cur_fb.resize(fb_size, 0);
-                for y in 0..height {
-                    let offset = y as usize * width as usize;
-                    self.fb
-                        .read_line(y, cur_fb[offset..offset + width as usize].as_mut_bytes());
-                }
-
-                let full_update = force_full_update || self.prev_fb.len() != fb_size;
-
-                // Find dirty tiles.
+                // Find the tiles that changed since the last update.
                 dirty_rects.clear();
-                let mut ty: u16 = 0;
-                while ty < height {
-                    let tile_h = TILE_SIZE.min(height - ty);
-                    let mut tx: u16 = 0;
-                    while tx < width {
-                        let tile_w = TILE_SIZE.min(width - tx);
-                        let dirty = if full_update {
-                            true
-                        } else {
-                            let mut d = false;
-                            for y in ty..ty + tile_h {
-                                let start = y as usize * width as usize + tx as usize;
-                                if cur_fb[start..start + tile_w as usize]
-                                    != self.prev_fb[start..start + tile_w as usize]
-                                {
-                                    d = true;
-                                    break;
-                                }
-                            }
-                            d
-                        };
-                        if dirty {
-                            dirty_rects.push((tx, ty, tile_w, tile_h));
-                        }
-                        tx += TILE_SIZE;
-                    }
-                    ty += TILE_SIZE;
-                }
+                tracker.update(
+                    &mut self.fb,
+                    width,
+                    height,
+                    force_full_update,
+                    &mut dirty_rects,
+                );
 
                 if !dirty_rects.is_empty() || send_cursor {
                     if !dirty_rects.is_empty() {
@@ -468,92 +516,22 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                         socket.write_all(&mask_flat).await?;
                     }
 
-                    let use_zlib = self.supports_zlib;
-
-                    for &(tx, ty, tw, th) in &dirty_rects {
-                        // Convert tile pixels.
-                        tile_buf.clear();
-                        for y in ty..ty + th {
-                            let start = y as usize * width as usize + tx as usize;
-                            convert_pixels(
-                                &cur_fb[start..start + tw as usize],
-                                &fmt,
-                                &mut tile_buf,
-                            );
-                        }
-
-                        if use_zlib {
-                            // Compress tile data with zlib. The RFB spec
-                            // requires a single continuous zlib stream per
-                            // connection — use Sync flush to emit all pending
-                            // output while preserving the dictionary.
-                            zlib_buf.clear();
-                            // Reserve enough space: worst case zlib output is
-                            // slightly larger than input + overhead.
-                            zlib_buf.resize(tile_buf.len() + 64, 0);
-                            let before_in = zlib_stream.total_in();
-                            let before_out = zlib_stream.total_out();
-                            loop {
-                                let status = zlib_stream
-                                    .compress(
-                                        &tile_buf[(zlib_stream.total_in() - before_in) as usize..],
-                                        &mut zlib_buf
-                                            [(zlib_stream.total_out() - before_out) as usize..],
-                                        FlushCompress::Sync,
-                                    )
-                                    .map_err(|_| Error::ZlibCompression)?;
-                                // Grow output buffer if needed.
-                                let out_used = (zlib_stream.total_out() - before_out) as usize;
-                                if out_used >= zlib_buf.len() - 16 {
-                                    zlib_buf.resize(zlib_buf.len() * 2, 0);
-                                }
-                                let in_done =
-                                    (zlib_stream.total_in() - before_in) as usize >= tile_buf.len();
-                                if in_done && status == flate2::Status::Ok {
-                                    break;
-                                }
-                            }
-                            let compressed_len = (zlib_stream.total_out() - before_out) as usize;
-                            zlib_buf.truncate(compressed_len);
-
-                            socket
-                                .write_all(
-                                    rfb::Rectangle {
-                                        x: tx.into(),
-                                        y: ty.into(),
-                                        width: tw.into(),
-                                        height: th.into(),
-                                        encoding_type: rfb::ENCODING_TYPE_ZLIB.into(),
-                                    }
-                                    .as_bytes(),
-                                )
-                                .await?;
-                            // Zlib encoding: 4-byte length prefix + compressed data.
-                            socket
-                                .write_all(&(zlib_buf.len() as u32).to_be_bytes())
-                                .await?;
-                            socket.write_all(&zlib_buf).await?;
-                        } else {
-                            socket
-                                .write_all(
-                                    rfb::Rectangle {
-                                        x: tx.into(),
-                                        y: ty.into(),
-                                        width: tw.into(),
-                                        height: th.into(),
-                                        encoding_type: rfb::ENCODING_TYPE_RAW.into(),
-                                    }
-                                    .as_bytes(),
-                                )
-                                .await?;
-                            socket.write_all(&tile_buf).await?;
-                        }
+                    update_buf.clear();
+                    for &rect in &dirty_rects {
+                        encoder.encode(
+                            self.encoding,
+                            self.jpeg_quality_level,
+                            &fmt,
+                            tracker.pixels(),
+                            width.into(),
+                            rect,
+                            &mut update_buf,
+                        )?;
                     }
+                    socket.write_all(&update_buf).await?;
                 }
                 // else: nothing dirty, keep ready_for_update = true
                 // so we check again on the next timer tick.
-
-                std::mem::swap(&mut self.prev_fb, &mut cur_fb);
             }
 
             if socket_ready {
@@ -588,7 +566,9 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                         socket.read_exact(encodings.as_mut_bytes()).await?;
                         self.supports_desktop_resize =
                             encodings.contains(&rfb::ENCODING_TYPE_DESKTOP_SIZE.into());
-                        self.supports_zlib = encodings.contains(&rfb::ENCODING_TYPE_ZLIB.into());
+                        let encoding_types = encodings.iter().map(|e| e.get()).collect::<Vec<_>>();
+                        self.encoding = encoding::Encoding::choose(&encoding_types);
+                        self.jpeg_quality_level = encoding::jpeg_quality_level(&encoding_types);
                         let had_cursor = self.supports_cursor;
                         self.supports_cursor =
                             encodings.contains(&rfb::ENCODING_TYPE_CURSOR.into());
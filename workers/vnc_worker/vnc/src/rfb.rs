@@ -55,6 +55,27 @@ pub struct SecurityResult {
 pub const SECURITY_RESULT_STATUS_FAILED: u32 = 1;
 pub const SECURITY_RESULT_STATUS_FAILED_TOO_MANY_ATTEMPTS: u32 = 2;
 
+// As defined in https://github.com/rfbproto/rfbproto/blob/master/rfbproto.rst#vencrypt
+
+pub const VENCRYPT_VERSION: [u8; 2] = [0, 2];
+
+pub const VENCRYPT_SUBTYPE_PLAIN: u32 = 256;
+pub const VENCRYPT_SUBTYPE_TLS_NONE: u32 = 257;
+pub const VENCRYPT_SUBTYPE_TLS_VNC: u32 = 258;
+pub const VENCRYPT_SUBTYPE_TLS_PLAIN: u32 = 259;
+pub const VENCRYPT_SUBTYPE_X509_NONE: u32 = 260;
+pub const VENCRYPT_SUBTYPE_X509_VNC: u32 = 261;
+pub const VENCRYPT_SUBTYPE_X509_PLAIN: u32 = 262;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct VeNCryptPlain {
+    pub username_length: u32_be,
+    pub password_length: u32_be,
+    // username: [u8; N],
+    // password: [u8; N],
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
 pub struct ClientInit {
@@ -128,6 +149,28 @@ pub struct SetEncodings {
 pub const ENCODING_TYPE_CURSOR: u32 = -239i32 as u32;
 pub const ENCODING_TYPE_DESKTOP_SIZE: u32 = -223i32 as u32;
 pub const ENCODING_TYPE_QEMU_EXTENDED_KEY_EVENT: u32 = -258i32 as u32;
+pub const ENCODING_TYPE_JPEG_QUALITY_LEVEL_0: u32 = -32i32 as u32;
+pub const ENCODING_TYPE_JPEG_QUALITY_LEVEL_9: u32 = -23i32 as u32;
+
+// ZRLE tile subencodings.
+pub const ZRLE_TILE_SIZE: u16 = 64;
+pub const ZRLE_RAW: u8 = 0;
+pub const ZRLE_SOLID: u8 = 1;
+pub const ZRLE_PACKED_PALETTE_MAX: usize = 16;
+pub const ZRLE_PLAIN_RLE: u8 = 128;
+/// Palette RLE subencodings are this plus the palette size.
+pub const ZRLE_PALETTE_RLE_BASE: u8 = 128;
+pub const ZRLE_PALETTE_RLE_MAX: usize = 127;
+
+// Tight compression control.
+pub const TIGHT_FILL: u8 = 0x80;
+pub const TIGHT_JPEG: u8 = 0x90;
+pub const TIGHT_EXPLICIT_FILTER: u8 = 0x40;
+pub const TIGHT_STREAM_SHIFT: u8 = 4;
+pub const TIGHT_FILTER_PALETTE: u8 = 1;
+pub const TIGHT_MAX_PALETTE: usize = 256;
+/// Data shorter than this is sent uncompressed.
+pub const TIGHT_MIN_TO_COMPRESS: usize = 12;
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, IntoBytes, Immutable, KnownLayout, FromBytes)]
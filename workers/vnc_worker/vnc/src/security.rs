@@ -0,0 +1,583 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Protocol version and security type negotiation.
+//!
+//! The server offers exactly one way in, chosen by the [`SecurityConfig`]:
+//! VeNCrypt if TLS is configured, else VNC authentication if a password is
+//! configured, else no security at all. Within VeNCrypt, the X.509 subtypes
+//! are offered, with VNC or plain (username and password) authentication
+//! inside the TLS session if a password is configured.
+
+use crate::Connection;
+use crate::Error;
+use crate::des::Des;
+use crate::rfb;
+use crate::tls;
+use crate::tls::TlsConfig;
+use futures::AsyncReadExt;
+use futures::AsyncWriteExt;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+/// The maximum length of a VeNCrypt plain username or password.
+const MAX_PLAIN_LENGTH: u32 = 1024;
+
+/// The security requirements for client connections.
+#[derive(Clone, Default)]
+pub struct SecurityConfig {
+    /// Require clients to authenticate with this password.
+    ///
+    /// Only the first eight bytes are significant to VNC authentication,
+    /// which is also weak enough to brute force from a captured handshake.
+    /// Use TLS to protect the password on untrusted networks.
+    pub password: Option<String>,
+    /// Require clients to use TLS.
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Version {
+    V33,
+    V37,
+    V38,
+}
+
+/// Negotiates the protocol version and security type with the client,
+/// upgrading `socket` to TLS if needed.
+pub(crate) async fn handshake(
+    socket: &mut Connection,
+    config: &SecurityConfig,
+) -> Result<(), Error> {
+    socket
+        .write_all(rfb::ProtocolVersion(rfb::PROTOCOL_VERSION_38).as_bytes())
+        .await?;
+
+    let mut protocol_version = rfb::ProtocolVersion::new_zeroed();
+    socket.read_exact(protocol_version.as_mut_bytes()).await?;
+    let version = match protocol_version.0 {
+        rfb::PROTOCOL_VERSION_33 => Version::V33,
+        rfb::PROTOCOL_VERSION_37 => Version::V37,
+        rfb::PROTOCOL_VERSION_38 => Version::V38,
+        _ => return Err(Error::UnsupportedVersion(protocol_version)),
+    };
+
+    let security_type = if config.tls.is_some() {
+        rfb::SECURITY_TYPE_VENCRYPT
+    } else if config.password.is_some() {
+        rfb::SECURITY_TYPE_VNC_AUTHENTICATION
+    } else {
+        rfb::SECURITY_TYPE_NONE
+    };
+
+    if version == Version::V33 {
+        // RFB 3.3: server dictates security type as a u32. VeNCrypt needs
+        // RFB 3.7 or later.
+        if security_type == rfb::SECURITY_TYPE_VENCRYPT {
+            socket
+                .write_all(
+                    rfb::Security33 {
+                        padding: [0; 3],
+                        security_type: rfb::SECURITY_TYPE_INVALID,
+                    }
+                    .as_bytes(),
+                )
+                .await?;
+            write_reason(socket, "TLS is required").await?;
+            return Err(Error::UnsupportedVersion(protocol_version));
+        }
+        socket
+            .write_all(
+                rfb::Security33 {
+                    padding: [0; 3],
+                    security_type,
+                }
+                .as_bytes(),
+            )
+            .await?;
+    } else {
+        // RFB 3.7/3.8: server sends a list of supported security types.
+        socket
+            .write_all(rfb::Security37 { type_count: 1 }.as_bytes())
+            .await?;
+        socket.write_all(&[security_type]).await?;
+
+        // Client responds with chosen security type.
+        let mut chosen_type = 0u8;
+        socket.read_exact(chosen_type.as_mut_bytes()).await?;
+        if chosen_type != security_type {
+            if version == Version::V38 {
+                fail(socket, version, "unsupported security type").await?;
+            }
+            return Err(Error::UnsupportedSecurityType(chosen_type));
+        }
+    }
+
+    match security_type {
+        rfb::SECURITY_TYPE_NONE => {
+            if version == Version::V38 {
+                // RFB 3.8: server sends SecurityResult after negotiation.
+                succeed(socket).await?;
+            }
+        }
+        rfb::SECURITY_TYPE_VNC_AUTHENTICATION => {
+            vnc_authentication(socket, version, config.password.as_deref().unwrap()).await?;
+        }
+        rfb::SECURITY_TYPE_VENCRYPT => {
+            vencrypt(socket, version, config.tls.as_ref().unwrap(), config).await?;
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+async fn succeed(socket: &mut Connection) -> Result<(), Error> {
+    socket
+        .write_all(
+            rfb::SecurityResult {
+                status: rfb::SECURITY_RESULT_STATUS_OK.into(),
+            }
+            .as_bytes(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Sends a failed SecurityResult, with a reason if the protocol version has
+/// one.
+async fn fail(socket: &mut Connection, version: Version, reason: &str) -> Result<(), Error> {
+    socket
+        .write_all(
+            rfb::SecurityResult {
+                status: rfb::SECURITY_RESULT_STATUS_FAILED.into(),
+            }
+            .as_bytes(),
+        )
+        .await?;
+    if version == Version::V38 {
+        write_reason(socket, reason).await?;
+    }
+    // Make sure the client sees the failure before the connection is closed.
+    socket.flush().await?;
+    Ok(())
+}
+
+async fn write_reason(socket: &mut Connection, reason: &str) -> Result<(), Error> {
+    socket
+        .write_all(&(reason.len() as u32).to_be_bytes())
+        .await?;
+    socket.write_all(reason.as_bytes()).await?;
+    Ok(())
+}
+
+/// Computes the response to a VNC authentication challenge: the challenge
+/// encrypted with DES, keyed by the password with the bits of each byte
+/// reversed.
+pub(crate) fn vnc_authentication_response(password: &str, challenge: &[u8; 16]) -> [u8; 16] {
+    let mut key = [0; 8];
+    for (k, &b) in key.iter_mut().zip(password.as_bytes()) {
+        *k = b.reverse_bits();
+    }
+    let des = Des::new(key);
+    let mut response = [0; 16];
+    for (out, block) in response.chunks_exact_mut(8).zip(challenge.chunks_exact(8)) {
+        out.copy_from_slice(&des.encrypt(block.try_into().unwrap()));
+    }
+    response
+}
+
+async fn vnc_authentication(
+    socket: &mut Connection,
+    version: Version,
+    password: &str,
+) -> Result<(), Error> {
+    let mut challenge = [0; 16];
+    getrandom::fill(&mut challenge).expect("rng failure");
+    socket.write_all(&challenge).await?;
+    let mut response = [0; 16];
+    socket.read_exact(&mut response).await?;
+    if !constant_time_eq(
+        &response,
+        &vnc_authentication_response(password, &challenge),
+    ) {
+        fail(socket, version, "authentication failed").await?;
+        return Err(Error::AuthenticationFailed);
+    }
+    succeed(socket).await
+}
+
+async fn vencrypt(
+    socket: &mut Connection,
+    version: Version,
+    tls: &TlsConfig,
+    config: &SecurityConfig,
+) -> Result<(), Error> {
+    socket.write_all(&rfb::VENCRYPT_VERSION).await?;
+    let mut client_version = [0; 2];
+    socket.read_exact(&mut client_version).await?;
+    if client_version != rfb::VENCRYPT_VERSION {
+        socket.write_all(&[1]).await?;
+        return Err(Error::UnsupportedVeNCryptVersion(
+            client_version[0],
+            client_version[1],
+        ));
+    }
+    socket.write_all(&[0]).await?;
+
+    let subtypes: &[u32] = if config.password.is_some() {
+        &[
+            rfb::VENCRYPT_SUBTYPE_X509_VNC,
+            rfb::VENCRYPT_SUBTYPE_X509_PLAIN,
+        ]
+    } else {
+        &[rfb::VENCRYPT_SUBTYPE_X509_NONE]
+    };
+    socket.write_all(&[subtypes.len() as u8]).await?;
+    for subtype in subtypes {
+        socket.write_all(&subtype.to_be_bytes()).await?;
+    }
+
+    let mut subtype = zerocopy::U32::<zerocopy::BE>::new_zeroed();
+    socket.read_exact(subtype.as_mut_bytes()).await?;
+    let subtype = subtype.get();
+    if !subtypes.contains(&subtype) {
+        socket.write_all(&[0]).await?;
+        return Err(Error::UnsupportedVeNCryptSubtype(subtype));
+    }
+    socket.write_all(&[1]).await?;
+
+    tls::upgrade(socket, tls).await?;
+
+    // The SecurityResult is always sent after VeNCrypt, even for the none
+    // subtype.
+    match subtype {
+        rfb::VENCRYPT_SUBTYPE_X509_NONE => succeed(socket).await,
+        rfb::VENCRYPT_SUBTYPE_X509_VNC => {
+            vnc_authentication(socket, version, config.password.as_deref().unwrap()).await
+        }
+        rfb::VENCRYPT_SUBTYPE_X509_PLAIN => {
+            let mut plain = rfb::VeNCryptPlain::new_zeroed();
+            socket.read_exact(plain.as_mut_bytes()).await?;
+            let username_length = plain.username_length.get();
+            let password_length = plain.password_length.get();
+            if username_length > MAX_PLAIN_LENGTH || password_length > MAX_PLAIN_LENGTH {
+                fail(socket, version, "authentication failed").await?;
+                return Err(Error::AuthenticationFailed);
+            }
+            // Only the password is checked.
+            let mut username_password = vec![0; (username_length + password_length) as usize];
+            socket.read_exact(&mut username_password).await?;
+            let password = &username_password[username_length as usize..];
+            if !constant_time_eq(password, config.password.as_deref().unwrap().as_bytes()) {
+                fail(socket, version, "authentication failed").await?;
+                return Err(Error::AuthenticationFailed);
+            }
+            succeed(socket).await
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Compares `a` and `b` in time that only depends on their lengths, so that
+/// the time taken to reject a client does not reveal how much of the secret
+/// it guessed correctly.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SecurityConfig;
+    use super::constant_time_eq;
+    use super::handshake;
+    use super::vnc_authentication_response;
+    use crate::Connection;
+    use crate::Error;
+    use crate::rfb;
+    use futures::AsyncWriteExt;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use pal_async::socket::PolledSocket;
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::net::TcpStream;
+
+    /// Runs the server handshake against `client`, which runs on a separate
+    /// thread with a blocking socket.
+    async fn run<T: 'static + Send>(
+        driver: &DefaultDriver,
+        config: &SecurityConfig,
+        client: impl 'static + Send + FnOnce(TcpStream) -> T,
+    ) -> (Result<(), Error>, T) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let thread = std::thread::spawn(move || client(TcpStream::connect(addr).unwrap()));
+        let (socket, _) = listener.accept().unwrap();
+        let mut socket = Connection::Plain(PolledSocket::new(driver, socket.into()).unwrap());
+        let result = handshake(&mut socket, config).await;
+        if result.is_ok() {
+            socket.flush().await.unwrap();
+        }
+        drop(socket);
+        (result, thread.join().unwrap())
+    }
+
+    fn read_array<const N: usize>(stream: &mut impl Read) -> [u8; N] {
+        let mut buf = [0; N];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    fn read_u32(stream: &mut impl Read) -> u32 {
+        u32::from_be_bytes(read_array(stream))
+    }
+
+    /// Negotiates RFB 3.8 and picks `security_type`, which must be the only
+    /// one offered.
+    fn client_start(stream: &mut TcpStream, security_type: u8) {
+        assert_eq!(read_array(stream), rfb::PROTOCOL_VERSION_38);
+        stream.write_all(&rfb::PROTOCOL_VERSION_38).unwrap();
+        assert_eq!(read_array(stream), [1, security_type]);
+        stream.write_all(&[security_type]).unwrap();
+    }
+
+    /// Answers a VNC authentication challenge, returning the security result.
+    fn client_vnc_authentication(stream: &mut impl ReadWrite, password: &str) -> u32 {
+        let challenge = read_array(stream);
+        stream
+            .write_all(&vnc_authentication_response(password, &challenge))
+            .unwrap();
+        read_u32(stream)
+    }
+
+    trait ReadWrite: Read + Write {}
+    impl<T: Read + Write> ReadWrite for T {}
+
+    #[async_test]
+    async fn test_none(driver: DefaultDriver) {
+        let (result, status) = run(&driver, &SecurityConfig::default(), |mut stream| {
+            client_start(&mut stream, rfb::SECURITY_TYPE_NONE);
+            read_u32(&mut stream)
+        })
+        .await;
+        result.unwrap();
+        assert_eq!(status, rfb::SECURITY_RESULT_STATUS_OK);
+    }
+
+    #[async_test]
+    async fn test_vnc_authentication(driver: DefaultDriver) {
+        let config = SecurityConfig {
+            password: Some("secret".into()),
+            tls: None,
+        };
+        for (password, expected) in [
+            ("secret", rfb::SECURITY_RESULT_STATUS_OK),
+            ("wrong", rfb::SECURITY_RESULT_STATUS_FAILED),
+        ] {
+            let (result, status) = run(&driver, &config, move |mut stream| {
+                client_start(&mut stream, rfb::SECURITY_TYPE_VNC_AUTHENTICATION);
+                client_vnc_authentication(&mut stream, password)
+            })
+            .await;
+            assert_eq!(status, expected);
+            if expected == rfb::SECURITY_RESULT_STATUS_OK {
+                result.unwrap();
+            } else {
+                assert!(matches!(result, Err(Error::AuthenticationFailed)));
+            }
+        }
+    }
+
+    #[async_test]
+    async fn test_wrong_security_type(driver: DefaultDriver) {
+        let config = SecurityConfig {
+            password: Some("secret".into()),
+            tls: None,
+        };
+        let (result, status) = run(&driver, &config, |mut stream| {
+            assert_eq!(read_array(&mut stream), rfb::PROTOCOL_VERSION_38);
+            stream.write_all(&rfb::PROTOCOL_VERSION_38).unwrap();
+            assert_eq!(
+                read_array(&mut stream),
+                [1, rfb::SECURITY_TYPE_VNC_AUTHENTICATION]
+            );
+            stream.write_all(&[rfb::SECURITY_TYPE_NONE]).unwrap();
+            read_u32(&mut stream)
+        })
+        .await;
+        assert!(matches!(
+            result,
+            Err(Error::UnsupportedSecurityType(rfb::SECURITY_TYPE_NONE))
+        ));
+        assert_eq!(status, rfb::SECURITY_RESULT_STATUS_FAILED);
+    }
+
+    #[cfg(all(feature = "tls", target_os = "linux"))]
+    mod tls {
+        use super::ReadWrite;
+        use super::client_start;
+        use super::client_vnc_authentication;
+        use super::read_array;
+        use super::read_u32;
+        use super::run;
+        use crate::SecurityConfig;
+        use crate::TlsConfig;
+        use crate::rfb;
+        use openssl::ssl::SslConnector;
+        use openssl::ssl::SslMethod;
+        use openssl::ssl::SslStream;
+        use openssl::ssl::SslVerifyMode;
+        use pal_async::DefaultDriver;
+        use pal_async::async_test;
+        use std::io::Write;
+        use std::net::TcpStream;
+
+        /// Returns a PEM-encoded self-signed certificate and its private key.
+        fn self_signed() -> (Vec<u8>, Vec<u8>) {
+            use openssl::asn1::Asn1Time;
+            use openssl::ec::EcGroup;
+            use openssl::ec::EcKey;
+            use openssl::hash::MessageDigest;
+            use openssl::nid::Nid;
+            use openssl::pkey::PKey;
+            use openssl::x509::X509;
+            use openssl::x509::X509NameBuilder;
+
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+            let mut name = X509NameBuilder::new().unwrap();
+            name.append_entry_by_text("CN", "localhost").unwrap();
+            let name = name.build();
+            let mut cert = X509::builder().unwrap();
+            cert.set_version(2).unwrap();
+            cert.set_subject_name(&name).unwrap();
+            cert.set_issuer_name(&name).unwrap();
+            cert.set_pubkey(&key).unwrap();
+            cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+                .unwrap();
+            cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+                .unwrap();
+            cert.sign(&key, MessageDigest::sha256()).unwrap();
+            (
+                cert.build().to_pem().unwrap(),
+                key.private_key_to_pem_pkcs8().unwrap(),
+            )
+        }
+
+        fn config(password: Option<&str>) -> SecurityConfig {
+            let (cert, key) = self_signed();
+            SecurityConfig {
+                password: password.map(Into::into),
+                tls: Some(TlsConfig::new(&cert, &key).unwrap()),
+            }
+        }
+
+        /// Negotiates VeNCrypt, checks the offered subtypes, picks `subtype`,
+        /// and completes the TLS handshake.
+        fn client_vencrypt(
+            mut stream: TcpStream,
+            offered: &[u32],
+            subtype: u32,
+        ) -> SslStream<TcpStream> {
+            client_start(&mut stream, rfb::SECURITY_TYPE_VENCRYPT);
+            assert_eq!(read_array(&mut stream), rfb::VENCRYPT_VERSION);
+            stream.write_all(&rfb::VENCRYPT_VERSION).unwrap();
+            assert_eq!(read_array(&mut stream), [0]);
+            let [count] = read_array(&mut stream);
+            let subtypes = (0..count)
+                .map(|_| read_u32(&mut stream))
+                .collect::<Vec<_>>();
+            assert_eq!(subtypes, offered);
+            stream.write_all(&subtype.to_be_bytes()).unwrap();
+            assert_eq!(read_array(&mut stream), [1]);
+
+            // The test certificate is self-signed.
+            let mut connector = SslConnector::builder(SslMethod::tls_client()).unwrap();
+            connector.set_verify(SslVerifyMode::NONE);
+            connector.build().connect("localhost", stream).unwrap()
+        }
+
+        fn client_plain(stream: &mut impl ReadWrite, username: &str, password: &str) -> u32 {
+            stream
+                .write_all(&(username.len() as u32).to_be_bytes())
+                .unwrap();
+            stream
+                .write_all(&(password.len() as u32).to_be_bytes())
+                .unwrap();
+            stream.write_all(username.as_bytes()).unwrap();
+            stream.write_all(password.as_bytes()).unwrap();
+            read_u32(stream)
+        }
+
+        #[async_test]
+        async fn test_x509_none(driver: DefaultDriver) {
+            let (result, status) = run(&driver, &config(None), |stream| {
+                let mut stream = client_vencrypt(
+                    stream,
+                    &[rfb::VENCRYPT_SUBTYPE_X509_NONE],
+                    rfb::VENCRYPT_SUBTYPE_X509_NONE,
+                );
+                read_u32(&mut stream)
+            })
+            .await;
+            result.unwrap();
+            assert_eq!(status, rfb::SECURITY_RESULT_STATUS_OK);
+        }
+
+        #[async_test]
+        async fn test_x509_vnc(driver: DefaultDriver) {
+            let config = config(Some("secret"));
+            let (result, status) = run(&driver, &config, |stream| {
+                let mut stream = client_vencrypt(
+                    stream,
+                    &[
+                        rfb::VENCRYPT_SUBTYPE_X509_VNC,
+                        rfb::VENCRYPT_SUBTYPE_X509_PLAIN,
+                    ],
+                    rfb::VENCRYPT_SUBTYPE_X509_VNC,
+                );
+                client_vnc_authentication(&mut stream, "secret")
+            })
+            .await;
+            result.unwrap();
+            assert_eq!(status, rfb::SECURITY_RESULT_STATUS_OK);
+        }
+
+        #[async_test]
+        async fn test_x509_plain(driver: DefaultDriver) {
+            let config = config(Some("a longer secret"));
+            for (password, expected) in [
+                ("a longer secret", rfb::SECURITY_RESULT_STATUS_OK),
+                ("a longer secreT", rfb::SECURITY_RESULT_STATUS_FAILED),
+            ] {
+                let (result, status) = run(&driver, &config, move |stream| {
+                    let mut stream = client_vencrypt(
+                        stream,
+                        &[
+                            rfb::VENCRYPT_SUBTYPE_X509_VNC,
+                            rfb::VENCRYPT_SUBTYPE_X509_PLAIN,
+                        ],
+                        rfb::VENCRYPT_SUBTYPE_X509_PLAIN,
+                    );
+                    client_plain(&mut stream, "user", password)
+                })
+                .await;
+                assert_eq!(status, expected);
+                assert_eq!(result.is_ok(), expected == rfb::SECURITY_RESULT_STATUS_OK);
+            }
+        }
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"password", b"password"));
+        assert!(!constant_time_eq(b"password", b"passwore"));
+        assert!(!constant_time_eq(b"password", b"passwor"));
+    }
+}
@@ -0,0 +1,341 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The Tight encoding.
+//!
+//! Each rectangle is sent as a solid fill, as a JPEG image if the client asked
+//! for one with a quality level pseudo-encoding, or as palette indexes or
+//! pixels compressed with one of four zlib streams.
+//!
+//! Tight limits the size of rectangles, which is not a problem for the tiles
+//! that the server sends.
+
+use crate::Error;
+use crate::convert_pixels;
+use crate::encoding::Palette;
+use crate::encoding::deflate;
+use crate::encoding::write_packed;
+use crate::rfb;
+use flate2::Compress;
+use flate2::Compression;
+
+/// The JPEG quality for each quality level, matching other servers.
+const JPEG_QUALITY: [u8; 10] = [15, 29, 41, 42, 62, 77, 79, 86, 92, 100];
+
+/// Rectangles with fewer colors than this are sent with a palette instead of
+/// as a JPEG image, since they are likely text or UI elements that JPEG would
+/// blur.
+const JPEG_MIN_COLORS: usize = 64;
+
+// The zlib stream used for each kind of data.
+const STREAM_COPY: u8 = 0;
+const STREAM_MONO: u8 = 1;
+const STREAM_PALETTE: u8 = 2;
+
+/// The per-connection Tight state.
+pub(crate) struct TightEncoder {
+    streams: [Compress; 4],
+    buf: Vec<u8>,
+    zbuf: Vec<u8>,
+    rgb: Vec<u8>,
+}
+
+impl TightEncoder {
+    pub fn new() -> Self {
+        Self {
+            streams: std::array::from_fn(|_| Compress::new(Compression::fast(), true)),
+            buf: Vec::new(),
+            zbuf: Vec::new(),
+            rgb: Vec::new(),
+        }
+    }
+
+    /// Appends the Tight encoding of `pixels`, a `width` by `height`
+    /// rectangle, to `out`.
+    pub fn encode(
+        &mut self,
+        pixels: &[u32],
+        width: usize,
+        height: usize,
+        fmt: &rfb::PixelFormat,
+        jpeg_quality_level: Option<u8>,
+        palette: &mut Palette,
+        out: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        let has_palette = palette.build(pixels, rfb::TIGHT_MAX_PALETTE);
+        if has_palette && palette.len() == 1 {
+            out.push(rfb::TIGHT_FILL);
+            write_tpixels(&pixels[..1], fmt, out);
+            return Ok(());
+        }
+
+        let jpeg = jpeg_quality_level.filter(|_| {
+            fmt.true_color_flag != 0
+                && fmt.bits_per_pixel >= 16
+                && (!has_palette || palette.len() > JPEG_MIN_COLORS)
+        });
+        if let Some(level) = jpeg {
+            return self.encode_jpeg(pixels, width, height, level, out);
+        }
+
+        self.buf.clear();
+        let stream = if has_palette {
+            let stream = if palette.len() == 2 {
+                write_packed(
+                    pixels.iter().map(|&p| palette.index(p)),
+                    width,
+                    1,
+                    &mut self.buf,
+                );
+                STREAM_MONO
+            } else {
+                self.buf.extend(pixels.iter().map(|&p| palette.index(p)));
+                STREAM_PALETTE
+            };
+            out.push(stream << rfb::TIGHT_STREAM_SHIFT | rfb::TIGHT_EXPLICIT_FILTER);
+            out.push(rfb::TIGHT_FILTER_PALETTE);
+            out.push((palette.len() - 1) as u8);
+            write_tpixels(palette.colors(), fmt, out);
+            stream
+        } else {
+            out.push(STREAM_COPY << rfb::TIGHT_STREAM_SHIFT);
+            write_tpixels(pixels, fmt, &mut self.buf);
+            STREAM_COPY
+        };
+
+        // Small data is sent uncompressed.
+        if self.buf.len() < rfb::TIGHT_MIN_TO_COMPRESS {
+            out.extend_from_slice(&self.buf);
+        } else {
+            self.zbuf.clear();
+            deflate(
+                &mut self.streams[stream as usize],
+                &self.buf,
+                &mut self.zbuf,
+            )?;
+            write_compact_len(self.zbuf.len(), out);
+            out.extend_from_slice(&self.zbuf);
+        }
+        Ok(())
+    }
+
+    fn encode_jpeg(
+        &mut self,
+        pixels: &[u32],
+        width: usize,
+        height: usize,
+        level: u8,
+        out: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        use image::codecs::jpeg::JpegEncoder;
+
+        self.rgb.clear();
+        self.rgb.extend(
+            pixels
+                .iter()
+                .flat_map(|&p| [(p >> 16) as u8, (p >> 8) as u8, p as u8]),
+        );
+        self.buf.clear();
+        JpegEncoder::new_with_quality(&mut self.buf, JPEG_QUALITY[level as usize])
+            .encode(
+                &self.rgb,
+                width as u32,
+                height as u32,
+                image::ExtendedColorType::Rgb8,
+            )
+            .map_err(Error::JpegCompression)?;
+        out.push(rfb::TIGHT_JPEG);
+        write_compact_len(self.buf.len(), out);
+        out.extend_from_slice(&self.buf);
+        Ok(())
+    }
+}
+
+/// Appends `len` in one to three bytes, least significant first, with seven
+/// bits in each of the first two bytes and the high bit set if another byte
+/// follows.
+fn write_compact_len(len: usize, out: &mut Vec<u8>) {
+    assert!(len < 1 << 22);
+    if len < 0x80 {
+        out.push(len as u8);
+    } else if len < 0x4000 {
+        out.extend_from_slice(&[len as u8 | 0x80, (len >> 7) as u8]);
+    } else {
+        out.extend_from_slice(&[len as u8 | 0x80, (len >> 7) as u8 | 0x80, (len >> 14) as u8]);
+    }
+}
+
+/// Appends `pixels` as TPIXELs, which are three bytes of red, green, and blue
+/// for 24-bit true color formats, or pixels in the client's format otherwise.
+fn write_tpixels(pixels: &[u32], fmt: &rfb::PixelFormat, out: &mut Vec<u8>) {
+    let rgb = fmt.bits_per_pixel == 32
+        && fmt.depth == 24
+        && fmt.true_color_flag != 0
+        && fmt.red_max.get() == 255
+        && fmt.green_max.get() == 255
+        && fmt.blue_max.get() == 255;
+    if rgb {
+        out.extend(
+            pixels
+                .iter()
+                .flat_map(|&p| [(p >> 16) as u8, (p >> 8) as u8, p as u8]),
+        );
+    } else {
+        convert_pixels(pixels, fmt, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TightEncoder;
+    use super::write_compact_len;
+    use crate::encoding::Palette;
+    use crate::encoding::tests::inflate;
+    use crate::encoding::tests::rgb888;
+    use crate::rfb;
+    use flate2::Decompress;
+
+    /// Decodes a compact length, returning it and the remaining data.
+    fn read_compact_len(data: &[u8]) -> (usize, &[u8]) {
+        let mut len = 0;
+        for (i, &b) in data.iter().enumerate().take(3) {
+            if i == 2 {
+                return ((b as usize) << 14 | len, &data[3..]);
+            }
+            len |= ((b & 0x7f) as usize) << (7 * i);
+            if b & 0x80 == 0 {
+                return (len, &data[i + 1..]);
+            }
+        }
+        unreachable!()
+    }
+
+    fn rgb(p: u32) -> [u8; 3] {
+        [(p >> 16) as u8, (p >> 8) as u8, p as u8]
+    }
+
+    #[test]
+    fn test_compact_len() {
+        for len in [0, 1, 127, 128, 16383, 16384, (1 << 22) - 1] {
+            let mut out = Vec::new();
+            write_compact_len(len, &mut out);
+            assert_eq!(read_compact_len(&out), (len, &[][..]));
+        }
+    }
+
+    #[test]
+    fn test_tight() {
+        let mut encoder = TightEncoder::new();
+        let mut palette = Palette::default();
+        let mut streams: [Decompress; 4] = std::array::from_fn(|_| Decompress::new(true));
+        let mut encode = |pixels: &[u32], width, jpeg| {
+            let mut out = Vec::new();
+            encoder
+                .encode(
+                    pixels,
+                    width,
+                    pixels.len() / width,
+                    &rgb888(),
+                    jpeg,
+                    &mut palette,
+                    &mut out,
+                )
+                .unwrap();
+            out
+        };
+
+        // Fill.
+        assert_eq!(
+            encode(&[0x123456; 64 * 64], 64, None),
+            [rfb::TIGHT_FILL, 0x12, 0x34, 0x56]
+        );
+
+        // Two colors, one bit per pixel, on stream 1.
+        let mono = (0..20 * 16)
+            .map(|i| [0xff0000, 0xff][i % 3 / 2])
+            .collect::<Vec<_>>();
+        let out = encode(&mono, 20, None);
+        assert_eq!(
+            out[..9],
+            [
+                1 << rfb::TIGHT_STREAM_SHIFT | rfb::TIGHT_EXPLICIT_FILTER,
+                rfb::TIGHT_FILTER_PALETTE,
+                1,
+                0xff,
+                0,
+                0,
+                0,
+                0,
+                0xff
+            ]
+        );
+        let (len, data) = read_compact_len(&out[9..]);
+        assert_eq!(len, data.len());
+        let bits = inflate(&mut streams[1], data);
+        assert_eq!(bits.len(), 3 * 16);
+        for (i, &p) in mono.iter().enumerate() {
+            let (x, y) = (i % 20, i / 20);
+            let bit = bits[y * 3 + x / 8] >> (7 - x % 8) & 1;
+            assert_eq!(bit, (p == 0xff) as u8);
+        }
+
+        // Several colors, one byte per pixel, on stream 2.
+        let indexed = (0..32 * 32).map(|i| (i % 5) as u32).collect::<Vec<_>>();
+        let out = encode(&indexed, 32, Some(5));
+        assert_eq!(
+            out[..3],
+            [
+                2 << rfb::TIGHT_STREAM_SHIFT | rfb::TIGHT_EXPLICIT_FILTER,
+                rfb::TIGHT_FILTER_PALETTE,
+                4
+            ]
+        );
+        let (len, data) = read_compact_len(&out[3 + 5 * 3..]);
+        assert_eq!(len, data.len());
+        let indexes = inflate(&mut streams[2], data);
+        assert_eq!(
+            indexes,
+            indexed.iter().map(|&p| p as u8).collect::<Vec<_>>()
+        );
+
+        // Many colors, copied on stream 0.
+        let gradient = (0..64 * 64).map(|i| i * 0x10101).collect::<Vec<_>>();
+        let out = encode(&gradient, 64, None);
+        assert_eq!(out[0], 0);
+        let (len, data) = read_compact_len(&out[1..]);
+        assert_eq!(len, data.len());
+        assert_eq!(
+            inflate(&mut streams[0], data),
+            gradient.iter().flat_map(|&p| rgb(p)).collect::<Vec<_>>()
+        );
+
+        // Small data is not compressed.
+        assert_eq!(
+            encode(&[1, 2, 3], 3, None),
+            [
+                2 << rfb::TIGHT_STREAM_SHIFT | rfb::TIGHT_EXPLICIT_FILTER,
+                rfb::TIGHT_FILTER_PALETTE,
+                2,
+                0,
+                0,
+                1,
+                0,
+                0,
+                2,
+                0,
+                0,
+                3,
+                0,
+                1,
+                2
+            ]
+        );
+
+        // Many colors, as JPEG.
+        let out = encode(&gradient, 64, Some(9));
+        assert_eq!(out[0], rfb::TIGHT_JPEG);
+        let (len, data) = read_compact_len(&out[1..]);
+        assert_eq!(len, data.len());
+        assert_eq!(data[..2], [0xff, 0xd8]);
+    }
+}
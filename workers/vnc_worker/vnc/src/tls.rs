@@ -0,0 +1,268 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! TLS for the VeNCrypt security type, via OpenSSL.
+//!
+//! TLS is only available on Linux, with the `tls` feature. Elsewhere,
+//! [`TlsConfig::new`] fails, so the server never offers VeNCrypt.
+
+use crate::Connection;
+use crate::Error;
+use thiserror::Error;
+
+/// The TLS configuration for VeNCrypt's X.509 security types.
+#[derive(Clone)]
+pub struct TlsConfig {
+    #[cfg(all(feature = "tls", target_os = "linux"))]
+    acceptor: openssl::ssl::SslAcceptor,
+    #[cfg(not(all(feature = "tls", target_os = "linux")))]
+    never: std::convert::Infallible,
+}
+
+#[derive(Debug, Error)]
+pub enum TlsConfigError {
+    #[error("TLS is not supported in this build")]
+    Unsupported,
+    #[cfg(all(feature = "tls", target_os = "linux"))]
+    #[error("invalid certificate or private key")]
+    OpenSsl(#[source] openssl::error::ErrorStack),
+}
+
+impl TlsConfig {
+    /// Returns a configuration that presents the PEM-encoded certificate chain
+    /// `cert_chain`, leaf first, authenticated by the PEM-encoded
+    /// `private_key`.
+    #[cfg(all(feature = "tls", target_os = "linux"))]
+    pub fn new(cert_chain: &[u8], private_key: &[u8]) -> Result<Self, TlsConfigError> {
+        use openssl::pkey::PKey;
+        use openssl::ssl::SslAcceptor;
+        use openssl::ssl::SslMethod;
+        use openssl::x509::X509;
+
+        let acceptor = (|| -> Result<_, openssl::error::ErrorStack> {
+            let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+            let mut certs = X509::stack_from_pem(cert_chain)?.into_iter();
+            if let Some(leaf) = certs.next() {
+                builder.set_certificate(&leaf)?;
+            }
+            for cert in certs {
+                builder.add_extra_chain_cert(cert)?;
+            }
+            builder.set_private_key(&PKey::private_key_from_pem(private_key)?)?;
+            builder.check_private_key()?;
+            Ok(builder.build())
+        })()
+        .map_err(TlsConfigError::OpenSsl)?;
+        Ok(Self { acceptor })
+    }
+
+    /// Returns a configuration that presents the PEM-encoded certificate chain
+    /// `cert_chain`, leaf first, authenticated by the PEM-encoded
+    /// `private_key`.
+    #[cfg(not(all(feature = "tls", target_os = "linux")))]
+    pub fn new(cert_chain: &[u8], private_key: &[u8]) -> Result<Self, TlsConfigError> {
+        let _ = (cert_chain, private_key);
+        Err(TlsConfigError::Unsupported)
+    }
+}
+
+/// Runs the TLS handshake on `socket`, replacing it with the TLS stream.
+pub(crate) async fn upgrade(socket: &mut Connection, config: &TlsConfig) -> Result<(), Error> {
+    #[cfg(all(feature = "tls", target_os = "linux"))]
+    {
+        let Connection::Plain(plain) = std::mem::replace(socket, Connection::Invalid) else {
+            unreachable!("already upgraded")
+        };
+        let stream = stream::TlsStream::accept(&config.acceptor, plain)
+            .await
+            .map_err(Error::Tls)?;
+        *socket = Connection::Tls(Box::new(stream));
+        Ok(())
+    }
+    #[cfg(not(all(feature = "tls", target_os = "linux")))]
+    {
+        let _ = socket;
+        match config.never {}
+    }
+}
+
+#[cfg(all(feature = "tls", target_os = "linux"))]
+pub(crate) use stream::TlsStream;
+
+#[cfg(all(feature = "tls", target_os = "linux"))]
+mod stream {
+    use futures::AsyncRead;
+    use futures::AsyncReadExt;
+    use futures::AsyncWrite;
+    use futures::AsyncWriteExt;
+    use openssl::ssl::ErrorCode;
+    use openssl::ssl::HandshakeError;
+    use openssl::ssl::SslAcceptor;
+    use openssl::ssl::SslStream;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::Context;
+    use std::task::Poll;
+    use std::task::ready;
+
+    /// The transport that OpenSSL sees: buffers of ciphertext, so that OpenSSL
+    /// never blocks and the socket IO can be done asynchronously.
+    #[derive(Default)]
+    struct Buffers {
+        incoming: Vec<u8>,
+        outgoing: Vec<u8>,
+        eof: bool,
+    }
+
+    impl io::Read for Buffers {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.incoming.is_empty() {
+                return if self.eof {
+                    Ok(0)
+                } else {
+                    Err(io::ErrorKind::WouldBlock.into())
+                };
+            }
+            let n = buf.len().min(self.incoming.len());
+            buf[..n].copy_from_slice(&self.incoming[..n]);
+            self.incoming.drain(..n);
+            Ok(n)
+        }
+    }
+
+    impl io::Write for Buffers {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.outgoing.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A TLS session over an async socket.
+    pub struct TlsStream<S> {
+        socket: S,
+        ssl: SslStream<Buffers>,
+    }
+
+    fn tls_error(err: openssl::ssl::Error) -> io::Error {
+        err.into_io_error().unwrap_or_else(io::Error::other)
+    }
+
+    impl<S: AsyncRead + AsyncWrite + Unpin> TlsStream<S> {
+        /// Runs the server side of the TLS handshake over `socket`.
+        pub async fn accept(acceptor: &SslAcceptor, mut socket: S) -> io::Result<Self> {
+            let mut result = acceptor.accept(Buffers::default());
+            loop {
+                let mut mid = match result {
+                    Ok(ssl) => {
+                        let mut stream = Self { socket, ssl };
+                        stream.flush().await?;
+                        return Ok(stream);
+                    }
+                    Err(HandshakeError::WouldBlock(mid)) => mid,
+                    Err(HandshakeError::SetupFailure(err)) => return Err(io::Error::other(err)),
+                    Err(HandshakeError::Failure(mid)) => {
+                        // Try to send the alert to the client.
+                        let _ = socket.write_all(&mid.get_ref().outgoing).await;
+                        return Err(tls_error(mid.into_error()));
+                    }
+                };
+                // OpenSSL needs more data from the client. Send it whatever
+                // it's waiting for first.
+                let buffers = mid.get_mut();
+                socket.write_all(&buffers.outgoing).await?;
+                buffers.outgoing.clear();
+                let mut buf = [0; 4096];
+                let n = socket.read(&mut buf).await?;
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                buffers.incoming.extend_from_slice(&buf[..n]);
+                result = mid.handshake();
+            }
+        }
+
+        /// Writes any buffered ciphertext to the socket.
+        fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            let outgoing = &mut self.ssl.get_mut().outgoing;
+            while !outgoing.is_empty() {
+                let n = ready!(Pin::new(&mut self.socket).poll_write(cx, outgoing))?;
+                if n == 0 {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+                outgoing.drain(..n);
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for TlsStream<S> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            loop {
+                // Finish sending any previous writes before waiting on the
+                // client, which may be waiting on them.
+                ready!(this.poll_send(cx))?;
+                let err = match this.ssl.ssl_read(buf) {
+                    Ok(n) => break Poll::Ready(Ok(n)),
+                    Err(err) => err,
+                };
+                match err.code() {
+                    ErrorCode::ZERO_RETURN => break Poll::Ready(Ok(0)),
+                    ErrorCode::WANT_READ => {}
+                    // Treat a client that disconnects without closing the
+                    // session as a normal disconnect.
+                    _ if this.ssl.get_ref().eof => break Poll::Ready(Ok(0)),
+                    _ => break Poll::Ready(Err(tls_error(err))),
+                }
+                if !this.ssl.get_ref().outgoing.is_empty() {
+                    continue;
+                }
+                let mut data = [0; 4096];
+                let n = ready!(Pin::new(&mut this.socket).poll_read(cx, &mut data))?;
+                let buffers = this.ssl.get_mut();
+                if n == 0 {
+                    buffers.eof = true;
+                }
+                buffers.incoming.extend_from_slice(&data[..n]);
+            }
+        }
+    }
+
+    impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for TlsStream<S> {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            ready!(this.poll_send(cx))?;
+            let n = this.ssl.ssl_write(buf).map_err(tls_error)?;
+            // Start sending the ciphertext. Anything left over is sent by the
+            // next read, write, or flush.
+            if let Poll::Ready(Err(err)) = this.poll_send(cx) {
+                return Poll::Ready(Err(err));
+            }
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            ready!(this.poll_send(cx))?;
+            Pin::new(&mut this.socket).poll_flush(cx)
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            ready!(this.poll_send(cx))?;
+            Pin::new(&mut this.socket).poll_close(cx)
+        }
+    }
+}
@@ -0,0 +1,305 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The ZRLE encoding.
+//!
+//! Each rectangle is divided into 64x64 tiles, and each tile is sent with
+//! whichever of the raw, solid, packed palette, plain RLE, or palette RLE
+//! subencodings is smallest. The caller compresses the result with the
+//! connection's ZRLE zlib stream.
+
+use crate::convert_pixels;
+use crate::encoding::Palette;
+use crate::encoding::write_packed;
+use crate::rfb;
+use std::ops::Range;
+
+/// Appends the ZRLE encoding of `pixels`, a `width` by `height` rectangle, to
+/// `out`, before compression.
+pub(crate) fn encode(
+    pixels: &[u32],
+    width: usize,
+    height: usize,
+    fmt: &rfb::PixelFormat,
+    palette: &mut Palette,
+    out: &mut Vec<u8>,
+) {
+    let cpixel = cpixel_bytes(fmt);
+    let tile_size = rfb::ZRLE_TILE_SIZE as usize;
+    let mut tile = Vec::with_capacity(tile_size * tile_size);
+    for ty in (0..height).step_by(tile_size) {
+        let tile_height = tile_size.min(height - ty);
+        for tx in (0..width).step_by(tile_size) {
+            let tile_width = tile_size.min(width - tx);
+            tile.clear();
+            for y in ty..ty + tile_height {
+                tile.extend_from_slice(&pixels[y * width + tx..][..tile_width]);
+            }
+            encode_tile(&tile, tile_width, fmt, &cpixel, palette, out);
+        }
+    }
+}
+
+/// Returns the bytes of a pixel in the client's format that make up a
+/// CPIXEL, which drops the unused byte of 32-bit pixels with 24-bit depth.
+fn cpixel_bytes(fmt: &rfb::PixelFormat) -> Range<usize> {
+    let bytes = fmt.bits_per_pixel as usize / 8;
+    if fmt.bits_per_pixel == 32 && fmt.depth <= 24 && fmt.true_color_flag != 0 {
+        let mask = (fmt.red_max.get() as u64) << fmt.red_shift
+            | (fmt.green_max.get() as u64) << fmt.green_shift
+            | (fmt.blue_max.get() as u64) << fmt.blue_shift;
+        let big_endian = fmt.big_endian_flag != 0;
+        if mask < 1 << 24 {
+            return if big_endian { 1..4 } else { 0..3 };
+        } else if mask & 0xff == 0 && mask < 1 << 32 {
+            return if big_endian { 0..3 } else { 1..4 };
+        }
+    }
+    0..bytes
+}
+
+/// Appends `pixels` as CPIXELs.
+fn write_cpixels(pixels: &[u32], fmt: &rfb::PixelFormat, cpixel: &Range<usize>, out: &mut Vec<u8>) {
+    let start = out.len();
+    convert_pixels(pixels, fmt, out);
+    let bytes = fmt.bits_per_pixel as usize / 8;
+    if cpixel.len() != bytes {
+        // Compact the converted pixels in place.
+        for i in 0..pixels.len() {
+            let src = start + i * bytes;
+            out.copy_within(
+                src + cpixel.start..src + cpixel.end,
+                start + i * cpixel.len(),
+            );
+        }
+        out.truncate(start + pixels.len() * cpixel.len());
+    }
+}
+
+/// Returns the number of bytes in the encoding of a run length.
+fn run_length_len(len: usize) -> usize {
+    (len - 1) / 255 + 1
+}
+
+fn write_run_length(len: usize, out: &mut Vec<u8>) {
+    let mut n = len - 1;
+    while n >= 255 {
+        out.push(255);
+        n -= 255;
+    }
+    out.push(n as u8);
+}
+
+/// Returns the runs of identical pixels, which may span lines.
+fn runs(pixels: &[u32]) -> impl Iterator<Item = (u32, usize)> + '_ {
+    pixels
+        .chunk_by(|a, b| a == b)
+        .map(|run| (run[0], run.len()))
+}
+
+fn encode_tile(
+    pixels: &[u32],
+    width: usize,
+    fmt: &rfb::PixelFormat,
+    cpixel: &Range<usize>,
+    palette: &mut Palette,
+    out: &mut Vec<u8>,
+) {
+    let height = pixels.len() / width;
+    let has_palette = palette.build(pixels, rfb::ZRLE_PALETTE_RLE_MAX);
+    if has_palette && palette.len() == 1 {
+        out.push(rfb::ZRLE_SOLID);
+        write_cpixels(&pixels[..1], fmt, cpixel, out);
+        return;
+    }
+
+    // Compute the size of each subencoding to pick the smallest.
+    let cpixel_len = cpixel.len();
+    let raw_len = pixels.len() * cpixel_len;
+    let mut plain_rle_len = 0;
+    let mut palette_rle_len = palette.len() * cpixel_len;
+    for (_, len) in runs(pixels) {
+        plain_rle_len += cpixel_len + run_length_len(len);
+        palette_rle_len += if len == 1 { 1 } else { 1 + run_length_len(len) };
+    }
+    let bits = match palette.len() {
+        0..=2 => 1,
+        3..=4 => 2,
+        _ => 4,
+    };
+    let packed_len = palette.len() * cpixel_len + height * (width * bits).div_ceil(8);
+
+    let packed = has_palette && palette.len() <= rfb::ZRLE_PACKED_PALETTE_MAX;
+    if packed && packed_len <= palette_rle_len.min(plain_rle_len).min(raw_len) {
+        out.push(palette.len() as u8);
+        write_cpixels(palette.colors(), fmt, cpixel, out);
+        write_packed(pixels.iter().map(|&p| palette.index(p)), width, bits, out);
+    } else if has_palette && palette_rle_len <= plain_rle_len.min(raw_len) {
+        out.push(rfb::ZRLE_PALETTE_RLE_BASE + palette.len() as u8);
+        write_cpixels(palette.colors(), fmt, cpixel, out);
+        for (pixel, len) in runs(pixels) {
+            let index = palette.index(pixel);
+            if len == 1 {
+                out.push(index);
+            } else {
+                out.push(index | 0x80);
+                write_run_length(len, out);
+            }
+        }
+    } else if plain_rle_len < raw_len {
+        out.push(rfb::ZRLE_PLAIN_RLE);
+        for (pixel, len) in runs(pixels) {
+            write_cpixels(&[pixel], fmt, cpixel, out);
+            write_run_length(len, out);
+        }
+    } else {
+        out.push(rfb::ZRLE_RAW);
+        write_cpixels(pixels, fmt, cpixel, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::encode;
+    use crate::encoding::Palette;
+    use crate::encoding::tests::rgb888;
+    use crate::rfb;
+
+    struct Reader<'a>(&'a [u8]);
+
+    impl<'a> Reader<'a> {
+        fn bytes(&mut self, n: usize) -> &'a [u8] {
+            let (bytes, rest) = self.0.split_at(n);
+            self.0 = rest;
+            bytes
+        }
+
+        fn byte(&mut self) -> u8 {
+            self.bytes(1)[0]
+        }
+
+        /// Reads a CPIXEL holding the low bytes of a 32-bit little-endian
+        /// pixel.
+        fn cpixel(&mut self) -> u32 {
+            let b = self.bytes(3);
+            u32::from_le_bytes([b[0], b[1], b[2], 0])
+        }
+
+        fn cpixels(&mut self, n: usize) -> Vec<u32> {
+            (0..n).map(|_| self.cpixel()).collect()
+        }
+
+        fn run_length(&mut self) -> usize {
+            let mut len = 1;
+            loop {
+                let b = self.byte();
+                len += b as usize;
+                if b != 255 {
+                    break len;
+                }
+            }
+        }
+    }
+
+    /// Decodes ZRLE data for a `width` by `height` rectangle.
+    fn decode(data: &[u8], width: usize, height: usize) -> Vec<u32> {
+        let mut data = Reader(data);
+        let mut pixels = vec![0; width * height];
+        for ty in (0..height).step_by(64) {
+            let th = 64.min(height - ty);
+            for tx in (0..width).step_by(64) {
+                let tw = 64.min(width - tx);
+                let mut tile = Vec::new();
+                match data.byte() {
+                    0 => tile = data.cpixels(tw * th),
+                    1 => tile.resize(tw * th, data.cpixel()),
+                    n @ 2..=16 => {
+                        let palette = data.cpixels(n.into());
+                        let bits = match n {
+                            2 => 1,
+                            3..=4 => 2,
+                            _ => 4,
+                        };
+                        for _ in 0..th {
+                            let row = data.bytes((tw * bits).div_ceil(8));
+                            for x in 0..tw {
+                                let bit = x * bits;
+                                let index =
+                                    (row[bit / 8] >> (8 - bits - bit % 8)) & ((1 << bits) - 1);
+                                tile.push(palette[index as usize]);
+                            }
+                        }
+                    }
+                    128 => {
+                        while tile.len() < tw * th {
+                            let pixel = data.cpixel();
+                            let len = data.run_length();
+                            tile.extend(std::iter::repeat_n(pixel, len));
+                        }
+                    }
+                    n @ 130.. => {
+                        let palette = data.cpixels((n - 128).into());
+                        while tile.len() < tw * th {
+                            let index = data.byte();
+                            let len = if index & 0x80 != 0 {
+                                data.run_length()
+                            } else {
+                                1
+                            };
+                            tile.extend(std::iter::repeat_n(palette[(index & 0x7f) as usize], len));
+                        }
+                    }
+                    n => panic!("invalid subencoding {n}"),
+                }
+                assert_eq!(tile.len(), tw * th);
+                for y in 0..th {
+                    pixels[(ty + y) * width + tx..][..tw].copy_from_slice(&tile[y * tw..][..tw]);
+                }
+            }
+        }
+        assert!(data.0.is_empty());
+        pixels
+    }
+
+    fn round_trip(pixels: &[u32], width: usize) -> u8 {
+        let height = pixels.len() / width;
+        let mut out = Vec::new();
+        encode(
+            pixels,
+            width,
+            height,
+            &rgb888(),
+            &mut Palette::default(),
+            &mut out,
+        );
+        assert_eq!(decode(&out, width, height), pixels);
+        out[0]
+    }
+
+    #[test]
+    fn test_zrle() {
+        // Solid.
+        assert_eq!(round_trip(&[0x123456; 64 * 10], 64), rfb::ZRLE_SOLID);
+        // Packed palette.
+        let stripes = (0..40 * 8).map(|i| [0xff0000, 0xff][i / 3 % 2]);
+        assert_eq!(round_trip(&stripes.collect::<Vec<_>>(), 40), 2);
+        // Palette RLE.
+        let blocks = (0..64 * 64).map(|i| (i / 7 % 20) as u32 * 0x10101);
+        assert_eq!(
+            round_trip(&blocks.collect::<Vec<_>>(), 64),
+            rfb::ZRLE_PALETTE_RLE_BASE + 20
+        );
+        // Plain RLE.
+        let runs = (0..64 * 64).map(|i| (i / 20) as u32);
+        assert_eq!(
+            round_trip(&runs.collect::<Vec<_>>(), 64),
+            rfb::ZRLE_PLAIN_RLE
+        );
+        // Raw.
+        let noise = (0..64 * 64).map(|i: u32| i.wrapping_mul(2654435761) >> 8);
+        assert_eq!(round_trip(&noise.collect::<Vec<_>>(), 64), rfb::ZRLE_RAW);
+        // Multiple tiles.
+        let gradient = (0..100 * 70).map(|i| (i % 100 / 10) as u32);
+        round_trip(&gradient.collect::<Vec<_>>(), 100);
+    }
+}
@@ -17,6 +17,26 @@ pub struct VncParameters<T> {
     pub framebuffer: framebuffer::FramebufferAccess,
     /// A channel to send input to.
     pub input_send: mesh::Sender<input_core::InputData>,
+    /// The security requirements for clients.
+    pub security: VncSecurity,
+}
+
+/// The VNC server's security requirements.
+#[derive(MeshPayload, Clone, Default)]
+pub struct VncSecurity {
+    /// Require clients to authenticate with this password.
+    pub password: Option<String>,
+    /// Require clients to use TLS.
+    pub tls: Option<VncTls>,
+}
+
+/// The VNC server's TLS identity.
+#[derive(MeshPayload, Clone)]
+pub struct VncTls {
+    /// The PEM-encoded certificate chain, leaf first.
+    pub cert_chain: Vec<u8>,
+    /// The PEM-encoded private key.
+    pub private_key: Vec<u8>,
 }
 
 pub const VNC_WORKER_TCP: WorkerId<VncParameters<TcpListener>> = WorkerId::new("VncWorkerTcp");